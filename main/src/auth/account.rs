@@ -0,0 +1,978 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Account settings: changing your email address, password or timezone, and deleting your
+//! account.
+//!
+//! Changing your email address or password requires your current password, so that somebody who
+//! gets hold of a logged-in session can't use it to take over the account for good.
+
+use std::str::FromStr;
+
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use rocket::{http::Status, serde::json::Json};
+use thiserror::Error as ThisError;
+
+use crate::{
+    db::{Database, DatabaseConnection},
+    models::User,
+    schema::{
+        administrator, class, class_asynchronous_task, class_synchronous_task, class_teacher,
+        institution, session, users,
+    },
+    utils::{default_head, json_response::ApiResponse, timezones::timezone_field},
+};
+
+use super::{
+    hash::{hash_password, verify_password},
+    register::{validate_new_password, PasswordError, EMAIL_RE},
+    session::{end_session, CurrentSession},
+    verify::send_verification_email,
+    AuthCookie,
+};
+
+#[derive(ThisError, Debug)]
+pub enum AccountError {
+    #[error("the current password supplied is not correct")]
+    IncorrectPassword,
+    #[error("passwords do not match")]
+    NonMatchingPasswords,
+    #[error("invalid email address supplied")]
+    InvalidEmail,
+    #[error("email address already in use")]
+    EmailTaken,
+    #[error("invalid timezone")]
+    InvalidTimezone,
+    #[error("the user is the only teacher of some classes")]
+    LastTeacher(Vec<String>),
+    #[error("the user is the only administrator of some institutions")]
+    LastAdministrator(Vec<String>),
+    #[error("could not send email")]
+    EmailError,
+    #[error("encrypting password error")]
+    EncryptingPasswordError,
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for AccountError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl AccountError {
+    fn status(&self) -> Status {
+        match self {
+            AccountError::IncorrectPassword => Status::Forbidden,
+            AccountError::NonMatchingPasswords
+            | AccountError::InvalidEmail
+            | AccountError::InvalidTimezone => Status::BadRequest,
+            AccountError::EmailTaken
+            | AccountError::LastTeacher(_)
+            | AccountError::LastAdministrator(_) => Status::Conflict,
+            AccountError::EmailError
+            | AccountError::EncryptingPasswordError
+            | AccountError::DatabaseError => Status::InternalServerError,
+        }
+    }
+
+    fn explanation(&self) -> String {
+        match self {
+            AccountError::IncorrectPassword => {
+                "The current password you supplied isn't correct.".to_string()
+            }
+            AccountError::NonMatchingPasswords => {
+                "The new passwords you supplied don't match.".to_string()
+            }
+            AccountError::InvalidEmail => "That isn't a valid email address.".to_string(),
+            AccountError::EmailTaken => {
+                "Somebody else is already using that email address.".to_string()
+            }
+            AccountError::InvalidTimezone => "That isn't a valid timezone.".to_string(),
+            AccountError::LastTeacher(classes) => format!(
+                "You are the only teacher of these classes: {}. Please invite another teacher to \
+                them (or delete them) before deleting your account.",
+                classes.join(", ")
+            ),
+            AccountError::LastAdministrator(institutions) => format!(
+                "You are the only administrator of these institutions: {}. Please invite another \
+                administrator (or delete them) before deleting your account.",
+                institutions.join(", ")
+            ),
+            AccountError::EmailError => "We couldn't send an email to that address. Please try \
+            again later."
+                .to_string(),
+            AccountError::EncryptingPasswordError => {
+                "We're having problems encrypting your password.".to_string()
+            }
+            AccountError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+        }
+    }
+
+    fn render(self, title: &'static str) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head(title))
+            .body(
+                Body::new()
+                    .child(H1::new(title))
+                    .child(P::with_text(self.explanation()))
+                    .child(
+                        A::new()
+                            .attribute(Href::new("/auth/account"))
+                            .text("Back to your account settings"),
+                    ),
+            )
+    }
+}
+
+async fn get_user(user_id: i32, conn: &Database) -> Result<User, AccountError> {
+    Ok(conn
+        .run(move |c| users::table.find(user_id).first::<User>(c))
+        .await?)
+}
+
+fn check_password(user: &User, password: &str) -> Result<(), AccountError> {
+    if verify_password(password, &user.password)
+        .map_err(|e| error!("{:#?}", e))
+        .unwrap_or(false)
+    {
+        Ok(())
+    } else {
+        Err(AccountError::IncorrectPassword)
+    }
+}
+
+fn change_email_form() -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/account/email"))
+        .child(
+            Input::new()
+                .attribute(Type::Email)
+                .attribute(Name::new("email"))
+                .attribute(Placeholder::new("Your new email address")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Password)
+                .attribute(Name::new("current_password"))
+                .attribute(Placeholder::new("Your current password")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Change email address")),
+        )
+}
+
+fn change_password_form() -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/account/password"))
+        .child(
+            Input::new()
+                .attribute(Type::Password)
+                .attribute(Name::new("current_password"))
+                .attribute(Placeholder::new("Your current password")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Password)
+                .attribute(Name::new("password"))
+                .attribute(Placeholder::new("A new password")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Password)
+                .attribute(Name::new("password_confirmation"))
+                .attribute(Placeholder::new("Your new password (again)")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Change password")),
+        )
+}
+
+fn change_timezone_form() -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/account/timezone"))
+        .child(timezone_field("timezone", Some("Your new timezone:")))
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Change timezone")),
+        )
+}
+
+#[get("/account")]
+pub async fn account_page(auth: AuthCookie, conn: Database) -> Html {
+    let user = match get_user(auth.0, &conn).await {
+        Ok(user) => user,
+        Err(e) => return e.render("Account settings"),
+    };
+    Html::new().head(default_head("Account settings")).body(
+        Body::new()
+            .child(H1::new("Account settings"))
+            .child(H3::new("Email address"))
+            .child(P::with_text(format!(
+                "Your email address is {} ({}).",
+                user.email,
+                if user.email_verified {
+                    "verified"
+                } else {
+                    "not yet verified"
+                }
+            )))
+            .map(|body| {
+                if user.email_verified {
+                    body
+                } else {
+                    body.child(
+                        Form::new()
+                            .attribute(Method::Post)
+                            .attribute(Action::new("/auth/verify/resend"))
+                            .child(
+                                Input::new()
+                                    .attribute(Type::Submit)
+                                    .attribute(Value::new("Resend verification email")),
+                            ),
+                    )
+                }
+            })
+            .child(change_email_form())
+            .child(H3::new("Password"))
+            .child(change_password_form())
+            .child(H3::new("Timezone"))
+            .child(P::with_text(format!(
+                "Your timezone is currently {}.",
+                user.timezone
+            )))
+            .child(change_timezone_form())
+            .child(H3::new("Security"))
+            .child(
+                P::default().child(
+                    A::new()
+                        .attribute(Href::new("/auth/sessions"))
+                        .text("Where you're logged in"),
+                ),
+            )
+            .child(
+                P::default().child(
+                    A::new()
+                        .attribute(Href::new("/auth/tokens"))
+                        .text("API tokens"),
+                ),
+            )
+            .child(
+                P::default().child(
+                    A::new()
+                        .attribute(Href::new("/auth/2fa"))
+                        .text("Two-factor authentication"),
+                ),
+            )
+            .child(H3::new("Your data"))
+            .child(
+                P::default().child(
+                    A::new()
+                        .attribute(Href::new("/export"))
+                        .text("Download a copy of your data"),
+                ),
+            )
+            .child(H3::new("Delete your account"))
+            .child(
+                P::default().child(
+                    A::new()
+                        .attribute(Href::new("/auth/account/delete"))
+                        .text("Delete your account"),
+                ),
+            ),
+    )
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEmailForm {
+    email: String,
+    current_password: String,
+}
+
+/// Changes the user's email address. The new address has to be verified (using the link which we
+/// email to it) before it counts as verified.
+async fn change_email_base(
+    auth: AuthCookie,
+    data: &ChangeEmailForm,
+    conn: &Database,
+) -> Result<User, AccountError> {
+    let user = get_user(auth.0, conn).await?;
+    check_password(&user, &data.current_password)?;
+    let email = data.email.trim().to_string();
+    if !EMAIL_RE.is_match(&email) {
+        return Err(AccountError::InvalidEmail);
+    }
+    if email == user.email {
+        return Ok(user);
+    }
+    let check_email = email.clone();
+    let taken = conn
+        .run(move |c| {
+            diesel::select(diesel::dsl::exists(
+                users::table.filter(users::email.eq(check_email)),
+            ))
+            .get_result::<bool>(c)
+        })
+        .await?;
+    if taken {
+        return Err(AccountError::EmailTaken);
+    }
+    // the verification email is sent before the new address is saved, so that if it can't be sent
+    // the user keeps their old (verified) address
+    send_verification_email(&User {
+        email: email.clone(),
+        email_verified: false,
+        ..user
+    })
+    .await
+    .map_err(|_| AccountError::EmailError)?;
+    conn.run(move |c| {
+        c.transaction(|| {
+            // (someone else could have taken the address while the email was being sent)
+            let taken = diesel::select(diesel::dsl::exists(
+                users::table.filter(users::email.eq(&email)),
+            ))
+            .get_result::<bool>(c)?;
+            if taken {
+                return Err(AccountError::EmailTaken);
+            }
+            Ok(diesel::update(users::table.find(auth.0))
+                .set((users::email.eq(email), users::email_verified.eq(false)))
+                .get_result::<User>(c)?)
+        })
+    })
+    .await
+}
+
+#[post("/account/email", data = "<data>")]
+pub async fn html_change_email(
+    auth: AuthCookie,
+    data: rocket::form::Form<ChangeEmailForm>,
+    conn: Database,
+) -> Html {
+    match change_email_base(auth, &data, &conn).await {
+        Ok(user) => Html::new()
+            .head(default_head("Email address changed"))
+            .body(
+                Body::new()
+                    .child(H1::new("Email address changed"))
+                    .child(P::with_text(format!(
+                        "Your email address is now {}. We've sent you an email with a link to \
+                        verify it.",
+                        user.email
+                    ))),
+            ),
+        Err(e) => e.render("Could not change your email address"),
+    }
+}
+
+#[post("/account/email", data = "<data>")]
+pub async fn api_change_email(
+    auth: AuthCookie,
+    data: Json<ChangeEmailForm>,
+    conn: Database,
+) -> Json<ApiResponse<User>> {
+    Json(match change_email_base(auth, &data, &conn).await {
+        Ok(user) => ApiResponse::new_ok(user),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct ChangePasswordForm {
+    current_password: String,
+    password: String,
+    password_confirmation: String,
+}
+
+/// Changes the user's password, and logs them out everywhere other than the session which they
+/// used to change it.
+async fn change_password_base(
+    current: CurrentSession,
+    data: &ChangePasswordForm,
+    conn: &Database,
+) -> Result<(), AccountError> {
+    let user = get_user(current.0.user_id, conn).await?;
+    check_password(&user, &data.current_password)?;
+    validate_new_password(&data.password, &data.password_confirmation).map_err(|e| match e {
+        PasswordError::NonMatchingPasswords => AccountError::NonMatchingPasswords,
+    })?;
+    let hashed_password = hash_password(&data.password).map_err(|e| {
+        error!("{:#?}", e);
+        AccountError::EncryptingPasswordError
+    })?;
+    let session_id = current.0.id;
+    conn.run(move |c| {
+        c.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::update(users::table.find(user.id))
+                .set(users::password.eq(hashed_password))
+                .execute(c)?;
+            diesel::delete(
+                session::table
+                    .filter(session::user_id.eq(user.id))
+                    .filter(session::id.ne(session_id)),
+            )
+            .execute(c)
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+#[post("/account/password", data = "<data>")]
+pub async fn html_change_password(
+    current: CurrentSession,
+    data: rocket::form::Form<ChangePasswordForm>,
+    conn: Database,
+) -> Html {
+    match change_password_base(current, &data, &conn).await {
+        Ok(()) => Html::new().head(default_head("Password changed")).body(
+            Body::new()
+                .child(H1::new("Password changed"))
+                .child(P::with_text(
+                    "Your password has been changed, and you've been logged out everywhere else.",
+                )),
+        ),
+        Err(e) => e.render("Could not change your password"),
+    }
+}
+
+#[post("/account/password", data = "<data>")]
+pub async fn api_change_password(
+    current: CurrentSession,
+    data: Json<ChangePasswordForm>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(match change_password_base(current, &data, &conn).await {
+        Ok(()) => ApiResponse::new_ok(()),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeTimezoneForm {
+    timezone: String,
+}
+
+async fn change_timezone_base(
+    auth: AuthCookie,
+    data: &ChangeTimezoneForm,
+    conn: &Database,
+) -> Result<User, AccountError> {
+    let timezone = chrono_tz::Tz::from_str(data.timezone.trim())
+        .map_err(|_| AccountError::InvalidTimezone)?
+        .to_string();
+    Ok(conn
+        .run(move |c| {
+            diesel::update(users::table.find(auth.0))
+                .set(users::timezone.eq(timezone))
+                .get_result::<User>(c)
+        })
+        .await?)
+}
+
+#[post("/account/timezone", data = "<data>")]
+pub async fn html_change_timezone(
+    auth: AuthCookie,
+    data: rocket::form::Form<ChangeTimezoneForm>,
+    conn: Database,
+) -> Html {
+    match change_timezone_base(auth, &data, &conn).await {
+        Ok(user) => Html::new().head(default_head("Timezone changed")).body(
+            Body::new()
+                .child(H1::new("Timezone changed"))
+                .child(P::with_text(format!(
+                    "Your timezone is now {}.",
+                    user.timezone
+                ))),
+        ),
+        Err(e) => e.render("Could not change your timezone"),
+    }
+}
+
+#[post("/account/timezone", data = "<data>")]
+pub async fn api_change_timezone(
+    auth: AuthCookie,
+    data: Json<ChangeTimezoneForm>,
+    conn: Database,
+) -> Json<ApiResponse<User>> {
+    Json(match change_timezone_base(auth, &data, &conn).await {
+        Ok(user) => ApiResponse::new_ok(user),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[get("/account/delete")]
+pub fn delete_account_page(_auth: AuthCookie) -> Html {
+    Html::new().head(default_head("Delete your account")).body(
+        Body::new()
+            .child(H1::new("Delete your account"))
+            .child(P::with_text(
+                "This will permanently delete your account, including the messages and \
+                    replies you've posted, your calendars and your place in every class and \
+                    institution you're part of. Tasks you've set in classes which have other \
+                    teachers will be handed over to one of them. This can't be undone!",
+            ))
+            .child(
+                Form::new()
+                    .attribute(Method::Post)
+                    .child(
+                        Input::new()
+                            .attribute(Type::Password)
+                            .attribute(Name::new("current_password"))
+                            .attribute(Placeholder::new("Your current password")),
+                    )
+                    .child(
+                        Input::new()
+                            .attribute(Type::Submit)
+                            .attribute(Value::new("Delete my account")),
+                    ),
+            ),
+    )
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteAccountForm {
+    current_password: String,
+}
+
+/// Deletes the user's account.
+///
+/// Most of the user's data (class memberships, messages, calendars and so on) is removed by the
+/// database when the `users` row is deleted. The exceptions are classes and institutions which
+/// would be left without a teacher or administrator (in which case the account is not deleted),
+/// and tasks the user has set (which are handed over to another teacher of the class, so that
+/// students don't lose them).
+fn delete_account(c: &DatabaseConnection, user_id: i32) -> Result<(), AccountError> {
+    c.transaction::<_, AccountError, _>(|| {
+        let teaching = class_teacher::table
+            .filter(class_teacher::user_id.eq(user_id))
+            .inner_join(class::table)
+            .select((class_teacher::id, class::id, class::name))
+            .load::<(i32, i32, String)>(c)?;
+        let mut last_teacher_of = vec![];
+        for (class_teacher_id, class_id, class_name) in teaching {
+            let replacement = class_teacher::table
+                .filter(class_teacher::class_id.eq(class_id))
+                .filter(class_teacher::user_id.ne(user_id))
+                .select(class_teacher::id)
+                .order_by(class_teacher::id)
+                .first::<i32>(c)
+                .optional()?;
+            match replacement {
+                Some(replacement) => {
+                    diesel::update(
+                        class_asynchronous_task::table
+                            .filter(class_asynchronous_task::class_teacher_id.eq(class_teacher_id)),
+                    )
+                    .set(class_asynchronous_task::class_teacher_id.eq(replacement))
+                    .execute(c)?;
+                    diesel::update(
+                        class_synchronous_task::table
+                            .filter(class_synchronous_task::class_teacher_id.eq(class_teacher_id)),
+                    )
+                    .set(class_synchronous_task::class_teacher_id.eq(replacement))
+                    .execute(c)?;
+                }
+                None => last_teacher_of.push(class_name),
+            }
+        }
+        if !last_teacher_of.is_empty() {
+            return Err(AccountError::LastTeacher(last_teacher_of));
+        }
+
+        let last_administrator_of = administrator::table
+            .filter(administrator::user_id.eq(user_id))
+            .inner_join(institution::table)
+            .select((institution::id, institution::name))
+            .load::<(i32, String)>(c)?
+            .into_iter()
+            .map(|(institution_id, name)| {
+                administrator::table
+                    .filter(administrator::institution_id.eq(institution_id))
+                    .filter(administrator::user_id.ne(user_id))
+                    .count()
+                    .get_result::<i64>(c)
+                    .map(|others| (others, name))
+            })
+            .collect::<QueryResult<Vec<_>>>()?
+            .into_iter()
+            .filter(|(others, _)| *others == 0)
+            .map(|(_, name)| name)
+            .collect::<Vec<_>>();
+        if !last_administrator_of.is_empty() {
+            return Err(AccountError::LastAdministrator(last_administrator_of));
+        }
+
+        diesel::delete(users::table.find(user_id)).execute(c)?;
+        Ok(())
+    })
+}
+
+async fn delete_account_base(
+    auth: AuthCookie,
+    data: &DeleteAccountForm,
+    conn: &Database,
+) -> Result<(), AccountError> {
+    let user = get_user(auth.0, conn).await?;
+    check_password(&user, &data.current_password)?;
+    conn.run(move |c| delete_account(c, user.id)).await
+}
+
+#[post("/account/delete", data = "<data>")]
+pub async fn html_delete_account(
+    auth: AuthCookie,
+    data: rocket::form::Form<DeleteAccountForm>,
+    cookies: &rocket::http::CookieJar<'_>,
+    conn: Database,
+) -> Html {
+    match delete_account_base(auth, &data, &conn).await {
+        Ok(()) => {
+            // the session itself has already been deleted along with the account
+            if let Err(e) = end_session(cookies, &conn).await {
+                error!("{:#?}", e);
+            }
+            Html::new().head(default_head("Account deleted")).body(
+                Body::new()
+                    .child(H1::new("Account deleted"))
+                    .child(P::with_text("Your account has been deleted. Goodbye!")),
+            )
+        }
+        Err(e) => e.render("Could not delete your account"),
+    }
+}
+
+#[post("/account/delete", data = "<data>")]
+pub async fn api_delete_account(
+    auth: AuthCookie,
+    data: Json<DeleteAccountForm>,
+    cookies: &rocket::http::CookieJar<'_>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(match delete_account_base(auth, &data, &conn).await {
+        Ok(()) => {
+            if let Err(e) = end_session(cookies, &conn).await {
+                error!("{:#?}", e);
+            }
+            ApiResponse::new_ok(())
+        }
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[cfg(test)]
+mod test_account {
+    use chrono::Utc;
+    use diesel::prelude::*;
+    use rocket::http::{ContentType, Status};
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        auth::verify::verification_code,
+        db::Database,
+        institution::test_ctx::{
+            setup_env, ADMIN_EMAIL, ADMIN_PASSWORD, STUDENT_EMAIL, STUDENT_PASSWORD, TEACHER_EMAIL,
+            TEACHER_PASSWORD,
+        },
+        models::{
+            Class, ClassAsynchronousTask, NewClass, NewClassAsynchronousTask, NewClassTeacher, User,
+        },
+        schema::{class, class_asynchronous_task, class_teacher, users},
+        utils::{client, login_user, logout},
+    };
+
+    const NEW_PASSWORD: &str = "a-brand-new-passw0rd";
+
+    #[rocket::async_test]
+    async fn test_change_password_and_timezone() {
+        let client = client().await;
+        let (_, _, student_id, _, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+
+        let res = client
+            .post("/auth/account/password")
+            .header(ContentType::Form)
+            .body(format!(
+                "current_password=wrong&password={0}&password_confirmation={0}",
+                NEW_PASSWORD
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+
+        let res = client
+            .post("/auth/account/password")
+            .header(ContentType::Form)
+            .body(format!(
+                "current_password={}&password={1}&password_confirmation={1}",
+                STUDENT_PASSWORD, NEW_PASSWORD
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .post("/auth/account/timezone")
+            .header(ContentType::Form)
+            .body("timezone=Not/A_Timezone")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .post("/auth/account/timezone")
+            .header(ContentType::Form)
+            .body("timezone=Europe/London")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let user = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| users::table.find(student_id).first::<User>(c).unwrap())
+            .await;
+        assert_eq!(user.timezone, "Europe/London");
+        assert!(crate::auth::hash::verify_password(NEW_PASSWORD, &user.password).unwrap());
+
+        logout(&client).await;
+        let res = client
+            .post("/auth/login")
+            .header(ContentType::Form)
+            .body(format!(
+                "identifier={}&password={}",
+                STUDENT_EMAIL, STUDENT_PASSWORD
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        login_user(STUDENT_EMAIL, NEW_PASSWORD, &client).await;
+    }
+
+    #[rocket::async_test]
+    async fn test_change_email_requires_verification() {
+        let mock_server = MockServer::start().await;
+        std::env::set_var("SENDGRID_API_KEY", "SomeRandomAPIKey");
+        std::env::set_var("SENDGRID_API_SERVER", mock_server.uri());
+        Mock::given(method("post"))
+            .and(path_regex("/v3/mail/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = client().await;
+        let (_, _, student_id, _, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+        let old_code = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                verification_code(&users::table.find(student_id).first::<User>(c).unwrap())
+            })
+            .await;
+
+        // somebody else already has this address
+        let res = client
+            .post("/auth/account/email")
+            .header(ContentType::Form)
+            .body(format!(
+                "email={}&current_password={}",
+                TEACHER_EMAIL, STUDENT_PASSWORD
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+
+        let res = client
+            .post("/auth/account/email")
+            .header(ContentType::Form)
+            .body(format!(
+                "email=new-address@example.com&current_password={}",
+                STUDENT_PASSWORD
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let user = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| users::table.find(student_id).first::<User>(c).unwrap())
+            .await;
+        assert_eq!(user.email, "new-address@example.com");
+        assert!(!user.email_verified);
+
+        // a link sent to the old address doesn't verify the new one
+        let res = client
+            .get(format!("/auth/verify?code={}", old_code))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let user = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| users::table.find(student_id).first::<User>(c).unwrap())
+            .await;
+        assert!(!user.email_verified);
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_account() {
+        let client = client().await;
+        let (admin_id, teacher_id, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        let (class_id, task_id) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                let class = diesel::insert_into(class::table)
+                    .values(NewClass::new(
+                        "Some class",
+                        "A class with just one teacher.",
+                        Utc::now().naive_utc(),
+                        "account-deletion-class",
+                        Some(institution_id),
+                        None,
+                    ))
+                    .get_result::<Class>(c)
+                    .unwrap();
+                let class_teacher_id = diesel::insert_into(class_teacher::table)
+                    .values(NewClassTeacher {
+                        user_id: teacher_id,
+                        class_id: class.id,
+                    })
+                    .returning(class_teacher::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let task = diesel::insert_into(class_asynchronous_task::table)
+                    .values(NewClassAsynchronousTask {
+                        title: "Some homework",
+                        description: "Set by the teacher who is about to leave.",
+                        created: Utc::now().naive_utc(),
+                        due_date: Utc::now().naive_utc(),
+                        class_teacher_id,
+                        class_id: class.id,
+                        resubmit_until: None,
+                        estimated_minutes: 25,
+                    })
+                    .get_result::<ClassAsynchronousTask>(c)
+                    .unwrap();
+                (class.id, task.id)
+            })
+            .await;
+
+        // the only administrator of an institution can't delete their account
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let res = client
+            .post("/auth/account/delete")
+            .header(ContentType::Form)
+            .body(format!("current_password={}", ADMIN_PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+        logout(&client).await;
+
+        // neither can the only teacher of a class
+        login_user(TEACHER_EMAIL, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .post("/auth/account/delete")
+            .header(ContentType::Form)
+            .body(format!("current_password={}", TEACHER_PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+        assert!(res.into_string().await.unwrap().contains("Some class"));
+
+        // once there is another teacher, the account can be deleted and the task is handed over
+        let admin_class_teacher_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::insert_into(class_teacher::table)
+                    .values(NewClassTeacher {
+                        user_id: admin_id,
+                        class_id,
+                    })
+                    .returning(class_teacher::id)
+                    .get_result::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        let res = client
+            .post("/auth/account/delete")
+            .header(ContentType::Form)
+            .body("current_password=wrong")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        let res = client
+            .post("/auth/account/delete")
+            .header(ContentType::Form)
+            .body(format!("current_password={}", TEACHER_PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(
+            client.get("/auth/account").dispatch().await.status(),
+            Status::NotFound
+        );
+
+        let (teacher_exists, task) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                (
+                    diesel::select(diesel::dsl::exists(users::table.find(teacher_id)))
+                        .get_result::<bool>(c)
+                        .unwrap(),
+                    class_asynchronous_task::table
+                        .find(task_id)
+                        .first::<ClassAsynchronousTask>(c)
+                        .unwrap(),
+                )
+            })
+            .await;
+        assert!(!teacher_exists);
+        assert_eq!(task.class_teacher_id, admin_class_teacher_id);
+
+        // students can delete their accounts without any fuss
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+        let res = client
+            .post("/auth/account/delete")
+            .header(ContentType::Form)
+            .body(format!("current_password={}", STUDENT_PASSWORD))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+}
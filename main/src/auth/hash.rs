@@ -0,0 +1,172 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Password hashing.
+//!
+//! New passwords are hashed with Argon2id. The parameters can be tuned with the
+//! `ARGON2_MEMORY_COST` (in KiB), `ARGON2_TIME_COST` and `ARGON2_PARALLELISM` environment
+//! variables; they default to the minimums recommended by OWASP.
+//!
+//! Passwords used to be hashed with bcrypt, so those hashes are still accepted. Whenever somebody
+//! logs in with a password whose hash is out of date (either because it is a bcrypt hash, or
+//! because the Argon2 parameters have since been changed) it is rehashed, so stored hashes move
+//! over without anybody having to reset their password.
+
+use std::convert::TryFrom;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use thiserror::Error as ThisError;
+
+const DEFAULT_MEMORY_COST: u32 = 19 * 1024;
+const DEFAULT_TIME_COST: u32 = 2;
+const DEFAULT_PARALLELISM: u32 = 1;
+
+#[derive(ThisError, Debug)]
+pub enum HashError {
+    #[error("argon2 error: {0}")]
+    Argon2(argon2::password_hash::Error),
+    #[error("bcrypt error: {0}")]
+    Bcrypt(#[from] bcrypt::BcryptError),
+}
+
+impl From<argon2::password_hash::Error> for HashError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        Self::Argon2(e)
+    }
+}
+
+fn env_param(name: &str, default: u32) -> u32 {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            panic!("the `{}` environment variable must be a whole number", name)
+        }),
+        Err(_) => default,
+    }
+}
+
+lazy_static! {
+    static ref PARAMS: Params = Params::new(
+        env_param("ARGON2_MEMORY_COST", DEFAULT_MEMORY_COST),
+        env_param("ARGON2_TIME_COST", DEFAULT_TIME_COST),
+        env_param("ARGON2_PARALLELISM", DEFAULT_PARALLELISM),
+        None,
+    )
+    .expect("the Argon2 parameters provided are invalid");
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, PARAMS.clone())
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    hash.starts_with("$2")
+}
+
+/// Hashes a password with Argon2id, using the configured parameters.
+pub fn hash_password(password: &str) -> Result<String, HashError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(argon2()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Checks a password against a stored hash, which can either be an Argon2 or a bcrypt hash.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, HashError> {
+    if is_bcrypt(hash) {
+        return Ok(bcrypt::verify(password, hash)?);
+    }
+    let parsed = PasswordHash::new(hash)?;
+    match argon2().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the hash should be replaced with a new one (which will be an Argon2id hash using the
+/// current parameters) the next time we know the password.
+pub fn needs_rehash(hash: &str) -> bool {
+    if is_bcrypt(hash) {
+        return true;
+    }
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() != PARAMS.m_cost()
+                || params.t_cost() != PARAMS.t_cost()
+                || params.p_cost() != PARAMS.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test_hash {
+    use diesel::prelude::*;
+
+    use super::{hash_password, needs_rehash, verify_password};
+    use crate::{
+        db::Database,
+        models::{NewUser, User},
+        schema::users,
+        utils::{client, login_user},
+    };
+
+    const PASSWORD: &str = "SecurePasswordWhichM33tsTh3Criteri@";
+
+    #[test]
+    fn test_hash_and_verify() {
+        let hash = hash_password(PASSWORD).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password(PASSWORD, &hash).unwrap());
+        assert!(!verify_password("not the password", &hash).unwrap());
+        assert!(!needs_rehash(&hash));
+
+        let legacy = bcrypt::hash(PASSWORD, 4).unwrap();
+        assert!(verify_password(PASSWORD, &legacy).unwrap());
+        assert!(!verify_password("not the password", &legacy).unwrap());
+        assert!(needs_rehash(&legacy));
+    }
+
+    #[rocket::async_test]
+    async fn test_legacy_hashes_are_upgraded_on_login() {
+        let client = client().await;
+        let user_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                diesel::insert_into(users::table)
+                    .values(NewUser {
+                        username: "legacy",
+                        email: "legacy@example.com",
+                        password: &bcrypt::hash(PASSWORD, 4).unwrap(),
+                        created: chrono::Utc::now().naive_utc(),
+                        email_verified: true,
+                        timezone: "Africa/Abidjan",
+                    })
+                    .returning(users::id)
+                    .get_result::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        login_user("legacy", PASSWORD, &client).await;
+        let user = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| users::table.find(user_id).first::<User>(c).unwrap())
+            .await;
+        assert!(user.password.starts_with("$argon2id$"));
+        assert!(verify_password(PASSWORD, &user.password).unwrap());
+    }
+}
@@ -0,0 +1,403 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Rate limits for the authentication endpoints.
+//!
+//! Attempts which count towards a limit (failed logins, registrations and so on) are recorded
+//! against the account they were made on (where there is one) and the IP address they came from.
+//! Once more than a handful of attempts have been made within the last hour, each further attempt
+//! has to wait twice as long as the last one did. Accounts which see too many failed logins are
+//! locked for a while (and their owner is sent an email); an administrator of one of the user's
+//! institutions can unlock them early.
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::render::Render;
+use rocket::http::Status;
+use thiserror::Error as ThisError;
+
+use crate::{
+    db::DatabaseConnection,
+    email::{EmailBuilder, RecipientBuilder, RecipientsBuilder, SendMail, SendgridMailSender},
+    models::{
+        auth::limit::{NewAccountLockout, NewAuthAttempt},
+        User,
+    },
+    schema::{account_lockout, auth_attempt},
+    utils::default_head,
+};
+
+/// Attempts older than this are forgotten about.
+const ATTEMPT_WINDOW_MINUTES: i64 = 60;
+/// The longest anybody will be asked to wait between attempts.
+const MAX_DELAY_SECONDS: i64 = ATTEMPT_WINDOW_MINUTES * 60;
+/// How many failed logins within the window cause an account to be locked.
+const LOCKOUT_ATTEMPTS: i64 = 10;
+const LOCKOUT_MINUTES: i64 = 30;
+
+/// The endpoints which are rate limited.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Failed logins.
+    Login,
+    /// Registrations (whether or not they succeed).
+    Register,
+    /// Attempts to verify an email address with an invalid code.
+    VerifyEmail,
+    /// Requests for another verification email.
+    ResendVerification,
+    /// Invalid two-factor authentication (TOTP or recovery) codes.
+    SecondFactor,
+    /// Requests for a password reset email (whether or not the account exists).
+    ResetPassword,
+}
+
+/// How many attempts can be made before back-off starts, and how long the first wait is.
+#[derive(Debug, Copy, Clone)]
+struct Limit {
+    free_attempts: i64,
+    first_delay_seconds: i64,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Login => "login",
+            Action::Register => "register",
+            Action::VerifyEmail => "verify_email",
+            Action::ResendVerification => "resend_verification",
+            Action::SecondFactor => "second_factor",
+            Action::ResetPassword => "reset_password",
+        }
+    }
+
+    fn account_limit(self) -> Option<Limit> {
+        match self {
+            Action::Login => Some(Limit {
+                free_attempts: 3,
+                first_delay_seconds: 1,
+            }),
+            Action::ResendVerification => Some(Limit {
+                free_attempts: 1,
+                first_delay_seconds: 60,
+            }),
+            Action::ResetPassword => Some(Limit {
+                free_attempts: 3,
+                first_delay_seconds: 60,
+            }),
+            Action::SecondFactor => Some(Limit {
+                free_attempts: 3,
+                first_delay_seconds: 1,
+            }),
+            Action::Register | Action::VerifyEmail => None,
+        }
+    }
+
+    fn ip_limit(self) -> Limit {
+        match self {
+            Action::Login => Limit {
+                free_attempts: 20,
+                first_delay_seconds: 1,
+            },
+            Action::Register => Limit {
+                free_attempts: 5,
+                first_delay_seconds: 60,
+            },
+            Action::VerifyEmail => Limit {
+                free_attempts: 5,
+                first_delay_seconds: 10,
+            },
+            Action::ResendVerification | Action::ResetPassword => Limit {
+                free_attempts: 10,
+                first_delay_seconds: 60,
+            },
+            Action::SecondFactor => Limit {
+                free_attempts: 20,
+                first_delay_seconds: 1,
+            },
+        }
+    }
+}
+
+#[derive(ThisError, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    #[error("too many attempts have been made; try again in {0} seconds")]
+    TooManyAttempts(i64),
+    #[error("this account has been locked until {0}")]
+    AccountLocked(NaiveDateTime),
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for LimitError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl LimitError {
+    pub fn status(&self) -> Status {
+        match self {
+            LimitError::TooManyAttempts(_) | LimitError::AccountLocked(_) => {
+                Status::TooManyRequests
+            }
+            LimitError::DatabaseError => Status::InternalServerError,
+        }
+    }
+
+    pub fn explanation(&self) -> String {
+        match self {
+            LimitError::TooManyAttempts(seconds) => format!(
+                "There have been too many attempts recently. Please wait {} before trying again.",
+                describe_wait(*seconds)
+            ),
+            LimitError::AccountLocked(until) => format!(
+                "This account has been temporarily locked because of too many failed login \
+                attempts. It will be unlocked at {} (UTC), or an administrator of your \
+                institution can unlock it sooner.",
+                until.format("%H:%M")
+            ),
+            LimitError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+        }
+    }
+}
+
+impl Render<Html> for LimitError {
+    fn render(self) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head("Too many attempts".to_string()))
+            .body(
+                Body::new()
+                    .child(H1::new("Too many attempts"))
+                    .child(P::with_text(self.explanation())),
+            )
+    }
+}
+
+fn describe_wait(seconds: i64) -> String {
+    match seconds {
+        1 => "a second".to_string(),
+        2..=59 => format!("{} seconds", seconds),
+        60..=119 => "a minute".to_string(),
+        _ => format!("{} minutes", (seconds + 59) / 60),
+    }
+}
+
+/// How long somebody must wait after the last of `attempts` attempts before trying again.
+fn delay(limit: Limit, attempts: i64) -> Option<Duration> {
+    if attempts < limit.free_attempts {
+        return None;
+    }
+    let doublings = (attempts - limit.free_attempts).min(32) as u32;
+    Some(Duration::seconds(
+        limit
+            .first_delay_seconds
+            .saturating_mul(2i64.saturating_pow(doublings))
+            .min(MAX_DELAY_SECONDS),
+    ))
+}
+
+/// How many seconds are left to wait, given when the recent attempts were made.
+fn remaining_wait(limit: Limit, attempts: &[NaiveDateTime], now: NaiveDateTime) -> i64 {
+    match (delay(limit, attempts.len() as i64), attempts.iter().max()) {
+        (Some(delay), Some(last)) => {
+            // rounded up, so that nobody is told to try again before they are allowed to
+            ((*last + delay - now).num_milliseconds().max(0) + 999) / 1000
+        }
+        _ => 0,
+    }
+}
+
+/// Checks whether an attempt may be made now. This should be called before doing anything which
+/// is rate limited (in particular, before checking a password).
+pub fn check(
+    c: &DatabaseConnection,
+    action: Action,
+    user_id: Option<i32>,
+    ip: Option<&str>,
+) -> Result<(), LimitError> {
+    let now = Utc::now().naive_utc();
+    let window_start = now - Duration::minutes(ATTEMPT_WINDOW_MINUTES);
+    let recent = auth_attempt::table
+        .filter(auth_attempt::action.eq(action.name()))
+        .filter(auth_attempt::created.gt(window_start));
+    let mut wait = 0;
+    if let Some(user_id) = user_id {
+        if let Some(locked_until) = account_lockout::table
+            .filter(account_lockout::user_id.eq(user_id))
+            .filter(account_lockout::locked_until.gt(now))
+            .select(account_lockout::locked_until)
+            .first::<NaiveDateTime>(c)
+            .optional()?
+        {
+            return Err(LimitError::AccountLocked(locked_until));
+        }
+        if let Some(limit) = action.account_limit() {
+            let attempts = recent
+                .filter(auth_attempt::user_id.eq(user_id))
+                .select(auth_attempt::created)
+                .load::<NaiveDateTime>(c)?;
+            wait = wait.max(remaining_wait(limit, &attempts, now));
+        }
+    }
+    if let Some(ip) = ip {
+        let attempts = recent
+            .filter(auth_attempt::ip.eq(ip))
+            .select(auth_attempt::created)
+            .load::<NaiveDateTime>(c)?;
+        wait = wait.max(remaining_wait(action.ip_limit(), &attempts, now));
+    }
+    if wait > 0 {
+        Err(LimitError::TooManyAttempts(wait))
+    } else {
+        Ok(())
+    }
+}
+
+/// Records an attempt which counts towards the rate limits.
+///
+/// If this causes the account to be locked, the time until which it is locked is returned (and the
+/// caller should let the user know, using [`send_lockout_email`]).
+pub fn record(
+    c: &DatabaseConnection,
+    action: Action,
+    user_id: Option<i32>,
+    ip: Option<&str>,
+) -> QueryResult<Option<NaiveDateTime>> {
+    let now = Utc::now().naive_utc();
+    let window_start = now - Duration::minutes(ATTEMPT_WINDOW_MINUTES);
+    diesel::delete(auth_attempt::table.filter(auth_attempt::created.le(window_start)))
+        .execute(c)?;
+    diesel::insert_into(auth_attempt::table)
+        .values(NewAuthAttempt {
+            action: action.name(),
+            user_id,
+            ip,
+            created: now,
+        })
+        .execute(c)?;
+    let user_id = match (action, user_id) {
+        (Action::Login, Some(user_id)) => user_id,
+        _ => return Ok(None),
+    };
+    let attempts = auth_attempt::table
+        .filter(auth_attempt::action.eq(action.name()))
+        .filter(auth_attempt::user_id.eq(user_id))
+        .count()
+        .get_result::<i64>(c)?;
+    if attempts < LOCKOUT_ATTEMPTS {
+        return Ok(None);
+    }
+    let locked_until = now + Duration::minutes(LOCKOUT_MINUTES);
+    diesel::insert_into(account_lockout::table)
+        .values(NewAccountLockout {
+            user_id,
+            locked_until,
+            created: now,
+        })
+        .on_conflict(account_lockout::user_id)
+        .do_update()
+        .set((
+            account_lockout::locked_until.eq(locked_until),
+            account_lockout::created.eq(now),
+        ))
+        .execute(c)?;
+    // the count starts again once the lockout is over
+    reset(c, action, user_id)?;
+    Ok(Some(locked_until))
+}
+
+/// Forgets about the attempts made on an account (e.g. once the user has logged in successfully).
+pub fn reset(c: &DatabaseConnection, action: Action, user_id: i32) -> QueryResult<()> {
+    diesel::delete(
+        auth_attempt::table
+            .filter(auth_attempt::action.eq(action.name()))
+            .filter(auth_attempt::user_id.eq(user_id)),
+    )
+    .execute(c)
+    .map(drop)
+}
+
+/// Unlocks an account, and clears its failed logins. Returns `false` if it wasn't locked.
+pub fn unlock(c: &DatabaseConnection, user_id: i32) -> QueryResult<bool> {
+    reset(c, Action::Login, user_id)?;
+    let now = Utc::now().naive_utc();
+    let unlocked = diesel::delete(
+        account_lockout::table
+            .filter(account_lockout::user_id.eq(user_id))
+            .filter(account_lockout::locked_until.gt(now)),
+    )
+    .execute(c)?;
+    diesel::delete(account_lockout::table.filter(account_lockout::user_id.eq(user_id)))
+        .execute(c)?;
+    Ok(unlocked > 0)
+}
+
+/// Lets the owner of an account know that it has been locked.
+pub async fn send_lockout_email(user: &User, locked_until: NaiveDateTime) {
+    let message = format!(
+        "There have been a lot of failed attempts to log in to your Lovelace account, so we've \
+        locked it until {} (UTC). If this wasn't you, somebody may be trying to guess your \
+        password; you might want to choose a new (stronger) one by resetting it. If you need to \
+        log in before then, an administrator of your institution can unlock your account.",
+        locked_until.format("%Y-%m-%d %H:%M")
+    );
+    if let Err(e) = SendgridMailSender::default()
+        .send(
+            &EmailBuilder::default()
+                .subject("Your account has been locked".to_string())
+                .plaintext(Some(message.clone()))
+                .html_text(Some(
+                    Html::new()
+                        .head(default_head("Your account has been locked".to_string()))
+                        .body(
+                            Body::new().child(P::with_text(message)).child(
+                                A::new()
+                                    .attribute(Href::new("/auth/reset"))
+                                    .text("Reset your password"),
+                            ),
+                        )
+                        .to_string(),
+                ))
+                .recipients(
+                    RecipientsBuilder::default()
+                        .recipients(vec![RecipientBuilder::default()
+                            .email(user.email.clone())
+                            .name(user.username.clone())
+                            .build()
+                            .unwrap()])
+                        .build()
+                        .unwrap(),
+                )
+                .from(("Lovelace".to_string(), "no-reply@lovelace.ga".to_string()))
+                .reply_to(("Lovelace".to_string(), "contact@lovelace.ga".to_string()))
+                .build()
+                .unwrap(),
+        )
+        .await
+    {
+        error!("failed to send lockout email: {:#?}", e);
+    }
+}
+
+#[cfg(test)]
+mod test_limit {
+    use super::{delay, Action};
+
+    #[test]
+    fn test_back_off_doubles() {
+        let limit = Action::Login.account_limit().unwrap();
+        assert_eq!(delay(limit, 2), None);
+        assert_eq!(delay(limit, 3).unwrap().num_seconds(), 1);
+        assert_eq!(delay(limit, 4).unwrap().num_seconds(), 2);
+        assert_eq!(delay(limit, 8).unwrap().num_seconds(), 32);
+        assert_eq!(delay(limit, 1000).unwrap().num_seconds(), 60 * 60);
+    }
+}
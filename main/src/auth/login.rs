@@ -1,17 +1,23 @@
-use bcrypt::verify;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use malvolio::prelude::*;
 use mercutio::Apply;
 use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
-use portia::render::RenderCtx;
-use rocket::http::{Cookie, CookieJar, Status};
+use portia::render::{Render, RenderCtx};
+use rocket::http::{CookieJar, Status};
 use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
 use crate::ui::page::Page;
 use crate::{db::Database, models::User, utils::default_head};
 
-use super::{OptionAuthCookie, LOGIN_COOKIE};
+use super::{
+    hash::{hash_password, needs_rehash, verify_password},
+    limit::{self, Action, LimitError},
+    session::{start_session, SessionMetadata},
+    two_factor::{second_factor_for, second_factor_form, PendingLogin, SecondFactor},
+    OptionAuthCookie,
+};
 
 fn login_form() -> malvolio::prelude::Form {
     malvolio::prelude::Form::new()
@@ -49,6 +55,17 @@ pub fn login_page() -> Html {
             Page::default()
                 .child(H1::new("Login"))
                 .child(login_form())
+                .child(
+                    A::default()
+                        .attribute(Href::new("/auth/reset"))
+                        .text("Forgotten your password?"),
+                )
+                .child(Br)
+                .child(
+                    A::default()
+                        .attribute(Href::new("/auth/oidc"))
+                        .text("Sign in with your school account"),
+                )
                 .render(OptionAuthCookie::none()),
         )
 }
@@ -61,40 +78,137 @@ pub enum LoginError {
     PasswordNotValid,
     #[error("database error")]
     DatabaseError,
+    #[error("second factor required")]
+    SecondFactorRequired,
+    #[error("second factor enrolment required")]
+    SecondFactorEnrolmentRequired,
+    #[error("too many attempts")]
+    TooManyAttempts(i64),
+    #[error("account locked")]
+    AccountLocked(NaiveDateTime),
+}
+
+impl From<LimitError> for LoginError {
+    fn from(e: LimitError) -> Self {
+        match e {
+            LimitError::TooManyAttempts(seconds) => LoginError::TooManyAttempts(seconds),
+            LimitError::AccountLocked(until) => LoginError::AccountLocked(until),
+            LimitError::DatabaseError => LoginError::DatabaseError,
+        }
+    }
 }
 
 async fn login_base(
     cookies: &CookieJar<'_>,
+    metadata: &SessionMetadata,
     data: &LoginData,
     conn: Database,
 ) -> Result<User, LoginError> {
     use crate::schema::users;
     let closure_data = data.clone();
-    match conn
+    let ip = metadata.ip.clone();
+    let user = conn
         .run(move |c| {
-            users::table
+            let user = users::table
                 .filter(users::username.eq(&closure_data.identifier))
                 .or_filter(users::email.eq(&closure_data.identifier))
                 .first::<User>(c)
+                .optional()
+                .map_err(|e| {
+                    error!("{:#?}", e);
+                    LoginError::DatabaseError
+                })?;
+            limit::check(
+                c,
+                Action::Login,
+                user.as_ref().map(|user| user.id),
+                ip.as_deref(),
+            )?;
+            Ok::<_, LoginError>(user)
         })
-        .await
+        .await?;
+    let user = match user {
+        Some(user) => user,
+        None => {
+            let ip = metadata.ip.clone();
+            conn.run(move |c| limit::record(c, Action::Login, None, ip.as_deref()))
+                .await
+                .map_err(|e| {
+                    error!("{:#?}", e);
+                    LoginError::DatabaseError
+                })?;
+            return Err(LoginError::UserNotFound);
+        }
+    };
+    let user_id = user.id;
+    let ip = metadata.ip.clone();
+    if !verify_password(&data.password, &user.password)
+        .map_err(|e| error!("{:#?}", e))
+        .unwrap_or(false)
     {
-        Ok(user) => {
-            if verify(&data.password, &user.password)
-                .map_err(|e| error!("{:#?}", e))
-                .unwrap_or(false)
-            {
-                cookies.add_private(Cookie::new(LOGIN_COOKIE, user.id.to_string()));
-                Ok(user)
-            } else {
-                Err(LoginError::PasswordNotValid)
+        let locked_until = conn
+            .run(move |c| limit::record(c, Action::Login, Some(user_id), ip.as_deref()))
+            .await
+            .map_err(|e| {
+                error!("{:#?}", e);
+                LoginError::DatabaseError
+            })?;
+        return Err(match locked_until {
+            Some(locked_until) => {
+                limit::send_lockout_email(&user, locked_until).await;
+                LoginError::AccountLocked(locked_until)
             }
+            None => LoginError::PasswordNotValid,
+        });
+    }
+    conn.run(move |c| limit::reset(c, Action::Login, user_id))
+        .await
+        .map_err(|e| {
+            error!("{:#?}", e);
+            LoginError::DatabaseError
+        })?;
+    if needs_rehash(&user.password) {
+        // this is the only time we know the password, so old hashes are upgraded here (if this
+        // fails we'll try again the next time they log in)
+        match hash_password(&data.password) {
+            Ok(hashed_password) => {
+                if let Err(e) = conn
+                    .run(move |c| {
+                        use crate::schema::users;
+                        diesel::update(users::table.find(user_id))
+                            .set(users::password.eq(hashed_password))
+                            .execute(c)
+                    })
+                    .await
+                {
+                    error!("{:#?}", e);
+                }
+            }
+            Err(e) => error!("{:#?}", e),
         }
-        Err(error) => match error {
-            diesel::result::Error::NotFound => Err(LoginError::UserNotFound),
-            _ => Err(LoginError::DatabaseError),
-        },
     }
+    let second_factor = second_factor_for(user.id, &conn).await.map_err(|e| {
+        error!("{:#?}", e);
+        LoginError::DatabaseError
+    })?;
+    if second_factor != SecondFactor::NotNeeded {
+        PendingLogin {
+            user_id: user.id,
+            stage: second_factor,
+        }
+        .set(cookies);
+        return Err(match second_factor {
+            SecondFactor::Enrol => LoginError::SecondFactorEnrolmentRequired,
+            _ => LoginError::SecondFactorRequired,
+        });
+    }
+    start_session(user.id, metadata, cookies, &conn)
+        .await
+        .map_err(|e| {
+            error!("{:#?}", e);
+            LoginError::DatabaseError
+        })?;
+    Ok(user)
 }
 
 #[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
@@ -118,10 +232,11 @@ pub struct LoginErrorExplanation {
 #[post("/login", data = "<data>")]
 pub async fn api_login(
     cookies: &CookieJar<'_>,
+    metadata: SessionMetadata,
     data: Json<LoginData>,
     conn: Database,
 ) -> Json<LoginResponse> {
-    Json(match login_base(cookies, &data, conn).await {
+    Json(match login_base(cookies, &metadata, &data, conn).await {
         Ok(user) => LoginResponse {
             success: true,
             data: Some(user),
@@ -149,6 +264,38 @@ pub async fn api_login(
                     reason: "There was an internal database error logging in.".to_string(),
                 }),
             },
+            LoginError::SecondFactorRequired => LoginResponse {
+                success: false,
+                data: None,
+                error: Some(LoginErrorExplanation {
+                    reason: "A code from your authenticator app is required to finish logging \
+                    in (send it to `/api/auth/login/2fa`)."
+                        .to_string(),
+                }),
+            },
+            LoginError::SecondFactorEnrolmentRequired => LoginResponse {
+                success: false,
+                data: None,
+                error: Some(LoginErrorExplanation {
+                    reason: "You must set up two-factor authentication before you can log in \
+                    (see `/auth/2fa`)."
+                        .to_string(),
+                }),
+            },
+            LoginError::TooManyAttempts(seconds) => LoginResponse {
+                success: false,
+                data: None,
+                error: Some(LoginErrorExplanation {
+                    reason: LimitError::TooManyAttempts(seconds).explanation(),
+                }),
+            },
+            LoginError::AccountLocked(until) => LoginResponse {
+                success: false,
+                data: None,
+                error: Some(LoginErrorExplanation {
+                    reason: LimitError::AccountLocked(until).explanation(),
+                }),
+            },
         },
     })
 }
@@ -156,10 +303,11 @@ pub async fn api_login(
 #[post("/login", data = "<data>")]
 pub async fn html_login(
     cookies: &CookieJar<'_>,
+    metadata: SessionMetadata,
     data: rocket::form::Form<LoginData>,
     conn: Database,
 ) -> Html {
-    match login_base(cookies, &data, conn).await {
+    match login_base(cookies, &metadata, &data, conn).await {
         Ok(_) => Html::default()
             .head(default_head("Logged in".to_string()))
             .body(
@@ -200,6 +348,38 @@ pub async fn html_login(
                         ))
                         .child(login_form()),
                 ),
+            LoginError::SecondFactorRequired => Html::default()
+                .head(default_head("Two-factor authentication".to_string()))
+                .body(
+                    Body::default()
+                        .child(H1::new("Two-factor authentication"))
+                        .child(P::with_text(
+                            "Enter the code from your authenticator app (or one of your recovery \
+                            codes) to finish logging in.",
+                        ))
+                        .child(second_factor_form()),
+                ),
+            LoginError::SecondFactorEnrolmentRequired => Html::default()
+                .head(default_head("Set up two-factor authentication".to_string()))
+                .body(
+                    Body::default()
+                        .child(H1::new("Set up two-factor authentication"))
+                        .child(P::with_text(
+                            "An institution which you are part of requires you to use two-factor \
+                            authentication. You'll need to set it up before you can log in.",
+                        ))
+                        .child(
+                            A::default()
+                                .attribute(Href::new("/auth/2fa"))
+                                .text("Set up two-factor authentication"),
+                        ),
+                ),
+            LoginError::TooManyAttempts(seconds) => {
+                Render::<Html>::render(LimitError::TooManyAttempts(seconds))
+            }
+            LoginError::AccountLocked(until) => {
+                Render::<Html>::render(LimitError::AccountLocked(until))
+            }
         },
     }
 }
@@ -1,24 +1,33 @@
 use malvolio::prelude::*;
-use rocket::http::{Cookie, CookieJar};
+use rocket::http::CookieJar;
 use rocket::serde::json::Json;
 
-use crate::utils::default_head;
+use crate::{
+    db::Database,
+    utils::{default_head, error_message},
+};
 
-use super::LOGIN_COOKIE;
+use super::session::end_session;
 
 #[get("/logout")]
-pub fn html_logout_user(cookies: &CookieJar<'_>) -> Html {
-    if cookies.get_private(LOGIN_COOKIE).is_none() {
-        return Html::default()
+pub async fn html_logout_user(cookies: &CookieJar<'_>, conn: Database) -> Html {
+    match end_session(cookies, &conn).await {
+        Ok(true) => Html::default()
+            .head(default_head("Logged out.".to_string()))
+            .body(Body::default().child(H1::new("You are logged out.".to_string()))),
+        Ok(false) => Html::default()
             .head(default_head("Cannot log you out.".to_string()))
             .body(
                 Body::default().child(H1::new("You are not logged in, so we cannot log you out.")),
-            );
+            ),
+        Err(e) => {
+            error!("{:#?}", e);
+            error_message(
+                "Database error".to_string(),
+                "We couldn't log you out because of a database error.".to_string(),
+            )
+        }
     }
-    cookies.remove_private(Cookie::named(LOGIN_COOKIE));
-    Html::default()
-        .head(default_head("Logged out.".to_string()))
-        .body(Body::default().child(H1::new("You are logged out.".to_string())))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -33,19 +42,26 @@ pub struct LogoutError {
 }
 
 #[get("/logout")]
-pub fn api_logout(cookies: &CookieJar<'_>) -> Json<LogoutResponse> {
-    Json(if cookies.get_private(LOGIN_COOKIE).is_none() {
-        LogoutResponse {
+pub async fn api_logout(cookies: &CookieJar<'_>, conn: Database) -> Json<LogoutResponse> {
+    Json(match end_session(cookies, &conn).await {
+        Ok(true) => LogoutResponse {
+            success: true,
+            error: None,
+        },
+        Ok(false) => LogoutResponse {
             success: false,
             error: Some(LogoutError {
                 reason: "You are not logged in, so you cannot be logged out.".to_string(),
             }),
-        }
-    } else {
-        cookies.remove_private(Cookie::named(LOGIN_COOKIE));
-        LogoutResponse {
-            success: true,
-            error: None,
+        },
+        Err(e) => {
+            error!("{:#?}", e);
+            LogoutResponse {
+                success: false,
+                error: Some(LogoutError {
+                    reason: "There was an internal database error logging out.".to_string(),
+                }),
+            }
         }
     })
 }
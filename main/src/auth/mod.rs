@@ -3,24 +3,131 @@ This source code file is distributed subject to the terms of the GNU Affero Gene
 A copy of this exp: (), user_id: () exp: (), user_id: () exp: (), user_id: () exp: (), user_id: () license can be found in the `licenses` directory at the root of this project.
 */
 
-use rocket::{outcome::IntoOutcome, request::FromRequest};
+use diesel::prelude::*;
+use rocket::{http::Cookie, outcome::IntoOutcome, request::FromRequest, Request};
 use thiserror::Error as ThisError;
 
+use self::{
+    session::{find_session, ResolvedSession},
+    token::{bearer_token, find_api_token, hash_token, ResolvedApiToken},
+    verify::ResolvedEmailVerification,
+};
+use crate::{db::Database, schema::users};
+
 pub const LOGIN_COOKIE: &str = "AUTHORISED";
 
+mod account;
+pub mod hash;
+pub mod limit;
 mod login;
 mod logout;
-mod register;
+mod oidc;
+pub mod register;
 mod reset;
-mod verify;
+pub mod session;
+pub mod token;
+pub mod two_factor;
+pub mod verify;
 
+pub use account::{
+    account_page, api_change_email, api_change_password, api_change_timezone, api_delete_account,
+    delete_account_page, html_change_email, html_change_password, html_change_timezone,
+    html_delete_account,
+};
 pub use login::{api_login, html_login, login_page};
 pub use logout::{api_logout, html_logout_user};
+pub use oidc::{begin_oidc_login, oidc_callback, oidc_page};
 pub use register::{api_register, html_register, register_page};
-pub use verify::verify_email;
+pub use reset::{
+    api_apply_reset, api_request_reset, confirm_reset_page, html_apply_reset, html_request_reset,
+    reset_page,
+};
+pub use session::{api_list_sessions, api_revoke_session, html_revoke_session, sessions_page};
+pub use token::{
+    api_create_token, api_list_tokens, api_revoke_token, html_create_token, html_revoke_token,
+    scope, tokens_page, ApiAuth,
+};
+pub use two_factor::{
+    api_begin_enrolment, api_complete_login, api_confirm_enrolment, api_disable_two_factor,
+    html_complete_login, html_confirm_enrolment, html_disable_two_factor, two_factor_page,
+};
+pub use verify::{
+    api_resend_verification, html_resend_verification, verify_email, EmailVerification,
+};
+
+/// Works out who made the request (from the login cookie, or the API token in the `Authorization`
+/// header) and whether they've verified their email address, and stores the answers in the
+/// request's local cache (for [`ResolvedSession`], [`ResolvedApiToken`] and [`EmailVerification`]).
+///
+/// This runs as a fairing, once per request, so that the request guards don't need database
+/// connections of their own (see [`session`]). Requests which don't have any credentials don't
+/// touch the database at all.
+pub async fn resolve_credentials(request: &Request<'_>) {
+    let session_token = request
+        .cookies()
+        .get_private(LOGIN_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    let token_hash = bearer_token(request).map(hash_token);
+    if session_token.is_none() && token_hash.is_none() {
+        return;
+    }
+    let conn = match request.guard::<Database>().await.succeeded() {
+        Some(conn) => conn,
+        None => return,
+    };
+    let has_cookie = session_token.is_some();
+    let resolved = conn
+        .run(move |c| -> QueryResult<_> {
+            let session = match session_token {
+                Some(token) => find_session(&token, c)?,
+                None => None,
+            };
+            let token = match token_hash {
+                Some(token_hash) => find_api_token(&token_hash, c)?,
+                None => None,
+            };
+            let user_id = match (&session, &token) {
+                (Some(session), _) => Some(session.user_id),
+                (None, Some(token)) => Some(token.user_id),
+                (None, None) => None,
+            };
+            let verified = match user_id {
+                Some(user_id) => Some(
+                    users::table
+                        .find(user_id)
+                        .select(users::email_verified)
+                        .first::<bool>(c)?,
+                ),
+                None => None,
+            };
+            Ok((session, token, verified))
+        })
+        .await;
+    match resolved {
+        Ok((session, token, verified)) => {
+            // the session has expired or been revoked
+            if has_cookie && session.is_none() {
+                request
+                    .cookies()
+                    .remove_private(Cookie::named(LOGIN_COOKIE));
+            }
+            request.local_cache(move || ResolvedSession(session));
+            request.local_cache(move || ResolvedApiToken(token));
+            request.local_cache(move || {
+                ResolvedEmailVerification(verified.map(|verified| EmailVerification { verified }))
+            });
+        }
+        Err(e) => error!("{:#?}", e),
+    }
+}
 
 #[derive(ThisError, Debug)]
-pub enum AuthError {}
+pub enum AuthError {
+    #[error("the API token provided is invalid, has expired or has been revoked")]
+    InvalidToken,
+    #[error("the API token provided has not been granted the scope required for this request")]
+    InsufficientScope,
+}
 
 #[derive(Debug, Copy, Clone)]
 pub struct AuthCookie(pub i32);
@@ -32,11 +139,10 @@ impl<'r> FromRequest<'r> for AuthCookie {
     async fn from_request(
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
-        request
-            .cookies()
-            .get_private(LOGIN_COOKIE)
-            .and_then(|cookie| cookie.value().parse().ok())
-            .map(AuthCookie)
+        ResolvedSession::of(request)
+            .0
+            .as_ref()
+            .map(|session| AuthCookie(session.user_id))
             .or_forward(())
     }
 }
@@ -64,10 +170,10 @@ impl<'r> FromRequest<'r> for OptionAuthCookie {
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
         rocket::request::Outcome::Success(OptionAuthCookie(
-            request
-                .cookies()
-                .get_private(LOGIN_COOKIE)
-                .and_then(|cookie| cookie.value().parse().ok()),
+            ResolvedSession::of(request)
+                .0
+                .as_ref()
+                .map(|session| session.user_id),
         ))
     }
 }
@@ -92,7 +198,7 @@ mod test_authentication {
         Mock, MockServer, ResponseTemplate,
     };
 
-    use super::verify::EmailVerificationToken;
+    use super::verify::verification_code;
 
     #[rocket::async_test]
     async fn test_register_validation() {
@@ -171,7 +277,7 @@ mod test_authentication {
     async fn test_email_verification() {
         use crate::schema::users::dsl as users;
         let client = client().await;
-        let user_id = Database::get_one(client.rocket())
+        let user = Database::get_one(client.rocket())
             .await
             .unwrap()
             .run(|c| {
@@ -184,29 +290,13 @@ mod test_authentication {
                         email_verified: false,
                         timezone: "Africa/Abidjan",
                     })
-                    .returning(users::id)
-                    .get_result::<i32>(c)
+                    .get_result::<User>(c)
                     .unwrap()
             })
             .await;
+        let user_id = user.id;
         let res = client
-            .get(format!(
-                "/auth/verify?code={}",
-                jwt::encode(
-                    &jwt::Header::default(),
-                    &EmailVerificationToken {
-                        exp: (chrono::Utc::now() + chrono::Duration::days(1)).timestamp() as usize,
-                        user_id
-                    },
-                    &jwt::EncodingKey::from_base64_secret(
-                        &std::env::var("SECRET_KEY").unwrap_or_else(|_| {
-                            "NNnXxqFeQ/1Sn8lh9MtlIW2uePR4TL/1O5dB2CPkTmg=".to_string()
-                        })
-                    )
-                    .unwrap(),
-                )
-                .unwrap()
-            ))
+            .get(format!("/auth/verify?code={}", verification_code(&user)))
             .dispatch()
             .await;
         let string = res.into_string().await.expect("invalid body response");
@@ -228,4 +318,102 @@ mod test_authentication {
             true
         )
     }
+
+    #[rocket::async_test]
+    async fn test_verification_is_rate_limited() {
+        use rocket::http::Status;
+        let client = client().await;
+        let remote = "203.0.113.7:4000".parse::<std::net::SocketAddr>().unwrap();
+        for _ in 0..5 {
+            let res = client
+                .get("/auth/verify?code=not-a-real-code")
+                .remote(remote)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::BadRequest);
+        }
+        let res = client
+            .get("/auth/verify?code=not-a-real-code")
+            .remote(remote)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::TooManyRequests);
+        // other people are unaffected
+        let res = client
+            .get("/auth/verify?code=not-a-real-code")
+            .remote("198.51.100.2:4000".parse().unwrap())
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_unverified_users_are_restricted() {
+        use crate::schema::users::dsl as users;
+        use rocket::http::Status;
+        let mock_server = MockServer::start().await;
+        std::env::set_var("SENDGRID_API_KEY", "SomeRandomAPIKey");
+        std::env::set_var("SENDGRID_API_SERVER", mock_server.uri());
+        Mock::given(method("post"))
+            .and(path_regex("/v3/mail/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let client = client().await;
+        let user_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                diesel::insert_into(users::users)
+                    .values(NewUser {
+                        username: USERNAME,
+                        email: EMAIL,
+                        password: &crate::auth::hash::hash_password(PASSWORD).unwrap(),
+                        created: chrono::Utc::now().naive_utc(),
+                        email_verified: false,
+                        timezone: TIMEZONE,
+                    })
+                    .returning(users::id)
+                    .get_result::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        login_user(USERNAME, PASSWORD, &client).await;
+
+        let create_class = || {
+            client
+                .post("/class/create")
+                .header(ContentType::Form)
+                .body("name=Some+class&description=A+class")
+                .dispatch()
+        };
+        let res = create_class().await;
+        assert_eq!(res.status(), Status::Forbidden);
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("verify your email address"));
+
+        let res = client.post("/auth/verify/resend").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        // the next email can't be sent straight away
+        let res = client.post("/auth/verify/resend").dispatch().await;
+        assert_eq!(res.status(), Status::TooManyRequests);
+
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::update(users::users.find(user_id))
+                    .set(users::email_verified.eq(true))
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        assert_eq!(create_class().await.status(), Status::Ok);
+        let res = client.post("/auth/verify/resend").dispatch().await;
+        assert_eq!(res.status(), Status::Conflict);
+    }
 }
@@ -1,12 +1,11 @@
 use std::str::FromStr;
 
-use bcrypt::{hash, DEFAULT_COST};
 use chrono::Utc;
 use diesel::{insert_into, prelude::*};
 use malvolio::prelude::*;
 use mercutio::Apply;
 use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
-use portia::render::RenderCtx;
+use portia::render::{Render, RenderCtx};
 use regex::Regex;
 use rocket::http::CookieJar;
 use rocket::serde::json::Json;
@@ -15,13 +14,17 @@ use thiserror::Error as ThisError;
 use crate::ui::page::Page;
 use crate::{
     db::Database,
-    email::{EmailBuilder, RecipientBuilder, RecipientsBuilder, SendMail, SendgridMailSender},
     models::{NewUser, User},
     utils::{default_head, json_response::ApiResponse, timezones::timezone_field},
 };
 
-use super::OptionAuthCookie;
-use super::{verify::EmailVerificationToken, LOGIN_COOKIE};
+use super::{
+    hash::hash_password,
+    limit::{self, Action, LimitError},
+    session::SessionMetadata,
+    OptionAuthCookie,
+};
+use super::{verify::send_verification_email, LOGIN_COOKIE};
 
 fn register_form() -> malvolio::prelude::Form {
     malvolio::prelude::Form::new()
@@ -91,7 +94,7 @@ pub struct RegisterData {
 }
 
 lazy_static! {
-    static ref EMAIL_RE: Regex =
+    pub(crate) static ref EMAIL_RE: Regex =
         Regex::new(r#"(?:[a-z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-z0-9!#$%&'*+/=?^_`{|}~-]+)*|"(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21\x23-\x5b\x5d-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])*")@(?:(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z0-9](?:[a-z0-9-]*[a-z0-9])?|\[(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?|[a-z0-9-]*[a-z0-9]:(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21-\x5a\x53-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])+)\])"#).unwrap();
 }
 
@@ -111,10 +114,31 @@ pub enum RegisterError {
     UserAlreadyRegistered,
     #[error("database error")]
     DatabaseError,
+    #[error("too many registrations")]
+    TooManyAttempts(i64),
+}
+
+#[derive(ThisError, Debug)]
+pub enum PasswordError {
+    #[error("passwords do not match")]
+    NonMatchingPasswords,
+}
+
+/// Checks that a newly chosen password is acceptable. Both registration and password resets go
+/// through this, so that the same rules apply no matter how a password was set.
+pub(crate) fn validate_new_password(
+    password: &str,
+    password_confirmation: &str,
+) -> Result<(), PasswordError> {
+    if password != password_confirmation {
+        return Err(PasswordError::NonMatchingPasswords);
+    }
+    Ok(())
 }
 
 pub async fn register_base(
     data: &RegisterData,
+    metadata: &SessionMetadata,
     conn: Database,
     cookies: &CookieJar<'_>,
 ) -> Result<User, RegisterError> {
@@ -122,6 +146,18 @@ pub async fn register_base(
     if cookies.get(LOGIN_COOKIE).is_some() {
         return Err(RegisterError::AlreadyLoggedInError);
     };
+    // every attempt counts, so that this can't be used to send lots of emails
+    let ip = metadata.ip.clone();
+    conn.run(move |c| {
+        limit::check(c, Action::Register, None, ip.as_deref())?;
+        limit::record(c, Action::Register, None, ip.as_deref())?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| match e {
+        LimitError::TooManyAttempts(seconds) => RegisterError::TooManyAttempts(seconds),
+        LimitError::AccountLocked(_) | LimitError::DatabaseError => RegisterError::DatabaseError,
+    })?;
     let chrono_timezone: chrono_tz::Tz = match FromStr::from_str(data.timezone.trim()) {
         Ok(tz) => tz,
         Err(_) => return Err(RegisterError::InvalidTimezoneError),
@@ -129,10 +165,10 @@ pub async fn register_base(
     if !EMAIL_RE.is_match(&data.email) {
         return Err(RegisterError::InvalidEmail);
     }
-    if data.password != data.password_confirmation {
-        return Err(RegisterError::NonMatchingPasswords);
-    }
-    let hashed_password = match hash(&data.password, DEFAULT_COST) {
+    validate_new_password(&data.password, &data.password_confirmation).map_err(|e| match e {
+        PasswordError::NonMatchingPasswords => RegisterError::NonMatchingPasswords,
+    })?;
+    let hashed_password = match hash_password(&data.password) {
         Ok(string) => string,
         Err(err) => {
             error!("{:#?}", err);
@@ -156,59 +192,10 @@ pub async fn register_base(
         .await
     {
         Ok(user) => {
-            let email_verification_link = format!(
-                "/auth/verify?code={}",
-                jwt::encode(
-                    &jwt::Header::default(),
-                    &EmailVerificationToken {
-                        exp: (chrono::Utc::now() + chrono::Duration::days(1)).timestamp() as usize,
-                        user_id: user.id
-                    },
-                    &jwt::EncodingKey::from_base64_secret(
-                        &std::env::var("SECRET_KEY").unwrap_or_else(|_| {
-                            "NNnXxqFeQ/1Sn8lh9MtlIW2uePR4TL/1O5dB2CPkTmg=".to_string()
-                        })
-                    )
-                    .unwrap(),
-                )
-                .unwrap()
-            );
-            let mail_sender = SendgridMailSender::default();
-            mail_sender
-                .send(
-                    &EmailBuilder::default()
-                        .subject("Verify your email".to_string())
-                        .plaintext(Some(format!(
-                            "Copy and paste this link into your browser: {}",
-                            email_verification_link
-                        )))
-                        .html_text(Some(
-                            Html::new()
-                                .head(default_head("Verify your email".to_string()))
-                                .body(
-                                    Body::new().child(P::with_text("Verify your email")).child(
-                                        A::new().attribute(Href::new(email_verification_link)),
-                                    ),
-                                )
-                                .to_string(),
-                        ))
-                        .recipients(
-                            RecipientsBuilder::default()
-                                .recipients(vec![RecipientBuilder::default()
-                                    .email(user.email.clone())
-                                    .name(user.username.clone())
-                                    .build()
-                                    .unwrap()])
-                                .build()
-                                .unwrap(),
-                        )
-                        .from(("Lovelace".to_string(), "no-reply@lovelace.ga".to_string()))
-                        .reply_to(("Lovelace".to_string(), "contact@lovelace.ga".to_string()))
-                        .build()
-                        .unwrap(),
-                )
-                .await
-                .expect("fatal: failed to send verification email");
+            // the account is still created if this fails; the user can ask for another email
+            if let Err(e) = send_verification_email(&user).await {
+                error!("failed to send verification email: {:#?}", e);
+            }
             Ok(user)
         }
         Err(problem) => match problem {
@@ -227,16 +214,21 @@ pub async fn register_base(
 #[post("/register", data = "<data>")]
 pub async fn html_register(
     data: rocket::form::Form<RegisterData>,
+    metadata: SessionMetadata,
     conn: Database,
     cookies: &CookieJar<'_>,
 ) -> Html {
-    match register_base(&data, conn, cookies).await {
+    match register_base(&data, &metadata, conn, cookies).await {
         Ok(_) => Html::default()
             .head(default_head("You have sucessfully registered!".to_string()))
             .body(
                 Body::default()
                     .child(H1::new("Registration successful!"))
-                    .child(P::with_text("We're so happy to have you on board.")),
+                    .child(P::with_text("We're so happy to have you on board."))
+                    .child(P::with_text(
+                        "We've sent you an email with a link to verify your email address. If it \
+                        doesn't arrive, you can ask for another one from your account settings.",
+                    )),
             ),
         Err(e) => {
             match e {
@@ -316,6 +308,9 @@ pub async fn html_register(
                             .child(register_form()),
                     )
                 }
+                RegisterError::TooManyAttempts(seconds) => {
+                    Render::<Html>::render(LimitError::TooManyAttempts(seconds))
+                }
             }
         }
     }
@@ -324,10 +319,11 @@ pub async fn html_register(
 #[post("/register", data = "<data>")]
 pub async fn api_register(
     data: rocket::form::Form<RegisterData>,
+    metadata: SessionMetadata,
     conn: Database,
     cookies: &CookieJar<'_>,
 ) -> Json<ApiResponse<User>> {
-    Json(match register_base(&data, conn, cookies).await {
+    Json(match register_base(&data, &metadata, conn, cookies).await {
         Ok(user) => ApiResponse::new_ok(user),
         Err(e) => ApiResponse::new_err(match e {
             RegisterError::InvalidEmail => {
@@ -343,6 +339,10 @@ pub async fn api_register(
             RegisterError::DatabaseError => {
                 "Encountered a database error while undertaking this operation."
             }
+            RegisterError::TooManyAttempts(_) => {
+                "Too many accounts have been registered from your network recently. Please try \
+                again later."
+            }
         }),
     })
 }
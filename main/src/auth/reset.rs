@@ -1,13 +1,676 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Password resets.
+//!
+//! A user who has forgotten their password supplies their username or email address. We then email
+//! them a link containing a signed token (a JWT, in the same way as for email verification) which
+//! they can use to choose a new password.
+//!
+//! Each token carries a random `nonce` which is also stored in the `password_reset` table. A token
+//! is only accepted while its nonce is still in that table, and using any token removes all of the
+//! user's rows – so tokens are single-use, and using one invalidates any older ones. Resetting a
+//! password also logs the user out everywhere, in case somebody else had access to their account.
+
+use chrono::Utc;
+use diesel::prelude::*;
 use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
+use portia::render::RenderCtx;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use thiserror::Error as ThisError;
 
-#[get("/reset")]
-#[allow(unused)]
-pub fn reset() -> Html {
-    todo!()
+use crate::{
+    db::Database,
+    email::{EmailBuilder, RecipientBuilder, RecipientsBuilder, SendMail, SendgridMailSender},
+    models::{auth::reset::NewPasswordReset, User},
+    ui::page::Page,
+    utils::{default_head, json_response::ApiResponse},
+};
+
+use super::{
+    hash::hash_password,
+    limit::{self, LimitError},
+    register::{validate_new_password, PasswordError},
+    session::{end_all_sessions, SessionMetadata},
+    OptionAuthCookie,
+};
+
+/// How long a password reset link remains valid for.
+const RESET_TOKEN_VALIDITY_HOURS: i64 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetToken {
+    pub exp: usize,
+    pub user_id: i32,
+    pub nonce: String,
 }
 
-#[post("/reset")]
-#[allow(unused)]
+fn request_reset_form() -> malvolio::prelude::Form {
+    malvolio::prelude::Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Placeholder::new("Username or email"))
+                .attribute(Name::new("identifier")),
+        )
+        .child(Br)
+        .child(
+            Input::default()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Send me a reset link")),
+        )
+}
+
+fn apply_reset_form(token: &str) -> malvolio::prelude::Form {
+    malvolio::prelude::Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/reset/confirm"))
+        .child(
+            Input::default()
+                .attribute(Type::Hidden)
+                .attribute(Name::new("token"))
+                .attribute(Value::new(token.to_string())),
+        )
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Password)
+                .attribute(Placeholder::new("New password"))
+                .attribute(Name::new("password")),
+        )
+        .child(Br)
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Password)
+                .attribute(Placeholder::new("New password confirmation"))
+                .attribute(Name::new("password_confirmation")),
+        )
+        .child(Br)
+        .child(
+            Input::default()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Reset password")),
+        )
+}
+
+#[get("/reset")]
 pub fn reset_page() -> Html {
-    todo!()
+    Html::default()
+        .head(default_head("Reset your password".to_string()))
+        .body(
+            Page::default()
+                .child(H1::new("Reset your password"))
+                .child(P::with_text(
+                    "Enter your username or email and we'll send you a link which you can use to \
+                    choose a new password.",
+                ))
+                .child(request_reset_form())
+                .render(OptionAuthCookie::none()),
+        )
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct RequestResetData {
+    identifier: String,
+}
+
+#[derive(ThisError, Debug)]
+pub enum RequestResetError {
+    #[error("database error")]
+    DatabaseError,
+    #[error("could not send email")]
+    EmailError,
+    #[error("too many attempts")]
+    RateLimited(LimitError),
+}
+
+impl From<diesel::result::Error> for RequestResetError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl From<LimitError> for RequestResetError {
+    fn from(e: LimitError) -> Self {
+        match e {
+            LimitError::DatabaseError => Self::DatabaseError,
+            e => Self::RateLimited(e),
+        }
+    }
+}
+
+/// Emails a password reset link to the user with the provided username or email.
+///
+/// If no such user exists this still returns `Ok(())`, so that this cannot be used to find out
+/// whether somebody has an account. For the same reason, if too many resets have been requested
+/// for the account (rather than from the IP address) this silently doesn't send another email.
+async fn request_reset_base(
+    data: &RequestResetData,
+    metadata: &SessionMetadata,
+    conn: Database,
+) -> Result<(), RequestResetError> {
+    use crate::schema::{password_reset, users};
+    let identifier = data.identifier.clone();
+    let ip = metadata.ip.clone();
+    let nonce = nanoid!();
+    let closure_nonce = nonce.clone();
+    let user = conn
+        .run(move |c| {
+            limit::check(c, limit::Action::ResetPassword, None, ip.as_deref())?;
+            let user = users::table
+                .filter(users::username.eq(&identifier))
+                .or_filter(users::email.eq(&identifier))
+                .first::<User>(c)
+                .optional()?;
+            let user_id = user.as_ref().map(|user| user.id);
+            let limited = match user_id {
+                Some(user_id) => {
+                    match limit::check(c, limit::Action::ResetPassword, Some(user_id), None) {
+                        Ok(()) => false,
+                        Err(LimitError::DatabaseError) => {
+                            return Err(RequestResetError::DatabaseError)
+                        }
+                        Err(_) => true,
+                    }
+                }
+                None => false,
+            };
+            limit::record(c, limit::Action::ResetPassword, user_id, ip.as_deref())?;
+            let user = match user {
+                Some(user) if !limited => user,
+                _ => return Ok(None),
+            };
+            diesel::insert_into(password_reset::table)
+                .values(NewPasswordReset {
+                    user_id: user.id,
+                    nonce: &closure_nonce,
+                    created: Utc::now().naive_utc(),
+                })
+                .execute(c)?;
+            Ok(Some(user))
+        })
+        .await?;
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let reset_link = format!(
+        "/auth/reset/confirm?token={}",
+        jwt::encode(
+            &jwt::Header::default(),
+            &PasswordResetToken {
+                exp: (Utc::now() + chrono::Duration::hours(RESET_TOKEN_VALIDITY_HOURS)).timestamp()
+                    as usize,
+                user_id: user.id,
+                nonce,
+            },
+            &jwt::EncodingKey::from_base64_secret(
+                &std::env::var("SECRET_KEY")
+                    .unwrap_or_else(|_| "NNnXxqFeQ/1Sn8lh9MtlIW2uePR4TL/1O5dB2CPkTmg=".to_string())
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    );
+    SendgridMailSender::default()
+        .send(
+            &EmailBuilder::default()
+                .subject("Reset your password".to_string())
+                .plaintext(Some(format!(
+                    "Somebody (hopefully you) asked to reset your password. To choose a new \
+                    password, copy and paste this link into your browser: {} If you didn't ask \
+                    for this, you can safely ignore this email.",
+                    reset_link
+                )))
+                .html_text(Some(
+                    Html::new()
+                        .head(default_head("Reset your password".to_string()))
+                        .body(
+                            Body::new()
+                                .child(P::with_text(
+                                    "Somebody (hopefully you) asked to reset your password. If \
+                                    you didn't ask for this, you can safely ignore this email.",
+                                ))
+                                .child(
+                                    A::new()
+                                        .attribute(Href::new(reset_link))
+                                        .text("Choose a new password"),
+                                ),
+                        )
+                        .to_string(),
+                ))
+                .recipients(
+                    RecipientsBuilder::default()
+                        .recipients(vec![RecipientBuilder::default()
+                            .email(user.email.clone())
+                            .name(user.username.clone())
+                            .build()
+                            .unwrap()])
+                        .build()
+                        .unwrap(),
+                )
+                .from(("Lovelace".to_string(), "no-reply@lovelace.ga".to_string()))
+                .reply_to(("Lovelace".to_string(), "contact@lovelace.ga".to_string()))
+                .build()
+                .unwrap(),
+        )
+        .await
+        .map_err(|_| RequestResetError::EmailError)
+}
+
+#[post("/reset", data = "<data>")]
+pub async fn html_request_reset(
+    data: rocket::form::Form<RequestResetData>,
+    metadata: SessionMetadata,
+    conn: Database,
+) -> Html {
+    match request_reset_base(&data, &metadata, conn).await {
+        Ok(()) => Html::default()
+            .head(default_head("Check your email".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Check your email"))
+                    .child(P::with_text(
+                        "If an account with that username or email exists, we've sent it a link \
+                        which you can use to reset your password. The link expires in an hour.",
+                    )),
+            ),
+        Err(e) => Html::default()
+            .status(match &e {
+                RequestResetError::RateLimited(e) => e.status(),
+                _ => Status::InternalServerError,
+            })
+            .head(default_head("Error".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Could not send a reset link"))
+                    .child(P::with_text(match e {
+                        RequestResetError::DatabaseError => {
+                            "Something's up on our end. We're working to fix it as fast as we can!"
+                                .to_string()
+                        }
+                        RequestResetError::EmailError => {
+                            "We couldn't send you an email. Please try again in a little while."
+                                .to_string()
+                        }
+                        RequestResetError::RateLimited(e) => e.explanation(),
+                    }))
+                    .child(request_reset_form()),
+            ),
+    }
+}
+
+#[post("/reset", data = "<data>")]
+pub async fn api_request_reset(
+    data: Json<RequestResetData>,
+    metadata: SessionMetadata,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(match request_reset_base(&data, &metadata, conn).await {
+        Ok(()) => ApiResponse::new_ok(()),
+        Err(e) => ApiResponse::new_err(match e {
+            RequestResetError::DatabaseError => {
+                "Encountered a database error while undertaking this operation.".to_string()
+            }
+            RequestResetError::EmailError => "Could not send the password reset email.".to_string(),
+            RequestResetError::RateLimited(e) => e.explanation(),
+        }),
+    })
+}
+
+#[get("/reset/confirm?<token>")]
+pub fn confirm_reset_page(token: &str) -> Html {
+    Html::default()
+        .head(default_head("Choose a new password".to_string()))
+        .body(
+            Page::default()
+                .child(H1::new("Choose a new password"))
+                .child(apply_reset_form(token))
+                .render(OptionAuthCookie::none()),
+        )
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyResetData {
+    token: String,
+    password: String,
+    password_confirmation: String,
+}
+
+#[derive(ThisError, Debug)]
+pub enum ApplyResetError {
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("passwords do not match")]
+    NonMatchingPasswords,
+    #[error("encrypting password error")]
+    EncryptingPasswordError,
+    #[error("database error")]
+    DatabaseError,
+}
+
+/// Sets the user's password, provided that the token is valid and has not already been used.
+async fn apply_reset_base(data: &ApplyResetData, conn: Database) -> Result<(), ApplyResetError> {
+    use crate::schema::{password_reset, users};
+    let token = jwt::decode::<PasswordResetToken>(
+        &data.token,
+        &jwt::DecodingKey::from_base64_secret(
+            &std::env::var("SECRET_KEY")
+                .unwrap_or_else(|_| "NNnXxqFeQ/1Sn8lh9MtlIW2uePR4TL/1O5dB2CPkTmg=".to_string()),
+        )
+        .unwrap(),
+        &jwt::Validation::default(),
+    )
+    .map_err(|_| ApplyResetError::InvalidToken)?
+    .claims;
+    validate_new_password(&data.password, &data.password_confirmation).map_err(|e| match e {
+        PasswordError::NonMatchingPasswords => ApplyResetError::NonMatchingPasswords,
+    })?;
+    let hashed_password = hash_password(&data.password).map_err(|e| {
+        error!("{:#?}", e);
+        ApplyResetError::EncryptingPasswordError
+    })?;
+    let token_was_valid = conn
+        .run(move |c| {
+            c.transaction::<_, diesel::result::Error, _>(|| {
+                let used = diesel::delete(
+                    password_reset::table
+                        .filter(password_reset::user_id.eq(token.user_id))
+                        .filter(password_reset::nonce.eq(&token.nonce)),
+                )
+                .execute(c)?;
+                if used == 0 {
+                    return Ok(false);
+                }
+                diesel::delete(
+                    password_reset::table.filter(password_reset::user_id.eq(token.user_id)),
+                )
+                .execute(c)?;
+                diesel::update(users::table.filter(users::id.eq(token.user_id)))
+                    .set(users::password.eq(hashed_password))
+                    .execute(c)?;
+                end_all_sessions(token.user_id, c)?;
+                limit::unlock(c, token.user_id)?;
+                Ok(true)
+            })
+        })
+        .await
+        .map_err(|e| {
+            error!("{:#?}", e);
+            ApplyResetError::DatabaseError
+        })?;
+    if token_was_valid {
+        Ok(())
+    } else {
+        Err(ApplyResetError::InvalidToken)
+    }
+}
+
+#[post("/reset/confirm", data = "<data>")]
+pub async fn html_apply_reset(data: rocket::form::Form<ApplyResetData>, conn: Database) -> Html {
+    match apply_reset_base(&data, conn).await {
+        Ok(()) => Html::default()
+            .head(default_head("Password reset".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Your password has been reset"))
+                    .child(
+                        A::default()
+                            .attribute(Href::new("/auth/login"))
+                            .text("Log in with your new password."),
+                    ),
+            ),
+        Err(ApplyResetError::InvalidToken) => Html::default()
+            .status(Status::BadRequest)
+            .head(default_head("Invalid link".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Invalid link"))
+                    .child(P::with_text(
+                        "This password reset link is invalid or has expired. Links can only be \
+                        used once; you can request a new one below.",
+                    ))
+                    .child(request_reset_form().attribute(Action::new("/auth/reset"))),
+            ),
+        Err(ApplyResetError::NonMatchingPasswords) => Html::default()
+            .status(Status::BadRequest)
+            .head(default_head("Passwords don't match".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Passwords don't match"))
+                    .child(apply_reset_form(&data.token)),
+            ),
+        Err(ApplyResetError::EncryptingPasswordError) => Html::default()
+            .status(Status::InternalServerError)
+            .head(default_head("Encryption error".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Encryption error"))
+                    .child(P::with_text(
+                        "We're having problems encrypting your password.",
+                    ))
+                    .child(apply_reset_form(&data.token)),
+            ),
+        Err(ApplyResetError::DatabaseError) => Html::default()
+            .status(Status::InternalServerError)
+            .head(default_head("Database error".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Database error"))
+                    .child(P::with_text(
+                        "Something's up on our end. We're working to fix it as fast as we can!",
+                    ))
+                    .child(apply_reset_form(&data.token)),
+            ),
+    }
+}
+
+#[post("/reset/confirm", data = "<data>")]
+pub async fn api_apply_reset(data: Json<ApplyResetData>, conn: Database) -> Json<ApiResponse<()>> {
+    Json(match apply_reset_base(&data, conn).await {
+        Ok(()) => ApiResponse::new_ok(()),
+        Err(e) => ApiResponse::new_err(match e {
+            ApplyResetError::InvalidToken => "The reset token is invalid or has expired.",
+            ApplyResetError::NonMatchingPasswords => "The passwords supplied do not match",
+            ApplyResetError::EncryptingPasswordError => "Could not encrypt the provided password.",
+            ApplyResetError::DatabaseError => {
+                "Encountered a database error while undertaking this operation."
+            }
+        }),
+    })
+}
+
+#[cfg(test)]
+mod test_password_reset {
+    use chrono::Utc;
+    use diesel::prelude::*;
+    use rocket::{http::ContentType, local::asynchronous::Client};
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        db::Database,
+        schema::{password_reset, users},
+        utils::{client, create_user, login_user},
+    };
+
+    use super::PasswordResetToken;
+
+    const USERNAME: &str = "forgetful-user";
+    const EMAIL: &str = "forgetful@example.com";
+    const PASSWORD: &str = "0riginalPassw0rd";
+    const NEW_PASSWORD: &str = "n3wPassw0rdWhichIsRemembered";
+    const TIMEZONE: &str = "Africa/Abidjan";
+
+    async fn request_token(client: &Client) -> String {
+        let res = client
+            .post("/auth/reset")
+            .header(ContentType::Form)
+            .body(format!("identifier={}", EMAIL))
+            .dispatch()
+            .await;
+        assert!(res
+            .into_string()
+            .await
+            .expect("invalid body response")
+            .contains("Check your email"));
+        let (user_id, nonce) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                password_reset::table
+                    .inner_join(users::table)
+                    .filter(users::username.eq(USERNAME))
+                    .order(password_reset::id.desc())
+                    .select((users::id, password_reset::nonce))
+                    .first::<(i32, String)>(c)
+                    .unwrap()
+            })
+            .await;
+        jwt::encode(
+            &jwt::Header::default(),
+            &PasswordResetToken {
+                exp: (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+                user_id,
+                nonce,
+            },
+            &jwt::EncodingKey::from_base64_secret(
+                &std::env::var("SECRET_KEY")
+                    .unwrap_or_else(|_| "NNnXxqFeQ/1Sn8lh9MtlIW2uePR4TL/1O5dB2CPkTmg=".to_string()),
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    async fn apply_token(token: &str, client: &Client) -> String {
+        client
+            .post("/auth/reset/confirm")
+            .header(ContentType::Form)
+            .body(format!(
+                "token={}&password={password}&password_confirmation={password}",
+                token,
+                password = NEW_PASSWORD
+            ))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .expect("invalid body response")
+    }
+
+    #[rocket::async_test]
+    async fn test_can_reset_password() {
+        let mock_server = MockServer::start().await;
+        std::env::set_var("SENDGRID_API_KEY", "SomeRandomAPIKey");
+        std::env::set_var("SENDGRID_API_SERVER", mock_server.uri());
+        Mock::given(method("post"))
+            .and(path_regex("/v3/mail/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2..)
+            .mount(&mock_server)
+            .await;
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+
+        let older_token = request_token(&client).await;
+        let token = request_token(&client).await;
+
+        let page = client
+            .get(format!("/auth/reset/confirm?token={}", token))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .expect("invalid body response");
+        assert!(page.contains("Choose a new password"));
+
+        assert!(apply_token(&token, &client)
+            .await
+            .contains("has been reset"));
+        login_user(USERNAME, NEW_PASSWORD, &client).await;
+
+        // tokens are single use
+        assert!(apply_token(&token, &client)
+            .await
+            .contains("invalid or has expired"));
+        // and using a token invalidates the older ones
+        assert!(apply_token(&older_token, &client)
+            .await
+            .contains("invalid or has expired"));
+    }
+
+    #[rocket::async_test]
+    async fn test_requests_are_rate_limited() {
+        let mock_server = MockServer::start().await;
+        std::env::set_var("SENDGRID_API_KEY", "SomeRandomAPIKey");
+        std::env::set_var("SENDGRID_API_SERVER", mock_server.uri());
+        Mock::given(method("post"))
+            .and(path_regex("/v3/mail/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        for _ in 0..3 {
+            request_token(&client).await;
+        }
+        // further requests look the same, but no more emails are sent
+        request_token(&client).await;
+        let resets = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                password_reset::table
+                    .inner_join(users::table)
+                    .filter(users::username.eq(USERNAME))
+                    .count()
+                    .get_result::<i64>(c)
+                    .unwrap()
+            })
+            .await;
+        assert_eq!(resets, 3);
+    }
+
+    #[rocket::async_test]
+    async fn test_unknown_user_is_not_disclosed() {
+        let client = client().await;
+        let res = client
+            .post("/api/auth/reset")
+            .header(ContentType::JSON)
+            .body(r#"{"identifier": "nobody@example.com"}"#)
+            .dispatch()
+            .await;
+        assert!(res
+            .into_string()
+            .await
+            .expect("invalid body response")
+            .contains("\"success\":true"));
+    }
+
+    #[rocket::async_test]
+    async fn test_rejects_forged_token() {
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        assert!(apply_token("not-a-real-token", &client)
+            .await
+            .contains("invalid or has expired"));
+        login_user(USERNAME, PASSWORD, &client).await;
+    }
 }
@@ -0,0 +1,497 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Server-side sessions.
+//!
+//! Every time somebody logs in we create a row in the `session` table and store its (random)
+//! `token` in the login cookie. A request only counts as authenticated while that row exists, so
+//! deleting the row logs the user out of that device – whether they asked for it (by logging out
+//! or revoking the session) or because the session expired.
+//!
+//! Sessions are resolved once per request by a fairing (see [`super::resolve_credentials`]),
+//! rather than in the request guards themselves. This is so that the guards never need to take a
+//! database connection of their own (which, with a single-connection pool, would never become
+//! available if a handler had already asked for one).
+
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle};
+use rocket::{
+    http::{Cookie, CookieJar},
+    outcome::IntoOutcome,
+    request::FromRequest,
+    serde::json::Json,
+    Request,
+};
+
+use crate::{
+    db::{Database, DatabaseConnection},
+    models::auth::session::{NewSession, Session},
+    utils::{default_head, json_response::ApiResponse},
+};
+
+use super::{AuthCookie, AuthError, LOGIN_COOKIE};
+
+/// Sessions which have not been used for this many days are expired.
+const SESSION_IDLE_TIMEOUT_DAYS: i64 = 14;
+/// Sessions are expired this many days after being created, even if they are still in use.
+const SESSION_MAX_AGE_DAYS: i64 = 90;
+
+/// The session which the current request was made with (if there is one).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResolvedSession(pub Option<Session>);
+
+impl ResolvedSession {
+    /// Retrieves the session which [`super::resolve_credentials`] found for this request.
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r Self {
+        request.local_cache(ResolvedSession::default)
+    }
+}
+
+/// Looks up the session which `token` (from the login cookie) refers to. Expired or revoked
+/// sessions aren't returned (so the user is treated as if they're logged out).
+///
+/// This also updates the session's `last_seen` field.
+pub(crate) fn find_session(token: &str, c: &DatabaseConnection) -> QueryResult<Option<Session>> {
+    use crate::schema::session;
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        session::table
+            .filter(session::token.eq(token))
+            .filter(session::last_seen.gt(now - Duration::days(SESSION_IDLE_TIMEOUT_DAYS)))
+            .filter(session::created.gt(now - Duration::days(SESSION_MAX_AGE_DAYS))),
+    )
+    .set(session::last_seen.eq(now))
+    .get_result::<Session>(c)
+    .optional()
+}
+
+/// Details about the device somebody is logging in from, which we show to the user on the
+/// sessions page so that they can tell their sessions apart.
+#[derive(Debug, Clone)]
+pub struct SessionMetadata {
+    user_agent: Option<String>,
+    pub(crate) ip: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionMetadata {
+    type Error = AuthError;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(SessionMetadata {
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .map(ToString::to_string),
+            ip: request.client_ip().map(|ip| ip.to_string()),
+        })
+    }
+}
+
+/// The session which the current request was made with.
+///
+/// This is a more specific version of `AuthCookie`, for use in the (few) places which need to know
+/// about the session itself, rather than just about the user.
+#[derive(Debug, Clone)]
+pub struct CurrentSession(pub Session);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CurrentSession {
+    type Error = AuthError;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        ResolvedSession::of(request)
+            .0
+            .clone()
+            .map(CurrentSession)
+            .or_forward(())
+    }
+}
+
+/// Creates a new session for the user, and sets the login cookie so that it refers to that
+/// session.
+///
+/// Any of the user's sessions which have expired are cleaned up at the same time.
+pub async fn start_session(
+    user_id: i32,
+    metadata: &SessionMetadata,
+    cookies: &CookieJar<'_>,
+    conn: &Database,
+) -> Result<(), diesel::result::Error> {
+    use crate::schema::session;
+    let token = nanoid!();
+    let closure_token = token.clone();
+    let metadata = metadata.clone();
+    conn.run(move |c| {
+        let now = Utc::now().naive_utc();
+        diesel::delete(
+            session::table.filter(session::user_id.eq(user_id)).filter(
+                session::last_seen
+                    .le(now - Duration::days(SESSION_IDLE_TIMEOUT_DAYS))
+                    .or(session::created.le(now - Duration::days(SESSION_MAX_AGE_DAYS))),
+            ),
+        )
+        .execute(c)?;
+        diesel::insert_into(session::table)
+            .values(NewSession {
+                token: &closure_token,
+                user_id,
+                created: now,
+                last_seen: now,
+                user_agent: metadata.user_agent.as_deref(),
+                ip: metadata.ip.as_deref(),
+            })
+            .execute(c)
+    })
+    .await?;
+    cookies.add_private(Cookie::new(LOGIN_COOKIE, token));
+    Ok(())
+}
+
+/// Deletes the session which the login cookie refers to, and removes the cookie.
+///
+/// Returns `false` if the user was not logged in.
+pub async fn end_session(
+    cookies: &CookieJar<'_>,
+    conn: &Database,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::session;
+    let token = match cookies.get_private(LOGIN_COOKIE) {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Ok(false),
+    };
+    conn.run(move |c| diesel::delete(session::table.filter(session::token.eq(token))).execute(c))
+        .await?;
+    cookies.remove_private(Cookie::named(LOGIN_COOKIE));
+    Ok(true)
+}
+
+/// Deletes all of a user's sessions (logging them out everywhere).
+pub fn end_all_sessions(
+    user_id: i32,
+    conn: &DatabaseConnection,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::session;
+    diesel::delete(session::table.filter(session::user_id.eq(user_id))).execute(conn)
+}
+
+async fn list_sessions(
+    user_id: i32,
+    conn: &Database,
+) -> Result<Vec<Session>, diesel::result::Error> {
+    use crate::schema::session;
+    conn.run(move |c| {
+        session::table
+            .filter(session::user_id.eq(user_id))
+            .order_by(session::last_seen.desc())
+            .load::<Session>(c)
+    })
+    .await
+}
+
+fn revoke_session_form(id: i32) -> malvolio::prelude::Form {
+    malvolio::prelude::Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new(format!("/auth/sessions/{}/revoke", id)))
+        .child(
+            Input::default()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Log this device out")),
+        )
+}
+
+fn render_sessions(current: &Session, sessions: Vec<Session>, message: Option<&str>) -> Html {
+    Html::default()
+        .head(default_head("Your active sessions".to_string()))
+        .body(
+            Body::default()
+                .child(H1::new("Your active sessions"))
+                .map(|body| match message {
+                    Some(message) => body.child(P::with_text(message)),
+                    None => body,
+                })
+                .child(P::with_text(
+                    "These are the devices which are currently logged in to your account. If you \
+                    don't recognise one of them, log it out and change your password.",
+                ))
+                .children(sessions.into_iter().map(|session| {
+                    Div::new()
+                        .child(H3::new(
+                            session
+                                .user_agent
+                                .clone()
+                                .unwrap_or_else(|| "Unknown device".to_string()),
+                        ))
+                        .map(|div| {
+                            if session.id == current.id {
+                                div.child(P::with_text("This is the device you are using now."))
+                            } else {
+                                div
+                            }
+                        })
+                        .child(P::with_text(format!(
+                            "IP address: {}",
+                            session.ip.clone().unwrap_or_else(|| "unknown".to_string())
+                        )))
+                        .child(P::with_text(format!(
+                            "Logged in at {} (UTC), last active at {} (UTC).",
+                            session.created.format("%Y-%m-%d %H:%M"),
+                            session.last_seen.format("%Y-%m-%d %H:%M")
+                        )))
+                        .child(revoke_session_form(session.id))
+                })),
+        )
+}
+
+#[get("/sessions")]
+pub async fn sessions_page(current: CurrentSession, conn: Database) -> Html {
+    match list_sessions(current.0.user_id, &conn).await {
+        Ok(sessions) => render_sessions(&current.0, sessions, None),
+        Err(e) => {
+            error!("{:#?}", e);
+            crate::utils::error_message(
+                "Database error".to_string(),
+                "We couldn't retrieve your sessions from the database.".to_string(),
+            )
+        }
+    }
+}
+
+#[get("/sessions")]
+pub async fn api_list_sessions(
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<Vec<Session>>> {
+    Json(match list_sessions(auth.0, &conn).await {
+        Ok(sessions) => ApiResponse::new_ok(sessions),
+        Err(e) => {
+            error!("{:#?}", e);
+            ApiResponse::new_err("Encountered a database error trying to fulfil that request.")
+        }
+    })
+}
+
+/// Deletes the session with the given id, provided that it belongs to the user.
+///
+/// Returns `false` if no such session exists.
+async fn revoke_session(
+    id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::session;
+    conn.run(move |c| {
+        diesel::delete(
+            session::table
+                .filter(session::id.eq(id))
+                .filter(session::user_id.eq(user_id)),
+        )
+        .execute(c)
+    })
+    .await
+    .map(|deleted| deleted > 0)
+}
+
+#[post("/sessions/<id>/revoke")]
+pub async fn html_revoke_session(
+    id: i32,
+    current: CurrentSession,
+    cookies: &CookieJar<'_>,
+    conn: Database,
+) -> Html {
+    match revoke_session(id, current.0.user_id, &conn).await {
+        Ok(true) if id == current.0.id => {
+            cookies.remove_private(Cookie::named(LOGIN_COOKIE));
+            Html::default()
+                .head(default_head("Logged out.".to_string()))
+                .body(Body::default().child(H1::new("You are logged out.".to_string())))
+        }
+        Ok(revoked) => match list_sessions(current.0.user_id, &conn).await {
+            Ok(sessions) => render_sessions(
+                &current.0,
+                sessions,
+                Some(if revoked {
+                    "That device has been logged out."
+                } else {
+                    "That session doesn't exist (it may have already been logged out)."
+                }),
+            ),
+            Err(e) => {
+                error!("{:#?}", e);
+                crate::utils::error_message(
+                    "Database error".to_string(),
+                    "We couldn't retrieve your sessions from the database.".to_string(),
+                )
+            }
+        },
+        Err(e) => {
+            error!("{:#?}", e);
+            crate::utils::error_message(
+                "Database error".to_string(),
+                "We couldn't log that device out, because of a database error.".to_string(),
+            )
+        }
+    }
+}
+
+#[post("/sessions/<id>/revoke")]
+pub async fn api_revoke_session(
+    id: i32,
+    current: CurrentSession,
+    cookies: &CookieJar<'_>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(match revoke_session(id, current.0.user_id, &conn).await {
+        Ok(true) => {
+            if id == current.0.id {
+                cookies.remove_private(Cookie::named(LOGIN_COOKIE));
+            }
+            ApiResponse::new_ok(())
+        }
+        Ok(false) => ApiResponse::new_err("No session with that id exists."),
+        Err(e) => {
+            error!("{:#?}", e);
+            ApiResponse::new_err("Encountered a database error trying to fulfil that request.")
+        }
+    })
+}
+
+#[cfg(test)]
+mod test_sessions {
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+    use rocket::http::Status;
+
+    use crate::{
+        db::Database,
+        models::auth::session::{NewSession, Session},
+        schema::{session, users},
+        utils::{client, create_user, login_user},
+    };
+
+    const USERNAME: &str = "user-with-many-devices";
+    const EMAIL: &str = "devices@example.com";
+    const PASSWORD: &str = "s3cUREpassw0rdForTesting";
+    const TIMEZONE: &str = "Africa/Abidjan";
+
+    async fn sessions(client: &rocket::local::asynchronous::Client) -> Vec<Session> {
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                session::table
+                    .inner_join(users::table)
+                    .filter(users::username.eq(USERNAME))
+                    .select(session::all_columns)
+                    .order_by(session::id.asc())
+                    .load::<Session>(c)
+                    .unwrap()
+            })
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn test_can_revoke_sessions() {
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        login_user(USERNAME, PASSWORD, &client).await;
+
+        let own_session = sessions(&client).await.remove(0);
+        let user_id = own_session.user_id;
+        // pretend that the user has also logged in on another device
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::insert_into(session::table)
+                    .values(NewSession {
+                        token: "some-other-device",
+                        user_id,
+                        created: Utc::now().naive_utc(),
+                        last_seen: Utc::now().naive_utc(),
+                        user_agent: Some("Other Browser/1.0"),
+                        ip: Some("192.0.2.1"),
+                    })
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        let other_session = sessions(&client).await.remove(1);
+
+        let page = client
+            .get("/auth/sessions")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(page.contains("Other Browser/1.0"));
+        assert!(page.contains("This is the device you are using now."));
+
+        let res = client
+            .post(format!("/auth/sessions/{}/revoke", other_session.id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(res.contains("That device has been logged out."));
+        assert!(!res.contains("Other Browser/1.0"));
+        assert_eq!(sessions(&client).await.len(), 1);
+
+        let res = client
+            .post(format!("/auth/sessions/{}/revoke", own_session.id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(res.contains("You are logged out."));
+        assert!(sessions(&client).await.is_empty());
+        assert_eq!(
+            client.get("/auth/sessions").dispatch().await.status(),
+            Status::NotFound
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_logout_and_expiry_end_sessions() {
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        assert_eq!(sessions(&client).await.len(), 1);
+        crate::utils::logout(&client).await;
+        assert!(sessions(&client).await.is_empty());
+
+        login_user(USERNAME, PASSWORD, &client).await;
+        assert_eq!(
+            client.get("/auth/sessions").dispatch().await.status(),
+            Status::Ok
+        );
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                diesel::update(session::table)
+                    .set(session::last_seen.eq(Utc::now().naive_utc() - Duration::days(30)))
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        assert_eq!(
+            client.get("/auth/sessions").dispatch().await.status(),
+            Status::NotFound
+        );
+    }
+}
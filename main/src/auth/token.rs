@@ -0,0 +1,709 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Personal access tokens for the API.
+//!
+//! Users can create named tokens which are then sent as `Authorization: Bearer <token>` by scripts
+//! and other clients which cannot log in through the browser. Each token is limited to a set of
+//! scopes (and can optionally be made read-only), which are checked by the `ApiAuth` guard.
+//!
+//! Only a SHA-256 hash of each token is stored. Unlike passwords, tokens are long random strings,
+//! so a slow hash is not needed (and would mean that we could not look tokens up by their hash).
+
+use std::marker::PhantomData;
+
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
+use rocket::{http::Status, request::FromRequest, serde::json::Json, Request};
+use sha2::{Digest, Sha256};
+use thiserror::Error as ThisError;
+
+use crate::{
+    db::{Database, DatabaseConnection},
+    models::auth::token::{ApiToken, NewApiToken},
+    utils::{default_head, error_message, json_response::ApiResponse},
+};
+
+use super::{session::ResolvedSession, AuthCookie, AuthError};
+
+/// All tokens start with this prefix, which makes them easier to recognise (e.g. if one is
+/// accidentally committed to a repository).
+const TOKEN_PREFIX: &str = "lovelace_";
+
+/// The longest a token can be made to last for (tokens which shouldn't expire are created without
+/// an expiry date instead).
+const MAX_EXPIRY_DAYS: i64 = 3650;
+
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Returns the token provided in the `Authorization` header (if any).
+pub(crate) fn bearer_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// The API token which the current request was made with (if there is one).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResolvedApiToken(pub Option<ApiToken>);
+
+impl ResolvedApiToken {
+    /// Retrieves the token which [`super::resolve_credentials`] found for this request.
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r Self {
+        request.local_cache(ResolvedApiToken::default)
+    }
+}
+
+/// Looks up the (unexpired) token with the hash `token_hash`, updating when it was last used.
+pub(crate) fn find_api_token(
+    token_hash: &str,
+    c: &DatabaseConnection,
+) -> QueryResult<Option<ApiToken>> {
+    use crate::schema::api_token;
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        api_token::table
+            .filter(api_token::token_hash.eq(token_hash))
+            .filter(api_token::expires.is_null().or(api_token::expires.gt(now))),
+    )
+    .set(api_token::last_used.eq(now))
+    .get_result::<ApiToken>(c)
+    .optional()
+}
+
+/// The different things which a token can be allowed to do.
+///
+/// Each scope is a type, so that handlers can state which scope they require in their signature
+/// (e.g. `ApiAuth<ManageClasses>`).
+pub mod scope {
+    use crate::models::auth::token::ApiToken;
+
+    pub trait TokenScope: Send + Sync + 'static {
+        /// Whether the provided token has been granted this scope.
+        fn permits(token: &ApiToken) -> bool;
+    }
+
+    /// View classes (and their members and messages).
+    #[derive(Debug, Copy, Clone)]
+    pub struct ReadClasses;
+    /// Create, join, edit and delete classes (and their messages).
+    #[derive(Debug, Copy, Clone)]
+    pub struct ManageClasses;
+    /// View tasks.
+    #[derive(Debug, Copy, Clone)]
+    pub struct ReadTasks;
+    /// Create, edit and delete tasks.
+    #[derive(Debug, Copy, Clone)]
+    pub struct ManageTasks;
+    /// View notifications.
+    #[derive(Debug, Copy, Clone)]
+    pub struct ReadNotifications;
+    /// Mark notifications as read and delete them.
+    #[derive(Debug, Copy, Clone)]
+    pub struct ManageNotifications;
+
+    impl TokenScope for ReadClasses {
+        fn permits(token: &ApiToken) -> bool {
+            token.scope_classes
+        }
+    }
+
+    impl TokenScope for ManageClasses {
+        fn permits(token: &ApiToken) -> bool {
+            token.scope_classes && !token.read_only
+        }
+    }
+
+    impl TokenScope for ReadTasks {
+        fn permits(token: &ApiToken) -> bool {
+            token.scope_tasks
+        }
+    }
+
+    impl TokenScope for ManageTasks {
+        fn permits(token: &ApiToken) -> bool {
+            token.scope_tasks && !token.read_only
+        }
+    }
+
+    impl TokenScope for ReadNotifications {
+        fn permits(token: &ApiToken) -> bool {
+            token.scope_notifications
+        }
+    }
+
+    impl TokenScope for ManageNotifications {
+        fn permits(token: &ApiToken) -> bool {
+            token.scope_notifications && !token.read_only
+        }
+    }
+}
+
+/// Authenticates a request to the API, either through the login cookie (in which case every scope
+/// is permitted) or through an `Authorization: Bearer` token which has been granted the scope `S`.
+///
+/// This can be used in the same way as `AuthCookie` (and can be converted into one).
+#[derive(Debug, Copy, Clone)]
+pub struct ApiAuth<S>(pub i32, PhantomData<S>);
+
+impl<S> From<ApiAuth<S>> for AuthCookie {
+    fn from(auth: ApiAuth<S>) -> Self {
+        AuthCookie(auth.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, S> FromRequest<'r> for ApiAuth<S>
+where
+    S: scope::TokenScope,
+{
+    type Error = AuthError;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        if let Some(session) = &ResolvedSession::of(request).0 {
+            return rocket::request::Outcome::Success(ApiAuth(session.user_id, PhantomData));
+        }
+        if bearer_token(request).is_none() {
+            return rocket::request::Outcome::Forward(());
+        }
+        match &ResolvedApiToken::of(request).0 {
+            Some(token) if S::permits(token) => {
+                rocket::request::Outcome::Success(ApiAuth(token.user_id, PhantomData))
+            }
+            Some(_) => {
+                rocket::request::Outcome::Failure((Status::Forbidden, AuthError::InsufficientScope))
+            }
+            None => {
+                rocket::request::Outcome::Failure((Status::Unauthorized, AuthError::InvalidToken))
+            }
+        }
+    }
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct CreateTokenForm {
+    name: String,
+    /// The number of days after which the token should stop working. If this is not provided, the
+    /// token will work until it is revoked.
+    expires_in_days: Option<i64>,
+    #[serde(default)]
+    read_only: bool,
+    #[serde(default)]
+    scope_classes: bool,
+    #[serde(default)]
+    scope_tasks: bool,
+    #[serde(default)]
+    scope_notifications: bool,
+}
+
+/// A newly created token. This is the only time the token itself is ever shown to the user.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiToken {
+    token: String,
+    details: ApiToken,
+}
+
+async fn list_tokens(
+    user_id: i32,
+    conn: &Database,
+) -> Result<Vec<ApiToken>, diesel::result::Error> {
+    use crate::schema::api_token;
+    conn.run(move |c| {
+        api_token::table
+            .filter(api_token::user_id.eq(user_id))
+            .order_by(api_token::created.desc())
+            .load::<ApiToken>(c)
+    })
+    .await
+}
+
+#[derive(ThisError, Debug)]
+pub enum CreateTokenError {
+    #[error("invalid expiry")]
+    InvalidExpiry,
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for CreateTokenError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl CreateTokenError {
+    fn explanation(&self) -> String {
+        match self {
+            CreateTokenError::InvalidExpiry => format!(
+                "Tokens can expire after between 1 and {} days (leave the number of days blank \
+                for a token which never expires).",
+                MAX_EXPIRY_DAYS
+            ),
+            CreateTokenError::DatabaseError => {
+                "We couldn't create that token because of a database error.".to_string()
+            }
+        }
+    }
+}
+
+async fn create_token(
+    form: &CreateTokenForm,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<CreatedApiToken, CreateTokenError> {
+    use crate::schema::api_token;
+    if let Some(days) = form.expires_in_days {
+        if !(1..=MAX_EXPIRY_DAYS).contains(&days) {
+            return Err(CreateTokenError::InvalidExpiry);
+        }
+    }
+    let token = format!("{}{}", TOKEN_PREFIX, nanoid!(40));
+    let token_hash = hash_token(&token);
+    let form = form.clone();
+    let now = Utc::now().naive_utc();
+    let details = conn
+        .run(move |c| {
+            diesel::insert_into(api_token::table)
+                .values(NewApiToken {
+                    user_id: auth.0,
+                    name: &form.name,
+                    token_hash: &token_hash,
+                    created: now,
+                    expires: form.expires_in_days.map(|days| now + Duration::days(days)),
+                    read_only: form.read_only,
+                    scope_classes: form.scope_classes,
+                    scope_tasks: form.scope_tasks,
+                    scope_notifications: form.scope_notifications,
+                })
+                .returning(api_token::all_columns)
+                .get_result::<ApiToken>(c)
+        })
+        .await?;
+    Ok(CreatedApiToken { token, details })
+}
+
+/// Deletes the token with the given id, provided that it belongs to the user.
+///
+/// Returns `false` if no such token exists.
+async fn revoke_token(
+    id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::api_token;
+    conn.run(move |c| {
+        diesel::delete(
+            api_token::table
+                .filter(api_token::id.eq(id))
+                .filter(api_token::user_id.eq(auth.0)),
+        )
+        .execute(c)
+    })
+    .await
+    .map(|deleted| deleted > 0)
+}
+
+fn checkbox(name: &'static str, label: &'static str) -> Div {
+    Div::new().child(Label::new(label)).child(
+        Input::default()
+            .attribute(Type::Checkbox)
+            .attribute(Name::new(name)),
+    )
+}
+
+fn create_token_form() -> malvolio::prelude::Form {
+    malvolio::prelude::Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/tokens"))
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Placeholder::new("What is this token for?"))
+                .attribute(Name::new("name")),
+        )
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Placeholder::new(
+                    "Number of days until the token expires (leave blank for never)",
+                ))
+                .attribute(Name::new("expires_in_days")),
+        )
+        .child(checkbox("scope_classes", "Classes"))
+        .child(checkbox("scope_tasks", "Tasks"))
+        .child(checkbox("scope_notifications", "Notifications"))
+        .child(checkbox("read_only", "Read-only"))
+        .child(
+            Input::default()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Create token")),
+        )
+}
+
+fn render_tokens<B>(tokens: Vec<ApiToken>, message: Option<B>) -> Html
+where
+    B: Into<BodyNode>,
+{
+    Html::default()
+        .head(default_head("API tokens".to_string()))
+        .body(
+            Body::default()
+                .child(H1::new("API tokens"))
+                .map(|body| match message {
+                    Some(message) => body.child(message),
+                    None => body,
+                })
+                .child(P::with_text(
+                    "Tokens let scripts and other applications use the Lovelace API on your \
+                    behalf. Send them in the \"Authorization: Bearer\" header.",
+                ))
+                .children(tokens.into_iter().map(|token| {
+                    let scopes = [
+                        (token.scope_classes, "classes"),
+                        (token.scope_tasks, "tasks"),
+                        (token.scope_notifications, "notifications"),
+                    ]
+                    .iter()
+                    .filter(|(granted, _)| *granted)
+                    .map(|(_, scope)| *scope)
+                    .collect::<Vec<_>>();
+                    Div::new()
+                        .child(H3::new(token.name))
+                        .child(P::with_text(format!(
+                            "Scopes: {}{}",
+                            if scopes.is_empty() {
+                                "none".to_string()
+                            } else {
+                                scopes.join(", ")
+                            },
+                            if token.read_only { " (read-only)" } else { "" }
+                        )))
+                        .child(P::with_text(format!(
+                            "Created at {} (UTC). {}",
+                            token.created.format("%Y-%m-%d %H:%M"),
+                            match token.expires {
+                                Some(expires) => format!(
+                                    "Expires at {} (UTC).",
+                                    expires.format("%Y-%m-%d %H:%M")
+                                ),
+                                None => "Never expires.".to_string(),
+                            }
+                        )))
+                        .child(
+                            malvolio::prelude::Form::new()
+                                .apply(FormStyle)
+                                .attribute(Method::Post)
+                                .attribute(Action::new(format!("/auth/tokens/{}/revoke", token.id)))
+                                .child(
+                                    Input::default()
+                                        .apply(FormSubmitInputStyle)
+                                        .attribute(Type::Submit)
+                                        .attribute(Value::new("Revoke this token")),
+                                ),
+                        )
+                }))
+                .child(H3::new("Create a new token"))
+                .child(create_token_form()),
+        )
+}
+
+async fn tokens_page_with_message<B>(auth: AuthCookie, conn: &Database, message: Option<B>) -> Html
+where
+    B: Into<BodyNode>,
+{
+    match list_tokens(auth.0, conn).await {
+        Ok(tokens) => render_tokens(tokens, message),
+        Err(e) => {
+            error!("{:#?}", e);
+            error_message(
+                "Database error".to_string(),
+                "We couldn't retrieve your API tokens from the database.".to_string(),
+            )
+        }
+    }
+}
+
+#[get("/tokens")]
+pub async fn tokens_page(auth: AuthCookie, conn: Database) -> Html {
+    tokens_page_with_message::<P>(auth, &conn, None).await
+}
+
+#[post("/tokens", data = "<form>")]
+pub async fn html_create_token(
+    form: rocket::form::Form<CreateTokenForm>,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    match create_token(&form, auth, &conn).await {
+        Ok(created) => tokens_page_with_message(
+            auth,
+            &conn,
+            Some(
+                Div::new()
+                    .child(P::with_text(
+                        "Your new token is below. Make sure to copy it now – you won't be able \
+                            to see it again!",
+                    ))
+                    .child(P::with_text(created.token)),
+            ),
+        )
+        .await,
+        Err(e) => tokens_page_with_message(auth, &conn, Some(P::with_text(e.explanation()))).await,
+    }
+}
+
+#[post("/tokens/<id>/revoke")]
+pub async fn html_revoke_token(id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let message = match revoke_token(id, auth, &conn).await {
+        Ok(true) => "That token has been revoked.",
+        Ok(false) => "That token doesn't exist (it may have already been revoked).",
+        Err(e) => {
+            error!("{:#?}", e);
+            "We couldn't revoke that token because of a database error."
+        }
+    };
+    tokens_page_with_message(auth, &conn, Some(P::with_text(message))).await
+}
+
+#[get("/tokens")]
+pub async fn api_list_tokens(auth: AuthCookie, conn: Database) -> Json<ApiResponse<Vec<ApiToken>>> {
+    Json(match list_tokens(auth.0, &conn).await {
+        Ok(tokens) => ApiResponse::new_ok(tokens),
+        Err(e) => {
+            error!("{:#?}", e);
+            ApiResponse::new_err("Encountered a database error trying to fulfil that request.")
+        }
+    })
+}
+
+#[post("/tokens", data = "<form>")]
+pub async fn api_create_token(
+    form: Json<CreateTokenForm>,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<CreatedApiToken>> {
+    Json(match create_token(&form, auth, &conn).await {
+        Ok(created) => ApiResponse::new_ok(created),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[post("/tokens/<id>/revoke")]
+pub async fn api_revoke_token(id: i32, auth: AuthCookie, conn: Database) -> Json<ApiResponse<()>> {
+    Json(match revoke_token(id, auth, &conn).await {
+        Ok(true) => ApiResponse::new_ok(()),
+        Ok(false) => ApiResponse::new_err("No token with that id exists."),
+        Err(e) => {
+            error!("{:#?}", e);
+            ApiResponse::new_err("Encountered a database error trying to fulfil that request.")
+        }
+    })
+}
+
+#[cfg(test)]
+mod test_api_tokens {
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+    use rocket::http::{ContentType, Header, Status};
+    use serde_json::Value;
+
+    use crate::{
+        db::Database,
+        schema::api_token,
+        utils::{client, create_user, login_user, logout},
+    };
+
+    const USERNAME: &str = "scripting-user";
+    const EMAIL: &str = "scripts@example.com";
+    const PASSWORD: &str = "p4ssw0rdF0rScr1pts";
+    const TIMEZONE: &str = "Africa/Abidjan";
+
+    async fn create_token(
+        body: &'static str,
+        client: &rocket::local::asynchronous::Client,
+    ) -> String {
+        let res = client
+            .post("/api/auth/tokens")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let res: Value = serde_json::from_str(&res).unwrap();
+        res["data"]["token"].as_str().unwrap().to_string()
+    }
+
+    #[rocket::async_test]
+    async fn test_tokens_are_scoped() {
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        let read_only = create_token(
+            r#"{"name": "read-only", "read_only": true, "scope_notifications": true}"#,
+            &client,
+        )
+        .await;
+        let classes = create_token(r#"{"name": "classes", "scope_classes": true}"#, &client).await;
+        let stored_hashes = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                api_token::table
+                    .select(api_token::token_hash)
+                    .load::<String>(c)
+                    .unwrap()
+            })
+            .await;
+        assert!(!stored_hashes.contains(&read_only));
+        assert!(!stored_hashes.contains(&classes));
+        logout(&client).await;
+
+        let res = client
+            .get("/api/notifications")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", read_only),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("\"success\":true"));
+
+        // read-only tokens cannot change anything
+        let res = client
+            .get("/api/notifications/delete/1")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", read_only),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+
+        // tokens can only be used for the scopes they have been granted
+        let res = client
+            .get("/api/notifications")
+            .header(Header::new("Authorization", format!("Bearer {}", classes)))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        let res = client
+            .post("/api/class/create")
+            .header(Header::new("Authorization", format!("Bearer {}", classes)))
+            .header(ContentType::JSON)
+            .body(r#"{"name": "Class", "description": "Created with a token."}"#)
+            .dispatch()
+            .await;
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("\"success\":true"));
+
+        // tokens cannot be used to manage other tokens
+        let res = client
+            .get("/api/auth/tokens")
+            .header(Header::new("Authorization", format!("Bearer {}", classes)))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        let res = client
+            .get("/api/notifications")
+            .header(Header::new(
+                "Authorization",
+                "Bearer lovelace_not-a-real-token",
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn test_tokens_can_expire_and_be_revoked() {
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        let expiring = create_token(
+            r#"{"name": "expiring", "expires_in_days": 1, "scope_notifications": true}"#,
+            &client,
+        )
+        .await;
+        let revoked = create_token(
+            r#"{"name": "revoked", "scope_notifications": true}"#,
+            &client,
+        )
+        .await;
+        // expiry dates have to be in the (not too distant) future
+        for days in &[0i64, -1, 100_000_000_000] {
+            let res = client
+                .post("/api/auth/tokens")
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{"name": "invalid", "expires_in_days": {}}}"#,
+                    days
+                ))
+                .dispatch()
+                .await
+                .into_string()
+                .await
+                .unwrap();
+            assert!(res.contains("\"success\":false"));
+        }
+        let revoked_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                diesel::update(api_token::table.filter(api_token::name.eq("expiring")))
+                    .set(api_token::expires.eq(Utc::now().naive_utc() - Duration::hours(1)))
+                    .execute(c)
+                    .unwrap();
+                api_token::table
+                    .filter(api_token::name.eq("revoked"))
+                    .select(api_token::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        let res = client
+            .post(format!("/auth/tokens/{}/revoke", revoked_id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(res.contains("That token has been revoked."));
+        logout(&client).await;
+
+        for token in [expiring, revoked].iter() {
+            let res = client
+                .get("/api/notifications")
+                .header(Header::new("Authorization", format!("Bearer {}", token)))
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Unauthorized);
+        }
+    }
+}
@@ -0,0 +1,1076 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Two-factor authentication using time-based one-time passwords (RFC 6238).
+//!
+//! Users enrol by scanning a QR code with an authenticator app and then entering a code to confirm
+//! that it works. At that point they are given a set of single-use recovery codes, which can be used
+//! instead of a TOTP code if they lose access to their authenticator.
+//!
+//! Once a user has enrolled, a correct password is no longer enough to log in. Instead of a session
+//! we give them a short-lived "pending login" cookie, which can be exchanged for a session by
+//! providing a TOTP (or recovery) code. Institutions can also require their administrators and
+//! teachers to use two-factor authentication, in which case such users who have not yet enrolled
+//! are sent to the enrolment page (rather than being logged in) after entering their password.
+
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac, NewMac};
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
+use qrcode::{render::svg, QrCode};
+use rocket::{
+    http::{Cookie, CookieJar, Status},
+    outcome::IntoOutcome,
+    request::FromRequest,
+    serde::json::Json,
+};
+use sha1::Sha1;
+use thiserror::Error as ThisError;
+
+use crate::{
+    db::{Database, DatabaseConnection},
+    models::{
+        auth::two_factor::{NewRecoveryCode, NewTotp, Totp},
+        User,
+    },
+    schema::{administrator, institution, institution_teacher, totp, totp_recovery_code, users},
+    utils::{default_head, json_response::ApiResponse},
+};
+
+use super::{
+    limit::{self, LimitError},
+    session::{start_session, SessionMetadata},
+    token::hash_token,
+    AuthCookie, AuthError,
+};
+
+/// The cookie which stores a login which is waiting for a second factor.
+pub const PENDING_LOGIN_COOKIE: &str = "PENDING_SECOND_FACTOR";
+/// How long somebody has to provide their second factor after entering their password.
+const PENDING_LOGIN_MINUTES: i64 = 10;
+
+const TOTP_STEP_SECONDS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: [char; 32] = [
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'j', 'k', 'm', 'n', 'p', 'q', 'r', 's', 't', 'u', 'v',
+    'w', 'x', 'y', 'z', '2', '3', '4', '5', '6', '7', '8', '9', '0',
+];
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// Computes an HOTP value (RFC 4226) for the given counter.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Checks a TOTP code against the (base32 encoded) secret, returning the time step which it was
+/// valid for. To allow for clocks which are slightly out, codes from one step either side of the
+/// current one are also accepted.
+fn verify_totp(secret: &str, code: &str, unix_time: u64) -> Option<i64> {
+    let secret = base32::decode(BASE32, secret)?;
+    let code = code.trim().replace(' ', "");
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let step = unix_time / TOTP_STEP_SECONDS;
+    (step.saturating_sub(1)..=step + 1)
+        .find(|candidate| hotp(&secret, *candidate) == code)
+        .map(|step| step as i64)
+}
+
+fn otpauth_uri(secret: &str, username: &str) -> String {
+    format!(
+        "otpauth://totp/Lovelace:{}?secret={}&issuer=Lovelace&digits={}&period={}",
+        username, secret, TOTP_DIGITS, TOTP_STEP_SECONDS
+    )
+}
+
+/// Renders the QR code as an inline SVG image, so that no third-party service ever sees the secret.
+fn qr_code(uri: &str) -> Img {
+    let svg = QrCode::new(uri.as_bytes())
+        .map(|code| code.render::<svg::Color>().min_dimensions(200, 200).build())
+        .unwrap_or_default();
+    Img::new()
+        .attribute(Src::new(format!(
+            "data:image/svg+xml;base64,{}",
+            base64::encode(svg)
+        )))
+        .attribute(Alt::new(
+            "A QR code to scan with your authenticator app.".to_string(),
+        ))
+}
+
+/// Whether the user is an administrator or teacher at an institution which requires two-factor
+/// authentication.
+pub fn two_factor_required(
+    user_id: i32,
+    conn: &DatabaseConnection,
+) -> Result<bool, diesel::result::Error> {
+    let administrator = diesel::select(diesel::dsl::exists(
+        administrator::table
+            .inner_join(institution::table)
+            .filter(administrator::user_id.eq(user_id))
+            .filter(institution::require_two_factor.eq(true)),
+    ))
+    .get_result::<bool>(conn)?;
+    if administrator {
+        return Ok(true);
+    }
+    diesel::select(diesel::dsl::exists(
+        institution_teacher::table
+            .inner_join(institution::table)
+            .filter(institution_teacher::user_id.eq(user_id))
+            .filter(institution::require_two_factor.eq(true)),
+    ))
+    .get_result::<bool>(conn)
+}
+
+/// What (if anything) needs to happen before somebody who has entered the right password can be
+/// logged in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SecondFactor {
+    /// The user has not enrolled, and nobody requires them to.
+    NotNeeded,
+    /// The user must enter a code.
+    Verify,
+    /// The user's institution requires two-factor authentication, but they have not enrolled yet.
+    Enrol,
+}
+
+pub async fn second_factor_for(
+    user_id: i32,
+    conn: &Database,
+) -> Result<SecondFactor, diesel::result::Error> {
+    conn.run(move |c| {
+        let enrolled = diesel::select(diesel::dsl::exists(
+            totp::table
+                .filter(totp::user_id.eq(user_id))
+                .filter(totp::confirmed.eq(true)),
+        ))
+        .get_result::<bool>(c)?;
+        Ok(if enrolled {
+            SecondFactor::Verify
+        } else if two_factor_required(user_id, c)? {
+            SecondFactor::Enrol
+        } else {
+            SecondFactor::NotNeeded
+        })
+    })
+    .await
+}
+
+/// A login where the password has been checked, but which is waiting for a second factor.
+#[derive(Debug, Copy, Clone)]
+pub struct PendingLogin {
+    pub user_id: i32,
+    pub stage: SecondFactor,
+}
+
+impl PendingLogin {
+    /// Stores this pending login in a (private) cookie.
+    pub fn set(self, cookies: &CookieJar<'_>) {
+        let expires = (Utc::now() + Duration::minutes(PENDING_LOGIN_MINUTES)).timestamp();
+        let stage = match self.stage {
+            SecondFactor::Enrol => "enrol",
+            _ => "verify",
+        };
+        cookies.add_private(Cookie::new(
+            PENDING_LOGIN_COOKIE,
+            format!("{}:{}:{}", self.user_id, expires, stage),
+        ));
+    }
+
+    pub fn clear(cookies: &CookieJar<'_>) {
+        cookies.remove_private(Cookie::named(PENDING_LOGIN_COOKIE));
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(':');
+        let user_id = parts.next()?.parse().ok()?;
+        let expires = parts.next()?.parse::<i64>().ok()?;
+        let stage = match parts.next()? {
+            "enrol" => SecondFactor::Enrol,
+            "verify" => SecondFactor::Verify,
+            _ => return None,
+        };
+        if expires < Utc::now().timestamp() {
+            return None;
+        }
+        Some(Self { user_id, stage })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PendingLogin {
+    type Error = AuthError;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        request
+            .cookies()
+            .get_private(PENDING_LOGIN_COOKIE)
+            .and_then(|cookie| PendingLogin::parse(cookie.value()))
+            .or_forward(())
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum TwoFactorError {
+    #[error("invalid code")]
+    InvalidCode,
+    #[error("two-factor authentication is already set up")]
+    AlreadyEnrolled,
+    #[error("two-factor authentication is not set up")]
+    NotEnrolled,
+    #[error("two-factor authentication is required by an institution")]
+    RequiredByInstitution,
+    #[error("not logged in")]
+    NotLoggedIn,
+    #[error("too many attempts")]
+    RateLimited(LimitError),
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for TwoFactorError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl From<LimitError> for TwoFactorError {
+    fn from(e: LimitError) -> Self {
+        match e {
+            LimitError::DatabaseError => Self::DatabaseError,
+            e => Self::RateLimited(e),
+        }
+    }
+}
+
+impl TwoFactorError {
+    fn explanation(&self) -> String {
+        match self {
+            TwoFactorError::InvalidCode => "That code isn't right. Make sure that the clock on \
+            your device is correct, or use one of your recovery codes."
+                .to_string(),
+            TwoFactorError::AlreadyEnrolled => {
+                "You've already set up two-factor authentication for your account.".to_string()
+            }
+            TwoFactorError::NotEnrolled => {
+                "You haven't set up two-factor authentication for your account.".to_string()
+            }
+            TwoFactorError::RequiredByInstitution => "An institution which you are part of \
+            requires you to use two-factor authentication, so you can't turn it off."
+                .to_string(),
+            TwoFactorError::NotLoggedIn => "You need to log in first.".to_string(),
+            TwoFactorError::RateLimited(e) => e.explanation(),
+            TwoFactorError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+        }
+    }
+
+    fn status(&self) -> Status {
+        match self {
+            TwoFactorError::DatabaseError => Status::InternalServerError,
+            TwoFactorError::NotLoggedIn => Status::Unauthorized,
+            TwoFactorError::RateLimited(e) => e.status(),
+            _ => Status::BadRequest,
+        }
+    }
+}
+
+/// Checks a TOTP or recovery code for the user. Each code can only be used once.
+fn check_code(user_id: i32, code: &str, conn: &DatabaseConnection) -> Result<(), TwoFactorError> {
+    let totp = totp::table
+        .filter(totp::user_id.eq(user_id))
+        .filter(totp::confirmed.eq(true))
+        .first::<Totp>(conn)
+        .optional()?
+        .ok_or(TwoFactorError::NotEnrolled)?;
+    if let Some(step) = verify_totp(&totp.secret, code, Utc::now().timestamp() as u64) {
+        // only accept codes from after the last one which was used
+        let updated = diesel::update(
+            totp::table.filter(totp::id.eq(totp.id)).filter(
+                totp::last_used_step
+                    .is_null()
+                    .or(totp::last_used_step.lt(step)),
+            ),
+        )
+        .set(totp::last_used_step.eq(step))
+        .execute(conn)?;
+        return if updated == 1 {
+            Ok(())
+        } else {
+            Err(TwoFactorError::InvalidCode)
+        };
+    }
+    let used = diesel::update(
+        totp_recovery_code::table
+            .filter(totp_recovery_code::user_id.eq(user_id))
+            .filter(totp_recovery_code::code_hash.eq(hash_token(&code.trim().to_lowercase())))
+            .filter(totp_recovery_code::used.eq(false)),
+    )
+    .set(totp_recovery_code::used.eq(true))
+    .execute(conn)?;
+    if used == 1 {
+        Ok(())
+    } else {
+        Err(TwoFactorError::InvalidCode)
+    }
+}
+
+/// Runs `f` (which should check a code with [`check_code`]) in a transaction, subject to the rate
+/// limits on second factors. Otherwise anybody who knows somebody's password could try every
+/// possible code in turn.
+fn rate_limited<T>(
+    user_id: i32,
+    ip: Option<&str>,
+    c: &DatabaseConnection,
+    f: impl FnOnce() -> Result<T, TwoFactorError>,
+) -> Result<T, TwoFactorError> {
+    limit::check(c, limit::Action::SecondFactor, Some(user_id), ip)?;
+    let res = c.transaction(f);
+    match res {
+        Ok(_) => limit::reset(c, limit::Action::SecondFactor, user_id)?,
+        Err(TwoFactorError::InvalidCode) => {
+            limit::record(c, limit::Action::SecondFactor, Some(user_id), ip)?;
+        }
+        Err(_) => {}
+    }
+    res
+}
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct SecondFactorForm {
+    code: String,
+}
+
+pub fn second_factor_form() -> malvolio::prelude::Form {
+    malvolio::prelude::Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/login/2fa"))
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Placeholder::new("Code from your authenticator app"))
+                .attribute(Name::new("code")),
+        )
+        .child(Br)
+        .child(
+            Input::default()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Log in")),
+        )
+}
+
+/// Completes a login which was waiting for a second factor.
+async fn complete_login_base(
+    pending: PendingLogin,
+    metadata: &SessionMetadata,
+    cookies: &CookieJar<'_>,
+    data: &SecondFactorForm,
+    conn: &Database,
+) -> Result<User, TwoFactorError> {
+    if pending.stage != SecondFactor::Verify {
+        return Err(TwoFactorError::NotEnrolled);
+    }
+    let code = data.code.clone();
+    let ip = metadata.ip.clone();
+    let user = conn
+        .run(move |c| {
+            rate_limited(pending.user_id, ip.as_deref(), c, || {
+                check_code(pending.user_id, &code, c)?;
+                Ok(users::table.find(pending.user_id).first::<User>(c)?)
+            })
+        })
+        .await?;
+    start_session(user.id, metadata, cookies, conn).await?;
+    PendingLogin::clear(cookies);
+    Ok(user)
+}
+
+#[post("/login/2fa", data = "<data>")]
+pub async fn html_complete_login(
+    pending: Option<PendingLogin>,
+    metadata: SessionMetadata,
+    cookies: &CookieJar<'_>,
+    data: rocket::form::Form<SecondFactorForm>,
+    conn: Database,
+) -> Html {
+    let res = match pending {
+        Some(pending) => complete_login_base(pending, &metadata, cookies, &data, &conn).await,
+        None => Err(TwoFactorError::NotLoggedIn),
+    };
+    match res {
+        Ok(_) => Html::default()
+            .head(default_head("Logged in".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Logged in!"))
+                    .child(P::with_text("You are now logged in.")),
+            ),
+        Err(TwoFactorError::NotLoggedIn) => Html::default()
+            .status(Status::Unauthorized)
+            .head(default_head("Login expired".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Please log in again"))
+                    .child(P::with_text(
+                        "You took too long to enter your code, so you'll need to enter your \
+                        password again.",
+                    ))
+                    .child(
+                        A::default()
+                            .attribute(Href::new("/auth/login"))
+                            .text("Log in"),
+                    ),
+            ),
+        Err(e) => Html::default()
+            .status(e.status())
+            .head(default_head("Two-factor authentication".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Two-factor authentication"))
+                    .child(P::with_text(e.explanation()))
+                    .child(second_factor_form()),
+            ),
+    }
+}
+
+#[post("/login/2fa", data = "<data>")]
+pub async fn api_complete_login(
+    pending: Option<PendingLogin>,
+    metadata: SessionMetadata,
+    cookies: &CookieJar<'_>,
+    data: Json<SecondFactorForm>,
+    conn: Database,
+) -> Json<ApiResponse<User>> {
+    let res = match pending {
+        Some(pending) => complete_login_base(pending, &metadata, cookies, &data, &conn).await,
+        None => Err(TwoFactorError::NotLoggedIn),
+    };
+    Json(match res {
+        Ok(user) => ApiResponse::new_ok(user),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+/// The user who is setting up two-factor authentication – either somebody who is logged in, or
+/// somebody whose institution requires them to enrol before they can log in.
+fn enrolling_user(
+    auth: Option<AuthCookie>,
+    pending: Option<PendingLogin>,
+) -> Result<i32, TwoFactorError> {
+    match (auth, pending) {
+        (Some(auth), _) => Ok(auth.0),
+        (
+            None,
+            Some(PendingLogin {
+                user_id,
+                stage: SecondFactor::Enrol,
+            }),
+        ) => Ok(user_id),
+        _ => Err(TwoFactorError::NotLoggedIn),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Enrolment {
+    secret: String,
+    otpauth_uri: String,
+}
+
+/// Starts setting up two-factor authentication for the user. If they've already started (and
+/// `restart` isn't set), they're given the same secret as last time, so that reloading the page
+/// doesn't stop an authenticator app which has already scanned it from working. Otherwise a new
+/// (unconfirmed) secret replaces any existing unconfirmed one.
+async fn begin_enrolment_base(
+    user_id: i32,
+    restart: bool,
+    conn: &Database,
+) -> Result<Enrolment, TwoFactorError> {
+    let (secret, username) = conn
+        .run(move |c| {
+            c.transaction::<_, TwoFactorError, _>(|| {
+                let enrolled = diesel::select(diesel::dsl::exists(
+                    totp::table
+                        .filter(totp::user_id.eq(user_id))
+                        .filter(totp::confirmed.eq(true)),
+                ))
+                .get_result::<bool>(c)?;
+                if enrolled {
+                    return Err(TwoFactorError::AlreadyEnrolled);
+                }
+                let unconfirmed = totp::table
+                    .filter(totp::user_id.eq(user_id))
+                    .select(totp::secret)
+                    .first::<String>(c)
+                    .optional()?;
+                let secret = match unconfirmed {
+                    Some(secret) if !restart => secret,
+                    _ => {
+                        let secret = base32::encode(
+                            BASE32,
+                            &(0..20).map(|_| rand::random::<u8>()).collect::<Vec<_>>(),
+                        );
+                        diesel::delete(totp::table.filter(totp::user_id.eq(user_id))).execute(c)?;
+                        diesel::insert_into(totp::table)
+                            .values(NewTotp {
+                                user_id,
+                                secret: &secret,
+                                confirmed: false,
+                                created: Utc::now().naive_utc(),
+                            })
+                            .execute(c)?;
+                        secret
+                    }
+                };
+                let username = users::table
+                    .find(user_id)
+                    .select(users::username)
+                    .first::<String>(c)?;
+                Ok((secret, username))
+            })
+        })
+        .await?;
+    Ok(Enrolment {
+        otpauth_uri: otpauth_uri(&secret, &username),
+        secret,
+    })
+}
+
+fn confirm_enrolment_form() -> malvolio::prelude::Form {
+    malvolio::prelude::Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/2fa/confirm"))
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Placeholder::new("Code from your authenticator app"))
+                .attribute(Name::new("code")),
+        )
+        .child(Br)
+        .child(
+            Input::default()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Turn on two-factor authentication")),
+        )
+}
+
+fn disable_form() -> malvolio::prelude::Form {
+    malvolio::prelude::Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new("/auth/2fa/disable"))
+        .child(
+            Input::default()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Placeholder::new("Code from your authenticator app"))
+                .attribute(Name::new("code")),
+        )
+        .child(Br)
+        .child(
+            Input::default()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Turn off two-factor authentication")),
+        )
+}
+
+#[get("/2fa")]
+pub async fn two_factor_page(
+    auth: Option<AuthCookie>,
+    pending: Option<PendingLogin>,
+    conn: Database,
+) -> Html {
+    let res = match enrolling_user(auth, pending) {
+        Ok(user_id) => begin_enrolment_base(user_id, false, &conn).await,
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(enrolment) => Html::default()
+            .head(default_head("Set up two-factor authentication".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Set up two-factor authentication"))
+                    .child(P::with_text(
+                        "Scan this QR code with your authenticator app, and then enter the code \
+                        it shows to finish setting up two-factor authentication.",
+                    ))
+                    .child(BodyNode::Img(qr_code(&enrolment.otpauth_uri)))
+                    .child(P::with_text(format!(
+                        "If you can't scan the code, enter this key into your app instead: {}",
+                        enrolment.secret
+                    )))
+                    .child(confirm_enrolment_form()),
+            ),
+        Err(TwoFactorError::AlreadyEnrolled) => Html::default()
+            .head(default_head("Two-factor authentication".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Two-factor authentication is turned on"))
+                    .child(P::with_text(
+                        "You'll be asked for a code from your authenticator app whenever you log \
+                        in. To turn this off, enter a code below.",
+                    ))
+                    .child(disable_form()),
+            ),
+        Err(e) => Html::default()
+            .status(e.status())
+            .head(default_head("Two-factor authentication".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Two-factor authentication"))
+                    .child(P::with_text(e.explanation())),
+            ),
+    }
+}
+
+#[post("/2fa")]
+pub async fn api_begin_enrolment(auth: AuthCookie, conn: Database) -> Json<ApiResponse<Enrolment>> {
+    // (this is how API clients ask for a new secret, so one is always generated)
+    Json(match begin_enrolment_base(auth.0, true, &conn).await {
+        Ok(enrolment) => ApiResponse::new_ok(enrolment),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+/// Confirms the user's secret (given a valid code), and returns their recovery codes. If this
+/// completes a login which was waiting for the user to enrol, they are also logged in.
+async fn confirm_enrolment_base(
+    auth: Option<AuthCookie>,
+    pending: Option<PendingLogin>,
+    metadata: &SessionMetadata,
+    cookies: &CookieJar<'_>,
+    data: &SecondFactorForm,
+    conn: &Database,
+) -> Result<Vec<String>, TwoFactorError> {
+    let user_id = enrolling_user(auth, pending)?;
+    let code = data.code.clone();
+    let recovery_codes = (0..RECOVERY_CODE_COUNT)
+        .map(|_| nanoid!(10, &RECOVERY_CODE_ALPHABET))
+        .collect::<Vec<_>>();
+    let hashes = recovery_codes
+        .iter()
+        .map(|code| hash_token(code))
+        .collect::<Vec<_>>();
+    conn.run(move |c| {
+        c.transaction::<_, TwoFactorError, _>(|| {
+            let totp = totp::table
+                .filter(totp::user_id.eq(user_id))
+                .filter(totp::confirmed.eq(false))
+                .first::<Totp>(c)
+                .optional()?
+                .ok_or(TwoFactorError::AlreadyEnrolled)?;
+            let step = verify_totp(&totp.secret, &code, Utc::now().timestamp() as u64)
+                .ok_or(TwoFactorError::InvalidCode)?;
+            diesel::update(totp::table.filter(totp::id.eq(totp.id)))
+                .set((totp::confirmed.eq(true), totp::last_used_step.eq(step)))
+                .execute(c)?;
+            diesel::delete(
+                totp_recovery_code::table.filter(totp_recovery_code::user_id.eq(user_id)),
+            )
+            .execute(c)?;
+            diesel::insert_into(totp_recovery_code::table)
+                .values(
+                    hashes
+                        .iter()
+                        .map(|hash| NewRecoveryCode {
+                            user_id,
+                            code_hash: hash,
+                            used: false,
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(c)?;
+            Ok(())
+        })
+    })
+    .await?;
+    if auth.is_none() {
+        start_session(user_id, metadata, cookies, conn).await?;
+        PendingLogin::clear(cookies);
+    }
+    Ok(recovery_codes)
+}
+
+#[post("/2fa/confirm", data = "<data>")]
+pub async fn html_confirm_enrolment(
+    auth: Option<AuthCookie>,
+    pending: Option<PendingLogin>,
+    metadata: SessionMetadata,
+    cookies: &CookieJar<'_>,
+    data: rocket::form::Form<SecondFactorForm>,
+    conn: Database,
+) -> Html {
+    match confirm_enrolment_base(auth, pending, &metadata, cookies, &data, &conn).await {
+        Ok(codes) => Html::default()
+            .head(default_head("Two-factor authentication is on".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Two-factor authentication is turned on"))
+                    .child(P::with_text(
+                        "These are your recovery codes. Each one can be used once instead of a \
+                        code from your authenticator app (for example if you lose your phone). \
+                        Keep them somewhere safe – you won't be able to see them again!",
+                    ))
+                    .children(codes.into_iter().map(P::with_text)),
+            ),
+        Err(e) => Html::default()
+            .status(e.status())
+            .head(default_head("Two-factor authentication".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Two-factor authentication"))
+                    .child(P::with_text(e.explanation()))
+                    .map(|body| {
+                        if let TwoFactorError::InvalidCode = e {
+                            body.child(confirm_enrolment_form())
+                        } else {
+                            body
+                        }
+                    }),
+            ),
+    }
+}
+
+#[post("/2fa/confirm", data = "<data>")]
+pub async fn api_confirm_enrolment(
+    auth: Option<AuthCookie>,
+    pending: Option<PendingLogin>,
+    metadata: SessionMetadata,
+    cookies: &CookieJar<'_>,
+    data: Json<SecondFactorForm>,
+    conn: Database,
+) -> Json<ApiResponse<Vec<String>>> {
+    Json(
+        match confirm_enrolment_base(auth, pending, &metadata, cookies, &data, &conn).await {
+            Ok(codes) => ApiResponse::new_ok(codes),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+/// Turns off two-factor authentication for the user (given a valid code), unless one of their
+/// institutions requires it.
+async fn disable_base(
+    auth: AuthCookie,
+    metadata: &SessionMetadata,
+    data: &SecondFactorForm,
+    conn: &Database,
+) -> Result<(), TwoFactorError> {
+    let code = data.code.clone();
+    let ip = metadata.ip.clone();
+    conn.run(move |c| {
+        rate_limited(auth.0, ip.as_deref(), c, || {
+            if two_factor_required(auth.0, c)? {
+                return Err(TwoFactorError::RequiredByInstitution);
+            }
+            check_code(auth.0, &code, c)?;
+            diesel::delete(totp::table.filter(totp::user_id.eq(auth.0))).execute(c)?;
+            diesel::delete(
+                totp_recovery_code::table.filter(totp_recovery_code::user_id.eq(auth.0)),
+            )
+            .execute(c)?;
+            Ok(())
+        })
+    })
+    .await
+}
+
+#[post("/2fa/disable", data = "<data>")]
+pub async fn html_disable_two_factor(
+    auth: AuthCookie,
+    metadata: SessionMetadata,
+    data: rocket::form::Form<SecondFactorForm>,
+    conn: Database,
+) -> Html {
+    match disable_base(auth, &metadata, &data, &conn).await {
+        Ok(()) => Html::default()
+            .head(default_head("Two-factor authentication is off".to_string()))
+            .body(Body::default().child(H1::new("Two-factor authentication is turned off"))),
+        Err(e) => Html::default()
+            .status(e.status())
+            .head(default_head("Two-factor authentication".to_string()))
+            .body(
+                Body::default()
+                    .child(H1::new("Two-factor authentication"))
+                    .child(P::with_text(e.explanation()))
+                    .child(disable_form()),
+            ),
+    }
+}
+
+#[post("/2fa/disable", data = "<data>")]
+pub async fn api_disable_two_factor(
+    auth: AuthCookie,
+    metadata: SessionMetadata,
+    data: Json<SecondFactorForm>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(match disable_base(auth, &metadata, &data, &conn).await {
+        Ok(()) => ApiResponse::new_ok(()),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[cfg(test)]
+mod test_two_factor {
+    use chrono::Utc;
+    use diesel::prelude::*;
+    use rocket::{
+        http::{ContentType, Status},
+        local::asynchronous::Client,
+    };
+
+    use crate::{
+        db::Database,
+        institution::test_ctx::{setup_env, TEACHER_EMAIL, TEACHER_PASSWORD},
+        schema::{institution, totp},
+        utils::{client, create_user, login_user, logout},
+    };
+
+    use super::{hotp, verify_totp, BASE32, TOTP_STEP_SECONDS};
+
+    const USERNAME: &str = "careful-user";
+    const EMAIL: &str = "careful@example.com";
+    const PASSWORD: &str = "s3cur3passw0rdWith2FA";
+    const TIMEZONE: &str = "Africa/Abidjan";
+
+    #[test]
+    fn test_rfc_6238_vectors() {
+        // the SHA-1 test vectors from appendix B of RFC 6238, truncated to six digits
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+        ]
+        .iter()
+        {
+            assert_eq!(hotp(secret, time / TOTP_STEP_SECONDS), *code);
+        }
+        let encoded = base32::encode(BASE32, secret);
+        assert_eq!(verify_totp(&encoded, "287082", 59), Some(1));
+        // codes from one step either side are accepted, but no further
+        assert_eq!(verify_totp(&encoded, "287082", 89), Some(1));
+        assert_eq!(verify_totp(&encoded, "287082", 150), None);
+        assert_eq!(verify_totp(&encoded, "000000", 59), None);
+    }
+
+    /// Returns the current code for the user's secret.
+    async fn current_code(email: &'static str, client: &Client) -> String {
+        use crate::schema::users;
+        let secret = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                totp::table
+                    .inner_join(users::table)
+                    .filter(users::email.eq(email))
+                    .select(totp::secret)
+                    .first::<String>(c)
+                    .unwrap()
+            })
+            .await;
+        format!(
+            "{:06}",
+            hotp(
+                &base32::decode(BASE32, &secret).unwrap(),
+                Utc::now().timestamp() as u64 / TOTP_STEP_SECONDS
+            )
+        )
+    }
+
+    async fn post_code(url: &str, code: &str, client: &Client) -> String {
+        client
+            .post(url)
+            .header(ContentType::Form)
+            .body(format!("code={}", code))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap()
+    }
+
+    async fn enter_password(email: &str, password: &str, client: &Client) -> String {
+        client
+            .post("/auth/login")
+            .header(ContentType::Form)
+            .body(format!("identifier={}&password={}", email, password))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn test_enrol_and_log_in() {
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        login_user(USERNAME, PASSWORD, &client).await;
+
+        let page = client
+            .get("/auth/2fa")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(page.contains("data:image/svg+xml;base64,"));
+        // loading the page again doesn't change the secret
+        let reloaded = client
+            .get("/auth/2fa")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let key = |page: &str| {
+            page.split("instead: ")
+                .nth(1)
+                .and_then(|rest| rest.split('<').next())
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(key(&page), key(&reloaded));
+        assert!(post_code("/auth/2fa/confirm", "000000", &client)
+            .await
+            .contains("That code isn't right"));
+        let code = current_code(EMAIL, &client).await;
+        let res = post_code("/auth/2fa/confirm", &code, &client).await;
+        assert!(res.contains("These are your recovery codes"));
+        let recovery_code = res
+            .rsplit("</p>")
+            .nth(1)
+            .and_then(|last| last.rsplit('>').next())
+            .unwrap()
+            .to_string();
+        logout(&client).await;
+
+        // the password is no longer enough on its own
+        let res = enter_password(EMAIL, PASSWORD, &client).await;
+        assert!(res.contains("Two-factor authentication"));
+        assert_eq!(
+            client.get("/auth/sessions").dispatch().await.status(),
+            Status::NotFound
+        );
+        // the code used to enrol cannot be used again
+        assert!(post_code("/auth/login/2fa", &code, &client)
+            .await
+            .contains("That code isn't right"));
+        assert!(post_code("/auth/login/2fa", &recovery_code, &client)
+            .await
+            .contains("Logged in"));
+        assert_eq!(
+            client.get("/auth/sessions").dispatch().await.status(),
+            Status::Ok
+        );
+        logout(&client).await;
+
+        // recovery codes can only be used once
+        enter_password(EMAIL, PASSWORD, &client).await;
+        assert!(post_code("/auth/login/2fa", &recovery_code, &client)
+            .await
+            .contains("That code isn't right"));
+    }
+
+    #[rocket::async_test]
+    async fn test_codes_are_rate_limited() {
+        let client = client().await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, &client).await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        client.get("/auth/2fa").dispatch().await;
+        let code = current_code(EMAIL, &client).await;
+        post_code("/auth/2fa/confirm", &code, &client).await;
+        logout(&client).await;
+
+        enter_password(EMAIL, PASSWORD, &client).await;
+        for _ in 0..3 {
+            assert!(post_code("/auth/login/2fa", "000000", &client)
+                .await
+                .contains("That code isn't right"));
+        }
+        let res = client
+            .post("/auth/login/2fa")
+            .header(ContentType::Form)
+            .body("code=000000")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::TooManyRequests);
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("too many attempts"));
+    }
+
+    #[rocket::async_test]
+    async fn test_institution_can_require_two_factor() {
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::update(institution::table.find(institution_id))
+                    .set(institution::require_two_factor.eq(true))
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+
+        let res = enter_password(TEACHER_EMAIL, TEACHER_PASSWORD, &client).await;
+        assert!(res.contains("/auth/2fa"));
+        assert_eq!(
+            client.get("/auth/sessions").dispatch().await.status(),
+            Status::NotFound
+        );
+        client.get("/auth/2fa").dispatch().await;
+        let code = current_code(TEACHER_EMAIL, &client).await;
+        assert!(post_code("/auth/2fa/confirm", &code, &client)
+            .await
+            .contains("These are your recovery codes"));
+        // enrolling completes the login
+        assert_eq!(
+            client.get("/auth/sessions").dispatch().await.status(),
+            Status::Ok
+        );
+        assert!(post_code("/auth/2fa/disable", &code, &client)
+            .await
+            .contains("requires you to use two-factor authentication"));
+    }
+}
@@ -1,21 +1,119 @@
+//! Verifying users' email addresses.
+//!
+//! Users who haven't verified their email address can't create classes, join institutions or send
+//! messages; the [`EmailVerification`] request guard reports whether the current user has.
+
 use diesel::prelude::*;
 use malvolio::prelude::*;
+use portia::render::Render;
+use rocket::{
+    http::Status,
+    outcome::IntoOutcome,
+    request::{FromRequest, Outcome},
+    serde::json::Json,
+    Request,
+};
+use thiserror::Error as ThisError;
 
 use crate::{
     db::Database,
-    utils::{default_head, error_messages::database_error},
+    email::{
+        EmailBuilder, EmailSendError, RecipientBuilder, RecipientsBuilder, SendMail,
+        SendgridMailSender,
+    },
+    models::User,
+    schema::users,
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        error_messages::database_error,
+        json_response::ApiResponse,
+    },
+};
+
+use super::{
+    limit::{self, Action, LimitError},
+    session::SessionMetadata,
+    AuthCookie, AuthError,
 };
 
+/// The address is included so that a link only verifies the address it was sent to (and not
+/// whatever the user has since changed their address to).
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmailVerificationToken {
     pub exp: usize,
     pub user_id: i32,
+    pub email: String,
+}
+
+/// The code (which lasts for a day) in the link used to verify the user's current email address.
+pub(crate) fn verification_code(user: &User) -> String {
+    jwt::encode(
+        &jwt::Header::default(),
+        &EmailVerificationToken {
+            exp: (chrono::Utc::now() + chrono::Duration::days(1)).timestamp() as usize,
+            user_id: user.id,
+            email: user.email.clone(),
+        },
+        &jwt::EncodingKey::from_base64_secret(
+            &std::env::var("SECRET_KEY")
+                .unwrap_or_else(|_| "NNnXxqFeQ/1Sn8lh9MtlIW2uePR4TL/1O5dB2CPkTmg=".to_string()),
+        )
+        .unwrap(),
+    )
+    .unwrap()
+}
+
+/// Emails the user a link which they can use to verify their email address.
+pub(crate) async fn send_verification_email(user: &User) -> Result<(), EmailSendError> {
+    let email_verification_link = format!("/auth/verify?code={}", verification_code(user));
+    SendgridMailSender::default()
+        .send(
+            &EmailBuilder::default()
+                .subject("Verify your email".to_string())
+                .plaintext(Some(format!(
+                    "Copy and paste this link into your browser: {}",
+                    email_verification_link
+                )))
+                .html_text(Some(
+                    Html::new()
+                        .head(default_head("Verify your email".to_string()))
+                        .body(
+                            Body::new()
+                                .child(P::with_text("Verify your email"))
+                                .child(A::new().attribute(Href::new(email_verification_link))),
+                        )
+                        .to_string(),
+                ))
+                .recipients(
+                    RecipientsBuilder::default()
+                        .recipients(vec![RecipientBuilder::default()
+                            .email(user.email.clone())
+                            .name(user.username.clone())
+                            .build()
+                            .unwrap()])
+                        .build()
+                        .unwrap(),
+                )
+                .from(("Lovelace".to_string(), "no-reply@lovelace.ga".to_string()))
+                .reply_to(("Lovelace".to_string(), "contact@lovelace.ga".to_string()))
+                .build()
+                .unwrap(),
+        )
+        .await
 }
 
 #[get("/verify?<code>")]
-pub async fn verify_email(code: &str, conn: Database) -> Html {
+pub async fn verify_email(code: &str, metadata: SessionMetadata, conn: Database) -> Html {
     use crate::schema::users::dsl as users;
-    match jwt::decode::<EmailVerificationToken>(
+    let ip = metadata.ip.clone();
+    if let Err(e) = conn
+        .run(move |c| limit::check(c, Action::VerifyEmail, None, ip.as_deref()))
+        .await
+    {
+        return e.render();
+    }
+    let verified = match jwt::decode::<EmailVerificationToken>(
         code,
         &jwt::DecodingKey::from_base64_secret(
             &std::env::var("SECRET_KEY")
@@ -25,20 +123,207 @@ pub async fn verify_email(code: &str, conn: Database) -> Html {
         &jwt::Validation::default(),
     ) {
         Ok(code) => {
+            // if the user has changed their address since the link was sent, it doesn't verify
+            // the new one
             match conn
                 .run(move |c| {
-                    diesel::update(users::users.filter(users::id.eq(code.claims.user_id)))
-                        .set(users::email_verified.eq(true))
-                        .execute(c)
+                    diesel::update(
+                        users::users
+                            .filter(users::id.eq(code.claims.user_id))
+                            .filter(users::email.eq(code.claims.email)),
+                    )
+                    .set(users::email_verified.eq(true))
+                    .execute(c)
                 })
                 .await
             {
-                Ok(_) => Html::new()
-                    .head(default_head("Email verified".to_string()))
-                    .body(Body::new().child(H1::new("Your email has been verified."))),
-                Err(_) => database_error(),
+                Ok(updated) => updated > 0,
+                Err(_) => return database_error(),
+            }
+        }
+        Err(_) => false,
+    };
+    match verified {
+        true => Html::new()
+            .head(default_head("Email verified".to_string()))
+            .body(Body::new().child(H1::new("Your email has been verified."))),
+        false => {
+            let ip = metadata.ip;
+            if let Err(e) = conn
+                .run(move |c| limit::record(c, Action::VerifyEmail, None, ip.as_deref()))
+                .await
+            {
+                error!("{:#?}", e);
+            }
+            Html::new()
+                .status(Status::BadRequest)
+                .head(default_head("Invalid link".to_string()))
+                .body(
+                    Body::new()
+                        .child(H1::new("Invalid link"))
+                        .child(P::with_text(
+                            "This verification link is not valid (it may have expired).",
+                        )),
+                )
+        }
+    }
+}
+
+/// Whether the user making the request (through either a session or an API token) has verified
+/// their email address.
+///
+/// This forwards if nobody is logged in, so it should be used alongside the usual authentication
+/// guards (which take care of that).
+#[derive(Debug, Copy, Clone)]
+pub struct EmailVerification {
+    pub verified: bool,
+}
+
+impl EmailVerification {
+    /// Returns an error if the user has not verified their email address.
+    pub fn require(self) -> LovelaceResult<()> {
+        if self.verified {
+            Ok(())
+        } else {
+            Err(LovelaceError::EmailNotVerified)
+        }
+    }
+}
+
+/// Whether the user who made the request has verified their email address (this is looked up by
+/// [`super::resolve_credentials`], so that the request guard doesn't need a database connection
+/// of its own).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResolvedEmailVerification(pub Option<EmailVerification>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for EmailVerification {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        request
+            .local_cache(ResolvedEmailVerification::default)
+            .0
+            .or_forward(())
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum ResendVerificationError {
+    #[error("the email address has already been verified")]
+    AlreadyVerified,
+    #[error("too many attempts")]
+    Limited(LimitError),
+    #[error("could not send email")]
+    EmailError,
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for ResendVerificationError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl From<LimitError> for ResendVerificationError {
+    fn from(e: LimitError) -> Self {
+        Self::Limited(e)
+    }
+}
+
+impl ResendVerificationError {
+    fn status(&self) -> Status {
+        match self {
+            ResendVerificationError::AlreadyVerified => Status::Conflict,
+            ResendVerificationError::Limited(e) => e.status(),
+            ResendVerificationError::EmailError | ResendVerificationError::DatabaseError => {
+                Status::InternalServerError
             }
         }
-        Err(_) => Html::new(),
     }
+
+    fn explanation(&self) -> String {
+        match self {
+            ResendVerificationError::AlreadyVerified => {
+                "Your email address has already been verified.".to_string()
+            }
+            ResendVerificationError::Limited(e) => e.explanation(),
+            ResendVerificationError::EmailError => {
+                "We couldn't send you an email just now. Please try again later.".to_string()
+            }
+            ResendVerificationError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+        }
+    }
+}
+
+/// Sends the user another verification link (e.g. if the first one never arrived, or expired).
+async fn resend_verification_base(
+    auth: AuthCookie,
+    metadata: SessionMetadata,
+    conn: &Database,
+) -> Result<(), ResendVerificationError> {
+    let user = conn
+        .run(move |c| users::table.find(auth.0).first::<User>(c))
+        .await?;
+    if user.email_verified {
+        return Err(ResendVerificationError::AlreadyVerified);
+    }
+    conn.run(move |c| {
+        let ip = metadata.ip.as_deref();
+        limit::check(c, Action::ResendVerification, Some(auth.0), ip)?;
+        limit::record(c, Action::ResendVerification, Some(auth.0), ip)?;
+        Ok::<_, ResendVerificationError>(())
+    })
+    .await?;
+    send_verification_email(&user).await.map_err(|e| {
+        error!("{:#?}", e);
+        ResendVerificationError::EmailError
+    })
+}
+
+#[post("/verify/resend")]
+pub async fn html_resend_verification(
+    auth: AuthCookie,
+    metadata: SessionMetadata,
+    conn: Database,
+) -> Html {
+    match resend_verification_base(auth, metadata, &conn).await {
+        Ok(()) => Html::new()
+            .head(default_head("Verification email sent".to_string()))
+            .body(
+                Body::new()
+                    .child(H1::new("Verification email sent"))
+                    .child(P::with_text(
+                        "We've sent you another email with a link to verify your email address.",
+                    )),
+            ),
+        Err(e) => Html::new()
+            .status(e.status())
+            .head(default_head(
+                "Could not send verification email".to_string(),
+            ))
+            .body(
+                Body::new()
+                    .child(H1::new("Could not send verification email"))
+                    .child(P::with_text(e.explanation())),
+            ),
+    }
+}
+
+#[post("/verify/resend")]
+pub async fn api_resend_verification(
+    auth: AuthCookie,
+    metadata: SessionMetadata,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match resend_verification_base(auth, metadata, &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
 }
@@ -1,29 +1,64 @@
 //! Google calendar authentication.
+//!
+//! Connecting a calendar uses the OAuth authorization code flow: the `state` which is sent to Google
+//! is stored in the database (for [`OAUTH_STATE_MINUTES`]) along with the user it belongs to, and
+//! the code which Google sends back is exchanged for an access token and a refresh token. Access
+//! tokens only last for an hour or so, so they are refreshed (see [`refresh_access_token`]) when
+//! they are about to expire, or if Google stops accepting them.
 
+use chrono::{Duration, NaiveDateTime, Utc};
 use malvolio::prelude::*;
-use rocket::tokio::sync::RwLock;
-use rocket::{response::Redirect, State};
-use std::collections::HashMap;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle};
+use reqwest::Url;
+use rocket::response::Redirect;
 
 use diesel::prelude::*;
 
 use crate::{
     auth::AuthCookie,
+    calendar::scheduler::SchedulingError,
     catch_database_error,
     db::Database,
-    models::calendar::{CalendarType, NewCalendar, NewGoogleCalendar},
-    utils::{default_head, html_or_redirect::HtmlOrRedirect},
+    models::calendar::{
+        CalendarType, GcalOauthState, GoogleCalendar, NewCalendar, NewGcalOauthState,
+        NewGoogleCalendar,
+    },
+    schema::{calendar, gcal_oauth_state, google_calendar},
+    utils::{default_head, error_messages::database_error, html_or_redirect::HtmlOrRedirect},
 };
 
-#[derive(Debug, Clone)]
-pub struct StateValueEntry {
-    pub user_id: i32,
-    pub lovelace_calendar_id: String,
+/// How long (in minutes) users have to finish connecting their calendar once they've been sent to
+/// Google.
+pub const OAUTH_STATE_MINUTES: i64 = 10;
+
+fn token_url() -> String {
+    std::env::var("TOKEN_URL").unwrap_or_else(|_| "https://oauth2.googleapis.com/token".to_string())
+}
+
+fn revoke_url() -> String {
+    std::env::var("REVOKE_URL")
+        .unwrap_or_else(|_| "https://oauth2.googleapis.com/revoke".to_string())
+}
+
+/// Reads one of the environment variables which connecting to Google needs, returning the name of
+/// the variable if it hasn't been set.
+fn google_env(name: &'static str) -> Result<String, &'static str> {
+    std::env::var(name).map_err(|_| {
+        error!("the `{}` environment variable has not been set", name);
+        name
+    })
 }
 
-#[derive(Debug)]
-pub struct StateValues {
-    pub map: RwLock<HashMap<String, StateValueEntry>>,
+/// Where Google sends users back to once they've agreed to let us use their calendar. Exchanging
+/// the code for tokens only works if exactly the same value is sent then too.
+fn redirect_uri() -> Result<String, &'static str> {
+    google_env("HOSTNAME").map(|hostname| format!("{}/calendar/gcal/callback", hostname))
+}
+
+/// When an access token which lasts for `expires_in` seconds (from now) will stop working.
+fn expiry(expires_in: Option<i64>) -> Option<NaiveDateTime> {
+    expires_in.map(|seconds| (Utc::now() + Duration::seconds(seconds)).naive_utc())
 }
 
 #[get("/link")]
@@ -58,138 +93,581 @@ pub struct LinkCalendarForm {
 #[post("/link", data = "<form>")]
 pub async fn link_calendar(
     auth: AuthCookie,
-    oauth_state_values: &State<StateValues>,
+    conn: Database,
     form: rocket::form::Form<LinkCalendarForm>,
 ) -> HtmlOrRedirect {
-    let uuid = uuid::Uuid::new_v4().to_string();
+    let state = uuid::Uuid::new_v4().to_string();
 
-    oauth_state_values.map.write().await.insert(
-        uuid.clone(),
-        StateValueEntry {
-            user_id: auth.0,
-            lovelace_calendar_id: form.url.clone(),
-        },
-    );
-    let res = format!(
-        "{}?state={}&?scope=https%3A//www.googleapis.com/auth/calendar?redirect_uri={}?client_id={}",
-        std::env::var("OAUTH_TEST_SERVER")
+    let closure_state = state.clone();
+    let res = conn
+        .run(move |c| {
+            let now = Utc::now().naive_utc();
+            diesel::delete(gcal_oauth_state::table.filter(
+                gcal_oauth_state::created.le(now - Duration::minutes(OAUTH_STATE_MINUTES)),
+            ))
+            .execute(c)?;
+            diesel::insert_into(gcal_oauth_state::table)
+                .values(NewGcalOauthState {
+                    state: &closure_state,
+                    user_id: auth.0,
+                    lovelace_calendar_id: &form.url,
+                    created: now,
+                })
+                .execute(c)
+        })
+        .await;
+    if let Err(e) = res {
+        error!("{:#?}", e);
+        return HtmlOrRedirect::Html(database_error());
+    }
+    let (redirect_uri, client_id) = match (redirect_uri(), google_env("CLIENT_ID")) {
+        (Ok(redirect_uri), Ok(client_id)) => (redirect_uri, client_id),
+        _ => return HtmlOrRedirect::Html(couldnt_connect()),
+    };
+    let url = Url::parse_with_params(
+        &std::env::var("OAUTH_TEST_SERVER")
             .unwrap_or_else(|_| "https://accounts.google.com/o/oauth2/v2/auth".to_string()),
-        uuid,
-        format!(
-            "{}/calendar/auth/gcal/callback",
-            std::env::var("HOSTNAME")
-                .expect("the `HOSTNAME` environment variable has not been set")
-        ),
-        std::env::var("CLIENT_ID").expect("the `CLIENT_ID` environment variable has not been set")
-    );
-    HtmlOrRedirect::Redirect(Redirect::to(res))
+        &[
+            ("response_type", "code"),
+            ("state", &state),
+            ("scope", "https://www.googleapis.com/auth/calendar"),
+            ("redirect_uri", &redirect_uri),
+            ("client_id", &client_id),
+            // without these Google doesn't send a refresh token
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+        ],
+    )
+    .expect("the `OAUTH_TEST_SERVER` environment variable is not a valid URL");
+    HtmlOrRedirect::Redirect(Redirect::to(url.to_string()))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AccessTokenResponse {
     access_token: String,
-    expires_in: Option<i32>,
+    expires_in: Option<i64>,
     token_type: Option<String>,
     scope: Option<String>,
     refresh_token: String,
 }
 
+fn invalid_state() -> Html {
+    Html::new()
+        .head(default_head("Error".to_string()))
+        .body(Body::new().child(P::with_text(
+            "Not processing supplied code without a valid `state` query string.",
+        )))
+}
+
 #[get("/callback?<code>&<error>&<state>")]
 pub async fn gcal_callback(
     code: Option<String>,
     error: Option<String>,
     state: Option<String>,
-    oauth_state_values: &State<StateValues>,
+    auth: AuthCookie,
     conn: Database,
 ) -> Html {
-    use crate::schema::calendar;
-    use crate::schema::google_calendar;
     if error.is_some() {
         return Html::new();
     }
 
-    let lock = oauth_state_values.map.read().await;
-    if let Some(code) = code {
-        if let Some(state) = state {
-            if let Some(entry) = lock.get(&state) {
-                let entry = entry.clone();
-                // we drop the lock here because we want to read from it later.
-                std::mem::drop(lock);
-                let access_token_response: AccessTokenResponse = ureq::post(
-                    &std::env::var("TOKEN_URL")
-                        .unwrap_or_else(|_| "https://oauth2.googleapis.com/token".to_string()),
-                )
-                .set("Content-Type", "application/x-www-form-urlencoded")
-                .send_string(&format!(
-                    "code={}?
-                        client_id={}?
-                        client_secret={}?
-                        redirect_uri={}?
-                        grant_type=authorization_code",
-                    code,
-                    std::env::var("CLIENT_ID").unwrap(),
-                    std::env::var("CLIENT_SECRET").unwrap(),
-                    std::env::var("REDIRECT_URI").unwrap()
-                ))
-                .unwrap()
-                .into_json()
-                .unwrap();
-                let move_entry_user_id = entry.user_id;
-                let calendar_id = catch_database_error!(
-                    conn.run(move |c| diesel::insert_into(calendar::table)
-                        .values(NewCalendar {
-                            calendar_type: CalendarType::GoogleCalendar.into(),
-                            user_id: move_entry_user_id,
-                        })
-                        .returning(calendar::id)
-                        .get_result::<i32>(c))
-                        .await
-                );
-                cfg_if! {
-                    if #[cfg(test)] {
-                        let to_update = format!(
-                            "http://localhost:8080/user/calendars/{}",
-                            entry.lovelace_calendar_id
-                        );
-                    } else {
-                        let to_update = format!(
-                            "https://apidata.googleusercontent.com/caldav/v2/{}/events",
-                            entry.lovelace_calendar_id
-                        );
-                    }
-                };
-                catch_database_error!(
-                    conn.run(move |c| diesel::insert_into(google_calendar::table)
-                        .values(NewGoogleCalendar {
-                            refresh_token: &access_token_response.refresh_token,
-                            access_token: &access_token_response.access_token,
-                            calendar_id,
-                            lovelace_calendar_id: &to_update
-                        })
-                        .execute(c))
-                        .await
-                );
-                oauth_state_values.map.write().await.remove(&state);
-                Html::new()
-                    .head(default_head("Head".to_string()))
-                    .body(Body::new().child(H1::new("Connected your calendar")))
-            } else {
-                Html::new()
-                    .head(default_head("Error".to_string()))
-                    .body(Body::new().child(P::with_text(
-                        "Not processing supplied code without a valid `state` query string.",
-                    )))
+    let code = match code {
+        Some(code) => code,
+        None => {
+            return Html::new()
+                .head(default_head("Error".to_string()))
+                .body(Body::new().child(P::with_text("Did not get a valid code to process.")))
+        }
+    };
+    let state = match state {
+        Some(state) => state,
+        None => return invalid_state(),
+    };
+    // the state is deleted straight away so that it cannot be used twice, and it has to belong to
+    // whoever is logged in (otherwise someone could get another user to connect the attacker's
+    // calendar to their account)
+    let entry = catch_database_error!(
+        conn.run(move |c| diesel::delete(
+            gcal_oauth_state::table
+                .filter(gcal_oauth_state::state.eq(state))
+                .filter(gcal_oauth_state::user_id.eq(auth.0))
+        )
+        .get_result::<GcalOauthState>(c)
+        .optional())
+            .await
+    );
+    let entry = match entry.filter(|entry| {
+        entry.created > Utc::now().naive_utc() - Duration::minutes(OAUTH_STATE_MINUTES)
+    }) {
+        Some(entry) => entry,
+        None => return invalid_state(),
+    };
+
+    let (client_id, client_secret, redirect_uri) = match (
+        google_env("CLIENT_ID"),
+        google_env("CLIENT_SECRET"),
+        redirect_uri(),
+    ) {
+        (Ok(client_id), Ok(client_secret), Ok(redirect_uri)) => {
+            (client_id, client_secret, redirect_uri)
+        }
+        _ => return couldnt_connect(),
+    };
+    let access_token_response = match reqwest::Client::new()
+        .post(token_url())
+        .form(&[
+            ("code", code.as_str()),
+            ("client_id", &client_id),
+            ("client_secret", &client_secret),
+            ("redirect_uri", &redirect_uri),
+            ("grant_type", "authorization_code"),
+        ])
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+    {
+        Ok(res) => match res.json::<AccessTokenResponse>().await {
+            Ok(res) => res,
+            Err(e) => {
+                error!("{:#?}", e);
+                return couldnt_connect();
             }
+        },
+        Err(e) => {
+            error!("{:#?}", e);
+            return couldnt_connect();
+        }
+    };
+    let user_id = entry.user_id;
+    let calendar_id = catch_database_error!(
+        conn.run(move |c| diesel::insert_into(calendar::table)
+            .values(NewCalendar {
+                calendar_type: CalendarType::GoogleCalendar.into(),
+                user_id,
+            })
+            .returning(calendar::id)
+            .get_result::<i32>(c))
+            .await
+    );
+    cfg_if! {
+        if #[cfg(test)] {
+            let to_update = format!(
+                "http://localhost:8080/user/calendars/{}",
+                entry.lovelace_calendar_id
+            );
         } else {
-            Html::new()
-                .head(default_head("Error".to_string()))
-                .body(Body::new().child(P::with_text(
-                    "Not processing supplied code without a valid `state` query string.",
-                )))
+            let to_update = format!(
+                "https://apidata.googleusercontent.com/caldav/v2/{}/events",
+                entry.lovelace_calendar_id
+            );
+        }
+    };
+    catch_database_error!(
+        conn.run(move |c| diesel::insert_into(google_calendar::table)
+            .values(NewGoogleCalendar {
+                refresh_token: &access_token_response.refresh_token,
+                access_token: &access_token_response.access_token,
+                calendar_id,
+                lovelace_calendar_id: &to_update,
+                access_token_expires: expiry(access_token_response.expires_in),
+            })
+            .execute(c))
+            .await
+    );
+    Html::new()
+        .head(default_head("Head".to_string()))
+        .body(Body::new().child(H1::new("Connected your calendar")))
+}
+
+fn couldnt_connect() -> Html {
+    Html::new()
+        .head(default_head("Error".to_string()))
+        .body(Body::new().child(P::with_text(
+            "We couldn't connect your calendar, because Google didn't give us access to it.",
+        )))
+}
+
+#[derive(Deserialize, Debug)]
+struct RefreshTokenResponse {
+    access_token: String,
+    expires_in: Option<i64>,
+    /// Google only sends this if it has replaced the refresh token.
+    refresh_token: Option<String>,
+}
+
+/// Swaps the refresh token for a new access token (which is saved).
+pub async fn refresh_access_token(
+    gcal: GoogleCalendar,
+    conn: &Database,
+) -> Result<GoogleCalendar, SchedulingError> {
+    let client_id = google_env("CLIENT_ID").map_err(SchedulingError::MissingConfiguration)?;
+    let client_secret =
+        google_env("CLIENT_SECRET").map_err(SchedulingError::MissingConfiguration)?;
+    let res = reqwest::Client::new()
+        .post(token_url())
+        .form(&[
+            ("refresh_token", gcal.refresh_token.as_str()),
+            ("client_id", &client_id),
+            ("client_secret", &client_secret),
+            ("grant_type", "refresh_token"),
+        ])
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(SchedulingError::TokenRefresh)?
+        .json::<RefreshTokenResponse>()
+        .await
+        .map_err(SchedulingError::TokenRefresh)?;
+    let (access_token, access_token_expires) = (res.access_token, expiry(res.expires_in));
+    let refresh_token = res.refresh_token.unwrap_or(gcal.refresh_token);
+    let id = gcal.id;
+    Ok(conn
+        .run(move |c| {
+            diesel::update(google_calendar::table.find(id))
+                .set((
+                    google_calendar::access_token.eq(access_token),
+                    google_calendar::refresh_token.eq(refresh_token),
+                    google_calendar::access_token_expires.eq(access_token_expires),
+                ))
+                .get_result::<GoogleCalendar>(c)
+        })
+        .await?)
+}
+
+/// Deletes the user's Google Calendar connection (if they have one), and asks Google to revoke our
+/// access to it. Returns whether there was anything to disconnect.
+async fn disconnect(user_id: i32, conn: &Database) -> QueryResult<bool> {
+    let deleted = conn
+        .run(move |c| {
+            c.transaction::<_, diesel::result::Error, _>(|| {
+                let calendar_ids = calendar::table
+                    .filter(calendar::user_id.eq(user_id))
+                    .filter(calendar::calendar_type.eq(i32::from(CalendarType::GoogleCalendar)))
+                    .select(calendar::id)
+                    .load::<i32>(c)?;
+                let deleted = diesel::delete(
+                    google_calendar::table
+                        .filter(google_calendar::calendar_id.eq_any(&calendar_ids)),
+                )
+                .get_results::<GoogleCalendar>(c)?;
+                diesel::delete(calendar::table.filter(calendar::id.eq_any(&calendar_ids)))
+                    .execute(c)?;
+                Ok(deleted)
+            })
+        })
+        .await?;
+    for gcal in &deleted {
+        // the connection is gone either way, so this only needs to be logged if it doesn't work
+        if let Err(e) = reqwest::Client::new()
+            .post(revoke_url())
+            .form(&[("token", gcal.refresh_token.as_str())])
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+        {
+            warn!("couldn't revoke a Google Calendar token: {:#?}", e);
         }
+    }
+    Ok(!deleted.is_empty())
+}
+
+#[get("/disconnect")]
+pub fn disconnect_gcal_page(_auth: AuthCookie) -> Html {
+    Html::new()
+        .head(default_head("Disconnect your Google Calendar"))
+        .body(
+            Body::new()
+                .child(H1::new("Disconnect your Google Calendar"))
+                .child(P::with_text(
+                    "We'll stop scheduling work into your calendar, and won't be able to see it \
+                    any more.",
+                ))
+                .child(
+                    Form::new().apply(FormStyle).attribute(Method::Post).child(
+                        Input::new()
+                            .apply(FormSubmitInputStyle)
+                            .attribute(Type::Submit)
+                            .attribute(Value::new("Disconnect")),
+                    ),
+                ),
+        )
+}
+
+#[post("/disconnect")]
+pub async fn html_disconnect_gcal(auth: AuthCookie, conn: Database) -> Html {
+    if catch_database_error!(disconnect(auth.0, &conn).await) {
+        Html::new()
+            .head(default_head("Disconnected your calendar"))
+            .body(Body::new().child(H1::new("Disconnected your Google Calendar")))
     } else {
         Html::new()
-            .head(default_head("Error".to_string()))
-            .body(Body::new().child(P::with_text("Did not get a valid code to process.")))
+            .head(default_head("No calendar to disconnect"))
+            .body(Body::new().child(P::with_text(
+                "You haven't connected a Google Calendar, so there's nothing to disconnect.",
+            )))
+    }
+}
+
+#[cfg(test)]
+mod test_gcal {
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+    use reqwest::Url;
+    use rocket::http::ContentType;
+    use wiremock::{
+        matchers::{body_string_contains, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::OAUTH_STATE_MINUTES;
+    use crate::{
+        calendar::scheduler::gcal::google_calendar_clients,
+        db::Database,
+        institution::test_ctx::{
+            setup_env, STUDENT_EMAIL, STUDENT_PASSWORD, TEACHER_EMAIL, TEACHER_PASSWORD,
+        },
+        models::calendar::{
+            CalendarType, GoogleCalendar, NewCalendar, NewGcalOauthState, NewGoogleCalendar,
+        },
+        schema::{calendar, gcal_oauth_state, google_calendar},
+        utils::{client, login_user},
+    };
+
+    fn query_param(url: &Url, name: &str) -> String {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+            .unwrap()
+    }
+
+    /// Sets up a stand-in for Google's token and revocation endpoints.
+    async fn google_server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=authorization_code"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "access_token": "first-access-token",
+                    "refresh_token": "the-refresh-token",
+                    "expires_in": 3599
+                }"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .and(body_string_contains("refresh_token=the-refresh-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "access_token": "refreshed-access-token",
+                    "expires_in": 3599
+                }"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/revoke"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        std::env::set_var("OAUTH_TEST_SERVER", "https://example.com");
+        std::env::set_var("HOSTNAME", "https://example.com/redirect");
+        std::env::set_var("CLIENT_ID", "some-var");
+        std::env::set_var("CLIENT_SECRET", "some-secret-token");
+        std::env::set_var("TOKEN_URL", format!("{}/token", server.uri()));
+        std::env::set_var("REVOKE_URL", format!("{}/revoke", server.uri()));
+        server
+    }
+
+    #[rocket::async_test]
+    async fn test_oauth_state_is_stored_and_only_used_once() {
+        let server = google_server().await;
+        let client = client().await;
+        let student_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                let (_, _, student_id, _, _) = setup_env(c);
+                // this was started too long ago to still be used
+                diesel::insert_into(gcal_oauth_state::table)
+                    .values(NewGcalOauthState {
+                        state: "an-old-state",
+                        user_id: student_id,
+                        lovelace_calendar_id: "lovelace",
+                        created: (Utc::now() - Duration::minutes(OAUTH_STATE_MINUTES + 1))
+                            .naive_utc(),
+                    })
+                    .execute(c)
+                    .unwrap();
+                student_id
+            })
+            .await;
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+
+        let res = client
+            .get("/calendar/gcal/callback?state=an-old-state&code=a-code")
+            .dispatch()
+            .await;
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("without a valid `state`"));
+
+        let res = client
+            .post("/calendar/gcal/link")
+            .header(ContentType::Form)
+            .body("url=lovelace")
+            .dispatch()
+            .await;
+        assert_eq!(res.status().code, 303);
+        let location = Url::parse(res.headers().get_one("Location").unwrap()).unwrap();
+        let redirect_uri = query_param(&location, "redirect_uri");
+        assert_eq!(
+            redirect_uri,
+            "https://example.com/redirect/calendar/gcal/callback"
+        );
+        let state = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                gcal_oauth_state::table
+                    .filter(gcal_oauth_state::user_id.eq(student_id))
+                    .select(gcal_oauth_state::state)
+                    .load::<String>(c)
+                    .unwrap()
+            })
+            .await;
+        // the old state is cleared out when a new one is created
+        assert_eq!(state.len(), 1);
+        let callback = format!("/calendar/gcal/callback?state={}&code=a-code", state[0]);
+
+        // only the user who started connecting their calendar can finish doing so
+        login_user(TEACHER_EMAIL, TEACHER_PASSWORD, &client).await;
+        let res = client.get(&callback).dispatch().await;
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("without a valid `state`"));
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+
+        let res = client.get(&callback).dispatch().await;
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("Connected your calendar"));
+        let gcal = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| google_calendar::table.first::<GoogleCalendar>(c).unwrap())
+            .await;
+        assert_eq!(gcal.access_token, "first-access-token");
+        assert!(gcal.access_token_expires.unwrap() > Utc::now().naive_utc());
+        // Google only hands out tokens if the redirect URI matches the one it sent the user to
+        let token_request = server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|request| request.url.path() == "/token")
+            .unwrap();
+        let body = Url::parse(&format!(
+            "https://example.com/?{}",
+            String::from_utf8(token_request.body).unwrap()
+        ))
+        .unwrap();
+        assert_eq!(query_param(&body, "redirect_uri"), redirect_uri);
+
+        let res = client.get(&callback).dispatch().await;
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("without a valid `state`"));
+    }
+
+    #[rocket::async_test]
+    async fn test_refresh_and_disconnect() {
+        let _server = google_server().await;
+        let client = client().await;
+        let conn = Database::get_one(client.rocket()).await.unwrap();
+        let calendar_id = conn
+            .run(|c| {
+                let (_, _, student_id, _, _) = setup_env(c);
+                let calendar_id = diesel::insert_into(calendar::table)
+                    .values(NewCalendar {
+                        calendar_type: CalendarType::GoogleCalendar.into(),
+                        user_id: student_id,
+                    })
+                    .returning(calendar::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                diesel::insert_into(google_calendar::table)
+                    .values(NewGoogleCalendar {
+                        refresh_token: "the-refresh-token",
+                        access_token: "expired-access-token",
+                        calendar_id,
+                        lovelace_calendar_id: "lovelace",
+                        access_token_expires: Some((Utc::now() - Duration::minutes(5)).naive_utc()),
+                    })
+                    .execute(c)
+                    .unwrap();
+                calendar_id
+            })
+            .await;
+        let access_token = || {
+            conn.run(|c| {
+                google_calendar::table
+                    .select(google_calendar::access_token)
+                    .first::<String>(c)
+                    .unwrap()
+            })
+        };
+
+        google_calendar_clients(calendar_id, false, &conn)
+            .await
+            .unwrap();
+        assert_eq!(access_token().await, "refreshed-access-token");
+        // the refreshed token hasn't expired, so it's used as it is
+        conn.run(|c| {
+            diesel::update(google_calendar::table)
+                .set(google_calendar::access_token.eq("still-valid-access-token"))
+                .execute(c)
+                .unwrap()
+        })
+        .await;
+        google_calendar_clients(calendar_id, false, &conn)
+            .await
+            .unwrap();
+        assert_eq!(access_token().await, "still-valid-access-token");
+        // unless Google stops accepting it
+        google_calendar_clients(calendar_id, true, &conn)
+            .await
+            .unwrap();
+        assert_eq!(access_token().await, "refreshed-access-token");
+        drop(conn);
+
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+        let res = client.post("/calendar/gcal/disconnect").dispatch().await;
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("Disconnected your Google Calendar"));
+        let (calendars, google_calendars) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                (
+                    calendar::table.count().get_result::<i64>(c).unwrap(),
+                    google_calendar::table.count().get_result::<i64>(c).unwrap(),
+                )
+            })
+            .await;
+        assert_eq!((calendars, google_calendars), (0, 0));
     }
 }
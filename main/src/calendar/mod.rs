@@ -3,6 +3,9 @@
 /// Connects calendars to the application.
 pub mod connect;
 
+/// When (and how much) users are happy to have work scheduled.
+pub mod preferences;
+
 /// Schedules events. Currently we're just recomputing the entire schedule every time something
 /// changes. If this is too expensive then we may need to look at doing this incrementally.
 pub mod scheduler;
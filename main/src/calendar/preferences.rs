@@ -0,0 +1,513 @@
+//! Study preferences: when (and how much) users are happy to have work scheduled. The scheduler
+//! only places work inside the user's study hours, leaves a buffer around their events, puts in
+//! breaks and doesn't schedule more than a set amount of work on any one day.
+//!
+//! All of the times are in the user's timezone (`users.timezone`).
+
+use std::str::FromStr;
+
+use chrono::{NaiveTime, Weekday};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
+use rocket::{http::Status, serde::json::Json};
+use thiserror::Error as ThisError;
+
+use crate::{
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
+    calendar::scheduler::two_week_schedule,
+    db::{Database, DatabaseConnection},
+    models::calendar::{NewStudyHours, NewStudyPreferences, StudyHours, StudyPreferences},
+    schema::{study_hours, study_preferences, users},
+    utils::{default_head, json_response::ApiResponse},
+};
+
+const DEFAULT_MAX_MINUTES_PER_DAY: i32 = 240;
+const DEFAULT_BUFFER_MINUTES: i32 = 10;
+const DEFAULT_BREAK_EVERY_MINUTES: Option<i32> = Some(50);
+const DEFAULT_BREAK_MINUTES: i32 = 10;
+/// Users who haven't set any study hours can have work scheduled between these times on any day.
+const DEFAULT_STUDY_HOURS: (u32, u32) = (9, 21);
+
+pub(crate) const WEEKDAYS: [(Weekday, &str); 7] = [
+    (Weekday::Mon, "Monday"),
+    (Weekday::Tue, "Tuesday"),
+    (Weekday::Wed, "Wednesday"),
+    (Weekday::Thu, "Thursday"),
+    (Weekday::Fri, "Friday"),
+    (Weekday::Sat, "Saturday"),
+    (Weekday::Sun, "Sunday"),
+];
+
+/// A time of day during which the user is happy to study.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StudyWindow {
+    pub weekday: Weekday,
+    pub starts: NaiveTime,
+    pub ends: NaiveTime,
+}
+
+impl From<StudyHours> for StudyWindow {
+    fn from(hours: StudyHours) -> Self {
+        Self {
+            weekday: WEEKDAYS[hours.weekday as usize].0,
+            starts: hours.starts,
+            ends: hours.ends,
+        }
+    }
+}
+
+/// A user's study preferences (or the defaults, for anything they haven't set).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Preferences {
+    pub max_minutes_per_day: i32,
+    pub buffer_minutes: i32,
+    pub break_every_minutes: Option<i32>,
+    pub break_minutes: i32,
+    /// In order of weekday, and then of start time.
+    pub hours: Vec<StudyWindow>,
+    pub timezone: chrono_tz::Tz,
+}
+
+pub fn user_preferences(user_id: i32, c: &DatabaseConnection) -> QueryResult<Preferences> {
+    let timezone = users::table
+        .find(user_id)
+        .select(users::timezone)
+        .first::<String>(c)?;
+    let timezone = chrono_tz::Tz::from_str(&timezone).unwrap_or(chrono_tz::UTC);
+    let preferences = study_preferences::table
+        .filter(study_preferences::user_id.eq(user_id))
+        .first::<StudyPreferences>(c)
+        .optional()?;
+    let mut hours = study_hours::table
+        .filter(study_hours::user_id.eq(user_id))
+        .order_by((study_hours::weekday, study_hours::starts))
+        .load::<StudyHours>(c)?
+        .into_iter()
+        .map(StudyWindow::from)
+        .collect::<Vec<_>>();
+    if hours.is_empty() && preferences.is_none() {
+        let (starts, ends) = DEFAULT_STUDY_HOURS;
+        hours = WEEKDAYS
+            .iter()
+            .map(|(weekday, _)| StudyWindow {
+                weekday: *weekday,
+                starts: NaiveTime::from_hms(starts, 0, 0),
+                ends: NaiveTime::from_hms(ends, 0, 0),
+            })
+            .collect();
+    }
+    Ok(match preferences {
+        Some(preferences) => Preferences {
+            max_minutes_per_day: preferences.max_minutes_per_day,
+            buffer_minutes: preferences.buffer_minutes,
+            break_every_minutes: preferences.break_every_minutes,
+            break_minutes: preferences.break_minutes,
+            hours,
+            timezone,
+        },
+        None => Preferences {
+            max_minutes_per_day: DEFAULT_MAX_MINUTES_PER_DAY,
+            buffer_minutes: DEFAULT_BUFFER_MINUTES,
+            break_every_minutes: DEFAULT_BREAK_EVERY_MINUTES,
+            break_minutes: DEFAULT_BREAK_MINUTES,
+            hours,
+            timezone,
+        },
+    })
+}
+
+#[derive(ThisError, Debug, Clone, PartialEq)]
+pub enum PreferencesError {
+    #[error("database error")]
+    DatabaseError,
+    /// Something the user entered couldn't be used (the reason is shown to them).
+    #[error("invalid input")]
+    Invalid(String),
+}
+
+impl From<diesel::result::Error> for PreferencesError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl PreferencesError {
+    fn status(&self) -> Status {
+        match self {
+            PreferencesError::DatabaseError => Status::InternalServerError,
+            PreferencesError::Invalid(_) => Status::BadRequest,
+        }
+    }
+
+    pub fn explanation(&self) -> String {
+        match self {
+            PreferencesError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+            PreferencesError::Invalid(reason) => reason.clone(),
+        }
+    }
+
+    pub fn render(self) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head("Study preferences"))
+            .body(
+                Body::new()
+                    .child(H1::new("Couldn't save your study preferences"))
+                    .child(P::with_text(self.explanation())),
+            )
+    }
+}
+
+impl<T> From<PreferencesError> for ApiResponse<T> {
+    fn from(e: PreferencesError) -> Self {
+        ApiResponse::new_err(e.explanation())
+    }
+}
+
+/// Formats the study windows for one day of the week as they are entered in the form (e.g.
+/// "09:00-12:00, 14:00-18:00").
+fn format_windows(hours: &[StudyWindow], weekday: Weekday) -> String {
+    hours
+        .iter()
+        .filter(|window| window.weekday == weekday)
+        .map(|window| {
+            format!(
+                "{}-{}",
+                window.starts.format("%H:%M"),
+                window.ends.format("%H:%M")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parses the study windows for one day of the week (the opposite of [`format_windows`]).
+fn parse_windows(
+    input: &str,
+    weekday: Weekday,
+    name: &str,
+) -> Result<Vec<StudyWindow>, PreferencesError> {
+    let invalid = || {
+        PreferencesError::Invalid(format!(
+            "Couldn't understand your study hours for {}. These should look like \
+            \"09:00-12:00, 14:00-18:00\" (or be empty, if you don't want to study that day).",
+            name
+        ))
+    };
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|window| !window.is_empty())
+        .map(|window| {
+            let (starts, ends) = window.split_once('-').ok_or_else(invalid)?;
+            let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M");
+            match (parse(starts), parse(ends)) {
+                (Ok(starts), Ok(ends)) if starts < ends => Ok(StudyWindow {
+                    weekday,
+                    starts,
+                    ends,
+                }),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+#[derive(FromForm, Serialize, Deserialize, Debug, Clone)]
+pub struct PreferencesForm {
+    max_minutes_per_day: i32,
+    buffer_minutes: i32,
+    /// If this isn't given, no breaks are scheduled.
+    #[serde(default)]
+    break_every_minutes: Option<i32>,
+    break_minutes: i32,
+    /// The study hours for each day (see [`format_windows`]).
+    #[serde(default)]
+    monday: String,
+    #[serde(default)]
+    tuesday: String,
+    #[serde(default)]
+    wednesday: String,
+    #[serde(default)]
+    thursday: String,
+    #[serde(default)]
+    friday: String,
+    #[serde(default)]
+    saturday: String,
+    #[serde(default)]
+    sunday: String,
+}
+
+fn preferences_form(preferences: &Preferences) -> Form {
+    let text_input = |name: &str, value: String| {
+        Input::new()
+            .apply(FormTextInputStyle)
+            .attribute(Type::Text)
+            .attribute(Name::new(name.to_string()))
+            .attribute(Value::new(value))
+    };
+    let form = Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new("/calendar/preferences"))
+        .child(P::with_text(format!(
+            "Times are in your timezone ({}). Leave a day empty if you don't want any work to be \
+            scheduled on it.",
+            preferences.timezone.name()
+        )));
+    WEEKDAYS
+        .iter()
+        .fold(form, |form, (weekday, name)| {
+            form.child(Label::new(format!("Study hours on {}", name)))
+                .child(text_input(
+                    &name.to_lowercase(),
+                    format_windows(&preferences.hours, *weekday),
+                ))
+        })
+        .child(Label::new("The most you want to study on any one day (in minutes)"))
+        .child(text_input(
+            "max_minutes_per_day",
+            preferences.max_minutes_per_day.to_string(),
+        ))
+        .child(Label::new(
+            "How much time to leave free before and after your events (in minutes)",
+        ))
+        .child(text_input(
+            "buffer_minutes",
+            preferences.buffer_minutes.to_string(),
+        ))
+        .child(Label::new(
+            "Take a break after studying for this long (in minutes, leave this empty for no breaks)",
+        ))
+        .child(text_input(
+            "break_every_minutes",
+            preferences
+                .break_every_minutes
+                .map(|minutes| minutes.to_string())
+                .unwrap_or_default(),
+        ))
+        .child(Label::new("How long breaks should be (in minutes)"))
+        .child(text_input(
+            "break_minutes",
+            preferences.break_minutes.to_string(),
+        ))
+        .child(
+            Input::new()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Save")),
+        )
+}
+
+#[get("/")]
+pub async fn html_view_preferences(auth: AuthCookie, conn: Database) -> Html {
+    match conn.run(move |c| user_preferences(auth.0, c)).await {
+        Ok(preferences) => Html::new().head(default_head("Study preferences")).body(
+            Body::new()
+                .child(H1::new("Study preferences"))
+                .child(P::with_text(
+                    "Work is only scheduled into your calendar during the hours you choose here.",
+                ))
+                .child(preferences_form(&preferences)),
+        ),
+        Err(e) => PreferencesError::from(e).render(),
+    }
+}
+
+#[get("/")]
+pub async fn api_view_preferences(
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+) -> Json<ApiResponse<Preferences>> {
+    Json(match conn.run(move |c| user_preferences(auth.0, c)).await {
+        Ok(preferences) => ApiResponse::new_ok(preferences),
+        Err(e) => PreferencesError::from(e).into(),
+    })
+}
+
+async fn save_preferences(
+    user_id: i32,
+    form: PreferencesForm,
+    conn: Database,
+) -> Result<Preferences, PreferencesError> {
+    if form.max_minutes_per_day < 1 {
+        return Err(PreferencesError::Invalid(
+            "The most you want to study each day should be a whole number of minutes above zero."
+                .to_string(),
+        ));
+    }
+    if form.buffer_minutes < 0 || form.break_minutes < 0 {
+        return Err(PreferencesError::Invalid(
+            "Buffers and breaks can't be shorter than zero minutes.".to_string(),
+        ));
+    }
+    if form.break_every_minutes.map(|minutes| minutes < 1) == Some(true) {
+        return Err(PreferencesError::Invalid(
+            "The time between breaks should be a whole number of minutes above zero.".to_string(),
+        ));
+    }
+    let days = [
+        &form.monday,
+        &form.tuesday,
+        &form.wednesday,
+        &form.thursday,
+        &form.friday,
+        &form.saturday,
+        &form.sunday,
+    ];
+    let mut hours = vec![];
+    for ((weekday, name), input) in WEEKDAYS.iter().zip(days.iter()) {
+        hours.extend(parse_windows(input, *weekday, name)?);
+    }
+    let preferences = conn
+        .run(move |c| {
+            c.transaction(|| {
+                let preferences = NewStudyPreferences {
+                    user_id,
+                    max_minutes_per_day: form.max_minutes_per_day,
+                    buffer_minutes: form.buffer_minutes,
+                    break_every_minutes: form.break_every_minutes,
+                    break_minutes: form.break_minutes,
+                };
+                diesel::insert_into(study_preferences::table)
+                    .values(&preferences)
+                    .on_conflict(study_preferences::user_id)
+                    .do_update()
+                    .set(&preferences)
+                    .execute(c)?;
+                diesel::delete(study_hours::table.filter(study_hours::user_id.eq(user_id)))
+                    .execute(c)?;
+                diesel::insert_into(study_hours::table)
+                    .values(
+                        hours
+                            .iter()
+                            .map(|window| NewStudyHours {
+                                user_id,
+                                weekday: window.weekday.num_days_from_monday() as i16,
+                                starts: window.starts,
+                                ends: window.ends,
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .execute(c)?;
+                user_preferences(user_id, c)
+            })
+        })
+        .await?;
+    rocket::tokio::spawn(async move {
+        if let Err(e) = two_week_schedule(user_id, &conn).await {
+            error!("{:#?}", e);
+        }
+    });
+    Ok(preferences)
+}
+
+#[post("/", data = "<form>")]
+pub async fn html_save_preferences(
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<PreferencesForm>,
+) -> Html {
+    match save_preferences(auth.0, form.into_inner(), conn).await {
+        Ok(preferences) => Html::new()
+            .head(default_head("Saved your study preferences"))
+            .body(
+                Body::new()
+                    .child(H1::new("Saved your study preferences"))
+                    .child(preferences_form(&preferences)),
+            ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/", data = "<form>")]
+pub async fn api_save_preferences(
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<PreferencesForm>,
+) -> Json<ApiResponse<Preferences>> {
+    Json(match save_preferences(auth.0, form.0, conn).await {
+        Ok(preferences) => ApiResponse::new_ok(preferences),
+        Err(e) => e.into(),
+    })
+}
+
+#[cfg(test)]
+mod test_preferences {
+    use chrono::{NaiveTime, Weekday};
+    use rocket::http::{ContentType, Status};
+
+    use super::{user_preferences, StudyWindow};
+    use crate::{
+        db::Database,
+        institution::test_ctx::{setup_env, STUDENT_EMAIL, STUDENT_PASSWORD},
+        utils::{client, login_user},
+    };
+
+    #[rocket::async_test]
+    async fn test_save_preferences() {
+        let client = client().await;
+        let (_, _, student_id, _, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+
+        // users who haven't set anything can study at any (reasonable) time of day
+        let res = client.get("/calendar/preferences").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_string().await.unwrap().contains("09:00-21:00"));
+
+        let res = client
+            .post("/calendar/preferences")
+            .header(ContentType::Form)
+            .body(
+                "max_minutes_per_day=120&buffer_minutes=15&break_every_minutes=&break_minutes=0\
+                &monday=18%3A00-16%3A00&tuesday=&wednesday=&thursday=&friday=&saturday=&sunday=",
+            )
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client
+            .post("/calendar/preferences")
+            .header(ContentType::Form)
+            .body(
+                "max_minutes_per_day=120&buffer_minutes=15&break_every_minutes=&break_minutes=0\
+                &monday=16%3A00-18%3A00%2C+19%3A00-20%3A00&tuesday=&wednesday=&thursday=&friday=\
+                &saturday=10%3A00-12%3A00&sunday=",
+            )
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let preferences = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| user_preferences(student_id, c))
+            .await
+            .unwrap();
+        assert_eq!(preferences.max_minutes_per_day, 120);
+        assert_eq!(preferences.buffer_minutes, 15);
+        assert_eq!(preferences.break_every_minutes, None);
+        let window = |weekday, starts, ends| StudyWindow {
+            weekday,
+            starts: NaiveTime::from_hms(starts, 0, 0),
+            ends: NaiveTime::from_hms(ends, 0, 0),
+        };
+        assert_eq!(
+            preferences.hours,
+            vec![
+                window(Weekday::Mon, 16, 18),
+                window(Weekday::Mon, 19, 20),
+                window(Weekday::Sat, 10, 12)
+            ]
+        );
+    }
+}
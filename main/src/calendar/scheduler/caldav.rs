@@ -0,0 +1,507 @@
+//! Scheduling into calendars connected through CalDAV (see [`crate::calendar::connect::caldav`]
+//! and [`crate::calendar::connect::unauthenticated_caldav`]).
+//!
+//! Work isn't written into the calendar the user connected (so that the scheduler never touches
+//! their own events). Instead it goes into a calendar called [`LOVELACE_CALENDAR_NAME`] next to
+//! it, which is found (or created, if there isn't one) the first time the user is scheduled, and
+//! then remembered.
+
+use diesel::prelude::*;
+use prospero::{client::DavClient, client::MakeCalendar};
+
+use super::SchedulingError;
+use crate::{
+    db::Database,
+    models::calendar::{CalDav, CalDavUnauthenticated},
+    schema::{caldav, caldav_unauthenticated},
+};
+
+/// The name of the calendar which work is scheduled into.
+pub const LOVELACE_CALENDAR_NAME: &str = "Lovelace";
+
+/// The collection which holds the calendar at `url` (usually the user's calendar home).
+fn calendar_home(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    url.rsplit_once('/').map(|(home, _)| home).unwrap_or(url)
+}
+
+/// Finds the Lovelace calendar in the collection that `home` points to, creating it if it doesn't
+/// exist yet, and returns its URL.
+async fn find_or_make_lovelace_calendar(home: DavClient) -> Result<String, SchedulingError> {
+    let calendar = match home.find_calendar(LOVELACE_CALENDAR_NAME).await? {
+        Some(calendar) => calendar,
+        None => {
+            home.make_calendar(
+                MakeCalendar::new()
+                    .name(LOVELACE_CALENDAR_NAME.to_string())
+                    .id(format!("lovelace-{}", uuid::Uuid::new_v4())),
+            )
+            .await?
+        }
+    };
+    Ok(calendar.url().to_string())
+}
+
+/// Returns the clients for (respectively) the Lovelace calendar and the calendar the user
+/// connected, for a username/password CalDAV calendar.
+pub async fn caldav_clients(
+    calendar_id: i32,
+    conn: &Database,
+) -> Result<(DavClient, DavClient), SchedulingError> {
+    let row = conn
+        .run(move |c| {
+            caldav::table
+                .filter(caldav::calendar_id.eq(calendar_id))
+                .first::<CalDav>(c)
+        })
+        .await?;
+    let client = |url: &str| DavClient::new_username_password(&row.username, &row.password, url);
+    let lovelace_calendar_url = match row.lovelace_calendar_url {
+        Some(ref url) => url.clone(),
+        None => {
+            let url = find_or_make_lovelace_calendar(client(calendar_home(&row.url))).await?;
+            let (id, to_save) = (row.id, url.clone());
+            conn.run(move |c| {
+                diesel::update(caldav::table.find(id))
+                    .set(caldav::lovelace_calendar_url.eq(to_save))
+                    .execute(c)
+            })
+            .await?;
+            url
+        }
+    };
+    Ok((client(&lovelace_calendar_url), client(&row.url)))
+}
+
+/// Returns the clients for (respectively) the Lovelace calendar and the calendar the user
+/// connected, for an unauthenticated CalDAV calendar.
+pub async fn unauthenticated_caldav_clients(
+    calendar_id: i32,
+    conn: &Database,
+) -> Result<(DavClient, DavClient), SchedulingError> {
+    let row = conn
+        .run(move |c| {
+            caldav_unauthenticated::table
+                .filter(caldav_unauthenticated::calendar_id.eq(calendar_id))
+                .first::<CalDavUnauthenticated>(c)
+        })
+        .await?;
+    let lovelace_calendar_url = match row.lovelace_calendar_url {
+        Some(url) => url,
+        None => {
+            let url = find_or_make_lovelace_calendar(DavClient::new_unauthenticated(
+                calendar_home(&row.url),
+            ))
+            .await?;
+            let (id, to_save) = (row.id, url.clone());
+            conn.run(move |c| {
+                diesel::update(caldav_unauthenticated::table.find(id))
+                    .set(caldav_unauthenticated::lovelace_calendar_url.eq(to_save))
+                    .execute(c)
+            })
+            .await?;
+            url
+        }
+    };
+    Ok((
+        DavClient::new_unauthenticated(lovelace_calendar_url),
+        DavClient::new_unauthenticated(row.url),
+    ))
+}
+
+#[cfg(test)]
+mod test_caldav {
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+    use wiremock::{
+        matchers::{header_exists, method, path, path_regex},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use crate::{
+        calendar::scheduler::{schedule_class, two_week_schedule},
+        db::{Database, DatabaseConnection},
+        institution::test_ctx::setup_env,
+        models::{
+            calendar::{CalendarType, NewCalDav, NewCalDavUnauthenticated, NewCalendar},
+            NewClass, NewClassAsynchronousTask, NewClassStudent, NewClassTeacher,
+            NewStudentClassAsynchronousTask,
+        },
+        schema::{
+            caldav, caldav_unauthenticated, calendar, class, class_asynchronous_task,
+            class_student, class_teacher, student_class_asynchronous_task,
+        },
+    };
+
+    const EVENT: &str = "BEGIN:VCALENDAR
+VERSION:2.0
+BEGIN:VEVENT
+UID:lecture
+DTSTART:{start}
+DTEND:{end}
+SUMMARY:Lecture
+END:VEVENT
+END:VCALENDAR";
+
+    const BASIC: &str = "Basic realm=\"test\"";
+
+    fn multistatus(responses: &str) -> ResponseTemplate {
+        ResponseTemplate::new(207).set_body_string(format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:multistatus xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">{}</D:multistatus>"#,
+            responses
+        ))
+    }
+
+    /// A stand-in for a CalDAV server, which holds the student's own calendar (at
+    /// `/calendars/student/personal`, with one event in it) and nothing else to begin with.
+    ///
+    /// If `authenticated` is set, every request (apart from the one asking how to authenticate)
+    /// must have credentials attached. The server asks for them with `challenge` (the value of
+    /// its `WWW-Authenticate` header).
+    async fn caldav_server(authenticated: bool, challenge: &str) -> MockServer {
+        let server = MockServer::start().await;
+        let mock = |verb: &'static str| {
+            let mock = Mock::given(method(verb));
+            if authenticated {
+                mock.and(header_exists("Authorization"))
+            } else {
+                mock
+            }
+        };
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401).insert_header("WWW-Authenticate", challenge))
+            .mount(&server)
+            .await;
+        mock("PROPFIND")
+            .and(path("/calendars/student"))
+            .respond_with(multistatus(
+                r#"<D:response>
+                    <D:href>/calendars/student/personal/</D:href>
+                    <D:propstat>
+                        <D:prop>
+                            <D:displayname>Personal</D:displayname>
+                            <D:resourcetype><D:collection/><C:calendar/></D:resourcetype>
+                        </D:prop>
+                    </D:propstat>
+                </D:response>"#,
+            ))
+            .mount(&server)
+            .await;
+        // the Lovelace calendar should only be created once
+        mock("MKCALENDAR")
+            .and(path_regex("^/calendars/student/lovelace-[0-9a-f-]+$"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let start = Utc::now() + Duration::days(1);
+        let event = EVENT
+            .replace("{start}", &start.format("%Y%m%dT%H%M%SZ").to_string())
+            .replace(
+                "{end}",
+                &(start + Duration::hours(2))
+                    .format("%Y%m%dT%H%M%SZ")
+                    .to_string(),
+            );
+        mock("REPORT")
+            .and(path("/calendars/student/personal"))
+            .respond_with(multistatus(&format!(
+                "<D:response><D:propstat><D:prop><C:calendar-data>{}</C:calendar-data>\
+                </D:prop></D:propstat></D:response>",
+                event
+            )))
+            .mount(&server)
+            .await;
+        mock("REPORT")
+            .and(path_regex("^/calendars/student/lovelace-"))
+            .respond_with(multistatus(""))
+            .mount(&server)
+            .await;
+        mock("PUT")
+            .and(path_regex(
+                "^/calendars/student/lovelace-[0-9a-f-]+/.+\\.ics$",
+            ))
+            .respond_with(ResponseTemplate::new(201))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// Creates a student with a 45 minute task due in a week, and returns their id.
+    fn setup_task(c: &mut DatabaseConnection) -> i32 {
+        let (_, teacher_id, student_id, _, _) = setup_env(c);
+        let class_id = diesel::insert_into(class::table)
+            .values(NewClass {
+                name: "Physics",
+                description: "",
+                created: Utc::now().naive_utc(),
+                code: "physics",
+                institution_id: None,
+                student_group_id: None,
+                academic_term_id: None,
+            })
+            .returning(class::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        let class_teacher_id = diesel::insert_into(class_teacher::table)
+            .values(NewClassTeacher {
+                user_id: teacher_id,
+                class_id,
+            })
+            .returning(class_teacher::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        let class_student_id = diesel::insert_into(class_student::table)
+            .values(NewClassStudent {
+                user_id: student_id,
+                class_id,
+            })
+            .returning(class_student::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        let task_id = diesel::insert_into(class_asynchronous_task::table)
+            .values(NewClassAsynchronousTask {
+                title: "Pendulum lab report",
+                description: "",
+                created: Utc::now().naive_utc(),
+                due_date: (Utc::now() + Duration::days(7)).naive_utc(),
+                class_teacher_id,
+                class_id,
+                resubmit_until: None,
+                estimated_minutes: 45,
+            })
+            .returning(class_asynchronous_task::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        diesel::insert_into(student_class_asynchronous_task::table)
+            .values(NewStudentClassAsynchronousTask {
+                class_student_id,
+                class_asynchronous_task_id: task_id,
+                completed: false,
+            })
+            .execute(c)
+            .unwrap();
+        student_id
+    }
+
+    fn put_requests(requests: &[Request]) -> usize {
+        requests
+            .iter()
+            .filter(|request| request.method.to_string() == "PUT")
+            .count()
+    }
+
+    /// The `Authorization` header the request was sent with (which wiremock splits up at each
+    /// comma).
+    fn authorization(request: &Request) -> String {
+        request.headers[&"Authorization".into()]
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    #[rocket::async_test]
+    async fn test_schedule_into_unauthenticated_caldav() {
+        let server = caldav_server(false, BASIC).await;
+        let client = crate::utils::client().await;
+        let conn = Database::get_one(client.rocket()).await.unwrap();
+        let url = format!("{}/calendars/student/personal", server.uri());
+        let student_id = conn
+            .run(move |c| {
+                let student_id = setup_task(c);
+                let calendar_id = diesel::insert_into(calendar::table)
+                    .values(NewCalendar {
+                        calendar_type: CalendarType::CalDavUnauthenticated.into(),
+                        user_id: student_id,
+                    })
+                    .returning(calendar::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                diesel::insert_into(caldav_unauthenticated::table)
+                    .values(NewCalDavUnauthenticated {
+                        calendar_id,
+                        url: &url,
+                    })
+                    .execute(c)
+                    .unwrap();
+                student_id
+            })
+            .await;
+
+        let report = two_week_schedule(student_id, &conn).await.unwrap();
+        assert!(report.is_feasible());
+        assert_eq!(put_requests(&server.received_requests().await.unwrap()), 1);
+        let lovelace_calendar_url = conn
+            .run(|c| {
+                caldav_unauthenticated::table
+                    .select(caldav_unauthenticated::lovelace_calendar_url)
+                    .first::<Option<String>>(c)
+                    .unwrap()
+            })
+            .await
+            .unwrap();
+        assert!(lovelace_calendar_url
+            .starts_with(&format!("{}/calendars/student/lovelace-", server.uri())));
+
+        // the calendar which was created the first time around is used again
+        two_week_schedule(student_id, &conn).await.unwrap();
+        assert_eq!(put_requests(&server.received_requests().await.unwrap()), 2);
+
+        // no time is set aside for work which has already been handed in
+        conn.run(|c| {
+            diesel::update(student_class_asynchronous_task::table)
+                .set(student_class_asynchronous_task::completed.eq(true))
+                .execute(c)
+                .unwrap()
+        })
+        .await;
+        two_week_schedule(student_id, &conn).await.unwrap();
+        assert_eq!(put_requests(&server.received_requests().await.unwrap()), 2);
+    }
+
+    /// Connects a CalDAV calendar (at `url`) for the student who [`setup_task`] creates, and
+    /// returns their id.
+    async fn setup_caldav(url: String, conn: &Database) -> i32 {
+        conn.run(move |c| {
+            let student_id = setup_task(c);
+            let calendar_id = diesel::insert_into(calendar::table)
+                .values(NewCalendar {
+                    calendar_type: CalendarType::CalDav.into(),
+                    user_id: student_id,
+                })
+                .returning(calendar::id)
+                .get_result::<i32>(c)
+                .unwrap();
+            diesel::insert_into(caldav::table)
+                .values(NewCalDav {
+                    calendar_id,
+                    username: "student",
+                    password: "a-caldav-passw0rd",
+                    url: &url,
+                })
+                .execute(c)
+                .unwrap();
+            student_id
+        })
+        .await
+    }
+
+    /// Connects an unauthenticated CalDAV calendar (at `url`) for the user.
+    fn connect_unauthenticated(user_id: i32, url: &str, c: &mut DatabaseConnection) {
+        let calendar_id = diesel::insert_into(calendar::table)
+            .values(NewCalendar {
+                calendar_type: CalendarType::CalDavUnauthenticated.into(),
+                user_id,
+            })
+            .returning(calendar::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        diesel::insert_into(caldav_unauthenticated::table)
+            .values(NewCalDavUnauthenticated { calendar_id, url })
+            .execute(c)
+            .unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_one_unreachable_calendar_does_not_stop_the_class_being_scheduled() {
+        let server = caldav_server(false, BASIC).await;
+        let client = crate::utils::client().await;
+        let conn = Database::get_one(client.rocket()).await.unwrap();
+        let url = format!("{}/calendars/student/personal", server.uri());
+        let class_id = conn
+            .run(move |c| {
+                let student_id = setup_task(c);
+                connect_unauthenticated(student_id, &url, c);
+                let (class_id, teacher_id) = class_teacher::table
+                    .select((class_teacher::class_id, class_teacher::user_id))
+                    .first::<(i32, i32)>(c)
+                    .unwrap();
+                // the teacher is also in the class as a student, but nothing is listening where
+                // their calendar is meant to be
+                diesel::insert_into(class_student::table)
+                    .values(NewClassStudent {
+                        user_id: teacher_id,
+                        class_id,
+                    })
+                    .execute(c)
+                    .unwrap();
+                connect_unauthenticated(teacher_id, "http://127.0.0.1:9/calendars/teacher", c);
+                class_id
+            })
+            .await;
+
+        schedule_class(class_id, &conn).await.unwrap();
+        assert_eq!(put_requests(&server.received_requests().await.unwrap()), 1);
+    }
+
+    #[rocket::async_test]
+    async fn test_schedule_into_caldav() {
+        let server = caldav_server(true, BASIC).await;
+        let client = crate::utils::client().await;
+        let conn = Database::get_one(client.rocket()).await.unwrap();
+        let student_id = setup_caldav(
+            format!("{}/calendars/student/personal", server.uri()),
+            &conn,
+        )
+        .await;
+
+        let report = two_week_schedule(student_id, &conn).await.unwrap();
+        assert!(report.is_feasible());
+        assert_eq!(put_requests(&server.received_requests().await.unwrap()), 1);
+        assert!(conn
+            .run(|c| {
+                caldav::table
+                    .select(caldav::lovelace_calendar_url)
+                    .first::<Option<String>>(c)
+                    .unwrap()
+            })
+            .await
+            .is_some());
+    }
+
+    #[rocket::async_test]
+    async fn test_schedule_into_caldav_with_digest_authentication() {
+        let server = caldav_server(
+            true,
+            "Digest realm=\"test\", qop=\"auth\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+            opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .await;
+        let client = crate::utils::client().await;
+        let conn = Database::get_one(client.rocket()).await.unwrap();
+        let student_id = setup_caldav(
+            format!("{}/calendars/student/personal", server.uri()),
+            &conn,
+        )
+        .await;
+
+        two_week_schedule(student_id, &conn).await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(put_requests(&requests), 1);
+        // each of the three clients (for the calendar home, the Lovelace calendar and the
+        // student's own calendar) only asks the server how to authenticate once
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|request| request.method.to_string() == "GET")
+                .count(),
+            3
+        );
+        for request in requests
+            .iter()
+            .filter(|request| request.method.to_string() != "GET")
+        {
+            let authorization = authorization(request);
+            assert!(authorization.starts_with("Digest "));
+            assert!(authorization.contains(&format!("uri=\"{}\"", request.url.path())));
+        }
+        // the nonce is reused (by the Lovelace calendar's client, which searches the calendar
+        // before adding the work session to it)
+        assert!(requests
+            .iter()
+            .filter(|request| request.method.to_string() == "PUT")
+            .all(|request| authorization(request).contains("nc=00000002")));
+    }
+}
@@ -0,0 +1,76 @@
+//! Scheduling into Google Calendar (see [`crate::calendar::connect::gcal`]).
+
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use prospero::client::DavClient;
+
+use super::SchedulingError;
+use crate::{
+    calendar::connect::gcal::refresh_access_token, db::Database, models::calendar::GoogleCalendar,
+    schema::google_calendar,
+};
+
+/// Access tokens which will expire within this many seconds are refreshed before they are used
+/// (so that they don't stop working partway through scheduling).
+const EXPIRY_MARGIN_SECONDS: i64 = 60;
+
+/// Returns the clients for (respectively) the Lovelace calendar and the user's own calendar.
+///
+/// The access token is refreshed first if it has expired (or is about to), or if `refresh` is set
+/// (e.g. because Google has stopped accepting it).
+pub async fn google_calendar_clients(
+    calendar_id: i32,
+    refresh: bool,
+    conn: &Database,
+) -> Result<(DavClient, DavClient), SchedulingError> {
+    let gcal = conn
+        .run(move |c| {
+            google_calendar::table
+                .filter(google_calendar::calendar_id.eq(calendar_id))
+                .first::<GoogleCalendar>(c)
+        })
+        .await?;
+    let expired = gcal
+        .access_token_expires
+        .map(|expires| {
+            expires <= (Utc::now() + Duration::seconds(EXPIRY_MARGIN_SECONDS)).naive_utc()
+        })
+        .unwrap_or(true);
+    let gcal = if refresh || expired {
+        refresh_access_token(gcal, conn).await?
+    } else {
+        gcal
+    };
+
+    cfg_if! {
+        if #[cfg(test)] {
+            let user_calendar_url = "http://localhost:8080/user/calendars/calendar".to_string();
+        } else {
+            let user_calendar_url = format!("https://apidata.googleusercontent.com/caldav/v2/{}/events", gcal.calendar_id);
+        }
+    };
+
+    cfg_if! {
+        if #[cfg(test)] {
+            Ok((
+                DavClient::new_unauthenticated(
+                    gcal.lovelace_calendar_id,
+                ),
+                DavClient::new_unauthenticated(
+                    user_calendar_url,
+                ),
+            ))
+        } else {
+            Ok((
+                DavClient::new_oauth(
+                    gcal.lovelace_calendar_id,
+                    gcal.access_token.clone(),
+                ),
+                DavClient::new_oauth(
+                    user_calendar_url,
+                    gcal.access_token,
+                ),
+            ))
+        }
+    }
+}
@@ -4,25 +4,38 @@
 //!   1. Pick out all the events which are happening over the next two weeks
 //!   2. Work out all the times during which the user is busy
 //!   3. Work out all the tasks that the user has
-//!   4. Make sure that there is actually enough time to do all the work
-//!   5. Start filling in the tasks (currently we're using a shortest-task first system)
-//!
-//! NOTE: (because there's only one of me and thousands of lines of code) we are currently assuming
-//! that all tasks take 25 minutes, which is obviously not correct
+//!   4. Take out any time which falls in the holidays of the user's institutions, or outside of
+//!      the hours they're happy to study during (and put in breaks, and a limit on how much work
+//!      is scheduled each day; see [`crate::calendar::preferences`] and [`windows`])
+//!   5. Make sure that there is actually enough time to do all the work (if there isn't, the
+//!      student is warned about the tasks they might not finish in time; see
+//!      [`InfeasibilityReport`])
+//!   6. Split each task into work sessions (based on how long the student expects it to take; see
+//!      [`sessions`])
+//!   7. Start filling in the sessions (tasks which are due soonest are scheduled first)
+
+pub mod caldav;
+pub mod gcal;
+pub mod sessions;
+pub mod windows;
 
 use crate::models::{ClassAsynchronousTask, StudentClassAsynchronousTask};
 use crate::{
-    db::Database,
+    calendar::preferences::{user_preferences, Preferences},
+    class::tasks::asynchronous::effective_due_date,
+    db::{Database, DatabaseConnection},
+    institution::academic::user_holiday_periods,
     models::{
-        calendar::{parse_calendar_type, GoogleCalendar},
-        User,
+        calendar::{parse_calendar_type, CalendarType},
+        NewNotification, NewOverloadWarning, User,
     },
+    notifications::NotificationPriority,
     schema::{
-        calendar, class, class_asynchronous_task, class_student, google_calendar,
-        student_class_asynchronous_task, users,
+        calendar, class, class_asynchronous_task, class_student, class_teacher, notifications,
+        overload_warning, student_class_asynchronous_task, users,
     },
 };
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use diesel::prelude::*;
 use prospero::{
     client::DavClient,
@@ -30,7 +43,7 @@ use prospero::{
     event::EventPointer,
     icalendar::{Component, Event},
 };
-use std::ops::Add;
+use sessions::{plan_sessions, Plan, WorkItem, SESSION_LENGTHS};
 use thiserror::Error as ThisError;
 use uuid::Uuid;
 
@@ -40,6 +53,10 @@ pub enum SchedulingError {
     DatabaseError(diesel::result::Error),
     #[error("scheduling error")]
     SchedulingError(prospero::error::CalDavError),
+    #[error("couldn't refresh the access token")]
+    TokenRefresh(reqwest::Error),
+    #[error("the `{0}` environment variable has not been set")]
+    MissingConfiguration(&'static str),
 }
 
 impl From<CalDavError> for SchedulingError {
@@ -54,41 +71,258 @@ impl From<diesel::result::Error> for SchedulingError {
     }
 }
 
-struct FreeSlot {
+/// A period during which work can be scheduled.
+pub(crate) struct FreeSlot {
     start: DateTime<Utc>,
     end: DateTime<Utc>,
 }
 
-/// Maps use events to free time.
-async fn map_user_events_to_free_time(events: Vec<EventPointer>) -> Vec<FreeSlot> {
-    // this shouldn't really ever happen (because otherwise people are going to start getting
-    // scheduled to do things in the middle of the night)
-    if events.is_empty() {
-        return vec![FreeSlot {
-            start: Utc::now(),
-            end: Utc::now() + Duration::days(14),
-        }];
+/// Works out when the user is busy (including the buffer they want around each of their events).
+async fn busy_periods(
+    events: Vec<EventPointer>,
+    buffer: Duration,
+) -> Result<Vec<(DateTime<Utc>, DateTime<Utc>)>, SchedulingError> {
+    let mut busy = vec![];
+    for event in events {
+        busy.push((
+            event.start_time().await? - buffer,
+            event.end_time().await? + buffer,
+        ));
     }
-    let pad_start = Utc::now();
-    let mut free_slots = vec![FreeSlot {
-        start: pad_start,
-        end: events.get(0).unwrap().start_time().await.unwrap(),
-    }];
-    for (i, event) in events.iter().enumerate() {
-        if i == (events.len() - 1) {
-            break;
+    Ok(busy)
+}
+
+/// Works out when work can be scheduled between `from` and `to`: the time which isn't taken up by
+/// the user's events or their institutions' holidays, inside their study hours, with breaks and
+/// no more work on any one day than they want to do.
+fn available_time(
+    busy: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+    holidays: &[(DateTime<Utc>, DateTime<Utc>)],
+    preferences: &Preferences,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<FreeSlot> {
+    let free_slots = avoid_holidays(windows::free_time(busy, from, to), holidays);
+    let free_slots = windows::intersect(
+        free_slots,
+        &windows::study_windows(&preferences.hours, preferences.timezone, from, to),
+    );
+    let free_slots = match preferences.break_every_minutes {
+        Some(every) => windows::with_breaks(
+            free_slots,
+            Duration::minutes(every as i64),
+            Duration::minutes(preferences.break_minutes as i64),
+        ),
+        None => free_slots,
+    };
+    windows::limit_per_day(
+        free_slots,
+        Duration::minutes(preferences.max_minutes_per_day as i64),
+        preferences.timezone,
+    )
+}
+
+/// Removes the holidays (given as `(start, end)` pairs) from the free slots, splitting slots which
+/// have a holiday in the middle of them.
+fn avoid_holidays(
+    free_slots: Vec<FreeSlot>,
+    holidays: &[(DateTime<Utc>, DateTime<Utc>)],
+) -> Vec<FreeSlot> {
+    holidays
+        .iter()
+        .fold(free_slots, |free_slots, (start, end)| {
+            free_slots
+                .into_iter()
+                .flat_map(|slot| {
+                    let mut remaining = vec![];
+                    if slot.start < *start {
+                        remaining.push(FreeSlot {
+                            start: slot.start,
+                            end: slot.end.min(*start),
+                        });
+                    }
+                    if slot.end > *end {
+                        remaining.push(FreeSlot {
+                            start: slot.start.max(*end),
+                            end: slot.end,
+                        });
+                    }
+                    remaining
+                })
+                .collect()
+        })
+}
+
+/// A task which the student doesn't have enough free time to finish before it's due.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TaskAtRisk {
+    pub task_id: i32,
+    pub class_id: i32,
+    pub title: String,
+    pub due_date: NaiveDateTime,
+    /// How long (in minutes) the task is expected to take.
+    pub estimated_minutes: i32,
+    /// How much of the task (in minutes) couldn't be fitted in before it's due.
+    pub unscheduled_minutes: i32,
+}
+
+/// The work which couldn't be scheduled for a student (if `at_risk` is empty, everything fitted).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InfeasibilityReport {
+    pub user_id: i32,
+    /// In the order the tasks are due.
+    pub at_risk: Vec<TaskAtRisk>,
+}
+
+impl InfeasibilityReport {
+    fn new(
+        user_id: i32,
+        tasks: &[(ClassAsynchronousTask, StudentClassAsynchronousTask)],
+        plan: &Plan,
+    ) -> Self {
+        Self {
+            user_id,
+            at_risk: plan
+                .shortfalls
+                .iter()
+                .map(|shortfall| {
+                    let (task, student_task) = &tasks[shortfall.task];
+                    TaskAtRisk {
+                        task_id: task.id,
+                        class_id: task.class_id,
+                        title: task.title.clone(),
+                        due_date: student_task.effective_due_date(task),
+                        estimated_minutes: student_task.estimated_minutes(task),
+                        unscheduled_minutes: shortfall.minutes as i32,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_feasible(&self) -> bool {
+        self.at_risk.is_empty()
+    }
+
+    /// Warns the student about each of the tasks at risk, as well as the teachers of any classes
+    /// which have asked to be told. Nobody is warned about the same task again while they still
+    /// have an unread warning about it (because schedules are recomputed quite often).
+    pub fn notify(&self, c: &DatabaseConnection) -> QueryResult<()> {
+        let username = users::table
+            .find(self.user_id)
+            .select(users::username)
+            .first::<String>(c)?;
+        for task in &self.at_risk {
+            let due = task.due_date.format("%Y-%m-%d %H:%M");
+            warn_once(
+                self.user_id,
+                self.user_id,
+                task.task_id,
+                "You might not finish your work in time",
+                &format!(
+                    "There isn't enough free time in your calendar to finish \"{}\" before it's \
+                    due ({}): about {} of the {} minutes it should take couldn't be fitted in.",
+                    task.title, due, task.unscheduled_minutes, task.estimated_minutes
+                ),
+                c,
+            )?;
+            let teachers = class::table
+                .find(task.class_id)
+                .filter(class::notify_teachers_of_overload)
+                .inner_join(class_teacher::table)
+                .select(class_teacher::user_id)
+                .load::<i32>(c)?;
+            for teacher in teachers {
+                warn_once(
+                    teacher,
+                    self.user_id,
+                    task.task_id,
+                    "A student might not finish their work in time",
+                    &format!(
+                        "{} doesn't have enough free time in their calendar to finish \"{}\" \
+                        before it's due ({}): about {} of the {} minutes it should take couldn't \
+                        be fitted in.",
+                        username, task.title, due, task.unscheduled_minutes, task.estimated_minutes
+                    ),
+                    c,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends `user_id` a warning about `student_id`'s work on the task, unless they still have an unread
+/// warning about it. (Warnings are told apart by the task and the student rather than by their
+/// text, which changes whenever the amount of free time does.)
+fn warn_once(
+    user_id: i32,
+    student_id: i32,
+    task_id: i32,
+    title: &str,
+    message: &str,
+    c: &DatabaseConnection,
+) -> QueryResult<()> {
+    let already_warned = diesel::dsl::select(diesel::dsl::exists(
+        overload_warning::table
+            .inner_join(notifications::table)
+            .filter(notifications::user_id.eq(user_id))
+            .filter(notifications::read.eq(false))
+            .filter(overload_warning::class_asynchronous_task_id.eq(task_id))
+            .filter(overload_warning::student_id.eq(student_id)),
+    ))
+    .get_result::<bool>(c)?;
+    if already_warned {
+        return Ok(());
+    }
+    let notification_id = diesel::insert_into(notifications::table)
+        .values(NewNotification::new(
+            title,
+            message,
+            Utc::now().naive_utc(),
+            NotificationPriority::Warning,
+            user_id,
+            false,
+        ))
+        .returning(notifications::id)
+        .get_result::<i32>(c)?;
+    diesel::insert_into(overload_warning::table)
+        .values(NewOverloadWarning {
+            notification_id,
+            class_asynchronous_task_id: task_id,
+            student_id,
+        })
+        .execute(c)
+        .map(drop)
+}
+
+/// Returns the clients for (respectively) the calendar which work is scheduled into and the user's
+/// own calendar. If `refresh` is set, any access tokens are refreshed first.
+async fn calendar_clients(
+    r#type: CalendarType,
+    calendar_id: i32,
+    refresh: bool,
+    conn: &Database,
+) -> Result<(DavClient, DavClient), SchedulingError> {
+    match r#type {
+        CalendarType::GoogleCalendar => {
+            gcal::google_calendar_clients(calendar_id, refresh, conn).await
+        }
+        CalendarType::CalDav => caldav::caldav_clients(calendar_id, conn).await,
+        CalendarType::CalDavUnauthenticated => {
+            caldav::unauthenticated_caldav_clients(calendar_id, conn).await
         }
-        let start = event.end_time().await.unwrap();
-        let end = events.get(i + 1).unwrap().start_time().await.unwrap();
-        free_slots.push(FreeSlot { start, end })
     }
-    free_slots
 }
 
-/// Creates a schedule for the next two weeks.
+/// Creates a schedule for the next two weeks, and warns the student (see
+/// [`InfeasibilityReport::notify`]) about any work which can't be fitted in before it's due.
 ///
 /// Schedules cannot be created for people who have not connected a calendar.
-pub async fn two_week_schedule(user_id: i32, conn: &Database) -> Result<(), SchedulingError> {
+pub async fn two_week_schedule(
+    user_id: i32,
+    conn: &Database,
+) -> Result<InfeasibilityReport, SchedulingError> {
     let (_user, calendar) = conn
         .run(move |c| {
             users::table
@@ -98,79 +332,43 @@ pub async fn two_week_schedule(user_id: i32, conn: &Database) -> Result<(), Sche
                 .first::<(User, crate::models::calendar::Calendar)>(c)
         })
         .await?;
-    let r#type = calendar.calendar_type;
-    let (lovelace_client, user_client) = match parse_calendar_type(r#type) {
-        crate::models::calendar::CalendarType::GoogleCalendar => {
-            let gcal = conn
-                .run(move |c| {
-                    google_calendar::table
-                        .filter(google_calendar::id.eq(calendar.id))
-                        .first::<GoogleCalendar>(c)
-                })
-                .await?;
-
-            cfg_if! {
-                if #[cfg(test)] {
-                    let user_calendar_url = "http://localhost:8080/user/calendars/calendar".to_string();
-                } else {
-                    let user_calendar_url = format!("https://apidata.googleusercontent.com/caldav/v2/{}/events", gcal.calendar_id);
-                }
-            };
-
-            cfg_if! {
-                if #[cfg(test)] {
-                    (
-                        DavClient::new_unauthenticated(
-                            gcal.lovelace_calendar_id,
-                        ),
-                        DavClient::new_unauthenticated(
-                            user_calendar_url,
-                        ),
-                    )
-                } else {
-                    (
-                        DavClient::new_oauth(
-                            gcal.lovelace_calendar_id,
-                            gcal.access_token.clone(),
-                        ),
-                        DavClient::new_oauth(
-                            user_calendar_url,
-                            gcal.access_token,
-                        ),
-                    )
-                }
-            }
-        }
-        crate::models::calendar::CalendarType::CalDav => {
-            todo!()
-        }
-        crate::models::calendar::CalendarType::CalDavUnauthenticated => {
-            todo!()
+    let r#type = parse_calendar_type(calendar.calendar_type);
+    let (from, to) = (Utc::now(), Utc::now() + Duration::days(14));
+    let (mut lovelace_client, mut user_client) =
+        calendar_clients(r#type, calendar.id, false, conn).await?;
+    let user_events = match user_client.calendar().date_search(from, to).await {
+        // Google can stop accepting an access token before it was meant to expire (e.g. if it
+        // has been revoked), in which case we get a new one and try again
+        Err(CalDavError::Unauthorized) if matches!(r#type, CalendarType::GoogleCalendar) => {
+            let clients = calendar_clients(r#type, calendar.id, true, conn).await?;
+            lovelace_client = clients.0;
+            user_client = clients.1;
+            user_client.calendar().date_search(from, to).await?
         }
+        res => res?,
     };
-
     let lovelace_controller = lovelace_client.calendar();
-    let user_controller = user_client.calendar();
-    let user_events = user_controller
-        .date_search(
-            chrono::Utc::now(),
-            chrono::Utc::now() + chrono::Duration::days(14),
-        )
-        .await
-        .unwrap();
-    let set_events = lovelace_controller
-        .date_search(Utc::now(), Utc::now() + Duration::days(14))
-        .await?;
-    let mut tasks = conn
+    let set_events = lovelace_controller.date_search(from, to).await?;
+    let tasks = conn
         .run(move |c| {
             student_class_asynchronous_task::table
                 .inner_join(class_student::table.inner_join(users::table))
                 .inner_join(class_asynchronous_task::table)
                 .filter(users::id.eq(user_id))
-                .filter(class_asynchronous_task::due_date.ge(chrono::Utc::now().naive_utc()))
+                .filter(student_class_asynchronous_task::completed.eq(false))
                 .filter(
-                    class_asynchronous_task::due_date
-                        .le((chrono::Utc::now() + Duration::days(14)).naive_utc()),
+                    effective_due_date(
+                        student_class_asynchronous_task::due_date,
+                        class_asynchronous_task::due_date,
+                    )
+                    .ge(chrono::Utc::now().naive_utc()),
+                )
+                .filter(
+                    effective_due_date(
+                        student_class_asynchronous_task::due_date,
+                        class_asynchronous_task::due_date,
+                    )
+                    .le((chrono::Utc::now() + Duration::days(14)).naive_utc()),
                 )
                 .select((
                     class_asynchronous_task::all_columns,
@@ -178,18 +376,32 @@ pub async fn two_week_schedule(user_id: i32, conn: &Database) -> Result<(), Sche
                 ))
                 .load::<(ClassAsynchronousTask, StudentClassAsynchronousTask)>(c)
         })
-        .await
-        .unwrap();
-    let free_slots = map_user_events_to_free_time(user_events).await;
-
-    let mut events_to_add = vec![];
+        .await?;
+    let holidays = conn
+        .run(move |c| user_holiday_periods(user_id, from, to, c))
+        .await?;
+    let preferences = conn.run(move |c| user_preferences(user_id, c)).await?;
+    let busy = busy_periods(
+        user_events,
+        Duration::minutes(preferences.buffer_minutes as i64),
+    )
+    .await?;
+    let free_slots = available_time(busy, &holidays, &preferences, from, to);
 
-    for slot in free_slots {
-        if tasks.is_empty() {
-            break;
-        }
-        fill_slot(slot, &mut tasks, &mut events_to_add);
-    }
+    let work = tasks
+        .iter()
+        .map(|(task, student_task)| WorkItem {
+            due: DateTime::from_utc(student_task.effective_due_date(task), Utc),
+            minutes: student_task.estimated_minutes(task) as i64,
+        })
+        .collect::<Vec<_>>();
+    let plan = plan_sessions(&work, free_slots, *SESSION_LENGTHS);
+    let report = InfeasibilityReport::new(user_id, &tasks, &plan);
+    let events_to_add = plan
+        .sessions
+        .iter()
+        .map(|session| session_event(&tasks[session.task].0, session))
+        .collect::<Vec<_>>();
 
     for event in set_events {
         event.delete().await?;
@@ -199,67 +411,238 @@ pub async fn two_week_schedule(user_id: i32, conn: &Database) -> Result<(), Sche
         lovelace_controller.save_event(event).await?;
     }
 
-    Ok(())
+    if !report.is_feasible() {
+        let to_notify = report.clone();
+        conn.run(move |c| to_notify.notify(c)).await?;
+    }
+
+    Ok(report)
 }
 
-/// Recursively occupies the provided time slot with as many events as possible.
-fn fill_slot(
-    slot: FreeSlot,
-    tasks: &mut Vec<(ClassAsynchronousTask, StudentClassAsynchronousTask)>,
-    events_to_add: &mut Vec<Event>,
-) {
-    if tasks.is_empty() {
-        return;
-    }
-    let len = slot.end - slot.start;
-    if len > Duration::minutes(25) {
-        let (task, _) = tasks.pop().unwrap();
-        events_to_add.push(
-            Event::new()
-                .uid(&Uuid::new_v4().to_string())
-                .starts(slot.start)
-                .ends(slot.start.add(Duration::minutes(25)))
-                .summary(
-                    format!(
-                        "Task title: {} Task description: {}",
-                        task.title, task.description
-                    )
-                    .chars()
-                    .map(|char| if char == '\n' { ' ' } else { char })
-                    .collect::<String>()
-                    .as_str(),
-                )
-                .done(),
-        );
-        fill_slot(
-            FreeSlot {
-                start: slot.start + Duration::minutes(25),
-                end: slot.end,
-            },
-            tasks,
-            events_to_add,
-        );
-    }
+/// The calendar event for a work session.
+fn session_event(task: &ClassAsynchronousTask, session: &sessions::Session) -> Event {
+    let part = if session.parts > 1 {
+        format!(" (part {} of {})", session.part, session.parts)
+    } else {
+        String::new()
+    };
+    Event::new()
+        .uid(&Uuid::new_v4().to_string())
+        .starts(session.start)
+        .ends(session.end)
+        .summary(
+            format!(
+                "Task title: {}{} Task description: {}",
+                task.title, part, task.description
+            )
+            .chars()
+            .map(|char| if char == '\n' { ' ' } else { char })
+            .collect::<String>()
+            .as_str(),
+        )
+        .done()
 }
 
-/// Computes the schedule for all users in a class.
+/// Computes the schedule for all users in a class (who have connected a calendar). If somebody's
+/// schedule can't be computed (e.g. because their calendar server is down), everybody else's still
+/// is.
 pub async fn schedule_class(class_id: i32, conn: &Database) -> Result<(), SchedulingError> {
-    match conn
+    let users = conn
         .run(move |c| {
             class::table
                 .filter(class::id.eq(class_id))
-                .inner_join(class_student::table.inner_join(users::table))
+                .inner_join(
+                    class_student::table.inner_join(users::table.inner_join(calendar::table)),
+                )
                 .select(users::id)
+                .distinct()
                 .load::<i32>(c)
         })
-        .await
-    {
-        Ok(users) => {
-            for user_id in users {
-                two_week_schedule(user_id, conn).await?;
-            }
-            Ok(())
+        .await?;
+    for user_id in users {
+        if let Err(e) = two_week_schedule(user_id, conn).await {
+            error!("could not schedule work for user {}: {:#?}", user_id, e);
         }
-        Err(e) => Err(SchedulingError::DatabaseError(e)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_scheduler {
+    use chrono::{TimeZone, Utc};
+    use diesel::prelude::*;
+    use rocket::http::ContentType;
+
+    use super::{avoid_holidays, FreeSlot, InfeasibilityReport, TaskAtRisk};
+    use crate::{
+        db::Database,
+        institution::test_ctx::{setup_env, TEACHER_PASSWORD, TEACHER_USERNAME},
+        models::{NewClass, NewClassAsynchronousTask, NewClassTeacher},
+        schema::{class, class_asynchronous_task, class_teacher, notifications},
+        utils::{client, login_user},
+    };
+
+    #[test]
+    fn test_avoid_holidays() {
+        let day = |day| Utc.ymd(2021, 12, day).and_hms(0, 0, 0);
+        let slots = avoid_holidays(
+            vec![
+                FreeSlot {
+                    start: day(1),
+                    end: day(10),
+                },
+                FreeSlot {
+                    start: day(20),
+                    end: day(22),
+                },
+            ],
+            &[(day(3), day(5)), (day(18), day(25))],
+        );
+        assert_eq!(
+            slots
+                .iter()
+                .map(|slot| (slot.start, slot.end))
+                .collect::<Vec<_>>(),
+            vec![(day(1), day(3)), (day(5), day(10))]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_overload_warnings() {
+        let client = client().await;
+        let (teacher, student, class_id, task_id) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                let (_, teacher, student, _, _) = setup_env(c);
+                let class_id = diesel::insert_into(class::table)
+                    .values(NewClass {
+                        name: "Chemistry",
+                        description: "",
+                        created: Utc::now().naive_utc(),
+                        code: "chemistry",
+                        institution_id: None,
+                        student_group_id: None,
+                        academic_term_id: None,
+                    })
+                    .returning(class::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let class_teacher_id = diesel::insert_into(class_teacher::table)
+                    .values(NewClassTeacher {
+                        user_id: teacher,
+                        class_id,
+                    })
+                    .returning(class_teacher::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let task_id = diesel::insert_into(class_asynchronous_task::table)
+                    .values(NewClassAsynchronousTask {
+                        title: "Titration write-up",
+                        description: "",
+                        created: Utc::now().naive_utc(),
+                        due_date: Utc.ymd(2021, 9, 1).and_hms(9, 0, 0).naive_utc(),
+                        class_teacher_id,
+                        class_id,
+                        resubmit_until: None,
+                        estimated_minutes: 120,
+                    })
+                    .returning(class_asynchronous_task::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                (teacher, student, class_id, task_id)
+            })
+            .await;
+        let report = InfeasibilityReport {
+            user_id: student,
+            at_risk: vec![TaskAtRisk {
+                task_id,
+                class_id,
+                title: "Titration write-up".to_string(),
+                due_date: Utc.ymd(2021, 9, 1).and_hms(9, 0, 0).naive_utc(),
+                estimated_minutes: 120,
+                unscheduled_minutes: 45,
+            }],
+        };
+        let warnings = |user_id: i32| {
+            let client = &client;
+            async move {
+                Database::get_one(client.rocket())
+                    .await
+                    .unwrap()
+                    .run(move |c| {
+                        notifications::table
+                            .filter(notifications::user_id.eq(user_id))
+                            .select(notifications::contents)
+                            .load::<String>(c)
+                            .unwrap()
+                    })
+                    .await
+            }
+        };
+
+        // teachers aren't told unless they ask to be
+        let to_notify = report.clone();
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| to_notify.notify(c))
+            .await
+            .unwrap();
+        let student_warnings = warnings(student).await;
+        assert_eq!(student_warnings.len(), 1);
+        assert!(student_warnings[0].contains("\"Titration write-up\""));
+        assert!(student_warnings[0].contains("about 45 of the 120 minutes"));
+        assert!(warnings(teacher).await.is_empty());
+
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .post(format!("/class/{}/settings", class_id))
+            .header(ContentType::Form)
+            .body("notify_teachers_of_overload=true")
+            .dispatch()
+            .await;
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("Saved your settings"));
+
+        // the student still has an unread warning about the task, so they aren't sent another
+        // (even though how much of it can't be fitted in has changed since)
+        let mut report = report;
+        report.at_risk[0].unscheduled_minutes = 50;
+        let to_notify = report.clone();
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| to_notify.notify(c))
+            .await
+            .unwrap();
+        assert_eq!(warnings(student).await.len(), 1);
+        let teacher_warnings = warnings(teacher).await;
+        assert_eq!(teacher_warnings.len(), 1);
+        assert!(teacher_warnings[0].contains("before it's due (2021-09-01 09:00)"));
+
+        // once they've read it they're warned again
+        report.at_risk[0].unscheduled_minutes = 55;
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::update(notifications::table.filter(notifications::user_id.eq(student)))
+                    .set(notifications::read.eq(true))
+                    .execute(c)
+                    .unwrap();
+                report.notify(c)
+            })
+            .await
+            .unwrap();
+        let student_warnings = warnings(student).await;
+        assert_eq!(student_warnings.len(), 2);
+        assert!(student_warnings
+            .iter()
+            .any(|warning| warning.contains("about 55 of the 120 minutes")));
+        assert_eq!(warnings(teacher).await.len(), 1);
     }
 }
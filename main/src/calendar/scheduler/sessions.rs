@@ -0,0 +1,384 @@
+//! Splitting tasks into work sessions, and fitting those sessions into the user's free time.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::FreeSlot;
+
+const DEFAULT_MIN_SESSION_MINUTES: i64 = 25;
+const DEFAULT_MAX_SESSION_MINUTES: i64 = 90;
+
+/// The shortest and longest work sessions (in minutes) which the scheduler will create.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionLengths {
+    pub min: i64,
+    pub max: i64,
+}
+
+fn env_minutes(name: &str, default: i64) -> i64 {
+    match std::env::var(name) {
+        Ok(value) => match value.parse() {
+            Ok(minutes) if minutes > 0 => minutes,
+            _ => panic!(
+                "the `{}` environment variable must be a whole number above zero",
+                name
+            ),
+        },
+        Err(_) => default,
+    }
+}
+
+lazy_static! {
+    /// Configured with the `MIN_SESSION_MINUTES` and `MAX_SESSION_MINUTES` environment variables.
+    pub static ref SESSION_LENGTHS: SessionLengths = {
+        let lengths = SessionLengths {
+            min: env_minutes("MIN_SESSION_MINUTES", DEFAULT_MIN_SESSION_MINUTES),
+            max: env_minutes("MAX_SESSION_MINUTES", DEFAULT_MAX_SESSION_MINUTES),
+        };
+        assert!(
+            lengths.min <= lengths.max,
+            "`MIN_SESSION_MINUTES` must not be more than `MAX_SESSION_MINUTES`"
+        );
+        lengths
+    };
+}
+
+/// How far ahead (in minutes) work is scheduled.
+const SCHEDULE_MINUTES: i64 = 14 * 24 * 60;
+
+/// The most sessions which a task is split into: any more than this couldn't all be fitted into
+/// the time which is scheduled (because every session is at least `lengths.min` long).
+fn max_sessions(lengths: SessionLengths) -> i64 {
+    (SCHEDULE_MINUTES / lengths.min).max(1)
+}
+
+/// Splits `minutes` of work into as few sessions as possible, of (roughly) equal length, which are
+/// no longer than `lengths.max`. Sessions which would be shorter than `lengths.min` are made longer
+/// (so short tasks get a whole session to themselves). Work is never split into more sessions than
+/// could be scheduled (see [`max_sessions`]); if there is more work than that, the sessions are
+/// made longer instead.
+pub fn split_into_sessions(minutes: i64, lengths: SessionLengths) -> Vec<i64> {
+    let minutes = minutes.max(1);
+    let parts = ((minutes + lengths.max - 1) / lengths.max).min(max_sessions(lengths));
+    let (length, remainder) = (minutes / parts, minutes % parts);
+    (0..parts)
+        .map(|part| (length + if part < remainder { 1 } else { 0 }).max(lengths.min))
+        .collect()
+}
+
+/// A task which needs to be scheduled.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkItem {
+    pub due: DateTime<Utc>,
+    pub minutes: i64,
+}
+
+/// Time set aside to work on part of a task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// The index (in the list passed to [`plan_sessions`]) of the task to work on.
+    pub task: usize,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Which part of the task this is (starting from one).
+    pub part: usize,
+    /// How many parts the task has been split into.
+    pub parts: usize,
+}
+
+/// Some of a task which couldn't be fitted in before it is due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shortfall {
+    /// The index (in the list passed to [`plan_sessions`]) of the task.
+    pub task: usize,
+    /// How much of the work (in minutes) couldn't be scheduled.
+    pub minutes: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    /// The sessions, in the order they happen in.
+    pub sessions: Vec<Session>,
+    /// The tasks which there isn't enough time to finish before they are due.
+    pub shortfalls: Vec<Shortfall>,
+}
+
+/// How many minutes of the slot can be used for work which is due at `due`.
+fn room(slot: &FreeSlot, due: DateTime<Utc>) -> i64 {
+    (slot.end.min(due) - slot.start).num_minutes()
+}
+
+/// Fits the tasks into the free slots (which must be in order, and not overlap).
+///
+/// This uses earliest-deadline-first scheduling: the task which is due soonest is scheduled first,
+/// and each session has to finish before its task is due. If a session doesn't fit into any of
+/// the free slots it is split up to use whatever time is left, so (apart from where sessions have
+/// to be made shorter than `lengths.min`) every task gets done on time whenever that is possible.
+/// Where there is a choice, the sessions for a task are spread out over different free slots
+/// (rather than being done all at once).
+pub fn plan_sessions(
+    tasks: &[WorkItem],
+    mut free_slots: Vec<FreeSlot>,
+    lengths: SessionLengths,
+) -> Plan {
+    let mut order = (0..tasks.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| tasks[i].due);
+    let mut plan = Plan::default();
+    for i in order {
+        let due = tasks[i].due;
+        let mut pieces = split_into_sessions(tasks[i].minutes, lengths)
+            .into_iter()
+            .collect::<VecDeque<_>>();
+        let mut placed = vec![];
+        let mut scheduled = 0;
+        let mut last_slot = None;
+        while let Some(length) = pieces.pop_front() {
+            let after_last = |j: usize| last_slot.map(|last| j > last).unwrap_or(true);
+            let whole = free_slots
+                .iter()
+                .enumerate()
+                .position(|(j, slot)| after_last(j) && room(slot, due) >= length)
+                .or_else(|| free_slots.iter().position(|slot| room(slot, due) >= length));
+            let (j, minutes) = match whole {
+                Some(j) => (j, length),
+                None => match free_slots
+                    .iter()
+                    .position(|slot| room(slot, due) >= lengths.min.min(length))
+                {
+                    Some(j) => {
+                        let minutes = room(&free_slots[j], due);
+                        // the rest is done along with the next session (so that it doesn't end
+                        // up as a session which is too short to be useful)
+                        match pieces.front_mut() {
+                            Some(next) => *next += length - minutes,
+                            None => pieces.push_front(length - minutes),
+                        }
+                        (j, minutes)
+                    }
+                    None => {
+                        // short sessions are made longer, so the sessions can add up to more than
+                        // the task is estimated to take
+                        let minutes = (length + pieces.iter().sum::<i64>())
+                            .min(tasks[i].minutes.max(1) - scheduled);
+                        if minutes > 0 {
+                            plan.shortfalls.push(Shortfall { task: i, minutes });
+                        }
+                        break;
+                    }
+                },
+            };
+            let start = free_slots[j].start;
+            free_slots[j].start = start + Duration::minutes(minutes);
+            scheduled += minutes;
+            placed.push((start, free_slots[j].start));
+            last_slot = Some(j);
+        }
+        placed.sort();
+        let parts = placed.len();
+        plan.sessions.extend(
+            placed
+                .into_iter()
+                .enumerate()
+                .map(|(part, (start, end))| Session {
+                    task: i,
+                    start,
+                    end,
+                    part: part + 1,
+                    parts,
+                }),
+        );
+    }
+    plan.sessions.sort_by_key(|session| session.start);
+    plan.shortfalls
+        .sort_by_key(|shortfall| tasks[shortfall.task].due);
+    plan
+}
+
+#[cfg(test)]
+mod test_work_sessions {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{plan_sessions, split_into_sessions, SessionLengths, Shortfall, WorkItem};
+    use crate::calendar::scheduler::FreeSlot;
+
+    const LENGTHS: SessionLengths = SessionLengths { min: 25, max: 60 };
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2021, 9, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn test_split_into_sessions() {
+        assert_eq!(split_into_sessions(10, LENGTHS), vec![25]);
+        assert_eq!(split_into_sessions(60, LENGTHS), vec![60]);
+        assert_eq!(split_into_sessions(61, LENGTHS), vec![31, 30]);
+        assert_eq!(split_into_sessions(150, LENGTHS), vec![50, 50, 50]);
+        assert_eq!(
+            split_into_sessions(70, SessionLengths { min: 40, max: 60 }),
+            vec![40, 40]
+        );
+        // there isn't room for more than 806 sessions of 25 minutes in two weeks
+        let lengths = SessionLengths { min: 25, max: 25 };
+        let sessions = split_into_sessions(20160, lengths);
+        assert_eq!(sessions.len() as i64, super::max_sessions(lengths));
+        assert_eq!(sessions.len(), 806);
+        assert_eq!(sessions.iter().sum::<i64>(), 20160);
+    }
+
+    #[test]
+    fn test_plan_sessions() {
+        let slots = vec![
+            FreeSlot {
+                start: at(1, 9, 0),
+                end: at(1, 12, 0),
+            },
+            FreeSlot {
+                start: at(2, 9, 0),
+                end: at(2, 10, 0),
+            },
+            FreeSlot {
+                start: at(3, 9, 0),
+                end: at(3, 12, 0),
+            },
+        ];
+        let tasks = [
+            // split into three 50 minute parts, which should be spread over all three slots
+            WorkItem {
+                due: at(10, 0, 0),
+                minutes: 150,
+            },
+            // due first, so scheduled first
+            WorkItem {
+                due: at(1, 10, 0),
+                minutes: 30,
+            },
+            // there isn't anywhere to put this before it's due
+            WorkItem {
+                due: at(1, 9, 10),
+                minutes: 30,
+            },
+        ];
+        let plan = plan_sessions(&tasks, slots, LENGTHS);
+        assert_eq!(
+            plan.sessions
+                .iter()
+                .map(|session| (session.task, session.start, session.end, session.part))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, at(1, 9, 0), at(1, 9, 30), 1),
+                (0, at(1, 9, 30), at(1, 10, 20), 1),
+                (0, at(2, 9, 0), at(2, 9, 50), 2),
+                (0, at(3, 9, 0), at(3, 9, 50), 3),
+            ]
+        );
+        assert!(plan
+            .sessions
+            .iter()
+            .filter(|session| session.task == 0)
+            .all(|session| session.parts == 3));
+        assert_eq!(
+            plan.shortfalls,
+            vec![Shortfall {
+                task: 2,
+                minutes: 30
+            }]
+        );
+    }
+
+    #[test]
+    fn test_deadlines_are_met_where_possible() {
+        let slots = || {
+            vec![
+                FreeSlot {
+                    start: at(1, 9, 0),
+                    end: at(1, 9, 40),
+                },
+                FreeSlot {
+                    start: at(2, 9, 0),
+                    end: at(2, 9, 40),
+                },
+            ]
+        };
+        // the task which is due soonest goes first, even though it comes last in the list
+        let plan = plan_sessions(
+            &[
+                WorkItem {
+                    due: at(10, 0, 0),
+                    minutes: 40,
+                },
+                WorkItem {
+                    due: at(1, 12, 0),
+                    minutes: 40,
+                },
+            ],
+            slots(),
+            LENGTHS,
+        );
+        assert_eq!(
+            plan.sessions
+                .iter()
+                .map(|session| (session.task, session.start))
+                .collect::<Vec<_>>(),
+            vec![(1, at(1, 9, 0)), (0, at(2, 9, 0))]
+        );
+        assert!(plan.shortfalls.is_empty());
+
+        // an hour of work doesn't fit into either slot, so it's split between them
+        let plan = plan_sessions(
+            &[WorkItem {
+                due: at(3, 0, 0),
+                minutes: 60,
+            }],
+            slots(),
+            LENGTHS,
+        );
+        assert_eq!(
+            plan.sessions
+                .iter()
+                .map(|session| (session.start, session.end, session.part, session.parts))
+                .collect::<Vec<_>>(),
+            vec![
+                (at(1, 9, 0), at(1, 9, 40), 1, 2),
+                (at(2, 9, 0), at(2, 9, 20), 2, 2)
+            ]
+        );
+        assert!(plan.shortfalls.is_empty());
+
+        // there's only 80 minutes free before this is due
+        let plan = plan_sessions(
+            &[WorkItem {
+                due: at(3, 0, 0),
+                minutes: 120,
+            }],
+            slots(),
+            LENGTHS,
+        );
+        assert_eq!(
+            plan.shortfalls,
+            vec![Shortfall {
+                task: 0,
+                minutes: 40
+            }]
+        );
+
+        // the shortfall is the part of the estimate which is left, not the (longer) session which
+        // short tasks are given
+        let plan = plan_sessions(
+            &[WorkItem {
+                due: at(1, 9, 10),
+                minutes: 10,
+            }],
+            slots(),
+            LENGTHS,
+        );
+        assert!(plan.sessions.is_empty());
+        assert_eq!(
+            plan.shortfalls,
+            vec![Shortfall {
+                task: 0,
+                minutes: 10
+            }]
+        );
+    }
+}
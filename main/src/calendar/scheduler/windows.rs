@@ -0,0 +1,305 @@
+//! Working out when work can be scheduled: the gaps between the user's events, inside the hours
+//! they're happy to study during, with breaks and no more than a set amount of work each day.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use super::FreeSlot;
+use crate::calendar::preferences::StudyWindow;
+
+/// The gaps between `from` and `to` which aren't covered by any of the busy periods (these can be
+/// in any order, and can overlap).
+pub fn free_time(
+    mut busy: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<FreeSlot> {
+    busy.sort_unstable();
+    let mut free = vec![];
+    let mut cursor = from;
+    for (start, end) in busy {
+        if start > cursor {
+            free.push(FreeSlot {
+                start: cursor,
+                end: start.min(to),
+            });
+        }
+        cursor = cursor.max(end);
+        if cursor >= to {
+            break;
+        }
+    }
+    if cursor < to {
+        free.push(FreeSlot {
+            start: cursor,
+            end: to,
+        });
+    }
+    free.retain(|slot| slot.start < slot.end);
+    free
+}
+
+/// Converts a time on a date in the user's timezone to UTC. Times which are skipped when the
+/// clocks go forward don't exist, so these are moved forward to the first time which does (e.g. a
+/// window from 01:30 to 03:00 in London on the day the clocks change starts at 02:00).
+fn to_utc(timezone: Tz, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    let local = date.and_time(time);
+    // (no timezone skips more than a day)
+    (0..=24 * 60)
+        .map(|minutes| local + Duration::minutes(minutes))
+        .find_map(|local| timezone.from_local_datetime(&local).earliest())
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// The times between `from` and `to` which fall inside the study windows (which are in the user's
+/// timezone), in order.
+pub fn study_windows(
+    windows: &[StudyWindow],
+    timezone: Tz,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<FreeSlot> {
+    let first = from.with_timezone(&timezone).date().naive_local();
+    let last = to.with_timezone(&timezone).date().naive_local();
+    let mut slots = vec![];
+    let mut date = first;
+    while date <= last {
+        for window in windows
+            .iter()
+            .filter(|window| window.weekday == date.weekday())
+        {
+            if let (Some(start), Some(end)) = (
+                to_utc(timezone, date, window.starts),
+                to_utc(timezone, date, window.ends),
+            ) {
+                let (start, end) = (start.max(from), end.min(to));
+                if start < end {
+                    slots.push(FreeSlot { start, end });
+                }
+            }
+        }
+        date = date.succ();
+    }
+    slots.sort_by_key(|slot| slot.start);
+    slots
+}
+
+/// The parts of the free slots which are inside one of the windows (both should be in order, and
+/// not overlap).
+pub fn intersect(slots: Vec<FreeSlot>, windows: &[FreeSlot]) -> Vec<FreeSlot> {
+    slots
+        .into_iter()
+        .flat_map(|slot| {
+            windows
+                .iter()
+                .filter(|window| window.start < slot.end && slot.start < window.end)
+                .map(|window| FreeSlot {
+                    start: slot.start.max(window.start),
+                    end: slot.end.min(window.end),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Splits up the free slots (which should be in order) so that nobody is expected to work for more
+/// than `every` without a break of at least `length`. A long enough gap between two slots counts
+/// as a break.
+pub fn with_breaks(slots: Vec<FreeSlot>, every: Duration, length: Duration) -> Vec<FreeSlot> {
+    let mut result = vec![];
+    let mut worked = Duration::zero();
+    let mut last_end: Option<DateTime<Utc>> = None;
+    for slot in slots {
+        if last_end
+            .map(|last_end| slot.start - last_end >= length)
+            .unwrap_or(true)
+        {
+            worked = Duration::zero();
+        }
+        let mut cursor = slot.start;
+        while cursor < slot.end {
+            let end = slot.end.min(cursor + (every - worked));
+            result.push(FreeSlot { start: cursor, end });
+            worked = worked + (end - cursor);
+            last_end = Some(end);
+            cursor = end;
+            if worked >= every {
+                cursor = cursor + length;
+                worked = Duration::zero();
+            }
+        }
+    }
+    result
+}
+
+/// Only keeps the first `max` of free time on each day (in the user's timezone), so that no more
+/// than that much work is scheduled on any one day. Slots count towards the day they start on.
+pub fn limit_per_day(slots: Vec<FreeSlot>, max: Duration, timezone: Tz) -> Vec<FreeSlot> {
+    let mut result = vec![];
+    let mut day = None;
+    let mut used = Duration::zero();
+    for slot in slots {
+        let date = slot.start.with_timezone(&timezone).date().naive_local();
+        if day != Some(date) {
+            day = Some(date);
+            used = Duration::zero();
+        }
+        let end = slot.end.min(slot.start + (max - used));
+        if slot.start < end {
+            used = used + (end - slot.start);
+            result.push(FreeSlot {
+                start: slot.start,
+                end,
+            });
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test_windows {
+    use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc, Weekday};
+
+    use super::{free_time, intersect, limit_per_day, study_windows, with_breaks};
+    use crate::calendar::{preferences::StudyWindow, scheduler::FreeSlot};
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2021, 9, day).and_hms(hour, minute, 0)
+    }
+
+    fn times(slots: &[FreeSlot]) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        slots.iter().map(|slot| (slot.start, slot.end)).collect()
+    }
+
+    #[test]
+    fn test_free_time() {
+        let free = free_time(
+            vec![
+                (at(1, 12, 0), at(1, 13, 0)),
+                (at(1, 9, 0), at(1, 10, 0)),
+                (at(1, 9, 30), at(1, 11, 0)),
+            ],
+            at(1, 8, 0),
+            at(1, 18, 0),
+        );
+        assert_eq!(
+            times(&free),
+            vec![
+                (at(1, 8, 0), at(1, 9, 0)),
+                (at(1, 11, 0), at(1, 12, 0)),
+                (at(1, 13, 0), at(1, 18, 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_study_windows_use_the_users_timezone() {
+        // 2021-09-01 was a Wednesday, and London was an hour ahead of UTC
+        let windows = study_windows(
+            &[StudyWindow {
+                weekday: Weekday::Wed,
+                starts: NaiveTime::from_hms(17, 0, 0),
+                ends: NaiveTime::from_hms(19, 0, 0),
+            }],
+            chrono_tz::Europe::London,
+            at(1, 0, 0),
+            at(9, 0, 0),
+        );
+        assert_eq!(
+            times(&windows),
+            vec![(at(1, 16, 0), at(1, 18, 0)), (at(8, 16, 0), at(8, 18, 0))]
+        );
+        let free = intersect(
+            vec![
+                FreeSlot {
+                    start: at(1, 0, 0),
+                    end: at(1, 17, 0),
+                },
+                FreeSlot {
+                    start: at(1, 17, 30),
+                    end: at(2, 0, 0),
+                },
+            ],
+            &windows,
+        );
+        assert_eq!(
+            times(&free),
+            vec![(at(1, 16, 0), at(1, 17, 0)), (at(1, 17, 30), at(1, 18, 0))]
+        );
+    }
+
+    #[test]
+    fn test_study_windows_when_the_clocks_go_forward() {
+        // on 2021-03-28 (a Sunday) the clocks in London went from 01:00 to 02:00
+        let windows = study_windows(
+            &[StudyWindow {
+                weekday: Weekday::Sun,
+                starts: NaiveTime::from_hms(1, 30, 0),
+                ends: NaiveTime::from_hms(3, 0, 0),
+            }],
+            chrono_tz::Europe::London,
+            Utc.ymd(2021, 3, 27).and_hms(0, 0, 0),
+            Utc.ymd(2021, 3, 29).and_hms(0, 0, 0),
+        );
+        assert_eq!(
+            times(&windows),
+            vec![(
+                Utc.ymd(2021, 3, 28).and_hms(1, 0, 0),
+                Utc.ymd(2021, 3, 28).and_hms(2, 0, 0)
+            )]
+        );
+    }
+
+    #[test]
+    fn test_breaks_and_daily_limit() {
+        let slots = with_breaks(
+            vec![
+                FreeSlot {
+                    start: at(1, 9, 0),
+                    end: at(1, 11, 0),
+                },
+                // there's a long enough gap before this for it to start a new block of work
+                FreeSlot {
+                    start: at(1, 11, 10),
+                    end: at(1, 11, 40),
+                },
+            ],
+            Duration::minutes(50),
+            Duration::minutes(10),
+        );
+        assert_eq!(
+            times(&slots),
+            vec![
+                (at(1, 9, 0), at(1, 9, 50)),
+                (at(1, 10, 0), at(1, 10, 50)),
+                (at(1, 11, 10), at(1, 11, 40)),
+            ]
+        );
+        let limited = limit_per_day(
+            vec![
+                FreeSlot {
+                    start: at(1, 9, 0),
+                    end: at(1, 10, 0),
+                },
+                FreeSlot {
+                    start: at(1, 11, 0),
+                    end: at(1, 12, 0),
+                },
+                FreeSlot {
+                    start: at(2, 9, 0),
+                    end: at(2, 10, 0),
+                },
+            ],
+            Duration::minutes(90),
+            chrono_tz::UTC,
+        );
+        assert_eq!(
+            times(&limited),
+            vec![
+                (at(1, 9, 0), at(1, 10, 0)),
+                (at(1, 11, 0), at(1, 11, 30)),
+                (at(2, 9, 0), at(2, 10, 0))
+            ]
+        );
+    }
+}
@@ -2,7 +2,7 @@
 
 use crate::{
     models::calendar::{Calendar, GoogleCalendar},
-    schema::{calendar, google_calendar},
+    schema::{calendar, gcal_oauth_state, google_calendar},
     utils::{launch, login_user, logout},
 };
 use chrono::{Duration, Utc};
@@ -16,8 +16,6 @@ use crate::{
     models::{NewClass, NewClassStudent, NewClassTeacher, NewUser},
 };
 
-use super::connect::gcal::StateValues;
-
 const NEW_TASK_TITLE: &str = "new-task-title";
 const NEW_TASK_DESCRIPTION: &str = "new-task-description";
 
@@ -41,7 +39,7 @@ fn setup_env(conn: &DatabaseConnection) -> (i32, i32, i32) {
         .values(NewUser {
             username: TEACHER_USERNAME,
             email: TEACHER_EMAIL,
-            password: &bcrypt::hash(TEACHER_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+            password: &crate::auth::hash::hash_password(TEACHER_PASSWORD).unwrap(),
             created: Utc::now().naive_utc(),
             email_verified: true,
             timezone: TIMEZONE,
@@ -53,7 +51,7 @@ fn setup_env(conn: &DatabaseConnection) -> (i32, i32, i32) {
         .values(NewUser {
             username: STUDENT_USERNAME,
             email: STUDENT_EMAIL,
-            password: &bcrypt::hash(STUDENT_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+            password: &crate::auth::hash::hash_password(STUDENT_PASSWORD).unwrap(),
             created: Utc::now().naive_utc(),
             email_verified: true,
             timezone: TIMEZONE,
@@ -69,6 +67,7 @@ fn setup_env(conn: &DatabaseConnection) -> (i32, i32, i32) {
             code: "12345",
             institution_id: None,
             student_group_id: None,
+            academic_term_id: None,
         })
         .returning(class::id)
         .get_result(conn)
@@ -124,7 +123,6 @@ async fn google_oauth_caldav_integration_test() {
     std::env::set_var("HOSTNAME", "https://example.com/redirect");
     std::env::set_var("CLIENT_ID", "some-var");
     std::env::set_var("CLIENT_SECRET", "some-secret-token");
-    std::env::set_var("REDIRECT_URI", "https://example.com");
 
     let test_server = MockServer::start().await;
     std::env::set_var("TOKEN_URL", format!("{}/token", test_server.uri()));
@@ -161,16 +159,16 @@ async fn google_oauth_caldav_integration_test() {
         .await;
     assert_eq!(add_calendar_response.status().code, 303);
 
-    let (state_token, _) = client
-        .rocket()
-        .state::<StateValues>()
+    let state_token = Database::get_one(client.rocket())
+        .await
         .unwrap()
-        .map
-        .read()
+        .run(move |c| {
+            gcal_oauth_state::table
+                .filter(gcal_oauth_state::user_id.eq(student_id))
+                .select(gcal_oauth_state::state)
+                .first::<String>(c)
+        })
         .await
-        .iter()
-        .find(|(_, b)| b.user_id == student_id)
-        .map(|(a, b)| (a.clone(), b.clone()))
         .unwrap();
 
     let inp = format!(
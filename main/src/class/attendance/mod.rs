@@ -0,0 +1,596 @@
+//! The attendance register for synchronous tasks.
+//!
+//! At each occurrence of a synchronous task, teachers mark the students who were set it as present,
+//! late, or absent (with or without authorisation). A student's attendance is the percentage of
+//! the occurrences they've been marked for at which they were present (or late) – both kinds of
+//! absence count against it.
+
+pub mod register;
+pub mod summary;
+
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+
+use crate::{
+    db::DatabaseConnection,
+    models::{Attendance, ClassStudent, User},
+    notifications::{NotificationPriority, NotifyBuilder},
+    schema::{administrator, attendance, class, class_student, class_teacher, institution, users},
+};
+
+#[derive(FromFormField, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttendanceMark {
+    #[field(value = "present")]
+    Present,
+    #[field(value = "late")]
+    Late,
+    #[field(value = "authorised_absence")]
+    AuthorisedAbsence,
+    #[field(value = "unauthorised_absence")]
+    UnauthorisedAbsence,
+}
+
+impl AttendanceMark {
+    pub const ALL: [AttendanceMark; 4] = [
+        AttendanceMark::Present,
+        AttendanceMark::Late,
+        AttendanceMark::AuthorisedAbsence,
+        AttendanceMark::UnauthorisedAbsence,
+    ];
+
+    /// The value used for this mark in forms (and in the API).
+    pub fn value(&self) -> &'static str {
+        match self {
+            AttendanceMark::Present => "present",
+            AttendanceMark::Late => "late",
+            AttendanceMark::AuthorisedAbsence => "authorised_absence",
+            AttendanceMark::UnauthorisedAbsence => "unauthorised_absence",
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            AttendanceMark::Present => "Present",
+            AttendanceMark::Late => "Late",
+            AttendanceMark::AuthorisedAbsence => "Absent (authorised)",
+            AttendanceMark::UnauthorisedAbsence => "Absent (unauthorised)",
+        }
+    }
+}
+
+impl From<AttendanceMark> for i16 {
+    fn from(from: AttendanceMark) -> Self {
+        match from {
+            AttendanceMark::Present => 1,
+            AttendanceMark::Late => 2,
+            AttendanceMark::AuthorisedAbsence => 3,
+            AttendanceMark::UnauthorisedAbsence => 4,
+        }
+    }
+}
+
+impl From<i16> for AttendanceMark {
+    /// Converts a row in the database to an `AttendanceMark`, `panic`-ing if the database contains
+    /// invalid data. To make sure that this never happens, only ever insert `AttendanceMark`s
+    /// (converted with `Into<i16>`) into the `attendance.mark` column.
+    fn from(number: i16) -> Self {
+        match number {
+            1 => Self::Present,
+            2 => Self::Late,
+            3 => Self::AuthorisedAbsence,
+            4 => Self::UnauthorisedAbsence,
+            number => {
+                error!("Invalid number in database: {}", number);
+                panic!()
+            }
+        }
+    }
+}
+
+/// The number of each kind of mark somebody (or a class) has been given.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq)]
+pub struct AttendanceSummary {
+    pub present: u32,
+    pub late: u32,
+    pub authorised_absences: u32,
+    pub unauthorised_absences: u32,
+    /// The percentage of the marks which are for being present (or late). If there aren't any
+    /// marks, there isn't a rate.
+    pub rate: Option<f64>,
+}
+
+impl AttendanceSummary {
+    pub fn of(marks: impl IntoIterator<Item = AttendanceMark>) -> Self {
+        let mut summary = Self::default();
+        for mark in marks {
+            match mark {
+                AttendanceMark::Present => summary.present += 1,
+                AttendanceMark::Late => summary.late += 1,
+                AttendanceMark::AuthorisedAbsence => summary.authorised_absences += 1,
+                AttendanceMark::UnauthorisedAbsence => summary.unauthorised_absences += 1,
+            }
+        }
+        let total = summary.total();
+        if total > 0 {
+            summary.rate = Some((summary.present + summary.late) as f64 * 100.0 / total as f64);
+        }
+        summary
+    }
+
+    pub fn total(&self) -> u32 {
+        self.present + self.late + self.authorised_absences + self.unauthorised_absences
+    }
+
+    /// A one-line description (e.g. "92% – 20 present, 3 late, 1 absent (authorised), 1 absent
+    /// (unauthorised)").
+    pub fn describe(&self) -> String {
+        match self.rate {
+            Some(rate) => format!(
+                "{:.0}% – {} present, {} late, {} absent (authorised), {} absent (unauthorised)",
+                rate, self.present, self.late, self.authorised_absences, self.unauthorised_absences
+            ),
+            None => "No attendance has been recorded yet.".to_string(),
+        }
+    }
+}
+
+/// The attendance of one of the students in a class.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StudentAttendance {
+    pub class_student_id: i32,
+    pub user_id: i32,
+    pub username: String,
+    pub summary: AttendanceSummary,
+}
+
+/// The attendance of each of the students in the class (in alphabetical order), and of the class
+/// as a whole.
+pub fn class_attendance(
+    class_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<(AttendanceSummary, Vec<StudentAttendance>)> {
+    let students = class_student::table
+        .filter(class_student::class_id.eq(class_id))
+        .inner_join(users::table)
+        .order_by(users::username)
+        .load::<(ClassStudent, User)>(c)?;
+    let marks = attendance::table
+        .inner_join(class_student::table)
+        .filter(class_student::class_id.eq(class_id))
+        .select(attendance::all_columns)
+        .load::<Attendance>(c)?;
+    let mut by_student: HashMap<i32, Vec<AttendanceMark>> = HashMap::new();
+    for mark in &marks {
+        by_student
+            .entry(mark.class_student_id)
+            .or_default()
+            .push(mark.mark.into());
+    }
+    let overall = AttendanceSummary::of(marks.into_iter().map(|mark| mark.mark.into()));
+    let students = students
+        .into_iter()
+        .map(|(class_student, user)| StudentAttendance {
+            class_student_id: class_student.id,
+            user_id: user.id,
+            username: user.username,
+            summary: AttendanceSummary::of(
+                by_student.remove(&class_student.id).unwrap_or_default(),
+            ),
+        })
+        .collect();
+    Ok((overall, students))
+}
+
+/// Notifies the class' teachers and the administrators of its institution about the students
+/// whose attendance has just dropped below the institution's threshold (`before` is what everybody's
+/// attendance was before the register was taken, by `class_student` id).
+pub(crate) fn notify_low_attendance(
+    class_id: i32,
+    before: &HashMap<i32, AttendanceSummary>,
+    c: &DatabaseConnection,
+) -> QueryResult<()> {
+    let (class_name, institution_id) = class::table
+        .find(class_id)
+        .select((class::name, class::institution_id))
+        .first::<(String, Option<i32>)>(c)?;
+    let threshold = match institution_id {
+        Some(institution_id) => institution::table
+            .find(institution_id)
+            .select(institution::attendance_threshold)
+            .first::<Option<i32>>(c)?,
+        None => None,
+    };
+    let threshold = match threshold {
+        Some(threshold) => threshold as f64,
+        None => return Ok(()),
+    };
+    let dropped_below = class_attendance(class_id, c)?
+        .1
+        .into_iter()
+        .filter(|student| {
+            let was_below = before
+                .get(&student.class_student_id)
+                .and_then(|summary| summary.rate)
+                .map(|rate| rate < threshold)
+                .unwrap_or(false);
+            student.summary.rate.map(|rate| rate < threshold) == Some(true) && !was_below
+        })
+        .collect::<Vec<_>>();
+    if dropped_below.is_empty() {
+        return Ok(());
+    }
+    let mut recipients = class_teacher::table
+        .filter(class_teacher::class_id.eq(class_id))
+        .select(class_teacher::user_id)
+        .load::<i32>(c)?;
+    if let Some(institution_id) = institution_id {
+        recipients.extend(
+            administrator::table
+                .filter(administrator::institution_id.eq(institution_id))
+                .select(administrator::user_id)
+                .load::<i32>(c)?,
+        );
+    }
+    recipients.sort_unstable();
+    recipients.dedup();
+    for student in dropped_below {
+        let message = format!(
+            "{}'s attendance in {} has dropped to {:.0}%, which is below {:.0}%.",
+            student.username,
+            class_name,
+            student.summary.rate.unwrap_or_default(),
+            threshold
+        );
+        for recipient in &recipients {
+            NotifyBuilder::default()
+                .intended_for(*recipient)
+                .title("Low attendance")
+                .message(&message)
+                .priority(NotificationPriority::Warning)
+                .build()
+                .unwrap()
+                .create(c)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_attendance {
+    use chrono::{NaiveDate, Utc};
+    use diesel::prelude::*;
+    use rocket::http::ContentType;
+
+    use crate::{
+        db::{Database, DatabaseConnection},
+        institution::test_ctx::{
+            setup_env, ADMIN_PASSWORD, ADMIN_USERNAME, STUDENT_PASSWORD, STUDENT_USERNAME,
+            TEACHER_PASSWORD, TEACHER_USERNAME,
+        },
+        models::{
+            NewClass, NewClassStudent, NewClassSynchronousTask, NewClassTeacher,
+            NewStudentClassSynchronousTask,
+        },
+        schema::{
+            attendance, class, class_student, class_synchronous_task, class_teacher, institution,
+            notifications, student_class_synchronous_task,
+        },
+        utils::{client, login_user, logout},
+    };
+
+    /// Sets up a class with a weekly lesson (starting on 2021-09-06 at 09:00) which one student
+    /// has been set.
+    ///
+    /// Returns (admin_id, teacher_id, institution_id, class_id, class_student_id, task_id).
+    fn setup_class(c: &mut DatabaseConnection) -> (i32, i32, i32, i32, i32, i32) {
+        let (admin, teacher, student, institution_id, _) = setup_env(c);
+        diesel::update(institution::table.find(institution_id))
+            .set(institution::attendance_threshold.eq(Some(80)))
+            .execute(c)
+            .unwrap();
+        let class_id = diesel::insert_into(class::table)
+            .values(NewClass {
+                name: "Physics",
+                description: "",
+                created: Utc::now().naive_utc(),
+                code: "physics",
+                institution_id: Some(institution_id),
+                student_group_id: None,
+                academic_term_id: None,
+            })
+            .returning(class::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        let class_teacher_id = diesel::insert_into(class_teacher::table)
+            .values(NewClassTeacher {
+                user_id: teacher,
+                class_id,
+            })
+            .returning(class_teacher::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        let class_student_id = diesel::insert_into(class_student::table)
+            .values(NewClassStudent {
+                user_id: student,
+                class_id,
+            })
+            .returning(class_student::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        let start_time = NaiveDate::from_ymd(2021, 9, 6).and_hms(9, 0, 0);
+        let task_id = diesel::insert_into(class_synchronous_task::table)
+            .values(NewClassSynchronousTask {
+                title: "Lab",
+                description: "",
+                created: Utc::now().naive_utc(),
+                start_time,
+                end_time: NaiveDate::from_ymd(2021, 9, 6).and_hms(10, 0, 0),
+                class_teacher_id,
+                class_id,
+                recurrence: Some("FREQ=WEEKLY;COUNT=10"),
+            })
+            .returning(class_synchronous_task::id)
+            .get_result::<i32>(c)
+            .unwrap();
+        diesel::insert_into(student_class_synchronous_task::table)
+            .values(NewStudentClassSynchronousTask {
+                class_student_id,
+                class_synchronous_task_id: task_id,
+            })
+            .execute(c)
+            .unwrap();
+        (
+            admin,
+            teacher,
+            institution_id,
+            class_id,
+            class_student_id,
+            task_id,
+        )
+    }
+
+    #[rocket::async_test]
+    async fn test_attendance_register() {
+        let client = client().await;
+        let (admin, teacher, institution_id, class_id, class_student_id, task_id) =
+            Database::get_one(client.rocket())
+                .await
+                .unwrap()
+                .run(|c| setup_class(c))
+                .await;
+        let count_warnings = |user_id: i32| {
+            let client = &client;
+            async move {
+                Database::get_one(client.rocket())
+                    .await
+                    .unwrap()
+                    .run(move |c| {
+                        notifications::table
+                            .filter(notifications::user_id.eq(user_id))
+                            .filter(notifications::title.eq("Low attendance"))
+                            .count()
+                            .get_result::<i64>(c)
+                            .unwrap()
+                    })
+                    .await
+            }
+        };
+
+        // students can't take the register
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+        let res = client
+            .get(format!(
+                "/class/{}/task/sync/{}/register",
+                class_id, task_id
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Permission error"));
+        logout(&client).await;
+
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .get(format!(
+                "/class/{}/task/sync/{}/register?occurrence=2021-09-13T09:00:00",
+                class_id, task_id
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains(STUDENT_USERNAME));
+        assert!(string.contains(&format!("marks[{}]", class_student_id)));
+
+        let take_register = |occurrence: &'static str, mark: &'static str| {
+            let client = &client;
+            async move {
+                client
+                    .post(format!(
+                        "/class/{}/task/sync/{}/register?occurrence={}",
+                        class_id, task_id, occurrence
+                    ))
+                    .header(ContentType::Form)
+                    .body(format!(
+                        "marks[{id}]={}&notes[{id}]=Dentist",
+                        mark,
+                        id = class_student_id
+                    ))
+                    .dispatch()
+                    .await
+                    .into_string()
+                    .await
+                    .expect("invalid body response")
+            }
+        };
+        assert!(take_register("2021-09-06T09:00:00", "present")
+            .await
+            .contains("Saved the register"));
+        assert!(take_register("2021-09-13T09:00:00", "late")
+            .await
+            .contains("Saved the register"));
+        // not an occurrence of the task
+        assert!(!take_register("2021-09-14T09:00:00", "present")
+            .await
+            .contains("Saved the register"));
+        assert_eq!(count_warnings(teacher).await, 0);
+
+        // 2 out of 3 is below the threshold
+        assert!(take_register("2021-09-20T09:00:00", "authorised_absence")
+            .await
+            .contains("Saved the register"));
+        assert_eq!(count_warnings(teacher).await, 1);
+        assert_eq!(count_warnings(admin).await, 1);
+        // students are only reported once when they drop below it
+        assert!(take_register("2021-09-27T09:00:00", "unauthorised_absence")
+            .await
+            .contains("Saved the register"));
+        assert_eq!(count_warnings(teacher).await, 1);
+        // changing a mark replaces it
+        assert!(take_register("2021-09-27T09:00:00", "present")
+            .await
+            .contains("Saved the register"));
+
+        let res = client
+            .get(format!("/class/{}/attendance", class_id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("75% – 2 present, 1 late, 1 absent (authorised)"));
+        logout(&client).await;
+
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+        let res = client
+            .get(format!("/class/{}/attendance", class_id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("75%"));
+        assert!(string.contains("Absent (authorised)"));
+        assert!(string.contains("Dentist"));
+        let res = client
+            .get(format!("/institution/{}/attendance", institution_id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Permission error"));
+        logout(&client).await;
+
+        login_user(ADMIN_USERNAME, ADMIN_PASSWORD, &client).await;
+        let res = client
+            .get(format!("/api/institution/{}/attendance", institution_id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("\"threshold\":80"));
+        assert!(string.contains("Physics"));
+        assert!(string.contains(STUDENT_USERNAME));
+    }
+
+    #[rocket::async_test]
+    async fn test_register_follows_moved_lessons() {
+        let client = client().await;
+        let (_, _, _, class_id, class_student_id, task_id) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_class(c))
+            .await;
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+        for (occurrence, mark) in [
+            ("2021-09-06T09:00:00", "present"),
+            ("2021-09-13T09:00:00", "late"),
+            ("2021-09-27T09:00:00", "authorised_absence"),
+        ] {
+            let res = client
+                .post(format!(
+                    "/class/{}/task/sync/{}/register?occurrence={}",
+                    class_id, task_id, occurrence
+                ))
+                .header(ContentType::Form)
+                .body(format!("marks[{}]={}", class_student_id, mark))
+                .dispatch()
+                .await;
+            let string = res.into_string().await.expect("invalid body response");
+            assert!(string.contains("Saved the register"));
+        }
+        let mark_at = |task_id: i32, occurrence: &'static str| {
+            let client = &client;
+            async move {
+                let res = client
+                    .get(format!(
+                        "/api/class/{}/task/sync/{}/register?occurrence={}",
+                        class_id, task_id, occurrence
+                    ))
+                    .dispatch()
+                    .await
+                    .into_string()
+                    .await
+                    .expect("invalid body response");
+                serde_json::from_str::<serde_json::Value>(&res).unwrap()["data"]["students"][0]
+                    ["mark"]
+                    .clone()
+            }
+        };
+
+        // move every lesson to an hour later on the next day
+        let res = client
+            .post(format!("/class/{}/task/sync/{}/edit", class_id, task_id))
+            .header(ContentType::Form)
+            .body(
+                "title=Lab&description=&start_time=2021-09-07T10:00&end_time=2021-09-07T11:00\
+                &occurrence=2021-09-06T09:00:00&scope=all",
+            )
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("updated that task"));
+        assert_eq!(mark_at(task_id, "2021-09-07T10:00:00").await, "present");
+        assert_eq!(mark_at(task_id, "2021-09-14T10:00:00").await, "late");
+        assert_eq!(
+            mark_at(task_id, "2021-09-28T10:00:00").await,
+            "authorised_absence"
+        );
+
+        // and the ones from the 21st onwards to the Wednesday
+        let res = client
+            .post(format!("/class/{}/task/sync/{}/edit", class_id, task_id))
+            .header(ContentType::Form)
+            .body(
+                "title=Lab&description=&start_time=2021-09-22T10:00&end_time=2021-09-22T11:00\
+                &occurrence=2021-09-21T10:00:00&scope=following",
+            )
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("updated that task"));
+        let (new_task_id, left_behind) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                let new_task_id = class_synchronous_task::table
+                    .filter(class_synchronous_task::class_id.eq(class_id))
+                    .filter(class_synchronous_task::id.ne(task_id))
+                    .select(class_synchronous_task::id)
+                    .first::<i32>(c)
+                    .unwrap();
+                let left_behind = attendance::table
+                    .filter(attendance::class_synchronous_task_id.eq(task_id))
+                    .filter(
+                        attendance::occurrence
+                            .ge(NaiveDate::from_ymd(2021, 9, 21).and_hms(0, 0, 0)),
+                    )
+                    .count()
+                    .get_result::<i64>(c)
+                    .unwrap();
+                (new_task_id, left_behind)
+            })
+            .await;
+        assert_eq!(left_behind, 0);
+        assert_eq!(mark_at(task_id, "2021-09-14T10:00:00").await, "late");
+        assert_eq!(
+            mark_at(new_task_id, "2021-09-29T10:00:00").await,
+            "authorised_absence"
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! Taking the register for an occurrence of a synchronous task.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::{
+    form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle},
+    render::Render,
+};
+use rocket::serde::json::Json;
+
+use crate::{
+    auth::{
+        scope::{ManageTasks, ReadTasks},
+        ApiAuth, AuthCookie,
+    },
+    class::{
+        attendance::{class_attendance, notify_low_attendance, AttendanceMark},
+        get_user_role_in_class,
+        tasks::synchronous::recurrence::{
+            apply_change, find_occurrence, task_recurrence, SyncTaskOccurrence,
+        },
+        ClassMemberRole,
+    },
+    db::{Database, DatabaseConnection},
+    models::{
+        Attendance, ClassStudent, ClassSynchronousTask, ClassSynchronousTaskOccurrence,
+        NewAttendance, User,
+    },
+    schema::{
+        attendance, class_student, class_synchronous_task, class_synchronous_task_occurrence,
+        student_class_synchronous_task, users,
+    },
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        json_response::ApiResponse,
+    },
+};
+
+/// A student who has been set the task, and the mark they've been given (if the register has
+/// already been taken).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegisterEntry {
+    pub class_student_id: i32,
+    pub username: String,
+    pub mark: Option<AttendanceMark>,
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Register {
+    pub occurrence: SyncTaskOccurrence,
+    pub students: Vec<RegisterEntry>,
+}
+
+/// Finds the occurrence of the task the register is being taken for. If one isn't given, this is
+/// the latest one which has already started (or the first, if none have yet).
+fn find_register_occurrence(
+    task: &ClassSynchronousTask,
+    occurrence: Option<&str>,
+    c: &DatabaseConnection,
+) -> LovelaceResult<SyncTaskOccurrence> {
+    let original = match occurrence.map(str::trim) {
+        Some(occurrence) if !occurrence.is_empty() => find_occurrence(task, occurrence)?,
+        _ => {
+            let now = Utc::now().naive_utc();
+            match task_recurrence(task)? {
+                Some(recurrence) => recurrence
+                    .occurrences(task.start_time)
+                    .take_while(|start| *start <= now)
+                    .last()
+                    .unwrap_or(task.start_time),
+                None => task.start_time,
+            }
+        }
+    };
+    let change = class_synchronous_task_occurrence::table
+        .filter(class_synchronous_task_occurrence::class_synchronous_task_id.eq(task.id))
+        .filter(class_synchronous_task_occurrence::original_start_time.eq(original))
+        .first::<ClassSynchronousTaskOccurrence>(c)
+        .optional()?;
+    match apply_change(task, original, change.as_ref()) {
+        Some(task) => Ok(SyncTaskOccurrence {
+            task,
+            occurrence: original,
+        }),
+        None => Err(LovelaceError::InvalidRecurrence(
+            "That occurrence of this task has been cancelled.".to_string(),
+        )),
+    }
+}
+
+fn find_task(
+    class_id: i32,
+    task_id: i32,
+    c: &DatabaseConnection,
+) -> LovelaceResult<ClassSynchronousTask> {
+    class_synchronous_task::table
+        .filter(class_synchronous_task::id.eq(task_id))
+        .filter(class_synchronous_task::class_id.eq(class_id))
+        .first::<ClassSynchronousTask>(c)
+        .map_err(From::from)
+}
+
+/// The students who have been set the task, and what they've been marked as at this occurrence.
+fn load_register(
+    task_id: i32,
+    occurrence: SyncTaskOccurrence,
+    c: &DatabaseConnection,
+) -> QueryResult<Register> {
+    let students = student_class_synchronous_task::table
+        .filter(student_class_synchronous_task::class_synchronous_task_id.eq(task_id))
+        .inner_join(class_student::table.inner_join(users::table))
+        .order_by(users::username)
+        .select((class_student::all_columns, users::all_columns))
+        .load::<(ClassStudent, User)>(c)?;
+    let mut marks = attendance::table
+        .filter(attendance::class_synchronous_task_id.eq(task_id))
+        .filter(attendance::occurrence.eq(occurrence.occurrence))
+        .load::<Attendance>(c)?
+        .into_iter()
+        .map(|mark| (mark.class_student_id, mark))
+        .collect::<HashMap<_, _>>();
+    Ok(Register {
+        occurrence,
+        students: students
+            .into_iter()
+            .map(|(class_student, user)| {
+                let mark = marks.remove(&class_student.id);
+                RegisterEntry {
+                    class_student_id: class_student.id,
+                    username: user.username,
+                    mark: mark.as_ref().map(|mark| mark.mark.into()),
+                    note: mark.and_then(|mark| mark.note),
+                }
+            })
+            .collect(),
+    })
+}
+
+async fn get_register(
+    class_id: i32,
+    task_id: i32,
+    occurrence: Option<String>,
+    user_id: i32,
+    conn: &Database,
+) -> LovelaceResult<Register> {
+    if get_user_role_in_class(user_id, class_id, conn).await != Some(ClassMemberRole::Teacher) {
+        return Err(LovelaceError::PermissionError(None));
+    }
+    conn.run(move |c| {
+        let task = find_task(class_id, task_id, c)?;
+        let occurrence = find_register_occurrence(&task, occurrence.as_deref(), c)?;
+        load_register(task_id, occurrence, c).map_err(From::from)
+    })
+    .await
+}
+
+/// The select box through which a student's mark is chosen.
+fn mark_select(class_student_id: i32, current: Option<AttendanceMark>) -> Select {
+    AttendanceMark::ALL.iter().fold(
+        Select::new().attribute(Name::new(format!("marks[{}]", class_student_id))),
+        |select, mark| {
+            let option = SelectOption::new()
+                .attribute(Value::new(mark.value()))
+                .text(mark.describe());
+            // students who haven't been marked yet are presumed to be present
+            select.child(
+                if Some(*mark) == current || (current.is_none() && *mark == AttendanceMark::Present)
+                {
+                    option.raw_attribute("selected", "selected")
+                } else {
+                    option
+                },
+            )
+        },
+    )
+}
+
+#[get("/<class_id>/task/sync/<task_id>/register?<occurrence>")]
+/// The register for an occurrence of a synchronous task (see [`find_register_occurrence`] for
+/// which one is shown if `occurrence` isn't given). Everybody the task has been set to is listed
+/// on one page, so that the register can be taken quickly.
+pub async fn html_view_register(
+    class_id: i32,
+    task_id: i32,
+    occurrence: Option<String>,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    let register = match get_register(class_id, task_id, occurrence, auth.0, &conn).await {
+        Ok(register) => register,
+        Err(e) => return e.render(),
+    };
+    let task = &register.occurrence.task;
+    let form = register.students.iter().fold(
+        Form::new()
+            .apply(FormStyle)
+            .attribute(Method::Post)
+            .attribute(Action::new(format!(
+                "/class/{}/task/sync/{}/register?occurrence={}",
+                class_id,
+                task_id,
+                register.occurrence.identifier()
+            ))),
+        |form, student| {
+            form.child(
+                Div::new()
+                    .child(Label::new(student.username.clone()))
+                    .child(mark_select(student.class_student_id, student.mark))
+                    .child(
+                        Input::new()
+                            .apply(FormTextInputStyle)
+                            .attribute(Type::Text)
+                            .attribute(Name::new(format!("notes[{}]", student.class_student_id)))
+                            .attribute(Placeholder::new("Note (optional)"))
+                            .map(|input| match &student.note {
+                                Some(note) => input.attribute(Value::new(note.clone())),
+                                None => input,
+                            }),
+                    ),
+            )
+        },
+    );
+    Html::new()
+        .head(default_head(format!("Register for {}", task.title)))
+        .body(
+            Body::new()
+                .child(H1::new(format!("Register for {}", task.title)))
+                .child(P::with_text(format!(
+                    "{} to {}",
+                    task.start_time.format("%A %e %B %Y, %H:%M"),
+                    task.end_time.format("%H:%M")
+                )))
+                .map(|body| {
+                    if register.students.is_empty() {
+                        body.child(P::with_text("Nobody has been set this task."))
+                    } else {
+                        body.child(
+                            form.child(
+                                Input::new()
+                                    .apply(FormSubmitInputStyle)
+                                    .attribute(Type::Submit)
+                                    .attribute(Value::new("Save the register")),
+                            ),
+                        )
+                    }
+                }),
+        )
+}
+
+#[get("/<class_id>/task/sync/<task_id>/register?<occurrence>")]
+pub async fn api_view_register(
+    class_id: i32,
+    task_id: i32,
+    occurrence: Option<String>,
+    auth: ApiAuth<ReadTasks>,
+    conn: Database,
+) -> Json<ApiResponse<Register>> {
+    Json(
+        match get_register(class_id, task_id, occurrence, auth.0, &conn).await {
+            Ok(register) => ApiResponse::new_ok(register),
+            Err(e) => From::from(e),
+        },
+    )
+}
+
+/// The marks (and notes) given to each student, by the id of their `class_student` record.
+/// Students who aren't included aren't changed.
+#[derive(FromForm, Serialize, Deserialize, Debug, Clone)]
+pub struct RegisterForm {
+    marks: HashMap<i32, AttendanceMark>,
+    #[serde(default)]
+    notes: HashMap<i32, String>,
+}
+
+async fn take_register(
+    class_id: i32,
+    task_id: i32,
+    occurrence: Option<String>,
+    user_id: i32,
+    form: RegisterForm,
+    conn: &Database,
+) -> LovelaceResult<Register> {
+    if get_user_role_in_class(user_id, class_id, conn).await != Some(ClassMemberRole::Teacher) {
+        return Err(LovelaceError::PermissionError(None));
+    }
+    conn.run(move |c| {
+        c.transaction::<_, LovelaceError, _>(|| {
+            let task = find_task(class_id, task_id, c)?;
+            let occurrence = find_register_occurrence(&task, occurrence.as_deref(), c)?;
+            let register = load_register(task_id, occurrence.clone(), c)?;
+            if let Some(class_student_id) = form.marks.keys().find(|id| {
+                !register
+                    .students
+                    .iter()
+                    .any(|student| student.class_student_id == **id)
+            }) {
+                return Err(LovelaceError::PermissionError(Some(format!(
+                    "The student with the id {} hasn't been set this task, so they can't be \
+                    marked in its register.",
+                    class_student_id
+                ))));
+            }
+            let before = class_attendance(class_id, c)?
+                .1
+                .into_iter()
+                .map(|student| (student.class_student_id, student.summary))
+                .collect::<HashMap<_, _>>();
+            let recorded = Utc::now().naive_utc();
+            for (class_student_id, mark) in &form.marks {
+                let record = NewAttendance {
+                    class_synchronous_task_id: task_id,
+                    occurrence: occurrence.occurrence,
+                    class_student_id: *class_student_id,
+                    mark: (*mark).into(),
+                    note: form
+                        .notes
+                        .get(class_student_id)
+                        .map(|note| note.trim())
+                        .filter(|note| !note.is_empty()),
+                    recorded_by: Some(user_id),
+                    recorded,
+                };
+                diesel::insert_into(attendance::table)
+                    .values(&record)
+                    .on_conflict((
+                        attendance::class_synchronous_task_id,
+                        attendance::occurrence,
+                        attendance::class_student_id,
+                    ))
+                    .do_update()
+                    .set(&record)
+                    .execute(c)?;
+            }
+            notify_low_attendance(class_id, &before, c)?;
+            load_register(task_id, occurrence, c).map_err(From::from)
+        })
+    })
+    .await
+    .map_err(|e| {
+        error!("{:#?}", e);
+        e
+    })
+}
+
+#[post(
+    "/<class_id>/task/sync/<task_id>/register?<occurrence>",
+    data = "<form>"
+)]
+pub async fn html_take_register(
+    class_id: i32,
+    task_id: i32,
+    occurrence: Option<String>,
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<RegisterForm>,
+) -> Html {
+    match take_register(
+        class_id,
+        task_id,
+        occurrence,
+        auth.0,
+        form.into_inner(),
+        &conn,
+    )
+    .await
+    {
+        Ok(register) => Html::new()
+            .head(default_head("Saved the register".to_string()))
+            .body(
+                Body::new().child(H1::new("Saved the register.")).child(
+                    A::new()
+                        .attribute(Href::new(format!(
+                            "/class/{}/task/sync/{}/register?occurrence={}",
+                            class_id,
+                            task_id,
+                            register.occurrence.identifier()
+                        )))
+                        .text("Back to the register"),
+                ),
+            ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post(
+    "/<class_id>/task/sync/<task_id>/register?<occurrence>",
+    data = "<form>"
+)]
+pub async fn api_take_register(
+    class_id: i32,
+    task_id: i32,
+    occurrence: Option<String>,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<RegisterForm>,
+) -> Json<ApiResponse<Register>> {
+    Json(
+        match take_register(class_id, task_id, occurrence, auth.0, form.0, &conn).await {
+            Ok(register) => ApiResponse::new_ok(register),
+            Err(e) => From::from(e),
+        },
+    )
+}
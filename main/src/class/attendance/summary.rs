@@ -0,0 +1,142 @@
+//! Summaries of the attendance of a class (for its teachers) or of a student in it (for the
+//! student).
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::{levels::Level, render::Render};
+use rocket::serde::json::Json;
+
+use crate::{
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
+    class::{
+        attendance::{class_attendance, AttendanceMark, AttendanceSummary, StudentAttendance},
+        get_user_role_in_class, ClassMemberRole,
+    },
+    db::Database,
+    models::Attendance,
+    schema::{attendance, class_student, class_synchronous_task},
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        json_response::ApiResponse,
+    },
+};
+
+/// A mark a student has been given.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StudentMark {
+    pub class_synchronous_task_id: i32,
+    pub task_title: String,
+    pub occurrence: NaiveDateTime,
+    pub mark: AttendanceMark,
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ClassAttendance {
+    Teacher {
+        overall: AttendanceSummary,
+        students: Vec<StudentAttendance>,
+    },
+    Student {
+        summary: AttendanceSummary,
+        /// The most recent marks first.
+        marks: Vec<StudentMark>,
+    },
+}
+
+async fn get_class_attendance(
+    class_id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> LovelaceResult<ClassAttendance> {
+    match get_user_role_in_class(user_id, class_id, conn).await {
+        Some(ClassMemberRole::Teacher) => conn
+            .run(move |c| class_attendance(class_id, c))
+            .await
+            .map(|(overall, students)| ClassAttendance::Teacher { overall, students })
+            .map_err(From::from),
+        Some(ClassMemberRole::Student) => conn
+            .run(move |c| {
+                attendance::table
+                    .inner_join(class_student::table)
+                    .filter(class_student::class_id.eq(class_id))
+                    .filter(class_student::user_id.eq(user_id))
+                    .inner_join(class_synchronous_task::table)
+                    .order_by(attendance::occurrence.desc())
+                    .select((attendance::all_columns, class_synchronous_task::title))
+                    .load::<(Attendance, String)>(c)
+            })
+            .await
+            .map(|marks| {
+                let marks = marks
+                    .into_iter()
+                    .map(|(mark, task_title)| StudentMark {
+                        class_synchronous_task_id: mark.class_synchronous_task_id,
+                        task_title,
+                        occurrence: mark.occurrence,
+                        mark: mark.mark.into(),
+                        note: mark.note,
+                    })
+                    .collect::<Vec<_>>();
+                ClassAttendance::Student {
+                    summary: AttendanceSummary::of(marks.iter().map(|mark| mark.mark)),
+                    marks,
+                }
+            })
+            .map_err(From::from),
+        None => Err(LovelaceError::PermissionError(None)),
+    }
+}
+
+#[get("/<class_id>/attendance")]
+/// Teachers see the attendance of each student in the class; students see their own attendance.
+pub async fn html_view_class_attendance(class_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let attendance = match get_class_attendance(class_id, auth.0, &conn).await {
+        Ok(attendance) => attendance,
+        Err(e) => return e.render(),
+    };
+    let body = Body::new().child(H1::new("Attendance"));
+    Html::new()
+        .head(default_head("Attendance".to_string()))
+        .body(match attendance {
+            ClassAttendance::Teacher { overall, students } => body
+                .child(P::with_text(format!("Overall: {}", overall.describe())))
+                .children(students.into_iter().map(|student| {
+                    Level::new()
+                        .child(H3::new(student.username))
+                        .child(P::with_text(student.summary.describe()))
+                        .into_div()
+                })),
+            ClassAttendance::Student { summary, marks } => body
+                .child(P::with_text(summary.describe()))
+                .children(marks.into_iter().map(|mark| {
+                    let level = Level::new()
+                        .child(H3::new(mark.task_title))
+                        .child(P::with_text(format!(
+                            "{} – {}",
+                            mark.occurrence.format("%A %e %B %Y, %H:%M"),
+                            mark.mark.describe()
+                        )));
+                    match mark.note {
+                        Some(note) => level.child(P::with_text(note)),
+                        None => level,
+                    }
+                    .into_div()
+                })),
+        })
+}
+
+#[get("/<class_id>/attendance")]
+pub async fn api_view_class_attendance(
+    class_id: i32,
+    auth: ApiAuth<ReadTasks>,
+    conn: Database,
+) -> Json<ApiResponse<ClassAttendance>> {
+    Json(match get_class_attendance(class_id, auth.0, &conn).await {
+        Ok(attendance) => ApiResponse::new_ok(attendance),
+        Err(e) => From::from(e),
+    })
+}
@@ -4,31 +4,80 @@ A copy of this license can be found in the `licenses` directory at the root of t
 */
 
 use crate::{
-    auth::AuthCookie,
-    class::get_user_role_in_class,
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
+    class::{get_user_role_in_class, user_is_teacher},
     db::Database,
-    utils::{default_head, error_message},
+    models::Class,
+    schema::class,
+    utils::{default_head, error::LovelaceError, error_message, json_response::ApiResponse},
 };
 
 use super::ClassMemberRole;
 
+use diesel::prelude::*;
 use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::{
+    form::{FormStyle, FormSubmitInputStyle},
+    render::Render,
+};
+use rocket::serde::json::Json;
+
+fn settings_form(class: &Class) -> Form {
+    let option = |value: bool, text: &str| {
+        let option = SelectOption::new()
+            .attribute(Value::new(value.to_string()))
+            .text(text.to_string());
+        if value == class.notify_teachers_of_overload {
+            option.raw_attribute("selected", "selected")
+        } else {
+            option
+        }
+    };
+    Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new(format!("/class/{}/settings", class.id)))
+        .child(Label::new(
+            "Tell this class' teachers when a student doesn't have enough time to finish their \
+            work before it's due (students are always told)",
+        ))
+        .child(
+            Select::new()
+                .attribute(Name::new("notify_teachers_of_overload"))
+                .child(option(false, "No"))
+                .child(option(true, "Yes")),
+        )
+        .child(
+            Input::new()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Save")),
+        )
+}
 
 #[get("/class/<id>/settings")]
 pub async fn get_class_settings(id: usize, auth_cookie: AuthCookie, conn: Database) -> Html {
     if get_user_role_in_class(auth_cookie.0 as i32, id as i32, &conn).await
         == Some(ClassMemberRole::Teacher)
     {
+        let class = match Class::with_id(id as i32, &conn).await {
+            Ok(class) => class,
+            Err(_) => return LovelaceError::DatabaseError.render(),
+        };
         Html::default()
             .head(default_head("Settings".to_string()))
             .body(
-                Body::default().child(H1::new("Settings")).child(
-                    Div::new().child(
-                        A::default()
-                            .attribute(Href::new(format!("/class/{}/delete", id)))
-                            .text("Delete this class."),
+                Body::default()
+                    .child(H1::new("Settings"))
+                    .child(settings_form(&class))
+                    .child(
+                        Div::new().child(
+                            A::default()
+                                .attribute(Href::new(format!("/class/{}/delete", id)))
+                                .text("Delete this class."),
+                        ),
                     ),
-                ),
             )
     } else {
         error_message(
@@ -37,3 +86,58 @@ pub async fn get_class_settings(id: usize, auth_cookie: AuthCookie, conn: Databa
         )
     }
 }
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct ClassSettingsForm {
+    notify_teachers_of_overload: bool,
+}
+
+async fn update_class_settings(
+    id: i32,
+    user_id: i32,
+    form: ClassSettingsForm,
+    conn: &Database,
+) -> Result<Class, LovelaceError> {
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, id, c) {
+            return Err(LovelaceError::PermissionError(None));
+        }
+        diesel::update(class::table.find(id))
+            .set(class::notify_teachers_of_overload.eq(form.notify_teachers_of_overload))
+            .get_result::<Class>(c)
+            .map_err(From::from)
+    })
+    .await
+}
+
+#[post("/class/<id>/settings", data = "<form>")]
+pub async fn html_update_class_settings(
+    id: i32,
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<ClassSettingsForm>,
+) -> Html {
+    match update_class_settings(id, auth.0, form.into_inner(), &conn).await {
+        Ok(class) => Html::new().head(default_head("Saved your settings")).body(
+            Body::new()
+                .child(H1::new("Saved your settings"))
+                .child(settings_form(&class)),
+        ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<id>/settings", data = "<form>")]
+pub async fn api_update_class_settings(
+    id: i32,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<ClassSettingsForm>,
+) -> Json<ApiResponse<Class>> {
+    Json(
+        match update_class_settings(id, auth.0, form.0, &conn).await {
+            Ok(class) => ApiResponse::new_ok(class),
+            Err(e) => From::from(e),
+        },
+    )
+}
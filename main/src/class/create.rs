@@ -14,7 +14,7 @@ use portia::{
 use rocket::serde::json::Json;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie, EmailVerification},
     db::Database,
     models::{NewClass, NewClassTeacher},
     schema::{class, class_teacher},
@@ -67,8 +67,10 @@ pub struct CreateClassForm {
 async fn create_class(
     data: &CreateClassForm,
     auth: AuthCookie,
+    verification: EmailVerification,
     conn: Database,
 ) -> LovelaceResult<crate::models::Class> {
+    verification.require()?;
     let data = data.clone();
     conn.run(move |c| {
         diesel::insert_into(class::table)
@@ -106,9 +108,10 @@ async fn create_class(
 pub async fn html_create_class(
     form: rocket::form::Form<CreateClassForm>,
     auth: AuthCookie,
+    verification: EmailVerification,
     conn: Database,
 ) -> Html {
-    match create_class(&form, auth, conn).await {
+    match create_class(&form, auth, verification, conn).await {
         Ok(class) => Html::default()
             .head(default_head("Successfully created".to_string()))
             .body(
@@ -127,11 +130,14 @@ pub async fn html_create_class(
 #[post("/class/create", data = "<data>")]
 pub async fn api_create_class(
     data: Json<CreateClassForm>,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageClasses>,
+    verification: EmailVerification,
     conn: Database,
 ) -> Json<ApiResponse<crate::models::Class>> {
-    Json(match create_class(&data, auth, conn).await {
-        Ok(class) => ApiResponse::new_ok(class),
-        Err(e) => From::from(e),
-    })
+    Json(
+        match create_class(&data, auth.into(), verification, conn).await {
+            Ok(class) => ApiResponse::new_ok(class),
+            Err(e) => From::from(e),
+        },
+    )
 }
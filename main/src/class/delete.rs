@@ -11,7 +11,7 @@ use rocket::serde::json::Json;
 use rocket::FromForm;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie},
     db::Database,
     utils::{default_head, error::LovelaceError, error_message, json_response::ApiResponse},
 };
@@ -147,7 +147,7 @@ pub async fn html_delete_class(
 #[post("/class/delete", data = "<form>")]
 pub async fn api_delete_class(
     form: Json<DeleteClassForm>,
-    auth_cookie: AuthCookie,
+    auth_cookie: ApiAuth<ManageClasses>,
     conn: Database,
 ) -> Json<ApiResponse<()>> {
     use crate::schema::class::dsl as class;
@@ -0,0 +1,272 @@
+//! The categories tasks can be put into in a class' gradebook.
+
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::{
+    form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle},
+    levels::Level,
+};
+use rocket::serde::json::Json;
+
+use super::GradebookError;
+use crate::{
+    auth::{
+        scope::{ManageTasks, ReadTasks},
+        ApiAuth, AuthCookie,
+    },
+    class::user_is_teacher,
+    db::Database,
+    models::{GradeCategory, NewGradeCategory},
+    schema::grade_category,
+    utils::{default_head, json_response::ApiResponse},
+};
+
+async fn list_categories(
+    class_id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<Vec<GradeCategory>, GradebookError> {
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, class_id, c) {
+            return Err(GradebookError::PermissionError);
+        }
+        grade_category::table
+            .filter(grade_category::class_id.eq(class_id))
+            .order_by(grade_category::name)
+            .load::<GradeCategory>(c)
+            .map_err(From::from)
+    })
+    .await
+}
+
+#[get("/<class_id>/gradebook/categories")]
+pub async fn html_view_categories(class_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let categories = match list_categories(class_id, auth.0, &conn).await {
+        Ok(categories) => categories,
+        Err(e) => return e.render(),
+    };
+    Html::new().head(default_head("Gradebook categories")).body(
+        Body::new()
+            .child(H1::new("Gradebook categories"))
+            .child(P::with_text(
+                "Students' overall averages are worked out from their average in each \
+                    category, weighted by the category's weight. Tasks which aren't in a category \
+                    only count if the class doesn't have any categories.",
+            ))
+            .child(
+                Level::new().children(categories.into_iter().map(|category| {
+                    Div::new()
+                        .child(H3::new(category.name))
+                        .child(P::with_text(format!("Weight: {}", category.weight)))
+                        .child(
+                            Form::new()
+                                .attribute(Method::Post)
+                                .attribute(Action::new(format!(
+                                    "/class/{}/gradebook/category/{}/delete",
+                                    class_id, category.id
+                                )))
+                                .child(
+                                    Input::new()
+                                        .apply(FormSubmitInputStyle)
+                                        .attribute(Type::Submit)
+                                        .attribute(Value::new("Delete this category")),
+                                ),
+                        )
+                })),
+            )
+            .child(H3::new("Add a category"))
+            .child(
+                Form::new()
+                    .apply(FormStyle)
+                    .attribute(Method::Post)
+                    .attribute(Action::new(format!(
+                        "/class/{}/gradebook/categories",
+                        class_id
+                    )))
+                    .child(Label::new("Name"))
+                    .child(
+                        Input::new()
+                            .apply(FormTextInputStyle)
+                            .attribute(Type::Text)
+                            .attribute(Name::new("name")),
+                    )
+                    .child(Label::new("Weight"))
+                    .child(
+                        Input::new()
+                            .apply(FormTextInputStyle)
+                            .attribute(Type::Text)
+                            .attribute(Name::new("weight"))
+                            .attribute(Value::new("1")),
+                    )
+                    .child(
+                        Input::new()
+                            .apply(FormSubmitInputStyle)
+                            .attribute(Type::Submit)
+                            .attribute(Value::new("Add category")),
+                    ),
+            ),
+    )
+}
+
+#[get("/<class_id>/gradebook/categories")]
+pub async fn api_view_categories(
+    class_id: i32,
+    auth: ApiAuth<ReadTasks>,
+    conn: Database,
+) -> Json<ApiResponse<Vec<GradeCategory>>> {
+    Json(match list_categories(class_id, auth.0, &conn).await {
+        Ok(categories) => ApiResponse::new_ok(categories),
+        Err(e) => From::from(e),
+    })
+}
+
+#[derive(FromForm, Serialize, Deserialize, Debug, Clone)]
+pub struct CategoryForm {
+    name: String,
+    /// How much the category counts, relative to the other categories in the class.
+    weight: i32,
+}
+
+async fn create_category(
+    class_id: i32,
+    user_id: i32,
+    form: CategoryForm,
+    conn: &Database,
+) -> Result<GradeCategory, GradebookError> {
+    let name = form.name.trim().to_string();
+    if name.is_empty() {
+        return Err(GradebookError::Invalid(
+            "Please give the category a name.".to_string(),
+        ));
+    }
+    if form.weight < 1 {
+        return Err(GradebookError::Invalid(
+            "The category's weight should be a whole number above zero.".to_string(),
+        ));
+    }
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, class_id, c) {
+            return Err(GradebookError::PermissionError);
+        }
+        diesel::insert_into(grade_category::table)
+            .values(NewGradeCategory {
+                class_id,
+                name: &name,
+                weight: form.weight,
+            })
+            .on_conflict_do_nothing()
+            .get_result::<GradeCategory>(c)
+            .optional()?
+            .ok_or_else(|| {
+                GradebookError::Invalid(format!(
+                    "This class already has a category called \"{}\".",
+                    name
+                ))
+            })
+    })
+    .await
+}
+
+#[post("/<class_id>/gradebook/categories", data = "<form>")]
+pub async fn html_create_category(
+    class_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<CategoryForm>,
+) -> Html {
+    match create_category(class_id, auth.0, form.into_inner(), &conn).await {
+        Ok(category) => Html::new().head(default_head("Added that category")).body(
+            Body::new()
+                .child(H1::new(format!("Added the category {}", category.name)))
+                .child(
+                    A::new()
+                        .attribute(Href::new(format!(
+                            "/class/{}/gradebook/categories",
+                            class_id
+                        )))
+                        .text("Back to the categories"),
+                ),
+        ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<class_id>/gradebook/categories", data = "<form>")]
+pub async fn api_create_category(
+    class_id: i32,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<CategoryForm>,
+) -> Json<ApiResponse<GradeCategory>> {
+    Json(
+        match create_category(class_id, auth.0, form.0, &conn).await {
+            Ok(category) => ApiResponse::new_ok(category),
+            Err(e) => From::from(e),
+        },
+    )
+}
+
+/// Deletes a category (the tasks in it are left without a category).
+async fn delete_category(
+    class_id: i32,
+    category_id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<(), GradebookError> {
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, class_id, c) {
+            return Err(GradebookError::PermissionError);
+        }
+        match diesel::delete(
+            grade_category::table
+                .filter(grade_category::id.eq(category_id))
+                .filter(grade_category::class_id.eq(class_id)),
+        )
+        .execute(c)?
+        {
+            0 => Err(GradebookError::NotFound),
+            _ => Ok(()),
+        }
+    })
+    .await
+}
+
+#[post("/<class_id>/gradebook/category/<category_id>/delete")]
+pub async fn html_delete_category(
+    class_id: i32,
+    category_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    match delete_category(class_id, category_id, auth.0, &conn).await {
+        Ok(()) => Html::new()
+            .head(default_head("Deleted that category"))
+            .body(
+                Body::new().child(H1::new("Deleted that category")).child(
+                    A::new()
+                        .attribute(Href::new(format!(
+                            "/class/{}/gradebook/categories",
+                            class_id
+                        )))
+                        .text("Back to the categories"),
+                ),
+            ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<class_id>/gradebook/category/<category_id>/delete")]
+pub async fn api_delete_category(
+    class_id: i32,
+    category_id: i32,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match delete_category(class_id, category_id, auth.0, &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => From::from(e),
+        },
+    )
+}
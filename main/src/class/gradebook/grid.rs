@@ -0,0 +1,231 @@
+//! The gradebook for a class: a grid with a row for each student and a column for each graded
+//! task, along with everybody's averages. It can also be downloaded as a CSV file.
+
+use malvolio::{prelude::*, text::Text};
+use rocket::{
+    http::{ContentType, Header},
+    serde::json::Json,
+};
+
+use super::{load_gradebook, Gradebook, GradebookError};
+use crate::{
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
+    class::user_is_teacher,
+    db::Database,
+    utils::{default_head, json_response::ApiResponse},
+};
+
+async fn get_gradebook(
+    class_id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<Gradebook, GradebookError> {
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, class_id, c) {
+            return Err(GradebookError::PermissionError);
+        }
+        load_gradebook(class_id, c).map_err(From::from)
+    })
+    .await
+}
+
+fn format_average(average: Option<f64>) -> String {
+    average
+        .map(|average| format!("{:.1}", average))
+        .unwrap_or_default()
+}
+
+impl Gradebook {
+    /// The titles of the columns of the grid.
+    fn headings(&self) -> Vec<String> {
+        let mut headings = vec!["Student".to_string()];
+        headings.extend(self.tasks.iter().map(|task| task.title.clone()));
+        headings.extend(
+            self.categories
+                .iter()
+                .map(|category| format!("{} average", category.name)),
+        );
+        headings.push("Overall average".to_string());
+        headings
+    }
+
+    /// The cells in each row of the grid (marks are shown as they would be to the student, and
+    /// averages as percentages).
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.students
+            .iter()
+            .map(|student| {
+                let mut row = vec![student.username.clone()];
+                row.extend(self.tasks.iter().zip(&student.scores).map(|(task, score)| {
+                    score
+                        .map(|score| task.grading.display(score))
+                        .unwrap_or_default()
+                }));
+                row.extend(
+                    student
+                        .category_averages
+                        .iter()
+                        .copied()
+                        .map(format_average),
+                );
+                row.push(format_average(student.overall));
+                row
+            })
+            .collect()
+    }
+}
+
+/// Malvolio doesn't have tables, so the grid is written out by hand (everything in it is escaped).
+fn render_grid(gradebook: &Gradebook) -> Text {
+    let escape = |text: &str| Text::new(text.to_string()).to_string();
+    let heading = gradebook
+        .headings()
+        .iter()
+        .map(|heading| format!("<th>{}</th>", escape(heading)))
+        .collect::<String>();
+    let rows = gradebook
+        .rows()
+        .into_iter()
+        .map(|row| {
+            let cells = row
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| match i {
+                    0 => format!("<th>{}</th>", escape(cell)),
+                    // the averages are percentages
+                    i if i > gradebook.tasks.len() && !cell.is_empty() => {
+                        format!("<td>{}%</td>", escape(cell))
+                    }
+                    _ => format!("<td>{}</td>", escape(cell)),
+                })
+                .collect::<String>();
+            format!("<tr>{}</tr>", cells)
+        })
+        .collect::<String>();
+    Text::new_unchecked(format!(
+        "<table><thead><tr>{}</tr></thead><tbody>{}</tbody></table>",
+        heading, rows
+    ))
+}
+
+#[get("/<class_id>/gradebook")]
+/// Shows the gradebook for the class (only to its teachers).
+pub async fn html_view_gradebook(class_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let gradebook = match get_gradebook(class_id, auth.0, &conn).await {
+        Ok(gradebook) => gradebook,
+        Err(e) => return e.render(),
+    };
+    let links = Div::new()
+        .child(
+            A::new()
+                .attribute(Href::new(format!("/class/{}/gradebook/export", class_id)))
+                .text("Download as a CSV file"),
+        )
+        .child(
+            A::new()
+                .attribute(Href::new(format!(
+                    "/class/{}/gradebook/categories",
+                    class_id
+                )))
+                .text("Categories"),
+        );
+    Html::new().head(default_head("Gradebook")).body(
+        Body::new()
+            .child(H1::new("Gradebook"))
+            .child(links)
+            .map(|body| {
+                if gradebook.tasks.is_empty() {
+                    body.child(P::with_text(
+                        "None of this class' tasks are graded yet. You can choose how a task is \
+                        graded from its marks page.",
+                    ))
+                } else {
+                    body.child(render_grid(&gradebook))
+                        .children(gradebook.tasks.iter().map(|task| {
+                            A::new()
+                                .attribute(Href::new(format!(
+                                    "/class/{}/task/async/{}/marks",
+                                    class_id, task.task_id
+                                )))
+                                .text(format!("Mark {}", task.title))
+                        }))
+                }
+            }),
+    )
+}
+
+#[get("/<class_id>/gradebook")]
+pub async fn api_view_gradebook(
+    class_id: i32,
+    auth: ApiAuth<ReadTasks>,
+    conn: Database,
+) -> Json<ApiResponse<Gradebook>> {
+    Json(match get_gradebook(class_id, auth.0, &conn).await {
+        Ok(gradebook) => ApiResponse::new_ok(gradebook),
+        Err(e) => From::from(e),
+    })
+}
+
+/// Stops spreadsheet programs from treating a cell (e.g. a task called `=HYPERLINK(...)`) as a
+/// formula, by putting a `'` in front of anything which could start one.
+fn csv_cell(cell: String) -> String {
+    if cell.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) {
+        format!("'{}", cell)
+    } else {
+        cell
+    }
+}
+
+#[derive(Responder, Debug)]
+pub struct GradebookExport {
+    contents: (ContentType, String),
+    disposition: Header<'static>,
+}
+
+#[get("/<class_id>/gradebook/export")]
+/// Downloads the gradebook as a CSV file, with the same columns as the grid.
+pub async fn export_gradebook(
+    class_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Result<GradebookExport, Html> {
+    let gradebook = get_gradebook(class_id, auth.0, &conn)
+        .await
+        .map_err(GradebookError::render)?;
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let written = std::iter::once(gradebook.headings())
+        .chain(gradebook.rows())
+        .try_for_each(|row| writer.write_record(row.into_iter().map(csv_cell)));
+    let contents = written
+        .map_err(|e| e.to_string())
+        .and_then(|_| writer.into_inner().map_err(|e| e.to_string()))
+        .and_then(|contents| String::from_utf8(contents).map_err(|e| e.to_string()))
+        .map_err(|e| {
+            error!("{}", e);
+            GradebookError::DatabaseError.render()
+        })?;
+    Ok(GradebookExport {
+        contents: (ContentType::CSV, contents),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"gradebook-{}.csv\"", class_id),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod test_grid {
+    use super::csv_cell;
+
+    #[test]
+    fn test_csv_cells_cannot_be_formulas() {
+        assert_eq!(csv_cell("17/20".to_string()), "17/20");
+        assert_eq!(
+            csv_cell("=HYPERLINK(\"https://example.com\")".to_string()),
+            "'=HYPERLINK(\"https://example.com\")"
+        );
+        for start in &["+", "-", "@", "\t", "\r"] {
+            assert_eq!(csv_cell(format!("{}1", start)), format!("'{}1", start));
+        }
+    }
+}
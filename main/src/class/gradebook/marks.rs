@@ -0,0 +1,538 @@
+//! Choosing how an asynchronous task is graded, and marking the work students have done for it.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
+use rocket::serde::json::Json;
+
+use super::{GradebookError, Grading, GradingScheme};
+use crate::{
+    auth::{
+        scope::{ManageTasks, ReadTasks},
+        ApiAuth, AuthCookie,
+    },
+    class::user_is_teacher,
+    db::{Database, DatabaseConnection},
+    models::{
+        AsyncTaskGrading, AsyncTaskMark, ClassAsynchronousTask, GradeCategory, NewAsyncTaskGrading,
+        NewAsyncTaskMark,
+    },
+    schema::{
+        async_task_grading, async_task_mark, class_asynchronous_task, class_student,
+        grade_category, student_class_asynchronous_task, users,
+    },
+    utils::{default_head, json_response::ApiResponse},
+};
+
+/// A student who has been set the task, and the mark they've been given (if they've been marked).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MarkEntry {
+    pub student_class_asynchronous_task_id: i32,
+    pub username: String,
+    /// The mark as a percentage.
+    pub score: Option<f64>,
+    /// The mark as it's shown to the student (e.g. "17/20").
+    pub mark: Option<String>,
+    pub feedback: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskMarks {
+    pub task: ClassAsynchronousTask,
+    /// This is `None` if the teacher hasn't chosen how the task is graded yet.
+    pub grading: Option<AsyncTaskGrading>,
+    /// The categories the task could be put into.
+    pub categories: Vec<GradeCategory>,
+    pub students: Vec<MarkEntry>,
+}
+
+fn load_task_marks(class_id: i32, task_id: i32, c: &DatabaseConnection) -> QueryResult<TaskMarks> {
+    let task = class_asynchronous_task::table
+        .filter(class_asynchronous_task::id.eq(task_id))
+        .filter(class_asynchronous_task::class_id.eq(class_id))
+        .first::<ClassAsynchronousTask>(c)?;
+    let grading = async_task_grading::table
+        .filter(async_task_grading::class_asynchronous_task_id.eq(task_id))
+        .first::<AsyncTaskGrading>(c)
+        .optional()?;
+    let categories = grade_category::table
+        .filter(grade_category::class_id.eq(class_id))
+        .order_by(grade_category::name)
+        .load::<GradeCategory>(c)?;
+    let students = student_class_asynchronous_task::table
+        .filter(student_class_asynchronous_task::class_asynchronous_task_id.eq(task_id))
+        .inner_join(class_student::table.inner_join(users::table))
+        .left_join(async_task_mark::table)
+        .order_by(users::username)
+        .select((
+            student_class_asynchronous_task::id,
+            users::username,
+            async_task_mark::all_columns.nullable(),
+        ))
+        .load::<(i32, String, Option<AsyncTaskMark>)>(c)?;
+    let scheme = grading.as_ref().map(Grading::of);
+    Ok(TaskMarks {
+        task,
+        grading,
+        categories,
+        students: students
+            .into_iter()
+            .map(|(id, username, mark)| MarkEntry {
+                student_class_asynchronous_task_id: id,
+                username,
+                score: mark.as_ref().map(|mark| mark.score),
+                mark: mark
+                    .as_ref()
+                    .and_then(|mark| scheme.map(|scheme| scheme.display(mark.score))),
+                feedback: mark.and_then(|mark| mark.feedback),
+            })
+            .collect(),
+    })
+}
+
+async fn get_task_marks(
+    class_id: i32,
+    task_id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<TaskMarks, GradebookError> {
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, class_id, c) {
+            return Err(GradebookError::PermissionError);
+        }
+        load_task_marks(class_id, task_id, c).map_err(From::from)
+    })
+    .await
+}
+
+/// The form through which teachers choose how the task is graded.
+fn grading_form(class_id: i32, task_id: i32, marks: &TaskMarks) -> Form {
+    let current = marks.grading.as_ref();
+    let scheme = current.map(|grading| GradingScheme::from(grading.scheme));
+    let selected = |option: SelectOption, selected: bool| {
+        if selected {
+            option.raw_attribute("selected", "selected")
+        } else {
+            option
+        }
+    };
+    Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "/class/{}/task/async/{}/grading",
+            class_id, task_id
+        )))
+        .child(Label::new("How is this task graded?"))
+        .child(GradingScheme::ALL.iter().fold(
+            Select::new().attribute(Name::new("scheme")),
+            |select, option| {
+                select.child(selected(
+                    SelectOption::new()
+                        .attribute(Value::new(option.value()))
+                        .text(option.describe()),
+                    Some(*option) == scheme,
+                ))
+            },
+        ))
+        .child(Label::new(
+            "Maximum number of points (if it's marked out of a number of points)",
+        ))
+        .child(
+            Input::new()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Name::new("max_points"))
+                .map(
+                    |input| match current.and_then(|grading| grading.max_points) {
+                        Some(max_points) => input.attribute(Value::new(max_points.to_string())),
+                        None => input,
+                    },
+                ),
+        )
+        .child(Label::new("Category"))
+        .child(
+            marks.categories.iter().fold(
+                Select::new().attribute(Name::new("category")).child(
+                    SelectOption::new()
+                        .attribute(Value::new(""))
+                        .text("No category"),
+                ),
+                |select, category| {
+                    select.child(selected(
+                        SelectOption::new()
+                            .attribute(Value::new(category.id.to_string()))
+                            .text(category.name.clone()),
+                        current.and_then(|grading| grading.grade_category_id) == Some(category.id),
+                    ))
+                },
+            ),
+        )
+        .child(Label::new("Can students see their marks?"))
+        .child(
+            Select::new()
+                .attribute(Name::new("released"))
+                .child(selected(
+                    SelectOption::new()
+                        .attribute(Value::new("false"))
+                        .text("Not yet"),
+                    !current.map(|grading| grading.released).unwrap_or(false),
+                ))
+                .child(selected(
+                    SelectOption::new()
+                        .attribute(Value::new("true"))
+                        .text("Yes, release the marks"),
+                    current.map(|grading| grading.released).unwrap_or(false),
+                )),
+        )
+        .child(
+            Input::new()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Save")),
+        )
+}
+
+/// The form through which teachers mark each student's work.
+fn marks_form(class_id: i32, task_id: i32, marks: &TaskMarks) -> Form {
+    marks
+        .students
+        .iter()
+        .fold(
+            Form::new()
+                .apply(FormStyle)
+                .attribute(Method::Post)
+                .attribute(Action::new(format!(
+                    "/class/{}/task/async/{}/marks",
+                    class_id, task_id
+                ))),
+            |form, student| {
+                let id = student.student_class_asynchronous_task_id;
+                form.child(
+                    Div::new()
+                        .child(Label::new(student.username.clone()))
+                        .child(
+                            Input::new()
+                                .apply(FormTextInputStyle)
+                                .attribute(Type::Text)
+                                .attribute(Name::new(format!("marks[{}]", id)))
+                                .attribute(Placeholder::new("Mark"))
+                                .map(|input| match &student.mark {
+                                    Some(mark) => input.attribute(Value::new(mark.clone())),
+                                    None => input,
+                                }),
+                        )
+                        .child(
+                            Input::new()
+                                .apply(FormTextInputStyle)
+                                .attribute(Type::Text)
+                                .attribute(Name::new(format!("feedback[{}]", id)))
+                                .attribute(Placeholder::new("Feedback (optional)"))
+                                .map(|input| match &student.feedback {
+                                    Some(feedback) => input.attribute(Value::new(feedback.clone())),
+                                    None => input,
+                                }),
+                        ),
+                )
+            },
+        )
+        .child(
+            Input::new()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Save the marks")),
+        )
+}
+
+#[get("/<class_id>/task/async/<task_id>/marks")]
+/// Lets teachers choose how a task is graded, and mark everybody's work for it on one page.
+pub async fn html_view_marks(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    let marks = match get_task_marks(class_id, task_id, auth.0, &conn).await {
+        Ok(marks) => marks,
+        Err(e) => return e.render(),
+    };
+    Html::new()
+        .head(default_head(format!("Marks for {}", marks.task.title)))
+        .body(
+            Body::new()
+                .child(H1::new(format!("Marks for {}", marks.task.title)))
+                .child(grading_form(class_id, task_id, &marks))
+                .map(|body| match &marks.grading {
+                    None => body.child(P::with_text(
+                        "Choose how this task is graded to start marking it.",
+                    )),
+                    Some(_) if marks.students.is_empty() => {
+                        body.child(P::with_text("Nobody has been set this task."))
+                    }
+                    Some(grading) => body
+                        .child(H3::new("Marks"))
+                        .child(P::with_text(match grading.scheme.into() {
+                            GradingScheme::Points => format!(
+                                "Enter the number of points (out of {}) each student scored.",
+                                grading.max_points.unwrap_or(100)
+                            ),
+                            GradingScheme::Percentage => {
+                                "Enter each student's percentage.".to_string()
+                            }
+                            GradingScheme::Letter => format!(
+                                "Enter each student's grade ({}).",
+                                super::LETTER_GRADES.join(", ")
+                            ),
+                            GradingScheme::PassFail => {
+                                "Enter \"pass\" or \"fail\" for each student.".to_string()
+                            }
+                        }))
+                        .child(marks_form(class_id, task_id, &marks)),
+                }),
+        )
+}
+
+#[get("/<class_id>/task/async/<task_id>/marks")]
+pub async fn api_view_marks(
+    class_id: i32,
+    task_id: i32,
+    auth: ApiAuth<ReadTasks>,
+    conn: Database,
+) -> Json<ApiResponse<TaskMarks>> {
+    Json(
+        match get_task_marks(class_id, task_id, auth.0, &conn).await {
+            Ok(marks) => ApiResponse::new_ok(marks),
+            Err(e) => From::from(e),
+        },
+    )
+}
+
+#[derive(FromForm, Serialize, Deserialize, Debug, Clone)]
+pub struct GradingForm {
+    scheme: GradingScheme,
+    /// Required if the task is marked out of a number of points.
+    #[serde(default)]
+    max_points: Option<i32>,
+    /// The id of the category to put the task in.
+    #[serde(default)]
+    category: Option<i32>,
+    /// Whether students can see their marks.
+    #[serde(default)]
+    released: bool,
+}
+
+async fn set_grading(
+    class_id: i32,
+    task_id: i32,
+    user_id: i32,
+    form: GradingForm,
+    conn: &Database,
+) -> Result<TaskMarks, GradebookError> {
+    let max_points =
+        match (form.scheme, form.max_points) {
+            (GradingScheme::Points, Some(max_points)) if max_points > 0 => Some(max_points),
+            (GradingScheme::Points, _) => return Err(GradebookError::Invalid(
+                "Please give the maximum number of points (a whole number above zero) this task \
+                is marked out of."
+                    .to_string(),
+            )),
+            _ => None,
+        };
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, class_id, c) {
+            return Err(GradebookError::PermissionError);
+        }
+        c.transaction::<_, GradebookError, _>(|| {
+            class_asynchronous_task::table
+                .filter(class_asynchronous_task::id.eq(task_id))
+                .filter(class_asynchronous_task::class_id.eq(class_id))
+                .select(class_asynchronous_task::id)
+                .first::<i32>(c)?;
+            if let Some(category) = form.category {
+                if grade_category::table
+                    .filter(grade_category::id.eq(category))
+                    .filter(grade_category::class_id.eq(class_id))
+                    .select(grade_category::id)
+                    .first::<i32>(c)
+                    .optional()?
+                    .is_none()
+                {
+                    return Err(GradebookError::NotFound);
+                }
+            }
+            let grading = NewAsyncTaskGrading {
+                class_asynchronous_task_id: task_id,
+                scheme: form.scheme.into(),
+                max_points,
+                grade_category_id: form.category,
+                released: form.released,
+            };
+            diesel::insert_into(async_task_grading::table)
+                .values(&grading)
+                .on_conflict(async_task_grading::class_asynchronous_task_id)
+                .do_update()
+                .set(&grading)
+                .execute(c)?;
+            load_task_marks(class_id, task_id, c).map_err(From::from)
+        })
+    })
+    .await
+}
+
+#[post("/<class_id>/task/async/<task_id>/grading", data = "<form>")]
+pub async fn html_set_grading(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<GradingForm>,
+) -> Html {
+    match set_grading(class_id, task_id, auth.0, form.into_inner(), &conn).await {
+        Ok(_) => Html::new()
+            .head(default_head("Saved how this task is graded"))
+            .body(
+                Body::new()
+                    .child(H1::new("Saved how this task is graded."))
+                    .child(
+                        A::new()
+                            .attribute(Href::new(format!(
+                                "/class/{}/task/async/{}/marks",
+                                class_id, task_id
+                            )))
+                            .text("Back to the marks"),
+                    ),
+            ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<class_id>/task/async/<task_id>/grading", data = "<form>")]
+pub async fn api_set_grading(
+    class_id: i32,
+    task_id: i32,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<GradingForm>,
+) -> Json<ApiResponse<TaskMarks>> {
+    Json(
+        match set_grading(class_id, task_id, auth.0, form.0, &conn).await {
+            Ok(marks) => ApiResponse::new_ok(marks),
+            Err(e) => From::from(e),
+        },
+    )
+}
+
+/// The marks (and feedback) given to each student, by the id of their
+/// `student_class_asynchronous_task` record. A blank mark removes the student's mark; students who
+/// aren't included aren't changed.
+#[derive(FromForm, Serialize, Deserialize, Debug, Clone)]
+pub struct MarksForm {
+    marks: HashMap<i32, String>,
+    #[serde(default)]
+    feedback: HashMap<i32, String>,
+}
+
+async fn save_marks(
+    class_id: i32,
+    task_id: i32,
+    user_id: i32,
+    form: MarksForm,
+    conn: &Database,
+) -> Result<TaskMarks, GradebookError> {
+    conn.run(move |c| {
+        if !user_is_teacher(user_id, class_id, c) {
+            return Err(GradebookError::PermissionError);
+        }
+        c.transaction::<_, GradebookError, _>(|| {
+            let current = load_task_marks(class_id, task_id, c)?;
+            let grading = match &current.grading {
+                Some(grading) => Grading::of(grading),
+                None => {
+                    return Err(GradebookError::Invalid(
+                        "Please choose how this task is graded before marking it.".to_string(),
+                    ))
+                }
+            };
+            let marked = Utc::now().naive_utc();
+            for (id, mark) in &form.marks {
+                if !current
+                    .students
+                    .iter()
+                    .any(|student| student.student_class_asynchronous_task_id == *id)
+                {
+                    return Err(GradebookError::NotFound);
+                }
+                if mark.trim().is_empty() {
+                    diesel::delete(
+                        async_task_mark::table
+                            .filter(async_task_mark::student_class_asynchronous_task_id.eq(id)),
+                    )
+                    .execute(c)?;
+                    continue;
+                }
+                let record = NewAsyncTaskMark {
+                    student_class_asynchronous_task_id: *id,
+                    score: grading.parse(mark)?,
+                    feedback: form
+                        .feedback
+                        .get(id)
+                        .map(|feedback| feedback.trim())
+                        .filter(|feedback| !feedback.is_empty()),
+                    marked_by: Some(user_id),
+                    marked,
+                };
+                diesel::insert_into(async_task_mark::table)
+                    .values(&record)
+                    .on_conflict(async_task_mark::student_class_asynchronous_task_id)
+                    .do_update()
+                    .set(&record)
+                    .execute(c)?;
+            }
+            load_task_marks(class_id, task_id, c).map_err(From::from)
+        })
+    })
+    .await
+}
+
+#[post("/<class_id>/task/async/<task_id>/marks", data = "<form>")]
+pub async fn html_save_marks(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<MarksForm>,
+) -> Html {
+    match save_marks(class_id, task_id, auth.0, form.into_inner(), &conn).await {
+        Ok(_) => Html::new().head(default_head("Saved the marks")).body(
+            Body::new().child(H1::new("Saved the marks.")).child(
+                A::new()
+                    .attribute(Href::new(format!(
+                        "/class/{}/task/async/{}/marks",
+                        class_id, task_id
+                    )))
+                    .text("Back to the marks"),
+            ),
+        ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<class_id>/task/async/<task_id>/marks", data = "<form>")]
+pub async fn api_save_marks(
+    class_id: i32,
+    task_id: i32,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<MarksForm>,
+) -> Json<ApiResponse<TaskMarks>> {
+    Json(
+        match save_marks(class_id, task_id, auth.0, form.0, &conn).await {
+            Ok(marks) => ApiResponse::new_ok(marks),
+            Err(e) => From::from(e),
+        },
+    )
+}
@@ -0,0 +1,786 @@
+//! The gradebook.
+//!
+//! Teachers choose how each asynchronous task is graded (with one of the [`GradingScheme`]s), and
+//! then give each student who was set the task a mark and (optionally) some written feedback.
+//! Students can only see their marks once the teacher has released them.
+//!
+//! Whichever scheme is used, marks are stored as percentages, so that they can be averaged. Tasks
+//! can be put into categories; a student's overall average is the average of their averages in
+//! each category, weighted by the categories' weights. If a class doesn't have any categories,
+//! every task counts equally.
+
+pub mod categories;
+pub mod grid;
+pub mod marks;
+
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use rocket::http::Status;
+use thiserror::Error as ThisError;
+
+use crate::{
+    db::DatabaseConnection,
+    models::{AsyncTaskGrading, AsyncTaskMark, ClassStudent, GradeCategory, User},
+    schema::{
+        async_task_grading, async_task_mark, class_asynchronous_task, class_student,
+        grade_category, student_class_asynchronous_task, users,
+    },
+    utils::{default_head, json_response::ApiResponse},
+};
+
+/// The letters used by [`GradingScheme::Letter`], from best to worst.
+pub const LETTER_GRADES: [&str; 7] = ["A*", "A", "B", "C", "D", "E", "U"];
+
+#[derive(FromFormField, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GradingScheme {
+    /// A number of points out of the task's maximum.
+    #[field(value = "points")]
+    Points,
+    #[field(value = "percentage")]
+    Percentage,
+    /// One of the [`LETTER_GRADES`]. These are spread evenly between 100% (for the best) and 0%
+    /// (for the worst).
+    #[field(value = "letter")]
+    Letter,
+    #[field(value = "pass_fail")]
+    PassFail,
+}
+
+impl GradingScheme {
+    pub const ALL: [GradingScheme; 4] = [
+        GradingScheme::Points,
+        GradingScheme::Percentage,
+        GradingScheme::Letter,
+        GradingScheme::PassFail,
+    ];
+
+    /// The value used for this scheme in forms (and in the API).
+    pub fn value(&self) -> &'static str {
+        match self {
+            GradingScheme::Points => "points",
+            GradingScheme::Percentage => "percentage",
+            GradingScheme::Letter => "letter",
+            GradingScheme::PassFail => "pass_fail",
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            GradingScheme::Points => "Points out of a maximum",
+            GradingScheme::Percentage => "Percentage",
+            GradingScheme::Letter => "Letter grades (A* to U)",
+            GradingScheme::PassFail => "Pass or fail",
+        }
+    }
+}
+
+impl From<GradingScheme> for i16 {
+    fn from(from: GradingScheme) -> Self {
+        match from {
+            GradingScheme::Points => 1,
+            GradingScheme::Percentage => 2,
+            GradingScheme::Letter => 3,
+            GradingScheme::PassFail => 4,
+        }
+    }
+}
+
+impl From<i16> for GradingScheme {
+    /// Converts a row in the database to a `GradingScheme`, `panic`-ing if the database contains
+    /// invalid data. To make sure that this never happens, only ever insert `GradingScheme`s
+    /// (converted with `Into<i16>`) into the `async_task_grading.scheme` column.
+    fn from(number: i16) -> Self {
+        match number {
+            1 => Self::Points,
+            2 => Self::Percentage,
+            3 => Self::Letter,
+            4 => Self::PassFail,
+            number => {
+                error!("Invalid number in database: {}", number);
+                panic!()
+            }
+        }
+    }
+}
+
+/// Formats a number with at most two decimal places (and without any trailing zeroes).
+fn format_number(number: f64) -> String {
+    let formatted = format!("{:.2}", number);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// How a task is graded – this converts marks to and from the percentages they're stored as.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Grading {
+    pub scheme: GradingScheme,
+    pub max_points: Option<i32>,
+}
+
+impl Grading {
+    pub fn of(grading: &AsyncTaskGrading) -> Self {
+        Self {
+            scheme: grading.scheme.into(),
+            max_points: grading.max_points,
+        }
+    }
+
+    /// Parses a mark a teacher has entered, returning it as a percentage.
+    pub fn parse(&self, mark: &str) -> Result<f64, GradebookError> {
+        let mark = mark.trim();
+        let number = |mark: &str, max: f64| {
+            mark.parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite() && *number >= 0.0 && *number <= max)
+                .ok_or_else(|| {
+                    GradebookError::Invalid(format!(
+                        "\"{}\" isn't a valid mark – it should be a number between 0 and {}.",
+                        mark, max
+                    ))
+                })
+        };
+        match self.scheme {
+            GradingScheme::Points => {
+                let max = self.max_points.unwrap_or(100) as f64;
+                let mark = mark.split('/').next().unwrap_or_default().trim();
+                number(mark, max).map(|points| points * 100.0 / max)
+            }
+            GradingScheme::Percentage => number(mark.trim_end_matches('%').trim(), 100.0),
+            GradingScheme::Letter => LETTER_GRADES
+                .iter()
+                .position(|letter| letter.eq_ignore_ascii_case(mark))
+                .map(|position| {
+                    (LETTER_GRADES.len() - 1 - position) as f64 * 100.0
+                        / (LETTER_GRADES.len() - 1) as f64
+                })
+                .ok_or_else(|| {
+                    GradebookError::Invalid(format!(
+                        "\"{}\" isn't a valid mark – it should be one of {}.",
+                        mark,
+                        LETTER_GRADES.join(", ")
+                    ))
+                }),
+            GradingScheme::PassFail => match mark.to_lowercase().as_str() {
+                "pass" => Ok(100.0),
+                "fail" => Ok(0.0),
+                _ => Err(GradebookError::Invalid(format!(
+                    "\"{}\" isn't a valid mark – it should be either \"pass\" or \"fail\".",
+                    mark
+                ))),
+            },
+        }
+    }
+
+    /// Shows a mark (which is stored as a percentage) in the way the task is graded.
+    pub fn display(&self, score: f64) -> String {
+        match self.scheme {
+            GradingScheme::Points => {
+                let max = self.max_points.unwrap_or(100);
+                format!("{}/{}", format_number(score * max as f64 / 100.0), max)
+            }
+            GradingScheme::Percentage => format!("{}%", format_number(score)),
+            GradingScheme::Letter => {
+                let steps = (LETTER_GRADES.len() - 1) as f64;
+                let position = ((100.0 - score) * steps / 100.0).round() as usize;
+                LETTER_GRADES[position.min(LETTER_GRADES.len() - 1)].to_string()
+            }
+            GradingScheme::PassFail => {
+                if score >= 50.0 {
+                    "Pass".to_string()
+                } else {
+                    "Fail".to_string()
+                }
+            }
+        }
+    }
+}
+
+#[derive(ThisError, Debug, Clone, PartialEq)]
+pub enum GradebookError {
+    #[error("database error")]
+    DatabaseError,
+    #[error("permission error")]
+    PermissionError,
+    #[error("not found")]
+    NotFound,
+    /// Something the teacher entered couldn't be used (the reason is shown to them).
+    #[error("invalid input")]
+    Invalid(String),
+}
+
+impl From<diesel::result::Error> for GradebookError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::NotFound => Self::NotFound,
+            e => {
+                error!("{:#?}", e);
+                Self::DatabaseError
+            }
+        }
+    }
+}
+
+impl GradebookError {
+    fn status(&self) -> Status {
+        match self {
+            GradebookError::DatabaseError => Status::InternalServerError,
+            GradebookError::PermissionError => Status::Forbidden,
+            GradebookError::NotFound => Status::NotFound,
+            GradebookError::Invalid(_) => Status::BadRequest,
+        }
+    }
+
+    pub fn explanation(&self) -> String {
+        match self {
+            GradebookError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+            GradebookError::PermissionError => {
+                "You don't have permission to do this – only a class' teachers can see (and change) \
+                its gradebook."
+                    .to_string()
+            }
+            GradebookError::NotFound => "That task (or category) doesn't exist.".to_string(),
+            GradebookError::Invalid(reason) => reason.clone(),
+        }
+    }
+
+    pub fn render(self) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head("Gradebook"))
+            .body(
+                Body::new()
+                    .child(H1::new(match self {
+                        GradebookError::Invalid(_) => "Couldn't save that",
+                        _ => "Gradebook error",
+                    }))
+                    .child(P::with_text(self.explanation())),
+            )
+    }
+}
+
+impl<T> From<GradebookError> for ApiResponse<T> {
+    fn from(e: GradebookError) -> Self {
+        ApiResponse::new_err(e.explanation())
+    }
+}
+
+/// A mark which has been released to the student it was given to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReleasedMark {
+    pub grading: Grading,
+    /// The mark as it would be shown to the student (e.g. "17/20" or "B").
+    pub mark: String,
+    /// The mark as a percentage.
+    pub score: f64,
+    pub feedback: Option<String>,
+    pub marked: NaiveDateTime,
+}
+
+/// The mark given for this `student_class_asynchronous_task`, if it has been released.
+pub fn released_mark(
+    student_class_asynchronous_task_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<Option<ReleasedMark>> {
+    async_task_mark::table
+        .filter(
+            async_task_mark::student_class_asynchronous_task_id
+                .eq(student_class_asynchronous_task_id),
+        )
+        .inner_join(
+            student_class_asynchronous_task::table
+                .inner_join(class_asynchronous_task::table.inner_join(async_task_grading::table)),
+        )
+        .filter(async_task_grading::released.eq(true))
+        .select((
+            async_task_mark::all_columns,
+            async_task_grading::all_columns,
+        ))
+        .first::<(AsyncTaskMark, AsyncTaskGrading)>(c)
+        .optional()
+        .map(|mark| {
+            mark.map(|(mark, grading)| {
+                let grading = Grading::of(&grading);
+                ReleasedMark {
+                    grading,
+                    mark: grading.display(mark.score),
+                    score: mark.score,
+                    feedback: mark.feedback,
+                    marked: mark.marked,
+                }
+            })
+        })
+}
+
+/// A task which is graded, as it appears in the gradebook.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GradedTask {
+    pub task_id: i32,
+    pub title: String,
+    pub due_date: NaiveDateTime,
+    pub grading: Grading,
+    pub grade_category_id: Option<i32>,
+    pub released: bool,
+}
+
+/// A student's row in the gradebook.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GradebookRow {
+    pub user_id: i32,
+    pub username: String,
+    /// The student's mark (as a percentage) for each of the tasks, in the same order as
+    /// [`Gradebook::tasks`]. This is `None` if the student hasn't been marked (or wasn't set the
+    /// task).
+    pub scores: Vec<Option<f64>>,
+    /// The student's average in each of the categories, in the same order as
+    /// [`Gradebook::categories`].
+    pub category_averages: Vec<Option<f64>>,
+    pub overall: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Gradebook {
+    pub categories: Vec<GradeCategory>,
+    /// In the order they're due.
+    pub tasks: Vec<GradedTask>,
+    /// In alphabetical order.
+    pub students: Vec<GradebookRow>,
+}
+
+fn mean(scores: impl Iterator<Item = f64>) -> Option<f64> {
+    let (total, count) = scores.fold((0.0, 0), |(total, count), score| (total + score, count + 1));
+    if count == 0 {
+        None
+    } else {
+        Some(total / count as f64)
+    }
+}
+
+/// Works out a student's average in each category, and their overall average (see the module
+/// documentation). Categories (and, if there aren't any categories, tasks) which the student
+/// hasn't been marked for yet are left out.
+pub fn averages(
+    categories: &[GradeCategory],
+    tasks: &[GradedTask],
+    scores: &[Option<f64>],
+) -> (Vec<Option<f64>>, Option<f64>) {
+    if categories.is_empty() {
+        return (vec![], mean(scores.iter().flatten().copied()));
+    }
+    let category_averages = categories
+        .iter()
+        .map(|category| {
+            mean(
+                tasks
+                    .iter()
+                    .zip(scores)
+                    .filter(|(task, _)| task.grade_category_id == Some(category.id))
+                    .filter_map(|(_, score)| *score),
+            )
+        })
+        .collect::<Vec<_>>();
+    let (total, weights) = categories.iter().zip(&category_averages).fold(
+        (0.0, 0),
+        |(total, weights), (category, average)| match average {
+            Some(average) => (
+                total + average * category.weight as f64,
+                weights + category.weight,
+            ),
+            None => (total, weights),
+        },
+    );
+    let overall = if weights == 0 {
+        None
+    } else {
+        Some(total / weights as f64)
+    };
+    (category_averages, overall)
+}
+
+/// Loads the gradebook for a class. Only tasks which are graded are included.
+pub fn load_gradebook(class_id: i32, c: &DatabaseConnection) -> QueryResult<Gradebook> {
+    let categories = grade_category::table
+        .filter(grade_category::class_id.eq(class_id))
+        .order_by(grade_category::name)
+        .load::<GradeCategory>(c)?;
+    let tasks = class_asynchronous_task::table
+        .filter(class_asynchronous_task::class_id.eq(class_id))
+        .inner_join(async_task_grading::table)
+        .order_by((
+            class_asynchronous_task::due_date,
+            class_asynchronous_task::id,
+        ))
+        .select((
+            class_asynchronous_task::id,
+            class_asynchronous_task::title,
+            class_asynchronous_task::due_date,
+            async_task_grading::all_columns,
+        ))
+        .load::<(i32, String, NaiveDateTime, AsyncTaskGrading)>(c)?
+        .into_iter()
+        .map(|(task_id, title, due_date, grading)| GradedTask {
+            task_id,
+            title,
+            due_date,
+            grading: Grading::of(&grading),
+            grade_category_id: grading.grade_category_id,
+            released: grading.released,
+        })
+        .collect::<Vec<_>>();
+    let students = class_student::table
+        .filter(class_student::class_id.eq(class_id))
+        .inner_join(users::table)
+        .order_by(users::username)
+        .load::<(ClassStudent, User)>(c)?;
+    let marks = async_task_mark::table
+        .inner_join(student_class_asynchronous_task::table.inner_join(class_student::table))
+        .filter(class_student::class_id.eq(class_id))
+        .select((
+            class_student::id,
+            student_class_asynchronous_task::class_asynchronous_task_id,
+            async_task_mark::score,
+        ))
+        .load::<(i32, i32, f64)>(c)?
+        .into_iter()
+        .map(|(class_student_id, task_id, score)| ((class_student_id, task_id), score))
+        .collect::<HashMap<_, _>>();
+    let students = students
+        .into_iter()
+        .map(|(class_student, user)| {
+            let scores = tasks
+                .iter()
+                .map(|task| marks.get(&(class_student.id, task.task_id)).copied())
+                .collect::<Vec<_>>();
+            let (category_averages, overall) = averages(&categories, &tasks, &scores);
+            GradebookRow {
+                user_id: user.id,
+                username: user.username,
+                scores,
+                category_averages,
+                overall,
+            }
+        })
+        .collect();
+    Ok(Gradebook {
+        categories,
+        tasks,
+        students,
+    })
+}
+
+#[cfg(test)]
+mod test_gradebook {
+    use chrono::{NaiveDate, Utc};
+    use diesel::prelude::*;
+    use rocket::http::{ContentType, Status};
+
+    use super::{averages, GradedTask, Grading, GradingScheme};
+    use crate::{
+        db::Database,
+        institution::test_ctx::{
+            setup_env, STUDENT_PASSWORD, STUDENT_USERNAME, TEACHER_PASSWORD, TEACHER_USERNAME,
+        },
+        models::{
+            GradeCategory, NewClass, NewClassAsynchronousTask, NewClassStudent, NewClassTeacher,
+            NewStudentClassAsynchronousTask,
+        },
+        schema::{
+            class, class_asynchronous_task, class_student, class_teacher, grade_category,
+            student_class_asynchronous_task,
+        },
+        utils::{client, login_user, logout},
+    };
+
+    #[test]
+    fn test_grading_schemes() {
+        let points = Grading {
+            scheme: GradingScheme::Points,
+            max_points: Some(20),
+        };
+        assert_eq!(points.parse("17").unwrap(), 85.0);
+        assert_eq!(points.parse("17/20").unwrap(), 85.0);
+        assert_eq!(points.display(85.0), "17/20");
+        assert!(points.parse("21").is_err());
+        let thirds = Grading {
+            scheme: GradingScheme::Points,
+            max_points: Some(9),
+        };
+        assert_eq!(thirds.display(thirds.parse("7").unwrap()), "7/9");
+
+        let percentage = Grading {
+            scheme: GradingScheme::Percentage,
+            max_points: None,
+        };
+        assert_eq!(percentage.parse("62.5%").unwrap(), 62.5);
+        assert_eq!(percentage.display(62.5), "62.5%");
+        assert!(percentage.parse("-1").is_err());
+
+        let letter = Grading {
+            scheme: GradingScheme::Letter,
+            max_points: None,
+        };
+        assert_eq!(letter.parse("a*").unwrap(), 100.0);
+        assert_eq!(letter.parse("U").unwrap(), 0.0);
+        assert_eq!(letter.display(letter.parse("c").unwrap()), "C");
+        assert!(letter.parse("F").is_err());
+
+        let pass_fail = Grading {
+            scheme: GradingScheme::PassFail,
+            max_points: None,
+        };
+        assert_eq!(pass_fail.display(pass_fail.parse("Pass").unwrap()), "Pass");
+        assert_eq!(pass_fail.display(pass_fail.parse("fail").unwrap()), "Fail");
+    }
+
+    #[test]
+    fn test_weighted_averages() {
+        let category = |id, weight| GradeCategory {
+            id,
+            class_id: 1,
+            name: id.to_string(),
+            weight,
+        };
+        let task = |grade_category_id| GradedTask {
+            task_id: 1,
+            title: String::new(),
+            due_date: Utc::now().naive_utc(),
+            grading: Grading {
+                scheme: GradingScheme::Percentage,
+                max_points: None,
+            },
+            grade_category_id,
+            released: false,
+        };
+        let tasks = [task(Some(1)), task(Some(1)), task(Some(2)), task(None)];
+        let scores = [Some(60.0), Some(80.0), Some(40.0), Some(0.0)];
+
+        // without categories, every task counts equally
+        let (by_category, overall) = averages(&[], &tasks, &scores);
+        assert!(by_category.is_empty());
+        assert_eq!(overall, Some(45.0));
+
+        // homework (70 (the average of 60 and 80), weighted 3) and tests (40, weighted 1)
+        let categories = [category(1, 3), category(2, 1)];
+        let (by_category, overall) = averages(&categories, &tasks, &scores);
+        assert_eq!(by_category, vec![Some(70.0), Some(40.0)]);
+        assert_eq!(overall, Some(62.5));
+
+        // categories without any marks are left out
+        let (by_category, overall) = averages(&categories, &tasks, &[Some(60.0), None, None, None]);
+        assert_eq!(by_category, vec![Some(60.0), None]);
+        assert_eq!(overall, Some(60.0));
+        assert_eq!(averages(&categories, &tasks, &[None; 4]).1, None);
+    }
+
+    #[rocket::async_test]
+    async fn test_marking_and_gradebook() {
+        let client = client().await;
+        let (class_id, task_id, student_task_id) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                let (_, teacher, student, institution_id, _) = setup_env(c);
+                let class_id = diesel::insert_into(class::table)
+                    .values(NewClass {
+                        name: "Chemistry",
+                        description: "",
+                        created: Utc::now().naive_utc(),
+                        code: "chemistry",
+                        institution_id: Some(institution_id),
+                        student_group_id: None,
+                        academic_term_id: None,
+                    })
+                    .returning(class::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let class_teacher_id = diesel::insert_into(class_teacher::table)
+                    .values(NewClassTeacher {
+                        user_id: teacher,
+                        class_id,
+                    })
+                    .returning(class_teacher::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let class_student_id = diesel::insert_into(class_student::table)
+                    .values(NewClassStudent {
+                        user_id: student,
+                        class_id,
+                    })
+                    .returning(class_student::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let task_id = diesel::insert_into(class_asynchronous_task::table)
+                    .values(NewClassAsynchronousTask {
+                        title: "Titration write-up",
+                        description: "",
+                        created: Utc::now().naive_utc(),
+                        due_date: NaiveDate::from_ymd(2021, 9, 10).and_hms(9, 0, 0),
+                        class_teacher_id,
+                        class_id,
+                        resubmit_until: None,
+                        estimated_minutes: 25,
+                    })
+                    .returning(class_asynchronous_task::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let student_task_id = diesel::insert_into(student_class_asynchronous_task::table)
+                    .values(NewStudentClassAsynchronousTask {
+                        class_student_id,
+                        class_asynchronous_task_id: task_id,
+                        completed: true,
+                    })
+                    .returning(student_class_asynchronous_task::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                (class_id, task_id, student_task_id)
+            })
+            .await;
+
+        // students can't see the gradebook
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+        let res = client
+            .get(format!("/class/{}/gradebook", class_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        logout(&client).await;
+
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .post(format!("/class/{}/gradebook/categories", class_id))
+            .header(ContentType::Form)
+            .body("name=Practicals&weight=2")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let category_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                grade_category::table
+                    .filter(grade_category::class_id.eq(class_id))
+                    .select(grade_category::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+
+        // tasks can't be marked until the teacher has chosen how they're graded
+        let mark = |body: String| {
+            let client = &client;
+            async move {
+                client
+                    .post(format!("/class/{}/task/async/{}/marks", class_id, task_id))
+                    .header(ContentType::Form)
+                    .body(body)
+                    .dispatch()
+                    .await
+            }
+        };
+        let res = mark(format!("marks[{}]=17", student_task_id)).await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/grading",
+                class_id, task_id
+            ))
+            .header(ContentType::Form)
+            .body(format!(
+                "scheme=points&max_points=20&category={}",
+                category_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = mark(format!("marks[{}]=25", student_task_id)).await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = mark(format!(
+            "marks[{}]=17&feedback[{}]=Well+done",
+            student_task_id, student_task_id
+        ))
+        .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .get(format!("/class/{}/task/async/{}/marks", class_id, task_id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("value=\"17/20\""));
+        assert!(string.contains("Well done"));
+
+        let res = client
+            .get(format!("/class/{}/gradebook", class_id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Titration write-up"));
+        assert!(string.contains("17/20"));
+        assert!(string.contains("85.0%"));
+
+        let res = client
+            .get(format!("/class/{}/gradebook/export", class_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.content_type(), Some(ContentType::CSV));
+        let csv = res.into_string().await.expect("invalid body response");
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("Student,Titration write-up,Practicals average,Overall average")
+        );
+        assert_eq!(
+            lines.next(),
+            Some(format!("{},17/20,85.0,85.0", STUDENT_USERNAME).as_str())
+        );
+        logout(&client).await;
+
+        // the mark hasn't been released yet
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+        let view_task = || {
+            let client = &client;
+            async move {
+                client
+                    .get(format!("/class/{}/task/async/{}/view", class_id, task_id))
+                    .dispatch()
+                    .await
+                    .into_string()
+                    .await
+                    .expect("invalid body response")
+            }
+        };
+        assert!(!view_task().await.contains("17/20"));
+        logout(&client).await;
+
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/grading",
+                class_id, task_id
+            ))
+            .header(ContentType::Form)
+            .body(format!(
+                "scheme=points&max_points=20&category={}&released=true",
+                category_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        logout(&client).await;
+
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+        let string = view_task().await;
+        assert!(string.contains("Your mark: 17/20"));
+        assert!(string.contains("Well done"));
+    }
+}
@@ -3,18 +3,17 @@ This source code file is distributed subject to the terms of the GNU Affero Gene
 A copy of this license can be found in the `licenses` directory at the root of this project.
 */
 
-use diesel::prelude::*;
 use malvolio::prelude::*;
 use mercutio::Apply;
 use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
 use rocket::serde::json::Json;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie},
     class::user_is_teacher,
     db::Database,
-    models::{NewClassTeacherInvite, User},
-    utils::{default_head, error::LovelaceError, error_message, json_response::ApiResponse},
+    invite::{find_invitee, send_invite, InviteError, InviteKind},
+    utils::{default_head, json_response::ApiResponse},
 };
 
 fn invite_user_form() -> malvolio::prelude::Form {
@@ -25,7 +24,7 @@ fn invite_user_form() -> malvolio::prelude::Form {
             Input::default()
                 .apply(FormTextInputStyle)
                 .attribute(Type::Text)
-                .attribute(Name::new("invited-user-identifier")),
+                .attribute(Name::new("identifier")),
         )
         .child(
             Input::default()
@@ -51,6 +50,29 @@ pub struct InviteTeacherForm {
     identifier: String,
 }
 
+async fn invite_teacher_base(
+    class_id: i32,
+    inviting_user_id: i32,
+    identifier: String,
+    conn: &Database,
+) -> Result<(), InviteError> {
+    if !conn
+        .run(move |c| user_is_teacher(inviting_user_id, class_id, c))
+        .await
+    {
+        return Err(InviteError::PermissionError(None));
+    }
+    let user = find_invitee(identifier, conn).await?;
+    send_invite(
+        InviteKind::ClassTeacher,
+        class_id,
+        inviting_user_id,
+        user,
+        conn,
+    )
+    .await
+}
+
 #[post("/class/<id>/invite/teacher", data = "<form>")]
 pub async fn html_invite_teacher(
     id: usize,
@@ -58,131 +80,35 @@ pub async fn html_invite_teacher(
     form: rocket::form::Form<InviteTeacherForm>,
     conn: Database,
 ) -> Html {
-    use crate::schema::class_teacher_invite::dsl as class_teacher_invite;
-    use crate::schema::users::dsl as users;
-    if !conn
-        .run(move |c| user_is_teacher(auth_cookie.0, id as i32, c))
-        .await
-    {
-        return Html::default().head(default_head("Permission denied".to_string())).body(
-            Body::default()
-                .child(H1::new("Invite a new teacher"))
-                .child(P::with_text(
-                    "You don't have permission to do that because you're not a teacher for this class ."
-                ))
-                .child(invite_user_form()),
-        );
-    }
-    match conn
-        .run(move |c| {
-            users::users
-                .filter(users::username.eq(&form.identifier))
-                .or_filter(users::email.eq(&form.identifier))
-                .first::<User>(c)
-        })
-        .await
-    {
-        Ok(user) => {
-            match conn
-                .run(move |c| {
-                    diesel::insert_into(class_teacher_invite::class_teacher_invite)
-                        .values(NewClassTeacherInvite {
-                            inviting_user_id: auth_cookie.0,
-                            invited_user_id: user.id,
-                            class_id: id as i32,
-                            accepted: false,
-                        })
-                        .execute(c)
-                })
-                .await
-            {
-                Ok(_) => Html::default()
-                    .head(default_head("Header".to_string()))
-                    .body(Body::default().child(H1::new("Successfully invited that user."))),
-                Err(e) => {
-                    error!("{:#?}", e);
-                    error_message("Database error :(".to_string(),
-                    "We've run into some problems with our database. This error has been logged and
-                    we're working on fixing it.".to_string())
-                }
-            }
-        }
-        Err(diesel::result::Error::NotFound) => Html::default()
+    match invite_teacher_base(id as i32, auth_cookie.0, form.identifier.clone(), &conn).await {
+        Ok(()) => Html::default()
+            .head(default_head("Header".to_string()))
+            .body(Body::default().child(H1::new("Successfully invited that user."))),
+        Err(e) => Html::default()
+            .status(e.status())
             .head(default_head("Invite a new teacher".to_string()))
             .body(
                 Body::default()
                     .child(H1::new("Invite a new teacher"))
-                    .child(P::with_text(
-                        "A teacher with that username or email could not be found.",
-                    ))
+                    .child(P::with_text(e.explanation()))
                     .child(invite_user_form()),
             ),
-        Err(e) => {
-            error!("{:?}", e);
-            error_message(
-                "Database error".to_string(),
-                "Something's up with our database. We're working on fixing this.".to_string(),
-            )
-        }
     }
 }
 
 #[post("/class/<id>/invite/teacher", data = "<form>")]
 pub async fn api_invite_teacher(
     id: usize,
-    auth_cookie: AuthCookie,
+    auth_cookie: ApiAuth<ManageClasses>,
     form: rocket::form::Form<InviteTeacherForm>,
     conn: Database,
 ) -> Json<ApiResponse<()>> {
-    use crate::schema::class_teacher_invite::dsl as class_teacher_invite;
-    use crate::schema::users::dsl as users;
-    if !conn
-        .run(move |c| user_is_teacher(auth_cookie.0, id as i32, c))
-        .await
-    {
-        return Json(ApiResponse::new_err(
-            "You don't have permission to invite other teachers because you're not a teacher for
-            this class.",
-        ));
-    }
-    match conn
-        .run(move |c| {
-            users::users
-                .filter(users::username.eq(&form.identifier))
-                .or_filter(users::email.eq(&form.identifier))
-                .first::<User>(c)
-        })
-        .await
-    {
-        Ok(user) => {
-            match conn
-                .run(move |c| {
-                    diesel::insert_into(class_teacher_invite::class_teacher_invite)
-                        .values(NewClassTeacherInvite {
-                            inviting_user_id: auth_cookie.0,
-                            invited_user_id: user.id,
-                            class_id: id as i32,
-                            accepted: false,
-                        })
-                        .execute(c)
-                })
-                .await
-            {
-                Ok(_) => Json(ApiResponse::new_ok(())),
-                Err(e) => {
-                    error!("{:#?}", e);
-                    Json(From::from(LovelaceError::DatabaseError))
-                }
-            }
-        }
-        Err(diesel::result::Error::NotFound) => Json(ApiResponse::new_err(
-            "A teacher with those details could not be found.",
-        )),
-        Err(e) => {
-            error!("{:?}", e);
-            Json(From::from(LovelaceError::DatabaseError))
-        }
-    }
+    Json(
+        match invite_teacher_base(id as i32, auth_cookie.0, form.identifier.clone(), &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
 }
 
 #[cfg(test)]
@@ -195,7 +121,7 @@ mod test_invite_teacher {
         db::Database,
         models::{NewClass, NewClassTeacher, NewUser},
         schema::{class, class_teacher, class_teacher_invite, users},
-        utils::{client, login_user},
+        utils::{client, login_user, mock_email_server},
     };
 
     pub const USERNAME: &str = "teacher";
@@ -216,7 +142,7 @@ mod test_invite_teacher {
                 .values(NewUser {
                     username: USERNAME,
                     email: EMAIL,
-                    password: &bcrypt::hash(PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                    password: &crate::auth::hash::hash_password(PASSWORD).unwrap(),
                     created: Utc::now().naive_utc(),
                     email_verified: true,
                     timezone: TIMEZONE,
@@ -232,6 +158,7 @@ mod test_invite_teacher {
                     code: &nanoid!(5),
                     institution_id: None,
                     student_group_id: None,
+                    academic_term_id: None,
                 })
                 .returning(class::id)
                 .get_result(c)
@@ -244,7 +171,7 @@ mod test_invite_teacher {
                 .values(NewUser {
                     username: USERNAME_2,
                     email: EMAIL_2,
-                    password: &bcrypt::hash(PASSWORD_2, bcrypt::DEFAULT_COST).unwrap(),
+                    password: &crate::auth::hash::hash_password(PASSWORD_2).unwrap(),
                     created: Utc::now().naive_utc(),
                     email_verified: true,
                     timezone: TIMEZONE,
@@ -258,6 +185,7 @@ mod test_invite_teacher {
 
     #[rocket::async_test]
     async fn test_invite_teacher_html() {
+        let _mock_server = mock_email_server(1).await;
         let client = client().await;
         let (_, class_id) = setup_env(Database::get_one(client.rocket()).await.unwrap()).await;
         login_user(USERNAME, PASSWORD, &client).await;
@@ -290,6 +218,7 @@ mod test_invite_teacher {
     #[rocket::async_test]
 
     async fn test_invite_teacher_api() {
+        let _mock_server = mock_email_server(1).await;
         let client = client().await;
         let (_, class_id) = setup_env(Database::get_one(client.rocket()).await.unwrap()).await;
         login_user(USERNAME, PASSWORD, &client).await;
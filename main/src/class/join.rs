@@ -5,13 +5,15 @@ A copy of this license can be found in the `licenses` directory at the root of t
 
 use diesel::prelude::*;
 use malvolio::prelude::*;
+use portia::render::Render;
 use rocket::serde::json::Json;
 
 use crate::utils::default_head;
 use crate::utils::error_message;
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie},
     db::Database,
+    institution::policy,
     models::{Class, ClassStudent, NewClassStudent},
     utils::{error::LovelaceError, json_response::ApiResponse},
 };
@@ -43,12 +45,14 @@ pub async fn html_join_class(join_code: String, user_id: AuthCookie, conn: Datab
     };
     match conn
         .run(move |c| {
+            policy::can_join_class(user_id.0, &class_id, c)?;
             diesel::insert_into(crate::schema::class_student::table)
                 .values(NewClassStudent {
                     user_id: user_id.0,
                     class_id: class_id.id,
                 })
                 .get_result::<ClassStudent>(c)
+                .map_err(LovelaceError::from)
         })
         .await
     {
@@ -59,6 +63,9 @@ pub async fn html_join_class(join_code: String, user_id: AuthCookie, conn: Datab
                     .child(H1::new("Class joined!"))
                     .child(P::with_text("You have sucessfully joined this class.")),
             ),
+        Err(e @ (LovelaceError::PermissionError(_) | LovelaceError::EmailNotVerified)) => {
+            e.render()
+        }
         Err(_) => error_message(
             "Internal server error".to_string(),
             "Something's up with our database – fear not, we're fixing it.".to_string(),
@@ -69,7 +76,7 @@ pub async fn html_join_class(join_code: String, user_id: AuthCookie, conn: Datab
 #[get("/join/<join_code>")]
 pub async fn api_join_class(
     join_code: String,
-    user_id: AuthCookie,
+    user_id: ApiAuth<ManageClasses>,
     conn: Database,
 ) -> Json<ApiResponse<crate::models::Class>> {
     use crate::schema::class::dsl as class;
@@ -90,20 +97,23 @@ pub async fn api_join_class(
         Err(_) => return Json(From::from(LovelaceError::DatabaseError)),
     };
     let class_id = class_instance.id;
+    let class_clone = class_instance.clone();
     Json(
         match conn
             .run(move |c| {
+                policy::can_join_class(user_id.0, &class_clone, c)?;
                 diesel::insert_into(crate::schema::class_student::table)
                     .values(NewClassStudent {
                         user_id: user_id.0,
                         class_id,
                     })
                     .get_result::<ClassStudent>(c)
+                    .map_err(LovelaceError::from)
             })
             .await
         {
             Ok(_) => ApiResponse::new_ok(class_instance),
-            Err(_) => LovelaceError::DatabaseError.into(),
+            Err(e) => e.into(),
         },
     )
 }
@@ -11,7 +11,10 @@ use rocket::serde::json::Json;
 
 use crate::utils::error::LovelaceResult;
 use crate::utils::{default_head, json_response::ApiResponse};
-use crate::{auth::AuthCookie, db::Database};
+use crate::{
+    auth::{scope::ReadClasses, ApiAuth, AuthCookie},
+    db::Database,
+};
 
 /// (vec<classes student in>, vec<classes taught>)
 async fn view_all_classes(
@@ -102,9 +105,9 @@ pub struct ViewAllClasses {
 #[get("/class")]
 pub async fn api_view_all_classes(
     conn: Database,
-    auth: AuthCookie,
+    auth: ApiAuth<ReadClasses>,
 ) -> Json<ApiResponse<ViewAllClasses>> {
-    Json(match view_all_classes(auth, conn).await {
+    Json(match view_all_classes(auth.into(), conn).await {
         Ok((student, teacher)) => ApiResponse::new_ok(ViewAllClasses { teacher, student }),
         Err(e) => From::from(e),
     })
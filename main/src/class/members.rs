@@ -1,5 +1,5 @@
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ReadClasses, ApiAuth, AuthCookie},
     class::get_user_role_in_class,
     db::Database,
     models::User,
@@ -78,7 +78,7 @@ pub struct ViewClassMembers {
 pub async fn api_view_class_members_page(
     id: usize,
     conn: Database,
-    auth_cookie: AuthCookie,
+    auth_cookie: ApiAuth<ReadClasses>,
 ) -> Json<ApiResponse<ViewClassMembers>> {
     use crate::schema::class::dsl as class;
     use crate::schema::class_student::dsl as class_student;
@@ -1,15 +1,22 @@
 use diesel::prelude::*;
 use malvolio::prelude::*;
 use mercutio::Apply;
-use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
+use portia::{
+    form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle},
+    render::Render,
+};
 use rocket::response::Redirect;
 use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
 use crate::utils::default_head;
+use crate::utils::error::LovelaceError;
 use crate::utils::error_messages::database_error;
 use crate::utils::html_or_redirect::HtmlOrRedirect;
-use crate::{auth::AuthCookie, db::Database};
+use crate::{
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie, EmailVerification},
+    db::Database,
+};
 use crate::{
     models::{ClassMessage, NewClassMessage},
     utils::json_response::ApiResponse,
@@ -88,6 +95,8 @@ pub async fn create_new_class_message_page(id: i32, auth: AuthCookie, conn: Data
 pub enum CreateNewClassMessageError {
     #[error("permission error")]
     PermissionError,
+    #[error("email address not verified")]
+    EmailNotVerified,
     #[error("database error")]
     DatabaseError,
 }
@@ -95,9 +104,13 @@ pub enum CreateNewClassMessageError {
 pub async fn create_new_class_message_base(
     class_id: i32,
     auth: AuthCookie,
+    verification: EmailVerification,
     conn: Database,
     form: &CreateNewMessageForm,
 ) -> Result<ClassMessage, CreateNewClassMessageError> {
+    if !verification.verified {
+        return Err(CreateNewClassMessageError::EmailNotVerified);
+    }
     use crate::schema::class::dsl as class;
     use crate::schema::class_message::dsl as class_message;
     use crate::schema::class_teacher::dsl as class_teacher;
@@ -160,10 +173,11 @@ pub struct CreateNewMessageForm {
 pub async fn html_apply_create_new_class_message(
     class_id: i32,
     auth: AuthCookie,
+    verification: EmailVerification,
     conn: Database,
     form: rocket::form::Form<CreateNewMessageForm>,
 ) -> HtmlOrRedirect {
-    match create_new_class_message_base(class_id, auth, conn, &form).await {
+    match create_new_class_message_base(class_id, auth, verification, conn, &form).await {
         Ok(class_message) => HtmlOrRedirect::Redirect(Redirect::to(format!(
             "/class/{}/message/{}/view",
             class_id, class_message.id
@@ -183,6 +197,9 @@ pub async fn html_apply_create_new_class_message(
                         ),
                 )
             }
+            CreateNewClassMessageError::EmailNotVerified => {
+                HtmlOrRedirect::Html(LovelaceError::EmailNotVerified.render())
+            }
             CreateNewClassMessageError::DatabaseError => HtmlOrRedirect::Html(database_error()),
         },
     }
@@ -191,17 +208,22 @@ pub async fn html_apply_create_new_class_message(
 #[post("/<class_id>/message/new", data = "<form>")]
 pub async fn api_apply_create_new_class_message(
     class_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageClasses>,
+    verification: EmailVerification,
     conn: Database,
     form: Json<CreateNewMessageForm>,
 ) -> Json<ApiResponse<ClassMessage>> {
     Json(
-        match create_new_class_message_base(class_id, auth, conn, &form).await {
+        match create_new_class_message_base(class_id, auth.into(), verification, conn, &form).await
+        {
             Ok(class_message) => ApiResponse::new_ok(class_message),
             Err(e) => match e {
                 CreateNewClassMessageError::PermissionError => {
                     ApiResponse::new_err("You do not have the permissions to send that message.")
                 }
+                CreateNewClassMessageError::EmailNotVerified => {
+                    From::from(LovelaceError::EmailNotVerified)
+                }
                 CreateNewClassMessageError::DatabaseError => ApiResponse::new_err(
                     "Encountered a database error while trying to send that message.",
                 ),
@@ -1,14 +1,16 @@
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie, EmailVerification},
     db::Database,
     models::{ClassMessageReply, NewClassMessageReply},
     utils::html_or_redirect::HtmlOrRedirect,
 };
 use crate::{class::get_user_role_in_class, utils::json_response::ApiResponse};
 
+use crate::utils::error::LovelaceError;
 use crate::utils::error_messages::database_error;
 use crate::utils::permission_error::permission_error;
 use diesel::prelude::*;
+use portia::render::Render;
 use rocket::response::Redirect;
 use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
@@ -22,6 +24,8 @@ pub struct ReplyToTeacherMessageForm {
 pub enum AddReplyError {
     #[error("permission error")]
     PermissionError,
+    #[error("email address not verified")]
+    EmailNotVerified,
     #[error("database error")]
     DatabaseError,
 }
@@ -30,11 +34,16 @@ async fn add_reply_to_teacher_message_base(
     class_id: i32,
     message_id: i32,
     auth: AuthCookie,
+    verification: EmailVerification,
     conn: Database,
     form: &ReplyToTeacherMessageForm,
 ) -> Result<ClassMessageReply, AddReplyError> {
     use crate::schema::class_message_reply::dsl as class_message_reply;
 
+    if !verification.verified {
+        return Err(AddReplyError::EmailNotVerified);
+    }
+
     if get_user_role_in_class(auth.0, class_id, &conn)
         .await
         .is_none()
@@ -73,16 +82,22 @@ pub async fn html_reply_to_teacher_message(
     class_id: i32,
     message_id: i32,
     auth: AuthCookie,
+    verification: EmailVerification,
     conn: Database,
     form: rocket::form::Form<ReplyToTeacherMessageForm>,
 ) -> HtmlOrRedirect {
-    match add_reply_to_teacher_message_base(class_id, message_id, auth, conn, &form).await {
+    match add_reply_to_teacher_message_base(class_id, message_id, auth, verification, conn, &form)
+        .await
+    {
         Ok(_) => HtmlOrRedirect::Redirect(Redirect::to(format!(
             "/class/{}/message/{}/view",
             class_id, message_id
         ))),
         Err(e) => match e {
             AddReplyError::PermissionError => HtmlOrRedirect::Html(permission_error()),
+            AddReplyError::EmailNotVerified => {
+                HtmlOrRedirect::Html(LovelaceError::EmailNotVerified.render())
+            }
             AddReplyError::DatabaseError => HtmlOrRedirect::Html(database_error()),
         },
     }
@@ -92,15 +107,26 @@ pub async fn html_reply_to_teacher_message(
 pub async fn api_reply_to_teacher_message(
     class_id: i32,
     message_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageClasses>,
+    verification: EmailVerification,
     conn: Database,
     form: rocket::form::Form<ReplyToTeacherMessageForm>,
 ) -> Json<ApiResponse<ClassMessageReply>> {
     Json(
-        match add_reply_to_teacher_message_base(class_id, message_id, auth, conn, &form).await {
+        match add_reply_to_teacher_message_base(
+            class_id,
+            message_id,
+            auth.into(),
+            verification,
+            conn,
+            &form,
+        )
+        .await
+        {
             Ok(reply) => ApiResponse::new_ok(reply),
             Err(e) => ApiResponse::new_err(match e {
                 AddReplyError::PermissionError => "invalid permissions",
+                AddReplyError::EmailNotVerified => "email address not verified",
                 AddReplyError::DatabaseError => "database error",
             }),
         },
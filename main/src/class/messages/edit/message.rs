@@ -7,7 +7,7 @@ use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie},
     db::Database,
     models::{ClassMessage, UpdateClassMessage},
     utils::{
@@ -127,11 +127,11 @@ pub async fn api_apply_message_edit(
     _class_id: i32,
     message_id: i32,
     conn: Database,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageClasses>,
     form: rocket::form::Form<EditMessageForm>,
 ) -> Json<ApiResponse<ClassMessage>> {
     Json(
-        match apply_message_edit_base(message_id, conn, auth, &form).await {
+        match apply_message_edit_base(message_id, conn, auth.into(), &form).await {
             Ok(ok) => ApiResponse::new_ok(ok),
             Err(e) => ApiResponse::new_err(match e {
                 EditClassMessageError::DatabaseError => "",
@@ -7,7 +7,7 @@ use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageClasses, ApiAuth, AuthCookie},
     db::Database,
     models::{ClassMessageReply, UpdateClassMessageReply},
     utils::{
@@ -138,11 +138,13 @@ pub async fn api_apply_message_reply_edit(
     message_reply_id: i32,
     _message_id: i32,
     conn: Database,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageClasses>,
     form: Json<ApplyMessageReplyEditForm>,
 ) -> Json<ApiResponse<ClassMessageReply>> {
     Json(
-        match apply_message_reply_edit_base(class_id, message_reply_id, conn, auth, &form).await {
+        match apply_message_reply_edit_base(class_id, message_reply_id, conn, auth.into(), &form)
+            .await
+        {
             Ok(reply) => ApiResponse::new_ok(reply),
             Err(e) => ApiResponse::new_err(match e {
                 EditMessageReplyError::DatabaseError => {
@@ -1,5 +1,5 @@
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ReadClasses, ApiAuth, AuthCookie},
     class::get_user_role_in_class,
     db::Database,
     models::ClassMessage,
@@ -105,9 +105,9 @@ pub struct ListAllClassMessages {
 pub async fn api_list_all_messages(
     id: i32,
     conn: Database,
-    auth: AuthCookie,
+    auth: ApiAuth<ReadClasses>,
 ) -> Json<ApiResponse<ListAllClassMessages>> {
-    Json(match list_all_messages_base(id, conn, auth).await {
+    Json(match list_all_messages_base(id, conn, auth.into()).await {
         Ok((class, messages)) => ApiResponse::new_ok(ListAllClassMessages {
             class,
             messages: messages
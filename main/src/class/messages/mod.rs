@@ -80,7 +80,7 @@ mod class_tests {
             .values(NewUser {
                 username: TEACHER_USERNAME,
                 email: TEACHER_EMAIL,
-                password: &bcrypt::hash(TEACHER_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(TEACHER_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -92,7 +92,7 @@ mod class_tests {
             .values(NewUser {
                 username: STUDENT_USERNAME,
                 email: STUDENT_EMAIL,
-                password: &bcrypt::hash(STUDENT_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(STUDENT_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -108,6 +108,7 @@ mod class_tests {
                 code: &nanoid!(5),
                 institution_id: None,
                 student_group_id: None,
+                academic_term_id: None,
             })
             .returning(crate::schema::class::all_columns)
             .get_result::<Class>(conn)
@@ -6,7 +6,7 @@ use thiserror::Error as ThisError;
 
 use super::super::get_user_role_in_class;
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ReadClasses, ApiAuth, AuthCookie},
     db::Database,
     utils::{default_head, error_messages::database_error},
 };
@@ -132,11 +132,11 @@ pub struct ViewMessageResponse {
 pub async fn api_view_message(
     class_id: i32,
     message_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ReadClasses>,
     conn: Database,
 ) -> Json<ApiResponse<ViewMessageResponse>> {
     Json(
-        match view_message_base(class_id, message_id, auth, conn).await {
+        match view_message_base(class_id, message_id, auth.into(), conn).await {
             Ok((message, replies)) => ApiResponse::new_ok(ViewMessageResponse {
                 message,
                 replies: replies
@@ -5,9 +5,11 @@ A copy of this license can be found in the `licenses` directory at the root of t
 
 use diesel::prelude::*;
 
+pub mod attendance;
 pub mod configure;
 pub mod create;
 pub mod delete;
+pub mod gradebook;
 pub mod invite;
 pub mod join;
 pub mod list;
@@ -16,7 +18,7 @@ pub mod messages;
 pub mod overview;
 pub mod tasks;
 
-pub use configure::get_class_settings;
+pub use configure::{api_update_class_settings, get_class_settings, html_update_class_settings};
 pub use create::{api_create_class, create_class_page, html_create_class};
 pub use delete::{api_delete_class, delete_class_page, html_delete_class};
 pub use invite::{api_invite_teacher, html_invite_teacher, invite_teacher_page};
@@ -3,7 +3,7 @@ use portia::render::Render;
 use rocket::serde::json::Json;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ReadClasses, ApiAuth, AuthCookie},
     db::Database,
     models::institution::{student_group::StudentGroup, Institution},
     utils::{default_head, error::LovelaceError, json_response::ApiResponse},
@@ -140,7 +140,7 @@ pub enum ClassOverview {
 #[get("/class/<id>")]
 pub async fn api_view_class_overview(
     id: usize,
-    auth_cookie: AuthCookie,
+    auth_cookie: ApiAuth<ReadClasses>,
     conn: Database,
 ) -> Json<ApiResponse<ClassOverview>> {
     Json(
@@ -180,7 +180,7 @@ pub async fn api_view_class_overview(
                     }))
                 }
             },
-            None => LovelaceError::PermissionError.into(),
+            None => LovelaceError::PermissionError(None).into(),
         },
     )
 }
@@ -228,7 +228,7 @@ pub mod test_class_overview_handling_with_institutions {
                 .values(NewUser {
                     username: TEACHER_USERNAME,
                     email: TEACHER_EMAIL,
-                    password: &bcrypt::hash(TEACHER_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                    password: &crate::auth::hash::hash_password(TEACHER_PASSWORD).unwrap(),
                     created: Utc::now().naive_utc(),
                     email_verified: true,
                     timezone: TIMEZONE,
@@ -245,6 +245,8 @@ pub mod test_class_overview_handling_with_institutions {
                     let_teachers_create_classes: true,
                     let_all_users_create_classes: false,
                     let_teachers_add_sync_tasks: true,
+                    require_two_factor: false,
+                    attendance_threshold: None,
                 })
                 .returning(institution::id)
                 .get_result::<i32>(c)
@@ -275,6 +277,7 @@ pub mod test_class_overview_handling_with_institutions {
                     code: &nanoid!(5),
                     institution_id: Some(institution_id),
                     student_group_id: Some(student_group_id),
+                    academic_term_id: None,
                 })
                 .returning(class::id)
                 .get_result::<i32>(c)
@@ -283,7 +286,7 @@ pub mod test_class_overview_handling_with_institutions {
                 .values(NewUser {
                     username: STUDENT_USERNAME,
                     email: STUDENT_EMAIL,
-                    password: &bcrypt::hash(STUDENT_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                    password: &crate::auth::hash::hash_password(STUDENT_PASSWORD).unwrap(),
                     created: Utc::now().naive_utc(),
                     email_verified: true,
                     timezone: TIMEZONE,
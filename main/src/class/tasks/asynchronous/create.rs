@@ -1,13 +1,24 @@
 use crate::calendar::scheduler::schedule_class;
+use crate::class::tasks::asynchronous::submit::parse_resubmit_until;
+use crate::class::tasks::asynchronous::{
+    is_valid_estimate, DEFAULT_ESTIMATED_MINUTES, INVALID_ESTIMATE,
+};
 use crate::class::user_is_teacher;
+use crate::db::DatabaseConnection;
+use crate::institution::group::descendants;
+use crate::models::institution::student_group::StudentGroup;
 use crate::models::ClassAsynchronousTask;
 use crate::models::NewClassAsynchronousTask;
 use crate::models::NewStudentClassAsynchronousTask;
+use crate::schema::{class, class_student, student_group, student_group_student, users};
 use crate::utils::default_head;
 use crate::utils::error_messages::database_error;
 use crate::utils::error_messages::invalid_date;
 use crate::utils::permission_error::permission_error;
-use crate::{auth::AuthCookie, db::Database};
+use crate::{
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
+    db::Database,
+};
 use crate::{class::get_user_role_in_class, utils::json_response::ApiResponse};
 
 use chrono::Duration;
@@ -19,8 +30,106 @@ use portia::form::FormStyle;
 use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
+/// Who a new task can be set to, apart from the whole class.
+#[derive(Debug, Clone, Default)]
+struct AssignmentOptions {
+    /// The students in the class, as (`class_student` id, username) pairs.
+    students: Vec<(i32, String)>,
+    /// The groups whose students the task can be set to. If the class is attached to a group,
+    /// these are the groups nested inside it; otherwise, if the class is part of an institution,
+    /// these are all of the institution's groups.
+    groups: Vec<StudentGroup>,
+}
+
+fn assignment_options(class_id: i32, c: &DatabaseConnection) -> QueryResult<AssignmentOptions> {
+    let students = class_student::table
+        .filter(class_student::class_id.eq(class_id))
+        .inner_join(users::table)
+        .order_by(users::username)
+        .select((class_student::id, users::username))
+        .load::<(i32, String)>(c)?;
+    let (institution_id, group_id) = class::table
+        .find(class_id)
+        .select((class::institution_id, class::student_group_id))
+        .first::<(Option<i32>, Option<i32>)>(c)?;
+    let groups = match (institution_id, group_id) {
+        (_, Some(group_id)) => student_group::table
+            .filter(student_group::id.eq_any(descendants(group_id, c)?))
+            .filter(student_group::id.ne(group_id))
+            .order_by(student_group::name)
+            .load::<StudentGroup>(c)?,
+        (Some(institution_id), None) => student_group::table
+            .filter(student_group::institution_id.eq(institution_id))
+            .order_by(student_group::name)
+            .load::<StudentGroup>(c)?,
+        (None, None) => vec![],
+    };
+    Ok(AssignmentOptions { students, groups })
+}
+
+/// Works out which students (by the id of their `class_student` record) the task should be set
+/// to.
+fn chosen_students(
+    class_id: i32,
+    students: &[i32],
+    group: Option<i32>,
+    c: &DatabaseConnection,
+) -> Result<Vec<i32>, CreateAsyncTaskError> {
+    let options = assignment_options(class_id, c).map_err(|e| {
+        error!("{:#?}", e);
+        CreateAsyncTaskError::DatabaseError
+    })?;
+    if students.is_empty() && group.is_none() {
+        return Ok(options.students.into_iter().map(|(id, _)| id).collect());
+    }
+    if let Some(id) = students
+        .iter()
+        .find(|id| !options.students.iter().any(|(student, _)| student == *id))
+    {
+        return Err(CreateAsyncTaskError::InvalidAssignment(format!(
+            "The student with the id {} isn't in this class.",
+            id
+        )));
+    }
+    let mut chosen = students.to_vec();
+    if let Some(group) = group {
+        if !options.groups.iter().any(|option| option.id == group) {
+            return Err(CreateAsyncTaskError::InvalidAssignment(
+                "Tasks in this class can't be set to that group.".to_string(),
+            ));
+        }
+        let members = descendants(group, c)
+            .and_then(|groups| {
+                class_student::table
+                    .filter(class_student::class_id.eq(class_id))
+                    .filter(
+                        class_student::user_id.eq_any(
+                            student_group_student::table
+                                .filter(student_group_student::student_group_id.eq_any(groups))
+                                .select(student_group_student::user_id),
+                        ),
+                    )
+                    .select(class_student::id)
+                    .load::<i32>(c)
+            })
+            .map_err(|e| {
+                error!("{:#?}", e);
+                CreateAsyncTaskError::DatabaseError
+            })?;
+        chosen.extend(members);
+    }
+    chosen.sort_unstable();
+    chosen.dedup();
+    if chosen.is_empty() {
+        return Err(CreateAsyncTaskError::InvalidAssignment(
+            "Nobody in this class is in that group.".to_string(),
+        ));
+    }
+    Ok(chosen)
+}
+
 /// Create a new form containing the necessary fields to create a new asynchronous task.
-fn create_new_async_task_form() -> Form {
+fn create_new_async_task_form(options: &AssignmentOptions) -> Form {
     Form::new()
         .apply(FormStyle)
         .child(
@@ -38,6 +147,61 @@ fn create_new_async_task_form() -> Form {
                 .attribute(Name::new("due_date"))
                 .attribute(Type::Text),
         )
+        .child(Label::new(
+            "Students can resubmit their work until (optional)",
+        ))
+        .child(
+            Input::new()
+                .attribute(Name::new("resubmit_until"))
+                .attribute(Type::DateTimeLocal),
+        )
+        .child(Label::new("How long this task should take (in minutes)"))
+        .child(
+            Input::new()
+                .attribute(Name::new("estimated_minutes"))
+                .attribute(Type::Text)
+                .attribute(Value::new(DEFAULT_ESTIMATED_MINUTES.to_string())),
+        )
+        .apply(|form: Form| {
+            if options.students.is_empty() {
+                return form;
+            }
+            form.child(Label::new(
+                "Only set this task to some students (if you don't choose anybody, it will be set \
+                to the whole class)",
+            ))
+            .children(options.students.iter().map(|(id, username)| {
+                Div::new()
+                    .child(
+                        Input::new()
+                            .attribute(Type::Checkbox)
+                            .attribute(Name::new("students"))
+                            .attribute(Value::new(id.to_string())),
+                    )
+                    .child(Label::new(username.clone()))
+            }))
+        })
+        .apply(|form: Form| {
+            if options.groups.is_empty() {
+                return form;
+            }
+            form.child(Label::new("Set this task to a group")).child(
+                options.groups.iter().fold(
+                    Select::new().attribute(Name::new("student_group")).child(
+                        SelectOption::new()
+                            .attribute(Value::new(""))
+                            .text("No group"),
+                    ),
+                    |select, group| {
+                        select.child(
+                            SelectOption::new()
+                                .attribute(Value::new(group.id.to_string()))
+                                .text(group.name.clone()),
+                        )
+                    },
+                ),
+            )
+        })
         .child(Input::new().attribute(Type::Submit))
 }
 
@@ -49,21 +213,45 @@ pub struct CreateNewAsyncTask {
     title: String,
     description: String,
     due_date: String,
+    /// If this is given, students can resubmit their work until this date.
+    #[serde(default)]
+    resubmit_until: Option<String>,
+    /// The students (by the id of their `class_student` record) to set the task to. If neither
+    /// this nor `student_group` is given, the task is set to everybody in the class.
+    #[serde(default)]
+    students: Vec<i32>,
+    /// Sets the task to everybody in the class who is in this group (or in one of the groups
+    /// nested inside it), as well as to any `students` who were chosen.
+    #[serde(default)]
+    student_group: Option<i32>,
+    /// How long (in minutes) the task should take. If this isn't given, it is assumed to take
+    /// [`DEFAULT_ESTIMATED_MINUTES`].
+    #[serde(default)]
+    estimated_minutes: Option<i32>,
 }
 
 #[get("/<class_id>/task/async/create")]
 pub async fn get_create_new_async_task(class_id: i32, auth: AuthCookie, conn: Database) -> Html {
-    if conn
-        .run(move |c| user_is_teacher(auth.0, class_id, c))
+    match conn
+        .run(move |c| {
+            if user_is_teacher(auth.0, class_id, c) {
+                assignment_options(class_id, c).map(Some)
+            } else {
+                Ok(None)
+            }
+        })
         .await
     {
-        Html::new().body(
+        Ok(Some(options)) => Html::new().body(
             Body::new()
                 .child(H1::new("Create a new asynchronous task."))
-                .child(create_new_async_task_form()),
-        )
-    } else {
-        permission_error()
+                .child(create_new_async_task_form(&options)),
+        ),
+        Ok(None) => permission_error(),
+        Err(e) => {
+            error!("{:#?}", e);
+            database_error()
+        }
     }
 }
 
@@ -75,6 +263,12 @@ pub enum CreateAsyncTaskError {
     PermissionError,
     #[error("invalid date")]
     InvalidDate,
+    /// The students the task should be set to couldn't be worked out (the reason is shown to the
+    /// user).
+    #[error("invalid assignment")]
+    InvalidAssignment(String),
+    #[error("invalid estimate")]
+    InvalidEstimate,
 }
 
 async fn new_async_task(
@@ -94,8 +288,19 @@ async fn new_async_task(
         Ok(date) => date,
         Err(_) => return Err(CreateAsyncTaskError::InvalidDate),
     };
+    let resubmit_until = parse_resubmit_until(form.resubmit_until.as_deref())
+        .map_err(|_| CreateAsyncTaskError::InvalidDate)?;
+    let estimated_minutes = form.estimated_minutes.unwrap_or(DEFAULT_ESTIMATED_MINUTES);
+    if !is_valid_estimate(estimated_minutes) {
+        return Err(CreateAsyncTaskError::InvalidEstimate);
+    }
     let title = form.title.clone();
     let description = form.description.clone();
+    let students = form.students.clone();
+    let student_group = form.student_group;
+    let student_list = conn
+        .run(move |c| chosen_students(class_id, &students, student_group, c))
+        .await?;
     match conn
         .run(move |c| {
             diesel::insert_into(crate::schema::class_asynchronous_task::table)
@@ -110,6 +315,8 @@ async fn new_async_task(
                         .first::<i32>(c)
                         .unwrap(),
                     class_id,
+                    resubmit_until,
+                    estimated_minutes,
                 })
                 .returning(crate::schema::class_asynchronous_task::all_columns)
                 .get_result::<ClassAsynchronousTask>(c)
@@ -118,18 +325,6 @@ async fn new_async_task(
     {
         Ok(async_task) => {
             let async_task_id = async_task.id;
-            let student_list = match conn
-                .run(move |c| {
-                    crate::schema::class_student::table
-                        .filter(crate::schema::class_student::class_id.eq(class_id))
-                        .select(crate::schema::class_student::id)
-                        .get_results::<i32>(c)
-                })
-                .await
-            {
-                Ok(t) => t,
-                Err(_) => return Err(CreateAsyncTaskError::DatabaseError),
-            };
             match conn
                 .run(move |c| {
                     diesel::insert_into(crate::schema::student_class_asynchronous_task::table)
@@ -175,6 +370,12 @@ pub async fn html_create_new_async_task(
     auth: AuthCookie,
     form: rocket::form::Form<CreateNewAsyncTask>,
 ) -> Html {
+    // loaded up front (because `new_async_task` takes the database connection) so that the form
+    // can be shown again if something is wrong with it
+    let options = conn
+        .run(move |c| assignment_options(class_id, c))
+        .await
+        .unwrap_or_default();
     match new_async_task(conn, class_id, auth, &form).await {
         Ok(_) => Html::new()
             .head(default_head("Created that task".to_string()))
@@ -186,7 +387,25 @@ pub async fn html_create_new_async_task(
         Err(e) => match e {
             CreateAsyncTaskError::DatabaseError => database_error(),
             CreateAsyncTaskError::PermissionError => permission_error(),
-            CreateAsyncTaskError::InvalidDate => invalid_date(Some(create_new_async_task_form())),
+            CreateAsyncTaskError::InvalidDate => {
+                invalid_date(Some(create_new_async_task_form(&options)))
+            }
+            CreateAsyncTaskError::InvalidAssignment(reason) => Html::new()
+                .head(default_head("Couldn't set that task".to_string()))
+                .body(
+                    Body::new()
+                        .child(H1::new("Couldn't set that task"))
+                        .child(P::with_text(reason))
+                        .child(create_new_async_task_form(&options)),
+                ),
+            CreateAsyncTaskError::InvalidEstimate => Html::new()
+                .head(default_head("Couldn't set that task".to_string()))
+                .body(
+                    Body::new()
+                        .child(H1::new("Couldn't set that task"))
+                        .child(P::with_text(INVALID_ESTIMATE))
+                        .child(create_new_async_task_form(&options)),
+                ),
         },
     }
 }
@@ -195,19 +414,26 @@ pub async fn html_create_new_async_task(
 pub async fn api_create_new_async_task(
     conn: Database,
     class_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageTasks>,
     form: Json<CreateNewAsyncTask>,
 ) -> Json<ApiResponse<ClassAsynchronousTask>> {
-    Json(match new_async_task(conn, class_id, auth, &form).await {
-        Ok(task) => ApiResponse::new_ok(task),
-        Err(e) => ApiResponse::new_err(match e {
-            CreateAsyncTaskError::DatabaseError => {
-                "Encountered a database error when trying to fulfill this operation."
-            }
-            CreateAsyncTaskError::PermissionError => {
-                "You don't have permissions to create tasks in this class."
-            }
-            CreateAsyncTaskError::InvalidDate => "The date you provided is not in a valid format.",
-        }),
-    })
+    Json(
+        match new_async_task(conn, class_id, auth.into(), &form).await {
+            Ok(task) => ApiResponse::new_ok(task),
+            Err(CreateAsyncTaskError::InvalidAssignment(reason)) => ApiResponse::new_err(reason),
+            Err(e) => ApiResponse::new_err(match e {
+                CreateAsyncTaskError::DatabaseError => {
+                    "Encountered a database error when trying to fulfill this operation."
+                }
+                CreateAsyncTaskError::PermissionError => {
+                    "You don't have permissions to create tasks in this class."
+                }
+                CreateAsyncTaskError::InvalidDate => {
+                    "The date you provided is not in a valid format."
+                }
+                CreateAsyncTaskError::InvalidEstimate => INVALID_ESTIMATE,
+                CreateAsyncTaskError::InvalidAssignment(_) => unreachable!(),
+            }),
+        },
+    )
 }
@@ -4,7 +4,7 @@ use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
     class::{get_user_role_in_class, ClassMemberRole},
     db::Database,
     utils::{default_head, error_messages::database_error, json_response::ApiResponse},
@@ -67,11 +67,13 @@ pub async fn html_delete_task(
 pub async fn api_delete_task(
     class_id: i32,
     task_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageTasks>,
     conn: Database,
 ) -> Json<ApiResponse<()>> {
-    Json(match delete_task(class_id, task_id, auth, conn).await {
-        Ok(_) => ApiResponse::new_ok(()),
-        Err(_) => ApiResponse::new_err("database error"),
-    })
+    Json(
+        match delete_task(class_id, task_id, auth.into(), conn).await {
+            Ok(_) => ApiResponse::new_ok(()),
+            Err(_) => ApiResponse::new_err("database error"),
+        },
+    )
 }
@@ -1,6 +1,9 @@
 use crate::{
     catch_database_error,
     class::get_user_role_in_class,
+    class::tasks::asynchronous::{
+        is_valid_estimate, submit::parse_resubmit_until, INVALID_ESTIMATE,
+    },
     class::ClassMemberRole,
     models::{ClassAsynchronousTask, UpdateClassAsynchronousTask},
     utils::{
@@ -18,12 +21,17 @@ use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
 use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
-use crate::{auth::AuthCookie, db::Database};
+use crate::{
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
+    db::Database,
+};
 
 fn edit_task_form(
     title: Option<String>,
     description: Option<String>,
     due_date: Option<String>,
+    resubmit_until: Option<String>,
+    estimated_minutes: Option<String>,
 ) -> Form {
     Form::new()
         .apply(FormStyle)
@@ -66,6 +74,35 @@ fn edit_task_form(
                 })
                 .attribute(Name::new("due_date")),
         )
+        .child(Label::new(
+            "Students can resubmit their work until (optional)",
+        ))
+        .child(
+            Input::new()
+                .attribute(Type::DateTimeLocal)
+                .map(|item| {
+                    if let Some(resubmit_until) = resubmit_until {
+                        item.attribute(Value::new(resubmit_until))
+                    } else {
+                        item
+                    }
+                })
+                .attribute(Name::new("resubmit_until")),
+        )
+        .child(Label::new("How long this task should take (in minutes)"))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .apply(FormTextInputStyle)
+                .map(|item| {
+                    if let Some(estimated_minutes) = estimated_minutes {
+                        item.attribute(Value::new(estimated_minutes))
+                    } else {
+                        item
+                    }
+                })
+                .attribute(Name::new("estimated_minutes")),
+        )
 }
 
 #[get("/<class_id>/task/async/<task_id>/edit")]
@@ -95,6 +132,9 @@ pub async fn view_edit_task_page(
                         Some(res.title),
                         Some(res.description),
                         Some(res.due_date.format("%Y-%m-%dT%H:%M").to_string()),
+                        res.resubmit_until
+                            .map(|date| date.format("%Y-%m-%dT%H:%M").to_string()),
+                        Some(res.estimated_minutes.to_string()),
                     )),
             )
     } else {
@@ -107,6 +147,13 @@ pub struct EditTaskForm {
     title: String,
     description: String,
     due_date: String,
+    /// If this isn't given (or is empty), students can't resubmit their work.
+    #[serde(default)]
+    resubmit_until: Option<String>,
+    /// How long (in minutes) the task should take. If this isn't given, the estimate is left as it
+    /// is.
+    #[serde(default)]
+    estimated_minutes: Option<i32>,
 }
 
 #[derive(ThisError, Debug)]
@@ -117,6 +164,8 @@ pub enum EditTaskError {
     PermissionError,
     #[error("invalid date")]
     InvalidDate,
+    #[error("invalid estimate")]
+    InvalidEstimate,
 }
 
 pub async fn apply_edit_task(
@@ -129,6 +178,12 @@ pub async fn apply_edit_task(
     use crate::schema::class_asynchronous_task::dsl as class_asynchronous_task;
     let due_date = NaiveDateTime::parse_from_str(&form.due_date, "%Y-%m-%dT%H:%M")
         .map_err(|_| EditTaskError::InvalidDate)?;
+    let resubmit_until = parse_resubmit_until(form.resubmit_until.as_deref())
+        .map_err(|_| EditTaskError::InvalidDate)?;
+    if form.estimated_minutes.map(is_valid_estimate) == Some(false) {
+        return Err(EditTaskError::InvalidEstimate);
+    }
+    let estimated_minutes = form.estimated_minutes;
     if let Some(role) = get_user_role_in_class(auth.0, class_id, &conn).await {
         if role != ClassMemberRole::Teacher {
             return Err(EditTaskError::DatabaseError);
@@ -145,6 +200,8 @@ pub async fn apply_edit_task(
                 title,
                 description,
                 due_date: Some(due_date),
+                resubmit_until: Some(resubmit_until),
+                estimated_minutes,
                 ..Default::default()
             })
             .returning(crate::schema::class_asynchronous_task::all_columns)
@@ -179,7 +236,23 @@ pub async fn html_apply_edit_task(
                 Some(form.title.clone()),
                 Some(form.description.clone()),
                 Some(form.due_date.clone()),
+                form.resubmit_until.clone(),
+                form.estimated_minutes.map(|minutes| minutes.to_string()),
             ))),
+            EditTaskError::InvalidEstimate => Html::new()
+                .head(default_head("Couldn't update that task".to_string()))
+                .body(
+                    Body::new()
+                        .child(H1::new("Couldn't update that task"))
+                        .child(P::with_text(INVALID_ESTIMATE))
+                        .child(edit_task_form(
+                            Some(form.title.clone()),
+                            Some(form.description.clone()),
+                            Some(form.due_date.clone()),
+                            form.resubmit_until.clone(),
+                            None,
+                        )),
+                ),
         },
     }
 }
@@ -188,17 +261,18 @@ pub async fn html_apply_edit_task(
 pub async fn api_apply_edit_task(
     class_id: i32,
     task_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageTasks>,
     conn: Database,
     form: Json<EditTaskForm>,
 ) -> Json<ApiResponse<ClassAsynchronousTask>> {
     Json(
-        match apply_edit_task(class_id, task_id, auth, conn, &form).await {
+        match apply_edit_task(class_id, task_id, auth.into(), conn, &form).await {
             Ok(task) => ApiResponse::new_ok(task),
             Err(e) => ApiResponse::new_err(match e {
                 EditTaskError::DatabaseError => "database error",
                 EditTaskError::PermissionError => "permission error",
                 EditTaskError::InvalidDate => "invalid date",
+                EditTaskError::InvalidEstimate => INVALID_ESTIMATE,
             }),
         },
     )
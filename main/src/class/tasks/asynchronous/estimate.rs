@@ -0,0 +1,125 @@
+//! Students' own estimates of how long tasks will take them (which the scheduler uses instead of
+//! the teacher's estimate).
+
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::render::Render;
+use rocket::serde::json::Json;
+
+use crate::{
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
+    calendar::scheduler::two_week_schedule,
+    class::tasks::asynchronous::is_valid_estimate,
+    db::Database,
+    models::{ClassAsynchronousTask, StudentClassAsynchronousTask},
+    schema::{class_asynchronous_task, class_student, student_class_asynchronous_task},
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        json_response::ApiResponse,
+    },
+};
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct StudentEstimateForm {
+    /// How long (in minutes) the student thinks the task will take them. If this isn't given, the
+    /// teacher's estimate is used.
+    #[serde(default)]
+    estimated_minutes: Option<i32>,
+}
+
+async fn set_student_estimate(
+    class_id: i32,
+    task_id: i32,
+    user_id: i32,
+    form: StudentEstimateForm,
+    conn: Database,
+) -> LovelaceResult<(ClassAsynchronousTask, StudentClassAsynchronousTask)> {
+    if form.estimated_minutes.map(is_valid_estimate) == Some(false) {
+        return Err(LovelaceError::InvalidEstimate);
+    }
+    let (task, student_task) = conn
+        .run(move |c| -> LovelaceResult<_> {
+            let (task, student_task_id) = student_class_asynchronous_task::table
+                .inner_join(class_asynchronous_task::table)
+                .inner_join(class_student::table)
+                .filter(class_asynchronous_task::id.eq(task_id))
+                .filter(class_asynchronous_task::class_id.eq(class_id))
+                .filter(class_student::user_id.eq(user_id))
+                .select((
+                    class_asynchronous_task::all_columns,
+                    student_class_asynchronous_task::id,
+                ))
+                .first::<(ClassAsynchronousTask, i32)>(c)
+                .optional()?
+                .ok_or_else(|| {
+                    LovelaceError::PermissionError(Some(
+                        "You haven't been set this task.".to_string(),
+                    ))
+                })?;
+            let student_task =
+                diesel::update(student_class_asynchronous_task::table.find(student_task_id))
+                    .set(
+                        student_class_asynchronous_task::estimated_minutes
+                            .eq(form.estimated_minutes),
+                    )
+                    .get_result::<StudentClassAsynchronousTask>(c)?;
+            Ok((task, student_task))
+        })
+        .await?;
+    if student_task.effective_due_date(&task) < Utc::now().naive_utc() + Duration::days(14) {
+        rocket::tokio::spawn(async move {
+            if let Err(e) = two_week_schedule(user_id, &conn).await {
+                error!("{:#?}", e);
+            }
+        });
+    }
+    Ok((task, student_task))
+}
+
+#[post("/<class_id>/task/async/<task_id>/estimate", data = "<form>")]
+pub async fn html_set_student_estimate(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<StudentEstimateForm>,
+) -> Html {
+    match set_student_estimate(class_id, task_id, auth.0, form.into_inner(), conn).await {
+        Ok((task, student_task)) => Html::new()
+            .head(default_head("Updated your estimate"))
+            .body(
+                Body::new()
+                    .child(H1::new(format!(
+                        "Your work for this task will be scheduled as though it takes {} minutes.",
+                        student_task.estimated_minutes(&task)
+                    )))
+                    .child(
+                        A::new()
+                            .attribute(Href::new(format!(
+                                "/class/{}/task/async/{}/view",
+                                class_id, task_id
+                            )))
+                            .text("Back to the task"),
+                    ),
+            ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<class_id>/task/async/<task_id>/estimate", data = "<form>")]
+pub async fn api_set_student_estimate(
+    class_id: i32,
+    task_id: i32,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<StudentEstimateForm>,
+) -> Json<ApiResponse<StudentClassAsynchronousTask>> {
+    Json(
+        match set_student_estimate(class_id, task_id, auth.0, form.0, conn).await {
+            Ok((_, student_task)) => ApiResponse::new_ok(student_task),
+            Err(e) => From::from(e),
+        },
+    )
+}
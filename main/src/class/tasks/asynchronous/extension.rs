@@ -0,0 +1,137 @@
+//! Giving individual students a different due date for a task (e.g. an extension).
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::render::Render;
+use rocket::serde::json::Json;
+
+use crate::{
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
+    calendar::scheduler::two_week_schedule,
+    class::user_is_teacher,
+    db::Database,
+    models::{ClassAsynchronousTask, StudentClassAsynchronousTask},
+    schema::{class_asynchronous_task, class_student, student_class_asynchronous_task},
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        json_response::ApiResponse,
+    },
+};
+
+#[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
+pub struct StudentDueDateForm {
+    /// The id of the student's `student_class_asynchronous_task` record.
+    student_task: i32,
+    /// When the student's work should be due (in the form YYYY-MM-DDTHH:MM). If this is empty (or
+    /// isn't given), the student's work is due when the task is.
+    #[serde(default)]
+    due_date: Option<String>,
+}
+
+async fn set_student_due_date(
+    class_id: i32,
+    task_id: i32,
+    user_id: i32,
+    form: StudentDueDateForm,
+    conn: Database,
+) -> LovelaceResult<StudentClassAsynchronousTask> {
+    let due_date = match form.due_date.as_deref().map(str::trim) {
+        Some(date) if !date.is_empty() => Some(
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M")
+                .map_err(|_| LovelaceError::ParseDateError)?,
+        ),
+        _ => None,
+    };
+    let (task, previous, student_task, student_user_id) = conn
+        .run(move |c| {
+            if !user_is_teacher(user_id, class_id, c) {
+                return Err(LovelaceError::PermissionError(None));
+            }
+            let (task, previous, student_user_id) = student_class_asynchronous_task::table
+                .filter(student_class_asynchronous_task::id.eq(form.student_task))
+                .inner_join(class_asynchronous_task::table)
+                .inner_join(class_student::table)
+                .filter(class_asynchronous_task::id.eq(task_id))
+                .filter(class_asynchronous_task::class_id.eq(class_id))
+                .select((
+                    class_asynchronous_task::all_columns,
+                    student_class_asynchronous_task::all_columns,
+                    class_student::user_id,
+                ))
+                .first::<(ClassAsynchronousTask, StudentClassAsynchronousTask, i32)>(c)
+                .optional()?
+                .ok_or_else(|| {
+                    LovelaceError::PermissionError(Some(
+                        "That student hasn't been set this task.".to_string(),
+                    ))
+                })?;
+            let student_task =
+                diesel::update(student_class_asynchronous_task::table.find(form.student_task))
+                    .set(student_class_asynchronous_task::due_date.eq(due_date))
+                    .get_result::<StudentClassAsynchronousTask>(c)?;
+            Ok((task, previous, student_task, student_user_id))
+        })
+        .await?;
+    // if the work was due inside the window it has already been scheduled (and those sessions
+    // might need removing), and if it is now due inside the window it needs scheduling
+    let window_ends = Utc::now().naive_utc() + Duration::days(14);
+    if previous.effective_due_date(&task) < window_ends
+        || student_task.effective_due_date(&task) < window_ends
+    {
+        rocket::tokio::spawn(async move {
+            if let Err(e) = two_week_schedule(student_user_id, &conn).await {
+                error!("{:#?}", e);
+            }
+        });
+    }
+    Ok(student_task)
+}
+
+#[post("/<class_id>/task/async/<task_id>/due_date", data = "<form>")]
+pub async fn html_set_student_due_date(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+    form: rocket::form::Form<StudentDueDateForm>,
+) -> Html {
+    match set_student_due_date(class_id, task_id, auth.0, form.into_inner(), conn).await {
+        Ok(student_task) => Html::new().head(default_head("Updated the due date")).body(
+            Body::new()
+                .child(H1::new(match student_task.due_date {
+                    Some(due_date) => format!(
+                        "This student's work is now due {}.",
+                        due_date.format("%Y-%m-%d %H:%M")
+                    ),
+                    None => "This student's work is now due when the task is.".to_string(),
+                }))
+                .child(
+                    A::new()
+                        .attribute(Href::new(format!(
+                            "/class/{}/task/async/{}/view",
+                            class_id, task_id
+                        )))
+                        .text("Back to the task"),
+                ),
+        ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<class_id>/task/async/<task_id>/due_date", data = "<form>")]
+pub async fn api_set_student_due_date(
+    class_id: i32,
+    task_id: i32,
+    auth: ApiAuth<ManageTasks>,
+    conn: Database,
+    form: Json<StudentDueDateForm>,
+) -> Json<ApiResponse<StudentClassAsynchronousTask>> {
+    Json(
+        match set_student_due_date(class_id, task_id, auth.0, form.0, conn).await {
+            Ok(student_task) => ApiResponse::new_ok(student_task),
+            Err(e) => From::from(e),
+        },
+    )
+}
@@ -7,6 +7,9 @@ A copy of this license can be found in the `licenses` directory at the root of t
 mod create;
 mod delete;
 mod edit;
+mod estimate;
+mod extension;
+mod submit;
 mod summary;
 mod view;
 
@@ -15,8 +18,40 @@ pub use create::{
 };
 pub use delete::{api_delete_task, html_delete_task};
 pub use edit::{api_apply_edit_task, html_apply_edit_task, view_edit_task_page};
+pub use estimate::{api_set_student_estimate, html_set_student_estimate};
+pub use extension::{api_set_student_due_date, html_set_student_due_date};
+pub use submit::{api_submit_work, download_submitted_file, html_submit_work};
 pub use summary::{api_view_all_async_tasks_in_class, html_view_all_async_tasks_in_class};
-pub use view::{api_view_specific_asynchronous_task, html_view_specific_asynchronous_task};
+pub use view::{
+    api_view_specific_asynchronous_task, api_view_submissions,
+    html_view_specific_asynchronous_task, html_view_submissions,
+};
+
+use diesel::sql_types::{Nullable, Timestamp};
+
+/// How long (in minutes) tasks are assumed to take if the teacher doesn't say.
+pub const DEFAULT_ESTIMATED_MINUTES: i32 = 25;
+
+/// The longest (in minutes) that a task can be estimated to take (two weeks, which is as far
+/// ahead as work is scheduled).
+pub const MAX_ESTIMATED_MINUTES: i32 = 20160;
+
+pub(crate) const INVALID_ESTIMATE: &str =
+    "The time the task should take should be a whole number of minutes above zero (and no more \
+    than two weeks).";
+
+/// Checks that an estimate of how long a task will take is in range.
+pub(crate) fn is_valid_estimate(minutes: i32) -> bool {
+    (1..=MAX_ESTIMATED_MINUTES).contains(&minutes)
+}
+
+sql_function! {
+    /// When a student's work is due, in a query: pass it `student_class_asynchronous_task::due_date`
+    /// and `class_asynchronous_task::due_date` (see
+    /// [`crate::models::StudentClassAsynchronousTask::effective_due_date`]).
+    #[sql_name = "coalesce"]
+    fn effective_due_date(student_due_date: Nullable<Timestamp>, task_due_date: Timestamp) -> Timestamp;
+}
 
 #[cfg(test)]
 mod async_task_tests {
@@ -67,6 +102,7 @@ mod async_task_tests {
                 code: CLASS_CODE,
                 institution_id: None,
                 student_group_id: None,
+                academic_term_id: None,
             })
             .returning(crate::schema::class::id)
             .get_result::<i32>(conn)
@@ -75,7 +111,7 @@ mod async_task_tests {
             .values(crate::models::NewUser {
                 username: TEACHER_USERNAME,
                 email: TEACHER_EMAIL,
-                password: &bcrypt::hash(TEACHER_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(TEACHER_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -95,7 +131,7 @@ mod async_task_tests {
             .values(crate::models::NewUser {
                 username: STUDENT_1_USERNAME,
                 email: STUDENT_1_EMAIL,
-                password: &bcrypt::hash(STUDENT_1_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(STUDENT_1_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -115,7 +151,7 @@ mod async_task_tests {
             .values(crate::models::NewUser {
                 username: STUDENT_2_USERNAME,
                 email: STUDENT_2_EMAIL,
-                password: &bcrypt::hash(STUDENT_2_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(STUDENT_2_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -141,6 +177,8 @@ mod async_task_tests {
                     .naive_utc(),
                 class_teacher_id,
                 class_id,
+                resubmit_until: None,
+                estimated_minutes: 25,
             })
             .returning(crate::schema::class_asynchronous_task::id)
             .get_result::<i32>(conn)
@@ -171,6 +209,8 @@ mod async_task_tests {
                     .naive_utc(),
                 class_teacher_id,
                 class_id,
+                resubmit_until: None,
+                estimated_minutes: 25,
             })
             .returning(crate::schema::class_asynchronous_task::id)
             .get_result::<i32>(conn)
@@ -381,4 +421,256 @@ mod async_task_tests {
         let string = res.into_string().await.expect("invalid body response");
         assert!(string.contains("deleted that task"));
     }
+    #[rocket::async_test]
+    async fn test_students_can_submit_work() {
+        const BOUNDARY: &str = "submission-boundary";
+        const TEXT: &str = "Here is my essay";
+        const FILENAME: &str = "essay.txt";
+        const CONTENTS: &str = "It was a dark and stormy night";
+        let multipart_body = format!(
+            "--{b}\r\n\
+            Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+            {text}\r\n\
+            --{b}\r\n\
+            Content-Disposition: form-data; name=\"files\"; filename=\"{filename}\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            {contents}\r\n\
+            --{b}--\r\n",
+            b = BOUNDARY,
+            text = TEXT,
+            filename = FILENAME,
+            contents = CONTENTS
+        );
+        let multipart =
+            ContentType::parse_flexible(&format!("multipart/form-data; boundary={}", BOUNDARY))
+                .unwrap();
+
+        let client = client().await;
+        let (class_id, _, _, tasks) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| populate_database(c))
+            .await;
+        login_user(STUDENT_1_USERNAME, STUDENT_1_PASSWORD, &client).await;
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/submit",
+                class_id, tasks[0]
+            ))
+            .header(multipart.clone())
+            .body(&multipart_body)
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Work submitted"));
+        assert!(!string.contains("late"));
+
+        // resubmissions haven't been allowed for this task
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/submit",
+                class_id, tasks[0]
+            ))
+            .header(multipart.clone())
+            .body(&multipart_body)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), rocket::http::Status::BadRequest);
+
+        // the other student can't see the file
+        let file_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                crate::schema::async_task_submission_file::table
+                    .select(crate::schema::async_task_submission_file::id)
+                    .order_by(crate::schema::async_task_submission_file::id.desc())
+                    .first::<i32>(c)
+            })
+            .await
+            .unwrap();
+        let file_url = format!(
+            "/class/{}/task/async/{}/submission/file/{}",
+            class_id, tasks[0], file_id
+        );
+        login_user(STUDENT_2_USERNAME, STUDENT_2_PASSWORD, &client).await;
+        let res = client.get(&file_url).dispatch().await;
+        assert_eq!(res.status(), rocket::http::Status::Forbidden);
+
+        // but the teacher can
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .get(format!(
+                "/class/{}/task/async/{}/submissions",
+                class_id, tasks[0]
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("1 of 2 students have submitted their work"));
+        assert!(string.contains(TEXT));
+        assert!(string.contains(FILENAME));
+        assert!(string.contains(&file_url));
+        assert!(string.contains("Not submitted yet."));
+        let res = client.get(&file_url).dispatch().await;
+        assert_eq!(res.status(), rocket::http::Status::Ok);
+        assert_eq!(
+            res.into_bytes().await.expect("invalid body response"),
+            CONTENTS.as_bytes()
+        );
+    }
+    #[rocket::async_test]
+    async fn test_tasks_for_some_students_and_extensions() {
+        const NEW_TASK_TITLE: &str = "only-for-student-1";
+        const NEW_TASK_DESCRIPTION: &str = "only-for-student-1-description";
+        let client = client().await;
+        let (class_id, _, student_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| populate_database(c))
+            .await;
+        let class_student_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                crate::schema::class_student::table
+                    .filter(crate::schema::class_student::user_id.eq(student_id))
+                    .select(crate::schema::class_student::id)
+                    .first::<i32>(c)
+            })
+            .await
+            .unwrap();
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+
+        let res = client
+            .post(format!("/class/{}/task/async/create", class_id))
+            .header(ContentType::Form)
+            .body(format!(
+                "title={}&description={}&due_date={}&students={}",
+                NEW_TASK_TITLE,
+                NEW_TASK_DESCRIPTION,
+                (chrono::Utc::now() + chrono::Duration::days(7))
+                    .naive_utc()
+                    .format("%Y-%m-%dT%H:%M"),
+                class_student_id
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Created that task"));
+        let results = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                crate::schema::class_asynchronous_task::table
+                    .filter(crate::schema::class_asynchronous_task::title.eq(NEW_TASK_TITLE))
+                    .inner_join(crate::schema::student_class_asynchronous_task::table)
+                    .load::<(ClassAsynchronousTask, StudentClassAsynchronousTask)>(c)
+            })
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        let (task, student_task) = results.into_iter().next().unwrap();
+        assert_eq!(student_task.class_student_id, class_student_id);
+
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/due_date",
+                class_id, task.id
+            ))
+            .header(ContentType::Form)
+            .body(format!(
+                "student_task={}&due_date=2100-01-02T09:30",
+                student_task.id
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("This student's work is now due 2100-01-02 09:30."));
+
+        login_user(STUDENT_1_USERNAME, STUDENT_1_PASSWORD, &client).await;
+        let res = client
+            .get(format!("/class/{}/task/async/{}/view", class_id, task.id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains(
+            "Due 2100-01-02 09:30 (your teacher has changed your due date for this task)"
+        ));
+
+        // students can't change their own due dates
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/due_date",
+                class_id, task.id
+            ))
+            .header(ContentType::Form)
+            .body(format!("student_task={}&due_date=", student_task.id))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(!string.contains("This student's work is now due"));
+    }
+    #[rocket::async_test]
+    async fn test_students_can_estimate_how_long_tasks_take() {
+        let client = client().await;
+        let (class_id, _, _, tasks) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| populate_database(c))
+            .await;
+        login_user(STUDENT_1_USERNAME, STUDENT_1_PASSWORD, &client).await;
+
+        let res = client
+            .get(format!("/class/{}/task/async/{}/view", class_id, tasks[0]))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("This task should take about 25 minutes."));
+
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/estimate",
+                class_id, tasks[0]
+            ))
+            .header(ContentType::Form)
+            .body("estimated_minutes=0")
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Couldn't use that estimate"));
+
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/estimate",
+                class_id, tasks[0]
+            ))
+            .header(ContentType::Form)
+            .body("estimated_minutes=2000000000")
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Couldn't use that estimate"));
+
+        let res = client
+            .post(format!(
+                "/class/{}/task/async/{}/estimate",
+                class_id, tasks[0]
+            ))
+            .header(ContentType::Form)
+            .body("estimated_minutes=100")
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("as though it takes 100 minutes"));
+
+        let res = client
+            .get(format!("/class/{}/task/async/{}/view", class_id, tasks[0]))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(
+            string.contains("This task should take about 100 minutes (this is your own estimate).")
+        );
+    }
 }
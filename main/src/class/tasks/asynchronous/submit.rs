@@ -0,0 +1,574 @@
+//! Handing in work for asynchronous tasks.
+//!
+//! Students can submit some text, files or both. Every submission is kept; students can only
+//! resubmit their work if the task has a resubmission deadline which hasn't passed yet. Files are
+//! kept in the [`Storage`] backend.
+
+use std::collections::HashMap;
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use malvolio::{prelude::*, text::Text};
+use rocket::{
+    fs::TempFile,
+    http::{ContentType, Header, Status},
+    serde::json::Json,
+    tokio::fs,
+    State,
+};
+
+use crate::{
+    auth::{scope::ManageTasks, ApiAuth, AuthCookie},
+    class::{get_user_role_in_class, ClassMemberRole},
+    db::{Database, DatabaseConnection},
+    models::{
+        AsyncTaskSubmission, ClassAsynchronousTask, NewAsyncTaskSubmission, NewSubmissionFile,
+        StudentClassAsynchronousTask, SubmissionFile,
+    },
+    schema::{
+        async_task_submission, async_task_submission_file, class_asynchronous_task, class_student,
+        student_class_asynchronous_task,
+    },
+    storage::Storage,
+    utils::{default_head, json_response::ApiResponse},
+};
+
+use thiserror::Error as ThisError;
+
+/// Parses the (optional) date until which students can resubmit their work. Empty strings are
+/// treated as not having set one.
+pub(crate) fn parse_resubmit_until(
+    date: Option<&str>,
+) -> Result<Option<NaiveDateTime>, chrono::ParseError> {
+    match date.map(str::trim) {
+        Some(date) if !date.is_empty() => {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M").map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum SubmissionError {
+    #[error("database error")]
+    DatabaseError,
+    #[error("storage error")]
+    StorageError,
+    #[error("permission error")]
+    PermissionError,
+    #[error("not found")]
+    NotFound,
+    #[error("empty submission")]
+    Empty,
+    #[error("invalid file")]
+    InvalidFile,
+    #[error("resubmission closed")]
+    ResubmissionClosed,
+}
+
+impl From<diesel::result::Error> for SubmissionError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        match e {
+            diesel::result::Error::NotFound => Self::NotFound,
+            _ => Self::DatabaseError,
+        }
+    }
+}
+
+impl From<std::io::Error> for SubmissionError {
+    fn from(e: std::io::Error) -> Self {
+        error!("{:#?}", e);
+        Self::StorageError
+    }
+}
+
+impl SubmissionError {
+    fn status(&self) -> Status {
+        match self {
+            SubmissionError::DatabaseError | SubmissionError::StorageError => {
+                Status::InternalServerError
+            }
+            SubmissionError::PermissionError => Status::Forbidden,
+            SubmissionError::NotFound => Status::NotFound,
+            SubmissionError::Empty
+            | SubmissionError::InvalidFile
+            | SubmissionError::ResubmissionClosed => Status::BadRequest,
+        }
+    }
+
+    pub(crate) fn explanation(&self) -> &'static str {
+        match self {
+            SubmissionError::DatabaseError | SubmissionError::StorageError => {
+                "Something's up on our end. We're working to fix it as fast as we can!"
+            }
+            SubmissionError::PermissionError => {
+                "You don't have permission to do this – only the students who have been set a task \
+                can hand in work for it, and only they and the class' teachers can see it."
+            }
+            SubmissionError::NotFound => "That task (or submission) doesn't exist.",
+            SubmissionError::Empty => "Please write something or attach a file to submit.",
+            SubmissionError::InvalidFile => "One of the files you attached couldn't be read.",
+            SubmissionError::ResubmissionClosed => {
+                "You've already submitted your work for this task, and it can't be resubmitted \
+                (either because resubmissions aren't allowed, or because the deadline for them \
+                has passed)."
+            }
+        }
+    }
+
+    pub(crate) fn render(self) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head("Could not submit this work"))
+            .body(
+                Body::new()
+                    .child(H1::new("Could not submit this work"))
+                    .child(P::with_text(self.explanation())),
+            )
+    }
+}
+
+/// A submission, along with the files which are part of it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubmissionWithFiles {
+    #[serde(flatten)]
+    pub submission: AsyncTaskSubmission,
+    pub files: Vec<SubmissionFile>,
+}
+
+/// The submissions made for each of the `student_class_asynchronous_task`s (most recent first).
+pub(crate) fn load_submissions(
+    student_task_ids: Vec<i32>,
+    c: &DatabaseConnection,
+) -> QueryResult<HashMap<i32, Vec<SubmissionWithFiles>>> {
+    let submissions = async_task_submission::table
+        .filter(async_task_submission::student_class_asynchronous_task_id.eq_any(student_task_ids))
+        .order_by(async_task_submission::submitted.desc())
+        .load::<AsyncTaskSubmission>(c)?;
+    let mut files = SubmissionFile::belonging_to(&submissions)
+        .order_by(async_task_submission_file::id)
+        .load::<SubmissionFile>(c)?
+        .grouped_by(&submissions)
+        .into_iter();
+    let mut grouped: HashMap<i32, Vec<SubmissionWithFiles>> = HashMap::new();
+    for submission in submissions {
+        grouped
+            .entry(submission.student_class_asynchronous_task_id)
+            .or_default()
+            .push(SubmissionWithFiles {
+                submission,
+                files: files.next().unwrap_or_default(),
+            });
+    }
+    Ok(grouped)
+}
+
+/// A link to download a file which was submitted.
+pub(crate) fn file_link(class_id: i32, task_id: i32, file: &SubmissionFile) -> A {
+    A::new()
+        .attribute(Href::new(format!(
+            "/class/{}/task/async/{}/submission/file/{}",
+            class_id, task_id, file.id
+        )))
+        .text(format!("{} ({} bytes)", file.filename, file.size))
+}
+
+/// Renders a submission (for both students and teachers).
+pub(crate) fn render_submission(
+    class_id: i32,
+    task_id: i32,
+    submission: &SubmissionWithFiles,
+) -> Div {
+    Div::new()
+        .child(P::with_text(format!(
+            "Submitted {}{}",
+            submission.submission.submitted.format("%Y-%m-%d %H:%M"),
+            if submission.submission.late {
+                " (late)"
+            } else {
+                ""
+            }
+        )))
+        .map(|div| match &submission.submission.text {
+            Some(text) => div.child(P::with_text(text.clone())),
+            None => div,
+        })
+        .children(
+            submission
+                .files
+                .iter()
+                .map(|file| Div::new().child(file_link(class_id, task_id, file))),
+        )
+}
+
+/// The form through which students hand in their work. (This is written out by hand because
+/// malvolio doesn't support file inputs.)
+pub(crate) fn submission_form(class_id: i32, task_id: i32) -> Text {
+    Text::new_unchecked(format!(
+        "<form method=\"post\" action=\"/class/{}/task/async/{}/submit\" \
+        enctype=\"multipart/form-data\">\
+        <label>Your work <textarea name=\"text\"></textarea></label>\
+        <label>Files <input type=\"file\" name=\"files\" multiple/></label>\
+        <input type=\"submit\" value=\"Submit\"/></form>",
+        class_id, task_id
+    ))
+}
+
+/// Only keeps the last part of the name of an uploaded file, and removes anything from it which
+/// would cause problems in a `Content-Disposition` header.
+fn clean_filename(filename: &str) -> String {
+    let filename = filename
+        .rsplit(&['/', '\\'][..])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .collect::<String>();
+    if filename.trim().is_empty() {
+        "file".to_string()
+    } else {
+        filename
+    }
+}
+
+/// A file which is being submitted.
+pub struct Upload {
+    pub filename: String,
+    pub content_type: String,
+    pub contents: Vec<u8>,
+}
+
+async fn submit_base(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    text: Option<String>,
+    uploads: Vec<Upload>,
+    storage: &Storage,
+    conn: &Database,
+) -> Result<SubmissionWithFiles, SubmissionError> {
+    if get_user_role_in_class(auth.0, class_id, conn).await != Some(ClassMemberRole::Student) {
+        return Err(SubmissionError::PermissionError);
+    }
+    let text = text
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty());
+    if text.is_none() && uploads.is_empty() {
+        return Err(SubmissionError::Empty);
+    }
+    let (task, student_task, previous) = conn
+        .run(move |c| {
+            let (task, student_task) = student_class_asynchronous_task::table
+                .inner_join(class_asynchronous_task::table)
+                .inner_join(class_student::table)
+                .filter(class_asynchronous_task::id.eq(task_id))
+                .filter(class_asynchronous_task::class_id.eq(class_id))
+                .filter(class_student::user_id.eq(auth.0))
+                .select((
+                    class_asynchronous_task::all_columns,
+                    student_class_asynchronous_task::all_columns,
+                ))
+                .first::<(ClassAsynchronousTask, StudentClassAsynchronousTask)>(c)
+                .optional()?
+                .ok_or(SubmissionError::PermissionError)?;
+            let previous = async_task_submission::table
+                .filter(
+                    async_task_submission::student_class_asynchronous_task_id.eq(student_task.id),
+                )
+                .count()
+                .get_result::<i64>(c)?;
+            Ok::<_, SubmissionError>((task, student_task, previous))
+        })
+        .await?;
+    let now = Utc::now().naive_utc();
+    // (this is checked again when the submission is saved, but checking now means that files
+    // aren't uploaded for nothing)
+    check_can_submit(&task, previous, now)?;
+
+    let mut stored: Vec<(String, Upload)> = vec![];
+    for upload in uploads {
+        let key = Storage::new_key("submissions");
+        if let Err(e) = storage.store(&key, upload.contents.clone()).await {
+            for (key, _) in &stored {
+                let _ = storage.delete(key).await;
+            }
+            return Err(e.into());
+        }
+        stored.push((key, upload));
+    }
+    let keys = stored
+        .iter()
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    let res = conn
+        .run(move |c| {
+            c.transaction::<_, SubmissionError, _>(|| {
+                // the row is locked so that two submissions made at the same time can't both
+                // count as the first one
+                student_class_asynchronous_task::table
+                    .find(student_task.id)
+                    .for_update()
+                    .first::<StudentClassAsynchronousTask>(c)?;
+                let previous = async_task_submission::table
+                    .filter(
+                        async_task_submission::student_class_asynchronous_task_id
+                            .eq(student_task.id),
+                    )
+                    .count()
+                    .get_result::<i64>(c)?;
+                check_can_submit(&task, previous, now)?;
+                let submission = diesel::insert_into(async_task_submission::table)
+                    .values(NewAsyncTaskSubmission {
+                        student_class_asynchronous_task_id: student_task.id,
+                        text: text.as_deref(),
+                        submitted: now,
+                        late: now > student_task.effective_due_date(&task),
+                    })
+                    .returning(async_task_submission::all_columns)
+                    .get_result::<AsyncTaskSubmission>(c)?;
+                let files = diesel::insert_into(async_task_submission_file::table)
+                    .values(
+                        stored
+                            .iter()
+                            .map(|(key, upload)| NewSubmissionFile {
+                                async_task_submission_id: submission.id,
+                                filename: &upload.filename,
+                                content_type: &upload.content_type,
+                                size: upload.contents.len() as i64,
+                                storage_key: key,
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .returning(async_task_submission_file::all_columns)
+                    .get_results::<SubmissionFile>(c)?;
+                diesel::update(student_class_asynchronous_task::table.find(student_task.id))
+                    .set(student_class_asynchronous_task::completed.eq(true))
+                    .execute(c)?;
+                Ok(SubmissionWithFiles { submission, files })
+            })
+        })
+        .await;
+    match res {
+        Ok(submission) => Ok(submission),
+        Err(e) => {
+            for key in &keys {
+                let _ = storage.delete(key).await;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Students can hand a task in once, and then again as many times as they like until the task's
+/// `resubmit_until` (if it has one).
+fn check_can_submit(
+    task: &ClassAsynchronousTask,
+    previous: i64,
+    now: NaiveDateTime,
+) -> Result<(), SubmissionError> {
+    if previous > 0
+        && !task
+            .resubmit_until
+            .map(|until| now <= until)
+            .unwrap_or(false)
+    {
+        return Err(SubmissionError::ResubmissionClosed);
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Debug)]
+pub struct SubmitForm<'r> {
+    text: Option<String>,
+    files: Vec<TempFile<'r>>,
+}
+
+/// Reads the files uploaded through the form (browsers send an empty file if none are chosen,
+/// which is ignored).
+async fn read_uploads(files: &[TempFile<'_>]) -> Result<Vec<Upload>, SubmissionError> {
+    let mut uploads = vec![];
+    for file in files {
+        let filename = file
+            .raw_name()
+            .map(|name| name.dangerous_unsafe_unsanitized_raw().as_str())
+            .unwrap_or_default();
+        if file.len() == 0 && filename.is_empty() {
+            continue;
+        }
+        let contents = match file.path() {
+            Some(path) => fs::read(path)
+                .await
+                .map_err(|_| SubmissionError::InvalidFile)?,
+            None => return Err(SubmissionError::InvalidFile),
+        };
+        uploads.push(Upload {
+            filename: clean_filename(filename),
+            content_type: file
+                .content_type()
+                .map(ToString::to_string)
+                .unwrap_or_else(|| ContentType::Binary.to_string()),
+            contents,
+        });
+    }
+    Ok(uploads)
+}
+
+#[post("/<class_id>/task/async/<task_id>/submit", data = "<form>")]
+pub async fn html_submit_work(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<SubmitForm<'_>>,
+    storage: &State<Storage>,
+    conn: Database,
+) -> Html {
+    let form = form.into_inner();
+    let uploads = match read_uploads(&form.files).await {
+        Ok(uploads) => uploads,
+        Err(e) => return e.render(),
+    };
+    match submit_base(class_id, task_id, auth, form.text, uploads, storage, &conn).await {
+        Ok(submission) => Html::new().head(default_head("Work submitted")).body(
+            Body::new()
+                .child(H1::new("Work submitted"))
+                .child(P::with_text(if submission.submission.late {
+                    "Your work has been handed in, but it was late."
+                } else {
+                    "Your work has been handed in."
+                })),
+        ),
+        Err(e) => e.render(),
+    }
+}
+
+/// A file submitted through the API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiUpload {
+    pub filename: String,
+    pub content_type: Option<String>,
+    /// The contents of the file (base64 encoded).
+    pub contents: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiSubmitForm {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub files: Vec<ApiUpload>,
+}
+
+#[post("/<class_id>/task/async/<task_id>/submit", data = "<form>")]
+pub async fn api_submit_work(
+    class_id: i32,
+    task_id: i32,
+    auth: ApiAuth<ManageTasks>,
+    form: Json<ApiSubmitForm>,
+    storage: &State<Storage>,
+    conn: Database,
+) -> Json<ApiResponse<SubmissionWithFiles>> {
+    let form = form.into_inner();
+    let uploads = form
+        .files
+        .into_iter()
+        .map(|file| {
+            Ok(Upload {
+                filename: clean_filename(&file.filename),
+                content_type: file
+                    .content_type
+                    .unwrap_or_else(|| ContentType::Binary.to_string()),
+                contents: base64::decode(&file.contents)
+                    .map_err(|_| SubmissionError::InvalidFile)?,
+            })
+        })
+        .collect::<Result<Vec<_>, SubmissionError>>();
+    Json(match uploads {
+        Ok(uploads) => {
+            match submit_base(
+                class_id,
+                task_id,
+                auth.into(),
+                form.text,
+                uploads,
+                storage,
+                &conn,
+            )
+            .await
+            {
+                Ok(submission) => ApiResponse::new_ok(submission),
+                Err(e) => ApiResponse::new_err(e.explanation()),
+            }
+        }
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+/// Files are always sent as `application/octet-stream` (whatever type they were uploaded as), so
+/// that browsers download them rather than rendering them (e.g. as HTML).
+#[derive(Responder)]
+pub struct FileDownload {
+    contents: (ContentType, Vec<u8>),
+    disposition: Header<'static>,
+    nosniff: Header<'static>,
+}
+
+/// Files can be downloaded by the student who submitted them, and by the class' teachers.
+async fn download_base(
+    class_id: i32,
+    task_id: i32,
+    file_id: i32,
+    auth: AuthCookie,
+    storage: &Storage,
+    conn: &Database,
+) -> Result<FileDownload, SubmissionError> {
+    let role = get_user_role_in_class(auth.0, class_id, conn)
+        .await
+        .ok_or(SubmissionError::PermissionError)?;
+    let (file, student_user_id) = conn
+        .run(move |c| {
+            async_task_submission_file::table
+                .find(file_id)
+                .inner_join(
+                    async_task_submission::table.inner_join(
+                        student_class_asynchronous_task::table
+                            .inner_join(class_asynchronous_task::table)
+                            .inner_join(class_student::table),
+                    ),
+                )
+                .filter(class_asynchronous_task::id.eq(task_id))
+                .filter(class_asynchronous_task::class_id.eq(class_id))
+                .select((
+                    async_task_submission_file::all_columns,
+                    class_student::user_id,
+                ))
+                .first::<(SubmissionFile, i32)>(c)
+        })
+        .await?;
+    if role == ClassMemberRole::Student && student_user_id != auth.0 {
+        return Err(SubmissionError::PermissionError);
+    }
+    let contents = storage.retrieve(&file.storage_key).await?;
+    Ok(FileDownload {
+        contents: (ContentType::Binary, contents),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", file.filename),
+        ),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+    })
+}
+
+#[get("/<class_id>/task/async/<task_id>/submission/file/<file_id>")]
+pub async fn download_submitted_file(
+    class_id: i32,
+    task_id: i32,
+    file_id: i32,
+    auth: AuthCookie,
+    storage: &State<Storage>,
+    conn: Database,
+) -> Result<FileDownload, Html> {
+    download_base(class_id, task_id, file_id, auth, storage, &conn)
+        .await
+        .map_err(SubmissionError::render)
+}
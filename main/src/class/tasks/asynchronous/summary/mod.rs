@@ -2,7 +2,7 @@ use rocket::serde::json::Json;
 use thiserror::Error as ThisError;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
     class::get_user_role_in_class,
     db::Database,
     models::{ClassAsynchronousTask, StudentClassAsynchronousTask, User},
@@ -92,7 +92,7 @@ pub enum ViewTasksSummary {
 /// student (this is retrieved from the database).
 pub async fn api_view_all_async_tasks_in_class(
     class_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ReadTasks>,
     conn: Database,
 ) -> Json<ApiResponse<ViewTasksSummary>> {
     Json(
@@ -57,6 +57,12 @@ pub fn render_student_task_summary(
                                 "Description: {}",
                                 class_task_instance.description
                             )))
+                            .child(P::with_text(format!(
+                                "Due: {}",
+                                student_task_instance
+                                    .effective_due_date(&class_task_instance)
+                                    .format("%Y-%m-%d %H:%M")
+                            )))
                             .child(P::with_text(format!(
                                 "Completed: {}",
                                 student_task_instance.completed
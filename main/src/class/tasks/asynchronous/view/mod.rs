@@ -1,6 +1,9 @@
 use crate::{
-    auth::AuthCookie,
-    class::get_user_role_in_class,
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
+    class::{
+        get_user_role_in_class, gradebook::ReleasedMark,
+        tasks::asynchronous::submit::SubmissionWithFiles,
+    },
     db::Database,
     models::{ClassAsynchronousTask, StudentClassAsynchronousTask, User},
     utils::{
@@ -18,6 +21,8 @@ use self::{
     teacher::{get_teacher_async_task_summary, render_teacher_task_summary},
 };
 
+pub use teacher::{api_view_submissions, html_view_submissions};
+
 mod student;
 mod teacher;
 
@@ -53,8 +58,8 @@ pub async fn html_view_specific_asynchronous_task(
         }
         crate::class::ClassMemberRole::Student => {
             match get_student_async_task_summary(task_id, class_id, auth.0, &conn).await {
-                Ok((class_task, student_task)) => {
-                    render_student_task_summary(class_task, student_task)
+                Ok((class_task, student_task, submissions, mark)) => {
+                    render_student_task_summary(class_task, student_task, submissions, mark)
                 }
                 Err(e) => match e {
                     ViewAsyncTaskSummaryError::DatabaseError => database_error(),
@@ -68,6 +73,10 @@ pub async fn html_view_specific_asynchronous_task(
 pub struct StudentViewClassRes {
     task: ClassAsynchronousTask,
     student_task: StudentClassAsynchronousTask,
+    /// The most recent submission first.
+    submissions: Vec<SubmissionWithFiles>,
+    /// This is only included once the teacher has released the marks for the task.
+    mark: Option<ReleasedMark>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -94,7 +103,7 @@ pub enum ViewSpecificAsynchronousTaskRes {
 pub async fn api_view_specific_asynchronous_task(
     class_id: i32,
     task_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ReadTasks>,
     conn: Database,
 ) -> Json<ApiResponse<ViewSpecificAsynchronousTaskRes>> {
     let role = if let Some(role) = get_user_role_in_class(auth.0, class_id, &conn).await {
@@ -125,10 +134,12 @@ pub async fn api_view_specific_asynchronous_task(
         }
         crate::class::ClassMemberRole::Student => {
             match get_student_async_task_summary(task_id, class_id, auth.0, &conn).await {
-                Ok((class_task, student_task)) => ApiResponse::new_ok(
+                Ok((class_task, student_task, submissions, mark)) => ApiResponse::new_ok(
                     ViewSpecificAsynchronousTaskRes::Student(StudentViewClassRes {
                         task: class_task,
                         student_task,
+                        submissions,
+                        mark,
                     }),
                 ),
                 Err(e) => ApiResponse::new_err(match e {
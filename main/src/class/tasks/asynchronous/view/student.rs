@@ -1,7 +1,15 @@
 use diesel::prelude::*;
 use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::form::{FormStyle, FormSubmitInputStyle, FormTextInputStyle};
 
 use crate::{
+    class::{
+        gradebook::{released_mark, ReleasedMark},
+        tasks::asynchronous::submit::{
+            load_submissions, render_submission, submission_form, SubmissionWithFiles,
+        },
+    },
     db::Database,
     models::{ClassAsynchronousTask, StudentClassAsynchronousTask},
     utils::default_head,
@@ -14,11 +22,19 @@ pub async fn get_student_async_task_summary(
     class_id: i32,
     user_id: i32,
     conn: &Database,
-) -> Result<(ClassAsynchronousTask, StudentClassAsynchronousTask), ViewAsyncTaskSummaryError> {
+) -> Result<
+    (
+        ClassAsynchronousTask,
+        StudentClassAsynchronousTask,
+        Vec<SubmissionWithFiles>,
+        Option<ReleasedMark>,
+    ),
+    ViewAsyncTaskSummaryError,
+> {
     use crate::schema::class_asynchronous_task::dsl as class_asynchronous_task;
     use crate::schema::class_student::dsl as class_student;
     conn.run(move |c| {
-        crate::schema::student_class_asynchronous_task::table
+        let (class_task, student_task) = crate::schema::student_class_asynchronous_task::table
             .inner_join(class_asynchronous_task::class_asynchronous_task)
             .filter(class_asynchronous_task::id.eq(task_id))
             .filter(class_asynchronous_task::class_id.eq(class_id))
@@ -29,19 +45,66 @@ pub async fn get_student_async_task_summary(
                 crate::schema::class_asynchronous_task::all_columns,
                 crate::schema::student_class_asynchronous_task::all_columns,
             ))
-            .first::<(ClassAsynchronousTask, StudentClassAsynchronousTask)>(c)
+            .first::<(ClassAsynchronousTask, StudentClassAsynchronousTask)>(c)?;
+        let submissions = load_submissions(vec![student_task.id], c)?
+            .remove(&student_task.id)
+            .unwrap_or_default();
+        let mark = released_mark(student_task.id, c)?;
+        Ok((class_task, student_task, submissions, mark))
     })
     .await
-    .map_err(|e| {
+    .map_err(|e: diesel::result::Error| {
         error!("{:#?}", e);
         ViewAsyncTaskSummaryError::DatabaseError
     })
 }
 
+/// Lets the student say how long they think the task will take them (leaving it empty goes back to
+/// their teacher's estimate).
+fn estimate_form(
+    class_task: &ClassAsynchronousTask,
+    student_task: &StudentClassAsynchronousTask,
+) -> Form {
+    Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "/class/{}/task/async/{}/estimate",
+            class_task.class_id, class_task.id
+        )))
+        .child(Label::new(
+            "How long you think this task will take you (in minutes)",
+        ))
+        .child(
+            Input::new()
+                .apply(FormTextInputStyle)
+                .attribute(Type::Text)
+                .attribute(Name::new("estimated_minutes"))
+                .map(|input| match student_task.estimated_minutes {
+                    Some(minutes) => input.attribute(Value::new(minutes.to_string())),
+                    None => input,
+                }),
+        )
+        .child(
+            Input::new()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Update my estimate")),
+        )
+}
+
 pub fn render_student_task_summary(
     class_task: ClassAsynchronousTask,
     student_task: StudentClassAsynchronousTask,
+    submissions: Vec<SubmissionWithFiles>,
+    mark: Option<ReleasedMark>,
 ) -> Html {
+    let now = chrono::Utc::now().naive_utc();
+    let can_submit = submissions.is_empty()
+        || class_task
+            .resubmit_until
+            .map(|until| now <= until)
+            .unwrap_or(false);
     Html::new().head(default_head("Task".to_string())).body(
         Body::new()
             .child(H1::new(format!("Task {}", class_task.title)))
@@ -49,10 +112,52 @@ pub fn render_student_task_summary(
                 "Description {}",
                 class_task.description
             )))
+            .child(P::with_text(match student_task.due_date {
+                Some(due_date) => format!(
+                    "Due {} (your teacher has changed your due date for this task)",
+                    due_date.format("%Y-%m-%d %H:%M")
+                ),
+                None => format!("Due {}", class_task.due_date.format("%Y-%m-%d %H:%M")),
+            }))
             .child(P::with_text(if !student_task.completed {
                 "You have not marked this task as done"
             } else {
                 "You have marked this task as done."
-            })),
+            }))
+            .child(P::with_text(format!(
+                "This task should take about {} minutes{}.",
+                student_task.estimated_minutes(&class_task),
+                if student_task.estimated_minutes.is_some() {
+                    " (this is your own estimate)"
+                } else {
+                    ""
+                }
+            )))
+            .child(estimate_form(&class_task, &student_task))
+            .map(|body| match mark {
+                Some(mark) => {
+                    let body = body.child(H3::new(format!("Your mark: {}", mark.mark)));
+                    match mark.feedback {
+                        Some(feedback) => body.child(P::with_text(feedback)),
+                        None => body,
+                    }
+                }
+                None => body,
+            })
+            .children(submissions.iter().map(|submission| {
+                render_submission(class_task.class_id, class_task.id, submission)
+            }))
+            .map(|body| {
+                if can_submit {
+                    body.child(H3::new(if submissions.is_empty() {
+                        "Submit your work"
+                    } else {
+                        "Resubmit your work"
+                    }))
+                    .child(submission_form(class_task.class_id, class_task.id))
+                } else {
+                    body
+                }
+            }),
     )
 }
@@ -1,12 +1,26 @@
 use crate::rocket::futures::TryFutureExt;
 use diesel::prelude::*;
 use malvolio::prelude::*;
-use portia::levels::Level;
+use mercutio::Apply;
+use portia::{
+    form::{FormStyle, FormSubmitInputStyle},
+    levels::Level,
+};
+use rocket::serde::json::Json;
 
 use crate::{
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
+    class::{
+        get_user_role_in_class,
+        tasks::asynchronous::submit::{
+            load_submissions, render_submission, SubmissionError, SubmissionWithFiles,
+        },
+        ClassMemberRole,
+    },
     db::Database,
     models::{ClassAsynchronousTask, StudentClassAsynchronousTask, User},
-    utils::default_head,
+    schema::{class_asynchronous_task, class_student, student_class_asynchronous_task, users},
+    utils::{default_head, json_response::ApiResponse},
 };
 
 use super::ViewAsyncTaskSummaryError;
@@ -61,6 +75,44 @@ pub async fn get_teacher_async_task_summary(
     })
 }
 
+/// Lets the teacher give the student a different due date (e.g. an extension). Leaving the date
+/// empty makes the student's work due when the task is again.
+fn student_due_date_form(
+    class_task: &ClassAsynchronousTask,
+    student_task: &StudentClassAsynchronousTask,
+) -> Form {
+    Form::new()
+        .apply(FormStyle)
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "/class/{}/task/async/{}/due_date",
+            class_task.class_id, class_task.id
+        )))
+        .child(
+            Input::new()
+                .attribute(Type::Hidden)
+                .attribute(Name::new("student_task"))
+                .attribute(Value::new(student_task.id.to_string())),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::DateTimeLocal)
+                .attribute(Name::new("due_date"))
+                .apply(|input: Input| match student_task.due_date {
+                    Some(due_date) => {
+                        input.attribute(Value::new(due_date.format("%Y-%m-%dT%H:%M").to_string()))
+                    }
+                    None => input,
+                }),
+        )
+        .child(
+            Input::new()
+                .apply(FormSubmitInputStyle)
+                .attribute(Type::Submit)
+                .attribute(Value::new("Change this student's due date")),
+        )
+}
+
 pub fn render_teacher_task_summary(
     class_task: ClassAsynchronousTask,
     tasks: Vec<(User, StudentClassAsynchronousTask)>,
@@ -74,6 +126,10 @@ pub fn render_teacher_task_summary(
                     "Description: {}",
                     class_task.description
                 )))
+                .child(P::with_text(format!(
+                    "Should take about {} minutes",
+                    class_task.estimated_minutes
+                )))
                 .child(P::with_text(format!(
                     "{} of {} completed",
                     tasks
@@ -82,10 +138,166 @@ pub fn render_teacher_task_summary(
                         .sum::<i32>(),
                     tasks.len()
                 )))
+                .child(
+                    A::new()
+                        .attribute(Href::new(format!(
+                            "/class/{}/task/async/{}/submissions",
+                            class_task.class_id, class_task.id
+                        )))
+                        .text("See what has been submitted"),
+                )
+                .child(
+                    A::new()
+                        .attribute(Href::new(format!(
+                            "/class/{}/task/async/{}/marks",
+                            class_task.class_id, class_task.id
+                        )))
+                        .text("Marks"),
+                )
                 .child(Level::new().children(tasks.into_iter().map(|(user, task)| {
                     Div::new()
                         .child(H3::new(format!("Student: {}", user.username)))
                         .child(P::with_text(format!("Completed: {}", task.completed)))
+                        .child(P::with_text(format!(
+                            "Due: {}{}",
+                            task.effective_due_date(&class_task)
+                                .format("%Y-%m-%d %H:%M"),
+                            if task.due_date.is_some() {
+                                " (changed for this student)"
+                            } else {
+                                ""
+                            }
+                        )))
+                        .child(student_due_date_form(&class_task, &task))
                 }))),
         )
 }
+
+/// What a student has handed in for a task.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StudentSubmissions {
+    pub user_id: i32,
+    pub username: String,
+    /// The most recent submission first (this is empty if the student hasn't submitted anything).
+    pub submissions: Vec<SubmissionWithFiles>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskSubmissions {
+    pub task: ClassAsynchronousTask,
+    pub students: Vec<StudentSubmissions>,
+}
+
+/// Everything which has been handed in for the task (by each of the students it was set to, in
+/// alphabetical order).
+async fn get_task_submissions(
+    task_id: i32,
+    class_id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<TaskSubmissions, SubmissionError> {
+    if get_user_role_in_class(user_id, class_id, conn).await != Some(ClassMemberRole::Teacher) {
+        return Err(SubmissionError::PermissionError);
+    }
+    conn.run(move |c| {
+        let task = class_asynchronous_task::table
+            .filter(class_asynchronous_task::id.eq(task_id))
+            .filter(class_asynchronous_task::class_id.eq(class_id))
+            .first::<ClassAsynchronousTask>(c)?;
+        let students = StudentClassAsynchronousTask::belonging_to(&task)
+            .inner_join(class_student::table.inner_join(users::table))
+            .order_by(users::username)
+            .select((
+                student_class_asynchronous_task::id,
+                users::id,
+                users::username,
+            ))
+            .load::<(i32, i32, String)>(c)?;
+        let mut submissions = load_submissions(students.iter().map(|(id, _, _)| *id).collect(), c)?;
+        Ok(TaskSubmissions {
+            task,
+            students: students
+                .into_iter()
+                .map(|(id, user_id, username)| StudentSubmissions {
+                    user_id,
+                    username,
+                    submissions: submissions.remove(&id).unwrap_or_default(),
+                })
+                .collect(),
+        })
+    })
+    .await
+}
+
+#[get("/<class_id>/task/async/<task_id>/submissions")]
+/// Shows teachers who has handed in their work for the task, and what they handed in.
+pub async fn html_view_submissions(
+    class_id: i32,
+    task_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    let submissions = match get_task_submissions(task_id, class_id, auth.0, &conn).await {
+        Ok(submissions) => submissions,
+        Err(e) => return e.render(),
+    };
+    let submitted = submissions
+        .students
+        .iter()
+        .filter(|student| !student.submissions.is_empty())
+        .count();
+    Html::new()
+        .head(default_head(format!(
+            "Submissions for {}",
+            submissions.task.title
+        )))
+        .body(
+            Body::new()
+                .child(H1::new(format!(
+                    "Submissions for {}",
+                    submissions.task.title
+                )))
+                .child(P::with_text(format!(
+                    "{} of {} students have submitted their work (it was due {}).",
+                    submitted,
+                    submissions.students.len(),
+                    submissions.task.due_date.format("%Y-%m-%d %H:%M")
+                )))
+                .child(
+                    Level::new().children(submissions.students.into_iter().map(|student| {
+                        let div = Div::new().child(H3::new(student.username.clone()));
+                        let mut submissions = student.submissions.iter();
+                        match submissions.next() {
+                            None => div.child(P::with_text("Not submitted yet.")),
+                            Some(latest) => div
+                                .child(render_submission(class_id, task_id, latest))
+                                .map(|div| {
+                                    if student.submissions.len() > 1 {
+                                        div.child(P::with_text("Earlier submissions:"))
+                                    } else {
+                                        div
+                                    }
+                                })
+                                .children(submissions.map(|submission| {
+                                    render_submission(class_id, task_id, submission)
+                                })),
+                        }
+                    })),
+                ),
+        )
+}
+
+#[get("/<class_id>/task/async/<task_id>/submissions")]
+pub async fn api_view_submissions(
+    class_id: i32,
+    task_id: i32,
+    auth: ApiAuth<ReadTasks>,
+    conn: Database,
+) -> Json<ApiResponse<TaskSubmissions>> {
+    Json(
+        match get_task_submissions(task_id, class_id, auth.0, &conn).await {
+            Ok(submissions) => ApiResponse::new_ok(submissions),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
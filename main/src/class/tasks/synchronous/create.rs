@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
 use malvolio::prelude::*;
 use mercutio::Apply;
@@ -9,10 +9,22 @@ use portia::{
 use rocket::serde::json::Json;
 
 use crate::{
-    class::{get_user_role_in_class, tasks::synchronous::AuthCookie, user_is_teacher},
-    db::Database,
-    models::{ClassSynchronousTask, NewClassSynchronousTask, NewStudentClassSynchronousTask},
-    schema::{class_synchronous_task, class_teacher},
+    auth::{scope::ManageTasks, ApiAuth},
+    class::{
+        get_user_role_in_class,
+        tasks::synchronous::{
+            recurrence::{expand_occurrences, parse_recurrence, Recurrence},
+            AuthCookie,
+        },
+        user_is_teacher,
+    },
+    db::{Database, DatabaseConnection},
+    institution::{academic::class_holidays_between, policy},
+    models::{
+        ClassSynchronousTask, NewClassSynchronousTask, NewClassSynchronousTaskOccurrence,
+        NewStudentClassSynchronousTask,
+    },
+    schema::{class_synchronous_task, class_synchronous_task_occurrence, class_teacher},
     utils::{
         default_head,
         error::{LovelaceError, LovelaceResult},
@@ -37,11 +49,34 @@ fn create_new_sync_task_form() -> Form {
                 .attribute(Name::new("description"))
                 .attribute(Type::Text),
         )
+        .child(
+            Input::new()
+                .attribute(Name::new("start_time"))
+                .attribute(Type::DateTimeLocal),
+        )
+        .child(
+            Input::new()
+                .attribute(Name::new("end_time"))
+                .attribute(Type::DateTimeLocal),
+        )
         .child(
             Input::new()
                 .apply(FormTextInputStyle)
-                .attribute(Name::new("due_date"))
-                .attribute(Type::Text),
+                .attribute(Name::new("recurrence"))
+                .attribute(Type::Text)
+                .attribute(Placeholder::new(
+                    "How often the task repeats, if it does (e.g. \
+                    FREQ=WEEKLY;BYDAY=MO,WE;UNTIL=20220722)",
+                )),
+        )
+        .child(
+            Input::new()
+                .apply(FormTextInputStyle)
+                .attribute(Name::new("exceptions"))
+                .attribute(Type::Text)
+                .attribute(Placeholder::new(
+                    "Days on which it doesn't take place (e.g. 2021-10-25, 2021-10-27)",
+                )),
         )
         .child(
             Input::new()
@@ -59,36 +94,148 @@ pub struct CreateNewSyncTask {
     description: String,
     start_time: String,
     end_time: String,
+    /// If the task repeats, an RRULE (see [`super::recurrence`]).
+    recurrence: Option<String>,
+    /// Days (separated by commas) on which a repeating task doesn't take place.
+    exceptions: Option<String>,
 }
 
 #[get("/<class_id>/task/sync/create")]
 pub async fn get_create_new_sync_task(class_id: i32, auth: AuthCookie, conn: Database) -> Html {
-    if conn
-        .run(move |c| user_is_teacher(auth.0, class_id, c))
+    match conn
+        .run(move |c| {
+            if user_is_teacher(auth.0, class_id, c) {
+                Some(policy::can_add_sync_task(auth.0, class_id, c))
+            } else {
+                None
+            }
+        })
         .await
     {
-        Html::new().body(
+        Some(Ok(())) => Html::new().body(
             Body::new()
                 .child(H1::new("Create a new synchronous task."))
                 .child(create_new_sync_task_form()),
-        )
-    } else {
-        permission_error()
+        ),
+        Some(Err(e)) => e.render(),
+        None => permission_error(),
     }
 }
 
+/// A newly created task, and anything the teacher should know about it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreatedSyncTask {
+    #[serde(flatten)]
+    pub task: ClassSynchronousTask,
+    /// For example, if the task is during the holidays.
+    pub warnings: Vec<String>,
+}
+
+/// Warns about (occurrences of) tasks which are scheduled during a holiday of the class'
+/// institution (they can still be created, because sometimes this is intentional). Only the first
+/// year of a repeating task is checked.
+fn holiday_warnings(
+    task: &ClassSynchronousTask,
+    c: &DatabaseConnection,
+) -> QueryResult<Vec<String>> {
+    let occurrences = expand_occurrences(
+        vec![(task.clone(), ())],
+        task.start_time,
+        task.start_time + Duration::days(366),
+        c,
+    )?;
+    let last_day = match occurrences
+        .iter()
+        .map(|(o, _)| o.task.end_time.date())
+        .max()
+    {
+        Some(last_day) => last_day,
+        None => return Ok(vec![]),
+    };
+    Ok(
+        class_holidays_between(task.class_id, task.start_time.date(), last_day, c)?
+            .into_iter()
+            .filter_map(|holiday| {
+                let during = occurrences
+                    .iter()
+                    .filter(|(occurrence, _)| {
+                        occurrence.task.start_time.date() <= holiday.ends
+                            && occurrence.task.end_time.date() >= holiday.starts
+                    })
+                    .count();
+                let which = match during {
+                    0 => return None,
+                    1 if task.recurrence.is_none() => "This task takes place".to_string(),
+                    1 => "One of the occurrences of this task takes place".to_string(),
+                    n => format!("{} of the occurrences of this task take place", n),
+                };
+                Some(format!(
+                    "{} during a holiday ({}, from {} to {}).",
+                    which,
+                    holiday.name,
+                    holiday.starts.format("%Y-%m-%d"),
+                    holiday.ends.format("%Y-%m-%d")
+                ))
+            })
+            .collect(),
+    )
+}
+
+/// Works out which occurrences of the task the exceptions (a list of dates separated by commas)
+/// refer to.
+fn parse_exceptions(
+    exceptions: Option<&str>,
+    recurrence: Option<&Recurrence>,
+    start_time: NaiveDateTime,
+) -> LovelaceResult<Vec<NaiveDateTime>> {
+    let dates = exceptions
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|date| !date.is_empty())
+        .map(|date| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| LovelaceError::ParseDateError)
+        })
+        .collect::<LovelaceResult<Vec<_>>>()?;
+    if dates.is_empty() {
+        return Ok(vec![]);
+    }
+    let recurrence = recurrence.ok_or_else(|| {
+        LovelaceError::InvalidRecurrence(
+            "Only tasks which repeat can have days on which they don't take place.".to_string(),
+        )
+    })?;
+    dates
+        .into_iter()
+        .map(|date| {
+            recurrence
+                .occurrences(start_time)
+                .take_while(|start| start.date() <= date)
+                .find(|start| start.date() == date)
+                .ok_or_else(|| {
+                    LovelaceError::InvalidRecurrence(format!(
+                        "This task doesn't take place on {}, so it can't be skipped then.",
+                        date
+                    ))
+                })
+        })
+        .collect()
+}
+
 async fn create_new_sync_task(
     conn: Database,
     class_id: i32,
     auth: AuthCookie,
     form: &CreateNewSyncTask,
-) -> LovelaceResult<ClassSynchronousTask> {
+) -> LovelaceResult<CreatedSyncTask> {
     match get_user_role_in_class(auth.0, class_id, &conn).await {
         Some(crate::class::ClassMemberRole::Teacher) => {}
         None | Some(crate::class::ClassMemberRole::Student) => {
-            return Err(LovelaceError::PermissionError)
+            return Err(LovelaceError::PermissionError(None))
         }
     }
+    conn.run(move |c| policy::can_add_sync_task(auth.0, class_id, c))
+        .await?;
     let start_time = match NaiveDateTime::parse_from_str(&form.start_time, "%Y-%m-%dT%H:%M") {
         Ok(date) => date,
         Err(_) => return Err(LovelaceError::ParseDateError),
@@ -97,6 +244,12 @@ async fn create_new_sync_task(
         Ok(date) => date,
         Err(_) => return Err(LovelaceError::ParseDateError),
     };
+    let recurrence = parse_recurrence(form.recurrence.as_deref())?;
+    if let Some(recurrence) = &recurrence {
+        recurrence.check_occurs(start_time)?;
+    }
+    let exceptions = parse_exceptions(form.exceptions.as_deref(), recurrence.as_ref(), start_time)?;
+    let recurrence = recurrence.map(|recurrence| recurrence.to_string());
     let title = form.title.clone();
     let description = form.description.clone();
     let task = conn
@@ -114,6 +267,7 @@ async fn create_new_sync_task(
                         .first::<i32>(c)
                         .unwrap(),
                     class_id,
+                    recurrence: recurrence.as_deref(),
                 })
                 .returning(class_synchronous_task::all_columns)
                 .get_result::<ClassSynchronousTask>(c)
@@ -154,7 +308,33 @@ async fn create_new_sync_task(
         error!("{:#?}", e);
         LovelaceError::DatabaseError
     })?;
-    Ok(task)
+    conn.run(move |c| {
+        diesel::insert_into(class_synchronous_task_occurrence::table)
+            .values(
+                exceptions
+                    .into_iter()
+                    .map(|original_start_time| NewClassSynchronousTaskOccurrence {
+                        class_synchronous_task_id,
+                        original_start_time,
+                        cancelled: true,
+                        title: None,
+                        description: None,
+                        start_time: None,
+                        end_time: None,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(c)
+    })
+    .await
+    .map_err(|e| {
+        error!("{:#?}", e);
+        LovelaceError::DatabaseError
+    })?;
+    let (task, warnings) = conn
+        .run(move |c| holiday_warnings(&task, c).map(|warnings| (task, warnings)))
+        .await?;
+    Ok(CreatedSyncTask { task, warnings })
 }
 
 #[post("/<class_id>/task/sync/create", data = "<form>")]
@@ -165,12 +345,18 @@ pub async fn html_create_new_sync_task(
     form: rocket::form::Form<CreateNewSyncTask>,
 ) -> Html {
     match create_new_sync_task(conn, class_id, auth, &form).await {
-        Ok(_) => Html::new()
+        Ok(created) => Html::new()
             .head(default_head("Created that task".to_string()))
             .body(
                 Body::new()
                     .child(H1::new("Created that task"))
-                    .child(P::with_text("That task has now been sucessfully created.")),
+                    .child(P::with_text("That task has now been sucessfully created."))
+                    .children(
+                        created
+                            .warnings
+                            .into_iter()
+                            .map(|warning| P::with_text(format!("Warning: {}", warning))),
+                    ),
             ),
         Err(e) => {
             if e == LovelaceError::ParseDateError {
@@ -185,11 +371,11 @@ pub async fn html_create_new_sync_task(
 pub async fn api_create_new_async_task(
     conn: Database,
     class_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageTasks>,
     form: Json<CreateNewSyncTask>,
-) -> Json<ApiResponse<ClassSynchronousTask>> {
+) -> Json<ApiResponse<CreatedSyncTask>> {
     Json(
-        match create_new_sync_task(conn, class_id, auth, &form).await {
+        match create_new_sync_task(conn, class_id, auth.into(), &form).await {
             Ok(task) => ApiResponse::new_ok(task),
             Err(e) => From::from(e),
         },
@@ -4,9 +4,15 @@ use portia::render::Render;
 use rocket::serde::json::Json;
 
 use crate::{
-    class::{get_user_role_in_class, tasks::synchronous::AuthCookie, ClassMemberRole},
+    auth::{scope::ManageTasks, ApiAuth},
+    class::{
+        get_user_role_in_class,
+        tasks::synchronous::{recurrence::find_occurrence, AuthCookie},
+        ClassMemberRole,
+    },
     db::Database,
-    schema::class_synchronous_task,
+    models::{ClassSynchronousTask, NewClassSynchronousTaskOccurrence},
+    schema::{class_synchronous_task, class_synchronous_task_occurrence},
     utils::{
         default_head,
         error::{LovelaceError, LovelaceResult},
@@ -14,40 +20,72 @@ use crate::{
     },
 };
 
+/// Deletes the task, or (if the task repeats and an `occurrence` is given) cancels just that
+/// occurrence of it.
 async fn delete_task(
     class_id: i32,
     task_id: i32,
+    occurrence: Option<String>,
     auth: AuthCookie,
     conn: Database,
 ) -> LovelaceResult<()> {
     if let Some(ClassMemberRole::Teacher) = get_user_role_in_class(auth.0, class_id, &conn).await {
         conn.run(move |c| {
-            diesel::delete(
-                class_synchronous_task::table
-                    .filter(class_synchronous_task::id.eq(task_id))
-                    .filter(class_synchronous_task::class_id.eq(class_id)),
-            )
-            .execute(c)
+            let task = match class_synchronous_task::table
+                .filter(class_synchronous_task::id.eq(task_id))
+                .filter(class_synchronous_task::class_id.eq(class_id))
+                .first::<ClassSynchronousTask>(c)
+                .optional()?
+            {
+                Some(task) => task,
+                None => return Ok(()),
+            };
+            match occurrence {
+                Some(occurrence) if task.recurrence.is_some() => {
+                    let original_start_time = find_occurrence(&task, &occurrence)?;
+                    diesel::insert_into(class_synchronous_task_occurrence::table)
+                        .values(NewClassSynchronousTaskOccurrence {
+                            class_synchronous_task_id: task.id,
+                            original_start_time,
+                            cancelled: true,
+                            title: None,
+                            description: None,
+                            start_time: None,
+                            end_time: None,
+                        })
+                        .on_conflict((
+                            class_synchronous_task_occurrence::class_synchronous_task_id,
+                            class_synchronous_task_occurrence::original_start_time,
+                        ))
+                        .do_update()
+                        .set(class_synchronous_task_occurrence::cancelled.eq(true))
+                        .execute(c)?;
+                }
+                _ => {
+                    diesel::delete(class_synchronous_task::table.find(task.id)).execute(c)?;
+                }
+            }
+            Ok(())
         })
         .await
-        .map(drop)
         .map_err(|e| {
             error!("{:#?}", e);
-            LovelaceError::DatabaseError
+            e
         })
     } else {
-        Err(LovelaceError::PermissionError)
+        Err(LovelaceError::PermissionError(None))
     }
 }
 
-#[get("/<class_id>/task/sync/<task_id>/delete")]
+#[get("/<class_id>/task/sync/<task_id>/delete?<occurrence>")]
 pub async fn html_delete_task(
     class_id: i32,
     task_id: i32,
+    occurrence: Option<String>,
     auth: AuthCookie,
     conn: Database,
 ) -> Html {
-    match delete_task(class_id, task_id, auth, conn).await {
+    match delete_task(class_id, task_id, occurrence, auth, conn).await {
         Ok(()) => Html::new()
             .head(default_head("Successfully deleted that task".to_string()))
             .body(Body::new().child(H1::new("Successfully deleted that task."))),
@@ -55,15 +93,18 @@ pub async fn html_delete_task(
     }
 }
 
-#[get("/<class_id>/task/sync/<task_id>/delete")]
+#[get("/<class_id>/task/sync/<task_id>/delete?<occurrence>")]
 pub async fn api_delete_task(
     class_id: i32,
     task_id: i32,
-    auth: AuthCookie,
+    occurrence: Option<String>,
+    auth: ApiAuth<ManageTasks>,
     conn: Database,
 ) -> Json<ApiResponse<()>> {
-    Json(match delete_task(class_id, task_id, auth, conn).await {
-        Ok(()) => ApiResponse::new_ok(()),
-        Err(e) => From::from(e),
-    })
+    Json(
+        match delete_task(class_id, task_id, occurrence, auth.into(), conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => From::from(e),
+        },
+    )
 }
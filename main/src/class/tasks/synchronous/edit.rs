@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime};
 use diesel::prelude::*;
 use malvolio::prelude::*;
 use mercutio::Apply;
@@ -7,10 +7,26 @@ use portia::{form::FormStyle, render::Render};
 use rocket::serde::json::Json;
 
 use crate::{
+    auth::{scope::ManageTasks, ApiAuth},
     catch_database_error,
-    class::{get_user_role_in_class, tasks::synchronous::AuthCookie, ClassMemberRole},
-    db::Database,
-    models::{ClassSynchronousTask, UpdateClassSynchronousTask},
+    class::{
+        get_user_role_in_class,
+        tasks::synchronous::{
+            recurrence::{apply_change, find_occurrence, task_recurrence, Recurrence},
+            AuthCookie,
+        },
+        ClassMemberRole,
+    },
+    db::{Database, DatabaseConnection},
+    models::{
+        Attendance, ClassSynchronousTask, ClassSynchronousTaskOccurrence, NewAttendance,
+        NewClassSynchronousTask, NewClassSynchronousTaskOccurrence, NewStudentClassSynchronousTask,
+        UpdateClassSynchronousTask,
+    },
+    schema::{
+        attendance, class_synchronous_task, class_synchronous_task_occurrence,
+        student_class_synchronous_task,
+    },
     utils::{
         default_head,
         error::{LovelaceError, LovelaceResult},
@@ -19,6 +35,7 @@ use crate::{
     },
 };
 
+/// The fields of the form through which tasks are edited (without the submit button).
 fn edit_task_form(
     title: Option<String>,
     description: Option<String>,
@@ -76,17 +93,15 @@ fn edit_task_form(
                 })
                 .attribute(Name::new("end_time")),
         )
-        .child(
-            Input::new()
-                .apply(FormSubmitInputStyle)
-                .attribute(Type::Submit),
-        )
 }
 
-#[get("/<class_id>/task/sync/<task_id>/edit")]
+#[get("/<class_id>/task/sync/<task_id>/edit?<occurrence>")]
+/// The page through which tasks are edited. For tasks which repeat, `occurrence` is the occurrence
+/// being edited (see [`find_occurrence`]); if it isn't given, the first one is.
 pub async fn view_edit_task_page(
     class_id: i32,
     task_id: i32,
+    occurrence: Option<String>,
     auth: AuthCookie,
     conn: Database,
 ) -> Html {
@@ -101,29 +116,355 @@ pub async fn view_edit_task_page(
                 .first::<ClassSynchronousTask>(c))
                 .await
         );
+        let recurring = res.recurrence.is_some();
+        let (res, occurrence) = match occurrence {
+            Some(occurrence) if recurring => {
+                let original = match find_occurrence(&res, &occurrence) {
+                    Ok(original) => original,
+                    Err(e) => return e.render(),
+                };
+                let change = catch_database_error!(
+                    conn.run(move |c| class_synchronous_task_occurrence::table
+                        .filter(
+                            class_synchronous_task_occurrence::class_synchronous_task_id
+                                .eq(task_id)
+                        )
+                        .filter(class_synchronous_task_occurrence::original_start_time.eq(original))
+                        .first::<ClassSynchronousTaskOccurrence>(c)
+                        .optional())
+                        .await
+                );
+                // editing a cancelled occurrence reinstates it
+                let shown = apply_change(&res, original, change.as_ref())
+                    .or_else(|| apply_change(&res, original, None))
+                    .unwrap();
+                (
+                    shown,
+                    Some(original.format("%Y-%m-%dT%H:%M:%S").to_string()),
+                )
+            }
+            _ => (res, None),
+        };
         Html::new()
             .head(default_head("Edit a task".to_string()))
             .body(
-                Body::new()
-                    .child(H1::new("Edit this task"))
-                    .child(edit_task_form(
+                Body::new().child(H1::new("Edit this task")).child(
+                    edit_task_form(
                         Some(res.title),
                         Some(res.description),
                         Some(res.start_time.format("%Y-%m-%dT%H:%M").to_string()),
                         Some(res.end_time.format("%Y-%m-%dT%H:%M").to_string()),
-                    )),
+                    )
+                    .apply(|form| recurrence_fields(form, occurrence, recurring))
+                    .child(
+                        Input::new()
+                            .apply(FormSubmitInputStyle)
+                            .attribute(Type::Submit),
+                    ),
+                ),
             )
     } else {
         permission_error()
     }
 }
 
+/// Which occurrences of a repeating task an edit applies to.
+#[derive(FromFormField, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EditScope {
+    /// Only the occurrence being edited.
+    This,
+    /// The occurrence being edited, and all the ones after it.
+    Following,
+    /// Every occurrence.
+    All,
+}
+
+/// Adds the fields which say which occurrence(s) of a repeating task to edit to the form.
+fn recurrence_fields(form: Form, occurrence: Option<String>, recurring: bool) -> Form {
+    if !recurring {
+        return form;
+    }
+    let form = match occurrence {
+        Some(occurrence) => form.child(
+            Input::new()
+                .attribute(Type::Hidden)
+                .attribute(Name::new("occurrence"))
+                .attribute(Value::new(occurrence)),
+        ),
+        None => form,
+    };
+    form.child(
+        Select::new()
+            .attribute(Name::new("scope"))
+            .child(
+                SelectOption::new()
+                    .attribute(Value::new("this"))
+                    .text("Only this occurrence"),
+            )
+            .child(
+                SelectOption::new()
+                    .attribute(Value::new("following"))
+                    .text("This occurrence and the ones after it"),
+            )
+            .child(
+                SelectOption::new()
+                    .attribute(Value::new("all"))
+                    .text("All the occurrences"),
+            ),
+    )
+}
+
 #[derive(FromForm, Debug, Clone, Serialize, Deserialize)]
 pub struct EditTaskForm {
     title: String,
     description: String,
     start_time: String,
     end_time: String,
+    /// Which occurrence of a repeating task is being edited (see [`find_occurrence`]). If this
+    /// isn't given, the first one is.
+    occurrence: Option<String>,
+    /// Which occurrences of a repeating task to change (all of them, if this isn't given).
+    scope: Option<EditScope>,
+}
+
+/// The new details of the task (or of the occurrence(s) of it being edited).
+struct Edit<'a> {
+    title: &'a str,
+    description: &'a str,
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+}
+
+/// Stores the changes made to individual occurrences again (e.g. after they've been moved to a
+/// different task), with the times at which they would have started moved by `moved_by`.
+fn reinsert_changes(
+    changes: Vec<ClassSynchronousTaskOccurrence>,
+    task_id: i32,
+    moved_by: Duration,
+    c: &DatabaseConnection,
+) -> QueryResult<usize> {
+    diesel::insert_into(class_synchronous_task_occurrence::table)
+        .values(
+            changes
+                .iter()
+                .map(|change| NewClassSynchronousTaskOccurrence {
+                    class_synchronous_task_id: task_id,
+                    original_start_time: change.original_start_time + moved_by,
+                    cancelled: change.cancelled,
+                    title: change.title.as_deref(),
+                    description: change.description.as_deref(),
+                    start_time: change.start_time,
+                    end_time: change.end_time,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(c)
+}
+
+/// Moves the attendance register for the task's occurrences (only those which would have started
+/// at or after `from`, if it's given) over to `new_task_id`, with the times at which the
+/// occurrences would have started moved by `moved_by` (so that the marks still line up with the
+/// occurrences after they've been moved).
+fn move_attendance(
+    task_id: i32,
+    new_task_id: i32,
+    from: Option<NaiveDateTime>,
+    moved_by: Duration,
+    c: &DatabaseConnection,
+) -> QueryResult<usize> {
+    let marks = match from {
+        Some(from) => diesel::delete(
+            attendance::table
+                .filter(attendance::class_synchronous_task_id.eq(task_id))
+                .filter(attendance::occurrence.ge(from)),
+        )
+        .get_results::<Attendance>(c)?,
+        None => diesel::delete(
+            attendance::table.filter(attendance::class_synchronous_task_id.eq(task_id)),
+        )
+        .get_results::<Attendance>(c)?,
+    };
+    diesel::insert_into(attendance::table)
+        .values(
+            marks
+                .iter()
+                .map(|mark| NewAttendance {
+                    class_synchronous_task_id: new_task_id,
+                    occurrence: mark.occurrence + moved_by,
+                    class_student_id: mark.class_student_id,
+                    mark: mark.mark,
+                    note: mark.note.as_deref(),
+                    recorded_by: mark.recorded_by,
+                    recorded: mark.recorded,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(c)
+}
+
+/// Edits the task (if it repeats, `recurring` holds its rule and the occurrence through which it's
+/// being edited – all the occurrences are moved by as much as that one was).
+fn edit_all(
+    task: &ClassSynchronousTask,
+    recurring: Option<(Recurrence, NaiveDateTime)>,
+    edit: &Edit,
+    c: &DatabaseConnection,
+) -> LovelaceResult<ClassSynchronousTask> {
+    let (start_time, recurrence) = match recurring {
+        None => {
+            move_attendance(task.id, task.id, None, edit.start_time - task.start_time, c)?;
+            (edit.start_time, None)
+        }
+        Some((recurrence, occurrence)) => {
+            let moved_by = edit.start_time - occurrence;
+            let days = (edit.start_time.date() - occurrence.date()).num_days();
+            let recurrence = recurrence.shifted_by_days(days);
+            recurrence.check_occurs(task.start_time + moved_by)?;
+            // changes made to individual occurrences are kept (apart from those made to the one
+            // being edited, which this edit replaces)
+            let changes =
+                diesel::delete(class_synchronous_task_occurrence::table.filter(
+                    class_synchronous_task_occurrence::class_synchronous_task_id.eq(task.id),
+                ))
+                .get_results::<ClassSynchronousTaskOccurrence>(c)?
+                .into_iter()
+                .filter(|change| change.original_start_time != occurrence)
+                .collect();
+            reinsert_changes(changes, task.id, moved_by, c)?;
+            move_attendance(task.id, task.id, None, moved_by, c)?;
+            (task.start_time + moved_by, Some(recurrence.to_string()))
+        }
+    };
+    diesel::update(class_synchronous_task::table.find(task.id))
+        .set(UpdateClassSynchronousTask {
+            title: Some(edit.title),
+            description: Some(edit.description),
+            created: None,
+            start_time: Some(start_time),
+            end_time: Some(start_time + (edit.end_time - edit.start_time)),
+            class_teacher_id: None,
+            class_id: None,
+            recurrence: recurrence.as_deref().map(Some),
+        })
+        .returning(class_synchronous_task::all_columns)
+        .get_result(c)
+        .map_err(From::from)
+}
+
+/// Edits a single occurrence of the task.
+fn edit_occurrence(
+    task: &ClassSynchronousTask,
+    occurrence: NaiveDateTime,
+    edit: &Edit,
+    c: &DatabaseConnection,
+) -> LovelaceResult<ClassSynchronousTask> {
+    let change = NewClassSynchronousTaskOccurrence {
+        class_synchronous_task_id: task.id,
+        original_start_time: occurrence,
+        cancelled: false,
+        title: Some(edit.title),
+        description: Some(edit.description),
+        start_time: Some(edit.start_time),
+        end_time: Some(edit.end_time),
+    };
+    diesel::insert_into(class_synchronous_task_occurrence::table)
+        .values(&change)
+        .on_conflict((
+            class_synchronous_task_occurrence::class_synchronous_task_id,
+            class_synchronous_task_occurrence::original_start_time,
+        ))
+        .do_update()
+        .set(&change)
+        .execute(c)?;
+    Ok(ClassSynchronousTask {
+        title: edit.title.to_string(),
+        description: edit.description.to_string(),
+        start_time: edit.start_time,
+        end_time: edit.end_time,
+        ..task.clone()
+    })
+}
+
+/// Edits the occurrence and the ones after it. This ends the task before the occurrence, and
+/// creates a new (repeating) task which starts with it.
+fn edit_following(
+    task: &ClassSynchronousTask,
+    recurrence: Recurrence,
+    occurrence: NaiveDateTime,
+    edit: &Edit,
+    c: &DatabaseConnection,
+) -> LovelaceResult<ClassSynchronousTask> {
+    let before = recurrence
+        .occurrences(task.start_time)
+        .take_while(|start| *start < occurrence)
+        .count() as u32;
+    if before == 0 {
+        return edit_all(task, Some((recurrence, occurrence)), edit, c);
+    }
+    let moved_by = edit.start_time - occurrence;
+    let days = (edit.start_time.date() - occurrence.date()).num_days();
+    let ended = match recurrence.count {
+        Some(_) => Recurrence {
+            count: Some(before),
+            ..recurrence.clone()
+        },
+        None => Recurrence {
+            until: Some(occurrence - Duration::seconds(1)),
+            ..recurrence.clone()
+        },
+    };
+    let following = Recurrence {
+        count: recurrence.count.map(|count| count - before),
+        ..recurrence.shifted_by_days(days)
+    };
+    following.check_occurs(edit.start_time)?;
+    diesel::update(class_synchronous_task::table.find(task.id))
+        .set(class_synchronous_task::recurrence.eq(ended.to_string()))
+        .execute(c)?;
+    let new_task = diesel::insert_into(class_synchronous_task::table)
+        .values(NewClassSynchronousTask {
+            title: edit.title,
+            description: edit.description,
+            created: chrono::Utc::now().naive_utc(),
+            start_time: edit.start_time,
+            end_time: edit.end_time,
+            class_teacher_id: task.class_teacher_id,
+            class_id: task.class_id,
+            recurrence: Some(&following.to_string()),
+        })
+        .returning(class_synchronous_task::all_columns)
+        .get_result::<ClassSynchronousTask>(c)?;
+    // the new task is set to the same students
+    let students = student_class_synchronous_task::table
+        .filter(student_class_synchronous_task::class_synchronous_task_id.eq(task.id))
+        .select(student_class_synchronous_task::class_student_id)
+        .load::<i32>(c)?;
+    diesel::insert_into(student_class_synchronous_task::table)
+        .values(
+            students
+                .into_iter()
+                .map(|class_student_id| NewStudentClassSynchronousTask {
+                    class_student_id,
+                    class_synchronous_task_id: new_task.id,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(c)?;
+    // and changes to its occurrences are moved over to it
+    let changes = diesel::delete(
+        class_synchronous_task_occurrence::table
+            .filter(class_synchronous_task_occurrence::class_synchronous_task_id.eq(task.id))
+            .filter(class_synchronous_task_occurrence::original_start_time.ge(occurrence)),
+    )
+    .get_results::<ClassSynchronousTaskOccurrence>(c)?
+    .into_iter()
+    .filter(|change| change.original_start_time != occurrence)
+    .collect();
+    reinsert_changes(changes, new_task.id, moved_by, c)?;
+    // as is the register for them
+    move_attendance(task.id, new_task.id, Some(occurrence), moved_by, c)?;
+    Ok(new_task)
 }
 
 async fn apply_edit_task(
@@ -133,10 +474,9 @@ async fn apply_edit_task(
     conn: Database,
     form: &EditTaskForm,
 ) -> LovelaceResult<ClassSynchronousTask> {
-    use crate::schema::class_synchronous_task::dsl as class_synchronous_task;
     if let Some(role) = get_user_role_in_class(auth.0, class_id, &conn).await {
         if role != ClassMemberRole::Teacher {
-            return Err(LovelaceError::PermissionError);
+            return Err(LovelaceError::PermissionError(None));
         }
         let end_time = match NaiveDateTime::parse_from_str(&form.end_time, "%Y-%m-%dT%H:%M") {
             Ok(date) => date,
@@ -146,32 +486,41 @@ async fn apply_edit_task(
             Ok(date) => date,
             Err(_) => return Err(LovelaceError::ParseDateError),
         };
-        let title = form.title.clone();
-        let description = form.description.clone();
-        match conn
-            .run(move |c| {
-                diesel::update(
-                    class_synchronous_task::class_synchronous_task
-                        .filter(class_synchronous_task::id.eq(task_id))
-                        .filter(class_synchronous_task::class_id.eq(class_id)),
-                )
-                .set(UpdateClassSynchronousTask {
-                    title: Some(&title),
-                    description: Some(&description),
-                    created: None,
-                    start_time: Some(start_time),
-                    end_time: Some(end_time),
-                    class_teacher_id: None,
-                    class_id: None,
-                })
-                .returning(crate::schema::class_synchronous_task::all_columns)
-                .get_result(c)
+        let form = form.clone();
+        conn.run(move |c| {
+            c.transaction::<_, LovelaceError, _>(|| {
+                let task = class_synchronous_task::table
+                    .filter(class_synchronous_task::id.eq(task_id))
+                    .filter(class_synchronous_task::class_id.eq(class_id))
+                    .first::<ClassSynchronousTask>(c)?;
+                let edit = Edit {
+                    title: &form.title,
+                    description: &form.description,
+                    start_time,
+                    end_time,
+                };
+                let recurrence = match task_recurrence(&task)? {
+                    Some(recurrence) => recurrence,
+                    None => return edit_all(&task, None, &edit, c),
+                };
+                let occurrence = match form.occurrence.as_deref().map(str::trim) {
+                    Some(occurrence) if !occurrence.is_empty() => {
+                        find_occurrence(&task, occurrence)?
+                    }
+                    _ => task.start_time,
+                };
+                match form.scope.unwrap_or(EditScope::All) {
+                    EditScope::This => edit_occurrence(&task, occurrence, &edit, c),
+                    EditScope::Following => edit_following(&task, recurrence, occurrence, &edit, c),
+                    EditScope::All => edit_all(&task, Some((recurrence, occurrence)), &edit, c),
+                }
             })
-            .await
-        {
-            Ok(sync_task) => Ok(sync_task),
-            Err(_) => Err(LovelaceError::DatabaseError),
-        }
+        })
+        .await
+        .map_err(|e| {
+            error!("{:#?}", e);
+            e
+        })
     } else {
         Err(LovelaceError::DatabaseError)
     }
@@ -197,12 +546,12 @@ pub async fn html_apply_edit_task(
 pub async fn api_apply_edit_task(
     class_id: i32,
     task_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageTasks>,
     conn: Database,
     form: Json<EditTaskForm>,
 ) -> Json<ApiResponse<ClassSynchronousTask>> {
     Json(
-        match apply_edit_task(class_id, task_id, auth, conn, &form).await {
+        match apply_edit_task(class_id, task_id, auth.into(), conn, &form).await {
             Ok(task) => ApiResponse::new_ok(task),
             Err(e) => From::from(e),
         },
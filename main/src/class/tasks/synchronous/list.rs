@@ -1,11 +1,15 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
 use diesel::prelude::*;
 use malvolio::prelude::*;
 use portia::{levels::Level, render::Render};
 use rocket::serde::json::Json;
 
 use crate::{
-    auth::AuthCookie,
-    class::get_user_role_in_class,
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
+    class::{
+        get_user_role_in_class,
+        tasks::synchronous::recurrence::{expand_occurrences, SyncTaskOccurrence},
+    },
     db::Database,
     models::{ClassSynchronousTask, StudentClassSynchronousTask, User},
     utils::{
@@ -16,12 +20,40 @@ use crate::{
     },
 };
 
-/// Show a list of all the tasks in a class that a student has been assigned.
+/// The dates between which tasks are listed, if none are given.
+const DEFAULT_WEEKS_BEFORE: i64 = 4;
+const DEFAULT_WEEKS_AFTER: i64 = 8;
+
+/// Parses the (inclusive) dates between which the occurrences of tasks should be listed. If they
+/// aren't given, the listing covers the past few weeks and the next couple of months.
+fn parse_range(
+    from: Option<&str>,
+    to: Option<&str>,
+) -> LovelaceResult<(NaiveDateTime, NaiveDateTime)> {
+    let parse = |date: Option<&str>| {
+        date.map(|date| {
+            NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+                .map_err(|_| LovelaceError::ParseDateError)
+        })
+        .transpose()
+    };
+    let today = Utc::now().naive_utc().date();
+    let from = parse(from)?.unwrap_or(today - Duration::weeks(DEFAULT_WEEKS_BEFORE));
+    let to = parse(to)?.unwrap_or(today + Duration::weeks(DEFAULT_WEEKS_AFTER));
+    Ok((
+        from.and_hms(0, 0, 0),
+        (to + Duration::days(1)).and_hms(0, 0, 0),
+    ))
+}
+
+/// Show a list of all the tasks in a class that a student has been assigned (repeating tasks are
+/// listed once for each of their occurrences between `from` and `to`).
 async fn show_student_sync_tasks_summary(
     class_id: i32,
     user_id: i32,
+    (from, to): (NaiveDateTime, NaiveDateTime),
     conn: &Database,
-) -> LovelaceResult<Vec<(StudentClassSynchronousTask, ClassSynchronousTask)>> {
+) -> LovelaceResult<Vec<(SyncTaskOccurrence, StudentClassSynchronousTask)>> {
     use crate::schema::class_student::dsl as class_student;
     use crate::schema::class_synchronous_task::dsl as class_synchronous_task;
     use crate::schema::student_class_synchronous_task::dsl as student_class_synchronous_task;
@@ -34,10 +66,11 @@ async fn show_student_sync_tasks_summary(
                 .inner_join(class_synchronous_task::class_synchronous_task)
                 .filter(class_synchronous_task::class_id.eq(class_id))
                 .select((
-                    crate::schema::student_class_synchronous_task::all_columns,
                     crate::schema::class_synchronous_task::all_columns,
+                    crate::schema::student_class_synchronous_task::all_columns,
                 ))
-                .load::<(StudentClassSynchronousTask, ClassSynchronousTask)>(c)
+                .load::<(ClassSynchronousTask, StudentClassSynchronousTask)>(c)
+                .and_then(|tasks| expand_occurrences(tasks, from, to, c))
         })
         .await
     {
@@ -49,7 +82,16 @@ async fn show_student_sync_tasks_summary(
     }
 }
 
-struct RenderClassTaskList(pub Vec<(StudentClassSynchronousTask, ClassSynchronousTask)>);
+/// When the occurrence takes place.
+fn render_times(occurrence: &SyncTaskOccurrence) -> P {
+    P::with_text(format!(
+        "When: {} to {}",
+        occurrence.task.start_time.format("%Y-%m-%d %H:%M"),
+        occurrence.task.end_time.format("%Y-%m-%d %H:%M")
+    ))
+}
+
+struct RenderClassTaskList(pub Vec<(SyncTaskOccurrence, StudentClassSynchronousTask)>);
 
 impl Render<Html> for RenderClassTaskList {
     fn render(self) -> Html {
@@ -61,28 +103,31 @@ impl Render<Html> for RenderClassTaskList {
             Html::new()
                 .head(default_head("Tasks for this class".to_string()))
                 .body(Body::new().child(H1::new("Tasks for this class")).child(
-                    Level::new().children(self.0.into_iter().map(|(_, class_task_instance)| {
+                    Level::new().children(self.0.into_iter().map(|(occurrence, _)| {
                         Div::new()
-                            .child(H3::new(format!("Task: {}", class_task_instance.title)))
+                            .child(H3::new(format!("Task: {}", occurrence.task.title)))
                             .child(P::with_text(format!(
                                 "Description: {}",
-                                class_task_instance.description
+                                occurrence.task.description
                             )))
+                            .child(render_times(&occurrence))
                     })),
                 ))
         }
     }
 }
 
-/// Show the list of tasks that have been set in a class. At some point we'll want to add pagination
+/// Show the list of tasks that have been set in a class (repeating tasks are listed once for each
+/// of their occurrences between `from` and `to`). At some point we'll want to add pagination
 /// support for this.
 ///
 /// MAKE SURE YOU HAVE CHECKED THAT THE USER IS A TEACHER IN THE CLASS BEFORE YOU CALL THIS
 /// FUNCTION. (sorry for the all caps, I (@teymour-aldridge) kept forgetting to do so :-)
 async fn show_teacher_sync_tasks_summary(
     class_id: i32,
+    (from, to): (NaiveDateTime, NaiveDateTime),
     conn: &Database,
-) -> LovelaceResult<Vec<(ClassSynchronousTask, User)>> {
+) -> LovelaceResult<Vec<(SyncTaskOccurrence, User)>> {
     use crate::schema::class_synchronous_task::dsl as class_synchronous_task;
     use crate::schema::class_teacher::dsl as class_teacher;
     use crate::schema::student_class_synchronous_task::dsl as student_class_synchronous_task;
@@ -98,6 +143,7 @@ async fn show_teacher_sync_tasks_summary(
                 crate::schema::users::all_columns,
             ))
             .load::<(ClassSynchronousTask, User)>(c)
+            .and_then(|tasks| expand_occurrences(tasks, from, to, c))
     })
     .await
     .map_err(|e| {
@@ -106,7 +152,7 @@ async fn show_teacher_sync_tasks_summary(
     })
 }
 
-struct RenderTeacherTaskList(pub Vec<(ClassSynchronousTask, User)>);
+struct RenderTeacherTaskList(pub Vec<(SyncTaskOccurrence, User)>);
 
 impl Render<Html> for RenderTeacherTaskList {
     fn render(self) -> Html {
@@ -114,38 +160,67 @@ impl Render<Html> for RenderTeacherTaskList {
             .head(default_head("Tasks".to_string()))
             .body(
                 Body::new().child(Level::new().children(self.0.into_iter().map(
-                    |(task, set_by)| {
+                    |(occurrence, set_by)| {
                         Div::new()
-                            .child(task.render())
+                            .child(occurrence.task.render())
+                            .child(render_times(&occurrence))
                             .child(P::with_text(format!("Set by: {}", set_by.username)))
+                            .child(
+                                A::new()
+                                    .attribute(Href::new(format!(
+                                        "/class/{}/task/sync/{}/edit?occurrence={}",
+                                        occurrence.task.class_id,
+                                        occurrence.task.id,
+                                        occurrence.identifier()
+                                    )))
+                                    .text("Edit"),
+                            )
+                            .child(
+                                A::new()
+                                    .attribute(Href::new(format!(
+                                        "/class/{}/task/sync/{}/register?occurrence={}",
+                                        occurrence.task.class_id,
+                                        occurrence.task.id,
+                                        occurrence.identifier()
+                                    )))
+                                    .text("Register"),
+                            )
                     },
                 ))),
             )
     }
 }
 
-#[get("/<class_id>/task/sync/all")]
+#[get("/<class_id>/task/sync/all?<from>&<to>")]
 /// Show a list of all the synchronous tasks have been set in a class, either to a teacher or a
-/// student (this is retrieved from the database).
+/// student (this is retrieved from the database). `from` and `to` are the dates (in the form
+/// YYYY-MM-DD) between which the occurrences of the tasks are listed.
 pub async fn html_view_all_sync_tasks_in_class(
     class_id: i32,
+    from: Option<String>,
+    to: Option<String>,
     auth: AuthCookie,
     conn: Database,
 ) -> Html {
+    let range = match parse_range(from.as_deref(), to.as_deref()) {
+        Ok(range) => range,
+        Err(e) => return e.render(),
+    };
     if let Some(role) = get_user_role_in_class(auth.0, class_id, &conn).await {
         match role {
             crate::class::ClassMemberRole::Teacher => {
-                let tasks = match show_teacher_sync_tasks_summary(class_id, &conn).await {
+                let tasks = match show_teacher_sync_tasks_summary(class_id, range, &conn).await {
                     Ok(t) => t,
                     Err(e) => return e.render(),
                 };
                 RenderTeacherTaskList(tasks).render()
             }
             crate::class::ClassMemberRole::Student => {
-                let tasks = match show_student_sync_tasks_summary(class_id, auth.0, &conn).await {
-                    Ok(t) => t,
-                    Err(e) => return e.render(),
-                };
+                let tasks =
+                    match show_student_sync_tasks_summary(class_id, auth.0, range, &conn).await {
+                        Ok(t) => t,
+                        Err(e) => return e.render(),
+                    };
                 RenderClassTaskList(tasks).render()
             }
         }
@@ -156,13 +231,13 @@ pub async fn html_view_all_sync_tasks_in_class(
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TeacherTask {
-    task: ClassSynchronousTask,
+    task: SyncTaskOccurrence,
     user: User,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StudentTask {
-    task: ClassSynchronousTask,
+    task: SyncTaskOccurrence,
     student_task: StudentClassSynchronousTask,
 }
 
@@ -173,19 +248,27 @@ pub enum ViewAllSyncTasks {
     Student(Vec<StudentTask>),
 }
 
-#[get("/<class_id>/task/sync/all")]
+#[get("/<class_id>/task/sync/all?<from>&<to>")]
 /// Show a list of all the synchronous tasks have been set in a class, either to a teacher or a
-/// student (this is retrieved from the database).
+/// student (this is retrieved from the database). `from` and `to` are the dates (in the form
+/// YYYY-MM-DD) between which the occurrences of the tasks are listed.
 pub async fn api_view_all_sync_tasks_in_class(
     class_id: i32,
-    auth: AuthCookie,
+    from: Option<String>,
+    to: Option<String>,
+    auth: ApiAuth<ReadTasks>,
     conn: Database,
 ) -> Json<ApiResponse<ViewAllSyncTasks>> {
+    let range = match parse_range(from.as_deref(), to.as_deref()) {
+        Ok(range) => range,
+        Err(e) => return Json(e.into()),
+    };
     Json(
         if let Some(role) = get_user_role_in_class(auth.0, class_id, &conn).await {
             match role {
                 crate::class::ClassMemberRole::Teacher => {
-                    let tasks = match show_teacher_sync_tasks_summary(class_id, &conn).await {
+                    let tasks = match show_teacher_sync_tasks_summary(class_id, range, &conn).await
+                    {
                         Ok(t) => t,
                         Err(e) => return Json(e.into()),
                     };
@@ -197,21 +280,22 @@ pub async fn api_view_all_sync_tasks_in_class(
                     ))
                 }
                 crate::class::ClassMemberRole::Student => {
-                    let tasks = match show_student_sync_tasks_summary(class_id, auth.0, &conn).await
-                    {
-                        Ok(t) => t,
-                        Err(e) => return Json(e.into()),
-                    };
+                    let tasks =
+                        match show_student_sync_tasks_summary(class_id, auth.0, range, &conn).await
+                        {
+                            Ok(t) => t,
+                            Err(e) => return Json(e.into()),
+                        };
                     ApiResponse::new_ok(ViewAllSyncTasks::Student(
                         tasks
                             .into_iter()
-                            .map(|(student_task, task)| StudentTask { task, student_task })
+                            .map(|(task, student_task)| StudentTask { task, student_task })
                             .collect(),
                     ))
                 }
             }
         } else {
-            return Json(From::from(LovelaceError::PermissionError));
+            return Json(From::from(LovelaceError::PermissionError(None)));
         },
     )
 }
@@ -10,6 +10,7 @@ pub mod create;
 pub mod delete;
 pub mod edit;
 pub mod list;
+pub mod recurrence;
 pub mod view;
 
 pub use create::{api_create_new_async_task, get_create_new_sync_task, html_create_new_sync_task};
@@ -67,6 +68,7 @@ mod synchronous_task_tests {
                 code: CLASS_CODE,
                 institution_id: None,
                 student_group_id: None,
+                academic_term_id: None,
             })
             .returning(crate::schema::class::id)
             .get_result::<i32>(conn)
@@ -75,7 +77,7 @@ mod synchronous_task_tests {
             .values(crate::models::NewUser {
                 username: TEACHER_USERNAME,
                 email: TEACHER_EMAIL,
-                password: &bcrypt::hash(TEACHER_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(TEACHER_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -95,7 +97,7 @@ mod synchronous_task_tests {
             .values(crate::models::NewUser {
                 username: STUDENT_1_USERNAME,
                 email: STUDENT_1_EMAIL,
-                password: &bcrypt::hash(STUDENT_1_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(STUDENT_1_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -115,7 +117,7 @@ mod synchronous_task_tests {
             .values(crate::models::NewUser {
                 username: STUDENT_2_USERNAME,
                 email: STUDENT_2_EMAIL,
-                password: &bcrypt::hash(STUDENT_2_PASSWORD, bcrypt::DEFAULT_COST).unwrap(),
+                password: &crate::auth::hash::hash_password(STUDENT_2_PASSWORD).unwrap(),
                 created: chrono::Utc::now().naive_utc(),
                 email_verified: true,
                 timezone: TIMEZONE,
@@ -144,6 +146,7 @@ mod synchronous_task_tests {
                     .naive_utc(),
                 class_teacher_id,
                 class_id,
+                recurrence: None,
             })
             .returning(crate::schema::class_synchronous_task::id)
             .get_result::<i32>(conn)
@@ -175,6 +178,7 @@ mod synchronous_task_tests {
                     .naive_utc(),
                 class_teacher_id,
                 class_id,
+                recurrence: None,
             })
             .returning(crate::schema::class_synchronous_task::id)
             .get_result::<i32>(conn)
@@ -467,4 +471,134 @@ mod synchronous_task_tests {
         assert!(string.contains(TASK_2_TITLE));
         assert!(string.contains(&format!("Set by: {}", TEACHER_USERNAME)));
     }
+    #[rocket::async_test]
+    async fn test_recurring_synchronous_tasks() {
+        const TITLE: &str = "weekly-maths";
+        const MOVED_TITLE: &str = "moved-maths";
+        const LATER_TITLE: &str = "later-maths";
+        let client = client().await;
+        let (class_id, _, _, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| populate_database(c))
+            .await;
+        let list_occurrences = |title: &'static str| {
+            let client = &client;
+            async move {
+                login_user(STUDENT_1_USERNAME, STUDENT_1_PASSWORD, client).await;
+                let res = client
+                    .get(format!(
+                        "/class/{}/task/sync/all?from=2030-01-01&to=2030-02-28",
+                        class_id
+                    ))
+                    .dispatch()
+                    .await;
+                let string = res.into_string().await.expect("invalid body response");
+                logout(client).await;
+                login_user(TEACHER_USERNAME, TEACHER_PASSWORD, client).await;
+                string.matches(title).count()
+            }
+        };
+        login_user(TEACHER_USERNAME, TEACHER_PASSWORD, &client).await;
+
+        // Mondays and Wednesdays (2030-01-07 is a Monday), but not on the 9th
+        let res = client
+            .post(format!("/class/{}/task/sync/create", class_id))
+            .header(ContentType::Form)
+            .body(format!(
+                "title={}&description=&start_time=2030-01-07T09:00&end_time=2030-01-07T10:00\
+                &recurrence=FREQ%3DWEEKLY%3BBYDAY%3DMO%2CWE%3BCOUNT%3D6&exceptions=2030-01-09",
+                TITLE
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("Created that task"));
+        let task = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                use crate::schema::class_synchronous_task::dsl as class_synchronous_task;
+                class_synchronous_task::class_synchronous_task
+                    .filter(class_synchronous_task::title.eq(TITLE))
+                    .first::<ClassSynchronousTask>(c)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            task.recurrence.as_deref(),
+            Some("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=6")
+        );
+        assert_eq!(list_occurrences(TITLE).await, 5);
+
+        // move the lesson on the 14th to the 15th
+        let res = client
+            .post(format!("/class/{}/task/sync/{}/edit", class_id, task.id))
+            .header(ContentType::Form)
+            .body(format!(
+                "title={}&description=&start_time=2030-01-15T11:00&end_time=2030-01-15T12:00\
+                &occurrence=2030-01-14T09:00:00&scope=this",
+                MOVED_TITLE
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("updated that task"));
+        assert_eq!(list_occurrences(TITLE).await, 4);
+        assert_eq!(list_occurrences(MOVED_TITLE).await, 1);
+
+        // and change the ones from the 16th onwards
+        let res = client
+            .post(format!("/class/{}/task/sync/{}/edit", class_id, task.id))
+            .header(ContentType::Form)
+            .body(format!(
+                "title={}&description=&start_time=2030-01-16T13:00&end_time=2030-01-16T14:00\
+                &occurrence=2030-01-16T09:00:00&scope=following",
+                LATER_TITLE
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("updated that task"));
+        assert_eq!(list_occurrences(TITLE).await, 1);
+        assert_eq!(list_occurrences(MOVED_TITLE).await, 1);
+        assert_eq!(list_occurrences(LATER_TITLE).await, 3);
+        let (old, new) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                use crate::schema::class_synchronous_task::dsl as class_synchronous_task;
+                (
+                    class_synchronous_task::class_synchronous_task
+                        .find(task.id)
+                        .first::<ClassSynchronousTask>(c)
+                        .unwrap(),
+                    class_synchronous_task::class_synchronous_task
+                        .filter(class_synchronous_task::title.eq(LATER_TITLE))
+                        .first::<ClassSynchronousTask>(c)
+                        .unwrap(),
+                )
+            })
+            .await;
+        assert_eq!(
+            old.recurrence.as_deref(),
+            Some("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=3")
+        );
+        assert_eq!(
+            new.recurrence.as_deref(),
+            Some("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=3")
+        );
+
+        // cancel the lesson on the 21st
+        let res = client
+            .get(format!(
+                "/class/{}/task/sync/{}/delete?occurrence=2030-01-21T13:00:00",
+                class_id, new.id
+            ))
+            .dispatch()
+            .await;
+        let string = res.into_string().await.expect("invalid body response");
+        assert!(string.contains("deleted that task"));
+        assert_eq!(list_occurrences(LATER_TITLE).await, 2);
+    }
 }
@@ -0,0 +1,609 @@
+//! Recurring synchronous tasks (e.g. a lesson which takes place every Monday and Wednesday).
+//!
+//! When a task repeats is described by an RRULE (see RFC 5545). We only support a subset of them:
+//! `FREQ` (which has to be `DAILY` or `WEEKLY`), `INTERVAL`, `BYDAY` (without the numeric
+//! prefixes), `UNTIL` and `COUNT`. Weeks start on Mondays.
+//!
+//! Occurrences which have been changed (or which don't take place at all) are stored as
+//! [`ClassSynchronousTaskOccurrence`]s, and are identified by the time at which they would have
+//! started.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt, iter,
+    str::FromStr,
+};
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use diesel::prelude::*;
+
+use crate::{
+    db::DatabaseConnection,
+    models::{ClassSynchronousTask, ClassSynchronousTaskOccurrence},
+    schema::class_synchronous_task_occurrence,
+    utils::error::{LovelaceError, LovelaceResult},
+};
+
+/// The longest gap between occurrences (in days or weeks) we allow.
+const MAX_INTERVAL: u32 = 366;
+
+/// How many days after the first occurrence of a task we look for occurrences. Some rules never
+/// place an occurrence on any day, so without a limit looking for one would never finish.
+const MAX_SPAN: i64 = 10 * 366;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+/// A recurrence rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    /// For example, 2 if the task takes place every other week.
+    pub interval: u32,
+    /// The days of the week on which the task takes place. If this is empty, weekly tasks take
+    /// place on the same day of the week as the first occurrence (and daily ones on every day).
+    pub by_day: Vec<Weekday>,
+    /// No occurrences start after this time.
+    pub until: Option<NaiveDateTime>,
+    /// The number of occurrences (including the first one).
+    pub count: Option<u32>,
+}
+
+fn invalid(reason: impl Into<String>) -> LovelaceError {
+    LovelaceError::InvalidRecurrence(reason.into())
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    WEEKDAYS
+        .iter()
+        .find(|(_, day)| *day == weekday)
+        .map(|(name, _)| *name)
+        .unwrap()
+}
+
+fn parse_positive(value: &str, key: &str) -> LovelaceResult<u32> {
+    match value.trim().parse::<u32>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(invalid(format!(
+            "{} should be a positive whole number.",
+            key
+        ))),
+    }
+}
+
+fn parse_until(value: &str) -> LovelaceResult<NaiveDateTime> {
+    let value = value.trim().trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_else(|_| {
+            // if only a date is given, occurrences on that day are included
+            NaiveDate::parse_from_str(value, "%Y%m%d").map(|date| date.and_hms(23, 59, 59))
+        })
+        .map_err(|_| {
+            invalid(format!(
+                "`{}` isn't a valid date for UNTIL (it should look like 20220722 or \
+                20220722T170000).",
+                value
+            ))
+        })
+}
+
+impl FromStr for Recurrence {
+    type Err = LovelaceError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut by_day = vec![];
+        let mut until = None;
+        let mut count = None;
+        for part in rule.split(';').filter(|part| !part.trim().is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("`{}` should be of the form KEY=VALUE.", part)))?;
+            match key.trim().to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.trim().to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        _ => {
+                            return Err(invalid(format!(
+                                "Tasks can only repeat daily or weekly (not `{}`).",
+                                value
+                            )))
+                        }
+                    })
+                }
+                "INTERVAL" => {
+                    interval = parse_positive(value, "INTERVAL")?;
+                    if interval > MAX_INTERVAL {
+                        return Err(invalid(format!(
+                            "INTERVAL can be at most {}.",
+                            MAX_INTERVAL
+                        )));
+                    }
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let day = day.trim().to_ascii_uppercase();
+                        let weekday = WEEKDAYS
+                            .iter()
+                            .find(|(name, _)| *name == day)
+                            .map(|(_, weekday)| *weekday)
+                            .ok_or_else(|| {
+                                invalid(format!(
+                                    "`{}` isn't a day of the week (days should be given as MO, \
+                                    TU, WE, TH, FR, SA or SU).",
+                                    day
+                                ))
+                            })?;
+                        if !by_day.contains(&weekday) {
+                            by_day.push(weekday);
+                        }
+                    }
+                }
+                "UNTIL" => until = Some(parse_until(value)?),
+                "COUNT" => count = Some(parse_positive(value, "COUNT")?),
+                "WKST" if value.trim().eq_ignore_ascii_case("MO") => {}
+                _ => {
+                    return Err(invalid(format!(
+                        "`{}` isn't supported in recurrence rules (only FREQ, INTERVAL, BYDAY, \
+                        UNTIL and COUNT are).",
+                        part
+                    )))
+                }
+            }
+        }
+        let frequency = frequency.ok_or_else(|| {
+            invalid("Recurrence rules need a FREQ (which can be either DAILY or WEEKLY).")
+        })?;
+        if until.is_some() && count.is_some() {
+            return Err(invalid(
+                "Recurrence rules can have an UNTIL or a COUNT, but not both.",
+            ));
+        }
+        by_day.sort_by_key(|day| day.num_days_from_monday());
+        Ok(Self {
+            frequency,
+            interval,
+            by_day,
+            until,
+            count,
+        })
+    }
+}
+
+/// Formats the rule as an RRULE (which is how it's stored).
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "FREQ={}",
+            match self.frequency {
+                Frequency::Daily => "DAILY",
+                Frequency::Weekly => "WEEKLY",
+            }
+        )?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            write!(
+                f,
+                ";BYDAY={}",
+                self.by_day
+                    .iter()
+                    .map(|day| weekday_name(*day))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%S"))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        Ok(())
+    }
+}
+
+fn start_of_week(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
+impl Recurrence {
+    /// Whether the rule places an occurrence on this date (ignoring `UNTIL` and `COUNT`), given the
+    /// date of the first occurrence.
+    fn falls_on(&self, first: NaiveDate, date: NaiveDate) -> bool {
+        if date < first {
+            return false;
+        }
+        let on_day = if self.by_day.is_empty() {
+            self.frequency == Frequency::Daily || date.weekday() == first.weekday()
+        } else {
+            self.by_day.contains(&date.weekday())
+        };
+        let period = match self.frequency {
+            Frequency::Daily => (date - first).num_days(),
+            Frequency::Weekly => (start_of_week(date) - start_of_week(first)).num_days() / 7,
+        };
+        on_day && period % self.interval as i64 == 0
+    }
+
+    /// The times at which the occurrences of a task whose first occurrence starts at `start`
+    /// start, in order. Occurrences more than [`MAX_SPAN`] days after `start` are left out.
+    ///
+    /// If `start` doesn't fit the rule (e.g. it's on a Tuesday, but the rule says that the task
+    /// takes place on Mondays), it isn't an occurrence.
+    pub fn occurrences(&self, start: NaiveDateTime) -> impl Iterator<Item = NaiveDateTime> + '_ {
+        let first = start.date();
+        let last = first + Duration::days(MAX_SPAN);
+        let until = self.until;
+        iter::successors(Some(first), |date| date.succ_opt())
+            .take_while(move |date| *date <= last)
+            .filter(move |date| self.falls_on(first, *date))
+            .map(move |date| date.and_time(start.time()))
+            .take_while(move |time| until.map(|until| *time <= until).unwrap_or(true))
+            .take(self.count.map(|count| count as usize).unwrap_or(usize::MAX))
+    }
+
+    /// Checks that a task whose first occurrence starts at `start` would take place at least
+    /// once under this rule (e.g. `FREQ=DAILY;INTERVAL=7;BYDAY=TU` never places an occurrence on
+    /// a Tuesday if the task starts on a Monday).
+    pub fn check_occurs(&self, start: NaiveDateTime) -> LovelaceResult<()> {
+        match self.occurrences(start).next() {
+            Some(_) => Ok(()),
+            None => Err(invalid(
+                "A task with this recurrence rule would never take place.",
+            )),
+        }
+    }
+
+    /// The same rule, but with the days of the week on which the task takes place moved `days`
+    /// days later (for when a lesson is moved from a Monday to a Tuesday).
+    pub fn shifted_by_days(&self, days: i64) -> Self {
+        let mut by_day = self
+            .by_day
+            .iter()
+            .map(|day| (0..days.rem_euclid(7)).fold(*day, |day, _| day.succ()))
+            .collect::<Vec<_>>();
+        by_day.sort_by_key(|day| day.num_days_from_monday());
+        Self {
+            by_day,
+            ..self.clone()
+        }
+    }
+}
+
+/// Parses the (optional) recurrence rule supplied in a form. Empty rules are treated as though no
+/// rule had been given.
+pub fn parse_recurrence(rule: Option<&str>) -> LovelaceResult<Option<Recurrence>> {
+    match rule.map(str::trim) {
+        Some(rule) if !rule.is_empty() => rule.parse().map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// The task's recurrence rule (if it has one).
+pub fn task_recurrence(task: &ClassSynchronousTask) -> LovelaceResult<Option<Recurrence>> {
+    parse_recurrence(task.recurrence.as_deref())
+}
+
+/// Parses the time at which an occurrence (originally) started, and checks that the task has an
+/// occurrence at that time.
+///
+/// Occurrences are identified to the second, in the form `YYYY-MM-DDTHH:MM:SS` (the seconds can
+/// be left out).
+pub fn find_occurrence(
+    task: &ClassSynchronousTask,
+    occurrence: &str,
+) -> LovelaceResult<NaiveDateTime> {
+    let occurrence = NaiveDateTime::parse_from_str(occurrence.trim(), "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(occurrence.trim(), "%Y-%m-%dT%H:%M"))
+        .map_err(|_| LovelaceError::ParseDateError)?;
+    let not_found = || invalid("This task doesn't take place at that time.");
+    match task_recurrence(task)? {
+        Some(recurrence) => recurrence
+            .occurrences(task.start_time)
+            .take_while(|start| start.timestamp() <= occurrence.timestamp())
+            .find(|start| start.timestamp() == occurrence.timestamp())
+            .ok_or_else(not_found),
+        None if task.start_time.timestamp() == occurrence.timestamp() => Ok(task.start_time),
+        None => Err(not_found()),
+    }
+}
+
+/// One occurrence of a synchronous task (tasks which don't repeat have exactly one).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncTaskOccurrence {
+    /// The task, with the details (and times) of this occurrence.
+    #[serde(flatten)]
+    pub task: ClassSynchronousTask,
+    /// When this occurrence would have started had it not been changed (this is what identifies
+    /// it, e.g. when editing it).
+    pub occurrence: NaiveDateTime,
+}
+
+impl SyncTaskOccurrence {
+    /// The `occurrence` parameter to use in links to this occurrence.
+    pub fn identifier(&self) -> String {
+        self.occurrence.format("%Y-%m-%dT%H:%M:%S").to_string()
+    }
+}
+
+/// The occurrences of the task which overlap the period from `from` to `to`, with any changes
+/// made to them applied. Cancelled occurrences are left out.
+pub fn occurrences_between(
+    task: &ClassSynchronousTask,
+    changes: &[ClassSynchronousTaskOccurrence],
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Vec<SyncTaskOccurrence> {
+    // (tasks which start and end at the same time are included if they start during the period)
+    let overlaps =
+        |start: NaiveDateTime, end: NaiveDateTime| start < to && (end > from || start >= from);
+    let recurrence = match task_recurrence(task) {
+        Ok(Some(recurrence)) => recurrence,
+        Ok(None) => {
+            return if overlaps(task.start_time, task.end_time) {
+                vec![SyncTaskOccurrence {
+                    task: task.clone(),
+                    occurrence: task.start_time,
+                }]
+            } else {
+                vec![]
+            }
+        }
+        Err(e) => {
+            error!("task {} has an invalid recurrence rule: {:?}", task.id, e);
+            return vec![];
+        }
+    };
+    let duration = task.end_time - task.start_time;
+    let mut starts = recurrence
+        .occurrences(task.start_time)
+        .take_while(|start| *start < to)
+        .filter(|start| overlaps(*start, *start + duration))
+        .collect::<BTreeSet<_>>();
+    // occurrences which have been moved into this period (from outside it)
+    for change in changes {
+        if (change.start_time.is_some() || change.end_time.is_some())
+            && recurrence
+                .occurrences(task.start_time)
+                .take_while(|start| *start <= change.original_start_time)
+                .any(|start| start == change.original_start_time)
+        {
+            starts.insert(change.original_start_time);
+        }
+    }
+    starts
+        .into_iter()
+        .filter_map(|original| {
+            let change = changes
+                .iter()
+                .find(|change| change.original_start_time == original);
+            apply_change(task, original, change).map(|task| SyncTaskOccurrence {
+                task,
+                occurrence: original,
+            })
+        })
+        .filter(|occurrence| overlaps(occurrence.task.start_time, occurrence.task.end_time))
+        .collect()
+}
+
+/// The details of the occurrence of the task which would have started at `original`, taking into
+/// account the changes made to it (if any). Returns `None` if the occurrence has been cancelled.
+pub fn apply_change(
+    task: &ClassSynchronousTask,
+    original: NaiveDateTime,
+    change: Option<&ClassSynchronousTaskOccurrence>,
+) -> Option<ClassSynchronousTask> {
+    let mut occurrence = ClassSynchronousTask {
+        start_time: original,
+        end_time: original + (task.end_time - task.start_time),
+        ..task.clone()
+    };
+    if let Some(change) = change {
+        if change.cancelled {
+            return None;
+        }
+        if let Some(title) = &change.title {
+            occurrence.title = title.clone();
+        }
+        if let Some(description) = &change.description {
+            occurrence.description = description.clone();
+        }
+        if let Some(start_time) = change.start_time {
+            occurrence.start_time = start_time;
+        }
+        if let Some(end_time) = change.end_time {
+            occurrence.end_time = end_time;
+        }
+    }
+    Some(occurrence)
+}
+
+/// Expands the tasks (each of which is loaded along with something else, e.g. the class it's part
+/// of) into their occurrences between `from` and `to`, in the order in which they start.
+pub fn expand_occurrences<T: Clone>(
+    tasks: Vec<(ClassSynchronousTask, T)>,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    c: &DatabaseConnection,
+) -> QueryResult<Vec<(SyncTaskOccurrence, T)>> {
+    let recurring = tasks
+        .iter()
+        .filter(|(task, _)| task.recurrence.is_some())
+        .map(|(task, _)| task.id)
+        .collect::<Vec<_>>();
+    let mut changes: HashMap<i32, Vec<ClassSynchronousTaskOccurrence>> = HashMap::new();
+    if !recurring.is_empty() {
+        for change in class_synchronous_task_occurrence::table
+            .filter(class_synchronous_task_occurrence::class_synchronous_task_id.eq_any(recurring))
+            .load::<ClassSynchronousTaskOccurrence>(c)?
+        {
+            changes
+                .entry(change.class_synchronous_task_id)
+                .or_default()
+                .push(change);
+        }
+    }
+    let mut occurrences = vec![];
+    for (task, extra) in tasks {
+        let task_changes = changes.get(&task.id).map(Vec::as_slice).unwrap_or(&[]);
+        for occurrence in occurrences_between(&task, task_changes, from, to) {
+            occurrences.push((occurrence, extra.clone()));
+        }
+    }
+    occurrences.sort_by_key(|(occurrence, _)| occurrence.task.start_time);
+    Ok(occurrences)
+}
+
+#[cfg(test)]
+mod test_recurrence {
+    use chrono::{NaiveDate, NaiveDateTime, Weekday};
+
+    use super::{occurrences_between, Frequency, Recurrence};
+    use crate::models::{ClassSynchronousTask, ClassSynchronousTaskOccurrence};
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        // 2021-09-06 is a Monday
+        NaiveDate::from_ymd(2021, 9, day).and_hms(hour, 0, 0)
+    }
+
+    #[test]
+    fn test_parse_recurrence() {
+        let rule = "RRULE:FREQ=weekly;BYDAY=WE,MO;UNTIL=20211022"
+            .parse::<Recurrence>()
+            .unwrap();
+        assert_eq!(rule.frequency, Frequency::Weekly);
+        assert_eq!(rule.by_day, vec![Weekday::Mon, Weekday::Wed]);
+        assert_eq!(
+            rule.until,
+            Some(NaiveDate::from_ymd(2021, 10, 22).and_hms(23, 59, 59))
+        );
+        assert_eq!(
+            rule.to_string(),
+            "FREQ=WEEKLY;BYDAY=MO,WE;UNTIL=20211022T235959"
+        );
+        assert_eq!(rule.to_string().parse::<Recurrence>().unwrap(), rule);
+
+        assert!("FREQ=MONTHLY".parse::<Recurrence>().is_err());
+        assert!("BYDAY=MO".parse::<Recurrence>().is_err());
+        assert!("FREQ=WEEKLY;BYDAY=1MO".parse::<Recurrence>().is_err());
+        assert!("FREQ=WEEKLY;COUNT=3;UNTIL=20211022"
+            .parse::<Recurrence>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let rule = "FREQ=WEEKLY;BYDAY=MO,WE;COUNT=5"
+            .parse::<Recurrence>()
+            .unwrap();
+        assert_eq!(
+            rule.occurrences(at(6, 9)).collect::<Vec<_>>(),
+            vec![at(6, 9), at(8, 9), at(13, 9), at(15, 9), at(20, 9)]
+        );
+        let rule = "FREQ=WEEKLY;INTERVAL=2;UNTIL=20210927"
+            .parse::<Recurrence>()
+            .unwrap();
+        assert_eq!(
+            rule.occurrences(at(7, 9)).collect::<Vec<_>>(),
+            vec![at(7, 9), at(21, 9)]
+        );
+        let rule = "FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR"
+            .parse::<Recurrence>()
+            .unwrap();
+        assert_eq!(
+            rule.occurrences(at(10, 9)).take(3).collect::<Vec<_>>(),
+            vec![at(10, 9), at(13, 9), at(14, 9)]
+        );
+        assert_eq!(
+            "FREQ=WEEKLY;BYDAY=MO,SU"
+                .parse::<Recurrence>()
+                .unwrap()
+                .shifted_by_days(1)
+                .by_day,
+            vec![Weekday::Mon, Weekday::Tue]
+        );
+    }
+
+    #[test]
+    fn test_rules_which_never_match() {
+        // every seventh day is a Monday, so this never falls on a Tuesday
+        let rule = "FREQ=DAILY;INTERVAL=7;BYDAY=TU"
+            .parse::<Recurrence>()
+            .unwrap();
+        assert_eq!(rule.occurrences(at(6, 9)).next(), None);
+        assert!(rule.check_occurs(at(6, 9)).is_err());
+        assert!(rule.check_occurs(at(7, 9)).is_ok());
+        let rule = "FREQ=WEEKLY;UNTIL=20210901".parse::<Recurrence>().unwrap();
+        assert!(rule.check_occurs(at(6, 9)).is_err());
+    }
+
+    #[test]
+    fn test_occurrences_between() {
+        let task = ClassSynchronousTask {
+            id: 1,
+            title: "Maths".to_string(),
+            description: String::new(),
+            created: at(1, 0),
+            start_time: at(6, 9),
+            end_time: at(6, 10),
+            class_teacher_id: 1,
+            class_id: 1,
+            recurrence: Some("FREQ=WEEKLY;BYDAY=MO,WE".to_string()),
+        };
+        let change = |original, cancelled, start_time: Option<NaiveDateTime>| {
+            ClassSynchronousTaskOccurrence {
+                id: 1,
+                class_synchronous_task_id: 1,
+                original_start_time: original,
+                cancelled,
+                title: None,
+                description: None,
+                start_time,
+                end_time: start_time.map(|start| start + chrono::Duration::hours(1)),
+            }
+        };
+        let changes = vec![
+            // the lesson on the 8th is cancelled
+            change(at(8, 9), true, None),
+            // and the one on the 20th takes place on the 10th
+            change(at(20, 9), false, Some(at(10, 14))),
+        ];
+        let occurrences = occurrences_between(&task, &changes, at(7, 0), at(14, 0))
+            .into_iter()
+            .map(|occurrence| {
+                (
+                    occurrence.occurrence,
+                    occurrence.task.start_time,
+                    occurrence.task.end_time,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            occurrences,
+            vec![
+                (at(13, 9), at(13, 9), at(13, 10)),
+                (at(20, 9), at(10, 14), at(10, 15)),
+            ]
+        );
+    }
+}
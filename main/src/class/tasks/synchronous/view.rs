@@ -4,7 +4,7 @@ use portia::{levels::Level, render::Render};
 use rocket::serde::json::Json;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
     class::get_user_role_in_class,
     db::Database,
     models::{sync_task, user, ClassSynchronousTask, StudentClassSynchronousTask, User},
@@ -206,13 +206,13 @@ pub enum ViewSpecificSyncTaskApiRes {
 pub async fn api_view_specific_synchronous_task(
     class_id: i32,
     task_id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ReadTasks>,
     conn: Database,
 ) -> Json<ApiResponse<ViewSpecificSyncTaskApiRes>> {
     let role = if let Some(role) = get_user_role_in_class(auth.0, class_id, &conn).await {
         role
     } else {
-        return Json(From::from(LovelaceError::PermissionError));
+        return Json(From::from(LovelaceError::PermissionError(None)));
     };
     Json(match role {
         crate::class::ClassMemberRole::Teacher => {
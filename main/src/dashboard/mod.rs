@@ -1,6 +1,7 @@
 //! The dashboard. This is designed to be actually navigable unlike certain people's softwae (cough,
 //! cough Google Classroom).
 
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::prelude::*;
 use malvolio::prelude::*;
 use mercutio::Apply;
@@ -8,12 +9,14 @@ use portia::{card::Card, levels::Level, render::Render};
 use rocket::serde::json::Json;
 
 use crate::{
-    auth::AuthCookie,
-    db::Database,
-    models::{
-        ClassAsynchronousTask, ClassStudent, ClassSynchronousTask, ClassTeacher,
-        StudentClassAsynchronousTask, User,
+    auth::{scope::ReadTasks, ApiAuth, AuthCookie},
+    class::tasks::{
+        asynchronous::effective_due_date,
+        synchronous::recurrence::{expand_occurrences, SyncTaskOccurrence},
     },
+    db::Database,
+    institution::academic::{current_terms, user_today, CurrentTerm},
+    models::{ClassAsynchronousTask, ClassSynchronousTask, StudentClassAsynchronousTask, User},
     schema::{
         class, class_asynchronous_task, class_student, class_synchronous_task, class_teacher,
         student_class_asynchronous_task, users,
@@ -46,12 +49,20 @@ pub enum AsynchronousTask {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SynchronousTask {
+    /// For repeating tasks, this has the details of the occurrence.
     task: ClassSynchronousTask,
+    /// Identifies the occurrence of a repeating task (see [`SyncTaskOccurrence`]).
+    occurrence: NaiveDateTime,
     class: crate::models::Class,
 }
 
+/// How far ahead the occurrences of repeating synchronous tasks are shown.
+const UPCOMING_OCCURRENCE_WEEKS: i64 = 2;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Dashboard {
+    /// Which term it is at each of the user's institutions.
+    terms: Vec<CurrentTerm>,
     sync_tasks: Vec<SynchronousTask>,
     async_tasks: Vec<AsynchronousTask>,
 }
@@ -60,6 +71,8 @@ impl Dashboard {
     /// Retrieve the dashboard from the database.
     pub async fn query(auth: AuthCookie, conn: Database) -> Result<Self, diesel::result::Error> {
         conn.run(move |c| {
+            let terms = current_terms(auth.0, user_today(auth.0, c)?, c)?;
+            let now = Utc::now().naive_utc();
             let sync_tasks = class_synchronous_task::table
                 .inner_join(
                     class::table
@@ -73,82 +86,103 @@ impl Dashboard {
                         .eq(auth.0)
                         .or(class_teacher::user_id.eq(auth.0)),
                 )
-                .filter(class_synchronous_task::start_time.ge(chrono::Utc::now().naive_utc()))
+                .filter(
+                    class_synchronous_task::start_time
+                        .ge(now)
+                        .or(class_synchronous_task::recurrence.is_not_null()),
+                )
                 .select((class_synchronous_task::all_columns, class::all_columns))
-                .load::<(ClassSynchronousTask, crate::models::Class)>(c)?
+                .load::<(ClassSynchronousTask, crate::models::Class)>(c)?;
+            // repeating tasks go on for a long time, so only their next few occurrences are shown
+            let (recurring, one_off): (Vec<_>, Vec<_>) = sync_tasks
                 .into_iter()
-                .map(|(task, class)| SynchronousTask { task, class })
-                .collect::<Vec<_>>();
-            let async_tasks = class_asynchronous_task::table
-                .inner_join(
-                    class::table
-                        .left_join(
-                            class_student::table.inner_join(student_class_asynchronous_task::table),
-                        )
-                        .left_join(class_teacher::table),
+                .partition(|(task, _)| task.recurrence.is_some());
+            let mut sync_tasks = expand_occurrences(
+                recurring,
+                now,
+                now + Duration::weeks(UPCOMING_OCCURRENCE_WEEKS),
+                c,
+            )?;
+            sync_tasks.extend(one_off.into_iter().map(|(task, class)| {
+                (
+                    SyncTaskOccurrence {
+                        occurrence: task.start_time,
+                        task,
+                    },
+                    class,
                 )
-                .filter(class_asynchronous_task::due_date.ge(chrono::Utc::now().naive_utc()))
+            }));
+            sync_tasks.sort_by_key(|(occurrence, _)| occurrence.task.start_time);
+            let sync_tasks = sync_tasks
+                .into_iter()
+                .map(|(occurrence, class)| SynchronousTask {
+                    task: occurrence.task,
+                    occurrence: occurrence.occurrence,
+                    class,
+                })
+                .collect::<Vec<_>>();
+            // students only see the tasks which have been set to them (with their own due dates)
+            let mut async_tasks = class_asynchronous_task::table
+                .inner_join(student_class_asynchronous_task::table.inner_join(class_student::table))
+                .inner_join(class::table)
+                .filter(class_student::user_id.eq(auth.0))
                 .filter(
-                    class_student::user_id
-                        .eq(auth.0)
-                        .or(class_teacher::user_id.eq(auth.0)),
+                    effective_due_date(
+                        student_class_asynchronous_task::due_date,
+                        class_asynchronous_task::due_date,
+                    )
+                    .ge(now),
                 )
-                // for each teacher task we have to send another query to the database, so to this
-                // is here (the number is a total guess) to keep response times reasonable.
+                .select((
+                    class_asynchronous_task::all_columns,
+                    student_class_asynchronous_task::all_columns,
+                    class::all_columns,
+                ))
                 .limit(5)
                 .load::<(
                     ClassAsynchronousTask,
-                    (
-                        crate::models::Class,
-                        Option<(ClassStudent, StudentClassAsynchronousTask)>,
-                        Option<ClassTeacher>,
-                    ),
-                )>(c)
-                .and_then(|list| {
-                    let mut output = vec![];
-                    for (task, (class, class_student, class_teacher)) in list {
-                        if class_student.is_none() && class_teacher.is_none() {
-                            continue;
-                        }
-                        let is_class_student = class_student
-                            .as_ref()
-                            .map(|(student, _)| student.user_id == auth.0)
-                            .unwrap_or(false);
-                        if is_class_student {
-                            if let Some((_, student_task)) = class_student {
-                                output.push(AsynchronousTask::Student(StudentTask {
-                                    task,
-                                    student_task,
-                                    class,
-                                }))
-                            } else {
-                                unreachable!()
-                            }
-                        } else if class_teacher.is_some() {
-                            let student_tasks = StudentClassAsynchronousTask::belonging_to(&task)
-                                .inner_join(class_student::table.inner_join(users::table))
-                                .select((
-                                    crate::schema::users::all_columns,
-                                    crate::schema::student_class_asynchronous_task::all_columns,
-                                ))
-                                .load::<(User, StudentClassAsynchronousTask)>(c)?;
-                            output.push(AsynchronousTask::Teacher(TeacherTask {
-                                task,
-                                number_set_to: student_tasks.len() as i32,
-                                number_completed: student_tasks
-                                    .into_iter()
-                                    .map(|(_, task)| if task.completed { 1 } else { 0 })
-                                    .sum::<i32>(),
-                                class,
-                            }))
-                        } else {
-                            unreachable!()
-                        }
-                    }
-                    Ok(output)
-                })?;
+                    StudentClassAsynchronousTask,
+                    crate::models::Class,
+                )>(c)?
+                .into_iter()
+                .map(|(task, student_task, class)| {
+                    AsynchronousTask::Student(StudentTask {
+                        task,
+                        student_task,
+                        class,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let teacher_tasks = class_asynchronous_task::table
+                .inner_join(class::table.inner_join(class_teacher::table))
+                .filter(class_teacher::user_id.eq(auth.0))
+                .filter(class_asynchronous_task::due_date.ge(now))
+                .select((class_asynchronous_task::all_columns, class::all_columns))
+                // for each teacher task we have to send another query to the database, so to this
+                // is here (the number is a total guess) to keep response times reasonable.
+                .limit(5)
+                .load::<(ClassAsynchronousTask, crate::models::Class)>(c)?;
+            for (task, class) in teacher_tasks {
+                let student_tasks = StudentClassAsynchronousTask::belonging_to(&task)
+                    .inner_join(class_student::table.inner_join(users::table))
+                    .select((
+                        crate::schema::users::all_columns,
+                        crate::schema::student_class_asynchronous_task::all_columns,
+                    ))
+                    .load::<(User, StudentClassAsynchronousTask)>(c)?;
+                async_tasks.push(AsynchronousTask::Teacher(TeacherTask {
+                    task,
+                    number_set_to: student_tasks.len() as i32,
+                    number_completed: student_tasks
+                        .into_iter()
+                        .map(|(_, task)| if task.completed { 1 } else { 0 })
+                        .sum::<i32>(),
+                    class,
+                }))
+            }
 
             Ok(Self {
+                terms,
                 sync_tasks,
                 async_tasks,
             })
@@ -161,6 +195,7 @@ impl Render<Html> for Dashboard {
     fn render(self) -> Html {
         Html::new().head(default_head("Dashboard")).body(
             Body::new()
+                .children(self.terms.into_iter().map(|term| term.render()))
                 .child(
                     Level::new()
                         .child(H1::new("Upcoming asynchronous tasks"))
@@ -195,7 +230,10 @@ impl Render<Html> for Dashboard {
                                             teacher.task.due_date.format("%Y-%m-%d %H:%M:%S")
                                         }
                                         AsynchronousTask::Student(ref student) => {
-                                            student.task.due_date.format("%Y-%m-%d %H:%M:%S")
+                                            student
+                                                .student_task
+                                                .effective_due_date(&student.task)
+                                                .format("%Y-%m-%d %H:%M:%S")
                                         }
                                     }
                                 )))
@@ -245,6 +283,29 @@ impl Render<Html> for Dashboard {
     }
 }
 
+impl Render<Div> for CurrentTerm {
+    fn render(self) -> Div {
+        let mut summary = match (&self.term, &self.half_term) {
+            (Some(term), Some(half_term)) => format!("{} ({})", term.name, half_term.name),
+            (Some(term), None) | (None, Some(term)) => term.name.clone(),
+            (None, None) => String::new(),
+        };
+        if let Some(week) = self.week {
+            summary.push_str(&format!(", week {}", week));
+        }
+        if let Some(holiday) = self.holiday {
+            if !summary.is_empty() {
+                summary.push_str(" – ");
+            }
+            summary.push_str(&format!("Holiday: {}", holiday.name));
+        }
+        Level::new()
+            .child(H3::new(self.institution_name))
+            .child(P::with_text(summary))
+            .into_div()
+    }
+}
+
 pub struct SyncTaskCard(pub SynchronousTask);
 
 impl Render<Div> for SyncTaskCard {
@@ -289,8 +350,11 @@ pub async fn html_dashboard(conn: Database, auth: AuthCookie) -> Html {
 }
 
 #[get("/")]
-pub async fn api_dashboard(conn: Database, auth: AuthCookie) -> Json<ApiResponse<Dashboard>> {
-    Json(match Dashboard::query(auth, conn).await {
+pub async fn api_dashboard(
+    conn: Database,
+    auth: ApiAuth<ReadTasks>,
+) -> Json<ApiResponse<Dashboard>> {
+    Json(match Dashboard::query(auth.into(), conn).await {
         Ok(res) => ApiResponse::new_ok(res),
         Err(e) => {
             error!("{:#?}", e);
@@ -312,15 +376,16 @@ mod test_dashboard {
             setup_env as setup_env_to_be_extended, STUDENT_PASSWORD, STUDENT_USERNAME,
             TEACHER_PASSWORD, TEACHER_USERNAME,
         },
+        models::StudentClassAsynchronousTask,
         models::{
             NewClass, NewClassAsynchronousTask, NewClassStudent, NewClassSynchronousTask,
             NewClassTeacher, NewStudentClassAsynchronousTask,
         },
         schema::{
             class, class_asynchronous_task, class_student, class_synchronous_task, class_teacher,
-            student_class_asynchronous_task,
+            student_class_asynchronous_task, users,
         },
-        utils::{client, login_user},
+        utils::{client, create_user, login_user, logout},
     };
 
     const CLASS_NAME: &str = "some-class";
@@ -332,7 +397,8 @@ mod test_dashboard {
     const SYNC_TASK_NAME: &str = "a synchronous task";
     const SYNC_TASK_DESCRIPTION: &str = "description of the synchronous task";
 
-    async fn setup_env(conn: Database) {
+    /// Returns the ids of the class and of its teacher.
+    async fn setup_env(conn: Database) -> (i32, i32) {
         let (_, teacher_id, student_id, institution_id, student_group_id) =
             conn.run(|c| setup_env_to_be_extended(c)).await;
         conn.run(move |c| {
@@ -344,6 +410,7 @@ mod test_dashboard {
                     code: &nanoid!(5),
                     institution_id: Some(institution_id),
                     student_group_id: Some(student_group_id),
+                    academic_term_id: None,
                 })
                 .returning(class::id)
                 .get_result::<i32>(c)
@@ -376,6 +443,7 @@ mod test_dashboard {
                         .naive_utc(),
                     class_teacher_id,
                     class_id,
+                    recurrence: None,
                 })
                 .execute(c)
                 .unwrap();
@@ -387,6 +455,8 @@ mod test_dashboard {
                     due_date: Utc::now().add(Duration::days(5)).naive_utc(),
                     class_teacher_id,
                     class_id,
+                    resubmit_until: None,
+                    estimated_minutes: 25,
                 })
                 .returning(class_asynchronous_task::id)
                 .get_result(c)
@@ -399,6 +469,7 @@ mod test_dashboard {
                 })
                 .execute(c)
                 .unwrap();
+            (class_id, class_teacher_id)
         })
         .await
     }
@@ -436,4 +507,95 @@ mod test_dashboard {
         println!("{}", string);
         assert!(string.contains("0 of 1"));
     }
+
+    #[rocket::async_test]
+    async fn test_student_sees_own_tasks_and_due_dates() {
+        const OTHER_USERNAME: &str = "other-student";
+        const OTHER_EMAIL: &str = "other.student@example.com";
+        const OTHER_PASSWORD: &str = "other-student-passw0rd";
+        const OTHER_TASK_NAME: &str = "a task set to the other student";
+
+        let client = client().await;
+        let (class_id, class_teacher_id) =
+            setup_env(Database::get_one(client.rocket()).await.unwrap()).await;
+        create_user(OTHER_USERNAME, OTHER_EMAIL, "UTC", OTHER_PASSWORD, &client).await;
+        let (task_due, extended_due) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                let user_id = users::table
+                    .filter(users::email.eq(OTHER_EMAIL))
+                    .select(users::id)
+                    .first::<i32>(c)
+                    .unwrap();
+                let class_student_id = diesel::insert_into(class_student::table)
+                    .values(NewClassStudent { user_id, class_id })
+                    .returning(class_student::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                // the task was due yesterday, but the other student has been given an extension
+                let task_due = Utc::now().naive_utc() - Duration::days(1);
+                let task_id = diesel::insert_into(class_asynchronous_task::table)
+                    .values(NewClassAsynchronousTask {
+                        title: OTHER_TASK_NAME,
+                        description: ASYNC_TASK_DESCRIPTION,
+                        created: Utc::now().naive_utc(),
+                        due_date: task_due,
+                        class_teacher_id,
+                        class_id,
+                        resubmit_until: None,
+                        estimated_minutes: 25,
+                    })
+                    .returning(class_asynchronous_task::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                let extended_due = Utc::now().naive_utc() + Duration::days(9);
+                diesel::insert_into(student_class_asynchronous_task::table)
+                    .values(NewStudentClassAsynchronousTask {
+                        class_student_id,
+                        class_asynchronous_task_id: task_id,
+                        completed: false,
+                    })
+                    .returning(student_class_asynchronous_task::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                diesel::update(student_class_asynchronous_task::table.filter(
+                    student_class_asynchronous_task::class_student_id.eq(class_student_id),
+                ))
+                .set(student_class_asynchronous_task::due_date.eq(extended_due))
+                .get_result::<StudentClassAsynchronousTask>(c)
+                .unwrap();
+                (task_due, extended_due)
+            })
+            .await;
+        let format = |date: chrono::NaiveDateTime| date.format("%Y-%m-%d %H:%M:%S").to_string();
+
+        // the student isn't shown the task (or the extension) which only the other student has
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+        let string = client
+            .get("/dashboard")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .expect("invalid body response");
+        assert!(string.contains(ASYNC_TASK_NAME));
+        assert!(!string.contains(OTHER_TASK_NAME));
+        assert!(!string.contains(&format(extended_due)));
+        logout(&client).await;
+
+        // the other student sees their task (with their due date), but not the one they weren't set
+        login_user(OTHER_USERNAME, OTHER_PASSWORD, &client).await;
+        let string = client
+            .get("/dashboard")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .expect("invalid body response");
+        assert!(string.contains(OTHER_TASK_NAME));
+        assert!(string.contains(&format(extended_due)));
+        assert!(!string.contains(&format(task_due)));
+        assert!(!string.contains(ASYNC_TASK_NAME));
+    }
 }
@@ -0,0 +1,789 @@
+//! Setting up an institution's academic years, terms and holidays.
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use mercutio::Apply;
+use portia::levels::Level;
+use rocket::{response::Redirect, serde::json::Json};
+
+use super::{parse_date, AcademicError};
+use crate::{
+    auth::AuthCookie,
+    db::{Database, DatabaseConnection},
+    institution::{is_administrator, is_member},
+    models::institution::academic::{
+        AcademicTerm, AcademicYear, Holiday, NewAcademicTerm, NewAcademicYear, NewHoliday,
+    },
+    schema::{academic_term, academic_year, holiday},
+    utils::{default_head, html_or_redirect::HtmlOrRedirect, json_response::ApiResponse},
+};
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct CreateYearForm {
+    name: String,
+    /// In the form YYYY-MM-DD.
+    starts: String,
+    ends: String,
+}
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct CreateTermForm {
+    name: String,
+    starts: String,
+    ends: String,
+    /// If this is provided, this is a half-term of that term.
+    parent_term_id: Option<i32>,
+}
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct CreateHolidayForm {
+    name: String,
+    starts: String,
+    ends: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct YearDetails {
+    pub year: AcademicYear,
+    /// Terms and half-terms, in order.
+    pub terms: Vec<AcademicTerm>,
+    pub holidays: Vec<Holiday>,
+}
+
+fn calendar_url(institution_id: i32) -> String {
+    format!("/institution/{}/calendar", institution_id)
+}
+
+/// Parses the dates, checking that they're in the right order.
+fn parse_dates(starts: &str, ends: &str) -> Result<(NaiveDate, NaiveDate), AcademicError> {
+    let (starts, ends) = (parse_date(starts)?, parse_date(ends)?);
+    if ends < starts {
+        Err(AcademicError::InvalidRange(
+            "The end date can't be before the start date.",
+        ))
+    } else {
+        Ok((starts, ends))
+    }
+}
+
+fn find_year(
+    institution_id: i32,
+    year_id: i32,
+    c: &DatabaseConnection,
+) -> Result<AcademicYear, AcademicError> {
+    academic_year::table
+        .filter(academic_year::id.eq(year_id))
+        .filter(academic_year::institution_id.eq(institution_id))
+        .first::<AcademicYear>(c)
+        .optional()?
+        .ok_or(AcademicError::NotFound)
+}
+
+fn load_years(institution_id: i32, c: &DatabaseConnection) -> QueryResult<Vec<YearDetails>> {
+    academic_year::table
+        .filter(academic_year::institution_id.eq(institution_id))
+        .order_by(academic_year::starts)
+        .load::<AcademicYear>(c)?
+        .into_iter()
+        .map(|year| {
+            let terms = academic_term::table
+                .filter(academic_term::academic_year_id.eq(year.id))
+                .order_by((academic_term::starts, academic_term::parent_term_id.desc()))
+                .load::<AcademicTerm>(c)?;
+            let holidays = holiday::table
+                .filter(holiday::academic_year_id.eq(year.id))
+                .order_by(holiday::starts)
+                .load::<Holiday>(c)?;
+            Ok(YearDetails {
+                year,
+                terms,
+                holidays,
+            })
+        })
+        .collect()
+}
+
+/// Every member of the institution can see its terms and holidays.
+async fn list_years_base(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<(Vec<YearDetails>, bool), AcademicError> {
+    conn.run(move |c| {
+        if !is_member(institution_id, auth.0, c)? {
+            return Err(AcademicError::PermissionError);
+        }
+        let is_admin = is_administrator(institution_id, auth, c).is_ok();
+        Ok((load_years(institution_id, c)?, is_admin))
+    })
+    .await
+}
+
+fn date_inputs(form: Form) -> Form {
+    form.child(Label::new("Starts (YYYY-MM-DD)"))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("starts")),
+        )
+        .child(Label::new("Ends (YYYY-MM-DD)"))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("ends")),
+        )
+}
+
+fn create_form(action: String, placeholder: &'static str) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(action))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("name"))
+                .attribute(Placeholder::new(placeholder)),
+        )
+        .apply(date_inputs)
+}
+
+fn delete_button(action: String, label: &'static str) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(action))
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new(label)),
+        )
+}
+
+fn date_range(starts: NaiveDate, ends: NaiveDate) -> String {
+    format!(
+        "{} to {}",
+        starts.format("%A %-d %B %Y"),
+        ends.format("%A %-d %B %Y")
+    )
+}
+
+fn render_year(institution_id: i32, details: YearDetails, is_admin: bool) -> Level {
+    let url = calendar_url(institution_id);
+    let year_url = format!("{}/years/{}", url, details.year.id);
+    let top_level_terms = details
+        .terms
+        .iter()
+        .filter(|term| term.parent_term_id.is_none())
+        .cloned()
+        .collect::<Vec<_>>();
+    Level::new()
+        .child(H2::new(format!(
+            "{} ({})",
+            details.year.name,
+            date_range(details.year.starts, details.year.ends)
+        )))
+        .child(H3::new("Terms"))
+        .children(details.terms.into_iter().map(|term| {
+            Div::new()
+                .child(P::with_text(format!(
+                    "{}{}: {}",
+                    if term.parent_term_id.is_some() {
+                        "Half-term – "
+                    } else {
+                        ""
+                    },
+                    term.name,
+                    date_range(term.starts, term.ends)
+                )))
+                .map(|div| {
+                    if is_admin {
+                        div.child(delete_button(
+                            format!("{}/terms/{}/delete", url, term.id),
+                            "Delete",
+                        ))
+                    } else {
+                        div
+                    }
+                })
+        }))
+        .child(H3::new("Holidays"))
+        .children(details.holidays.into_iter().map(|holiday| {
+            Div::new()
+                .child(P::with_text(format!(
+                    "{}: {}",
+                    holiday.name,
+                    date_range(holiday.starts, holiday.ends)
+                )))
+                .map(|div| {
+                    if is_admin {
+                        div.child(delete_button(
+                            format!("{}/holidays/{}/delete", url, holiday.id),
+                            "Delete",
+                        ))
+                    } else {
+                        div
+                    }
+                })
+        }))
+        .apply(|level| {
+            if !is_admin {
+                return level;
+            }
+            level
+                .child(H3::new("Add a term"))
+                .child(
+                    create_form(format!("{}/terms", year_url), "The term's name")
+                        .child(Label::new("Make this a half-term of"))
+                        .child(
+                            Select::new()
+                                .attribute(Name::new("parent_term_id"))
+                                .child(
+                                    SelectOption::new()
+                                        .attribute(Value::new(""))
+                                        .text("(Not a half-term)"),
+                                )
+                                .children(top_level_terms.into_iter().map(|term| {
+                                    SelectOption::new()
+                                        .attribute(Value::new(term.id.to_string()))
+                                        .text(term.name)
+                                })),
+                        )
+                        .child(
+                            Input::new()
+                                .attribute(Type::Submit)
+                                .attribute(Value::new("Add term")),
+                        ),
+                )
+                .child(H3::new("Add a holiday"))
+                .child(
+                    create_form(format!("{}/holidays", year_url), "The holiday's name").child(
+                        Input::new()
+                            .attribute(Type::Submit)
+                            .attribute(Value::new("Add holiday")),
+                    ),
+                )
+                .child(delete_button(
+                    format!("{}/delete", year_url),
+                    "Delete this academic year",
+                ))
+        })
+}
+
+#[get("/<institution_id>/calendar")]
+pub async fn academic_calendar_page(institution_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let (years, is_admin) = match list_years_base(institution_id, auth, &conn).await {
+        Ok(t) => t,
+        Err(e) => return e.render("Could not load the academic calendar"),
+    };
+    Html::new().head(default_head("Terms and holidays")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Terms and holidays"))
+                .apply(|level| {
+                    if years.is_empty() {
+                        level.child(P::with_text(
+                            "This institution hasn't set up any academic years yet.",
+                        ))
+                    } else {
+                        level.children(
+                            years
+                                .into_iter()
+                                .map(|year| render_year(institution_id, year, is_admin)),
+                        )
+                    }
+                })
+                .apply(|level| {
+                    if is_admin {
+                        level.child(H2::new("Add an academic year")).child(
+                            create_form(
+                                format!("{}/years", calendar_url(institution_id)),
+                                "The year's name (e.g. 2021–22)",
+                            )
+                            .child(
+                                Input::new()
+                                    .attribute(Type::Submit)
+                                    .attribute(Value::new("Add academic year")),
+                            ),
+                        )
+                    } else {
+                        level
+                    }
+                }),
+        ),
+    )
+}
+
+#[get("/<institution_id>/calendar")]
+pub async fn api_academic_calendar(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<Vec<YearDetails>>> {
+    Json(match list_years_base(institution_id, auth, &conn).await {
+        Ok((years, _)) => ApiResponse::new_ok(years),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+async fn create_year_base(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: CreateYearForm,
+    conn: &Database,
+) -> Result<AcademicYear, AcademicError> {
+    let (starts, ends) = parse_dates(&form.starts, &form.ends)?;
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        Ok(diesel::insert_into(academic_year::table)
+            .values(NewAcademicYear {
+                institution_id,
+                name: &form.name,
+                starts,
+                ends,
+            })
+            .returning(academic_year::all_columns)
+            .get_result::<AcademicYear>(c)?)
+    })
+    .await
+}
+
+#[post("/<institution_id>/calendar/years", data = "<form>")]
+pub async fn html_create_year(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<CreateYearForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match create_year_base(institution_id, auth, form.into_inner(), &conn).await {
+        Ok(_) => HtmlOrRedirect::Redirect(Redirect::to(calendar_url(institution_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not add that academic year")),
+    }
+}
+
+#[post("/<institution_id>/calendar/years", data = "<form>")]
+pub async fn api_create_year(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: Json<CreateYearForm>,
+    conn: Database,
+) -> Json<ApiResponse<AcademicYear>> {
+    Json(
+        match create_year_base(institution_id, auth, form.into_inner(), &conn).await {
+            Ok(year) => ApiResponse::new_ok(year),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+async fn create_term_base(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    form: CreateTermForm,
+    conn: &Database,
+) -> Result<AcademicTerm, AcademicError> {
+    let (starts, ends) = parse_dates(&form.starts, &form.ends)?;
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        let year = find_year(institution_id, year_id, c)?;
+        if starts < year.starts || ends > year.ends {
+            return Err(AcademicError::InvalidRange(
+                "Terms have to be within their academic year.",
+            ));
+        }
+        if let Some(parent_term_id) = form.parent_term_id {
+            let parent = academic_term::table
+                .filter(academic_term::id.eq(parent_term_id))
+                .filter(academic_term::academic_year_id.eq(year_id))
+                .filter(academic_term::parent_term_id.is_null())
+                .first::<AcademicTerm>(c)
+                .optional()?
+                .ok_or(AcademicError::InvalidRange(
+                    "Half-terms have to be part of a term in the same academic year.",
+                ))?;
+            if starts < parent.starts || ends > parent.ends {
+                return Err(AcademicError::InvalidRange(
+                    "Half-terms have to be within their term.",
+                ));
+            }
+        }
+        Ok(diesel::insert_into(academic_term::table)
+            .values(NewAcademicTerm {
+                academic_year_id: year_id,
+                parent_term_id: form.parent_term_id,
+                name: &form.name,
+                starts,
+                ends,
+            })
+            .returning(academic_term::all_columns)
+            .get_result::<AcademicTerm>(c)?)
+    })
+    .await
+}
+
+#[post("/<institution_id>/calendar/years/<year_id>/terms", data = "<form>")]
+pub async fn html_create_term(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<CreateTermForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match create_term_base(institution_id, year_id, auth, form.into_inner(), &conn).await {
+        Ok(_) => HtmlOrRedirect::Redirect(Redirect::to(calendar_url(institution_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not add that term")),
+    }
+}
+
+#[post("/<institution_id>/calendar/years/<year_id>/terms", data = "<form>")]
+pub async fn api_create_term(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    form: Json<CreateTermForm>,
+    conn: Database,
+) -> Json<ApiResponse<AcademicTerm>> {
+    Json(
+        match create_term_base(institution_id, year_id, auth, form.into_inner(), &conn).await {
+            Ok(term) => ApiResponse::new_ok(term),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+async fn create_holiday_base(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    form: CreateHolidayForm,
+    conn: &Database,
+) -> Result<Holiday, AcademicError> {
+    let (starts, ends) = parse_dates(&form.starts, &form.ends)?;
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        let year = find_year(institution_id, year_id, c)?;
+        if starts < year.starts || ends > year.ends {
+            return Err(AcademicError::InvalidRange(
+                "Holidays have to be within their academic year.",
+            ));
+        }
+        Ok(diesel::insert_into(holiday::table)
+            .values(NewHoliday {
+                academic_year_id: year_id,
+                name: &form.name,
+                starts,
+                ends,
+            })
+            .returning(holiday::all_columns)
+            .get_result::<Holiday>(c)?)
+    })
+    .await
+}
+
+#[post("/<institution_id>/calendar/years/<year_id>/holidays", data = "<form>")]
+pub async fn html_create_holiday(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<CreateHolidayForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match create_holiday_base(institution_id, year_id, auth, form.into_inner(), &conn).await {
+        Ok(_) => HtmlOrRedirect::Redirect(Redirect::to(calendar_url(institution_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not add that holiday")),
+    }
+}
+
+#[post("/<institution_id>/calendar/years/<year_id>/holidays", data = "<form>")]
+pub async fn api_create_holiday(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    form: Json<CreateHolidayForm>,
+    conn: Database,
+) -> Json<ApiResponse<Holiday>> {
+    Json(
+        match create_holiday_base(institution_id, year_id, auth, form.into_inner(), &conn).await {
+            Ok(holiday) => ApiResponse::new_ok(holiday),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+/// What can be deleted from the academic calendar.
+#[derive(Debug, Copy, Clone)]
+enum Deletable {
+    /// Deleting a year deletes its terms and holidays too.
+    Year,
+    /// Deleting a term deletes its half-terms too. Classes which were bound to it no longer are.
+    Term,
+    Holiday,
+}
+
+async fn delete_base(
+    institution_id: i32,
+    deletable: Deletable,
+    id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<(), AcademicError> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        let deleted = match deletable {
+            Deletable::Year => diesel::delete(
+                academic_year::table
+                    .filter(academic_year::id.eq(id))
+                    .filter(academic_year::institution_id.eq(institution_id)),
+            )
+            .execute(c)?,
+            Deletable::Term => diesel::delete(
+                academic_term::table
+                    .filter(academic_term::id.eq(id))
+                    .filter(
+                        academic_term::academic_year_id.eq_any(
+                            academic_year::table
+                                .filter(academic_year::institution_id.eq(institution_id))
+                                .select(academic_year::id),
+                        ),
+                    ),
+            )
+            .execute(c)?,
+            Deletable::Holiday => diesel::delete(
+                holiday::table.filter(holiday::id.eq(id)).filter(
+                    holiday::academic_year_id.eq_any(
+                        academic_year::table
+                            .filter(academic_year::institution_id.eq(institution_id))
+                            .select(academic_year::id),
+                    ),
+                ),
+            )
+            .execute(c)?,
+        };
+        if deleted == 0 {
+            Err(AcademicError::NotFound)
+        } else {
+            Ok(())
+        }
+    })
+    .await
+}
+
+fn html_delete_response(institution_id: i32, res: Result<(), AcademicError>) -> HtmlOrRedirect {
+    match res {
+        Ok(()) => HtmlOrRedirect::Redirect(Redirect::to(calendar_url(institution_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not delete that")),
+    }
+}
+
+fn api_delete_response(res: Result<(), AcademicError>) -> Json<ApiResponse<()>> {
+    Json(match res {
+        Ok(()) => ApiResponse::new_ok(()),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[post("/<institution_id>/calendar/years/<year_id>/delete")]
+pub async fn html_delete_year(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> HtmlOrRedirect {
+    html_delete_response(
+        institution_id,
+        delete_base(institution_id, Deletable::Year, year_id, auth, &conn).await,
+    )
+}
+
+#[post("/<institution_id>/calendar/years/<year_id>/delete")]
+pub async fn api_delete_year(
+    institution_id: i32,
+    year_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    api_delete_response(delete_base(institution_id, Deletable::Year, year_id, auth, &conn).await)
+}
+
+#[post("/<institution_id>/calendar/terms/<term_id>/delete")]
+pub async fn html_delete_term(
+    institution_id: i32,
+    term_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> HtmlOrRedirect {
+    html_delete_response(
+        institution_id,
+        delete_base(institution_id, Deletable::Term, term_id, auth, &conn).await,
+    )
+}
+
+#[post("/<institution_id>/calendar/terms/<term_id>/delete")]
+pub async fn api_delete_term(
+    institution_id: i32,
+    term_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    api_delete_response(delete_base(institution_id, Deletable::Term, term_id, auth, &conn).await)
+}
+
+#[post("/<institution_id>/calendar/holidays/<holiday_id>/delete")]
+pub async fn html_delete_holiday(
+    institution_id: i32,
+    holiday_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> HtmlOrRedirect {
+    html_delete_response(
+        institution_id,
+        delete_base(institution_id, Deletable::Holiday, holiday_id, auth, &conn).await,
+    )
+}
+
+#[post("/<institution_id>/calendar/holidays/<holiday_id>/delete")]
+pub async fn api_delete_holiday(
+    institution_id: i32,
+    holiday_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    api_delete_response(
+        delete_base(institution_id, Deletable::Holiday, holiday_id, auth, &conn).await,
+    )
+}
+
+#[cfg(test)]
+mod test_academic_calendar {
+    use diesel::prelude::*;
+    use rocket::http::{ContentType, Status};
+
+    use crate::{
+        db::Database,
+        institution::test_ctx::{
+            setup_env, ADMIN_EMAIL, ADMIN_PASSWORD, STUDENT_EMAIL, STUDENT_PASSWORD,
+        },
+        models::institution::academic::{AcademicTerm, AcademicYear},
+        schema::{academic_term, academic_year, holiday},
+        utils::{client, login_user, logout},
+    };
+
+    #[rocket::async_test]
+    async fn test_manage_academic_calendar() {
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        let url = format!("/institution/{}/calendar", institution_id);
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let res = client
+            .post(format!("{}/years", url))
+            .header(ContentType::Form)
+            .body("name=2021-22&starts=2021-09-01&ends=2022-07-22")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let year = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                academic_year::table
+                    .filter(academic_year::institution_id.eq(institution_id))
+                    .first::<AcademicYear>(c)
+                    .unwrap()
+            })
+            .await;
+        let year_url = format!("{}/years/{}", url, year.id);
+
+        let res = client
+            .post(format!("{}/terms", year_url))
+            .header(ContentType::Form)
+            .body("name=Autumn&starts=2021-09-01&ends=2021-12-17")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let autumn = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                academic_term::table
+                    .filter(academic_term::academic_year_id.eq(year.id))
+                    .first::<AcademicTerm>(c)
+                    .unwrap()
+            })
+            .await;
+        // half-terms have to be within their term
+        let res = client
+            .post(format!("{}/terms", year_url))
+            .header(ContentType::Form)
+            .body(format!(
+                "name=Autumn%202&starts=2021-11-01&ends=2022-01-07&parent_term_id={}",
+                autumn.id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .post(format!("{}/terms", year_url))
+            .header(ContentType::Form)
+            .body(format!(
+                "name=Autumn%202&starts=2021-11-01&ends=2021-12-17&parent_term_id={}",
+                autumn.id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        // as do holidays
+        let res = client
+            .post(format!("{}/holidays", year_url))
+            .header(ContentType::Form)
+            .body("name=Summer&starts=2022-07-23&ends=2022-08-31")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .post(format!("{}/holidays", year_url))
+            .header(ContentType::Form)
+            .body("name=Christmas&starts=2021-12-18&ends=2022-01-03")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let res = client
+            .post(format!("{}/holidays", year_url))
+            .header(ContentType::Form)
+            .body("name=Bad&starts=03/01/2022&ends=2022-01-03")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        logout(&client).await;
+
+        // students can see the calendar, but not change it
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+        let page = client.get(&url).dispatch().await;
+        let page = page.into_string().await.unwrap();
+        assert!(page.contains("Half-term – Autumn 2"));
+        assert!(page.contains("Christmas"));
+        assert!(!page.contains("Add a holiday"));
+        let res = client.post(format!("{}/delete", year_url)).dispatch().await;
+        assert_eq!(res.status(), Status::Forbidden);
+        logout(&client).await;
+
+        // deleting a year deletes everything in it
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let res = client.post(format!("{}/delete", year_url)).dispatch().await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let (terms, holidays) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                (
+                    academic_term::table.count().get_result::<i64>(c).unwrap(),
+                    holiday::table.count().get_result::<i64>(c).unwrap(),
+                )
+            })
+            .await;
+        assert_eq!((terms, holidays), (0, 0));
+    }
+}
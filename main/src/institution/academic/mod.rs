@@ -0,0 +1,304 @@
+//! Academic years, terms (and half-terms) and holidays.
+//!
+//! Administrators set these up for their institution; classes can then be bound to a term. They
+//! are used to show people which term (and week of it) it is, and to stop the scheduler from
+//! planning work during the holidays.
+
+pub mod manage;
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use rocket::http::Status;
+use thiserror::Error as ThisError;
+
+use crate::{
+    db::DatabaseConnection,
+    models::institution::academic::{AcademicTerm, Holiday},
+    schema::{
+        academic_term, academic_year, administrator, class, class_student, class_teacher, holiday,
+        institution, institution_student, institution_teacher, users,
+    },
+    utils::{default_head, error::LovelaceError},
+};
+
+#[derive(ThisError, Debug)]
+pub enum AcademicError {
+    #[error("not found")]
+    NotFound,
+    #[error("permission error")]
+    PermissionError,
+    #[error("invalid date")]
+    InvalidDate,
+    #[error("invalid dates")]
+    InvalidRange(&'static str),
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for AcademicError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl From<LovelaceError> for AcademicError {
+    fn from(e: LovelaceError) -> Self {
+        match e {
+            LovelaceError::PermissionError(_) => Self::PermissionError,
+            _ => Self::DatabaseError,
+        }
+    }
+}
+
+impl AcademicError {
+    fn status(&self) -> Status {
+        match self {
+            AcademicError::NotFound => Status::NotFound,
+            AcademicError::PermissionError => Status::Forbidden,
+            AcademicError::InvalidDate | AcademicError::InvalidRange(_) => Status::BadRequest,
+            AcademicError::DatabaseError => Status::InternalServerError,
+        }
+    }
+
+    fn explanation(&self) -> &'static str {
+        match self {
+            AcademicError::NotFound => "That academic year, term or holiday doesn't exist.",
+            AcademicError::PermissionError => {
+                "You don't have permission to do this – only administrators of this institution \
+                can change its terms and holidays."
+            }
+            AcademicError::InvalidDate => "Please provide dates in the form YYYY-MM-DD.",
+            AcademicError::InvalidRange(explanation) => explanation,
+            AcademicError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!"
+            }
+        }
+    }
+
+    fn render(self, title: &'static str) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head(title))
+            .body(
+                Body::new()
+                    .child(H1::new(title))
+                    .child(P::with_text(self.explanation())),
+            )
+    }
+}
+
+/// Parses a date in the form YYYY-MM-DD.
+fn parse_date(date: &str) -> Result<NaiveDate, AcademicError> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").map_err(|_| AcademicError::InvalidDate)
+}
+
+/// The week of the term the date falls in. Weeks start on Mondays, and the week the term starts in
+/// is the first week (even if the term starts part of the way through it).
+pub fn week_of_term(term_starts: NaiveDate, date: NaiveDate) -> i64 {
+    let first_monday =
+        term_starts - Duration::days(term_starts.weekday().num_days_from_monday() as i64);
+    (date - first_monday).num_days().div_euclid(7) + 1
+}
+
+/// Which term it is at one of the institutions somebody is part of.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrentTerm {
+    pub institution_id: i32,
+    pub institution_name: String,
+    /// If it's the holidays, there might not be a term.
+    pub term: Option<AcademicTerm>,
+    pub half_term: Option<AcademicTerm>,
+    /// The week of the term (see [`week_of_term`]).
+    pub week: Option<i64>,
+    pub holiday: Option<Holiday>,
+}
+
+/// The institutions the user is a member of, or has classes with.
+fn user_institutions(user_id: i32, c: &DatabaseConnection) -> QueryResult<Vec<i32>> {
+    let mut institutions = administrator::table
+        .filter(administrator::user_id.eq(user_id))
+        .select(administrator::institution_id)
+        .load::<i32>(c)?;
+    institutions.extend(
+        institution_teacher::table
+            .filter(institution_teacher::user_id.eq(user_id))
+            .select(institution_teacher::institution_id)
+            .load::<i32>(c)?,
+    );
+    institutions.extend(
+        institution_student::table
+            .filter(institution_student::user_id.eq(user_id))
+            .select(institution_student::institution_id)
+            .load::<i32>(c)?,
+    );
+    institutions.extend(
+        class::table
+            .left_join(class_student::table)
+            .left_join(class_teacher::table)
+            .filter(
+                class_student::user_id
+                    .eq(user_id)
+                    .or(class_teacher::user_id.eq(user_id)),
+            )
+            .select(class::institution_id)
+            .load::<Option<i32>>(c)?
+            .into_iter()
+            .flatten(),
+    );
+    institutions.sort_unstable();
+    institutions.dedup();
+    Ok(institutions)
+}
+
+/// The date it is for the user (in their timezone).
+pub(crate) fn user_today(user_id: i32, c: &DatabaseConnection) -> QueryResult<NaiveDate> {
+    let timezone = users::table
+        .find(user_id)
+        .select(users::timezone)
+        .first::<String>(c)?;
+    Ok(match chrono_tz::Tz::from_str(&timezone) {
+        Ok(timezone) => Utc::now().with_timezone(&timezone).date().naive_local(),
+        Err(_) => Utc::now().date().naive_utc(),
+    })
+}
+
+/// Which term it is (on the given date) at each of the institutions the user is part of. Only
+/// institutions which are in a term or a holiday on that date are included.
+pub fn current_terms(
+    user_id: i32,
+    date: NaiveDate,
+    c: &DatabaseConnection,
+) -> QueryResult<Vec<CurrentTerm>> {
+    let mut current = vec![];
+    for institution_id in user_institutions(user_id, c)? {
+        let terms = academic_term::table
+            .inner_join(academic_year::table)
+            .filter(academic_year::institution_id.eq(institution_id))
+            .filter(academic_term::starts.le(date))
+            .filter(academic_term::ends.ge(date))
+            .select(academic_term::all_columns)
+            .load::<AcademicTerm>(c)?;
+        let term = terms
+            .iter()
+            .find(|term| term.parent_term_id.is_none())
+            .cloned();
+        let half_term = terms
+            .iter()
+            .find(|term| term.parent_term_id.is_some())
+            .cloned();
+        let holiday = holidays_between(institution_id, date, date, c)?
+            .into_iter()
+            .next();
+        if term.is_none() && half_term.is_none() && holiday.is_none() {
+            continue;
+        }
+        let week = term
+            .as_ref()
+            .or(half_term.as_ref())
+            .map(|term| week_of_term(term.starts, date));
+        current.push(CurrentTerm {
+            institution_id,
+            institution_name: institution::table
+                .find(institution_id)
+                .select(institution::name)
+                .first::<String>(c)?,
+            term,
+            half_term,
+            week,
+            holiday,
+        });
+    }
+    Ok(current)
+}
+
+/// The institution's holidays which overlap the dates (inclusive).
+pub fn holidays_between(
+    institution_id: i32,
+    from: NaiveDate,
+    to: NaiveDate,
+    c: &DatabaseConnection,
+) -> QueryResult<Vec<Holiday>> {
+    holiday::table
+        .inner_join(academic_year::table)
+        .filter(academic_year::institution_id.eq(institution_id))
+        .filter(holiday::starts.le(to))
+        .filter(holiday::ends.ge(from))
+        .select(holiday::all_columns)
+        .order_by(holiday::starts)
+        .load::<Holiday>(c)
+}
+
+/// The holidays (which overlap the dates) of the institution the class is part of. Stand-alone
+/// classes don't have any holidays.
+pub fn class_holidays_between(
+    class_id: i32,
+    from: NaiveDate,
+    to: NaiveDate,
+    c: &DatabaseConnection,
+) -> QueryResult<Vec<Holiday>> {
+    match class::table
+        .find(class_id)
+        .select(class::institution_id)
+        .first::<Option<i32>>(c)?
+    {
+        Some(institution_id) => holidays_between(institution_id, from, to, c),
+        None => Ok(vec![]),
+    }
+}
+
+/// The times (from the start of the first day to the end of the last, in the user's timezone)
+/// during which any of the institutions the user is part of are on holiday.
+pub fn user_holiday_periods(
+    user_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    c: &DatabaseConnection,
+) -> QueryResult<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+    let timezone = users::table
+        .find(user_id)
+        .select(users::timezone)
+        .first::<String>(c)?;
+    let timezone = chrono_tz::Tz::from_str(&timezone).unwrap_or(chrono_tz::UTC);
+    let start_of = |date: NaiveDate| {
+        timezone
+            .from_local_datetime(&date.and_hms(0, 0, 0))
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .unwrap_or_else(|| DateTime::from_utc(date.and_hms(0, 0, 0), Utc))
+    };
+    // a day either side, so that it doesn't matter which timezone the dates are in
+    let from_date = from.date().naive_utc() - Duration::days(1);
+    let to_date = to.date().naive_utc() + Duration::days(1);
+    let mut periods = vec![];
+    for institution_id in user_institutions(user_id, c)? {
+        for holiday in holidays_between(institution_id, from_date, to_date, c)? {
+            periods.push((
+                start_of(holiday.starts),
+                start_of(holiday.ends + Duration::days(1)),
+            ));
+        }
+    }
+    periods.sort_unstable();
+    Ok(periods)
+}
+
+#[cfg(test)]
+mod test_academic {
+    use chrono::NaiveDate;
+
+    use super::week_of_term;
+
+    #[test]
+    fn test_week_of_term() {
+        // a Wednesday
+        let starts = NaiveDate::from_ymd(2021, 9, 1);
+        assert_eq!(week_of_term(starts, starts), 1);
+        assert_eq!(week_of_term(starts, NaiveDate::from_ymd(2021, 9, 5)), 1);
+        assert_eq!(week_of_term(starts, NaiveDate::from_ymd(2021, 9, 6)), 2);
+        assert_eq!(week_of_term(starts, NaiveDate::from_ymd(2021, 10, 22)), 8);
+    }
+}
@@ -0,0 +1,141 @@
+//! Lets institution administrators see the attendance of each of the institution's classes, and
+//! which students' attendance is below the institution's threshold.
+
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::{levels::Level, render::Render};
+use rocket::serde::json::Json;
+
+use super::is_administrator;
+use crate::{
+    auth::AuthCookie,
+    class::attendance::{class_attendance, AttendanceSummary, StudentAttendance},
+    db::Database,
+    schema::{class, institution},
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        json_response::ApiResponse,
+    },
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClassAttendanceReport {
+    pub class_id: i32,
+    pub class_name: String,
+    pub summary: AttendanceSummary,
+    /// The students whose attendance in this class is below the institution's threshold (this is
+    /// always empty if the institution hasn't set one).
+    pub below_threshold: Vec<StudentAttendance>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttendanceReport {
+    pub threshold: Option<i32>,
+    pub classes: Vec<ClassAttendanceReport>,
+}
+
+async fn attendance_report_base(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> LovelaceResult<AttendanceReport> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        let threshold = institution::table
+            .find(institution_id)
+            .select(institution::attendance_threshold)
+            .first::<Option<i32>>(c)?;
+        let classes = class::table
+            .filter(class::institution_id.eq(institution_id))
+            .order_by(class::name)
+            .select((class::id, class::name))
+            .load::<(i32, String)>(c)?;
+        let mut report = AttendanceReport {
+            threshold,
+            classes: vec![],
+        };
+        for (class_id, class_name) in classes {
+            let (summary, students) = class_attendance(class_id, c)?;
+            report.classes.push(ClassAttendanceReport {
+                class_id,
+                class_name,
+                summary,
+                below_threshold: students
+                    .into_iter()
+                    .filter(|student| match (student.summary.rate, threshold) {
+                        (Some(rate), Some(threshold)) => rate < threshold as f64,
+                        _ => false,
+                    })
+                    .collect(),
+            });
+        }
+        Ok(report)
+    })
+    .await
+    .map_err(|e: LovelaceError| {
+        error!("{:#?}", e);
+        e
+    })
+}
+
+#[get("/<institution_id>/attendance")]
+pub async fn attendance_report_page(institution_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let report = match attendance_report_base(institution_id, auth, &conn).await {
+        Ok(report) => report,
+        Err(e) => return e.render(),
+    };
+    Html::new().head(default_head("Attendance")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Attendance"))
+                .child(P::with_text(match report.threshold {
+                    Some(threshold) => format!(
+                        "Students whose attendance is below {}% are listed under each class.",
+                        threshold
+                    ),
+                    None => "You haven't set an attendance threshold, so students with low \
+                        attendance aren't listed. You can set one in your institution's settings."
+                        .to_string(),
+                }))
+                .child(Div::new().map(|div| {
+                    report.classes.into_iter().fold(div, |div, class| {
+                        let ClassAttendanceReport {
+                            class_name,
+                            summary,
+                            below_threshold,
+                            ..
+                        } = class;
+                        div.child(
+                            Div::new()
+                                .child(H3::new(class_name))
+                                .child(P::with_text(summary.describe()))
+                                .child(Div::new().map(|div| {
+                                    below_threshold.into_iter().fold(div, |div, student| {
+                                        div.child(P::with_text(format!(
+                                            "{}: {}",
+                                            student.username,
+                                            student.summary.describe()
+                                        )))
+                                    })
+                                })),
+                        )
+                    })
+                })),
+        ),
+    )
+}
+
+#[get("/<institution_id>/attendance")]
+pub async fn api_attendance_report(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<AttendanceReport>> {
+    Json(
+        match attendance_report_base(institution_id, auth, &conn).await {
+            Ok(report) => ApiResponse::new_ok(report),
+            Err(e) => From::from(e),
+        },
+    )
+}
@@ -11,18 +11,16 @@ use rocket::serde::json::Json;
 use rocket::{response::Redirect, FromForm};
 
 use crate::{
-    auth::AuthCookie,
+    auth::{AuthCookie, EmailVerification},
     db::Database,
+    institution::{group::cascade_enrolments, policy},
     models::{
-        institution::{
-            administrator::Administrator, student::InstitutionStudent, student_group::StudentGroup,
-            teacher::InstitutionTeacher, Institution,
-        },
+        institution::{academic::AcademicTerm, student_group::StudentGroup, Institution},
         NewClass,
     },
     schema::{
-        administrator, class, institution, institution_student, institution_teacher, student_group,
-        users,
+        academic_term, academic_year, administrator, class, institution, institution_student,
+        institution_teacher, student_group,
     },
     utils::{
         default_head,
@@ -33,7 +31,10 @@ use crate::{
     },
 };
 
-fn create_institution_class_form(student_groups: Vec<StudentGroup>) -> Form {
+fn create_institution_class_form(
+    student_groups: Vec<StudentGroup>,
+    terms: Vec<(AcademicTerm, String)>,
+) -> Form {
     Form::new()
         .child(Label::new(
             "Pick a student group to add this class as part of. If you don't want to add this
@@ -41,7 +42,7 @@ fn create_institution_class_form(student_groups: Vec<StudentGroup>) -> Form {
         ))
         .child(
             Select::new()
-                .attribute(Name::new("institution_id"))
+                .attribute(Name::new("student_group_id"))
                 .child(
                     SelectOption::new()
                         .attribute(Value::new("none"))
@@ -53,6 +54,23 @@ fn create_institution_class_form(student_groups: Vec<StudentGroup>) -> Form {
                         .text(student_group.name)
                 })),
         )
+        .child(Label::new(
+            "If this class only runs during one term, pick the term (otherwise select \"none\").",
+        ))
+        .child(
+            Select::new()
+                .attribute(Name::new("academic_term_id"))
+                .child(
+                    SelectOption::new()
+                        .attribute(Value::new("none"))
+                        .text("None"),
+                )
+                .children(terms.into_iter().map(|(term, year)| {
+                    SelectOption::new()
+                        .attribute(Value::new(term.id.to_string()))
+                        .text(format!("{} ({})", term.name, year))
+                })),
+        )
         .child(
             Input::new()
                 .apply(FormTextInputStyle)
@@ -80,38 +98,35 @@ pub async fn pick_which_institution_to_create_class_as_part_of(
     auth: AuthCookie,
 ) -> Html {
     let institutions = match conn
-        .run(
-            move |c| -> Result<Vec<Institution>, diesel::result::Error> {
-                let admin = institution::table
-                    .inner_join(administrator::table.inner_join(users::table))
-                    .filter(users::id.eq(auth.0))
-                    .select(institution::all_columns)
-                    .load::<Institution>(c)?;
-                let teacher = institution::table
-                    .filter(institution::let_teachers_create_classes.eq(true))
-                    .or_filter(institution::let_all_users_create_classes.eq(true))
-                    .inner_join(institution_teacher::table.inner_join(users::table))
-                    .select(institution::all_columns)
-                    .load::<Institution>(c)?;
-                let student = institution::table
-                    .filter(institution::let_all_users_create_classes.eq(true))
-                    .inner_join(institution_student::table.inner_join(users::table))
-                    .select(institution::all_columns)
-                    .load::<Institution>(c)?;
-                Ok(admin
-                    .into_iter()
-                    .chain(teacher.into_iter())
-                    .chain(student.into_iter())
-                    .collect())
-            },
-        )
+        .run(move |c| -> LovelaceResult<Vec<Institution>> {
+            let institutions = institution::table
+                .left_join(administrator::table)
+                .left_join(institution_teacher::table)
+                .left_join(institution_student::table)
+                .filter(
+                    administrator::user_id
+                        .eq(auth.0)
+                        .or(institution_teacher::user_id.eq(auth.0))
+                        .or(institution_student::user_id.eq(auth.0)),
+                )
+                .select(institution::all_columns)
+                .distinct()
+                .load::<Institution>(c)?;
+            // only offer the institutions which would let the user create a class
+            let mut allowed = vec![];
+            for institution in institutions {
+                match policy::can_create_class(auth.0, institution.id, c) {
+                    Ok(()) => allowed.push(institution),
+                    Err(LovelaceError::PermissionError(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(allowed)
+        })
         .await
     {
         Ok(t) => t,
-        Err(e) => {
-            error!("{:#?}", e);
-            return database_error();
-        }
+        Err(_) => return database_error(),
     };
     Html::new().head(default_head("Create a new class")).body(
         Body::new().child(
@@ -131,7 +146,9 @@ pub async fn pick_which_institution_to_create_class_as_part_of(
                 )
                 .apply(|level| {
                     if institutions.is_empty() {
-                        level.child(H1::new("You're not part of any institutions."))
+                        level.child(H1::new(
+                            "You're not part of any institutions which let you create classes.",
+                        ))
                     } else {
                         level.children(institutions.into_iter().map(|institution| {
                             A::new()
@@ -147,99 +164,38 @@ pub async fn pick_which_institution_to_create_class_as_part_of(
     )
 }
 
-async fn get_user_institution_relationship(
-    conn: &Database,
-    institution_id: i32,
-    auth: AuthCookie,
-) -> Result<
-    (
-        Institution,
-        Option<Administrator>,
-        Option<InstitutionTeacher>,
-        Option<InstitutionStudent>,
-    ),
-    diesel::result::Error,
-> {
-    conn.run(move |c| {
-        institution::table
-            .filter(institution::id.eq(institution_id))
-            .left_join(administrator::table)
-            .left_join(institution_teacher::table)
-            .left_join(institution_student::table)
-            .or_filter(administrator::user_id.eq(auth.0))
-            .or_filter(institution_teacher::user_id.eq(auth.0))
-            .or_filter(institution_student::user_id.eq(auth.0))
-            .first::<(
-                Institution,
-                Option<Administrator>,
-                Option<InstitutionTeacher>,
-                Option<InstitutionStudent>,
-            )>(c)
-    })
-    .await
-}
-
-fn check_permissions(
-    (institution, administrator, teacher, student): (
-        Institution,
-        Option<Administrator>,
-        Option<InstitutionTeacher>,
-        Option<InstitutionStudent>,
-    ),
-) -> bool {
-    // if anyone is allowed to create a class, check that the user fits in "anyone"
-    (institution.let_all_users_create_classes
-        && (administrator.is_some() || student.is_some() || teacher.is_some()))
-    // if only teachers and admins may create a class, check that the user matches this
-    || (institution.let_teachers_add_sync_tasks
-        && (administrator.is_some() || student.is_some()))
-    // otherwise check if we have an administrator
-    || administrator.is_some()
-}
-
 #[get("/<institution_id>/class/create")]
 pub async fn create_institution_class_page(
     institution_id: i32,
     auth: AuthCookie,
     conn: Database,
 ) -> Html {
-    let student_groups = match get_user_institution_relationship(&conn, institution_id, auth).await
+    let (student_groups, terms) = match conn
+        .run(move |c| {
+            policy::can_create_class(auth.0, institution_id, c)?;
+            let student_groups = student_group::table
+                .filter(student_group::institution_id.eq(institution_id))
+                .load::<StudentGroup>(c)?;
+            // terms which are already over aren't worth offering
+            let terms = academic_term::table
+                .inner_join(academic_year::table)
+                .filter(academic_year::institution_id.eq(institution_id))
+                .filter(academic_term::ends.ge(Utc::now().naive_utc().date()))
+                .order_by(academic_term::starts)
+                .select((academic_term::all_columns, academic_year::name))
+                .load::<(AcademicTerm, String)>(c)?;
+            Ok::<_, LovelaceError>((student_groups, terms))
+        })
+        .await
     {
-        Ok(res) => {
-            let cond = check_permissions(res);
-            if cond {
-                println!("COND WAS TRUE");
-                match conn
-                    .run(move |c| {
-                        institution::table
-                            .filter(institution::id.eq(institution_id))
-                            .inner_join(student_group::table)
-                            .select(student_group::all_columns)
-                            .load::<StudentGroup>(c)
-                    })
-                    .await
-                {
-                    Ok(t) => t,
-                    Err(e) => {
-                        println!("b");
-                        error!("{:#?}", e);
-                        return LovelaceError::DatabaseError.render();
-                    }
-                }
-            } else {
-                return LovelaceError::PermissionError.render();
-            }
-        }
-        Err(e) => {
-            error!("{:#?}", e);
-            return LovelaceError::DatabaseError.render();
-        }
+        Ok(t) => t,
+        Err(e) => return e.render(),
     };
     Html::new().head(default_head("Create a new class")).body(
         Body::new().child(
             Level::new()
                 .child(H1::new("Create a new class as part of this institution"))
-                .child(create_institution_class_form(student_groups)),
+                .child(create_institution_class_form(student_groups, terms)),
         ),
     )
 }
@@ -249,52 +205,82 @@ pub struct CreateClassForm {
     name: String,
     description: String,
     student_group_id: Option<i32>,
+    academic_term_id: Option<i32>,
 }
 
 async fn apply_create_institution_class(
     conn: Database,
     auth: AuthCookie,
+    verification: EmailVerification,
     data: &CreateClassForm,
     institution_id: i32,
 ) -> LovelaceResult<crate::models::Class> {
+    verification.require()?;
     let name = data.name.clone();
     let description = data.description.clone();
     let student_group_id = data.student_group_id;
-    if !get_user_institution_relationship(&conn, institution_id, auth)
-        .await
-        .map(check_permissions)
-        .unwrap_or(false)
-    {
-        return Err(LovelaceError::PermissionError);
-    }
+    let academic_term_id = data.academic_term_id;
     conn.run(move |c| {
-        diesel::insert_into(class::table)
-            .values(NewClass {
-                name: &name,
-                description: &description,
-                created: Utc::now().naive_utc(),
-                code: &nanoid!(5),
-                institution_id: Some(institution_id),
-                student_group_id,
-            })
-            .returning(class::all_columns)
-            .get_result(c)
+        c.transaction::<_, LovelaceError, _>(|| {
+            policy::can_create_class(auth.0, institution_id, c)?;
+            if let Some(student_group_id) = student_group_id {
+                let in_institution = diesel::select(diesel::dsl::exists(
+                    student_group::table
+                        .filter(student_group::id.eq(student_group_id))
+                        .filter(student_group::institution_id.eq(institution_id)),
+                ))
+                .get_result::<bool>(c)?;
+                if !in_institution {
+                    return Err(LovelaceError::PermissionError(Some(
+                        "That student group isn't part of this institution.".to_string(),
+                    )));
+                }
+            }
+            if let Some(academic_term_id) = academic_term_id {
+                let in_institution = diesel::select(diesel::dsl::exists(
+                    academic_term::table
+                        .inner_join(academic_year::table)
+                        .filter(academic_term::id.eq(academic_term_id))
+                        .filter(academic_year::institution_id.eq(institution_id)),
+                ))
+                .get_result::<bool>(c)?;
+                if !in_institution {
+                    return Err(LovelaceError::PermissionError(Some(
+                        "That term isn't one of this institution's terms.".to_string(),
+                    )));
+                }
+            }
+            let class = diesel::insert_into(class::table)
+                .values(NewClass {
+                    name: &name,
+                    description: &description,
+                    created: Utc::now().naive_utc(),
+                    code: &nanoid!(5),
+                    institution_id: Some(institution_id),
+                    student_group_id,
+                    academic_term_id,
+                })
+                .returning(class::all_columns)
+                .get_result::<crate::models::Class>(c)?;
+            // students of the group are enrolled in the class straight away
+            if let Some(student_group_id) = student_group_id {
+                cascade_enrolments(student_group_id, c)?;
+            }
+            Ok(class)
+        })
     })
     .await
-    .map_err(|e| {
-        error!("{:#?}", e);
-        LovelaceError::DatabaseError
-    })
 }
 
 #[post("/<institution_id>/class/create", data = "<data>")]
 pub async fn html_create_institution_class(
     conn: Database,
     auth: AuthCookie,
+    verification: EmailVerification,
     data: rocket::form::Form<CreateClassForm>,
     institution_id: i32,
 ) -> HtmlOrRedirect {
-    match apply_create_institution_class(conn, auth, &data, institution_id).await {
+    match apply_create_institution_class(conn, auth, verification, &data, institution_id).await {
         Ok(class) => HtmlOrRedirect::Redirect(Redirect::to(format!("/class/{}", class.id))),
         Err(e) => HtmlOrRedirect::Html(e.render()),
     }
@@ -304,11 +290,13 @@ pub async fn html_create_institution_class(
 pub async fn api_create_institution_class(
     conn: Database,
     auth: AuthCookie,
+    verification: EmailVerification,
     data: Json<CreateClassForm>,
     institution_id: i32,
 ) -> Json<ApiResponse<crate::models::Class>> {
     Json(
-        match apply_create_institution_class(conn, auth, &data, institution_id).await {
+        match apply_create_institution_class(conn, auth, verification, &data, institution_id).await
+        {
             Ok(class) => ApiResponse::new_ok(class),
             Err(e) => From::from(e),
         },
@@ -318,13 +306,16 @@ pub async fn api_create_institution_class(
 #[cfg(test)]
 pub mod test {
     use diesel::prelude::*;
-    use rocket::http::ContentType;
+    use rocket::http::{ContentType, Status};
 
     use crate::{
         db::Database,
-        institution::test_ctx::{setup_env, ADMIN_PASSWORD, ADMIN_USERNAME, STUDENT_GROUP_NAME},
-        schema::class,
-        utils::{client, login_user},
+        institution::test_ctx::{
+            setup_env, ADMIN_PASSWORD, ADMIN_USERNAME, STUDENT_EMAIL, STUDENT_GROUP_NAME,
+            STUDENT_PASSWORD, TEACHER_EMAIL, TEACHER_PASSWORD,
+        },
+        schema::{class, institution},
+        utils::{client, login_user, logout},
     };
 
     const CLASS_NAME: &str = "class-name";
@@ -382,4 +373,57 @@ pub mod test {
             assert_eq!(res.name, CLASS_NAME);
         }
     }
+
+    #[rocket::async_test]
+    async fn test_class_creation_policy() {
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        let create = |name: &'static str| {
+            client
+                .post(format!("/institution/{}/class/create", institution_id))
+                .header(ContentType::Form)
+                .body(format!("name={}&description={}", name, CLASS_DESCRIPTION))
+                .dispatch()
+        };
+
+        // by default, students can't create classes but teachers can
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+        let listing = client.get("/institution/class/create").dispatch().await;
+        assert!(!listing
+            .into_string()
+            .await
+            .unwrap()
+            .contains(&format!("/institution/{}", institution_id)));
+        let res = create("student-class").await;
+        assert_eq!(res.status(), Status::Forbidden);
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("only lets its teachers and administrators create classes"));
+        logout(&client).await;
+        login_user(TEACHER_EMAIL, TEACHER_PASSWORD, &client).await;
+        assert_eq!(create("teacher-class").await.status().code, 303);
+        logout(&client).await;
+
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::update(institution::table.find(institution_id))
+                    .set((
+                        institution::let_teachers_create_classes.eq(false),
+                        institution::let_all_users_create_classes.eq(true),
+                    ))
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+        assert_eq!(create("student-class").await.status().code, 303);
+    }
 }
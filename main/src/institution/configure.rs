@@ -1,18 +1,22 @@
 use crate::utils::form::{FormErrorMsg, FormProducer};
 use diesel::prelude::*;
-use malvolio::prelude::*;
+use malvolio::{prelude::*, text::Text};
 use portia::{levels::Level, render::Render};
 use rocket::serde::json::Json;
 use rocket::FromForm;
 
 use crate::{
     auth::AuthCookie,
-    db::Database,
-    models::institution::{Institution, UpdateInstitution},
+    db::{Database, DatabaseConnection},
+    models::institution::{
+        oidc::{InstitutionOidc, NewInstitutionOidc},
+        Institution, UpdateInstitution,
+    },
     utils::{default_head, error::LovelaceResult, json_response::ApiResponse},
 };
 use crate::{
-    schema::{administrator, institution, users},
+    institution::is_administrator,
+    schema::{administrator, institution, institution_oidc, users},
     utils::error::LovelaceError,
 };
 
@@ -21,6 +25,21 @@ pub struct ConfigureInstitutionForm {
     name: Option<String>,
     domain: Option<String>,
     enforce_same_domain: Option<bool>,
+    let_teachers_create_classes: Option<bool>,
+    /// Lets students create classes too.
+    let_all_users_create_classes: Option<bool>,
+    let_teachers_add_sync_tasks: Option<bool>,
+    require_two_factor: Option<bool>,
+    /// The percentage below which a student's attendance in a class has to drop for their
+    /// teachers and the institution's administrators to be notified. Set this to 0 to turn these
+    /// notifications off.
+    attendance_threshold: Option<i32>,
+    /// The OpenID Connect issuer used for single sign-on. Set this to an empty string to turn
+    /// single sign-on off.
+    oidc_issuer: Option<String>,
+    oidc_client_id: Option<String>,
+    /// If this is left empty when updating an existing issuer, the current secret is kept.
+    oidc_client_secret: Option<String>,
 }
 
 async fn apply_configure_institution(
@@ -29,38 +48,64 @@ async fn apply_configure_institution(
     auth: AuthCookie,
     institution_id: i32,
 ) -> LovelaceResult<Institution> {
-    let is_admin = conn
-        .run(move |c| {
-            diesel::select(diesel::dsl::exists(
-                institution::table
-                    .filter(institution::id.eq(institution_id))
-                    .inner_join(administrator::table.inner_join(users::table))
-                    .filter(users::id.eq(auth.0)),
-            ))
-            .get_result::<bool>(c)
-        })
-        .await
-        .map_err(|e| {
-            error!("{:#?}", e);
-            LovelaceError::DatabaseError
-        })?;
-    if !is_admin {
-        return Err(LovelaceError::PermissionError);
-    }
+    conn.run(move |c| is_administrator(institution_id, auth, c))
+        .await?;
     let name = data.name.clone();
     let domain = data.domain.clone();
     let enforce_same_domain = data.enforce_same_domain;
+    let let_teachers_create_classes = data.let_teachers_create_classes;
+    let let_all_users_create_classes = data.let_all_users_create_classes;
+    let let_teachers_add_sync_tasks = data.let_teachers_add_sync_tasks;
+    let require_two_factor = data.require_two_factor;
+    let attendance_threshold = data
+        .attendance_threshold
+        .map(|threshold| Some(threshold.min(100)).filter(|threshold| *threshold > 0));
+    let oidc_issuer = data.oidc_issuer.clone();
+    let oidc_client_id = data.oidc_client_id.clone();
+    let oidc_client_secret = data.oidc_client_secret.clone();
     let res = conn
         .run(move |c| {
-            diesel::update(institution::table.filter(institution::id.eq(institution_id)))
-                .set(UpdateInstitution {
+            c.transaction::<_, diesel::result::Error, _>(|| {
+                let update = UpdateInstitution {
                     name,
                     domain,
                     created: None,
                     enforce_same_domain,
-                })
-                .returning(institution::all_columns)
-                .get_result::<Institution>(c)
+                    let_teachers_create_classes,
+                    let_all_users_create_classes,
+                    let_teachers_add_sync_tasks,
+                    require_two_factor,
+                    attendance_threshold,
+                };
+                // Diesel refuses to run an update which doesn't change anything, which is the case
+                // if only the single sign-on settings are being changed.
+                let institution = if update.name.is_none()
+                    && update.domain.is_none()
+                    && update.enforce_same_domain.is_none()
+                    && update.let_teachers_create_classes.is_none()
+                    && update.let_all_users_create_classes.is_none()
+                    && update.let_teachers_add_sync_tasks.is_none()
+                    && update.require_two_factor.is_none()
+                    && update.attendance_threshold.is_none()
+                {
+                    institution::table
+                        .find(institution_id)
+                        .first::<Institution>(c)?
+                } else {
+                    diesel::update(institution::table.filter(institution::id.eq(institution_id)))
+                        .set(update)
+                        .returning(institution::all_columns)
+                        .get_result::<Institution>(c)?
+                };
+                configure_oidc(
+                    c,
+                    institution_id,
+                    oidc_issuer,
+                    oidc_client_id,
+                    oidc_client_secret,
+                )?;
+                Ok(institution)
+            })
         })
         .await
         .map_err(|e| {
@@ -70,11 +115,85 @@ async fn apply_configure_institution(
     Ok(res)
 }
 
-struct ConfigureInstitutionFormProducer(String, String, bool);
+/// Sets up (or turns off) single sign-on for the institution. Nothing happens unless an issuer is
+/// provided; an empty issuer removes the configuration.
+fn configure_oidc(
+    c: &DatabaseConnection,
+    institution_id: i32,
+    issuer: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> QueryResult<()> {
+    let issuer = match issuer.map(|issuer| issuer.trim().to_string()) {
+        Some(issuer) => issuer,
+        None => return Ok(()),
+    };
+    if issuer.is_empty() {
+        diesel::delete(
+            institution_oidc::table.filter(institution_oidc::institution_id.eq(institution_id)),
+        )
+        .execute(c)?;
+        return Ok(());
+    }
+    let existing = institution_oidc::table
+        .filter(institution_oidc::institution_id.eq(institution_id))
+        .first::<InstitutionOidc>(c)
+        .optional()?;
+    let client_id = client_id
+        .filter(|id| !id.trim().is_empty())
+        .or_else(|| existing.as_ref().map(|config| config.client_id.clone()))
+        .unwrap_or_default();
+    // a client without a secret is a public client, which PKCE allows
+    let client_secret = client_secret
+        .filter(|secret| !secret.is_empty())
+        .or_else(|| existing.map(|config| config.client_secret))
+        .unwrap_or_default();
+    let config = NewInstitutionOidc {
+        institution_id,
+        issuer: &issuer,
+        client_id: client_id.trim(),
+        client_secret: &client_secret,
+    };
+    diesel::insert_into(institution_oidc::table)
+        .values(&config)
+        .on_conflict(institution_oidc::institution_id)
+        .do_update()
+        .set((
+            institution_oidc::issuer.eq(config.issuer),
+            institution_oidc::client_id.eq(config.client_id),
+            institution_oidc::client_secret.eq(config.client_secret),
+        ))
+        .execute(c)?;
+    Ok(())
+}
+
+struct ConfigureInstitutionFormProducer {
+    name: String,
+    domain: String,
+    enforce_same_domain: bool,
+    let_teachers_create_classes: bool,
+    let_all_users_create_classes: bool,
+    let_teachers_add_sync_tasks: bool,
+    require_two_factor: bool,
+    attendance_threshold: Option<i32>,
+    oidc_issuer: String,
+    oidc_client_id: String,
+}
 
 impl FormProducer for ConfigureInstitutionFormProducer {
     fn produce(self) -> Form {
-        let Self(name, domain, enforce_same_domain) = self;
+        let Self {
+            name,
+            domain,
+            enforce_same_domain,
+            let_teachers_create_classes,
+            let_all_users_create_classes,
+            let_teachers_add_sync_tasks,
+            require_two_factor,
+            attendance_threshold,
+            oidc_issuer,
+            oidc_client_id,
+        } = self;
         Form::new()
             .child(
                 Input::new()
@@ -91,11 +210,60 @@ impl FormProducer for ConfigureInstitutionFormProducer {
                     .attribute(Name::new("domain"))
                     .attribute(Value::new(domain)),
             )
+            .child(checkbox("enforce_same_domain", enforce_same_domain))
+            .child(Label::new("Let teachers create classes."))
+            .child(checkbox(
+                "let_teachers_create_classes",
+                let_teachers_create_classes,
+            ))
+            .child(Label::new(
+                "Let everybody (including students) create classes.",
+            ))
+            .child(checkbox(
+                "let_all_users_create_classes",
+                let_all_users_create_classes,
+            ))
+            .child(Label::new(
+                "Let teachers add synchronous tasks to their classes.",
+            ))
+            .child(checkbox(
+                "let_teachers_add_sync_tasks",
+                let_teachers_add_sync_tasks,
+            ))
+            .child(checkbox("require_two_factor", require_two_factor))
+            .child(Label::new(
+                "Notify teachers and administrators when a student's attendance in a class drops \
+                below this percentage (0 turns this off).",
+            ))
+            .child(
+                Input::new()
+                    .attribute(Type::Text)
+                    .attribute(Name::new("attendance_threshold"))
+                    .attribute(Value::new(attendance_threshold.unwrap_or(0).to_string())),
+            )
+            .child(
+                Input::new()
+                    .attribute(Type::Text)
+                    .attribute(Name::new("oidc_issuer"))
+                    .attribute(Placeholder::new(
+                        "The OpenID Connect issuer to use for single sign-on (if any).",
+                    ))
+                    .attribute(Value::new(oidc_issuer)),
+            )
             .child(
                 Input::new()
-                    .attribute(Name::new("enforce_same_domain"))
-                    .attribute(Type::Checkbox)
-                    .attribute(Value::new(enforce_same_domain.to_string())),
+                    .attribute(Type::Text)
+                    .attribute(Name::new("oidc_client_id"))
+                    .attribute(Placeholder::new("The client ID issued to Lovelace."))
+                    .attribute(Value::new(oidc_client_id)),
+            )
+            .child(
+                Input::new()
+                    .attribute(Type::Password)
+                    .attribute(Name::new("oidc_client_secret"))
+                    .attribute(Placeholder::new(
+                        "The client secret (leave blank to keep the current one).",
+                    )),
             )
     }
 }
@@ -106,14 +274,19 @@ pub async fn configure_institution_page(
     conn: Database,
     auth: AuthCookie,
 ) -> Html {
-    let institution = match conn
+    let (institution, oidc) = match conn
         .run(move |c| {
-            institution::table
+            let institution = institution::table
                 .filter(institution::id.eq(institution_id))
                 .inner_join(administrator::table.inner_join(users::table))
                 .filter(users::id.eq(auth.0))
                 .select(institution::all_columns)
-                .first::<Institution>(c)
+                .first::<Institution>(c)?;
+            let oidc = institution_oidc::table
+                .filter(institution_oidc::institution_id.eq(institution_id))
+                .first::<InstitutionOidc>(c)
+                .optional()?;
+            Ok::<_, diesel::result::Error>((institution, oidc))
         })
         .await
     {
@@ -126,11 +299,21 @@ pub async fn configure_institution_page(
             Body::new()
                 .child(Level::new().child(H1::new("Configure new institution")))
                 .child(
-                    ConfigureInstitutionFormProducer(
-                        institution.name,
-                        institution.domain,
-                        institution.enforce_same_domain,
-                    )
+                    ConfigureInstitutionFormProducer {
+                        name: institution.name,
+                        domain: institution.domain,
+                        enforce_same_domain: institution.enforce_same_domain,
+                        let_teachers_create_classes: institution.let_teachers_create_classes,
+                        let_all_users_create_classes: institution.let_all_users_create_classes,
+                        let_teachers_add_sync_tasks: institution.let_teachers_add_sync_tasks,
+                        require_two_factor: institution.require_two_factor,
+                        attendance_threshold: institution.attendance_threshold,
+                        oidc_issuer: oidc
+                            .as_ref()
+                            .map(|oidc| oidc.issuer.clone())
+                            .unwrap_or_default(),
+                        oidc_client_id: oidc.map(|oidc| oidc.client_id).unwrap_or_default(),
+                    }
                     .produce(),
                 ),
         )
@@ -149,10 +332,47 @@ impl Render<Div> for Institution {
                 does not belong to your institution's domain may join (given that they have an \
                 invite)."
             }))
+            .child(P::with_text(if self.let_all_users_create_classes {
+                "Creating classes: everybody (including students) can create classes."
+            } else if self.let_teachers_create_classes {
+                "Creating classes: teachers and administrators can create classes."
+            } else {
+                "Creating classes: only administrators can create classes."
+            }))
+            .child(P::with_text(if self.let_teachers_add_sync_tasks {
+                "Synchronous tasks: teachers can add synchronous tasks to their classes."
+            } else {
+                "Synchronous tasks: only administrators can add synchronous tasks to classes."
+            }))
+            .child(P::with_text(if self.require_two_factor {
+                "Two-factor authentication: required. Administrators and teachers must set up \
+                two-factor authentication before they can log in."
+            } else {
+                "Two-factor authentication: optional."
+            }))
+            .child(P::with_text(match self.attendance_threshold {
+                Some(threshold) => format!(
+                    "Attendance: teachers and administrators are notified when a student's \
+                    attendance in a class drops below {}%.",
+                    threshold
+                ),
+                None => "Attendance: nobody is notified about students' attendance.".to_string(),
+            }))
             .into_div()
     }
 }
 
+/// A checkbox which submits `true` when ticked. Browsers leave unticked boxes out of the form
+/// entirely, so [`html_configure_institution`] reads a missing flag as `false`. (This is written
+/// out by hand because malvolio doesn't support the `checked` attribute.)
+fn checkbox(name: &'static str, checked: bool) -> Text {
+    Text::new_unchecked(format!(
+        "<input type=\"checkbox\" name=\"{}\" value=\"true\"{}/>",
+        name,
+        if checked { " checked" } else { "" }
+    ))
+}
+
 #[post("/<institution_id>/configure", data = "<form>")]
 pub async fn html_configure_institution(
     institution_id: i32,
@@ -160,6 +380,16 @@ pub async fn html_configure_institution(
     auth: AuthCookie,
     form: rocket::form::Form<ConfigureInstitutionForm>,
 ) -> Html {
+    let mut form = form.into_inner();
+    for flag in [
+        &mut form.enforce_same_domain,
+        &mut form.let_teachers_create_classes,
+        &mut form.let_all_users_create_classes,
+        &mut form.let_teachers_add_sync_tasks,
+        &mut form.require_two_factor,
+    ] {
+        flag.get_or_insert(false);
+    }
     match apply_configure_institution(conn, &form, auth, institution_id).await {
         Ok(institution) => Html::new().head(default_head("Succesfully updated")).body(
             Body::new().child(
@@ -170,11 +400,18 @@ pub async fn html_configure_institution(
         ),
         Err(e) => FormErrorMsg(
             e,
-            ConfigureInstitutionFormProducer(
-                form.name.clone().unwrap_or_else(|| "".to_string()),
-                form.domain.clone().unwrap_or_else(|| "".to_string()),
-                form.enforce_same_domain.unwrap_or(false),
-            ),
+            ConfigureInstitutionFormProducer {
+                name: form.name.clone().unwrap_or_default(),
+                domain: form.domain.clone().unwrap_or_default(),
+                enforce_same_domain: form.enforce_same_domain.unwrap_or(false),
+                let_teachers_create_classes: form.let_teachers_create_classes.unwrap_or(false),
+                let_all_users_create_classes: form.let_all_users_create_classes.unwrap_or(false),
+                let_teachers_add_sync_tasks: form.let_teachers_add_sync_tasks.unwrap_or(false),
+                require_two_factor: form.require_two_factor.unwrap_or(false),
+                attendance_threshold: form.attendance_threshold,
+                oidc_issuer: form.oidc_issuer.clone().unwrap_or_default(),
+                oidc_client_id: form.oidc_client_id.clone().unwrap_or_default(),
+            },
         )
         .render(),
     }
@@ -197,7 +434,6 @@ pub async fn api_configure_institution(
 
 #[cfg(test)]
 mod test_configure_institution {
-    use bcrypt::DEFAULT_COST;
     use chrono::Utc;
     use diesel::prelude::*;
     use rocket::http::ContentType;
@@ -205,8 +441,11 @@ mod test_configure_institution {
     use crate::{
         db::Database,
         institution::test_ctx::{ADMIN_EMAIL, ADMIN_PASSWORD, NAME, TIMEZONE, WEBSITE},
-        models::{institution::Institution, NewUser},
-        schema::{institution, users},
+        models::{
+            institution::{oidc::InstitutionOidc, Institution},
+            NewUser,
+        },
+        schema::{institution, institution_oidc, users},
         utils::{client, login_user},
     };
 
@@ -247,6 +486,113 @@ mod test_configure_institution {
         assert_eq!(institution.name, NAME);
     }
 
+    #[rocket::async_test]
+    async fn test_unticked_checkboxes_turn_flags_off() {
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let flags = |client| async move {
+            Database::get_one(client)
+                .await
+                .unwrap()
+                .run(move |c| {
+                    institution::table
+                        .filter(institution::id.eq(institution_id))
+                        .select((
+                            institution::let_teachers_create_classes,
+                            institution::require_two_factor,
+                        ))
+                        .get_result::<(bool, bool)>(c)
+                })
+                .await
+                .unwrap()
+        };
+        let res = client
+            .post(format!("/institution/{}/configure", institution_id))
+            .header(ContentType::Form)
+            .body("let_teachers_create_classes=true&require_two_factor=true")
+            .dispatch()
+            .await;
+        assert!(res.into_string().await.unwrap().contains("updated"));
+        assert_eq!(flags(client.rocket()).await, (true, true));
+        let page = client
+            .get(format!("/institution/{}/configure", institution_id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(page.contains("checked"));
+        // the browser leaves out the box that was unticked
+        let res = client
+            .post(format!("/institution/{}/configure", institution_id))
+            .header(ContentType::Form)
+            .body("let_teachers_create_classes=true")
+            .dispatch()
+            .await;
+        assert!(res.into_string().await.unwrap().contains("updated"));
+        assert_eq!(flags(client.rocket()).await, (true, false));
+    }
+
+    #[rocket::async_test]
+    async fn test_admin_can_configure_single_sign_on() {
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let configure = |body: &'static str| {
+            client
+                .post(format!("/institution/{}/configure", institution_id))
+                .header(ContentType::Form)
+                .body(body)
+                .dispatch()
+        };
+        let get_config = || async {
+            Database::get_one(client.rocket())
+                .await
+                .unwrap()
+                .run(move |c| {
+                    institution_oidc::table
+                        .filter(institution_oidc::institution_id.eq(institution_id))
+                        .first::<InstitutionOidc>(c)
+                        .optional()
+                })
+                .await
+                .unwrap()
+        };
+
+        configure(
+            "oidc_issuer=https://accounts.example.com&oidc_client_id=lovelace\
+            &oidc_client_secret=secret",
+        )
+        .await;
+        let config = get_config()
+            .await
+            .expect("single sign-on was not configured");
+        assert_eq!(config.issuer, "https://accounts.example.com");
+        assert_eq!(config.client_id, "lovelace");
+        assert_eq!(config.client_secret, "secret");
+
+        // leaving the secret blank keeps the existing one
+        configure(
+            "oidc_issuer=https://login.example.com&oidc_client_id=lovelace&oidc_client_secret=",
+        )
+        .await;
+        let config = get_config().await.unwrap();
+        assert_eq!(config.issuer, "https://login.example.com");
+        assert_eq!(config.client_secret, "secret");
+
+        configure("oidc_issuer=").await;
+        assert!(get_config().await.is_none());
+    }
+
     #[rocket::async_test]
     async fn test_not_admin_cannot_edit() {
         let client = client().await;
@@ -263,7 +609,7 @@ mod test_configure_institution {
                     .values(NewUser {
                         username: "notadmin",
                         email: "notadmin@example.com",
-                        password: &bcrypt::hash("notadminpassW0RD", DEFAULT_COST).unwrap(),
+                        password: &crate::auth::hash::hash_password("notadminpassW0RD").unwrap(),
                         created: Utc::now().naive_utc(),
                         email_verified: true,
                         timezone: TIMEZONE,
@@ -24,7 +24,7 @@ pub async fn delete_institution_page(
         .run(move |c| has_permission(institution_id, auth, c))
         .await;
     if !has_permission {
-        return LovelaceError::PermissionError.render();
+        return LovelaceError::PermissionError(None).render();
     }
     Html::new()
         .head(default_head("Delete this institution"))
@@ -80,7 +80,7 @@ async fn apply_delete_institution(
                     LovelaceError::DatabaseError
                 })
         } else {
-            Err(LovelaceError::PermissionError)
+            Err(LovelaceError::PermissionError(None))
         }
     })
     .await
@@ -137,7 +137,6 @@ pub async fn api_delete_institution(
 
 #[cfg(test)]
 mod test_delete_institution {
-    use bcrypt::DEFAULT_COST;
     use chrono::Utc;
     use diesel::prelude::*;
 
@@ -186,7 +185,7 @@ mod test_delete_institution {
                     .values(NewUser {
                         username: "notadmin",
                         email: "notadmin@example.com",
-                        password: &bcrypt::hash("notadminpassW0RD", DEFAULT_COST).unwrap(),
+                        password: &crate::auth::hash::hash_password("notadminpassW0RD").unwrap(),
                         created: Utc::now().naive_utc(),
                         email_verified: true,
                         timezone: TIMEZONE,
@@ -0,0 +1,304 @@
+//! Lets institution administrators export the data of members of their institution (e.g. to
+//! answer a subject access request on their behalf).
+
+use malvolio::prelude::*;
+use portia::{levels::Level, render::Render};
+use rocket::serde::json::Json;
+
+use super::{is_administrator, is_member};
+use crate::{
+    auth::AuthCookie,
+    db::Database,
+    export::request_export,
+    models::export::DataExport,
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        json_response::ApiResponse,
+    },
+};
+
+async fn export_member_base(
+    institution_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> LovelaceResult<DataExport> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        // administrators may only export the data of people in their institution
+        if !is_member(institution_id, user_id, c)? {
+            return Err(LovelaceError::PermissionError(None));
+        }
+        Ok(())
+    })
+    .await?;
+    request_export(user_id, auth.0, Some(institution_id), conn).await
+}
+
+#[post("/<institution_id>/members/<user_id>/export")]
+pub async fn html_export_member(
+    institution_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    match export_member_base(institution_id, user_id, auth, conn).await {
+        Ok(_) => Html::new().head(default_head("Export requested")).body(
+            Body::new().child(
+                Level::new()
+                    .child(H1::new("Export requested"))
+                    .child(P::with_text(
+                        "We're putting together a copy of this member's data. We'll send you a \
+                        notification when it's ready to download (and we've let them know that \
+                        you asked for it).",
+                    ))
+                    .child(
+                        A::new()
+                            .attribute(Href::new("/export"))
+                            .text("View your exports"),
+                    ),
+            ),
+        ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<institution_id>/members/<user_id>/export")]
+pub async fn api_export_member(
+    institution_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<DataExport>> {
+    Json(
+        match export_member_base(institution_id, user_id, auth, conn).await {
+            Ok(export) => ApiResponse::new_ok(export),
+            Err(e) => From::from(e),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test_institution_export {
+    use chrono::Utc;
+    use diesel::prelude::*;
+    use rocket::http::Status;
+
+    use crate::{
+        db::Database,
+        export::test_export::wait_for_export,
+        institution::test_ctx::{
+            setup_env, ADMIN_EMAIL, ADMIN_PASSWORD, STUDENT_USERNAME, TEACHER_EMAIL,
+            TEACHER_PASSWORD,
+        },
+        models::{
+            calendar::NewStudyPreferences,
+            export::NewDataExport,
+            institution::{student::NewInstitutionStudent, NewInstitution},
+            NewClass, NewClassMessage, NewClassStudent,
+        },
+        schema::{
+            class, class_message, class_student, data_export, institution, institution_student,
+            notifications, study_preferences,
+        },
+        utils::{client, login_user, logout},
+    };
+
+    #[rocket::async_test]
+    async fn test_admin_can_export_member_data() {
+        let client = client().await;
+        let (admin_id, _, student_id, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+
+        // teachers can't export other people's data
+        login_user(TEACHER_EMAIL, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .post(format!(
+                "/institution/{}/members/{}/export",
+                institution_id, student_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        logout(&client).await;
+
+        // the student's own export (which is still being built) isn't handed to the administrator
+        let own_export = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::insert_into(data_export::table)
+                    .values(NewDataExport {
+                        user_id: student_id,
+                        requested_by: Some(student_id),
+                        created: Utc::now().naive_utc(),
+                        institution_id: None,
+                    })
+                    .returning(data_export::id)
+                    .get_result::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let res = client
+            .post(format!(
+                "/api/institution/{}/members/{}/export",
+                institution_id, student_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let res =
+            serde_json::from_str::<serde_json::Value>(&res.into_string().await.unwrap()).unwrap();
+        assert_ne!(res["data"]["id"], own_export);
+        assert_eq!(res["data"]["requested_by"], admin_id);
+        let export_id = wait_for_export(&client).await;
+        let page = client
+            .get(format!("/export/{}", export_id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(page.contains(STUDENT_USERNAME));
+
+        // the member is told that their data has been exported
+        let told = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::select(diesel::dsl::exists(
+                    notifications::table
+                        .filter(notifications::user_id.eq(student_id))
+                        .filter(notifications::title.eq("Data export requested")),
+                ))
+                .get_result::<bool>(c)
+                .unwrap()
+            })
+            .await;
+        assert!(told);
+    }
+
+    #[rocket::async_test]
+    async fn test_admin_export_leaves_out_other_institutions() {
+        let client = client().await;
+        let (_, _, student_id, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        let (own_class, other_class) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                let other_institution = diesel::insert_into(institution::table)
+                    .values(NewInstitution {
+                        name: "Some other institution",
+                        domain: "https://other.example.com",
+                        created: Utc::now().naive_utc(),
+                        enforce_same_domain: false,
+                        let_teachers_create_classes: true,
+                        let_all_users_create_classes: false,
+                        let_teachers_add_sync_tasks: true,
+                        require_two_factor: false,
+                        attendance_threshold: None,
+                    })
+                    .returning(institution::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                diesel::insert_into(institution_student::table)
+                    .values(NewInstitutionStudent {
+                        user_id: student_id,
+                        institution_id: other_institution,
+                    })
+                    .execute(c)
+                    .unwrap();
+                let mut classes = vec![];
+                for (institution_id, code, title) in [
+                    (institution_id, "own-class", "own message"),
+                    (other_institution, "other-class", "other message"),
+                ] {
+                    let class_id = diesel::insert_into(class::table)
+                        .values(NewClass::new(
+                            code,
+                            "",
+                            Utc::now().naive_utc(),
+                            code,
+                            Some(institution_id),
+                            None,
+                        ))
+                        .returning(class::id)
+                        .get_result::<i32>(c)
+                        .unwrap();
+                    diesel::insert_into(class_student::table)
+                        .values(NewClassStudent {
+                            user_id: student_id,
+                            class_id,
+                        })
+                        .execute(c)
+                        .unwrap();
+                    diesel::insert_into(class_message::table)
+                        .values(NewClassMessage {
+                            title,
+                            contents: "",
+                            created_at: Utc::now().naive_utc(),
+                            user_id: student_id,
+                            class_id,
+                            edited: false,
+                        })
+                        .execute(c)
+                        .unwrap();
+                    classes.push(class_id);
+                }
+                diesel::insert_into(study_preferences::table)
+                    .values(NewStudyPreferences {
+                        user_id: student_id,
+                        max_minutes_per_day: 150,
+                        buffer_minutes: 10,
+                        break_every_minutes: None,
+                        break_minutes: 5,
+                    })
+                    .execute(c)
+                    .unwrap();
+                (classes[0], classes[1])
+            })
+            .await;
+
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let res = client
+            .post(format!(
+                "/api/institution/{}/members/{}/export",
+                institution_id, student_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let export_id = wait_for_export(&client).await;
+        let res = client
+            .get(format!("/api/export/{}", export_id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let data = &serde_json::from_str::<serde_json::Value>(&res).unwrap()["data"];
+        assert_eq!(data["profile"]["username"], STUDENT_USERNAME);
+        let institutions = data["institutions"].as_array().unwrap();
+        assert_eq!(institutions.len(), 1);
+        assert_eq!(institutions[0]["id"], institution_id);
+        let classes = data["classes"].as_array().unwrap();
+        assert_eq!(classes.len(), 1);
+        assert_eq!(classes[0]["id"], own_class);
+        assert!(!res.contains("other message"));
+        assert!(!res.contains(&format!("\"class_id\":{}", other_class)));
+        assert_eq!(data["messages"][0]["title"], "own message");
+        // data which belongs to the student (rather than to any institution) is left out too
+        assert!(data["study_preferences"].is_null());
+        assert!(data["notifications"].as_array().unwrap().is_empty());
+    }
+}
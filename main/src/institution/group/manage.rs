@@ -0,0 +1,925 @@
+//! Creating, editing, nesting, moving and deleting student groups.
+
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::levels::Level;
+use rocket::{response::Redirect, serde::json::Json};
+
+use super::{
+    cascade_enrolments, descendants, find_group, group_url,
+    members::{add_student_form, invite_teacher_form, remove_member_form},
+    GroupError,
+};
+use crate::{
+    auth::AuthCookie,
+    db::{Database, DatabaseConnection},
+    institution::is_administrator,
+    models::institution::student_group::{NewStudentGroup, StudentGroup, UpdateStudentGroup},
+    schema::{class, student_group, student_group_student, student_group_teacher, users},
+    utils::{default_head, html_or_redirect::HtmlOrRedirect, json_response::ApiResponse},
+};
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct CreateGroupForm {
+    name: String,
+    description: String,
+    /// An optional code which identifies the group (e.g. in a management information system).
+    code: Option<String>,
+    /// The group to nest this group inside (if any).
+    parent_group: Option<i32>,
+}
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct EditGroupForm {
+    name: String,
+    description: String,
+    code: Option<String>,
+}
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct MoveGroupForm {
+    /// The group to move this group inside; if this is not provided the group is moved to the top
+    /// level.
+    parent_group: Option<i32>,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone)]
+pub struct GroupMember {
+    pub user_id: i32,
+    pub username: String,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone)]
+pub struct GroupClass {
+    pub class_id: i32,
+    pub name: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GroupDetails {
+    pub group: StudentGroup,
+    /// The groups nested directly inside this one.
+    pub subgroups: Vec<StudentGroup>,
+    pub students: Vec<GroupMember>,
+    pub teachers: Vec<GroupMember>,
+    pub classes: Vec<GroupClass>,
+}
+
+/// Codes are optional, so an empty one is treated as not having one. Codes have to be unique.
+fn check_code(
+    code: &Option<String>,
+    group_id: Option<i32>,
+    c: &DatabaseConnection,
+) -> Result<Option<String>, GroupError> {
+    let code = match code.as_deref().map(str::trim) {
+        Some(code) if !code.is_empty() => code.to_string(),
+        _ => return Ok(None),
+    };
+    let taken = diesel::select(diesel::dsl::exists(
+        student_group::table
+            .filter(student_group::code.eq(&code))
+            .filter(student_group::id.ne(group_id.unwrap_or(-1))),
+    ))
+    .get_result::<bool>(c)?;
+    if taken {
+        Err(GroupError::CodeTaken)
+    } else {
+        Ok(Some(code))
+    }
+}
+
+/// Checks that the group can be nested inside the parent.
+fn check_parent(
+    institution_id: i32,
+    parent_group: Option<i32>,
+    group_id: Option<i32>,
+    c: &DatabaseConnection,
+) -> Result<(), GroupError> {
+    let parent_group = match parent_group {
+        Some(parent_group) => parent_group,
+        None => return Ok(()),
+    };
+    find_group(institution_id, parent_group, c).map_err(|e| match e {
+        GroupError::NotFound => GroupError::InvalidParent,
+        e => e,
+    })?;
+    if let Some(group_id) = group_id {
+        if descendants(group_id, c)?.contains(&parent_group) {
+            return Err(GroupError::InvalidParent);
+        }
+    }
+    Ok(())
+}
+
+async fn list_groups_base(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<Vec<StudentGroup>, GroupError> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        Ok(student_group::table
+            .filter(student_group::institution_id.eq(institution_id))
+            .order_by(student_group::name)
+            .load::<StudentGroup>(c)?)
+    })
+    .await
+}
+
+/// Renders the groups as a tree (with the groups nested inside each group shown underneath it).
+fn group_tree(
+    institution_id: i32,
+    parent_group: Option<i32>,
+    children: &HashMap<Option<i32>, Vec<StudentGroup>>,
+) -> Div {
+    Div::new().map(|div| match children.get(&parent_group) {
+        Some(groups) => groups.iter().fold(div, |div, group| {
+            div.child(
+                A::new()
+                    .attribute(Href::new(group_url(institution_id, group.id)))
+                    .text(group.name.clone()),
+            )
+            .child(group_tree(institution_id, Some(group.id), children))
+        }),
+        None => div,
+    })
+}
+
+/// A dropdown to pick a group from. The first option is picked by default.
+fn group_select(first: SelectOption, groups: &[StudentGroup]) -> Select {
+    Select::new()
+        .attribute(Name::new("parent_group"))
+        .child(first)
+        .children(groups.iter().map(|group| {
+            SelectOption::new()
+                .attribute(Value::new(group.id.to_string()))
+                .text(group.name.clone())
+        }))
+}
+
+fn create_group_form(institution_id: i32, groups: &[StudentGroup]) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "/institution/{}/groups",
+            institution_id
+        )))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("name"))
+                .attribute(Placeholder::new("The group's name")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Textarea)
+                .attribute(Name::new("description"))
+                .attribute(Placeholder::new("A description of the group")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("code"))
+                .attribute(Placeholder::new("A code for the group (optional)")),
+        )
+        .child(Label::new("Nest this group inside"))
+        .child(group_select(
+            SelectOption::new()
+                .attribute(Value::new(""))
+                .text("(No other group)"),
+            groups,
+        ))
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Create group")),
+        )
+}
+
+#[get("/<institution_id>/groups")]
+pub async fn groups_page(institution_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let groups = match list_groups_base(institution_id, auth, &conn).await {
+        Ok(groups) => groups,
+        Err(e) => return e.render("Could not load student groups"),
+    };
+    let mut children = HashMap::<Option<i32>, Vec<StudentGroup>>::new();
+    for group in groups.iter() {
+        children
+            .entry(group.parent_group)
+            .or_default()
+            .push(group.clone());
+    }
+    Html::new().head(default_head("Student groups")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Student groups"))
+                .child(if groups.is_empty() {
+                    Div::new().child(P::with_text(
+                        "This institution doesn't have any groups yet.",
+                    ))
+                } else {
+                    group_tree(institution_id, None, &children)
+                })
+                .child(H2::new("Create a new group"))
+                .child(create_group_form(institution_id, &groups)),
+        ),
+    )
+}
+
+#[get("/<institution_id>/groups")]
+pub async fn api_list_groups(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<Vec<StudentGroup>>> {
+    Json(match list_groups_base(institution_id, auth, &conn).await {
+        Ok(groups) => ApiResponse::new_ok(groups),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+async fn create_group_base(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: CreateGroupForm,
+    conn: &Database,
+) -> Result<StudentGroup, GroupError> {
+    conn.run(move |c| {
+        c.transaction::<_, GroupError, _>(|| {
+            is_administrator(institution_id, auth, c)?;
+            let code = check_code(&form.code, None, c)?;
+            check_parent(institution_id, form.parent_group, None, c)?;
+            Ok(diesel::insert_into(student_group::table)
+                .values(NewStudentGroup {
+                    parent_group: form.parent_group,
+                    institution_id,
+                    code: code.as_deref(),
+                    name: &form.name,
+                    description: &form.description,
+                })
+                .returning(student_group::all_columns)
+                .get_result::<StudentGroup>(c)?)
+        })
+    })
+    .await
+}
+
+#[post("/<institution_id>/groups", data = "<form>")]
+pub async fn html_create_group(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<CreateGroupForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match create_group_base(institution_id, auth, form.into_inner(), &conn).await {
+        Ok(group) => HtmlOrRedirect::Redirect(Redirect::to(group_url(institution_id, group.id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not create that group")),
+    }
+}
+
+#[post("/<institution_id>/groups", data = "<form>")]
+pub async fn api_create_group(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: Json<CreateGroupForm>,
+    conn: Database,
+) -> Json<ApiResponse<StudentGroup>> {
+    Json(
+        match create_group_base(institution_id, auth, form.into_inner(), &conn).await {
+            Ok(group) => ApiResponse::new_ok(group),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+fn load_group_details(
+    institution_id: i32,
+    group_id: i32,
+    c: &DatabaseConnection,
+) -> Result<GroupDetails, GroupError> {
+    let group = find_group(institution_id, group_id, c)?;
+    let subgroups = student_group::table
+        .filter(student_group::parent_group.eq(group_id))
+        .order_by(student_group::name)
+        .load::<StudentGroup>(c)?;
+    let students = student_group_student::table
+        .inner_join(users::table)
+        .filter(student_group_student::student_group_id.eq(group_id))
+        .select((users::id, users::username))
+        .order_by(users::username)
+        .load::<GroupMember>(c)?;
+    let teachers = student_group_teacher::table
+        .inner_join(users::table)
+        .filter(student_group_teacher::student_group_id.eq(group_id))
+        .select((users::id, users::username))
+        .order_by(users::username)
+        .load::<GroupMember>(c)?;
+    let classes = class::table
+        .filter(class::student_group_id.eq(group_id))
+        .select((class::id, class::name))
+        .order_by(class::name)
+        .load::<GroupClass>(c)?;
+    Ok(GroupDetails {
+        group,
+        subgroups,
+        students,
+        teachers,
+        classes,
+    })
+}
+
+async fn group_details_base(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<GroupDetails, GroupError> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        load_group_details(institution_id, group_id, c)
+    })
+    .await
+}
+
+fn edit_group_form(group: &StudentGroup) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "{}/edit",
+            group_url(group.institution_id, group.id)
+        )))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("name"))
+                .attribute(Value::new(group.name.clone())),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Textarea)
+                .attribute(Name::new("description"))
+                .attribute(Value::new(group.description.clone())),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("code"))
+                .attribute(Placeholder::new("A code for the group (optional)"))
+                .attribute(Value::new(group.code.clone().unwrap_or_default())),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Save changes")),
+        )
+}
+
+/// `groups` should be the groups which this group could be moved inside.
+fn move_group_form(group: &StudentGroup, groups: &[StudentGroup]) -> Form {
+    let current = groups
+        .iter()
+        .find(|candidate| Some(candidate.id) == group.parent_group);
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "{}/move",
+            group_url(group.institution_id, group.id)
+        )))
+        .child(Label::new("Nest this group inside"))
+        .child(match current {
+            Some(current) => group_select(
+                SelectOption::new()
+                    .attribute(Value::new(current.id.to_string()))
+                    .text(format!("{} (where it is now)", current.name)),
+                groups,
+            )
+            .child(
+                SelectOption::new()
+                    .attribute(Value::new(""))
+                    .text("(No other group)"),
+            ),
+            None => group_select(
+                SelectOption::new()
+                    .attribute(Value::new(""))
+                    .text("(No other group)"),
+                groups,
+            ),
+        })
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Move group")),
+        )
+}
+
+fn delete_group_form(group: &StudentGroup) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "{}/delete",
+            group_url(group.institution_id, group.id)
+        )))
+        .child(P::with_text(
+            "Deleting this group also deletes the groups nested inside it. Classes attached to \
+            these groups aren't deleted (and their students stay enrolled in them).",
+        ))
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Delete this group")),
+        )
+}
+
+#[get("/<institution_id>/groups/<group_id>")]
+pub async fn group_page(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    let res = conn
+        .run(move |c| {
+            is_administrator(institution_id, auth, c)?;
+            let details = load_group_details(institution_id, group_id, c)?;
+            let subtree = descendants(group_id, c)?;
+            let candidates = student_group::table
+                .filter(student_group::institution_id.eq(institution_id))
+                .filter(student_group::id.ne_all(subtree))
+                .order_by(student_group::name)
+                .load::<StudentGroup>(c)?;
+            Ok::<_, GroupError>((details, candidates))
+        })
+        .await;
+    let (details, candidates) = match res {
+        Ok(res) => res,
+        Err(e) => return e.render("Could not load this group"),
+    };
+    let group = &details.group;
+    Html::new().head(default_head(group.name.clone())).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new(group.name.clone()))
+                .child(P::with_text(group.description.clone()))
+                .child(
+                    A::new()
+                        .attribute(Href::new(format!("/institution/{}/groups", institution_id)))
+                        .text("All student groups"),
+                )
+                .child(H2::new("Groups nested inside this one"))
+                .child(Div::new().map(|div| {
+                    if details.subgroups.is_empty() {
+                        div.child(P::with_text("There aren't any."))
+                    } else {
+                        details.subgroups.iter().fold(div, |div, subgroup| {
+                            div.child(
+                                A::new()
+                                    .attribute(Href::new(group_url(institution_id, subgroup.id)))
+                                    .text(subgroup.name.clone()),
+                            )
+                        })
+                    }
+                }))
+                .child(H2::new("Classes"))
+                .child(Div::new().map(|div| {
+                    if details.classes.is_empty() {
+                        div.child(P::with_text("No classes are attached to this group."))
+                    } else {
+                        details.classes.iter().fold(div, |div, class| {
+                            div.child(
+                                A::new()
+                                    .attribute(Href::new(format!("/class/{}", class.class_id)))
+                                    .text(class.name.clone()),
+                            )
+                        })
+                    }
+                }))
+                .child(H2::new("Students"))
+                .child(details.students.iter().fold(Div::new(), |div, student| {
+                    div.child(P::with_text(student.username.clone()))
+                        .child(remove_member_form(group, "students", student.user_id))
+                }))
+                .child(add_student_form(group))
+                .child(H2::new("Teachers"))
+                .child(details.teachers.iter().fold(Div::new(), |div, teacher| {
+                    div.child(P::with_text(teacher.username.clone()))
+                        .child(remove_member_form(group, "teachers", teacher.user_id))
+                }))
+                .child(invite_teacher_form(group))
+                .child(H2::new("Edit this group"))
+                .child(edit_group_form(group))
+                .child(H2::new("Move this group"))
+                .child(move_group_form(group, &candidates))
+                .child(H2::new("Delete this group"))
+                .child(delete_group_form(group)),
+        ),
+    )
+}
+
+#[get("/<institution_id>/groups/<group_id>")]
+pub async fn api_view_group(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<GroupDetails>> {
+    Json(
+        match group_details_base(institution_id, group_id, auth, &conn).await {
+            Ok(details) => ApiResponse::new_ok(details),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+async fn edit_group_base(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: EditGroupForm,
+    conn: &Database,
+) -> Result<StudentGroup, GroupError> {
+    conn.run(move |c| {
+        c.transaction::<_, GroupError, _>(|| {
+            is_administrator(institution_id, auth, c)?;
+            find_group(institution_id, group_id, c)?;
+            let code = check_code(&form.code, Some(group_id), c)?;
+            Ok(diesel::update(student_group::table.find(group_id))
+                .set(UpdateStudentGroup {
+                    parent_group: None,
+                    institution_id,
+                    code: Some(code),
+                    name: Some(form.name),
+                    description: Some(form.description),
+                })
+                .returning(student_group::all_columns)
+                .get_result::<StudentGroup>(c)?)
+        })
+    })
+    .await
+}
+
+#[post("/<institution_id>/groups/<group_id>/edit", data = "<form>")]
+pub async fn html_edit_group(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<EditGroupForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match edit_group_base(institution_id, group_id, auth, form.into_inner(), &conn).await {
+        Ok(_) => HtmlOrRedirect::Redirect(Redirect::to(group_url(institution_id, group_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not edit this group")),
+    }
+}
+
+#[post("/<institution_id>/groups/<group_id>/edit", data = "<form>")]
+pub async fn api_edit_group(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: Json<EditGroupForm>,
+    conn: Database,
+) -> Json<ApiResponse<StudentGroup>> {
+    Json(
+        match edit_group_base(institution_id, group_id, auth, form.into_inner(), &conn).await {
+            Ok(group) => ApiResponse::new_ok(group),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+/// Moves the group (and everything nested inside it) inside another group, or to the top level.
+async fn move_group_base(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    parent_group: Option<i32>,
+    conn: &Database,
+) -> Result<StudentGroup, GroupError> {
+    conn.run(move |c| {
+        c.transaction::<_, GroupError, _>(|| {
+            is_administrator(institution_id, auth, c)?;
+            find_group(institution_id, group_id, c)?;
+            check_parent(institution_id, parent_group, Some(group_id), c)?;
+            let group = diesel::update(student_group::table.find(group_id))
+                .set(UpdateStudentGroup {
+                    parent_group: Some(parent_group),
+                    institution_id,
+                    code: None,
+                    name: None,
+                    description: None,
+                })
+                .returning(student_group::all_columns)
+                .get_result::<StudentGroup>(c)?;
+            // the students of the groups it has been moved into are now part of it
+            cascade_enrolments(group_id, c)?;
+            Ok(group)
+        })
+    })
+    .await
+}
+
+#[post("/<institution_id>/groups/<group_id>/move", data = "<form>")]
+pub async fn html_move_group(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<MoveGroupForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match move_group_base(institution_id, group_id, auth, form.parent_group, &conn).await {
+        Ok(_) => HtmlOrRedirect::Redirect(Redirect::to(group_url(institution_id, group_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not move this group")),
+    }
+}
+
+#[post("/<institution_id>/groups/<group_id>/move", data = "<form>")]
+pub async fn api_move_group(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: Json<MoveGroupForm>,
+    conn: Database,
+) -> Json<ApiResponse<StudentGroup>> {
+    Json(
+        match move_group_base(institution_id, group_id, auth, form.parent_group, &conn).await {
+            Ok(group) => ApiResponse::new_ok(group),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+/// Deletes the group and the groups nested inside it. Classes attached to any of these groups are
+/// detached from them (rather than deleted).
+async fn delete_group_base(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<(), GroupError> {
+    conn.run(move |c| {
+        c.transaction::<_, GroupError, _>(|| {
+            is_administrator(institution_id, auth, c)?;
+            find_group(institution_id, group_id, c)?;
+            let subtree = descendants(group_id, c)?;
+            diesel::update(class::table.filter(class::student_group_id.eq_any(subtree)))
+                .set(class::student_group_id.eq(None::<i32>))
+                .execute(c)?;
+            // nested groups (and memberships) are deleted by the database
+            diesel::delete(student_group::table.find(group_id)).execute(c)?;
+            Ok(())
+        })
+    })
+    .await
+}
+
+#[post("/<institution_id>/groups/<group_id>/delete")]
+pub async fn html_delete_group(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match delete_group_base(institution_id, group_id, auth, &conn).await {
+        Ok(()) => HtmlOrRedirect::Redirect(Redirect::to(format!(
+            "/institution/{}/groups",
+            institution_id
+        ))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not delete this group")),
+    }
+}
+
+#[post("/<institution_id>/groups/<group_id>/delete")]
+pub async fn api_delete_group(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match delete_group_base(institution_id, group_id, auth, &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test_manage_groups {
+    use diesel::prelude::*;
+    use rocket::{
+        http::{ContentType, Status},
+        local::asynchronous::Client,
+    };
+
+    use crate::{
+        db::Database,
+        institution::test_ctx::{
+            setup_env, ADMIN_PASSWORD, ADMIN_USERNAME, STUDENT_PASSWORD, STUDENT_USERNAME,
+        },
+        models::{institution::student_group::StudentGroup, Class, NewClass},
+        schema::{class, student_group},
+        utils::{client, login_user},
+    };
+
+    async fn create_group(
+        client: &Client,
+        institution_id: i32,
+        name: &str,
+        parent_group: Option<i32>,
+    ) -> i32 {
+        let res = client
+            .post(format!("/institution/{}/groups", institution_id))
+            .header(ContentType::Form)
+            .body(format!(
+                "name={}&description=A+group&code={}{}",
+                name,
+                name,
+                parent_group
+                    .map(|parent| format!("&parent_group={}", parent))
+                    .unwrap_or_default()
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let name = name.to_string();
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                student_group::table
+                    .filter(student_group::name.eq(name))
+                    .select(student_group::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await
+    }
+
+    async fn find_group(client: &Client, group_id: i32) -> Option<StudentGroup> {
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                student_group::table
+                    .find(group_id)
+                    .first::<StudentGroup>(c)
+                    .optional()
+                    .unwrap()
+            })
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn test_create_nest_move_and_delete_groups() {
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(ADMIN_USERNAME, ADMIN_PASSWORD, &client).await;
+
+        let year = create_group(&client, institution_id, "year-7", None).await;
+        let form = create_group(&client, institution_id, "form-7a", Some(year)).await;
+        assert_eq!(
+            find_group(&client, form).await.unwrap().parent_group,
+            Some(year)
+        );
+
+        let page = client
+            .get(format!("/institution/{}/groups", institution_id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(page.contains("year-7"));
+        assert!(page.contains("form-7a"));
+
+        // codes have to be unique
+        let res = client
+            .post(format!("/institution/{}/groups", institution_id))
+            .header(ContentType::Form)
+            .body("name=other&description=A+group&code=year-7")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+
+        // a group can't be nested inside a group which is nested inside it
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/move",
+                institution_id, year
+            ))
+            .header(ContentType::Form)
+            .body(format!("parent_group={}", form))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        assert_eq!(find_group(&client, year).await.unwrap().parent_group, None);
+
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/edit",
+                institution_id, form
+            ))
+            .header(ContentType::Form)
+            .body("name=form-7b&description=Another+group&code=")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let edited = find_group(&client, form).await.unwrap();
+        assert_eq!(edited.name, "form-7b");
+        assert_eq!(edited.code, None);
+        assert_eq!(edited.parent_group, Some(year));
+
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/move",
+                institution_id, form
+            ))
+            .header(ContentType::Form)
+            .body("parent_group=")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        assert_eq!(find_group(&client, form).await.unwrap().parent_group, None);
+
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/move",
+                institution_id, form
+            ))
+            .header(ContentType::Form)
+            .body(format!("parent_group={}", year))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+
+        // deleting a group deletes the groups nested inside it, but not their classes
+        let class_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::insert_into(class::table)
+                    .values(NewClass {
+                        name: "class",
+                        description: "description",
+                        created: chrono::Utc::now().naive_utc(),
+                        code: "code",
+                        institution_id: Some(institution_id),
+                        student_group_id: Some(form),
+                        academic_term_id: None,
+                    })
+                    .returning(class::id)
+                    .get_result::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/delete",
+                institution_id, year
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        assert!(find_group(&client, year).await.is_none());
+        assert!(find_group(&client, form).await.is_none());
+        let class = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| class::table.find(class_id).first::<Class>(c).unwrap())
+            .await;
+        assert_eq!(class.student_group_id, None);
+    }
+
+    #[rocket::async_test]
+    async fn test_only_administrators_can_manage_groups() {
+        let client = client().await;
+        let (_, _, _, institution_id, group_id) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+
+        let res = client
+            .get(format!("/institution/{}/groups", institution_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/delete",
+                institution_id, group_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        assert!(find_group(&client, group_id).await.is_some());
+    }
+}
@@ -0,0 +1,631 @@
+//! Adding students to (and removing them from) student groups, and inviting teachers to them.
+//!
+//! Removing a student from a group doesn't remove them from the classes they were enrolled in
+//! because of it (they might be doing work for these classes which nobody wants to lose); this can
+//! be done from the class's page.
+
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use rocket::{response::Redirect, serde::json::Json};
+
+use super::{cascade_enrolments, find_group, group_url, GroupError};
+use crate::{
+    auth::AuthCookie,
+    db::Database,
+    institution::is_administrator,
+    invite::{find_invitee, send_invite, InviteError, InviteKind},
+    models::institution::student_group::{student::NewStudentGroupStudent, StudentGroup},
+    schema::{
+        institution_student, institution_teacher, student_group_student, student_group_teacher,
+    },
+    utils::html_or_redirect::HtmlOrRedirect,
+    utils::json_response::ApiResponse,
+};
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct MemberForm {
+    /// The username or email address of the user.
+    identifier: String,
+}
+
+fn identifier_form(action: String, placeholder: &'static str, submit: &'static str) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(action))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("identifier"))
+                .attribute(Placeholder::new(placeholder)),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new(submit)),
+        )
+}
+
+pub(super) fn add_student_form(group: &StudentGroup) -> Form {
+    identifier_form(
+        format!("{}/students", group_url(group.institution_id, group.id)),
+        "The student's username or email address",
+        "Add student",
+    )
+}
+
+pub(super) fn invite_teacher_form(group: &StudentGroup) -> Form {
+    identifier_form(
+        format!("{}/teachers", group_url(group.institution_id, group.id)),
+        "The teacher's username or email address",
+        "Invite teacher",
+    )
+}
+
+/// `kind` should be either "students" or "teachers".
+pub(super) fn remove_member_form(group: &StudentGroup, kind: &'static str, user_id: i32) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "{}/{}/{}/remove",
+            group_url(group.institution_id, group.id),
+            kind,
+            user_id
+        )))
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new("Remove")),
+        )
+}
+
+/// Adds the student to the group, and enrols them in the classes attached to it (or to any of the
+/// groups nested inside it).
+async fn add_student_base(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    identifier: String,
+    conn: &Database,
+) -> Result<(), GroupError> {
+    conn.run(move |c| is_administrator(institution_id, auth, c))
+        .await?;
+    let user = find_invitee(identifier, conn).await.map_err(|e| match e {
+        InviteError::UserNotFound => GroupError::UserNotFound,
+        e => e.into(),
+    })?;
+    conn.run(move |c| {
+        c.transaction::<_, GroupError, _>(|| {
+            find_group(institution_id, group_id, c)?;
+            let is_student = diesel::select(diesel::dsl::exists(
+                institution_student::table
+                    .filter(institution_student::institution_id.eq(institution_id))
+                    .filter(institution_student::user_id.eq(user.id)),
+            ))
+            .get_result::<bool>(c)?;
+            if !is_student {
+                return Err(GroupError::NotAStudent);
+            }
+            let already_member = diesel::select(diesel::dsl::exists(
+                student_group_student::table
+                    .filter(student_group_student::student_group_id.eq(group_id))
+                    .filter(student_group_student::user_id.eq(user.id)),
+            ))
+            .get_result::<bool>(c)?;
+            if already_member {
+                return Err(GroupError::AlreadyMember);
+            }
+            diesel::insert_into(student_group_student::table)
+                .values(NewStudentGroupStudent {
+                    user_id: user.id,
+                    student_group_id: group_id,
+                })
+                .execute(c)?;
+            cascade_enrolments(group_id, c)?;
+            Ok(())
+        })
+    })
+    .await
+}
+
+#[post("/<institution_id>/groups/<group_id>/students", data = "<form>")]
+pub async fn html_add_student(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<MemberForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match add_student_base(
+        institution_id,
+        group_id,
+        auth,
+        form.into_inner().identifier,
+        &conn,
+    )
+    .await
+    {
+        Ok(()) => HtmlOrRedirect::Redirect(Redirect::to(group_url(institution_id, group_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not add that student")),
+    }
+}
+
+#[post("/<institution_id>/groups/<group_id>/students", data = "<form>")]
+pub async fn api_add_student(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: Json<MemberForm>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match add_student_base(
+            institution_id,
+            group_id,
+            auth,
+            form.into_inner().identifier,
+            &conn,
+        )
+        .await
+        {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+async fn remove_student_base(
+    institution_id: i32,
+    group_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<(), GroupError> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        find_group(institution_id, group_id, c)?;
+        let deleted = diesel::delete(
+            student_group_student::table
+                .filter(student_group_student::student_group_id.eq(group_id))
+                .filter(student_group_student::user_id.eq(user_id)),
+        )
+        .execute(c)?;
+        if deleted == 0 {
+            Err(GroupError::UserNotFound)
+        } else {
+            Ok(())
+        }
+    })
+    .await
+}
+
+#[post("/<institution_id>/groups/<group_id>/students/<user_id>/remove")]
+pub async fn html_remove_student(
+    institution_id: i32,
+    group_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match remove_student_base(institution_id, group_id, user_id, auth, &conn).await {
+        Ok(()) => HtmlOrRedirect::Redirect(Redirect::to(group_url(institution_id, group_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not remove that student")),
+    }
+}
+
+#[post("/<institution_id>/groups/<group_id>/students/<user_id>/remove")]
+pub async fn api_remove_student(
+    institution_id: i32,
+    group_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match remove_student_base(institution_id, group_id, user_id, auth, &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+/// Invites a teacher of the institution to teach the group. They become one of its teachers once
+/// they accept the invitation.
+async fn invite_teacher_base(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    identifier: String,
+    conn: &Database,
+) -> Result<(), GroupError> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        find_group(institution_id, group_id, c).map(drop)
+    })
+    .await?;
+    let user = find_invitee(identifier, conn).await.map_err(|e| match e {
+        InviteError::UserNotFound => GroupError::UserNotFound,
+        e => e.into(),
+    })?;
+    let user_id = user.id;
+    let is_teacher = conn
+        .run(move |c| {
+            diesel::select(diesel::dsl::exists(
+                institution_teacher::table
+                    .filter(institution_teacher::institution_id.eq(institution_id))
+                    .filter(institution_teacher::user_id.eq(user_id)),
+            ))
+            .get_result::<bool>(c)
+        })
+        .await?;
+    if !is_teacher {
+        return Err(GroupError::NotATeacher);
+    }
+    send_invite(
+        InviteKind::StudentGroupTeacher,
+        group_id,
+        auth.0,
+        user,
+        conn,
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/<institution_id>/groups/<group_id>/teachers", data = "<form>")]
+pub async fn html_invite_teacher(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<MemberForm>,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match invite_teacher_base(
+        institution_id,
+        group_id,
+        auth,
+        form.into_inner().identifier,
+        &conn,
+    )
+    .await
+    {
+        Ok(()) => HtmlOrRedirect::Redirect(Redirect::to(group_url(institution_id, group_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not invite that teacher")),
+    }
+}
+
+#[post("/<institution_id>/groups/<group_id>/teachers", data = "<form>")]
+pub async fn api_invite_teacher(
+    institution_id: i32,
+    group_id: i32,
+    auth: AuthCookie,
+    form: Json<MemberForm>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match invite_teacher_base(
+            institution_id,
+            group_id,
+            auth,
+            form.into_inner().identifier,
+            &conn,
+        )
+        .await
+        {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+async fn remove_teacher_base(
+    institution_id: i32,
+    group_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> Result<(), GroupError> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        find_group(institution_id, group_id, c)?;
+        let deleted = diesel::delete(
+            student_group_teacher::table
+                .filter(student_group_teacher::student_group_id.eq(group_id))
+                .filter(student_group_teacher::user_id.eq(user_id)),
+        )
+        .execute(c)?;
+        if deleted == 0 {
+            Err(GroupError::UserNotFound)
+        } else {
+            Ok(())
+        }
+    })
+    .await
+}
+
+#[post("/<institution_id>/groups/<group_id>/teachers/<user_id>/remove")]
+pub async fn html_remove_teacher(
+    institution_id: i32,
+    group_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> HtmlOrRedirect {
+    match remove_teacher_base(institution_id, group_id, user_id, auth, &conn).await {
+        Ok(()) => HtmlOrRedirect::Redirect(Redirect::to(group_url(institution_id, group_id))),
+        Err(e) => HtmlOrRedirect::Html(e.render("Could not remove that teacher")),
+    }
+}
+
+#[post("/<institution_id>/groups/<group_id>/teachers/<user_id>/remove")]
+pub async fn api_remove_teacher(
+    institution_id: i32,
+    group_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match remove_teacher_base(institution_id, group_id, user_id, auth, &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test_group_members {
+    use diesel::prelude::*;
+    use rocket::{
+        http::{ContentType, Status},
+        local::asynchronous::Client,
+    };
+
+    use crate::{
+        db::Database,
+        institution::test_ctx::{setup_env, ADMIN_PASSWORD, ADMIN_USERNAME, TIMEZONE},
+        models::{
+            institution::{
+                student::NewInstitutionStudent, student_group::NewStudentGroup,
+                teacher::NewInstitutionTeacher,
+            },
+            NewClass,
+        },
+        schema::{
+            class, class_student, institution_student, institution_teacher, student_group,
+            student_group_student, student_group_teacher, student_group_teacher_invite, users,
+        },
+        utils::{client, create_user, login_user, logout, mock_email_server},
+    };
+
+    const USERNAME: &str = "newcomer";
+    const EMAIL: &str = "newcomer@example.com";
+    const PASSWORD: &str = "n3wcomer-PASSWORD";
+
+    async fn newcomer_id(client: &Client) -> i32 {
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, client).await;
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                users::table
+                    .filter(users::username.eq(USERNAME))
+                    .select(users::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn test_adding_students_enrols_them_in_classes() {
+        let client = client().await;
+        let (_, _, _, institution_id, group_id) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        let user_id = newcomer_id(&client).await;
+        // a class attached to a group nested inside the group
+        let class_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                let subgroup = diesel::insert_into(student_group::table)
+                    .values(NewStudentGroup {
+                        parent_group: Some(group_id),
+                        institution_id,
+                        code: None,
+                        name: "subgroup",
+                        description: "description",
+                    })
+                    .returning(student_group::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+                diesel::insert_into(class::table)
+                    .values(NewClass {
+                        name: "class",
+                        description: "description",
+                        created: chrono::Utc::now().naive_utc(),
+                        code: "code",
+                        institution_id: Some(institution_id),
+                        student_group_id: Some(subgroup),
+                        academic_term_id: None,
+                    })
+                    .returning(class::id)
+                    .get_result::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        login_user(ADMIN_USERNAME, ADMIN_PASSWORD, &client).await;
+
+        // only students of the institution can be added
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/students",
+                institution_id, group_id
+            ))
+            .header(ContentType::Form)
+            .body(format!("identifier={}", USERNAME))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::insert_into(institution_student::table)
+                    .values(NewInstitutionStudent {
+                        user_id,
+                        institution_id,
+                    })
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/students",
+                institution_id, group_id
+            ))
+            .header(ContentType::Form)
+            .body(format!("identifier={}", EMAIL))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+
+        let enrolled = move |c: &mut crate::db::DatabaseConnection| {
+            diesel::select(diesel::dsl::exists(
+                class_student::table
+                    .filter(class_student::class_id.eq(class_id))
+                    .filter(class_student::user_id.eq(user_id)),
+            ))
+            .get_result::<bool>(c)
+            .unwrap()
+        };
+        assert!(
+            Database::get_one(client.rocket())
+                .await
+                .unwrap()
+                .run(enrolled)
+                .await
+        );
+
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/students/{}/remove",
+                institution_id, group_id, user_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        let still_member = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::select(diesel::dsl::exists(
+                    student_group_student::table
+                        .filter(student_group_student::student_group_id.eq(group_id))
+                        .filter(student_group_student::user_id.eq(user_id)),
+                ))
+                .get_result::<bool>(c)
+                .unwrap()
+            })
+            .await;
+        assert!(!still_member);
+        // they stay enrolled in the class
+        assert!(
+            Database::get_one(client.rocket())
+                .await
+                .unwrap()
+                .run(enrolled)
+                .await
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_inviting_teachers() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (_, _, _, institution_id, group_id) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        let user_id = newcomer_id(&client).await;
+        login_user(ADMIN_USERNAME, ADMIN_PASSWORD, &client).await;
+
+        // only teachers of the institution can be invited
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/teachers",
+                institution_id, group_id
+            ))
+            .header(ContentType::Form)
+            .body(format!("identifier={}", USERNAME))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::insert_into(institution_teacher::table)
+                    .values(NewInstitutionTeacher {
+                        user_id,
+                        institution_id,
+                    })
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        let res = client
+            .post(format!(
+                "/institution/{}/groups/{}/teachers",
+                institution_id, group_id
+            ))
+            .header(ContentType::Form)
+            .body(format!("identifier={}", USERNAME))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::SeeOther);
+        logout(&client).await;
+
+        let invite_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                student_group_teacher_invite::table
+                    .filter(student_group_teacher_invite::invited_user_id.eq(user_id))
+                    .filter(student_group_teacher_invite::student_group_id.eq(group_id))
+                    .select(student_group_teacher_invite::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        let res = client
+            .post(format!(
+                "/invites/student-group-teacher/{}/accept",
+                invite_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let is_teacher = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::select(diesel::dsl::exists(
+                    student_group_teacher::table
+                        .filter(student_group_teacher::student_group_id.eq(group_id))
+                        .filter(student_group_teacher::user_id.eq(user_id)),
+                ))
+                .get_result::<bool>(c)
+                .unwrap()
+            })
+            .await;
+        assert!(is_teacher);
+    }
+}
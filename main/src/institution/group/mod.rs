@@ -0,0 +1,218 @@
+//! Student groups (e.g. a year group, or a form within a year group) are how institutions
+//! organise their students. Groups can be nested inside one another, and classes can be attached
+//! to a group.
+//!
+//! Group membership cascades downwards: a student who is part of a group is enrolled in every
+//! class attached to that group or to any of the groups nested inside it.
+
+pub mod manage;
+pub mod members;
+
+use std::collections::HashSet;
+
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use rocket::http::Status;
+use thiserror::Error as ThisError;
+
+use crate::{
+    db::DatabaseConnection,
+    invite::InviteError,
+    models::{institution::student_group::StudentGroup, NewClassStudent},
+    schema::{class, class_student, student_group, student_group_student},
+    utils::{default_head, error::LovelaceError},
+};
+
+#[derive(ThisError, Debug)]
+pub enum GroupError {
+    #[error("student group not found")]
+    NotFound,
+    #[error("permission error")]
+    PermissionError,
+    #[error("invalid parent group")]
+    InvalidParent,
+    #[error("student group code already in use")]
+    CodeTaken,
+    #[error("user not found")]
+    UserNotFound,
+    #[error("the user is not a student of the institution")]
+    NotAStudent,
+    #[error("the user is not a teacher of the institution")]
+    NotATeacher,
+    #[error("the user is already a member of the group")]
+    AlreadyMember,
+    #[error("could not invite the user")]
+    Invite(InviteError),
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for GroupError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl From<LovelaceError> for GroupError {
+    fn from(e: LovelaceError) -> Self {
+        match e {
+            LovelaceError::PermissionError(_) => Self::PermissionError,
+            _ => Self::DatabaseError,
+        }
+    }
+}
+
+impl From<InviteError> for GroupError {
+    fn from(e: InviteError) -> Self {
+        Self::Invite(e)
+    }
+}
+
+impl GroupError {
+    fn status(&self) -> Status {
+        match self {
+            GroupError::NotFound | GroupError::UserNotFound => Status::NotFound,
+            GroupError::PermissionError => Status::Forbidden,
+            GroupError::InvalidParent | GroupError::NotAStudent | GroupError::NotATeacher => {
+                Status::BadRequest
+            }
+            GroupError::CodeTaken | GroupError::AlreadyMember => Status::Conflict,
+            GroupError::Invite(e) => e.status(),
+            GroupError::DatabaseError => Status::InternalServerError,
+        }
+    }
+
+    fn explanation(&self) -> String {
+        let explanation = match self {
+            GroupError::NotFound => "That student group doesn't exist.",
+            GroupError::PermissionError => {
+                "You don't have permission to do this – only administrators of this institution \
+                can manage its student groups."
+            }
+            GroupError::InvalidParent => {
+                "A group can only be nested inside another group which is part of the same \
+                institution (and not inside itself, or one of the groups nested inside it)."
+            }
+            GroupError::CodeTaken => "Another student group is already using that code.",
+            GroupError::UserNotFound => {
+                "Nobody with that username or email address could be found."
+            }
+            GroupError::NotAStudent => {
+                "Only students of this institution can be added to its student groups."
+            }
+            GroupError::NotATeacher => {
+                "Only teachers of this institution can teach its student groups."
+            }
+            GroupError::AlreadyMember => "They're already part of this group.",
+            GroupError::Invite(e) => return e.explanation(),
+            GroupError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!"
+            }
+        };
+        explanation.to_string()
+    }
+
+    fn render(self, title: &'static str) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head(title))
+            .body(
+                Body::new()
+                    .child(H1::new(title))
+                    .child(P::with_text(self.explanation())),
+            )
+    }
+}
+
+fn group_url(institution_id: i32, group_id: i32) -> String {
+    format!("/institution/{}/groups/{}", institution_id, group_id)
+}
+
+/// Finds the group, checking that it is part of the institution.
+fn find_group(
+    institution_id: i32,
+    group_id: i32,
+    c: &DatabaseConnection,
+) -> Result<StudentGroup, GroupError> {
+    student_group::table
+        .filter(student_group::id.eq(group_id))
+        .filter(student_group::institution_id.eq(institution_id))
+        .first::<StudentGroup>(c)
+        .optional()?
+        .ok_or(GroupError::NotFound)
+}
+
+/// The group, and all the groups nested inside it (however deeply).
+pub(crate) fn descendants(group_id: i32, c: &DatabaseConnection) -> QueryResult<Vec<i32>> {
+    let mut found = vec![group_id];
+    let mut frontier = vec![group_id];
+    while !frontier.is_empty() {
+        frontier = student_group::table
+            .filter(student_group::parent_group.eq_any(frontier))
+            .filter(student_group::id.ne_all(found.clone()))
+            .select(student_group::id)
+            .load::<i32>(c)?;
+        found.extend(frontier.iter().copied());
+    }
+    Ok(found)
+}
+
+/// The group, and all the groups it is nested inside.
+pub(crate) fn ancestors(group_id: i32, c: &DatabaseConnection) -> QueryResult<Vec<i32>> {
+    let mut found = vec![group_id];
+    let mut seen = HashSet::new();
+    seen.insert(group_id);
+    let mut current = group_id;
+    while let Some(parent) = student_group::table
+        .find(current)
+        .select(student_group::parent_group)
+        .first::<Option<i32>>(c)?
+    {
+        if !seen.insert(parent) {
+            break;
+        }
+        found.push(parent);
+        current = parent;
+    }
+    Ok(found)
+}
+
+/// Enrols the students of the group (and of the groups it is nested inside) in the classes
+/// attached to the group and the groups nested inside it, if they aren't enrolled already.
+///
+/// This should be called whenever a student joins a group, a group is moved or a class is
+/// attached to a group.
+pub(crate) fn cascade_enrolments(group_id: i32, c: &DatabaseConnection) -> QueryResult<()> {
+    for group in descendants(group_id, c)? {
+        let classes = class::table
+            .filter(class::student_group_id.eq(group))
+            .select(class::id)
+            .load::<i32>(c)?;
+        if classes.is_empty() {
+            continue;
+        }
+        let students = student_group_student::table
+            .filter(student_group_student::student_group_id.eq_any(ancestors(group, c)?))
+            .select(student_group_student::user_id)
+            .distinct()
+            .load::<i32>(c)?;
+        for class_id in classes {
+            let enrolled = class_student::table
+                .filter(class_student::class_id.eq(class_id))
+                .select(class_student::user_id)
+                .load::<i32>(c)?;
+            let new_students = students
+                .iter()
+                .filter(|student| !enrolled.contains(student))
+                .map(|&user_id| NewClassStudent { user_id, class_id })
+                .collect::<Vec<_>>();
+            if !new_students.is_empty() {
+                diesel::insert_into(class_student::table)
+                    .values(new_students)
+                    .execute(c)?;
+            }
+        }
+    }
+    Ok(())
+}
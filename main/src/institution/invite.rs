@@ -0,0 +1,162 @@
+//! Lets institution administrators invite people to join the institution as administrators,
+//! teachers or students. The invitations end up in the invited user's inbox (see
+//! [`crate::invite`]).
+
+use malvolio::prelude::*;
+use portia::levels::Level;
+use rocket::{request::FromParam, serde::json::Json};
+
+use super::is_administrator;
+use crate::{
+    auth::AuthCookie,
+    db::Database,
+    invite::{find_invitee, send_invite, InviteError, InviteKind},
+    utils::{default_head, json_response::ApiResponse},
+};
+
+/// The role somebody is being invited to take on at an institution.
+#[derive(Debug, Copy, Clone)]
+pub enum InstitutionRole {
+    Administrator,
+    Teacher,
+    Student,
+}
+
+impl InstitutionRole {
+    fn name(self) -> &'static str {
+        match self {
+            InstitutionRole::Administrator => "administrator",
+            InstitutionRole::Teacher => "teacher",
+            InstitutionRole::Student => "student",
+        }
+    }
+}
+
+impl<'a> FromParam<'a> for InstitutionRole {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        match param {
+            "administrator" => Ok(InstitutionRole::Administrator),
+            "teacher" => Ok(InstitutionRole::Teacher),
+            "student" => Ok(InstitutionRole::Student),
+            _ => Err(param),
+        }
+    }
+}
+
+impl From<InstitutionRole> for InviteKind {
+    fn from(role: InstitutionRole) -> Self {
+        match role {
+            InstitutionRole::Administrator => InviteKind::Administrator,
+            InstitutionRole::Teacher => InviteKind::InstitutionTeacher,
+            InstitutionRole::Student => InviteKind::InstitutionStudent,
+        }
+    }
+}
+
+fn invite_form(institution_id: i32, role: InstitutionRole) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "/institution/{}/invite/{}",
+            institution_id,
+            role.name()
+        )))
+        .child(
+            Input::new()
+                .attribute(Type::Text)
+                .attribute(Name::new("identifier"))
+                .attribute(Placeholder::new("Their username or email address")),
+        )
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new(format!("Invite {}", role.name()))),
+        )
+}
+
+#[get("/<institution_id>/invite")]
+pub fn invite_member_page(institution_id: i32, _auth: AuthCookie) -> Html {
+    Html::new().head(default_head("Invite people")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Invite people to join your institution"))
+                .child(H2::new("Administrators"))
+                .child(invite_form(institution_id, InstitutionRole::Administrator))
+                .child(H2::new("Teachers"))
+                .child(invite_form(institution_id, InstitutionRole::Teacher))
+                .child(H2::new("Students"))
+                .child(invite_form(institution_id, InstitutionRole::Student)),
+        ),
+    )
+}
+
+#[derive(FromForm, Deserialize, Debug, Clone)]
+pub struct InviteMemberForm {
+    identifier: String,
+}
+
+async fn invite_member_base(
+    institution_id: i32,
+    role: InstitutionRole,
+    auth: AuthCookie,
+    identifier: String,
+    conn: &Database,
+) -> Result<(), InviteError> {
+    conn.run(move |c| is_administrator(institution_id, auth, c))
+        .await?;
+    let user = find_invitee(identifier, conn).await?;
+    send_invite(role.into(), institution_id, auth.0, user, conn).await
+}
+
+#[post("/<institution_id>/invite/<role>", data = "<form>")]
+pub async fn html_invite_member(
+    institution_id: i32,
+    role: InstitutionRole,
+    auth: AuthCookie,
+    form: rocket::form::Form<InviteMemberForm>,
+    conn: Database,
+) -> Html {
+    match invite_member_base(institution_id, role, auth, form.identifier.clone(), &conn).await {
+        Ok(()) => Html::new().head(default_head("Invitation sent")).body(
+            Body::new()
+                .child(H1::new("Invitation sent"))
+                .child(P::with_text(format!(
+                    "We've invited {} to join as {} {}.",
+                    form.identifier,
+                    match role {
+                        InstitutionRole::Administrator => "an",
+                        _ => "a",
+                    },
+                    role.name()
+                )))
+                .child(invite_form(institution_id, role)),
+        ),
+        Err(e) => Html::new()
+            .status(e.status())
+            .head(default_head("Could not send that invitation"))
+            .body(
+                Body::new()
+                    .child(H1::new("Could not send that invitation"))
+                    .child(P::with_text(e.explanation()))
+                    .child(invite_form(institution_id, role)),
+            ),
+    }
+}
+
+#[post("/<institution_id>/invite/<role>", data = "<form>")]
+pub async fn api_invite_member(
+    institution_id: i32,
+    role: InstitutionRole,
+    auth: AuthCookie,
+    form: Json<InviteMemberForm>,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match invite_member_base(institution_id, role, auth, form.identifier.clone(), &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
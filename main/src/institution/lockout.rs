@@ -0,0 +1,329 @@
+//! Lets institution administrators see (and unlock) the accounts of members of their institution
+//! which have been locked after too many failed logins.
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::{levels::Level, render::Render};
+use rocket::serde::json::Json;
+
+use super::{is_administrator, is_member};
+use crate::{
+    auth::{limit, AuthCookie},
+    db::Database,
+    schema::{account_lockout, administrator, institution_student, institution_teacher, users},
+    utils::{
+        default_head,
+        error::{LovelaceError, LovelaceResult},
+        json_response::ApiResponse,
+    },
+};
+
+#[derive(Queryable, Serialize, Debug, Clone)]
+pub struct LockedAccount {
+    pub user_id: i32,
+    pub username: String,
+    pub locked_until: NaiveDateTime,
+}
+
+async fn locked_accounts_base(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> LovelaceResult<Vec<LockedAccount>> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        account_lockout::table
+            .inner_join(users::table)
+            .filter(account_lockout::locked_until.gt(Utc::now().naive_utc()))
+            .filter(
+                users::id
+                    .eq_any(
+                        administrator::table
+                            .filter(administrator::institution_id.eq(institution_id))
+                            .select(administrator::user_id),
+                    )
+                    .or(users::id.eq_any(
+                        institution_teacher::table
+                            .filter(institution_teacher::institution_id.eq(institution_id))
+                            .select(institution_teacher::user_id),
+                    ))
+                    .or(users::id.eq_any(
+                        institution_student::table
+                            .filter(institution_student::institution_id.eq(institution_id))
+                            .select(institution_student::user_id),
+                    )),
+            )
+            .select((users::id, users::username, account_lockout::locked_until))
+            .order_by(users::username)
+            .load::<LockedAccount>(c)
+            .map_err(|e| {
+                error!("{:#?}", e);
+                LovelaceError::DatabaseError
+            })
+    })
+    .await
+}
+
+#[get("/<institution_id>/lockouts")]
+pub async fn locked_accounts_page(institution_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    let accounts = match locked_accounts_base(institution_id, auth, &conn).await {
+        Ok(accounts) => accounts,
+        Err(e) => return e.render(),
+    };
+    Html::new().head(default_head("Locked accounts")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Locked accounts"))
+                .child(P::with_text(if accounts.is_empty() {
+                    "None of your institution's accounts are locked at the moment."
+                } else {
+                    "These accounts have been locked because somebody failed to log in to them \
+                        too many times. Only unlock an account if you're sure that its owner was \
+                        the one trying to log in."
+                }))
+                .child(Div::new().map(|div| {
+                    accounts.into_iter().fold(div, |div, account| {
+                        div.child(
+                            Div::new()
+                                .child(P::with_text(format!(
+                                    "{} (locked until {} UTC)",
+                                    account.username,
+                                    account.locked_until.format("%Y-%m-%d %H:%M")
+                                )))
+                                .child(
+                                    Form::new()
+                                        .attribute(Method::Post)
+                                        .attribute(Action::new(format!(
+                                            "/institution/{}/lockouts/{}/unlock",
+                                            institution_id, account.user_id
+                                        )))
+                                        .child(
+                                            Input::new()
+                                                .attribute(Type::Submit)
+                                                .attribute(Value::new("Unlock")),
+                                        ),
+                                ),
+                        )
+                    })
+                })),
+        ),
+    )
+}
+
+#[get("/<institution_id>/lockouts")]
+pub async fn api_locked_accounts(
+    institution_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<Vec<LockedAccount>>> {
+    Json(
+        match locked_accounts_base(institution_id, auth, &conn).await {
+            Ok(accounts) => ApiResponse::new_ok(accounts),
+            Err(e) => From::from(e),
+        },
+    )
+}
+
+async fn unlock_account_base(
+    institution_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: &Database,
+) -> LovelaceResult<()> {
+    conn.run(move |c| {
+        is_administrator(institution_id, auth, c)?;
+        // administrators may only unlock the accounts of people in their institution
+        if !is_member(institution_id, user_id, c)? {
+            return Err(LovelaceError::PermissionError(None));
+        }
+        limit::unlock(c, user_id)?;
+        Ok(())
+    })
+    .await
+}
+
+#[post("/<institution_id>/lockouts/<user_id>/unlock")]
+pub async fn html_unlock_account(
+    institution_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    match unlock_account_base(institution_id, user_id, auth, &conn).await {
+        Ok(()) => Html::new().head(default_head("Account unlocked")).body(
+            Body::new().child(
+                Level::new()
+                    .child(H1::new("Account unlocked"))
+                    .child(P::with_text("The account can now be logged in to again."))
+                    .child(
+                        A::new()
+                            .attribute(Href::new(format!(
+                                "/institution/{}/lockouts",
+                                institution_id
+                            )))
+                            .text("Back to locked accounts"),
+                    ),
+            ),
+        ),
+        Err(e) => e.render(),
+    }
+}
+
+#[post("/<institution_id>/lockouts/<user_id>/unlock")]
+pub async fn api_unlock_account(
+    institution_id: i32,
+    user_id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Json<ApiResponse<()>> {
+    Json(
+        match unlock_account_base(institution_id, user_id, auth, &conn).await {
+            Ok(()) => ApiResponse::new_ok(()),
+            Err(e) => From::from(e),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test_lockout {
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+    use rocket::{
+        http::{ContentType, Status},
+        local::asynchronous::Client,
+    };
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        db::Database,
+        institution::test_ctx::{
+            setup_env, ADMIN_EMAIL, ADMIN_PASSWORD, STUDENT_EMAIL, STUDENT_PASSWORD,
+            STUDENT_USERNAME, TEACHER_EMAIL, TEACHER_PASSWORD,
+        },
+        models::auth::limit::NewAuthAttempt,
+        schema::auth_attempt,
+        utils::{client, login_user, logout},
+    };
+
+    async fn attempt_login(client: &Client, password: &str) -> (Status, String) {
+        let res = client
+            .post("/auth/login")
+            .header(ContentType::Form)
+            .body(format!(
+                "identifier={}&password={}",
+                STUDENT_EMAIL, password
+            ))
+            .dispatch()
+            .await;
+        (res.status(), res.into_string().await.unwrap())
+    }
+
+    /// Locks the student's account by failing to log in. To save waiting for the back-off, most of
+    /// the failed attempts are recorded as though they happened a while ago (replacing any which
+    /// have already been made).
+    async fn lock_student_account(client: &Client, student_id: i32) {
+        let mock_server = MockServer::start().await;
+        std::env::set_var("SENDGRID_API_KEY", "SomeRandomAPIKey");
+        std::env::set_var("SENDGRID_API_SERVER", mock_server.uri());
+        Mock::given(method("post"))
+            .and(path_regex("/v3/mail/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::delete(auth_attempt::table.filter(auth_attempt::user_id.eq(student_id)))
+                    .execute(c)
+                    .unwrap();
+                for _ in 0..9 {
+                    diesel::insert_into(auth_attempt::table)
+                        .values(NewAuthAttempt {
+                            action: "login",
+                            user_id: Some(student_id),
+                            ip: None,
+                            created: Utc::now().naive_utc() - Duration::minutes(30),
+                        })
+                        .execute(c)
+                        .unwrap();
+                }
+            })
+            .await;
+        let (status, body) = attempt_login(client, "not-the-password").await;
+        assert_eq!(status, Status::TooManyRequests);
+        assert!(body.contains("temporarily locked"));
+    }
+
+    #[rocket::async_test]
+    async fn test_failed_logins_back_off_and_lock() {
+        let client = client().await;
+        let (_, _, student_id, _, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        for _ in 0..3 {
+            let (status, body) = attempt_login(&client, "not-the-password").await;
+            assert_eq!(status, Status::BadRequest);
+            assert!(body.contains("Invalid password"));
+        }
+        // the correct password is not checked while backing off
+        let (status, _) = attempt_login(&client, STUDENT_PASSWORD).await;
+        assert_eq!(status, Status::TooManyRequests);
+
+        lock_student_account(&client, student_id).await;
+        let (status, body) = attempt_login(&client, STUDENT_PASSWORD).await;
+        assert_eq!(status, Status::TooManyRequests);
+        assert!(body.contains("temporarily locked"));
+    }
+
+    #[rocket::async_test]
+    async fn test_admin_can_unlock_account() {
+        let client = client().await;
+        let (_, _, student_id, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        lock_student_account(&client, student_id).await;
+
+        // teachers can't see or unlock locked accounts
+        login_user(TEACHER_EMAIL, TEACHER_PASSWORD, &client).await;
+        let res = client
+            .post(format!(
+                "/institution/{}/lockouts/{}/unlock",
+                institution_id, student_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        logout(&client).await;
+
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let page = client
+            .get(format!("/institution/{}/lockouts", institution_id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(page.contains(STUDENT_USERNAME));
+        let res = client
+            .post(format!(
+                "/institution/{}/lockouts/{}/unlock",
+                institution_id, student_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        logout(&client).await;
+
+        login_user(STUDENT_EMAIL, STUDENT_PASSWORD, &client).await;
+    }
+}
@@ -1,7 +1,96 @@
+pub mod academic;
+pub mod attendance;
 pub mod class;
 pub mod configure;
 pub mod delete;
+pub mod export;
+pub mod group;
+pub mod invite;
+pub mod lockout;
+pub mod policy;
 pub mod register;
+pub mod roster;
 
 #[cfg(test)]
 pub mod test_ctx;
+
+use diesel::prelude::*;
+
+use crate::{
+    auth::AuthCookie,
+    db::DatabaseConnection,
+    schema::{administrator, institution_student, institution_teacher},
+    utils::error::{LovelaceError, LovelaceResult},
+};
+
+/// Checks that the user is an administrator of the institution.
+pub(crate) fn is_administrator(
+    institution_id: i32,
+    auth: AuthCookie,
+    c: &DatabaseConnection,
+) -> LovelaceResult<()> {
+    let is_admin = diesel::select(diesel::dsl::exists(
+        administrator::table
+            .filter(administrator::institution_id.eq(institution_id))
+            .filter(administrator::user_id.eq(auth.0)),
+    ))
+    .get_result::<bool>(c)
+    .map_err(|e| {
+        error!("{:#?}", e);
+        LovelaceError::DatabaseError
+    })?;
+    if is_admin {
+        Ok(())
+    } else {
+        Err(LovelaceError::PermissionError(None))
+    }
+}
+
+/// Whether the user is an administrator, teacher or student of the institution.
+pub(crate) fn is_member(
+    institution_id: i32,
+    user_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<bool> {
+    diesel::select(
+        diesel::dsl::exists(
+            administrator::table
+                .filter(administrator::institution_id.eq(institution_id))
+                .filter(administrator::user_id.eq(user_id)),
+        )
+        .or(diesel::dsl::exists(
+            institution_teacher::table
+                .filter(institution_teacher::institution_id.eq(institution_id))
+                .filter(institution_teacher::user_id.eq(user_id)),
+        ))
+        .or(diesel::dsl::exists(
+            institution_student::table
+                .filter(institution_student::institution_id.eq(institution_id))
+                .filter(institution_student::user_id.eq(user_id)),
+        )),
+    )
+    .get_result::<bool>(c)
+}
+
+/// Whether the email address is on the domain (or on one of its subdomains). Institutions'
+/// domains are sometimes entered as URLs (e.g. `https://example.com/`), so those are accepted too.
+pub(crate) fn email_on_domain(email: &str, domain: &str) -> bool {
+    let domain = domain.trim().to_lowercase();
+    let domain = domain
+        .split("://")
+        .last()
+        .unwrap_or_default()
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches('@')
+        .to_string();
+    match email.rsplit_once('@') {
+        Some((_, email_domain)) => {
+            let email_domain = email_domain.to_lowercase();
+            !domain.is_empty()
+                && (email_domain == domain || email_domain.ends_with(&format!(".{}", domain)))
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,358 @@
+//! The rules institutions set for what their members can do.
+//!
+//! Administrators can decide who may create classes, whether teachers may add synchronous tasks
+//! and whether only people with an email address on the institution's domain may join. Handlers
+//! should check these through the functions here (rather than reading the flags on the
+//! institution themselves), which explain why something isn't allowed in the
+//! [`LovelaceError::PermissionError`] they return.
+
+use diesel::prelude::*;
+
+use crate::{
+    db::DatabaseConnection,
+    institution::email_on_domain,
+    invite::InviteKind,
+    models::{institution::Institution, Class},
+    schema::{
+        administrator, class, institution, institution_student, institution_teacher, student_group,
+        users,
+    },
+    utils::error::{LovelaceError, LovelaceResult},
+};
+
+/// The part somebody plays in an institution. Administrators who are also teachers (or students)
+/// are treated as administrators.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InstitutionRole {
+    Administrator,
+    Teacher,
+    Student,
+}
+
+fn database_error(e: diesel::result::Error) -> LovelaceError {
+    error!("{:#?}", e);
+    LovelaceError::DatabaseError
+}
+
+fn forbidden(reason: String) -> LovelaceError {
+    LovelaceError::PermissionError(Some(reason))
+}
+
+fn find_institution(institution_id: i32, c: &DatabaseConnection) -> LovelaceResult<Institution> {
+    institution::table
+        .find(institution_id)
+        .first::<Institution>(c)
+        .map_err(database_error)
+}
+
+/// The user's role in the institution (if they're a member of it).
+pub(crate) fn role(
+    user_id: i32,
+    institution_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<Option<InstitutionRole>> {
+    let is_administrator = diesel::select(diesel::dsl::exists(
+        administrator::table
+            .filter(administrator::institution_id.eq(institution_id))
+            .filter(administrator::user_id.eq(user_id)),
+    ))
+    .get_result::<bool>(c)?;
+    if is_administrator {
+        return Ok(Some(InstitutionRole::Administrator));
+    }
+    let is_teacher = diesel::select(diesel::dsl::exists(
+        institution_teacher::table
+            .filter(institution_teacher::institution_id.eq(institution_id))
+            .filter(institution_teacher::user_id.eq(user_id)),
+    ))
+    .get_result::<bool>(c)?;
+    if is_teacher {
+        return Ok(Some(InstitutionRole::Teacher));
+    }
+    let is_student = diesel::select(diesel::dsl::exists(
+        institution_student::table
+            .filter(institution_student::institution_id.eq(institution_id))
+            .filter(institution_student::user_id.eq(user_id)),
+    ))
+    .get_result::<bool>(c)?;
+    Ok(if is_student {
+        Some(InstitutionRole::Student)
+    } else {
+        None
+    })
+}
+
+/// Checks that the user may create classes as part of the institution. Administrators always can;
+/// teachers can if the institution lets teachers (or everybody) create classes, and students can
+/// only if it lets everybody create them.
+pub(crate) fn can_create_class(
+    user_id: i32,
+    institution_id: i32,
+    c: &DatabaseConnection,
+) -> LovelaceResult<()> {
+    let institution = find_institution(institution_id, c)?;
+    let allowed_by = if institution.let_all_users_create_classes {
+        "its members"
+    } else if institution.let_teachers_create_classes {
+        "its teachers and administrators"
+    } else {
+        "its administrators"
+    };
+    let allowed = match role(user_id, institution_id, c).map_err(database_error)? {
+        Some(InstitutionRole::Administrator) => true,
+        Some(InstitutionRole::Teacher) => {
+            institution.let_teachers_create_classes || institution.let_all_users_create_classes
+        }
+        Some(InstitutionRole::Student) => institution.let_all_users_create_classes,
+        None => false,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(forbidden(format!(
+            "{} only lets {} create classes.",
+            institution.name, allowed_by
+        )))
+    }
+}
+
+/// Checks that the user may add synchronous tasks to the class. This only applies the
+/// institution's policy – callers should check that the user teaches the class.
+pub(crate) fn can_add_sync_task(
+    user_id: i32,
+    class_id: i32,
+    c: &DatabaseConnection,
+) -> LovelaceResult<()> {
+    let institution_id = class::table
+        .find(class_id)
+        .select(class::institution_id)
+        .first::<Option<i32>>(c)
+        .map_err(database_error)?;
+    // stand-alone classes aren't subject to anybody's policy
+    let institution_id = match institution_id {
+        Some(institution_id) => institution_id,
+        None => return Ok(()),
+    };
+    let institution = find_institution(institution_id, c)?;
+    if institution.let_teachers_add_sync_tasks
+        || role(user_id, institution_id, c).map_err(database_error)?
+            == Some(InstitutionRole::Administrator)
+    {
+        Ok(())
+    } else {
+        Err(forbidden(format!(
+            "{} only lets its administrators add synchronous tasks to its classes.",
+            institution.name
+        )))
+    }
+}
+
+/// Checks that somebody with this email address is allowed in the institution, given its domain
+/// policy.
+fn check_domain(email: &str, institution: &Institution) -> LovelaceResult<()> {
+    if institution.enforce_same_domain && !email_on_domain(email, &institution.domain) {
+        Err(forbidden(format!(
+            "{} only allows people with an email address on its own domain ({}).",
+            institution.name, institution.domain
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that the user may join the institution. Everybody has to have verified their email
+/// address first, and institutions which enforce this only admit people whose (verified) address
+/// is on the institution's domain.
+pub(crate) fn can_join(
+    user_id: i32,
+    institution_id: i32,
+    c: &DatabaseConnection,
+) -> LovelaceResult<()> {
+    let (email, email_verified) = users::table
+        .find(user_id)
+        .select((users::email, users::email_verified))
+        .first::<(String, bool)>(c)
+        .map_err(database_error)?;
+    if !email_verified {
+        return Err(LovelaceError::EmailNotVerified);
+    }
+    check_domain(&email, &find_institution(institution_id, c)?)
+}
+
+/// Checks that the user may join the class using its join code. Classes which are part of an
+/// institution can only be joined by people the institution would admit (see [`can_join`]).
+pub(crate) fn can_join_class(
+    user_id: i32,
+    class: &Class,
+    c: &DatabaseConnection,
+) -> LovelaceResult<()> {
+    match class.institution_id {
+        Some(institution_id) => can_join(user_id, institution_id, c),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the invitation is one the institution (if any) would allow the user to accept, so
+/// that people aren't invited to things they can't join. Whether the inviting user may send the
+/// invitation is up to the caller.
+pub(crate) fn can_invite(
+    kind: InviteKind,
+    invited_user_id: i32,
+    target_id: i32,
+    c: &DatabaseConnection,
+) -> LovelaceResult<()> {
+    let institution_id = match kind {
+        InviteKind::Administrator
+        | InviteKind::InstitutionTeacher
+        | InviteKind::InstitutionStudent => Some(target_id),
+        InviteKind::ClassTeacher => class::table
+            .find(target_id)
+            .select(class::institution_id)
+            .first::<Option<i32>>(c)
+            .map_err(database_error)?,
+        InviteKind::StudentGroupTeacher => Some(
+            student_group::table
+                .find(target_id)
+                .select(student_group::institution_id)
+                .first::<i32>(c)
+                .map_err(database_error)?,
+        ),
+    };
+    let institution_id = match institution_id {
+        Some(institution_id) => institution_id,
+        None => return Ok(()),
+    };
+    let (username, email) = users::table
+        .find(invited_user_id)
+        .select((users::username, users::email))
+        .first::<(String, String)>(c)
+        .map_err(database_error)?;
+    check_domain(&email, &find_institution(institution_id, c)?).map_err(|_| {
+        forbidden(format!(
+            "{} can't be invited, because their email address isn't on this institution's domain \
+            (and it only allows people with an email address on its domain).",
+            username
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test_policy {
+    use chrono::Utc;
+    use diesel::prelude::*;
+
+    use super::{can_add_sync_task, can_create_class, can_invite, can_join_class};
+    use crate::{
+        db::Database,
+        institution::test_ctx::setup_env,
+        invite::InviteKind,
+        models::{Class, NewClass, NewUser},
+        schema::{class, institution, users},
+        utils::{client, error::LovelaceError},
+    };
+
+    fn is_policy_violation<T>(result: Result<T, LovelaceError>) -> bool {
+        matches!(result, Err(LovelaceError::PermissionError(Some(_))))
+    }
+
+    #[rocket::async_test]
+    async fn test_policies() {
+        let client = client().await;
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                let (admin, teacher, student, institution_id, _) = setup_env(c);
+                let class = diesel::insert_into(class::table)
+                    .values(NewClass {
+                        name: "Maths",
+                        description: "",
+                        created: Utc::now().naive_utc(),
+                        code: "maths",
+                        institution_id: Some(institution_id),
+                        student_group_id: None,
+                        academic_term_id: None,
+                    })
+                    .get_result::<Class>(c)
+                    .unwrap();
+                let outsider = diesel::insert_into(users::table)
+                    .values(NewUser {
+                        username: "outsider",
+                        email: "outsider@elsewhere.example.org",
+                        password: "",
+                        created: Utc::now().naive_utc(),
+                        email_verified: true,
+                        timezone: "Etc/UTC",
+                    })
+                    .returning(users::id)
+                    .get_result::<i32>(c)
+                    .unwrap();
+
+                // the defaults used in the tests
+                assert!(can_create_class(admin, institution_id, c).is_ok());
+                assert!(can_create_class(teacher, institution_id, c).is_ok());
+                assert!(is_policy_violation(can_create_class(
+                    student,
+                    institution_id,
+                    c
+                )));
+                assert!(is_policy_violation(can_create_class(
+                    outsider,
+                    institution_id,
+                    c
+                )));
+                assert!(can_add_sync_task(teacher, class.id, c).is_ok());
+                assert!(can_join_class(outsider, &class, c).is_ok());
+                assert!(
+                    can_invite(InviteKind::InstitutionStudent, outsider, institution_id, c).is_ok()
+                );
+
+                diesel::update(institution::table.find(institution_id))
+                    .set((
+                        institution::let_teachers_create_classes.eq(false),
+                        institution::let_all_users_create_classes.eq(false),
+                        institution::let_teachers_add_sync_tasks.eq(false),
+                        institution::enforce_same_domain.eq(true),
+                        institution::domain.eq("example.com"),
+                    ))
+                    .execute(c)
+                    .unwrap();
+                assert!(can_create_class(admin, institution_id, c).is_ok());
+                assert!(is_policy_violation(can_create_class(
+                    teacher,
+                    institution_id,
+                    c
+                )));
+                assert!(is_policy_violation(can_add_sync_task(teacher, class.id, c)));
+                assert!(can_add_sync_task(admin, class.id, c).is_ok());
+                assert!(is_policy_violation(can_join_class(outsider, &class, c)));
+                assert!(can_join_class(student, &class, c).is_ok());
+                // otherwise anybody could sign up with an address on the domain
+                diesel::update(users::table.find(student))
+                    .set(users::email_verified.eq(false))
+                    .execute(c)
+                    .unwrap();
+                assert!(matches!(
+                    can_join_class(student, &class, c),
+                    Err(LovelaceError::EmailNotVerified)
+                ));
+                diesel::update(users::table.find(student))
+                    .set(users::email_verified.eq(true))
+                    .execute(c)
+                    .unwrap();
+                assert!(is_policy_violation(can_invite(
+                    InviteKind::ClassTeacher,
+                    outsider,
+                    class.id,
+                    c
+                )));
+
+                diesel::update(institution::table.find(institution_id))
+                    .set(institution::let_all_users_create_classes.eq(true))
+                    .execute(c)
+                    .unwrap();
+                assert!(can_create_class(student, institution_id, c).is_ok());
+            })
+            .await;
+    }
+}
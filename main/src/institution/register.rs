@@ -8,7 +8,7 @@ use rocket::serde::json::Json;
 use rocket::FromForm;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{AuthCookie, EmailVerification},
     db::Database,
     models::institution::Institution,
     schema::{administrator, institution},
@@ -56,9 +56,11 @@ pub struct CreateNewInstitutionForm {
 
 async fn register_new_institution(
     auth: AuthCookie,
+    verification: EmailVerification,
     conn: Database,
     data: &CreateNewInstitutionForm,
 ) -> Result<Institution, LovelaceError> {
+    verification.require()?;
     let name = data.name.clone();
     let domain = data.domain.clone();
     conn.run(move |c| {
@@ -72,6 +74,8 @@ async fn register_new_institution(
                 let_teachers_create_classes: false,
                 let_all_users_create_classes: false,
                 let_teachers_add_sync_tasks: false,
+                require_two_factor: false,
+                attendance_threshold: None,
             })
             .returning(institution::all_columns)
             .get_result::<Institution>(c)
@@ -99,10 +103,11 @@ async fn register_new_institution(
 #[post("/register", data = "<form>")]
 pub async fn html_register_new_institution(
     auth: AuthCookie,
+    verification: EmailVerification,
     form: rocket::form::Form<CreateNewInstitutionForm>,
     conn: Database,
 ) -> Html {
-    match register_new_institution(auth, conn, &form).await {
+    match register_new_institution(auth, verification, conn, &form).await {
         Ok(institution) => Html::new()
             .head(default_head("Successfully registered."))
             .body(
@@ -121,19 +126,21 @@ pub async fn html_register_new_institution(
 #[post("/register", data = "<form>")]
 pub async fn api_register_new_institution(
     auth: AuthCookie,
+    verification: EmailVerification,
     form: Json<CreateNewInstitutionForm>,
     conn: Database,
 ) -> Json<ApiResponse<Institution>> {
-    Json(match register_new_institution(auth, conn, &form).await {
-        Ok(institution) => ApiResponse::new_ok(institution),
-        Err(e) => From::from(e),
-    })
+    Json(
+        match register_new_institution(auth, verification, conn, &form).await {
+            Ok(institution) => ApiResponse::new_ok(institution),
+            Err(e) => From::from(e),
+        },
+    )
 }
 
 #[cfg(test)]
 mod test_register_institution {
     use crate::utils::login_user;
-    use bcrypt::DEFAULT_COST;
     use chrono::Utc;
     use diesel::prelude::*;
     use rocket::http::ContentType;
@@ -157,7 +164,7 @@ mod test_register_institution {
                 .values(NewUser {
                     username: USERNAME,
                     email: EMAIL,
-                    password: &bcrypt::hash(PASSWORD, DEFAULT_COST).unwrap(),
+                    password: &crate::auth::hash::hash_password(PASSWORD).unwrap(),
                     created: Utc::now().naive_utc(),
                     email_verified: true,
                     timezone: TIMEZONE,
@@ -0,0 +1,667 @@
+//! Importing a [`Roster`] into an institution.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use malvolio::prelude::*;
+
+use super::{Roster, RosterError, RosterRole, RosterUser};
+use crate::{
+    auth::{hash::hash_password, verify::send_verification_email, AuthCookie},
+    db::{Database, DatabaseConnection},
+    email::{EmailBuilder, RecipientBuilder, RecipientsBuilder, SendMail, SendgridMailSender},
+    institution::{email_on_domain, group::cascade_enrolments, is_administrator},
+    invite::{create_invite, has_pending_invite, send_invite_email, InviteError, InviteKind},
+    models::{
+        institution::{
+            roster::NewRosterLink,
+            student::NewInstitutionStudent,
+            student_group::{student::NewStudentGroupStudent, NewStudentGroup},
+            teacher::NewInstitutionTeacher,
+        },
+        NewClass, NewClassStudent, NewClassTeacher, NewUser, User,
+    },
+    schema::{
+        class, class_student, class_teacher, institution, institution_student, institution_teacher,
+        roster_link, student_group, student_group_student, users,
+    },
+    utils::default_head,
+};
+
+/// The timezone given to accounts created by an import (people can change it afterwards).
+const DEFAULT_TIMEZONE: &str = "Etc/UTC";
+
+/// What an import did (or, if it hasn't been applied, would do).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    /// Accounts which will be created for people who don't have one yet.
+    pub new_accounts: Vec<String>,
+    /// People who will become students of the institution.
+    pub new_students: Vec<String>,
+    /// People who will become teachers at the institution.
+    pub new_teachers: Vec<String>,
+    /// People who already have an account, and will be invited to join the institution.
+    pub invitations: Vec<String>,
+    /// People who have already been invited to join the institution (and haven't answered yet),
+    /// who aren't invited again.
+    #[serde(default)]
+    pub already_invited: Vec<String>,
+    pub new_groups: Vec<String>,
+    /// People who will be added to student groups.
+    pub new_group_members: Vec<String>,
+    pub new_classes: Vec<String>,
+    /// People who will be added to classes.
+    pub new_enrolments: Vec<String>,
+    /// Parts of the roster which won't be imported, and why.
+    pub problems: Vec<String>,
+}
+
+impl ImportReport {
+    /// Whether importing the roster doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.new_accounts.is_empty()
+            && self.new_students.is_empty()
+            && self.new_teachers.is_empty()
+            && self.invitations.is_empty()
+            && self.new_groups.is_empty()
+            && self.new_group_members.is_empty()
+            && self.new_classes.is_empty()
+            && self.new_enrolments.is_empty()
+    }
+}
+
+/// The people who should be emailed once an import has been applied.
+#[derive(Default)]
+struct Welcome {
+    /// People whose accounts were created by the import.
+    new_accounts: Vec<User>,
+    /// People who already had an account, with the inviting user's username and a description of
+    /// what they have been invited to.
+    invited: Vec<(User, String, String)>,
+}
+
+const USER: &str = "user";
+const GROUP: &str = "group";
+const CLASS: &str = "class";
+
+/// Looks up the row which an identifier from the roster was imported as, if it still exists.
+fn linked(
+    institution_id: i32,
+    kind: &str,
+    source_id: &str,
+    c: &DatabaseConnection,
+) -> QueryResult<Option<i32>> {
+    let target_id = match roster_link::table
+        .filter(roster_link::institution_id.eq(institution_id))
+        .filter(roster_link::kind.eq(kind))
+        .filter(roster_link::source_id.eq(source_id))
+        .select(roster_link::target_id)
+        .first::<i32>(c)
+        .optional()?
+    {
+        Some(target_id) => target_id,
+        None => return Ok(None),
+    };
+    let exists = match kind {
+        USER => diesel::select(diesel::dsl::exists(users::table.find(target_id))).get_result(c)?,
+        GROUP => diesel::select(diesel::dsl::exists(
+            student_group::table
+                .find(target_id)
+                .filter(student_group::institution_id.eq(institution_id)),
+        ))
+        .get_result(c)?,
+        _ => diesel::select(diesel::dsl::exists(
+            class::table
+                .find(target_id)
+                .filter(class::institution_id.eq(institution_id)),
+        ))
+        .get_result(c)?,
+    };
+    Ok(if exists { Some(target_id) } else { None })
+}
+
+fn link(
+    institution_id: i32,
+    kind: &str,
+    source_id: &str,
+    target_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<()> {
+    diesel::insert_into(roster_link::table)
+        .values(NewRosterLink {
+            institution_id,
+            kind,
+            source_id,
+            target_id,
+        })
+        .on_conflict((
+            roster_link::institution_id,
+            roster_link::kind,
+            roster_link::source_id,
+        ))
+        .do_update()
+        .set(roster_link::target_id.eq(target_id))
+        .execute(c)
+        .map(drop)
+}
+
+fn username_taken(username: &str, c: &DatabaseConnection) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+        users::table.filter(users::username.eq(username)),
+    ))
+    .get_result(c)
+}
+
+/// Makes up a username (which isn't already taken) from an email address.
+fn derive_username(email: &str, c: &DatabaseConnection) -> QueryResult<String> {
+    let base = email
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect::<String>();
+    let base = if base.is_empty() {
+        "user".to_string()
+    } else {
+        base
+    };
+    let mut candidate = base.clone();
+    let mut suffix = 1;
+    while username_taken(&candidate, c)? {
+        suffix += 1;
+        candidate = format!("{}{}", base, suffix);
+    }
+    Ok(candidate)
+}
+
+/// Finds (or creates) the account for somebody in the roster.
+///
+/// `password` is the hash every created account is given; it is the hash of a random password
+/// which nobody knows, so people set their own password by resetting it.
+fn find_or_create_user(
+    institution_id: i32,
+    roster_user: &RosterUser,
+    password: &str,
+    report: &mut ImportReport,
+    welcome: &mut Welcome,
+    c: &DatabaseConnection,
+) -> Result<Option<User>, RosterError> {
+    if let Some(user_id) = linked(institution_id, USER, &roster_user.source_id, c)? {
+        return Ok(Some(users::table.find(user_id).first::<User>(c)?));
+    }
+    let email = roster_user.email.to_lowercase();
+    let existing = users::table
+        .filter(users::email.eq(&roster_user.email))
+        .or_filter(users::email.eq(&email))
+        .first::<User>(c)
+        .optional()?;
+    let user = match existing {
+        Some(user) => user,
+        None => {
+            let username = match &roster_user.username {
+                Some(username) if username_taken(username, c)? => {
+                    report.problems.push(format!(
+                        "{}: the username \"{}\" is already used by somebody else.",
+                        roster_user.email, username
+                    ));
+                    return Ok(None);
+                }
+                Some(username) => username.clone(),
+                None => derive_username(&email, c)?,
+            };
+            let user = diesel::insert_into(users::table)
+                .values(NewUser {
+                    username: &username,
+                    email: &roster_user.email,
+                    password,
+                    created: Utc::now().naive_utc(),
+                    email_verified: false,
+                    timezone: DEFAULT_TIMEZONE,
+                })
+                .returning(users::all_columns)
+                .get_result::<User>(c)?;
+            report
+                .new_accounts
+                .push(format!("{} ({})", user.username, user.email));
+            welcome.new_accounts.push(user.clone());
+            user
+        }
+    };
+    link(institution_id, USER, &roster_user.source_id, user.id, c)?;
+    Ok(Some(user))
+}
+
+/// Whether the user is already a student or teacher (whichever `role` is) of the institution.
+fn has_role(
+    institution_id: i32,
+    user: &User,
+    role: RosterRole,
+    c: &DatabaseConnection,
+) -> QueryResult<bool> {
+    match role {
+        RosterRole::Student => diesel::select(diesel::dsl::exists(
+            institution_student::table
+                .filter(institution_student::institution_id.eq(institution_id))
+                .filter(institution_student::user_id.eq(user.id)),
+        ))
+        .get_result(c),
+        RosterRole::Teacher => diesel::select(diesel::dsl::exists(
+            institution_teacher::table
+                .filter(institution_teacher::institution_id.eq(institution_id))
+                .filter(institution_teacher::user_id.eq(user.id)),
+        ))
+        .get_result(c),
+    }
+}
+
+/// Makes the user (whose account was created by this import) a student or teacher of the
+/// institution.
+fn add_member(
+    institution_id: i32,
+    user: &User,
+    role: RosterRole,
+    report: &mut ImportReport,
+    c: &DatabaseConnection,
+) -> QueryResult<()> {
+    match role {
+        RosterRole::Student => {
+            diesel::insert_into(institution_student::table)
+                .values(NewInstitutionStudent {
+                    user_id: user.id,
+                    institution_id,
+                })
+                .execute(c)?;
+            report.new_students.push(user.username.clone());
+        }
+        RosterRole::Teacher => {
+            diesel::insert_into(institution_teacher::table)
+                .values(NewInstitutionTeacher {
+                    user_id: user.id,
+                    institution_id,
+                })
+                .execute(c)?;
+            report.new_teachers.push(user.username.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Invites somebody who already has an account to join the institution. Adding them without
+/// asking would let an administrator take anybody with a Lovelace account into their institution
+/// (and, for example, export their data).
+fn invite_member(
+    institution_id: i32,
+    inviting_user_id: i32,
+    user: &User,
+    role: RosterRole,
+    report: &mut ImportReport,
+    welcome: &mut Welcome,
+    c: &DatabaseConnection,
+) -> Result<(), RosterError> {
+    let kind = match role {
+        RosterRole::Student => InviteKind::InstitutionStudent,
+        RosterRole::Teacher => InviteKind::InstitutionTeacher,
+    };
+    let described = format!(
+        "{} (as a {})",
+        user.username,
+        match role {
+            RosterRole::Student => "student",
+            RosterRole::Teacher => "teacher",
+        }
+    );
+    // otherwise they would get another notification and email every time the roster is imported
+    if has_pending_invite(kind, institution_id, user.id, c)? {
+        report.already_invited.push(described);
+        return Ok(());
+    }
+    match create_invite(kind, institution_id, inviting_user_id, user.id, c) {
+        Ok((inviting_username, description)) => {
+            report.invitations.push(described);
+            welcome
+                .invited
+                .push((user.clone(), inviting_username, description));
+            Ok(())
+        }
+        Err(InviteError::PermissionError(reason)) => {
+            report.problems.push(format!(
+                "{}: {}",
+                user.email,
+                reason.unwrap_or_else(|| "they can't be invited.".to_string())
+            ));
+            Ok(())
+        }
+        Err(_) => Err(RosterError::DatabaseError),
+    }
+}
+
+/// Imports the roster. Everything which already exists is left as it is.
+fn run_import(
+    institution_id: i32,
+    inviting_user_id: i32,
+    roster: &Roster,
+    c: &DatabaseConnection,
+) -> Result<(ImportReport, Welcome), RosterError> {
+    let mut report = ImportReport {
+        problems: roster.problems.clone(),
+        ..Default::default()
+    };
+    let mut welcome = Welcome::default();
+    let (domain, enforce_same_domain) = institution::table
+        .find(institution_id)
+        .select((institution::domain, institution::enforce_same_domain))
+        .first::<(String, bool)>(c)
+        .optional()?
+        .ok_or(RosterError::InstitutionNotFound)?;
+    // hashing a password takes a while, so everybody gets the same (unusable) one
+    let password = hash_password(&nanoid!(32)).map_err(|e| {
+        error!("{:#?}", e);
+        RosterError::DatabaseError
+    })?;
+
+    let mut users = HashMap::new();
+    for roster_user in &roster.users {
+        if enforce_same_domain && !email_on_domain(&roster_user.email, &domain) {
+            report.problems.push(format!(
+                "{}: this institution only admits people with an email address on {}.",
+                roster_user.email, domain
+            ));
+            continue;
+        }
+        let user = match find_or_create_user(
+            institution_id,
+            roster_user,
+            &password,
+            &mut report,
+            &mut welcome,
+            c,
+        )? {
+            Some(user) => user,
+            None => continue,
+        };
+        if welcome.new_accounts.iter().any(|new| new.id == user.id) {
+            add_member(institution_id, &user, roster_user.role, &mut report, c)?;
+        } else if !has_role(institution_id, &user, roster_user.role, c)? {
+            // they'll be added to their groups and classes when the roster is next imported (if
+            // they have accepted the invitation by then)
+            invite_member(
+                institution_id,
+                inviting_user_id,
+                &user,
+                roster_user.role,
+                &mut report,
+                &mut welcome,
+                c,
+            )?;
+            continue;
+        }
+        users.insert(roster_user.source_id.as_str(), user);
+    }
+
+    let mut groups = HashMap::new();
+    for group in &roster.groups {
+        let group_id = match linked(institution_id, GROUP, &group.source_id, c)? {
+            Some(group_id) => group_id,
+            None => {
+                let existing = student_group::table
+                    .filter(student_group::institution_id.eq(institution_id))
+                    .filter(student_group::name.eq(&group.name))
+                    .select(student_group::id)
+                    .first::<i32>(c)
+                    .optional()?;
+                let group_id = match existing {
+                    Some(group_id) => group_id,
+                    None => {
+                        report.new_groups.push(group.name.clone());
+                        diesel::insert_into(student_group::table)
+                            .values(NewStudentGroup {
+                                parent_group: None,
+                                institution_id,
+                                code: None,
+                                name: &group.name,
+                                description: "",
+                            })
+                            .returning(student_group::id)
+                            .get_result::<i32>(c)?
+                    }
+                };
+                link(institution_id, GROUP, &group.source_id, group_id, c)?;
+                group_id
+            }
+        };
+        groups.insert(group.source_id.as_str(), (group_id, group.name.as_str()));
+    }
+
+    let mut changed_groups = vec![];
+    for roster_user in &roster.users {
+        let user = match users.get(roster_user.source_id.as_str()) {
+            Some(user) => user,
+            None => continue,
+        };
+        for group in &roster_user.groups {
+            let (group_id, name) = match groups.get(group.as_str()) {
+                Some(&group) => group,
+                None => continue,
+            };
+            let exists = diesel::select(diesel::dsl::exists(
+                student_group_student::table
+                    .filter(student_group_student::student_group_id.eq(group_id))
+                    .filter(student_group_student::user_id.eq(user.id)),
+            ))
+            .get_result::<bool>(c)?;
+            if !exists {
+                diesel::insert_into(student_group_student::table)
+                    .values(NewStudentGroupStudent {
+                        user_id: user.id,
+                        student_group_id: group_id,
+                    })
+                    .execute(c)?;
+                report
+                    .new_group_members
+                    .push(format!("{} (in {})", user.username, name));
+                changed_groups.push(group_id);
+            }
+        }
+    }
+
+    let mut classes = HashMap::new();
+    for roster_class in &roster.classes {
+        let class_id = match linked(institution_id, CLASS, &roster_class.source_id, c)? {
+            Some(class_id) => class_id,
+            None => {
+                let existing = class::table
+                    .filter(class::institution_id.eq(institution_id))
+                    .filter(class::name.eq(&roster_class.name))
+                    .select(class::id)
+                    .first::<i32>(c)
+                    .optional()?;
+                let class_id = match existing {
+                    Some(class_id) => class_id,
+                    None => {
+                        report.new_classes.push(roster_class.name.clone());
+                        diesel::insert_into(class::table)
+                            .values(NewClass {
+                                name: &roster_class.name,
+                                description: &roster_class.description,
+                                created: Utc::now().naive_utc(),
+                                code: &nanoid!(5),
+                                institution_id: Some(institution_id),
+                                student_group_id: None,
+                                academic_term_id: None,
+                            })
+                            .returning(class::id)
+                            .get_result::<i32>(c)?
+                    }
+                };
+                link(institution_id, CLASS, &roster_class.source_id, class_id, c)?;
+                class_id
+            }
+        };
+        classes.insert(
+            roster_class.source_id.as_str(),
+            (class_id, roster_class.name.as_str()),
+        );
+    }
+
+    for enrolment in &roster.enrolments {
+        let (user, (class_id, name)) = match (
+            users.get(enrolment.user.as_str()),
+            classes.get(enrolment.class.as_str()),
+        ) {
+            (Some(user), Some(&class)) => (user, class),
+            _ => continue,
+        };
+        let added = match enrolment.role {
+            RosterRole::Student => {
+                let exists = diesel::select(diesel::dsl::exists(
+                    class_student::table
+                        .filter(class_student::class_id.eq(class_id))
+                        .filter(class_student::user_id.eq(user.id)),
+                ))
+                .get_result::<bool>(c)?;
+                if !exists {
+                    diesel::insert_into(class_student::table)
+                        .values(NewClassStudent {
+                            user_id: user.id,
+                            class_id,
+                        })
+                        .execute(c)?;
+                }
+                !exists
+            }
+            RosterRole::Teacher => {
+                let exists = diesel::select(diesel::dsl::exists(
+                    class_teacher::table
+                        .filter(class_teacher::class_id.eq(class_id))
+                        .filter(class_teacher::user_id.eq(user.id)),
+                ))
+                .get_result::<bool>(c)?;
+                if !exists {
+                    diesel::insert_into(class_teacher::table)
+                        .values(NewClassTeacher {
+                            user_id: user.id,
+                            class_id,
+                        })
+                        .execute(c)?;
+                }
+                !exists
+            }
+        };
+        if added {
+            report.new_enrolments.push(format!(
+                "{} (in {}, as a {})",
+                user.username,
+                name,
+                match enrolment.role {
+                    RosterRole::Student => "student",
+                    RosterRole::Teacher => "teacher",
+                }
+            ));
+        }
+    }
+
+    // people who joined a group also join the classes attached to it
+    changed_groups.sort_unstable();
+    changed_groups.dedup();
+    for group_id in changed_groups {
+        cascade_enrolments(group_id, c)?;
+    }
+
+    Ok((report, welcome))
+}
+
+/// Imports the roster into the institution. If `apply` is false, nothing is changed, and the
+/// report describes what would happen if it were applied.
+pub async fn import_roster(
+    institution_id: i32,
+    auth: AuthCookie,
+    roster: Roster,
+    apply: bool,
+    conn: &Database,
+) -> Result<ImportReport, RosterError> {
+    let (report, welcome, institution_name) = conn
+        .run(move |c| {
+            is_administrator(institution_id, auth, c)?;
+            let institution_name = institution::table
+                .find(institution_id)
+                .select(institution::name)
+                .first::<String>(c)
+                .optional()?
+                .ok_or(RosterError::InstitutionNotFound)?;
+            // a dry run is done in a transaction which is then rolled back, so that it does exactly
+            // what applying the import would do
+            let mut dry_run = None;
+            let res = c.transaction::<_, RosterError, _>(|| {
+                let (report, welcome) = run_import(institution_id, auth.0, &roster, c)?;
+                if apply {
+                    Ok((report, welcome))
+                } else {
+                    dry_run = Some(report);
+                    Err(RosterError::RolledBack)
+                }
+            });
+            match res {
+                Ok((report, welcome)) => Ok((report, welcome, institution_name)),
+                Err(RosterError::RolledBack) => Ok((
+                    dry_run.take().unwrap_or_default(),
+                    Welcome::default(),
+                    institution_name,
+                )),
+                Err(e) => Err(e),
+            }
+        })
+        .await?;
+    for user in &welcome.new_accounts {
+        send_welcome_email(user, &institution_name).await;
+        if let Err(e) = send_verification_email(user).await {
+            error!("failed to send verification email: {:#?}", e);
+        }
+    }
+    for (user, inviting_username, description) in &welcome.invited {
+        send_invite_email(user, inviting_username, description).await;
+    }
+    Ok(report)
+}
+
+/// Lets somebody know that the import created an account for them, and how to start using it.
+async fn send_welcome_email(user: &User, institution_name: &str) {
+    let message = format!(
+        "{} has created a Lovelace account for you, with the username {}. To start using it, \
+        choose a password by going to /auth/reset and entering your email address. We'll also \
+        send you a separate email to verify your email address.",
+        institution_name, user.username
+    );
+    let subject = format!("Welcome to {} on Lovelace", institution_name);
+    if let Err(e) = SendgridMailSender::default()
+        .send(
+            &EmailBuilder::default()
+                .subject(subject.clone())
+                .plaintext(Some(message.clone()))
+                .html_text(Some(
+                    Html::new()
+                        .head(default_head(subject))
+                        .body(Body::new().child(P::with_text(message)))
+                        .to_string(),
+                ))
+                .recipients(
+                    RecipientsBuilder::default()
+                        .recipients(vec![RecipientBuilder::default()
+                            .email(user.email.clone())
+                            .name(user.username.clone())
+                            .build()
+                            .unwrap()])
+                        .build()
+                        .unwrap(),
+                )
+                .from(("Lovelace".to_string(), "no-reply@lovelace.ga".to_string()))
+                .reply_to(("Lovelace".to_string(), "contact@lovelace.ga".to_string()))
+                .build()
+                .unwrap(),
+        )
+        .await
+    {
+        error!("failed to send welcome email: {:#?}", e);
+    }
+}
@@ -0,0 +1,176 @@
+//! Importing an institution's students, teachers, student groups and classes in bulk.
+//!
+//! Administrators can upload either a plain CSV file (with one row per person) or the `users.csv`,
+//! `classes.csv` and `enrollments.csv` files from a OneRoster 1.1 CSV bundle. Both are parsed into
+//! a [`Roster`], which is then imported. Before anything is changed the administrator is shown
+//! what the import would do; they can then apply it (which happens in a single transaction).
+//!
+//! Importing the same roster twice doesn't create anything twice – every imported person, group
+//! and class is linked (in the `roster_link` table) to the identifier it was imported from, and
+//! everything which already exists is left as it is.
+//!
+//! Accounts are only created for people who don't have one yet. People who already have an account
+//! (but aren't yet a member of the institution in the role the roster gives them) are sent an
+//! invitation instead, and are only added to their groups and classes by a later import, once they
+//! have accepted it.
+
+pub mod import;
+pub mod parse;
+pub mod upload;
+
+use malvolio::prelude::*;
+use rocket::http::Status;
+use thiserror::Error as ThisError;
+
+use crate::utils::{default_head, error::LovelaceError};
+
+/// The role somebody has at the institution.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RosterRole {
+    Student,
+    Teacher,
+}
+
+impl RosterRole {
+    fn parse(role: &str) -> Option<Self> {
+        match role.trim().to_lowercase().as_str() {
+            "student" => Some(RosterRole::Student),
+            "teacher" => Some(RosterRole::Teacher),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RosterUser {
+    /// The identifier the school uses for this person.
+    pub source_id: String,
+    /// If this isn't provided, one is made up from their email address.
+    pub username: Option<String>,
+    pub email: String,
+    pub role: RosterRole,
+    /// The `source_id`s of the student groups this person is part of.
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RosterGroup {
+    pub source_id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RosterClass {
+    pub source_id: String,
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RosterEnrolment {
+    /// The `source_id` of the user.
+    pub user: String,
+    /// The `source_id` of the class.
+    pub class: String,
+    pub role: RosterRole,
+}
+
+/// An institution's roster, in a form which doesn't depend on the format it was uploaded in.
+#[derive(Debug, Clone, Default)]
+pub struct Roster {
+    pub users: Vec<RosterUser>,
+    pub groups: Vec<RosterGroup>,
+    pub classes: Vec<RosterClass>,
+    pub enrolments: Vec<RosterEnrolment>,
+    /// Rows which won't be imported, and why.
+    pub problems: Vec<String>,
+}
+
+#[derive(ThisError, Debug)]
+pub enum RosterError {
+    #[error("permission error")]
+    PermissionError,
+    #[error("institution not found")]
+    InstitutionNotFound,
+    #[error("a file is missing")]
+    MissingFile(&'static str),
+    #[error("a column is missing")]
+    MissingColumn {
+        file: &'static str,
+        column: &'static str,
+    },
+    #[error("invalid csv")]
+    InvalidCsv(&'static str),
+    #[error("invalid upload")]
+    InvalidUpload,
+    #[error("database error")]
+    DatabaseError,
+    /// Used to roll back the transaction a dry run is done in (this is never returned from
+    /// [`import::import_roster`]).
+    #[error("rolled back")]
+    RolledBack,
+}
+
+impl From<diesel::result::Error> for RosterError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl From<LovelaceError> for RosterError {
+    fn from(e: LovelaceError) -> Self {
+        match e {
+            LovelaceError::PermissionError(_) => Self::PermissionError,
+            _ => Self::DatabaseError,
+        }
+    }
+}
+
+impl RosterError {
+    fn status(&self) -> Status {
+        match self {
+            RosterError::PermissionError => Status::Forbidden,
+            RosterError::InstitutionNotFound => Status::NotFound,
+            RosterError::MissingFile(_)
+            | RosterError::MissingColumn { .. }
+            | RosterError::InvalidCsv(_)
+            | RosterError::InvalidUpload => Status::BadRequest,
+            RosterError::DatabaseError | RosterError::RolledBack => Status::InternalServerError,
+        }
+    }
+
+    fn explanation(&self) -> String {
+        match self {
+            RosterError::PermissionError => {
+                "Only administrators of this institution can import its roster.".to_string()
+            }
+            RosterError::InstitutionNotFound => "That institution doesn't exist.".to_string(),
+            RosterError::MissingFile(file) => format!("Please upload `{}`.", file),
+            RosterError::MissingColumn { file, column } => format!(
+                "`{}` doesn't have a `{}` column (the first row should contain the name of each \
+                column).",
+                file, column
+            ),
+            RosterError::InvalidCsv(file) => format!("`{}` isn't a valid CSV file.", file),
+            RosterError::InvalidUpload => {
+                "We couldn't read that upload. Please try uploading the roster again.".to_string()
+            }
+            RosterError::DatabaseError | RosterError::RolledBack => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+        }
+    }
+
+    fn render(self, title: &'static str) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head(title))
+            .body(
+                Body::new()
+                    .child(H1::new(title))
+                    .child(P::with_text(self.explanation())),
+            )
+    }
+}
@@ -0,0 +1,366 @@
+//! Parsing rosters.
+//!
+//! The plain CSV format has one row per person, with these columns (in any order):
+//!
+//! - `email` (required)
+//! - `role` (required), either `student` or `teacher`
+//! - `username`
+//! - `group`, the name of the student group they are part of
+//! - `classes`, the names of the classes they are part of (separated by semicolons)
+//!
+//! People are identified by their email address, and groups and classes by their names.
+//!
+//! From a OneRoster bundle, only students and teachers are imported (along with their classes and
+//! enrollments). Students are put into a student group for each grade they are in.
+
+use std::collections::{HashMap, HashSet};
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+
+use super::{
+    Roster, RosterClass, RosterEnrolment, RosterError, RosterGroup, RosterRole, RosterUser,
+};
+use crate::auth::register::EMAIL_RE;
+
+/// A CSV file, with its columns looked up by name.
+struct Table {
+    file: &'static str,
+    columns: HashMap<String, usize>,
+    rows: Vec<StringRecord>,
+}
+
+impl Table {
+    fn read(file: &'static str, data: &str) -> Result<Self, RosterError> {
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let columns = reader
+            .headers()
+            .map_err(|_| RosterError::InvalidCsv(file))?
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.trim_start_matches('\u{feff}').to_lowercase(), i))
+            .collect();
+        let rows = reader
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| RosterError::InvalidCsv(file))?;
+        Ok(Self {
+            file,
+            columns,
+            rows,
+        })
+    }
+
+    /// Checks that the columns exist.
+    fn require(&self, columns: &[&'static str]) -> Result<(), RosterError> {
+        match columns
+            .iter()
+            .find(|column| !self.columns.contains_key(&column.to_lowercase()))
+        {
+            Some(column) => Err(RosterError::MissingColumn {
+                file: self.file,
+                column,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The value in a column of a row (or an empty string, if there isn't one).
+    fn get<'a>(&self, row: &'a StringRecord, column: &str) -> &'a str {
+        self.columns
+            .get(&column.to_lowercase())
+            .and_then(|&i| row.get(i))
+            .unwrap_or_default()
+    }
+
+    /// The rows, numbered as they would be in a spreadsheet (so the first row after the column
+    /// names is row 2).
+    fn numbered_rows(&self) -> impl Iterator<Item = (usize, &StringRecord)> {
+        self.rows.iter().enumerate().map(|(i, row)| (i + 2, row))
+    }
+}
+
+fn valid_email(email: &str) -> bool {
+    EMAIL_RE.is_match(&email.to_lowercase())
+}
+
+/// Parses the plain CSV format (see the module documentation).
+pub fn parse_csv(data: &str) -> Result<Roster, RosterError> {
+    let table = Table::read("roster.csv", data)?;
+    table.require(&["email", "role"])?;
+    let mut roster = Roster::default();
+    let mut groups = HashSet::new();
+    let mut classes = HashSet::new();
+    let mut emails = HashSet::new();
+    for (number, row) in table.numbered_rows() {
+        let email = table.get(row, "email");
+        if !valid_email(email) {
+            roster.problems.push(format!(
+                "Row {}: \"{}\" isn't a valid email address.",
+                number, email
+            ));
+            continue;
+        }
+        if !emails.insert(email.to_lowercase()) {
+            roster.problems.push(format!(
+                "Row {}: {} is already in the roster (on an earlier row).",
+                number, email
+            ));
+            continue;
+        }
+        let role = match RosterRole::parse(table.get(row, "role")) {
+            Some(role) => role,
+            None => {
+                roster.problems.push(format!(
+                    "Row {}: the role should be either \"student\" or \"teacher\".",
+                    number
+                ));
+                continue;
+            }
+        };
+        let group = table.get(row, "group");
+        if !group.is_empty() && groups.insert(group.to_string()) {
+            roster.groups.push(RosterGroup {
+                source_id: group.to_string(),
+                name: group.to_string(),
+            });
+        }
+        for class in table
+            .get(row, "classes")
+            .split(';')
+            .map(str::trim)
+            .filter(|class| !class.is_empty())
+        {
+            if classes.insert(class.to_string()) {
+                roster.classes.push(RosterClass {
+                    source_id: class.to_string(),
+                    name: class.to_string(),
+                    description: String::new(),
+                });
+            }
+            roster.enrolments.push(RosterEnrolment {
+                user: email.to_lowercase(),
+                class: class.to_string(),
+                role,
+            });
+        }
+        let username = table.get(row, "username");
+        roster.users.push(RosterUser {
+            source_id: email.to_lowercase(),
+            username: if username.is_empty() {
+                None
+            } else {
+                Some(username.to_string())
+            },
+            email: email.to_string(),
+            role,
+            groups: if group.is_empty() {
+                vec![]
+            } else {
+                vec![group.to_string()]
+            },
+        });
+    }
+    Ok(roster)
+}
+
+/// OneRoster marks rows which have been removed from the management information system (rather
+/// than leaving them out).
+fn to_be_deleted(table: &Table, row: &StringRecord) -> bool {
+    table.get(row, "status").eq_ignore_ascii_case("tobedeleted")
+}
+
+/// Parses the `users.csv`, `classes.csv` and `enrollments.csv` files from a OneRoster 1.1 CSV
+/// bundle.
+pub fn parse_oneroster(
+    users: &str,
+    classes: &str,
+    enrollments: &str,
+) -> Result<Roster, RosterError> {
+    let users = Table::read("users.csv", users)?;
+    users.require(&["sourcedId", "role", "username", "email"])?;
+    let classes = Table::read("classes.csv", classes)?;
+    classes.require(&["sourcedId", "title"])?;
+    let enrollments = Table::read("enrollments.csv", enrollments)?;
+    enrollments.require(&["classSourcedId", "userSourcedId", "role"])?;
+
+    let mut roster = Roster::default();
+    let mut groups = HashSet::new();
+    let mut user_ids = HashSet::new();
+    for (number, row) in users.numbered_rows() {
+        if to_be_deleted(&users, row) {
+            continue;
+        }
+        let source_id = users.get(row, "sourcedId");
+        let email = users.get(row, "email");
+        let role = match RosterRole::parse(users.get(row, "role")) {
+            Some(role) => role,
+            None => {
+                roster.problems.push(format!(
+                    "users.csv row {}: only students and teachers are imported (not {}s).",
+                    number,
+                    users.get(row, "role")
+                ));
+                continue;
+            }
+        };
+        if source_id.is_empty() || !user_ids.insert(source_id.to_string()) {
+            roster.problems.push(format!(
+                "users.csv row {}: every user needs a different sourcedId.",
+                number
+            ));
+            continue;
+        }
+        if !valid_email(email) {
+            roster.problems.push(format!(
+                "users.csv row {}: \"{}\" isn't a valid email address.",
+                number, email
+            ));
+            continue;
+        }
+        let user_groups = match role {
+            RosterRole::Student => users
+                .get(row, "grades")
+                .split(',')
+                .map(str::trim)
+                .filter(|grade| !grade.is_empty())
+                .map(|grade| {
+                    let source_id = format!("grade:{}", grade);
+                    if groups.insert(source_id.clone()) {
+                        roster.groups.push(RosterGroup {
+                            source_id: source_id.clone(),
+                            name: format!("Grade {}", grade),
+                        });
+                    }
+                    source_id
+                })
+                .collect(),
+            RosterRole::Teacher => vec![],
+        };
+        let username = users.get(row, "username");
+        roster.users.push(RosterUser {
+            source_id: source_id.to_string(),
+            username: if username.is_empty() {
+                None
+            } else {
+                Some(username.to_string())
+            },
+            email: email.to_string(),
+            role,
+            groups: user_groups,
+        });
+    }
+
+    let mut class_ids = HashSet::new();
+    for (number, row) in classes.numbered_rows() {
+        if to_be_deleted(&classes, row) {
+            continue;
+        }
+        let source_id = classes.get(row, "sourcedId");
+        if source_id.is_empty() || !class_ids.insert(source_id.to_string()) {
+            roster.problems.push(format!(
+                "classes.csv row {}: every class needs a different sourcedId.",
+                number
+            ));
+            continue;
+        }
+        roster.classes.push(RosterClass {
+            source_id: source_id.to_string(),
+            name: classes.get(row, "title").to_string(),
+            description: classes.get(row, "classCode").to_string(),
+        });
+    }
+
+    for (number, row) in enrollments.numbered_rows() {
+        if to_be_deleted(&enrollments, row) {
+            continue;
+        }
+        let user = enrollments.get(row, "userSourcedId");
+        let class = enrollments.get(row, "classSourcedId");
+        let role = match RosterRole::parse(enrollments.get(row, "role")) {
+            Some(role) => role,
+            None => continue,
+        };
+        if !user_ids.contains(user) {
+            roster.problems.push(format!(
+                "enrollments.csv row {}: there isn't a student or teacher with the sourcedId \
+                \"{}\".",
+                number, user
+            ));
+            continue;
+        }
+        if !class_ids.contains(class) {
+            roster.problems.push(format!(
+                "enrollments.csv row {}: there isn't a class with the sourcedId \"{}\".",
+                number, class
+            ));
+            continue;
+        }
+        roster.enrolments.push(RosterEnrolment {
+            user: user.to_string(),
+            class: class.to_string(),
+            role,
+        });
+    }
+    Ok(roster)
+}
+
+#[cfg(test)]
+mod test_parse {
+    use super::{parse_csv, parse_oneroster};
+    use crate::institution::roster::{RosterError, RosterRole};
+
+    #[test]
+    fn test_parse_csv() {
+        let roster = parse_csv(
+            "Email,Username,Role,Group,Classes\n\
+            alice@example.com,alice,student,Year 7,Maths; English\n\
+            bob@example.com,,Teacher,,Maths\n\
+            not-an-email,carol,student,,\n\
+            dave@example.com,dave,parent,,\n",
+        )
+        .unwrap();
+        assert_eq!(roster.users.len(), 2);
+        assert_eq!(roster.users[0].groups, vec!["Year 7".to_string()]);
+        assert_eq!(roster.users[1].username, None);
+        assert_eq!(roster.users[1].role, RosterRole::Teacher);
+        assert_eq!(roster.groups.len(), 1);
+        assert_eq!(roster.classes.len(), 2);
+        assert_eq!(roster.enrolments.len(), 3);
+        assert_eq!(roster.problems.len(), 2);
+
+        assert!(matches!(
+            parse_csv("name,role\nalice,student\n"),
+            Err(RosterError::MissingColumn {
+                column: "email",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_oneroster() {
+        let roster = parse_oneroster(
+            "sourcedId,status,role,username,email,grades\n\
+            u1,active,student,alice,alice@example.com,\"07,08\"\n\
+            u2,active,teacher,bob,bob@example.com,\n\
+            u3,active,guardian,carol,carol@example.com,\n\
+            u4,tobedeleted,student,dave,dave@example.com,07\n",
+            "sourcedId,title,classCode\nc1,Maths,MA7\n",
+            "sourcedId,classSourcedId,userSourcedId,role\n\
+            e1,c1,u1,student\n\
+            e2,c1,u2,teacher\n\
+            e3,c1,u4,student\n",
+        )
+        .unwrap();
+        assert_eq!(roster.users.len(), 2);
+        assert_eq!(roster.groups.len(), 2);
+        assert_eq!(roster.users[0].groups, vec!["grade:07", "grade:08"]);
+        assert_eq!(roster.classes[0].description, "MA7");
+        assert_eq!(roster.enrolments.len(), 2);
+        // the guardian, and the enrolment of the deleted student
+        assert_eq!(roster.problems.len(), 2);
+    }
+}
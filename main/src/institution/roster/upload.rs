@@ -0,0 +1,597 @@
+//! The pages (and API routes) through which rosters are uploaded, checked and imported.
+
+use malvolio::{prelude::*, text::Text};
+use portia::levels::Level;
+use rocket::serde::json::Json;
+
+use super::{
+    import::{import_roster, ImportReport},
+    parse::{parse_csv, parse_oneroster},
+    Roster, RosterError,
+};
+use crate::{
+    auth::AuthCookie,
+    db::Database,
+    institution::is_administrator,
+    utils::{default_head, json_response::ApiResponse},
+};
+
+#[derive(FromFormField, Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum RosterFormat {
+    /// The plain CSV format (see [`super::parse`]).
+    Csv,
+    /// A OneRoster 1.1 CSV bundle.
+    OneRoster,
+}
+
+/// The uploaded files. Which of these are needed depends on the format.
+#[derive(FromForm, Serialize, Deserialize, Debug, Clone)]
+pub struct RosterUpload {
+    format: RosterFormat,
+    /// For the plain CSV format.
+    roster: Option<String>,
+    /// `users.csv`, `classes.csv` and `enrollments.csv` from a OneRoster bundle.
+    users: Option<String>,
+    classes: Option<String>,
+    enrollments: Option<String>,
+}
+
+fn required<'a>(file: &'a Option<String>, name: &'static str) -> Result<&'a str, RosterError> {
+    match file.as_deref() {
+        Some(file) if !file.trim().is_empty() => Ok(file),
+        _ => Err(RosterError::MissingFile(name)),
+    }
+}
+
+impl RosterUpload {
+    fn parse(&self) -> Result<Roster, RosterError> {
+        match self.format {
+            RosterFormat::Csv => parse_csv(required(&self.roster, "roster.csv")?),
+            RosterFormat::OneRoster => parse_oneroster(
+                required(&self.users, "users.csv")?,
+                required(&self.classes, "classes.csv")?,
+                required(&self.enrollments, "enrollments.csv")?,
+            ),
+        }
+    }
+}
+
+/// The preview page sends the upload back to us (so that it can be applied) in this form.
+#[derive(FromForm, Debug, Clone)]
+pub struct ApplyRosterForm {
+    /// The upload, serialized as JSON and then encoded as base64.
+    upload: String,
+}
+
+async fn import_base(
+    institution_id: i32,
+    auth: AuthCookie,
+    upload: RosterUpload,
+    apply: bool,
+    conn: &Database,
+) -> Result<ImportReport, RosterError> {
+    let roster = upload.parse()?;
+    import_roster(institution_id, auth, roster, apply, conn).await
+}
+
+/// Malvolio can't (yet) produce file inputs (or set a form's encoding), so the upload forms are
+/// written out by hand.
+fn upload_form(institution_id: i32, format: RosterFormat) -> Text {
+    let (format, files) = match format {
+        RosterFormat::Csv => (
+            "csv",
+            "<label>Roster (CSV) <input type=\"file\" name=\"roster\" accept=\".csv\"/></label>",
+        ),
+        RosterFormat::OneRoster => (
+            "oneroster",
+            "<label>users.csv <input type=\"file\" name=\"users\" accept=\".csv\"/></label>\
+            <label>classes.csv <input type=\"file\" name=\"classes\" accept=\".csv\"/></label>\
+            <label>enrollments.csv \
+            <input type=\"file\" name=\"enrollments\" accept=\".csv\"/></label>",
+        ),
+    };
+    Text::new_unchecked(format!(
+        "<form method=\"post\" action=\"/institution/{}/roster/preview\" \
+        enctype=\"multipart/form-data\">\
+        <input type=\"hidden\" name=\"format\" value=\"{}\"/>{}\
+        <input type=\"submit\" value=\"Check this roster\"/></form>",
+        institution_id, format, files
+    ))
+}
+
+#[get("/<institution_id>/roster")]
+pub async fn roster_page(institution_id: i32, auth: AuthCookie, conn: Database) -> Html {
+    if let Err(e) = conn
+        .run(move |c| is_administrator(institution_id, auth, c))
+        .await
+    {
+        return RosterError::from(e).render("Could not import a roster");
+    }
+    Html::new().head(default_head("Import a roster")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Import a roster"))
+                .child(P::with_text(
+                    "Add your students, teachers, student groups and classes all at once. You'll \
+                    be able to check what will change before anything is imported, and it's safe \
+                    to import the same roster more than once.",
+                ))
+                .child(H2::new("From a spreadsheet"))
+                .child(P::with_text(
+                    "Upload a CSV file with one row for each person. It should have \"email\" and \
+                    \"role\" (either \"student\" or \"teacher\") columns, and can also have \
+                    \"username\", \"group\" and \"classes\" columns (separate each class with a \
+                    semicolon).",
+                ))
+                .child(upload_form(institution_id, RosterFormat::Csv))
+                .child(H2::new("From OneRoster"))
+                .child(P::with_text(
+                    "Upload the users.csv, classes.csv and enrollments.csv files from a OneRoster \
+                    1.1 export. Students are put into a student group for each grade they're in.",
+                ))
+                .child(upload_form(institution_id, RosterFormat::OneRoster)),
+        ),
+    )
+}
+
+fn report_section(heading: &'static str, items: &[String]) -> Div {
+    if items.is_empty() {
+        Div::new()
+    } else {
+        Div::new()
+            .child(H3::new(format!("{} ({})", heading, items.len())))
+            .children(items.iter().map(|item| P::with_text(item.clone())))
+    }
+}
+
+fn render_report(report: &ImportReport) -> Div {
+    Div::new()
+        .child(report_section("New accounts", &report.new_accounts))
+        .child(report_section("New students", &report.new_students))
+        .child(report_section("New teachers", &report.new_teachers))
+        .child(report_section(
+            "People with an existing account who will be invited to join (they'll be added to \
+            their groups and classes the next time the roster is imported, if they have \
+            accepted by then)",
+            &report.invitations,
+        ))
+        .child(report_section(
+            "People who have already been invited to join (and haven't answered yet)",
+            &report.already_invited,
+        ))
+        .child(report_section("New student groups", &report.new_groups))
+        .child(report_section(
+            "People joining student groups",
+            &report.new_group_members,
+        ))
+        .child(report_section("New classes", &report.new_classes))
+        .child(report_section(
+            "People joining classes",
+            &report.new_enrolments,
+        ))
+        .child(report_section("Problems", &report.problems))
+}
+
+#[post("/<institution_id>/roster/preview", data = "<form>")]
+pub async fn html_preview_roster(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<RosterUpload>,
+    conn: Database,
+) -> Html {
+    let upload = form.into_inner();
+    let encoded = base64::encode(serde_json::to_string(&upload).unwrap());
+    let report = match import_base(institution_id, auth, upload, false, &conn).await {
+        Ok(report) => report,
+        Err(e) => return e.render("Could not import this roster"),
+    };
+    Html::new().head(default_head("Check this import")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Check this import"))
+                .child(render_report(&report))
+                .child(if report.is_empty() {
+                    Div::new().child(P::with_text(
+                        "There's nothing to import – everything in this roster is already here.",
+                    ))
+                } else {
+                    Div::new().child(
+                        Form::new()
+                            .attribute(Method::Post)
+                            .attribute(Action::new(format!(
+                                "/institution/{}/roster/apply",
+                                institution_id
+                            )))
+                            .child(
+                                Input::new()
+                                    .attribute(Type::Hidden)
+                                    .attribute(Name::new("upload"))
+                                    .attribute(Value::new(encoded)),
+                            )
+                            .child(
+                                Input::new()
+                                    .attribute(Type::Submit)
+                                    .attribute(Value::new("Import this roster")),
+                            ),
+                    )
+                }),
+        ),
+    )
+}
+
+#[post("/<institution_id>/roster/apply", data = "<form>")]
+pub async fn html_apply_roster(
+    institution_id: i32,
+    auth: AuthCookie,
+    form: rocket::form::Form<ApplyRosterForm>,
+    conn: Database,
+) -> Html {
+    let upload = match base64::decode(&form.upload)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<RosterUpload>(&json).ok())
+    {
+        Some(upload) => upload,
+        None => return RosterError::InvalidUpload.render("Could not import this roster"),
+    };
+    match import_base(institution_id, auth, upload, true, &conn).await {
+        Ok(report) => Html::new().head(default_head("Roster imported")).body(
+            Body::new().child(
+                Level::new()
+                    .child(H1::new("Roster imported"))
+                    .child(P::with_text(
+                        "Everybody who was added has been sent an email to let them know.",
+                    ))
+                    .child(render_report(&report)),
+            ),
+        ),
+        Err(e) => e.render("Could not import this roster"),
+    }
+}
+
+#[post("/<institution_id>/roster/preview", data = "<upload>")]
+pub async fn api_preview_roster(
+    institution_id: i32,
+    auth: AuthCookie,
+    upload: Json<RosterUpload>,
+    conn: Database,
+) -> Json<ApiResponse<ImportReport>> {
+    Json(
+        match import_base(institution_id, auth, upload.into_inner(), false, &conn).await {
+            Ok(report) => ApiResponse::new_ok(report),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+#[post("/<institution_id>/roster/apply", data = "<upload>")]
+pub async fn api_apply_roster(
+    institution_id: i32,
+    auth: AuthCookie,
+    upload: Json<RosterUpload>,
+    conn: Database,
+) -> Json<ApiResponse<ImportReport>> {
+    Json(
+        match import_base(institution_id, auth, upload.into_inner(), true, &conn).await {
+            Ok(report) => ApiResponse::new_ok(report),
+            Err(e) => ApiResponse::new_err(e.explanation()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test_roster {
+    use diesel::prelude::*;
+    use rocket::{
+        http::{ContentType, Status},
+        local::asynchronous::Client,
+    };
+    use serde_json::{json, Value};
+
+    use crate::{
+        db::Database,
+        institution::test_ctx::{
+            setup_env, ADMIN_PASSWORD, ADMIN_USERNAME, STUDENT_EMAIL, STUDENT_PASSWORD,
+            STUDENT_USERNAME,
+        },
+        models::User,
+        schema::{
+            class, class_student, class_teacher, institution_student, institution_student_invite,
+            institution_teacher, notifications, student_group, student_group_student, users,
+        },
+        utils::{client, create_user, login_user, mock_email_server},
+    };
+
+    const ROSTER: &str = "email,username,role,group,classes\n\
+        alice@example.com,alice,student,Year 7,Maths;English\n\
+        bob@example.com,,teacher,,Maths\n";
+
+    async fn import(client: &Client, institution_id: i32, step: &str, upload: Value) -> Value {
+        let res = client
+            .post(format!(
+                "/api/institution/{}/roster/{}",
+                institution_id, step
+            ))
+            .header(ContentType::JSON)
+            .body(upload.to_string())
+            .dispatch()
+            .await;
+        serde_json::from_str(&res.into_string().await.unwrap()).unwrap()
+    }
+
+    async fn find_user(client: &Client, username: &'static str) -> Option<User> {
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                users::table
+                    .filter(users::username.eq(username))
+                    .first::<User>(c)
+                    .optional()
+                    .unwrap()
+            })
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn test_csv_import() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (_, _, student_id, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        create_user(
+            "outsider",
+            "outsider@example.com",
+            "UTC",
+            "outsider-passw0rd",
+            &client,
+        )
+        .await;
+        let outsider_id = find_user(&client, "outsider").await.unwrap().id;
+        login_user(ADMIN_USERNAME, ADMIN_PASSWORD, &client).await;
+        let roster = format!(
+            "{}{},,student,Year 7,\noutsider@example.com,,student,Year 7,Maths\n",
+            ROSTER, STUDENT_EMAIL
+        );
+        let upload = json!({ "format": "csv", "roster": roster });
+
+        // checking the roster doesn't change anything
+        let preview = import(&client, institution_id, "preview", upload.clone()).await;
+        assert_eq!(preview["success"], true);
+        assert_eq!(preview["data"]["new_accounts"].as_array().unwrap().len(), 2);
+        assert_eq!(preview["data"]["new_groups"], json!(["Year 7"]));
+        assert_eq!(preview["data"]["new_classes"], json!(["Maths", "English"]));
+        // people who already have an account are invited rather than added
+        assert_eq!(
+            preview["data"]["invitations"],
+            json!(["outsider (as a student)"])
+        );
+        assert!(find_user(&client, "alice").await.is_none());
+
+        let applied = import(&client, institution_id, "apply", upload.clone()).await;
+        assert_eq!(applied["data"], preview["data"]);
+        let alice = find_user(&client, "alice").await.unwrap();
+        assert!(!alice.email_verified);
+        let alice_id = alice.id;
+        let bob_id = find_user(&client, "bob").await.unwrap().id;
+        let (student, teacher, group_members, maths_students, maths_teachers, invited) =
+            Database::get_one(client.rocket())
+                .await
+                .unwrap()
+                .run(move |c| {
+                    let student = diesel::select(diesel::dsl::exists(
+                        institution_student::table
+                            .filter(institution_student::institution_id.eq(institution_id))
+                            .filter(institution_student::user_id.eq(alice_id)),
+                    ))
+                    .get_result::<bool>(c)
+                    .unwrap();
+                    let teacher = diesel::select(diesel::dsl::exists(
+                        institution_teacher::table
+                            .filter(institution_teacher::institution_id.eq(institution_id))
+                            .filter(institution_teacher::user_id.eq(bob_id)),
+                    ))
+                    .get_result::<bool>(c)
+                    .unwrap();
+                    let mut group_members = student_group_student::table
+                        .inner_join(student_group::table)
+                        .filter(student_group::name.eq("Year 7"))
+                        .select(student_group_student::user_id)
+                        .load::<i32>(c)
+                        .unwrap();
+                    group_members.sort_unstable();
+                    let maths = class::table
+                        .filter(class::institution_id.eq(institution_id))
+                        .filter(class::name.eq("Maths"))
+                        .select(class::id)
+                        .first::<i32>(c)
+                        .unwrap();
+                    let maths_students = class_student::table
+                        .filter(class_student::class_id.eq(maths))
+                        .select(class_student::user_id)
+                        .load::<i32>(c)
+                        .unwrap();
+                    let maths_teachers = class_teacher::table
+                        .filter(class_teacher::class_id.eq(maths))
+                        .select(class_teacher::user_id)
+                        .load::<i32>(c)
+                        .unwrap();
+                    let invited = diesel::select(diesel::dsl::exists(
+                        institution_student_invite::table
+                            .filter(institution_student_invite::institution_id.eq(institution_id))
+                            .filter(institution_student_invite::invited_user_id.eq(outsider_id)),
+                    ))
+                    .get_result::<bool>(c)
+                    .unwrap();
+                    (
+                        student,
+                        teacher,
+                        group_members,
+                        maths_students,
+                        maths_teachers,
+                        invited,
+                    )
+                })
+                .await;
+        assert!(student);
+        assert!(teacher);
+        let mut expected = vec![student_id, alice_id];
+        expected.sort_unstable();
+        assert_eq!(group_members, expected);
+        assert_eq!(maths_students, vec![alice_id]);
+        assert_eq!(maths_teachers, vec![bob_id]);
+        assert!(invited);
+
+        // importing the same roster again doesn't change anything
+        let again = import(&client, institution_id, "apply", upload).await;
+        assert_eq!(again["success"], true);
+        for key in &[
+            "new_accounts",
+            "new_students",
+            "new_teachers",
+            "new_groups",
+            "new_group_members",
+            "new_classes",
+            "new_enrolments",
+            "invitations",
+        ] {
+            assert_eq!(again["data"][key], json!([]), "{}", key);
+        }
+        // nor is anybody invited again
+        assert_eq!(
+            again["data"]["already_invited"],
+            json!(["outsider (as a student)"])
+        );
+        let (invites, notifications) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                let invites = institution_student_invite::table
+                    .filter(institution_student_invite::invited_user_id.eq(outsider_id))
+                    .count()
+                    .get_result::<i64>(c)
+                    .unwrap();
+                let notifications = notifications::table
+                    .filter(notifications::user_id.eq(outsider_id))
+                    .filter(notifications::title.eq("New invitation"))
+                    .count()
+                    .get_result::<i64>(c)
+                    .unwrap();
+                (invites, notifications)
+            })
+            .await;
+        assert_eq!((invites, notifications), (1, 1));
+    }
+
+    #[rocket::async_test]
+    async fn test_uploading_a_roster() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(ADMIN_USERNAME, ADMIN_PASSWORD, &client).await;
+
+        let page = client
+            .get(format!("/institution/{}/roster", institution_id))
+            .dispatch()
+            .await;
+        assert_eq!(page.status(), Status::Ok);
+
+        let boundary = "X-ROSTER-BOUNDARY";
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"format\"\r\n\r\ncsv\r\n\
+            --{b}\r\nContent-Disposition: form-data; name=\"roster\"; filename=\"roster.csv\"\r\n\
+            Content-Type: text/csv\r\n\r\n{roster}\r\n--{b}--\r\n",
+            b = boundary,
+            roster = ROSTER
+        );
+        let preview = client
+            .post(format!("/institution/{}/roster/preview", institution_id))
+            .header(
+                ContentType::parse_flexible(&format!("multipart/form-data; boundary={}", boundary))
+                    .unwrap(),
+            )
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(preview.status(), Status::Ok);
+        let preview = preview.into_string().await.unwrap();
+        assert!(preview.contains("alice (alice@example.com)"));
+        assert!(find_user(&client, "alice").await.is_none());
+
+        // the preview page sends the upload back when the import is applied
+        let upload = preview
+            .split("<input")
+            .find(|input| input.contains("name=\"upload\""))
+            .and_then(|input| input.split("value=\"").nth(1))
+            .and_then(|value| value.split('"').next())
+            .unwrap()
+            .to_string();
+        let applied = client
+            .post(format!("/institution/{}/roster/apply", institution_id))
+            .header(ContentType::Form)
+            .body(format!(
+                "upload={}",
+                upload
+                    .replace('+', "%2B")
+                    .replace('=', "%3D")
+                    .replace('/', "%2F")
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(applied.status(), Status::Ok);
+        assert!(applied
+            .into_string()
+            .await
+            .unwrap()
+            .contains("Roster imported"));
+        assert!(find_user(&client, "alice").await.is_some());
+    }
+
+    #[rocket::async_test]
+    async fn test_oneroster_import() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (_, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        login_user(STUDENT_USERNAME, STUDENT_PASSWORD, &client).await;
+        let upload = json!({
+            "format": "oneroster",
+            "users": "sourcedId,status,role,username,email,grades\n\
+                s1,active,student,carol,carol@example.com,09\n\
+                t1,active,teacher,dave,dave@example.com,\n",
+            "classes": "sourcedId,title,classCode\nc1,Biology,BIO9\n",
+            "enrollments": "sourcedId,classSourcedId,userSourcedId,role\n\
+                e1,c1,s1,student\n\
+                e2,c1,t1,teacher\n",
+        });
+
+        // only administrators can import rosters
+        let res = import(&client, institution_id, "apply", upload.clone()).await;
+        assert_eq!(res["success"], false);
+        assert!(find_user(&client, "carol").await.is_none());
+
+        crate::utils::logout(&client).await;
+        login_user(ADMIN_USERNAME, ADMIN_PASSWORD, &client).await;
+        let res = import(&client, institution_id, "apply", upload.clone()).await;
+        assert_eq!(res["data"]["new_groups"], json!(["Grade 09"]));
+        assert_eq!(res["data"]["new_classes"], json!(["Biology"]));
+        assert_eq!(res["data"]["new_enrolments"].as_array().unwrap().len(), 2);
+
+        // the class is renamed in the management information system
+        let mut renamed = upload.clone();
+        renamed["classes"] = json!("sourcedId,title,classCode\nc1,Biology 9,BIO9\n");
+        let res = import(&client, institution_id, "apply", renamed).await;
+        assert_eq!(res["data"]["new_classes"], json!([]));
+        assert_eq!(res["data"]["new_enrolments"], json!([]));
+    }
+}
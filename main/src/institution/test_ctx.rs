@@ -1,4 +1,3 @@
-use bcrypt::DEFAULT_COST;
 use chrono::Utc;
 use diesel::prelude::*;
 
@@ -55,7 +54,7 @@ pub fn setup_env(c: &mut DatabaseConnection) -> (i32, i32, i32, i32, i32) {
         .values(NewUser {
             username: ADMIN_USERNAME,
             email: ADMIN_EMAIL,
-            password: &bcrypt::hash(ADMIN_PASSWORD, DEFAULT_COST).unwrap(),
+            password: &crate::auth::hash::hash_password(ADMIN_PASSWORD).unwrap(),
             created: Utc::now().naive_utc(),
             email_verified: true,
             timezone: TIMEZONE,
@@ -67,7 +66,7 @@ pub fn setup_env(c: &mut DatabaseConnection) -> (i32, i32, i32, i32, i32) {
         .values(NewUser {
             username: TEACHER_USERNAME,
             email: TEACHER_EMAIL,
-            password: &bcrypt::hash(TEACHER_PASSWORD, DEFAULT_COST).unwrap(),
+            password: &crate::auth::hash::hash_password(TEACHER_PASSWORD).unwrap(),
             created: Utc::now().naive_utc(),
             email_verified: true,
             timezone: TIMEZONE,
@@ -79,7 +78,7 @@ pub fn setup_env(c: &mut DatabaseConnection) -> (i32, i32, i32, i32, i32) {
         .values(NewUser {
             username: STUDENT_USERNAME,
             email: STUDENT_EMAIL,
-            password: &bcrypt::hash(STUDENT_PASSWORD, DEFAULT_COST).unwrap(),
+            password: &crate::auth::hash::hash_password(STUDENT_PASSWORD).unwrap(),
             created: Utc::now().naive_utc(),
             email_verified: true,
             timezone: TIMEZONE,
@@ -98,6 +97,8 @@ pub fn setup_env(c: &mut DatabaseConnection) -> (i32, i32, i32, i32, i32) {
             let_teachers_create_classes: true,
             let_all_users_create_classes: false,
             let_teachers_add_sync_tasks: true,
+            require_two_factor: false,
+            attendance_threshold: None,
         })
         .returning(institution::id)
         .get_result(c)
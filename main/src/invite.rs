@@ -0,0 +1,1333 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Invitations to teach a class, to join an institution (as an administrator, teacher or student)
+//! or to teach a student group.
+//!
+//! Invitations show up in the invited user's inbox (at `/invites`), where they can be accepted
+//! (which makes the user a member) or declined. They expire after a fortnight, and whoever sent
+//! an invitation can revoke it until it has been accepted.
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use malvolio::prelude::*;
+use portia::levels::Level;
+use rocket::{http::Status, request::FromParam, serde::json::Json};
+use thiserror::Error as ThisError;
+
+use crate::{
+    auth::{
+        scope::{ManageClasses, ReadClasses},
+        ApiAuth, AuthCookie,
+    },
+    db::{Database, DatabaseConnection},
+    email::{EmailBuilder, RecipientBuilder, RecipientsBuilder, SendMail, SendgridMailSender},
+    institution::policy,
+    models::{
+        institution::{
+            administrator::{NewAdministrator, NewAdministratorInvite},
+            student::{NewInstitutionStudent, NewInstitutionStudentInvite},
+            student_group::teacher::{NewStudentGroupTeacher, NewStudentGroupTeacherInvite},
+            teacher::{NewInstitutionTeacher, NewInstitutionTeacherInvite},
+        },
+        NewClassTeacher, NewClassTeacherInvite, User,
+    },
+    notifications::{NotificationPriority, NotifyBuilder},
+    schema::{
+        administrator, administrator_invite, class, class_teacher, class_teacher_invite,
+        institution, institution_student, institution_student_invite, institution_teacher,
+        institution_teacher_invite, student_group, student_group_teacher,
+        student_group_teacher_invite, users,
+    },
+    utils::{default_head, error::LovelaceError, json_response::ApiResponse},
+};
+
+/// How long people have to accept an invitation.
+pub const INVITE_VALID_DAYS: i64 = 14;
+
+/// What an invitation is an invitation to do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InviteKind {
+    ClassTeacher,
+    Administrator,
+    InstitutionTeacher,
+    InstitutionStudent,
+    StudentGroupTeacher,
+}
+
+impl InviteKind {
+    const ALL: [InviteKind; 5] = [
+        InviteKind::ClassTeacher,
+        InviteKind::Administrator,
+        InviteKind::InstitutionTeacher,
+        InviteKind::InstitutionStudent,
+        InviteKind::StudentGroupTeacher,
+    ];
+
+    /// How this kind of invitation is referred to in URLs.
+    fn slug(self) -> &'static str {
+        match self {
+            InviteKind::ClassTeacher => "class-teacher",
+            InviteKind::Administrator => "administrator",
+            InviteKind::InstitutionTeacher => "institution-teacher",
+            InviteKind::InstitutionStudent => "institution-student",
+            InviteKind::StudentGroupTeacher => "student-group-teacher",
+        }
+    }
+
+    /// What accepting the invitation lets somebody do (e.g. "teach the class \"Maths\"").
+    fn describe(self, target_name: &str) -> String {
+        match self {
+            InviteKind::ClassTeacher => format!("teach the class \"{}\"", target_name),
+            InviteKind::Administrator => format!("administer {}", target_name),
+            InviteKind::InstitutionTeacher => format!("join {} as a teacher", target_name),
+            InviteKind::InstitutionStudent => format!("join {} as a student", target_name),
+            InviteKind::StudentGroupTeacher => {
+                format!("teach the student group \"{}\"", target_name)
+            }
+        }
+    }
+}
+
+impl<'a> FromParam<'a> for InviteKind {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        InviteKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.slug() == param)
+            .ok_or(param)
+    }
+}
+
+/// Evaluates `$body` with `$table` bound to the schema of the table which stores invitations of
+/// the given kind, and (optionally) `$target` bound to the column of that table which holds the id
+/// of the class, institution or student group the invitation is to.
+macro_rules! with_invite_table {
+    ($kind:expr, $table:ident $(, $target:ident)? => $body:expr) => {
+        match $kind {
+            InviteKind::ClassTeacher => {
+                use class_teacher_invite as $table;
+                $(use class_teacher_invite::class_id as $target;)?
+                $body
+            }
+            InviteKind::Administrator => {
+                use administrator_invite as $table;
+                $(use administrator_invite::institution_id as $target;)?
+                $body
+            }
+            InviteKind::InstitutionTeacher => {
+                use institution_teacher_invite as $table;
+                $(use institution_teacher_invite::institution_id as $target;)?
+                $body
+            }
+            InviteKind::InstitutionStudent => {
+                use institution_student_invite as $table;
+                $(use institution_student_invite::institution_id as $target;)?
+                $body
+            }
+            InviteKind::StudentGroupTeacher => {
+                use student_group_teacher_invite as $table;
+                $(use student_group_teacher_invite::student_group_id as $target;)?
+                $body
+            }
+        }
+    };
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Invite {
+    pub id: i32,
+    pub kind: InviteKind,
+    pub inviting_user_id: i32,
+    pub inviting_username: String,
+    pub invited_user_id: i32,
+    pub invited_username: String,
+    /// The id of the class, institution or student group which this is an invitation to.
+    pub target_id: i32,
+    pub target_name: String,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
+}
+
+type InviteRow = (
+    i32,
+    i32,
+    String,
+    i32,
+    i32,
+    String,
+    NaiveDateTime,
+    NaiveDateTime,
+);
+
+impl Invite {
+    /// The username of the invited user isn't part of the row; it is filled in afterwards.
+    fn from_row(kind: InviteKind, row: InviteRow) -> Self {
+        Self {
+            id: row.0,
+            kind,
+            inviting_user_id: row.1,
+            inviting_username: row.2,
+            invited_user_id: row.3,
+            invited_username: String::new(),
+            target_id: row.4,
+            target_name: row.5,
+            created: row.6,
+            expires: row.7,
+        }
+    }
+
+    fn description(&self) -> String {
+        self.kind.describe(&self.target_name)
+    }
+}
+
+/// Loads the pending (not yet accepted and unexpired) invitations from one table which were sent
+/// to or by the user.
+macro_rules! pending_invites {
+    ($c:expr, $user_id:expr, $kind:expr, $invite:ident, $target:ident, $target_id:ident) => {
+        $invite::table
+            .inner_join(users::table.on(users::id.eq($invite::inviting_user_id)))
+            .inner_join($target::table.on($target::id.eq($invite::$target_id)))
+            .filter(
+                $invite::invited_user_id
+                    .eq($user_id)
+                    .or($invite::inviting_user_id.eq($user_id)),
+            )
+            .filter($invite::accepted.eq(false))
+            .filter($invite::expires.gt(Utc::now().naive_utc()))
+            .select((
+                $invite::id,
+                $invite::inviting_user_id,
+                users::username,
+                $invite::invited_user_id,
+                $target::id,
+                $target::name,
+                $invite::created,
+                $invite::expires,
+            ))
+            .load::<InviteRow>($c)?
+            .into_iter()
+            .map(|row| Invite::from_row($kind, row))
+    };
+}
+
+/// All the pending invitations which the user has either sent or been sent, newest first.
+fn pending_invites_involving(user_id: i32, c: &DatabaseConnection) -> QueryResult<Vec<Invite>> {
+    let mut invites = pending_invites!(
+        c,
+        user_id,
+        InviteKind::ClassTeacher,
+        class_teacher_invite,
+        class,
+        class_id
+    )
+    .chain(pending_invites!(
+        c,
+        user_id,
+        InviteKind::Administrator,
+        administrator_invite,
+        institution,
+        institution_id
+    ))
+    .chain(pending_invites!(
+        c,
+        user_id,
+        InviteKind::InstitutionTeacher,
+        institution_teacher_invite,
+        institution,
+        institution_id
+    ))
+    .chain(pending_invites!(
+        c,
+        user_id,
+        InviteKind::InstitutionStudent,
+        institution_student_invite,
+        institution,
+        institution_id
+    ))
+    .chain(pending_invites!(
+        c,
+        user_id,
+        InviteKind::StudentGroupTeacher,
+        student_group_teacher_invite,
+        student_group,
+        student_group_id
+    ))
+    .collect::<Vec<_>>();
+    let invited_usernames = users::table
+        .filter(
+            users::id.eq_any(
+                invites
+                    .iter()
+                    .map(|invite| invite.invited_user_id)
+                    .collect::<Vec<_>>(),
+            ),
+        )
+        .select((users::id, users::username))
+        .load::<(i32, String)>(c)?;
+    for invite in invites.iter_mut() {
+        if let Some((_, username)) = invited_usernames
+            .iter()
+            .find(|(id, _)| *id == invite.invited_user_id)
+        {
+            invite.invited_username = username.clone();
+        }
+    }
+    invites.sort_by_key(|invite| std::cmp::Reverse(invite.created));
+    Ok(invites)
+}
+
+#[derive(ThisError, Debug)]
+pub enum InviteError {
+    #[error("invite not found")]
+    NotFound,
+    #[error("user not found")]
+    UserNotFound,
+    #[error("the user is already a member")]
+    AlreadyMember,
+    /// Has the reason from [`LovelaceError::PermissionError`] (if there is one).
+    #[error("permission error")]
+    PermissionError(Option<String>),
+    #[error("email address not verified")]
+    EmailNotVerified,
+    #[error("database error")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for InviteError {
+    fn from(e: diesel::result::Error) -> Self {
+        error!("{:#?}", e);
+        Self::DatabaseError
+    }
+}
+
+impl From<LovelaceError> for InviteError {
+    fn from(e: LovelaceError) -> Self {
+        match e {
+            LovelaceError::PermissionError(reason) => Self::PermissionError(reason),
+            LovelaceError::EmailNotVerified => Self::EmailNotVerified,
+            _ => Self::DatabaseError,
+        }
+    }
+}
+
+impl InviteError {
+    pub(crate) fn status(&self) -> Status {
+        match self {
+            InviteError::NotFound | InviteError::UserNotFound => Status::NotFound,
+            InviteError::AlreadyMember => Status::Conflict,
+            InviteError::PermissionError(_) | InviteError::EmailNotVerified => Status::Forbidden,
+            InviteError::DatabaseError => Status::InternalServerError,
+        }
+    }
+
+    pub(crate) fn explanation(&self) -> String {
+        match self {
+            InviteError::NotFound => "That invitation doesn't exist – it might have expired, been \
+                withdrawn or already been accepted."
+                .to_string(),
+            InviteError::UserNotFound => {
+                "Nobody with that username or email address could be found.".to_string()
+            }
+            InviteError::AlreadyMember => "They're already a member.".to_string(),
+            InviteError::PermissionError(reason) => reason
+                .clone()
+                .unwrap_or_else(|| "You don't have permission to do this.".to_string()),
+            InviteError::EmailNotVerified => {
+                "You need to verify your email address before you can accept this invitation."
+                    .to_string()
+            }
+            InviteError::DatabaseError => {
+                "Something's up on our end. We're working to fix it as fast as we can!".to_string()
+            }
+        }
+    }
+
+    fn render(self, title: &'static str) -> Html {
+        Html::new()
+            .status(self.status())
+            .head(default_head(title))
+            .body(
+                Body::new()
+                    .child(H1::new(title))
+                    .child(P::with_text(self.explanation()))
+                    .child(
+                        A::new()
+                            .attribute(Href::new("/invites"))
+                            .text("Back to your invitations"),
+                    ),
+            )
+    }
+}
+
+/// Whether the user is already whatever the invitation would make them.
+fn already_member(
+    kind: InviteKind,
+    user_id: i32,
+    target_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<bool> {
+    match kind {
+        InviteKind::ClassTeacher => diesel::select(diesel::dsl::exists(
+            class_teacher::table
+                .filter(class_teacher::user_id.eq(user_id))
+                .filter(class_teacher::class_id.eq(target_id)),
+        ))
+        .get_result(c),
+        InviteKind::Administrator => diesel::select(diesel::dsl::exists(
+            administrator::table
+                .filter(administrator::user_id.eq(user_id))
+                .filter(administrator::institution_id.eq(target_id)),
+        ))
+        .get_result(c),
+        InviteKind::InstitutionTeacher => diesel::select(diesel::dsl::exists(
+            institution_teacher::table
+                .filter(institution_teacher::user_id.eq(user_id))
+                .filter(institution_teacher::institution_id.eq(target_id)),
+        ))
+        .get_result(c),
+        InviteKind::InstitutionStudent => diesel::select(diesel::dsl::exists(
+            institution_student::table
+                .filter(institution_student::user_id.eq(user_id))
+                .filter(institution_student::institution_id.eq(target_id)),
+        ))
+        .get_result(c),
+        InviteKind::StudentGroupTeacher => diesel::select(diesel::dsl::exists(
+            student_group_teacher::table
+                .filter(student_group_teacher::user_id.eq(user_id))
+                .filter(student_group_teacher::student_group_id.eq(target_id)),
+        ))
+        .get_result(c),
+    }
+}
+
+/// Whether the user is (still) allowed to invite people to the class, institution or student group:
+/// teachers of a class can invite other teachers, and administrators of an institution can invite
+/// people to it (and to its student groups).
+fn can_send(
+    kind: InviteKind,
+    inviting_user_id: i32,
+    target_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<bool> {
+    let institution_id = match kind {
+        InviteKind::ClassTeacher => {
+            return diesel::select(diesel::dsl::exists(
+                class_teacher::table
+                    .filter(class_teacher::user_id.eq(inviting_user_id))
+                    .filter(class_teacher::class_id.eq(target_id)),
+            ))
+            .get_result(c)
+        }
+        InviteKind::Administrator
+        | InviteKind::InstitutionTeacher
+        | InviteKind::InstitutionStudent => target_id,
+        InviteKind::StudentGroupTeacher => student_group::table
+            .find(target_id)
+            .select(student_group::institution_id)
+            .first::<i32>(c)?,
+    };
+    diesel::select(diesel::dsl::exists(
+        administrator::table
+            .filter(administrator::user_id.eq(inviting_user_id))
+            .filter(administrator::institution_id.eq(institution_id)),
+    ))
+    .get_result(c)
+}
+
+fn target_name(kind: InviteKind, target_id: i32, c: &DatabaseConnection) -> QueryResult<String> {
+    match kind {
+        InviteKind::ClassTeacher => class::table.find(target_id).select(class::name).first(c),
+        InviteKind::Administrator
+        | InviteKind::InstitutionTeacher
+        | InviteKind::InstitutionStudent => institution::table
+            .find(target_id)
+            .select(institution::name)
+            .first(c),
+        InviteKind::StudentGroupTeacher => student_group::table
+            .find(target_id)
+            .select(student_group::name)
+            .first(c),
+    }
+}
+
+fn insert_invite(
+    kind: InviteKind,
+    inviting_user_id: i32,
+    invited_user_id: i32,
+    target_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<usize> {
+    let created = Utc::now().naive_utc();
+    let expires = created + Duration::days(INVITE_VALID_DAYS);
+    match kind {
+        InviteKind::ClassTeacher => diesel::insert_into(class_teacher_invite::table)
+            .values(NewClassTeacherInvite {
+                inviting_user_id,
+                invited_user_id,
+                class_id: target_id,
+                accepted: false,
+                created,
+                expires,
+            })
+            .execute(c),
+        InviteKind::Administrator => diesel::insert_into(administrator_invite::table)
+            .values(NewAdministratorInvite {
+                inviting_user_id,
+                invited_user_id,
+                institution_id: target_id,
+                accepted: false,
+                created,
+                expires,
+            })
+            .execute(c),
+        InviteKind::InstitutionTeacher => diesel::insert_into(institution_teacher_invite::table)
+            .values(NewInstitutionTeacherInvite {
+                inviting_user_id,
+                invited_user_id,
+                institution_id: target_id,
+                accepted: false,
+                created,
+                expires,
+            })
+            .execute(c),
+        InviteKind::InstitutionStudent => diesel::insert_into(institution_student_invite::table)
+            .values(NewInstitutionStudentInvite {
+                inviting_user_id,
+                invited_user_id,
+                institution_id: target_id,
+                accepted: false,
+                created,
+                expires,
+            })
+            .execute(c),
+        InviteKind::StudentGroupTeacher => diesel::insert_into(student_group_teacher_invite::table)
+            .values(NewStudentGroupTeacherInvite {
+                inviting_user_id,
+                invited_user_id,
+                student_group_id: target_id,
+                accepted: false,
+                created,
+                expires,
+            })
+            .execute(c),
+    }
+}
+
+/// Adds the user to the class, institution or student group.
+fn add_member(
+    kind: InviteKind,
+    user_id: i32,
+    target_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<usize> {
+    match kind {
+        InviteKind::ClassTeacher => diesel::insert_into(class_teacher::table)
+            .values(NewClassTeacher {
+                user_id,
+                class_id: target_id,
+            })
+            .execute(c),
+        InviteKind::Administrator => diesel::insert_into(administrator::table)
+            .values(NewAdministrator {
+                user_id,
+                institution_id: target_id,
+            })
+            .execute(c),
+        InviteKind::InstitutionTeacher => diesel::insert_into(institution_teacher::table)
+            .values(NewInstitutionTeacher {
+                user_id,
+                institution_id: target_id,
+            })
+            .execute(c),
+        InviteKind::InstitutionStudent => diesel::insert_into(institution_student::table)
+            .values(NewInstitutionStudent {
+                user_id,
+                institution_id: target_id,
+            })
+            .execute(c),
+        InviteKind::StudentGroupTeacher => diesel::insert_into(student_group_teacher::table)
+            .values(NewStudentGroupTeacher {
+                user_id,
+                student_group_id: target_id,
+            })
+            .execute(c),
+    }
+}
+
+pub(crate) async fn send_invite_email(invited: &User, inviting_username: &str, description: &str) {
+    let message = format!(
+        "{} has invited you to {} on Lovelace. You can accept or decline the invitation from your \
+        invitations page; it will expire in {} days.",
+        inviting_username, description, INVITE_VALID_DAYS
+    );
+    if let Err(e) = SendgridMailSender::default()
+        .send(
+            &EmailBuilder::default()
+                .subject("You've been invited to Lovelace".to_string())
+                .plaintext(Some(message.clone()))
+                .html_text(Some(
+                    Html::new()
+                        .head(default_head("You've been invited".to_string()))
+                        .body(
+                            Body::new().child(P::with_text(message)).child(
+                                A::new()
+                                    .attribute(Href::new("/invites"))
+                                    .text("View your invitations"),
+                            ),
+                        )
+                        .to_string(),
+                ))
+                .recipients(
+                    RecipientsBuilder::default()
+                        .recipients(vec![RecipientBuilder::default()
+                            .email(invited.email.clone())
+                            .name(invited.username.clone())
+                            .build()
+                            .unwrap()])
+                        .build()
+                        .unwrap(),
+                )
+                .from(("Lovelace".to_string(), "no-reply@lovelace.ga".to_string()))
+                .reply_to(("Lovelace".to_string(), "contact@lovelace.ga".to_string()))
+                .build()
+                .unwrap(),
+        )
+        .await
+    {
+        error!("failed to send invitation email: {:#?}", e);
+    }
+}
+
+/// Looks up the user who is to be invited by their username or email address.
+pub(crate) async fn find_invitee(identifier: String, conn: &Database) -> Result<User, InviteError> {
+    conn.run(move |c| {
+        users::table
+            .filter(users::username.eq(&identifier))
+            .or_filter(users::email.eq(&identifier))
+            .first::<User>(c)
+            .optional()
+    })
+    .await?
+    .ok_or(InviteError::UserNotFound)
+}
+
+/// Invites the user, and lets them know (with a notification and an email). Callers should check
+/// that the inviting user is allowed to send this invitation.
+///
+/// If the user already has a pending invitation to the same thing, it is replaced with this one
+/// (so it won't expire as soon).
+pub(crate) async fn send_invite(
+    kind: InviteKind,
+    target_id: i32,
+    inviting_user_id: i32,
+    invited: User,
+    conn: &Database,
+) -> Result<(), InviteError> {
+    let invited_user_id = invited.id;
+    let (inviting_username, description) = conn
+        .run(move |c| create_invite(kind, target_id, inviting_user_id, invited_user_id, c))
+        .await?;
+    send_invite_email(&invited, &inviting_username, &description).await;
+    Ok(())
+}
+
+/// Whether the user has already been sent an invitation of this kind to the target which they
+/// haven't yet accepted (and which hasn't expired).
+pub(crate) fn has_pending_invite(
+    kind: InviteKind,
+    target_id: i32,
+    invited_user_id: i32,
+    c: &DatabaseConnection,
+) -> QueryResult<bool> {
+    with_invite_table!(kind, table, target => diesel::select(diesel::dsl::exists(
+        table::table
+            .filter(table::invited_user_id.eq(invited_user_id))
+            .filter(target.eq(target_id))
+            .filter(table::accepted.eq(false))
+            .filter(table::expires.gt(Utc::now().naive_utc())),
+    ))
+    .get_result::<bool>(c))
+}
+
+/// Does everything [`send_invite`] does apart from sending the email (which the caller should
+/// send with [`send_invite_email`] once its transaction has been committed). Returns the inviting
+/// user's username and a description of the invitation.
+pub(crate) fn create_invite(
+    kind: InviteKind,
+    target_id: i32,
+    inviting_user_id: i32,
+    invited_user_id: i32,
+    c: &DatabaseConnection,
+) -> Result<(String, String), InviteError> {
+    c.transaction::<_, InviteError, _>(|| {
+        if already_member(kind, invited_user_id, target_id, c)? {
+            return Err(InviteError::AlreadyMember);
+        }
+        policy::can_invite(kind, invited_user_id, target_id, c)?;
+        with_invite_table!(kind, table, target => diesel::delete(
+            table::table
+                .filter(table::invited_user_id.eq(invited_user_id))
+                .filter(target.eq(target_id))
+                .filter(table::accepted.eq(false)),
+        )
+        .execute(c))?;
+        insert_invite(kind, inviting_user_id, invited_user_id, target_id, c)?;
+        let inviting_username = users::table
+            .find(inviting_user_id)
+            .select(users::username)
+            .first::<String>(c)?;
+        let description = kind.describe(&target_name(kind, target_id, c)?);
+        NotifyBuilder::default()
+            .intended_for(invited_user_id)
+            .title("New invitation")
+            .message(&format!(
+                "{} has invited you to {}. You can accept or decline this invitation from your \
+                invitations page.",
+                inviting_username, description
+            ))
+            .priority(NotificationPriority::Info)
+            .build()
+            .unwrap()
+            .create(c)?;
+        Ok((inviting_username, description))
+    })
+}
+
+/// Finds one of the user's pending invitations.
+fn find_invite(
+    kind: InviteKind,
+    id: i32,
+    user_id: i32,
+    c: &DatabaseConnection,
+) -> Result<Invite, InviteError> {
+    pending_invites_involving(user_id, c)?
+        .into_iter()
+        .find(|invite| invite.kind == kind && invite.id == id)
+        .ok_or(InviteError::NotFound)
+}
+
+/// Accepts the invitation, adding the user to whatever it was an invitation to.
+async fn accept_invite_base(
+    kind: InviteKind,
+    id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<Invite, InviteError> {
+    conn.run(move |c| {
+        c.transaction::<_, InviteError, _>(|| {
+            let invite = find_invite(kind, id, user_id, c)?;
+            if invite.invited_user_id != user_id {
+                return Err(InviteError::NotFound);
+            }
+            // whoever sent the invitation might have lost the right to since (e.g. by no longer
+            // being an administrator)
+            if !can_send(kind, invite.inviting_user_id, invite.target_id, c)? {
+                return Err(InviteError::PermissionError(Some(format!(
+                    "{} can no longer invite people to {}, so this invitation can't be accepted.",
+                    invite.inviting_username, invite.target_name
+                ))));
+            }
+            match kind {
+                InviteKind::Administrator
+                | InviteKind::InstitutionTeacher
+                | InviteKind::InstitutionStudent => policy::can_join(user_id, invite.target_id, c)?,
+                InviteKind::ClassTeacher | InviteKind::StudentGroupTeacher => {
+                    let email_verified = users::table
+                        .find(user_id)
+                        .select(users::email_verified)
+                        .first::<bool>(c)?;
+                    if !email_verified {
+                        return Err(InviteError::EmailNotVerified);
+                    }
+                    policy::can_invite(kind, user_id, invite.target_id, c)?;
+                }
+            }
+            // somebody might have added them in the meantime
+            if !already_member(kind, user_id, invite.target_id, c)? {
+                add_member(kind, user_id, invite.target_id, c)?;
+            }
+            with_invite_table!(kind, table => diesel::update(table::table.find(id))
+                .set(table::accepted.eq(true))
+                .execute(c))?;
+            NotifyBuilder::default()
+                .intended_for(invite.inviting_user_id)
+                .title("Invitation accepted")
+                .message(&format!(
+                    "{} has accepted your invitation to {}.",
+                    invite.invited_username,
+                    invite.description()
+                ))
+                .priority(NotificationPriority::Info)
+                .build()
+                .unwrap()
+                .create(c)?;
+            Ok(invite)
+        })
+    })
+    .await
+}
+
+/// Declines the invitation (which deletes it).
+async fn decline_invite_base(
+    kind: InviteKind,
+    id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<Invite, InviteError> {
+    conn.run(move |c| {
+        c.transaction::<_, InviteError, _>(|| {
+            let invite = find_invite(kind, id, user_id, c)?;
+            if invite.invited_user_id != user_id {
+                return Err(InviteError::NotFound);
+            }
+            with_invite_table!(kind, table => diesel::delete(table::table.find(id))
+                .execute(c))?;
+            NotifyBuilder::default()
+                .intended_for(invite.inviting_user_id)
+                .title("Invitation declined")
+                .message(&format!(
+                    "{} has declined your invitation to {}.",
+                    invite.invited_username,
+                    invite.description()
+                ))
+                .priority(NotificationPriority::Info)
+                .build()
+                .unwrap()
+                .create(c)?;
+            Ok(invite)
+        })
+    })
+    .await
+}
+
+/// Withdraws an invitation which the user sent (which deletes it).
+async fn revoke_invite_base(
+    kind: InviteKind,
+    id: i32,
+    user_id: i32,
+    conn: &Database,
+) -> Result<Invite, InviteError> {
+    conn.run(move |c| {
+        c.transaction::<_, InviteError, _>(|| {
+            let invite = find_invite(kind, id, user_id, c)?;
+            if invite.inviting_user_id != user_id {
+                return Err(InviteError::NotFound);
+            }
+            with_invite_table!(kind, table => diesel::delete(table::table.find(id))
+                .execute(c))?;
+            NotifyBuilder::default()
+                .intended_for(invite.invited_user_id)
+                .title("Invitation withdrawn")
+                .message(&format!(
+                    "{} has withdrawn their invitation for you to {}.",
+                    invite.inviting_username,
+                    invite.description()
+                ))
+                .priority(NotificationPriority::Info)
+                .build()
+                .unwrap()
+                .create(c)?;
+            Ok(invite)
+        })
+    })
+    .await
+}
+
+fn action_form(invite: &Invite, action: &str, label: &'static str) -> Form {
+    Form::new()
+        .attribute(Method::Post)
+        .attribute(Action::new(format!(
+            "/invites/{}/{}/{}",
+            invite.kind.slug(),
+            invite.id,
+            action
+        )))
+        .child(
+            Input::new()
+                .attribute(Type::Submit)
+                .attribute(Value::new(label)),
+        )
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Invites {
+    /// Invitations which have been sent to the user.
+    pub received: Vec<Invite>,
+    /// Invitations which the user has sent.
+    pub sent: Vec<Invite>,
+}
+
+async fn invites_base(user_id: i32, conn: &Database) -> Result<Invites, InviteError> {
+    let (received, sent) = conn
+        .run(move |c| pending_invites_involving(user_id, c))
+        .await?
+        .into_iter()
+        .partition(|invite| invite.invited_user_id == user_id);
+    Ok(Invites { received, sent })
+}
+
+#[get("/")]
+pub async fn invites_page(auth: AuthCookie, conn: Database) -> Html {
+    let invites = match invites_base(auth.0, &conn).await {
+        Ok(invites) => invites,
+        Err(e) => return e.render("Could not load your invitations"),
+    };
+    let received = if invites.received.is_empty() {
+        Level::new().child(P::with_text(
+            "You don't have any invitations at the moment.",
+        ))
+    } else {
+        Level::new().children(invites.received.iter().map(|invite| {
+            Div::new()
+                .child(P::with_text(format!(
+                    "{} has invited you to {} (this invitation expires on {}).",
+                    invite.inviting_username,
+                    invite.description(),
+                    invite.expires.format("%Y-%m-%d")
+                )))
+                .child(action_form(invite, "accept", "Accept"))
+                .child(action_form(invite, "decline", "Decline"))
+        }))
+    };
+    let sent = if invites.sent.is_empty() {
+        Level::new().child(P::with_text(
+            "None of the invitations you've sent are waiting for a reply.",
+        ))
+    } else {
+        Level::new().children(invites.sent.iter().map(|invite| {
+            Div::new()
+                .child(P::with_text(format!(
+                    "You invited {} to {} (this invitation expires on {}).",
+                    invite.invited_username,
+                    invite.description(),
+                    invite.expires.format("%Y-%m-%d")
+                )))
+                .child(action_form(invite, "revoke", "Withdraw this invitation"))
+        }))
+    };
+    Html::new().head(default_head("Invitations")).body(
+        Body::new().child(
+            Level::new()
+                .child(H1::new("Invitations"))
+                .child(received)
+                .child(H2::new("Invitations you've sent"))
+                .child(sent),
+        ),
+    )
+}
+
+#[get("/")]
+pub async fn api_list_invites(
+    auth: ApiAuth<ReadClasses>,
+    conn: Database,
+) -> Json<ApiResponse<Invites>> {
+    Json(match invites_base(auth.0, &conn).await {
+        Ok(invites) => ApiResponse::new_ok(invites),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[post("/<kind>/<id>/accept")]
+pub async fn html_accept_invite(
+    kind: InviteKind,
+    id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    match accept_invite_base(kind, id, auth.0, &conn).await {
+        Ok(invite) => Html::new().head(default_head("Invitation accepted")).body(
+            Body::new()
+                .child(H1::new("Invitation accepted"))
+                .child(P::with_text(format!(
+                    "You can now {}.",
+                    invite.description()
+                )))
+                .child(
+                    A::new()
+                        .attribute(Href::new("/invites"))
+                        .text("Back to your invitations"),
+                ),
+        ),
+        Err(e) => e.render("Could not accept this invitation"),
+    }
+}
+
+#[post("/<kind>/<id>/accept")]
+pub async fn api_accept_invite(
+    kind: InviteKind,
+    id: i32,
+    auth: ApiAuth<ManageClasses>,
+    conn: Database,
+) -> Json<ApiResponse<Invite>> {
+    Json(match accept_invite_base(kind, id, auth.0, &conn).await {
+        Ok(invite) => ApiResponse::new_ok(invite),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[post("/<kind>/<id>/decline")]
+pub async fn html_decline_invite(
+    kind: InviteKind,
+    id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    match decline_invite_base(kind, id, auth.0, &conn).await {
+        Ok(_) => Html::new().head(default_head("Invitation declined")).body(
+            Body::new().child(H1::new("Invitation declined")).child(
+                A::new()
+                    .attribute(Href::new("/invites"))
+                    .text("Back to your invitations"),
+            ),
+        ),
+        Err(e) => e.render("Could not decline this invitation"),
+    }
+}
+
+#[post("/<kind>/<id>/decline")]
+pub async fn api_decline_invite(
+    kind: InviteKind,
+    id: i32,
+    auth: ApiAuth<ManageClasses>,
+    conn: Database,
+) -> Json<ApiResponse<Invite>> {
+    Json(match decline_invite_base(kind, id, auth.0, &conn).await {
+        Ok(invite) => ApiResponse::new_ok(invite),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[post("/<kind>/<id>/revoke")]
+pub async fn html_revoke_invite(
+    kind: InviteKind,
+    id: i32,
+    auth: AuthCookie,
+    conn: Database,
+) -> Html {
+    match revoke_invite_base(kind, id, auth.0, &conn).await {
+        Ok(_) => Html::new().head(default_head("Invitation withdrawn")).body(
+            Body::new().child(H1::new("Invitation withdrawn")).child(
+                A::new()
+                    .attribute(Href::new("/invites"))
+                    .text("Back to your invitations"),
+            ),
+        ),
+        Err(e) => e.render("Could not withdraw this invitation"),
+    }
+}
+
+#[post("/<kind>/<id>/revoke")]
+pub async fn api_revoke_invite(
+    kind: InviteKind,
+    id: i32,
+    auth: ApiAuth<ManageClasses>,
+    conn: Database,
+) -> Json<ApiResponse<Invite>> {
+    Json(match revoke_invite_base(kind, id, auth.0, &conn).await {
+        Ok(invite) => ApiResponse::new_ok(invite),
+        Err(e) => ApiResponse::new_err(e.explanation()),
+    })
+}
+
+#[cfg(test)]
+mod test_invite {
+    use diesel::prelude::*;
+    use rocket::{
+        http::{ContentType, Status},
+        local::asynchronous::Client,
+    };
+
+    use crate::{
+        db::Database,
+        institution::test_ctx::{
+            setup_env, ADMIN_EMAIL, ADMIN_PASSWORD, NAME, STUDENT_USERNAME, TIMEZONE,
+        },
+        schema::{
+            administrator, administrator_invite, institution, institution_student_invite,
+            institution_teacher, institution_teacher_invite, notifications, users,
+        },
+        utils::{client, create_user, login_user, logout, mock_email_server},
+    };
+
+    const USERNAME: &str = "newcomer";
+    const EMAIL: &str = "newcomer@example.com";
+    const PASSWORD: &str = "n3wcomer-PASSWORD";
+
+    /// Sets up an institution, and a user who isn't a member of it. Returns the ids of the
+    /// institution's administrator, the institution and the user.
+    async fn setup(client: &Client) -> (i32, i32, i32) {
+        let (admin_id, _, _, institution_id, _) = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| setup_env(c))
+            .await;
+        create_user(USERNAME, EMAIL, TIMEZONE, PASSWORD, client).await;
+        let user_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(|c| {
+                users::table
+                    .filter(users::username.eq(USERNAME))
+                    .select(users::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        (admin_id, institution_id, user_id)
+    }
+
+    async fn invite(client: &Client, institution_id: i32, role: &str, identifier: &str) -> Status {
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, client).await;
+        let res = client
+            .post(format!("/institution/{}/invite/{}", institution_id, role))
+            .header(ContentType::Form)
+            .body(format!("identifier={}", identifier))
+            .dispatch()
+            .await;
+        logout(client).await;
+        res.status()
+    }
+
+    async fn teacher_invite_id(client: &Client, user_id: i32) -> i32 {
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                institution_teacher_invite::table
+                    .filter(institution_teacher_invite::invited_user_id.eq(user_id))
+                    .order_by(institution_teacher_invite::id.desc())
+                    .select(institution_teacher_invite::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await
+    }
+
+    async fn has_notification(client: &Client, user_id: i32, title: &'static str) -> bool {
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::select(diesel::dsl::exists(
+                    notifications::table
+                        .filter(notifications::user_id.eq(user_id))
+                        .filter(notifications::title.eq(title)),
+                ))
+                .get_result::<bool>(c)
+                .unwrap()
+            })
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn test_accept_and_decline_invites() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (admin_id, institution_id, user_id) = setup(&client).await;
+
+        assert_eq!(
+            invite(&client, institution_id, "teacher", USERNAME).await,
+            Status::Ok
+        );
+        assert!(has_notification(&client, user_id, "New invitation").await);
+        login_user(USERNAME, PASSWORD, &client).await;
+        let inbox = client
+            .get("/invites")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(inbox.contains(&format!("join {} as a teacher", NAME)));
+        let invite_id = teacher_invite_id(&client, user_id).await;
+        let res = client
+            .post(format!(
+                "/invites/institution-teacher/{}/decline",
+                invite_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert!(has_notification(&client, admin_id, "Invitation declined").await);
+        logout(&client).await;
+
+        invite(&client, institution_id, "teacher", EMAIL).await;
+        let invite_id = teacher_invite_id(&client, user_id).await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        let res = client
+            .post(format!("/invites/institution-teacher/{}/accept", invite_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert!(has_notification(&client, admin_id, "Invitation accepted").await);
+        assert!(
+            Database::get_one(client.rocket())
+                .await
+                .unwrap()
+                .run(move |c| {
+                    diesel::select(diesel::dsl::exists(
+                        institution_teacher::table
+                            .filter(institution_teacher::user_id.eq(user_id))
+                            .filter(institution_teacher::institution_id.eq(institution_id)),
+                    ))
+                    .get_result::<bool>(c)
+                    .unwrap()
+                })
+                .await
+        );
+        // invitations can only be accepted once
+        let res = client
+            .post(format!("/invites/institution-teacher/{}/accept", invite_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+        logout(&client).await;
+
+        // nobody can be invited to something they're already part of
+        assert_eq!(
+            invite(&client, institution_id, "teacher", USERNAME).await,
+            Status::Conflict
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_revoked_and_expired_invites() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (_, institution_id, user_id) = setup(&client).await;
+
+        assert_eq!(
+            invite(&client, institution_id, "student", STUDENT_USERNAME).await,
+            Status::Conflict
+        );
+
+        invite(&client, institution_id, "student", USERNAME).await;
+        login_user(ADMIN_EMAIL, ADMIN_PASSWORD, &client).await;
+        let invites = client
+            .get("/invites")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(invites.contains(&format!("You invited {}", USERNAME)));
+        let invite_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                institution_student_invite::table
+                    .filter(institution_student_invite::invited_user_id.eq(user_id))
+                    .select(institution_student_invite::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        let res = client
+            .post(format!("/invites/institution-student/{}/revoke", invite_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        logout(&client).await;
+        assert!(has_notification(&client, user_id, "Invitation withdrawn").await);
+
+        invite(&client, institution_id, "teacher", USERNAME).await;
+        let invite_id = teacher_invite_id(&client, user_id).await;
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::update(institution_teacher_invite::table.find(invite_id))
+                    .set(
+                        institution_teacher_invite::expires
+                            .eq(chrono::Utc::now().naive_utc() - chrono::Duration::days(1)),
+                    )
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        let res = client
+            .post(format!("/invites/institution-teacher/{}/accept", invite_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_invites_from_demoted_admins_cannot_be_accepted() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (admin_id, institution_id, user_id) = setup(&client).await;
+        invite(&client, institution_id, "administrator", USERNAME).await;
+        let invite_id = Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::delete(administrator::table.filter(administrator::user_id.eq(admin_id)))
+                    .execute(c)
+                    .unwrap();
+                administrator_invite::table
+                    .filter(administrator_invite::invited_user_id.eq(user_id))
+                    .select(administrator_invite::id)
+                    .first::<i32>(c)
+                    .unwrap()
+            })
+            .await;
+        login_user(USERNAME, PASSWORD, &client).await;
+        let res = client
+            .post(format!("/invites/administrator/{}/accept", invite_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("can no longer invite"));
+    }
+
+    #[rocket::async_test]
+    async fn test_invitations_respect_institution_domain() {
+        let _mock_server = mock_email_server(1..).await;
+        let client = client().await;
+        let (_, institution_id, user_id) = setup(&client).await;
+        invite(&client, institution_id, "teacher", USERNAME).await;
+        let invite_id = teacher_invite_id(&client, user_id).await;
+        Database::get_one(client.rocket())
+            .await
+            .unwrap()
+            .run(move |c| {
+                diesel::update(institution::table.find(institution_id))
+                    .set(institution::enforce_same_domain.eq(true))
+                    .execute(c)
+                    .unwrap();
+                diesel::update(users::table.find(user_id))
+                    .set(users::email.eq("newcomer@elsewhere.org"))
+                    .execute(c)
+                    .unwrap()
+            })
+            .await;
+        // invitations sent before the policy was turned on can't be accepted
+        login_user(USERNAME, PASSWORD, &client).await;
+        let res = client
+            .post(format!("/invites/institution-teacher/{}/accept", invite_id))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Forbidden);
+        assert!(res.into_string().await.unwrap().contains("its own domain"));
+        logout(&client).await;
+
+        // and new ones can't be sent
+        assert_eq!(
+            invite(&client, institution_id, "student", USERNAME).await,
+            Status::Forbidden
+        );
+    }
+}
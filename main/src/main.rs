@@ -34,11 +34,14 @@ mod class;
 mod dashboard;
 mod db;
 mod email;
+mod export;
 mod home;
 mod institution;
+mod invite;
 mod models;
 mod notifications;
 mod schema;
+mod storage;
 mod ui;
 mod utils;
 
@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::{account_lockout, auth_attempt};
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[table_name = "auth_attempt"]
+pub struct AuthAttempt {
+    pub id: i32,
+    pub action: String,
+    pub user_id: Option<i32>,
+    pub ip: Option<String>,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "auth_attempt"]
+pub struct NewAuthAttempt<'a> {
+    pub action: &'a str,
+    pub user_id: Option<i32>,
+    pub ip: Option<&'a str>,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[table_name = "account_lockout"]
+pub struct AccountLockout {
+    pub id: i32,
+    pub user_id: i32,
+    pub locked_until: NaiveDateTime,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "account_lockout"]
+pub struct NewAccountLockout {
+    pub user_id: i32,
+    pub locked_until: NaiveDateTime,
+    pub created: NaiveDateTime,
+}
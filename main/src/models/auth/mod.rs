@@ -0,0 +1,8 @@
+//! Models used to authenticate users.
+
+pub mod limit;
+pub mod oidc;
+pub mod reset;
+pub mod session;
+pub mod token;
+pub mod two_factor;
@@ -0,0 +1,46 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::{oidc_link, oidc_login};
+
+/// An OpenID Connect login which has been started, but not yet completed.
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[table_name = "oidc_login"]
+pub struct OidcLogin {
+    pub id: i32,
+    pub state: String,
+    pub institution_id: i32,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "oidc_login"]
+pub struct NewOidcLogin<'a> {
+    pub state: &'a str,
+    pub institution_id: i32,
+    pub code_verifier: &'a str,
+    pub nonce: &'a str,
+    pub created: NaiveDateTime,
+}
+
+/// Records that logging in with an institution's identity provider as `subject` logs somebody in
+/// to an account.
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[table_name = "oidc_link"]
+pub struct OidcLink {
+    pub id: i32,
+    pub user_id: i32,
+    pub institution_id: i32,
+    pub subject: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "oidc_link"]
+pub struct NewOidcLink<'a> {
+    pub user_id: i32,
+    pub institution_id: i32,
+    pub subject: &'a str,
+    pub created: NaiveDateTime,
+}
@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::password_reset;
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[table_name = "password_reset"]
+pub struct PasswordReset {
+    pub id: i32,
+    pub user_id: i32,
+    pub nonce: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "password_reset"]
+pub struct NewPasswordReset<'a> {
+    pub user_id: i32,
+    pub nonce: &'a str,
+    pub created: NaiveDateTime,
+}
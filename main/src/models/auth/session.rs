@@ -0,0 +1,29 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::session;
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[table_name = "session"]
+pub struct Session {
+    pub id: i32,
+    /// The value stored in the user's login cookie. This is never sent back to the user once the
+    /// session has been created.
+    #[serde(skip_serializing)]
+    pub token: String,
+    pub user_id: i32,
+    pub created: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "session"]
+pub struct NewSession<'a> {
+    pub token: &'a str,
+    pub user_id: i32,
+    pub created: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub user_agent: Option<&'a str>,
+    pub ip: Option<&'a str>,
+}
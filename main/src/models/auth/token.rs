@@ -0,0 +1,35 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::api_token;
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[table_name = "api_token"]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    /// The SHA-256 hash of the token. The token itself is never stored.
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub created: NaiveDateTime,
+    pub expires: Option<NaiveDateTime>,
+    pub last_used: Option<NaiveDateTime>,
+    pub read_only: bool,
+    pub scope_classes: bool,
+    pub scope_tasks: bool,
+    pub scope_notifications: bool,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "api_token"]
+pub struct NewApiToken<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+    pub token_hash: &'a str,
+    pub created: NaiveDateTime,
+    pub expires: Option<NaiveDateTime>,
+    pub read_only: bool,
+    pub scope_classes: bool,
+    pub scope_tasks: bool,
+    pub scope_notifications: bool,
+}
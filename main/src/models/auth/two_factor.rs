@@ -0,0 +1,40 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::{totp, totp_recovery_code};
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[table_name = "totp"]
+pub struct Totp {
+    pub id: i32,
+    pub user_id: i32,
+    pub secret: String,
+    pub confirmed: bool,
+    pub last_used_step: Option<i64>,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "totp"]
+pub struct NewTotp<'a> {
+    pub user_id: i32,
+    pub secret: &'a str,
+    pub confirmed: bool,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[table_name = "totp_recovery_code"]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub user_id: i32,
+    pub code_hash: String,
+    pub used: bool,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "totp_recovery_code"]
+pub struct NewRecoveryCode<'a> {
+    pub user_id: i32,
+    pub code_hash: &'a str,
+    pub used: bool,
+}
@@ -1,7 +1,13 @@
+pub mod preferences;
+
+pub use preferences::*;
+
 use crate::schema::caldav;
 use crate::schema::caldav_unauthenticated;
 use crate::schema::calendar;
+use crate::schema::gcal_oauth_state;
 use crate::schema::google_calendar;
+use chrono::NaiveDateTime;
 
 #[derive(Debug, Queryable, Identifiable)]
 #[table_name = "calendar"]
@@ -53,6 +59,9 @@ pub struct GoogleCalendar {
     pub refresh_token: String,
     pub access_token: String,
     pub lovelace_calendar_id: String,
+    /// When the access token stops working (if this isn't known, the token is refreshed the next
+    /// time it is used).
+    pub access_token_expires: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Insertable)]
@@ -62,6 +71,27 @@ pub struct NewGoogleCalendar<'a> {
     pub access_token: &'a str,
     pub calendar_id: i32,
     pub lovelace_calendar_id: &'a str,
+    pub access_token_expires: Option<NaiveDateTime>,
+}
+
+/// A Google Calendar connection which has been started, but not yet completed.
+#[derive(Debug, Queryable, Identifiable, Clone)]
+#[table_name = "gcal_oauth_state"]
+pub struct GcalOauthState {
+    pub id: i32,
+    pub state: String,
+    pub user_id: i32,
+    pub lovelace_calendar_id: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "gcal_oauth_state"]
+pub struct NewGcalOauthState<'a> {
+    pub state: &'a str,
+    pub user_id: i32,
+    pub lovelace_calendar_id: &'a str,
+    pub created: NaiveDateTime,
 }
 
 #[derive(Queryable, Identifiable, Debug)]
@@ -72,6 +102,9 @@ pub struct CalDav {
     pub username: String,
     pub password: String,
     pub url: String,
+    /// The calendar which work is scheduled into (see
+    /// [`crate::calendar::scheduler::caldav`]).
+    pub lovelace_calendar_url: Option<String>,
 }
 
 #[derive(Insertable, Debug)]
@@ -89,6 +122,9 @@ pub struct CalDavUnauthenticated {
     pub id: i32,
     pub calendar_id: i32,
     pub url: String,
+    /// The calendar which work is scheduled into (see
+    /// [`crate::calendar::scheduler::caldav`]).
+    pub lovelace_calendar_url: Option<String>,
 }
 
 #[derive(Insertable, Debug)]
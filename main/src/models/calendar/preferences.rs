@@ -0,0 +1,51 @@
+use chrono::NaiveTime;
+
+use crate::schema::{study_hours, study_preferences};
+
+/// When (and how much) a user wants to study (see [`crate::calendar::preferences`]).
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[table_name = "study_preferences"]
+pub struct StudyPreferences {
+    pub id: i32,
+    pub user_id: i32,
+    /// The most work which will be scheduled on any one day.
+    pub max_minutes_per_day: i32,
+    /// How much time is left free before and after each of the user's events.
+    pub buffer_minutes: i32,
+    /// How long the user can work for before having a break (if this isn't set, no breaks are
+    /// scheduled).
+    pub break_every_minutes: Option<i32>,
+    pub break_minutes: i32,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Clone)]
+#[table_name = "study_preferences"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewStudyPreferences {
+    pub user_id: i32,
+    pub max_minutes_per_day: i32,
+    pub buffer_minutes: i32,
+    pub break_every_minutes: Option<i32>,
+    pub break_minutes: i32,
+}
+
+/// A time of day during which the user is happy to study (in their timezone).
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[table_name = "study_hours"]
+pub struct StudyHours {
+    pub id: i32,
+    pub user_id: i32,
+    /// The number of days from Monday (so Monday is 0 and Sunday is 6).
+    pub weekday: i16,
+    pub starts: NaiveTime,
+    pub ends: NaiveTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "study_hours"]
+pub struct NewStudyHours {
+    pub user_id: i32,
+    pub weekday: i16,
+    pub starts: NaiveTime,
+    pub ends: NaiveTime,
+}
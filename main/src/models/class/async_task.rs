@@ -14,6 +14,11 @@ pub struct ClassAsynchronousTask {
     pub due_date: NaiveDateTime,
     pub class_teacher_id: i32,
     pub class_id: i32,
+    /// If this is set, students can resubmit their work for this task until this date (otherwise
+    /// they can only submit it once).
+    pub resubmit_until: Option<NaiveDateTime>,
+    /// How long (in minutes) the teacher thinks the task will take.
+    pub estimated_minutes: i32,
 }
 
 impl ClassAsynchronousTask {
@@ -35,6 +40,8 @@ pub struct UpdateClassAsynchronousTask {
     pub due_date: Option<NaiveDateTime>,
     pub class_teacher_id: Option<i32>,
     pub class_id: Option<i32>,
+    pub resubmit_until: Option<Option<NaiveDateTime>>,
+    pub estimated_minutes: Option<i32>,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -46,6 +53,8 @@ pub struct NewClassAsynchronousTask<'a> {
     pub due_date: NaiveDateTime,
     pub class_teacher_id: i32,
     pub class_id: i32,
+    pub resubmit_until: Option<NaiveDateTime>,
+    pub estimated_minutes: i32,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -77,4 +86,23 @@ pub struct StudentClassAsynchronousTask {
     pub class_student_id: i32,
     pub class_asynchronous_task_id: i32,
     pub completed: bool,
+    /// If this is set, the student's work is due at this time rather than when the task is due
+    /// (e.g. because they've been given an extension). Use
+    /// [`StudentClassAsynchronousTask::effective_due_date`] to find out when their work is due.
+    pub due_date: Option<NaiveDateTime>,
+    /// The student's own estimate of how long (in minutes) the task will take them. If this isn't
+    /// set, the teacher's estimate is used.
+    pub estimated_minutes: Option<i32>,
+}
+
+impl StudentClassAsynchronousTask {
+    /// When the student's work for the task is due.
+    pub fn effective_due_date(&self, task: &ClassAsynchronousTask) -> NaiveDateTime {
+        self.due_date.unwrap_or(task.due_date)
+    }
+
+    /// How long (in minutes) the task is expected to take the student.
+    pub fn estimated_minutes(&self, task: &ClassAsynchronousTask) -> i32 {
+        self.estimated_minutes.unwrap_or(task.estimated_minutes)
+    }
 }
@@ -0,0 +1,32 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::attendance;
+
+/// A mark in the attendance register (see [`crate::class::attendance`]).
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[table_name = "attendance"]
+pub struct Attendance {
+    pub id: i32,
+    pub class_synchronous_task_id: i32,
+    /// When the occurrence of the task would originally have started.
+    pub occurrence: NaiveDateTime,
+    pub class_student_id: i32,
+    /// Convert this into an [`crate::class::attendance::AttendanceMark`].
+    pub mark: i16,
+    pub note: Option<String>,
+    pub recorded_by: Option<i32>,
+    pub recorded: NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Clone)]
+#[table_name = "attendance"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewAttendance<'a> {
+    pub class_synchronous_task_id: i32,
+    pub occurrence: NaiveDateTime,
+    pub class_student_id: i32,
+    pub mark: i16,
+    pub note: Option<&'a str>,
+    pub recorded_by: Option<i32>,
+    pub recorded: NaiveDateTime,
+}
@@ -0,0 +1,73 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::{async_task_grading, async_task_mark, grade_category};
+
+/// A category of tasks in a class' gradebook (e.g. "Homework" or "Tests").
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[table_name = "grade_category"]
+pub struct GradeCategory {
+    pub id: i32,
+    pub class_id: i32,
+    pub name: String,
+    /// How much the category counts towards students' overall averages, relative to the other
+    /// categories in the class.
+    pub weight: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "grade_category"]
+pub struct NewGradeCategory<'a> {
+    pub class_id: i32,
+    pub name: &'a str,
+    pub weight: i32,
+}
+
+/// How an asynchronous task is graded (see [`crate::class::gradebook`]).
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[table_name = "async_task_grading"]
+pub struct AsyncTaskGrading {
+    pub id: i32,
+    pub class_asynchronous_task_id: i32,
+    /// Convert this into a [`crate::class::gradebook::GradingScheme`].
+    pub scheme: i16,
+    /// Only used if the task is marked out of a number of points.
+    pub max_points: Option<i32>,
+    pub grade_category_id: Option<i32>,
+    /// Whether students can see their marks.
+    pub released: bool,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Clone)]
+#[table_name = "async_task_grading"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewAsyncTaskGrading {
+    pub class_asynchronous_task_id: i32,
+    pub scheme: i16,
+    pub max_points: Option<i32>,
+    pub grade_category_id: Option<i32>,
+    pub released: bool,
+}
+
+/// The mark a student has been given for an asynchronous task.
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[table_name = "async_task_mark"]
+pub struct AsyncTaskMark {
+    pub id: i32,
+    pub student_class_asynchronous_task_id: i32,
+    /// The mark as a percentage (whichever scheme the task is graded with).
+    pub score: f64,
+    pub feedback: Option<String>,
+    pub marked_by: Option<i32>,
+    pub marked: NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Clone)]
+#[table_name = "async_task_mark"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewAsyncTaskMark<'a> {
+    pub student_class_asynchronous_task_id: i32,
+    pub score: f64,
+    pub feedback: Option<&'a str>,
+    pub marked_by: Option<i32>,
+    pub marked: NaiveDateTime,
+}
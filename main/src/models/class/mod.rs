@@ -5,14 +5,20 @@ use crate::db::Database;
 use crate::schema::class;
 
 pub mod async_task;
+pub mod attendance;
+pub mod grade;
 pub mod message;
 pub mod student;
+pub mod submission;
 pub mod sync_task;
 pub mod teacher;
 
 pub use async_task::*;
+pub use attendance::*;
+pub use grade::*;
 pub use message::*;
 pub use student::*;
+pub use submission::*;
 pub use sync_task::*;
 pub use teacher::*;
 
@@ -31,6 +37,12 @@ pub struct Class {
     pub code: String,
     pub institution_id: Option<i32>,
     pub student_group_id: Option<i32>,
+    /// The term the class runs during (only classes which are part of an institution can have
+    /// one).
+    pub academic_term_id: Option<i32>,
+    /// Whether the class' teachers should be told when one of their students doesn't have enough
+    /// time to finish their work before it's due.
+    pub notify_teachers_of_overload: bool,
 }
 
 impl Class {
@@ -78,6 +90,7 @@ pub struct NewClass<'a> {
     pub code: &'a str,
     pub institution_id: Option<i32>,
     pub student_group_id: Option<i32>,
+    pub academic_term_id: Option<i32>,
 }
 
 impl<'a> NewClass<'a> {
@@ -96,6 +109,7 @@ impl<'a> NewClass<'a> {
             code,
             institution_id,
             student_group_id,
+            academic_term_id: None,
         }
     }
 }
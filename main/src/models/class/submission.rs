@@ -0,0 +1,56 @@
+use chrono::NaiveDateTime;
+
+use crate::models::StudentClassAsynchronousTask;
+use crate::schema::{async_task_submission, async_task_submission_file};
+
+/// Something a student has handed in for an asynchronous task.
+#[derive(
+    Queryable, Identifiable, Associations, Debug, Clone, Serialize, Deserialize, PartialEq,
+)]
+#[table_name = "async_task_submission"]
+#[belongs_to(StudentClassAsynchronousTask)]
+pub struct AsyncTaskSubmission {
+    pub id: i32,
+    pub student_class_asynchronous_task_id: i32,
+    pub text: Option<String>,
+    pub submitted: NaiveDateTime,
+    /// Whether this was submitted after the task's due date.
+    pub late: bool,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "async_task_submission"]
+pub struct NewAsyncTaskSubmission<'a> {
+    pub student_class_asynchronous_task_id: i32,
+    pub text: Option<&'a str>,
+    pub submitted: NaiveDateTime,
+    pub late: bool,
+}
+
+/// A file which is part of a submission. The contents of the file are kept in the storage backend
+/// (see [`crate::storage`]).
+#[derive(
+    Queryable, Identifiable, Associations, Debug, Clone, Serialize, Deserialize, PartialEq,
+)]
+#[table_name = "async_task_submission_file"]
+#[belongs_to(AsyncTaskSubmission)]
+pub struct SubmissionFile {
+    pub id: i32,
+    pub async_task_submission_id: i32,
+    pub filename: String,
+    pub content_type: String,
+    /// In bytes.
+    pub size: i64,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "async_task_submission_file"]
+pub struct NewSubmissionFile<'a> {
+    pub async_task_submission_id: i32,
+    pub filename: &'a str,
+    pub content_type: &'a str,
+    pub size: i64,
+    pub storage_key: &'a str,
+}
@@ -3,6 +3,7 @@ use malvolio::prelude::*;
 
 use crate::models::ClassStudent;
 use crate::schema::class_synchronous_task;
+use crate::schema::class_synchronous_task_occurrence;
 use crate::schema::student_class_synchronous_task;
 
 #[derive(Queryable, Identifiable, PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +17,10 @@ pub struct ClassSynchronousTask {
     pub end_time: NaiveDateTime,
     pub class_teacher_id: i32,
     pub class_id: i32,
+    /// If the task repeats, the RRULE which says when it does (see
+    /// [`crate::class::tasks::synchronous::recurrence`]). The start and end times are those of the
+    /// first occurrence.
+    pub recurrence: Option<String>,
 }
 
 impl ClassSynchronousTask {
@@ -37,6 +42,7 @@ pub struct NewClassSynchronousTask<'a> {
     pub end_time: NaiveDateTime,
     pub class_teacher_id: i32,
     pub class_id: i32,
+    pub recurrence: Option<&'a str>,
 }
 
 #[derive(AsChangeset, Clone, Debug)]
@@ -49,6 +55,7 @@ pub struct UpdateClassSynchronousTask<'a> {
     pub end_time: Option<NaiveDateTime>,
     pub class_teacher_id: Option<i32>,
     pub class_id: Option<i32>,
+    pub recurrence: Option<Option<&'a str>>,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -58,7 +65,7 @@ pub struct NewStudentClassSynchronousTask {
     pub class_synchronous_task_id: i32,
 }
 
-#[derive(Queryable, Identifiable, Associations, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Identifiable, Associations, Debug, Clone, Serialize, Deserialize)]
 #[table_name = "student_class_synchronous_task"]
 #[belongs_to(ClassStudent)]
 #[belongs_to(ClassSynchronousTask)]
@@ -67,3 +74,35 @@ pub struct StudentClassSynchronousTask {
     pub class_student_id: i32,
     pub class_synchronous_task_id: i32,
 }
+
+/// An occurrence of a recurring task which has been changed (or cancelled). Fields which are `None`
+/// are the same as the task's.
+#[derive(
+    Queryable, Identifiable, Associations, PartialEq, Debug, Clone, Serialize, Deserialize,
+)]
+#[table_name = "class_synchronous_task_occurrence"]
+#[belongs_to(ClassSynchronousTask)]
+pub struct ClassSynchronousTaskOccurrence {
+    pub id: i32,
+    pub class_synchronous_task_id: i32,
+    /// When the occurrence would have started, had it not been changed.
+    pub original_start_time: NaiveDateTime,
+    pub cancelled: bool,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub start_time: Option<NaiveDateTime>,
+    pub end_time: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Clone)]
+#[table_name = "class_synchronous_task_occurrence"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewClassSynchronousTaskOccurrence<'a> {
+    pub class_synchronous_task_id: i32,
+    pub original_start_time: NaiveDateTime,
+    pub cancelled: bool,
+    pub title: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub start_time: Option<NaiveDateTime>,
+    pub end_time: Option<NaiveDateTime>,
+}
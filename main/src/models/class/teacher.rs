@@ -1,3 +1,5 @@
+use chrono::NaiveDateTime;
+
 use crate::schema::class_teacher;
 use crate::schema::class_teacher_invite;
 
@@ -23,4 +25,6 @@ pub struct NewClassTeacherInvite {
     pub invited_user_id: i32,
     pub class_id: i32,
     pub accepted: bool,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
 }
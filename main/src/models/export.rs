@@ -0,0 +1,30 @@
+use chrono::NaiveDateTime;
+
+use crate::schema::data_export;
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[table_name = "data_export"]
+pub struct DataExport {
+    pub id: i32,
+    pub user_id: i32,
+    pub requested_by: Option<i32>,
+    pub created: NaiveDateTime,
+    pub completed: Option<NaiveDateTime>,
+    pub failed: bool,
+    #[serde(skip_serializing)]
+    pub json: Option<String>,
+    #[serde(skip_serializing)]
+    pub html: Option<String>,
+    /// Set for exports which an administrator asked for (which only contain data belonging to
+    /// their institution).
+    pub institution_id: Option<i32>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "data_export"]
+pub struct NewDataExport {
+    pub user_id: i32,
+    pub requested_by: Option<i32>,
+    pub created: NaiveDateTime,
+    pub institution_id: Option<i32>,
+}
@@ -0,0 +1,63 @@
+use chrono::NaiveDate;
+
+use crate::schema::{academic_term, academic_year, holiday};
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize)]
+#[table_name = "academic_year"]
+pub struct AcademicYear {
+    pub id: i32,
+    pub institution_id: i32,
+    pub name: String,
+    pub starts: NaiveDate,
+    pub ends: NaiveDate,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "academic_year"]
+pub struct NewAcademicYear<'a> {
+    pub institution_id: i32,
+    pub name: &'a str,
+    pub starts: NaiveDate,
+    pub ends: NaiveDate,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize)]
+#[table_name = "academic_term"]
+pub struct AcademicTerm {
+    pub id: i32,
+    pub academic_year_id: i32,
+    /// Half-terms are part of a term.
+    pub parent_term_id: Option<i32>,
+    pub name: String,
+    pub starts: NaiveDate,
+    pub ends: NaiveDate,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "academic_term"]
+pub struct NewAcademicTerm<'a> {
+    pub academic_year_id: i32,
+    pub parent_term_id: Option<i32>,
+    pub name: &'a str,
+    pub starts: NaiveDate,
+    pub ends: NaiveDate,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize)]
+#[table_name = "holiday"]
+pub struct Holiday {
+    pub id: i32,
+    pub academic_year_id: i32,
+    pub name: String,
+    pub starts: NaiveDate,
+    pub ends: NaiveDate,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "holiday"]
+pub struct NewHoliday<'a> {
+    pub academic_year_id: i32,
+    pub name: &'a str,
+    pub starts: NaiveDate,
+    pub ends: NaiveDate,
+}
@@ -1,3 +1,5 @@
+use chrono::NaiveDateTime;
+
 use crate::schema::{administrator, administrator_invite};
 
 #[derive(Queryable, Identifiable, Serialize, Deserialize, Debug, Clone)]
@@ -23,6 +25,8 @@ pub struct AdministratorInvite {
     pub invited_user_id: i32,
     pub institution_id: i32,
     pub accepted: bool,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
 }
 
 #[derive(Insertable, Debug)]
@@ -32,4 +36,6 @@ pub struct NewAdministratorInvite {
     pub invited_user_id: i32,
     pub institution_id: i32,
     pub accepted: bool,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
 }
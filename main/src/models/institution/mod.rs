@@ -2,7 +2,10 @@ use chrono::NaiveDateTime;
 
 use crate::schema::institution;
 
+pub mod academic;
 pub mod administrator;
+pub mod oidc;
+pub mod roster;
 pub mod student;
 pub mod student_group;
 pub mod teacher;
@@ -18,6 +21,10 @@ pub struct Institution {
     pub let_teachers_create_classes: bool,
     pub let_all_users_create_classes: bool,
     pub let_teachers_add_sync_tasks: bool,
+    pub require_two_factor: bool,
+    /// Teachers and administrators are notified when a student's attendance in one of the
+    /// institution's classes drops below this percentage (if it's set).
+    pub attendance_threshold: Option<i32>,
 }
 
 #[derive(Insertable, Debug)]
@@ -30,6 +37,8 @@ pub struct NewInstitution<'a> {
     pub let_teachers_create_classes: bool,
     pub let_all_users_create_classes: bool,
     pub let_teachers_add_sync_tasks: bool,
+    pub require_two_factor: bool,
+    pub attendance_threshold: Option<i32>,
 }
 
 #[derive(AsChangeset, Debug, Default)]
@@ -39,4 +48,9 @@ pub struct UpdateInstitution {
     pub domain: Option<String>,
     pub created: Option<NaiveDateTime>,
     pub enforce_same_domain: Option<bool>,
+    pub let_teachers_create_classes: Option<bool>,
+    pub let_all_users_create_classes: Option<bool>,
+    pub let_teachers_add_sync_tasks: Option<bool>,
+    pub require_two_factor: Option<bool>,
+    pub attendance_threshold: Option<Option<i32>>,
 }
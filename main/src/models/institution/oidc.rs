@@ -0,0 +1,22 @@
+use crate::schema::institution_oidc;
+
+/// The OpenID Connect issuer which members of an institution can use to log in.
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[table_name = "institution_oidc"]
+pub struct InstitutionOidc {
+    pub id: i32,
+    pub institution_id: i32,
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret: String,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "institution_oidc"]
+pub struct NewInstitutionOidc<'a> {
+    pub institution_id: i32,
+    pub issuer: &'a str,
+    pub client_id: &'a str,
+    pub client_secret: &'a str,
+}
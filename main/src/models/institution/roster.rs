@@ -0,0 +1,22 @@
+use crate::schema::roster_link;
+
+/// Records which row an identifier from an imported roster was imported as.
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[table_name = "roster_link"]
+pub struct RosterLink {
+    pub id: i32,
+    pub institution_id: i32,
+    /// Either "user", "group" or "class".
+    pub kind: String,
+    pub source_id: String,
+    pub target_id: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "roster_link"]
+pub struct NewRosterLink<'a> {
+    pub institution_id: i32,
+    pub kind: &'a str,
+    pub source_id: &'a str,
+    pub target_id: i32,
+}
@@ -1,3 +1,5 @@
+use chrono::NaiveDateTime;
+
 use crate::schema::institution_student;
 use crate::schema::institution_student_invite;
 
@@ -24,6 +26,8 @@ pub struct InstitutionStudentInvite {
     pub invited_user_id: i32,
     pub institution_id: i32,
     pub accepted: bool,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -33,4 +37,6 @@ pub struct NewInstitutionStudentInvite {
     pub invited_user_id: i32,
     pub institution_id: i32,
     pub accepted: bool,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
 }
@@ -1,4 +1,6 @@
-use crate::schema::student_group_teacher;
+use chrono::NaiveDateTime;
+
+use crate::schema::{student_group_teacher, student_group_teacher_invite};
 
 #[derive(Queryable, Identifiable, Debug, Clone, Serialize, Deserialize)]
 #[table_name = "student_group_teacher"]
@@ -14,3 +16,14 @@ pub struct NewStudentGroupTeacher {
     pub user_id: i32,
     pub student_group_id: i32,
 }
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "student_group_teacher_invite"]
+pub struct NewStudentGroupTeacherInvite {
+    pub inviting_user_id: i32,
+    pub invited_user_id: i32,
+    pub student_group_id: i32,
+    pub accepted: bool,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
+}
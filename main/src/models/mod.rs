@@ -3,8 +3,10 @@ This source code file is distributed subject to the terms of the GNU Affero Gene
 A copy of this license can be found in the `licenses` directory at the root of this project.
 */
 
+pub mod auth;
 pub mod calendar;
 pub mod class;
+pub mod export;
 pub mod institution;
 pub mod notification;
 pub mod user;
@@ -1,6 +1,9 @@
 use chrono::NaiveDateTime;
 
-use crate::{notifications::NotificationPriority, schema::notifications};
+use crate::{
+    notifications::NotificationPriority,
+    schema::{notifications, overload_warning},
+};
 
 #[derive(
     Queryable, Identifiable, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Ord, PartialOrd,
@@ -46,3 +49,13 @@ impl<'a> NewNotification<'a> {
         }
     }
 }
+
+/// Which task (and whose work on it) a warning about work which can't be finished in time is
+/// about (see [`crate::calendar::scheduler::InfeasibilityReport::notify`]).
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "overload_warning"]
+pub struct NewOverloadWarning {
+    pub notification_id: i32,
+    pub class_asynchronous_task_id: i32,
+    pub student_id: i32,
+}
@@ -9,7 +9,10 @@ use portia::levels::Level;
 use rocket::serde::json::Json;
 
 use crate::{
-    auth::AuthCookie,
+    auth::{
+        scope::{ManageNotifications, ReadNotifications},
+        ApiAuth, AuthCookie,
+    },
     db::{Database, DatabaseConnection},
     models::{NewNotification, Notification},
     utils::{default_head, json_response::ApiResponse},
@@ -84,7 +87,7 @@ where
 
 #[get("/")]
 pub async fn api_list_notifications(
-    auth: AuthCookie,
+    auth: ApiAuth<ReadNotifications>,
     conn: Database,
 ) -> Json<ApiResponse<Vec<Notification>>> {
     Json(match retrieve_notifications(auth.0, &conn).await {
@@ -122,10 +125,10 @@ async fn mark_read(
 #[get("/mark_read/<id>")]
 pub async fn api_mark_notification_as_read(
     id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageNotifications>,
     conn: Database,
 ) -> Json<ApiResponse<Notification>> {
-    Json(match mark_read(id, auth, &conn).await {
+    Json(match mark_read(id, auth.into(), &conn).await {
         Ok(notification) => ApiResponse::new_ok(notification),
         Err(_) => ApiResponse::new_err(
             "Encountered a database error when trying to update that item in the database.",
@@ -176,10 +179,10 @@ async fn delete_notification(
 #[get("/delete/<id>")]
 pub async fn api_delete_notification_with_id(
     id: i32,
-    auth: AuthCookie,
+    auth: ApiAuth<ManageNotifications>,
     conn: Database,
 ) -> Json<ApiResponse<()>> {
-    Json(match delete_notification(id, auth, &conn).await {
+    Json(match delete_notification(id, auth.into(), &conn).await {
         Ok(_) => ApiResponse::new_ok(()),
         Err(_) => ApiResponse::new_ok(()),
     })
@@ -271,7 +274,6 @@ impl<'a> Notify<'a> {
 
 #[cfg(test)]
 mod test {
-    use bcrypt::DEFAULT_COST;
     use diesel::prelude::*;
 
     use crate::{
@@ -302,7 +304,7 @@ mod test {
             .values(&NewUser::new(
                 USERNAME,
                 EMAIL,
-                bcrypt::hash(PASSWORD, DEFAULT_COST).unwrap().as_ref(),
+                crate::auth::hash::hash_password(PASSWORD).unwrap().as_ref(),
                 chrono::Utc::now().naive_utc(),
                 TIMEZONE,
             ))
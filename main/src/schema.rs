@@ -1,3 +1,33 @@
+table! {
+    academic_term (id) {
+        id -> Int4,
+        academic_year_id -> Int4,
+        parent_term_id -> Nullable<Int4>,
+        name -> Text,
+        starts -> Date,
+        ends -> Date,
+    }
+}
+
+table! {
+    academic_year (id) {
+        id -> Int4,
+        institution_id -> Int4,
+        name -> Text,
+        starts -> Date,
+        ends -> Date,
+    }
+}
+
+table! {
+    account_lockout (id) {
+        id -> Int4,
+        user_id -> Int4,
+        locked_until -> Timestamp,
+        created -> Timestamp,
+    }
+}
+
 table! {
     administrator (id) {
         id -> Int4,
@@ -13,6 +43,90 @@ table! {
         invited_user_id -> Int4,
         institution_id -> Int4,
         accepted -> Bool,
+        created -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
+table! {
+    api_token (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        created -> Timestamp,
+        expires -> Nullable<Timestamp>,
+        last_used -> Nullable<Timestamp>,
+        read_only -> Bool,
+        scope_classes -> Bool,
+        scope_tasks -> Bool,
+        scope_notifications -> Bool,
+    }
+}
+
+table! {
+    async_task_grading (id) {
+        id -> Int4,
+        class_asynchronous_task_id -> Int4,
+        scheme -> Int2,
+        max_points -> Nullable<Int4>,
+        grade_category_id -> Nullable<Int4>,
+        released -> Bool,
+    }
+}
+
+table! {
+    async_task_mark (id) {
+        id -> Int4,
+        student_class_asynchronous_task_id -> Int4,
+        score -> Float8,
+        feedback -> Nullable<Text>,
+        marked_by -> Nullable<Int4>,
+        marked -> Timestamp,
+    }
+}
+
+table! {
+    async_task_submission (id) {
+        id -> Int4,
+        student_class_asynchronous_task_id -> Int4,
+        text -> Nullable<Text>,
+        submitted -> Timestamp,
+        late -> Bool,
+    }
+}
+
+table! {
+    async_task_submission_file (id) {
+        id -> Int4,
+        async_task_submission_id -> Int4,
+        filename -> Text,
+        content_type -> Text,
+        size -> Int8,
+        storage_key -> Text,
+    }
+}
+
+table! {
+    attendance (id) {
+        id -> Int4,
+        class_synchronous_task_id -> Int4,
+        occurrence -> Timestamp,
+        class_student_id -> Int4,
+        mark -> Int2,
+        note -> Nullable<Text>,
+        recorded_by -> Nullable<Int4>,
+        recorded -> Timestamp,
+    }
+}
+
+table! {
+    auth_attempt (id) {
+        id -> Int4,
+        action -> Text,
+        user_id -> Nullable<Int4>,
+        ip -> Nullable<Text>,
+        created -> Timestamp,
     }
 }
 
@@ -23,6 +137,7 @@ table! {
         username -> Text,
         password -> Text,
         url -> Text,
+        lovelace_calendar_url -> Nullable<Text>,
     }
 }
 
@@ -31,6 +146,7 @@ table! {
         id -> Int4,
         calendar_id -> Int4,
         url -> Text,
+        lovelace_calendar_url -> Nullable<Text>,
     }
 }
 
@@ -51,6 +167,8 @@ table! {
         code -> Text,
         institution_id -> Nullable<Int4>,
         student_group_id -> Nullable<Int4>,
+        academic_term_id -> Nullable<Int4>,
+        notify_teachers_of_overload -> Bool,
     }
 }
 
@@ -63,6 +181,8 @@ table! {
         due_date -> Timestamp,
         class_teacher_id -> Int4,
         class_id -> Int4,
+        resubmit_until -> Nullable<Timestamp>,
+        estimated_minutes -> Int4,
     }
 }
 
@@ -108,6 +228,20 @@ table! {
         end_time -> Timestamp,
         class_teacher_id -> Int4,
         class_id -> Int4,
+        recurrence -> Nullable<Text>,
+    }
+}
+
+table! {
+    class_synchronous_task_occurrence (id) {
+        id -> Int4,
+        class_synchronous_task_id -> Int4,
+        original_start_time -> Timestamp,
+        cancelled -> Bool,
+        title -> Nullable<Text>,
+        description -> Nullable<Text>,
+        start_time -> Nullable<Timestamp>,
+        end_time -> Nullable<Timestamp>,
     }
 }
 
@@ -126,6 +260,32 @@ table! {
         invited_user_id -> Int4,
         class_id -> Int4,
         accepted -> Bool,
+        created -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
+table! {
+    data_export (id) {
+        id -> Int4,
+        user_id -> Int4,
+        requested_by -> Nullable<Int4>,
+        created -> Timestamp,
+        completed -> Nullable<Timestamp>,
+        failed -> Bool,
+        json -> Nullable<Text>,
+        html -> Nullable<Text>,
+        institution_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    gcal_oauth_state (id) {
+        id -> Int4,
+        state -> Text,
+        user_id -> Int4,
+        lovelace_calendar_id -> Text,
+        created -> Timestamp,
     }
 }
 
@@ -136,6 +296,26 @@ table! {
         refresh_token -> Text,
         access_token -> Text,
         lovelace_calendar_id -> Text,
+        access_token_expires -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    grade_category (id) {
+        id -> Int4,
+        class_id -> Int4,
+        name -> Text,
+        weight -> Int4,
+    }
+}
+
+table! {
+    holiday (id) {
+        id -> Int4,
+        academic_year_id -> Int4,
+        name -> Text,
+        starts -> Date,
+        ends -> Date,
     }
 }
 
@@ -149,6 +329,18 @@ table! {
         let_teachers_create_classes -> Bool,
         let_all_users_create_classes -> Bool,
         let_teachers_add_sync_tasks -> Bool,
+        require_two_factor -> Bool,
+        attendance_threshold -> Nullable<Int4>,
+    }
+}
+
+table! {
+    institution_oidc (id) {
+        id -> Int4,
+        institution_id -> Int4,
+        issuer -> Text,
+        client_id -> Text,
+        client_secret -> Text,
     }
 }
 
@@ -167,6 +359,8 @@ table! {
         invited_user_id -> Int4,
         institution_id -> Int4,
         accepted -> Bool,
+        created -> Timestamp,
+        expires -> Timestamp,
     }
 }
 
@@ -185,6 +379,8 @@ table! {
         invited_user_id -> Int4,
         institution_id -> Int4,
         accepted -> Bool,
+        created -> Timestamp,
+        expires -> Timestamp,
     }
 }
 
@@ -200,12 +396,75 @@ table! {
     }
 }
 
+table! {
+    oidc_link (id) {
+        id -> Int4,
+        user_id -> Int4,
+        institution_id -> Int4,
+        subject -> Text,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    oidc_login (id) {
+        id -> Int4,
+        state -> Text,
+        institution_id -> Int4,
+        code_verifier -> Text,
+        nonce -> Text,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    overload_warning (id) {
+        id -> Int4,
+        notification_id -> Int4,
+        class_asynchronous_task_id -> Int4,
+        student_id -> Int4,
+    }
+}
+
+table! {
+    password_reset (id) {
+        id -> Int4,
+        user_id -> Int4,
+        nonce -> Text,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    roster_link (id) {
+        id -> Int4,
+        institution_id -> Int4,
+        kind -> Text,
+        source_id -> Text,
+        target_id -> Int4,
+    }
+}
+
+table! {
+    session (id) {
+        id -> Int4,
+        token -> Text,
+        user_id -> Int4,
+        created -> Timestamp,
+        last_seen -> Timestamp,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
+    }
+}
+
 table! {
     student_class_asynchronous_task (id) {
         id -> Int4,
         class_student_id -> Int4,
         class_asynchronous_task_id -> Int4,
         completed -> Bool,
+        due_date -> Nullable<Timestamp>,
+        estimated_minutes -> Nullable<Int4>,
     }
 }
 
@@ -251,6 +510,49 @@ table! {
         invited_user_id -> Int4,
         student_group_id -> Int4,
         accepted -> Bool,
+        created -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
+table! {
+    study_hours (id) {
+        id -> Int4,
+        user_id -> Int4,
+        weekday -> Int2,
+        starts -> Time,
+        ends -> Time,
+    }
+}
+
+table! {
+    study_preferences (id) {
+        id -> Int4,
+        user_id -> Int4,
+        max_minutes_per_day -> Int4,
+        buffer_minutes -> Int4,
+        break_every_minutes -> Nullable<Int4>,
+        break_minutes -> Int4,
+    }
+}
+
+table! {
+    totp (id) {
+        id -> Int4,
+        user_id -> Int4,
+        secret -> Text,
+        confirmed -> Bool,
+        last_used_step -> Nullable<Int8>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    totp_recovery_code (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Text,
+        used -> Bool,
     }
 }
 
@@ -266,12 +568,27 @@ table! {
     }
 }
 
+joinable!(academic_term -> academic_year (academic_year_id));
+joinable!(academic_year -> institution (institution_id));
+joinable!(account_lockout -> users (user_id));
 joinable!(administrator -> institution (institution_id));
 joinable!(administrator -> users (user_id));
 joinable!(administrator_invite -> institution (institution_id));
+joinable!(api_token -> users (user_id));
+joinable!(async_task_grading -> class_asynchronous_task (class_asynchronous_task_id));
+joinable!(async_task_grading -> grade_category (grade_category_id));
+joinable!(async_task_mark -> student_class_asynchronous_task (student_class_asynchronous_task_id));
+joinable!(async_task_mark -> users (marked_by));
+joinable!(async_task_submission -> student_class_asynchronous_task (student_class_asynchronous_task_id));
+joinable!(async_task_submission_file -> async_task_submission (async_task_submission_id));
+joinable!(attendance -> class_student (class_student_id));
+joinable!(attendance -> class_synchronous_task (class_synchronous_task_id));
+joinable!(attendance -> users (recorded_by));
+joinable!(auth_attempt -> users (user_id));
 joinable!(caldav -> calendar (calendar_id));
 joinable!(caldav_unauthenticated -> calendar (calendar_id));
 joinable!(calendar -> users (user_id));
+joinable!(class -> academic_term (academic_term_id));
 joinable!(class -> institution (institution_id));
 joinable!(class -> student_group (student_group_id));
 joinable!(class_asynchronous_task -> class (class_id));
@@ -285,10 +602,17 @@ joinable!(class_student -> class (class_id));
 joinable!(class_student -> users (user_id));
 joinable!(class_synchronous_task -> class (class_id));
 joinable!(class_synchronous_task -> class_teacher (class_teacher_id));
+joinable!(class_synchronous_task_occurrence -> class_synchronous_task (class_synchronous_task_id));
 joinable!(class_teacher -> class (class_id));
 joinable!(class_teacher -> users (user_id));
 joinable!(class_teacher_invite -> class (class_id));
+joinable!(data_export -> institution (institution_id));
+joinable!(data_export -> users (user_id));
+joinable!(gcal_oauth_state -> users (user_id));
 joinable!(google_calendar -> calendar (calendar_id));
+joinable!(grade_category -> class (class_id));
+joinable!(holiday -> academic_year (academic_year_id));
+joinable!(institution_oidc -> institution (institution_id));
 joinable!(institution_student -> institution (institution_id));
 joinable!(institution_student -> users (user_id));
 joinable!(institution_student_invite -> institution (institution_id));
@@ -296,6 +620,15 @@ joinable!(institution_teacher -> institution (institution_id));
 joinable!(institution_teacher -> users (user_id));
 joinable!(institution_teacher_invite -> institution (institution_id));
 joinable!(notifications -> users (user_id));
+joinable!(oidc_link -> institution (institution_id));
+joinable!(oidc_link -> users (user_id));
+joinable!(oidc_login -> institution (institution_id));
+joinable!(overload_warning -> class_asynchronous_task (class_asynchronous_task_id));
+joinable!(overload_warning -> notifications (notification_id));
+joinable!(overload_warning -> users (student_id));
+joinable!(password_reset -> users (user_id));
+joinable!(roster_link -> institution (institution_id));
+joinable!(session -> users (user_id));
 joinable!(student_class_asynchronous_task -> class_asynchronous_task (class_asynchronous_task_id));
 joinable!(student_class_asynchronous_task -> class_student (class_student_id));
 joinable!(student_class_synchronous_task -> class_student (class_student_id));
@@ -306,10 +639,24 @@ joinable!(student_group_student -> users (user_id));
 joinable!(student_group_teacher -> student_group (student_group_id));
 joinable!(student_group_teacher -> users (user_id));
 joinable!(student_group_teacher_invite -> student_group (student_group_id));
+joinable!(study_hours -> users (user_id));
+joinable!(study_preferences -> users (user_id));
+joinable!(totp -> users (user_id));
+joinable!(totp_recovery_code -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    academic_term,
+    academic_year,
+    account_lockout,
     administrator,
     administrator_invite,
+    api_token,
+    async_task_grading,
+    async_task_mark,
+    async_task_submission,
+    async_task_submission_file,
+    attendance,
+    auth_attempt,
     caldav,
     caldav_unauthenticated,
     calendar,
@@ -319,20 +666,36 @@ allow_tables_to_appear_in_same_query!(
     class_message_reply,
     class_student,
     class_synchronous_task,
+    class_synchronous_task_occurrence,
     class_teacher,
     class_teacher_invite,
+    data_export,
+    gcal_oauth_state,
     google_calendar,
+    grade_category,
+    holiday,
     institution,
+    institution_oidc,
     institution_student,
     institution_student_invite,
     institution_teacher,
     institution_teacher_invite,
     notifications,
+    oidc_link,
+    oidc_login,
+    overload_warning,
+    password_reset,
+    roster_link,
+    session,
     student_class_asynchronous_task,
     student_class_synchronous_task,
     student_group,
     student_group_student,
     student_group_teacher,
     student_group_teacher_invite,
+    study_hours,
+    study_preferences,
+    totp,
+    totp_recovery_code,
     users,
 );
@@ -0,0 +1,157 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+//! Where uploaded files (e.g. homework submissions) are kept.
+//!
+//! Files are stored through a [`StorageBackend`], which maps keys (which we generate) to the
+//! contents of files. Only a backend which keeps files on the local disk is built in, but others
+//! (e.g. for object storage) can be added by implementing the trait and returning them from
+//! [`Storage::from_env`].
+//!
+//! The backend is chosen using the `STORAGE_BACKEND` environment variable (`local` by default).
+//! The local backend keeps files in the directory given by `UPLOAD_DIRECTORY` (`uploads`, relative
+//! to the working directory, by default).
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use futures::future::BoxFuture;
+use rocket::tokio::fs;
+
+/// Somewhere files can be kept.
+pub trait StorageBackend: Send + Sync {
+    /// Stores the contents under the key, replacing anything already stored under it.
+    fn store<'a>(&'a self, key: &'a str, contents: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+    /// Retrieves whatever is stored under the key.
+    fn retrieve<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>>;
+    /// Deletes whatever is stored under the key (it isn't an error if nothing is).
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Keeps files in a directory on the local disk.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The path the file with this key is kept at. Keys may contain slashes (which become
+    /// directories), but can't point outside of the root directory.
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let key = Path::new(key);
+        if key
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            Ok(self.root.join(key))
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid storage key",
+            ))
+        }
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn store<'a>(&'a self, key: &'a str, contents: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(path, contents).await
+        })
+    }
+
+    fn retrieve<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(async move { fs::read(self.path(key)?).await })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match fs::remove_file(self.path(key)?).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// The storage backend in use (this is managed by Rocket, so handlers can ask for a
+/// `&State<Storage>`).
+pub struct Storage(Box<dyn StorageBackend>);
+
+impl Storage {
+    pub fn new(backend: impl StorageBackend + 'static) -> Self {
+        Self(Box::new(backend))
+    }
+
+    /// Sets up the backend chosen through the environment variables (see the module
+    /// documentation).
+    pub fn from_env() -> Self {
+        match std::env::var("STORAGE_BACKEND").as_deref() {
+            Ok("local") | Err(_) => {}
+            Ok(other) => panic!("unknown storage backend `{}`", other),
+        }
+        cfg_if! {
+            if #[cfg(test)] {
+                // so that running the tests doesn't leave files lying around the repository
+                let root = std::env::temp_dir().join("lovelace-test-uploads");
+            } else {
+                let root = std::env::var("UPLOAD_DIRECTORY")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| PathBuf::from("uploads"));
+            }
+        }
+        Self::new(LocalStorage::new(root))
+    }
+
+    /// Generates a new (unique) key to store a file under.
+    pub fn new_key(prefix: &str) -> String {
+        format!("{}/{}", prefix, uuid::Uuid::new_v4())
+    }
+}
+
+impl std::ops::Deref for Storage {
+    type Target = dyn StorageBackend;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test_storage {
+    use super::{LocalStorage, StorageBackend};
+
+    #[rocket::async_test]
+    async fn test_local_storage() {
+        let root = std::env::temp_dir().join(format!("lovelace-storage-{}", uuid::Uuid::new_v4()));
+        let storage = LocalStorage::new(&root);
+        storage
+            .store("submissions/a", b"contents".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.retrieve("submissions/a").await.unwrap(),
+            b"contents".to_vec()
+        );
+        storage.delete("submissions/a").await.unwrap();
+        assert!(storage.retrieve("submissions/a").await.is_err());
+        // deleting something which isn't there is fine
+        storage.delete("submissions/a").await.unwrap();
+        // but keys can't escape the directory
+        assert!(storage.store("../escaped", vec![]).await.is_err());
+        assert!(storage.store("/etc/escaped", vec![]).await.is_err());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
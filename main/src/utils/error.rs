@@ -4,15 +4,26 @@ use rocket::http::Status;
 use thiserror::Error as ThisError;
 
 use super::{default_head, json_response::ApiResponse};
+use crate::class::tasks::asynchronous::INVALID_ESTIMATE;
 
 #[derive(ThisError, Debug, PartialEq, Clone, Eq)]
 pub enum LovelaceError {
+    /// The reason is shown to the user (if there isn't one, they're just told that they don't
+    /// have permission).
     #[error("permission error")]
-    PermissionError,
+    PermissionError(Option<String>),
     #[error("database error")]
     DatabaseError,
     #[error("date parsing error")]
     ParseDateError,
+    /// The recurrence rule supplied for a task couldn't be used (the reason is shown to the user).
+    #[error("invalid recurrence rule")]
+    InvalidRecurrence(String),
+    /// The estimate given for how long a task will take wasn't a positive number of minutes.
+    #[error("invalid estimate")]
+    InvalidEstimate,
+    #[error("email address not verified")]
+    EmailNotVerified,
     #[error("other error")]
     #[allow(dead_code)]
     OtherError,
@@ -29,10 +40,16 @@ pub type LovelaceResult<T> = Result<T, LovelaceError>;
 impl<T> From<LovelaceError> for ApiResponse<T> {
     fn from(e: LovelaceError) -> Self {
         ApiResponse::new_err(match e {
-            LovelaceError::PermissionError => "Permission error",
+            LovelaceError::PermissionError(Some(reason)) => return ApiResponse::new_err(reason),
+            LovelaceError::PermissionError(None) => "Permission error",
             LovelaceError::DatabaseError => "Database error",
             LovelaceError::OtherError => "Other error",
             LovelaceError::ParseDateError => "Could not parse one of the dates you supplied.",
+            LovelaceError::InvalidRecurrence(reason) => return ApiResponse::new_err(reason),
+            LovelaceError::InvalidEstimate => INVALID_ESTIMATE,
+            LovelaceError::EmailNotVerified => {
+                "You need to verify your email address before you can do this."
+            }
         })
     }
 }
@@ -40,9 +57,11 @@ impl<T> From<LovelaceError> for ApiResponse<T> {
 impl Render<Div> for LovelaceError {
     fn render(self) -> Div {
         match self {
-            LovelaceError::PermissionError => Level::new()
+            LovelaceError::PermissionError(reason) => Level::new()
                 .child(H1::new("Permission error"))
-                .child(P::with_text("You don't have permission to do this.")),
+                .child(P::with_text(reason.unwrap_or_else(|| {
+                    "You don't have permission to do this.".to_string()
+                }))),
             LovelaceError::DatabaseError => {
                 Level::new()
                     .child(H1::new("Database error"))
@@ -55,7 +74,29 @@ impl Render<Div> for LovelaceError {
                     ))
             }
             LovelaceError::OtherError => {Level::new().child(H1::new("Other error"))}
-            LovelaceError::ParseDateError => Level::new().child(H1::new("Could not parse one of the dates you supplied."))
+            LovelaceError::ParseDateError => Level::new().child(H1::new("Could not parse one of the dates you supplied.")),
+            LovelaceError::InvalidRecurrence(reason) => Level::new()
+                .child(H1::new("Couldn't use that recurrence rule"))
+                .child(P::with_text(reason)),
+            LovelaceError::InvalidEstimate => Level::new()
+                .child(H1::new("Couldn't use that estimate"))
+                .child(P::with_text(INVALID_ESTIMATE)),
+            LovelaceError::EmailNotVerified => Level::new()
+                .child(H1::new("Please verify your email address"))
+                .child(P::with_text(
+                    "You need to verify your email address before you can do this. Use the link \
+                    in the email we sent you when you signed up, or ask for a new one.",
+                ))
+                .child(
+                    Form::new()
+                        .attribute(Method::Post)
+                        .attribute(Action::new("/auth/verify/resend"))
+                        .child(
+                            Input::new()
+                                .attribute(Type::Submit)
+                                .attribute(Value::new("Send me another link")),
+                        ),
+                ),
         }
         .into_div()
     }
@@ -65,17 +106,24 @@ impl Render<Html> for LovelaceError {
     fn render(self) -> Html {
         Html::new()
             .status(match self {
-                LovelaceError::PermissionError => Status::Forbidden,
+                LovelaceError::PermissionError(_) | LovelaceError::EmailNotVerified => {
+                    Status::Forbidden
+                }
                 LovelaceError::DatabaseError | LovelaceError::OtherError => {
                     Status::InternalServerError
                 }
-                LovelaceError::ParseDateError => Status::BadRequest,
+                LovelaceError::ParseDateError
+                | LovelaceError::InvalidRecurrence(_)
+                | LovelaceError::InvalidEstimate => Status::BadRequest,
             })
             .head(default_head(match self {
-                LovelaceError::PermissionError => "Invalid permissions",
+                LovelaceError::PermissionError(_) => "Invalid permissions",
                 LovelaceError::DatabaseError => "Database error",
                 LovelaceError::OtherError => "Unknown error",
                 LovelaceError::ParseDateError => "Couldn't parse a provided date",
+                LovelaceError::InvalidRecurrence(_) => "Couldn't use that recurrence rule",
+                LovelaceError::InvalidEstimate => "Couldn't use that estimate",
+                LovelaceError::EmailNotVerified => "Please verify your email address",
             }))
             .body(Body::new().child(Render::<Div>::render(self)))
     }
@@ -33,7 +33,7 @@ where
             .body(
                 Body::new()
                     .child(H3::new(match self.0 {
-                        LovelaceError::PermissionError => {
+                        LovelaceError::PermissionError(_) => {
                             "Error – you don't have permission to do this."
                         }
                         LovelaceError::DatabaseError => {
@@ -43,6 +43,15 @@ where
                             "Encountered an unexpected error trying to do this."
                         }
                         LovelaceError::ParseDateError => "Could not parse date.",
+                        LovelaceError::InvalidRecurrence(_) => {
+                            "Could not use the recurrence rule you supplied."
+                        }
+                        LovelaceError::InvalidEstimate => {
+                            "Could not use the estimate you supplied."
+                        }
+                        LovelaceError::EmailNotVerified => {
+                            "Error – you need to verify your email address to do this."
+                        }
                     }))
                     .child(Render::<Div>::render(self)),
             )
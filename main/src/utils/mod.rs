@@ -4,7 +4,6 @@ A copy of this license can be found in the `licenses` directory at the root of t
 */
 #[cfg(test)]
 use crate::auth::LOGIN_COOKIE;
-use crate::calendar::connect::gcal::StateValues;
 #[cfg(test)]
 use crate::db::Database;
 #[cfg(test)]
@@ -14,7 +13,6 @@ use crate::schema::users;
 #[cfg(test)]
 use chrono::Utc;
 use malvolio::prelude::{Body, Content, Head, Html, Meta, MetaName, Title, H1, P};
-use rocket::tokio::sync::RwLock;
 use rocket::{fairing::AdHoc, Rocket};
 use rocket::{
     figment::{
@@ -26,7 +24,7 @@ use rocket::{
 };
 #[cfg(test)]
 use rocket::{http::ContentType, local::asynchronous::Client};
-use std::{collections::HashMap, net::IpAddr};
+use std::net::IpAddr;
 
 pub mod auto_database_error;
 pub mod error;
@@ -103,15 +101,24 @@ pub fn launch() -> Rocket<Build> {
     } else {
         Figment::from(rocket::Config::default()).merge(("databases", map!["postgres" => db]))
     };
+    // rosters are imported by uploading whole CSV files (and homework can be submitted as files),
+    // which can be much larger than Rocket's default limits allow
+    let figment = figment
+        .merge(("limits.form", "4 MiB"))
+        .merge(("limits.data-form", "32 MiB"))
+        .merge(("limits.file", "32 MiB"))
+        .merge(("limits.string", "4 MiB"))
+        .merge(("limits.json", "8 MiB"));
     rocket::custom(figment)
-        .manage(StateValues {
-            map: RwLock::new(HashMap::new()),
-        })
+        .manage(crate::storage::Storage::from_env())
         .attach(crate::db::Database::fairing())
         .attach(AdHoc::try_on_ignite(
             "Database Migrations",
             crate::db::run_migrations,
         ))
+        .attach(AdHoc::on_request("Credentials", |req, _| {
+            Box::pin(crate::auth::resolve_credentials(req))
+        }))
         .mount(
             "/api",
             routes![
@@ -122,6 +129,7 @@ pub fn launch() -> Rocket<Build> {
                 crate::class::api_view_all_classes,
                 crate::class::api_invite_teacher,
                 crate::class::api_view_class_overview,
+                crate::class::api_update_class_settings,
             ],
         )
         .mount(
@@ -134,6 +142,7 @@ pub fn launch() -> Rocket<Build> {
                 crate::class::html_view_all_classes,
                 crate::class::html_view_class_overview,
                 crate::class::get_class_settings,
+                crate::class::html_update_class_settings,
                 crate::class::html_view_class_members_page,
                 crate::class::invite_teacher_page,
                 crate::class::html_invite_teacher,
@@ -146,7 +155,27 @@ pub fn launch() -> Rocket<Build> {
         .mount("/dashboard", routes![crate::dashboard::html_dashboard])
         .mount(
             "/api/auth",
-            routes![crate::auth::api_login, crate::auth::api_logout, crate::auth::api_register],
+            routes![
+                crate::auth::api_login,
+                crate::auth::api_logout,
+                crate::auth::api_register,
+                crate::auth::api_request_reset,
+                crate::auth::api_apply_reset,
+                crate::auth::api_list_sessions,
+                crate::auth::api_revoke_session,
+                crate::auth::api_list_tokens,
+                crate::auth::api_create_token,
+                crate::auth::api_revoke_token,
+                crate::auth::api_complete_login,
+                crate::auth::api_begin_enrolment,
+                crate::auth::api_confirm_enrolment,
+                crate::auth::api_disable_two_factor,
+                crate::auth::api_change_email,
+                crate::auth::api_change_password,
+                crate::auth::api_change_timezone,
+                crate::auth::api_delete_account,
+                crate::auth::api_resend_verification
+            ],
         )
         .mount(
             "/auth",
@@ -155,7 +184,30 @@ pub fn launch() -> Rocket<Build> {
                 crate::auth::html_login,
                 crate::auth::register_page,
                 crate::auth::html_register,
-                crate::auth::verify_email
+                crate::auth::verify_email,
+                crate::auth::reset_page,
+                crate::auth::html_request_reset,
+                crate::auth::confirm_reset_page,
+                crate::auth::html_apply_reset,
+                crate::auth::sessions_page,
+                crate::auth::html_revoke_session,
+                crate::auth::tokens_page,
+                crate::auth::html_create_token,
+                crate::auth::html_revoke_token,
+                crate::auth::html_complete_login,
+                crate::auth::two_factor_page,
+                crate::auth::html_confirm_enrolment,
+                crate::auth::html_disable_two_factor,
+                crate::auth::oidc_page,
+                crate::auth::begin_oidc_login,
+                crate::auth::oidc_callback,
+                crate::auth::account_page,
+                crate::auth::html_change_email,
+                crate::auth::html_change_password,
+                crate::auth::html_change_timezone,
+                crate::auth::delete_account_page,
+                crate::auth::html_delete_account,
+                crate::auth::html_resend_verification
             ],
         )
         .mount(
@@ -164,7 +216,31 @@ pub fn launch() -> Rocket<Build> {
                 crate::institution::register::api_register_new_institution,
                 crate::institution::delete::api_delete_institution,
                 crate::institution::configure::api_configure_institution,
-                crate::institution::class::create::api_create_institution_class
+                crate::institution::class::create::api_create_institution_class,
+                crate::institution::lockout::api_locked_accounts,
+                crate::institution::lockout::api_unlock_account,
+                crate::institution::export::api_export_member,
+                crate::institution::invite::api_invite_member,
+                crate::institution::group::manage::api_list_groups,
+                crate::institution::group::manage::api_create_group,
+                crate::institution::group::manage::api_view_group,
+                crate::institution::group::manage::api_edit_group,
+                crate::institution::group::manage::api_move_group,
+                crate::institution::group::manage::api_delete_group,
+                crate::institution::group::members::api_add_student,
+                crate::institution::group::members::api_remove_student,
+                crate::institution::group::members::api_invite_teacher,
+                crate::institution::group::members::api_remove_teacher,
+                crate::institution::roster::upload::api_preview_roster,
+                crate::institution::roster::upload::api_apply_roster,
+                crate::institution::academic::manage::api_academic_calendar,
+                crate::institution::academic::manage::api_create_year,
+                crate::institution::academic::manage::api_create_term,
+                crate::institution::academic::manage::api_create_holiday,
+                crate::institution::academic::manage::api_delete_year,
+                crate::institution::academic::manage::api_delete_term,
+                crate::institution::academic::manage::api_delete_holiday,
+                crate::institution::attendance::api_attendance_report
             ],
         )
         .mount(
@@ -178,7 +254,68 @@ pub fn launch() -> Rocket<Build> {
                 crate::institution::configure::html_configure_institution,
                 crate::institution::class::create::pick_which_institution_to_create_class_as_part_of,
                 crate::institution::class::create::html_create_institution_class,
-                crate::institution::class::create::create_institution_class_page
+                crate::institution::class::create::create_institution_class_page,
+                crate::institution::lockout::locked_accounts_page,
+                crate::institution::lockout::html_unlock_account,
+                crate::institution::export::html_export_member,
+                crate::institution::invite::invite_member_page,
+                crate::institution::invite::html_invite_member,
+                crate::institution::group::manage::groups_page,
+                crate::institution::group::manage::html_create_group,
+                crate::institution::group::manage::group_page,
+                crate::institution::group::manage::html_edit_group,
+                crate::institution::group::manage::html_move_group,
+                crate::institution::group::manage::html_delete_group,
+                crate::institution::group::members::html_add_student,
+                crate::institution::group::members::html_remove_student,
+                crate::institution::group::members::html_invite_teacher,
+                crate::institution::group::members::html_remove_teacher,
+                crate::institution::roster::upload::roster_page,
+                crate::institution::roster::upload::html_preview_roster,
+                crate::institution::roster::upload::html_apply_roster,
+                crate::institution::academic::manage::academic_calendar_page,
+                crate::institution::academic::manage::html_create_year,
+                crate::institution::academic::manage::html_create_term,
+                crate::institution::academic::manage::html_create_holiday,
+                crate::institution::academic::manage::html_delete_year,
+                crate::institution::academic::manage::html_delete_term,
+                crate::institution::academic::manage::html_delete_holiday,
+                crate::institution::attendance::attendance_report_page
+            ],
+        )
+        .mount(
+            "/export",
+            routes![
+                crate::export::exports_page,
+                crate::export::html_request_export,
+                crate::export::view_export,
+                crate::export::download_export
+            ],
+        )
+        .mount(
+            "/api/export",
+            routes![
+                crate::export::api_list_exports,
+                crate::export::api_request_export,
+                crate::export::api_get_export
+            ],
+        )
+        .mount(
+            "/invites",
+            routes![
+                crate::invite::invites_page,
+                crate::invite::html_accept_invite,
+                crate::invite::html_decline_invite,
+                crate::invite::html_revoke_invite
+            ],
+        )
+        .mount(
+            "/api/invites",
+            routes![
+                crate::invite::api_list_invites,
+                crate::invite::api_accept_invite,
+                crate::invite::api_decline_invite,
+                crate::invite::api_revoke_invite
             ],
         )
         .mount(
@@ -212,11 +349,25 @@ pub fn launch() -> Rocket<Build> {
                 crate::class::tasks::asynchronous::api_view_specific_asynchronous_task,
                 crate::class::tasks::asynchronous::api_delete_task,
                 crate::class::tasks::asynchronous::api_view_all_async_tasks_in_class,
+                crate::class::tasks::asynchronous::api_submit_work,
+                crate::class::tasks::asynchronous::api_view_submissions,
+                crate::class::tasks::asynchronous::api_set_student_due_date,
+                crate::class::tasks::asynchronous::api_set_student_estimate,
                 crate::class::tasks::synchronous::api_create_new_async_task,
                 crate::class::tasks::synchronous::api_delete_task,
                 crate::class::tasks::synchronous::api_apply_edit_task,
                 crate::class::tasks::synchronous::api_view_specific_synchronous_task,
-                crate::class::tasks::synchronous::api_view_all_sync_tasks_in_class
+                crate::class::tasks::synchronous::api_view_all_sync_tasks_in_class,
+                crate::class::attendance::register::api_view_register,
+                crate::class::attendance::register::api_take_register,
+                crate::class::attendance::summary::api_view_class_attendance,
+                crate::class::gradebook::categories::api_view_categories,
+                crate::class::gradebook::categories::api_create_category,
+                crate::class::gradebook::categories::api_delete_category,
+                crate::class::gradebook::marks::api_view_marks,
+                crate::class::gradebook::marks::api_set_grading,
+                crate::class::gradebook::marks::api_save_marks,
+                crate::class::gradebook::grid::api_view_gradebook
             ],
         )
         .mount(
@@ -237,13 +388,29 @@ pub fn launch() -> Rocket<Build> {
                 crate::class::tasks::asynchronous::view_edit_task_page,
                 crate::class::tasks::asynchronous::html_apply_edit_task,
                 crate::class::tasks::asynchronous::html_delete_task,
+                crate::class::tasks::asynchronous::html_submit_work,
+                crate::class::tasks::asynchronous::html_view_submissions,
+                crate::class::tasks::asynchronous::html_set_student_due_date,
+                crate::class::tasks::asynchronous::html_set_student_estimate,
+                crate::class::tasks::asynchronous::download_submitted_file,
                 crate::class::tasks::synchronous::html_view_all_sync_tasks_in_class,
                 crate::class::tasks::synchronous::html_create_new_sync_task,
                 crate::class::tasks::synchronous::get_create_new_sync_task,
                 crate::class::tasks::synchronous::html_view_specific_synchronous_task,
                 crate::class::tasks::synchronous::view_edit_task_page,
                 crate::class::tasks::synchronous::html_apply_edit_task,
-                crate::class::tasks::synchronous::html_delete_task
+                crate::class::tasks::synchronous::html_delete_task,
+                crate::class::attendance::register::html_view_register,
+                crate::class::attendance::register::html_take_register,
+                crate::class::attendance::summary::html_view_class_attendance,
+                crate::class::gradebook::categories::html_view_categories,
+                crate::class::gradebook::categories::html_create_category,
+                crate::class::gradebook::categories::html_delete_category,
+                crate::class::gradebook::marks::html_view_marks,
+                crate::class::gradebook::marks::html_set_grading,
+                crate::class::gradebook::marks::html_save_marks,
+                crate::class::gradebook::grid::html_view_gradebook,
+                crate::class::gradebook::grid::export_gradebook
             ],
         )
         .mount(
@@ -253,12 +420,28 @@ pub fn launch() -> Rocket<Build> {
                 crate::calendar::connect::caldav::connect_caldav_calendar
             ]
         )
+        .mount(
+            "/calendar/preferences",
+            routes![
+                crate::calendar::preferences::html_view_preferences,
+                crate::calendar::preferences::html_save_preferences
+            ],
+        )
+        .mount(
+            "/api/calendar/preferences",
+            routes![
+                crate::calendar::preferences::api_view_preferences,
+                crate::calendar::preferences::api_save_preferences
+            ],
+        )
         .mount(
             "/calendar/gcal",
             routes![
                 crate::calendar::connect::gcal::link_calendar,
                 crate::calendar::connect::gcal::link_gcal,
-                crate::calendar::connect::gcal::gcal_callback
+                crate::calendar::connect::gcal::gcal_callback,
+                crate::calendar::connect::gcal::disconnect_gcal_page,
+                crate::calendar::connect::gcal::html_disconnect_gcal
             ],
         )
         .mount(
@@ -303,7 +486,7 @@ pub async fn create_user(
                 .values(NewUser {
                     username,
                     email,
-                    password: &bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap(),
+                    password: &crate::auth::hash::hash_password(password).unwrap(),
                     created: Utc::now().naive_utc(),
                     email_verified: true,
                     timezone,
@@ -345,3 +528,23 @@ pub async fn logout(client: &Client) {
         .unwrap()
         .contains("Logged out"));
 }
+
+/// Starts a mock SendGrid server (and sends emails to it), which expects to be sent `expected`
+/// emails by the end of the test.
+#[cfg(test)]
+pub async fn mock_email_server(expected: impl Into<wiremock::Times>) -> wiremock::MockServer {
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+    let mock_server = MockServer::start().await;
+    std::env::set_var("SENDGRID_API_KEY", "SomeRandomAPIKey");
+    std::env::set_var("SENDGRID_API_SERVER", mock_server.uri());
+    Mock::given(method("post"))
+        .and(path_regex("/v3/mail/send"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected)
+        .mount(&mock_server)
+        .await;
+    mock_server
+}
@@ -0,0 +1,14 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+/* Outstanding password reset requests. The token which is emailed to the user is a signed JWT which
+contains the `nonce`; a token is only accepted while its `nonce` is still present in this table,
+which is what makes tokens single-use (all of a user's rows are deleted once one of them is used). */
+create table if not exists password_reset (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    nonce text not null unique,
+    created timestamp not null default now()
+);
@@ -0,0 +1,17 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Each row is one place where a user is logged in. The login cookie stores `token` (which is
+    random, unlike `id`) and a request is only treated as authenticated while the row exists.
+*/
+create table if not exists session (
+    id serial primary key,
+    token text not null unique,
+    user_id integer not null references users (id) on delete cascade,
+    created timestamp not null default now(),
+    last_seen timestamp not null default now(),
+    user_agent text,
+    ip text
+);
@@ -0,0 +1,22 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Personal access tokens for the API. We only ever store the SHA-256 hash of a token; the token
+    itself is shown to the user once (when it is created) and then forgotten.
+*/
+create table if not exists api_token (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    name text not null,
+    token_hash text not null unique,
+    created timestamp not null default now(),
+    expires timestamp,
+    last_used timestamp,
+    /* if this is true the token can only be used to read data, and not to change it */
+    read_only boolean not null default false,
+    scope_classes boolean not null default false,
+    scope_tasks boolean not null default false,
+    scope_notifications boolean not null default false
+);
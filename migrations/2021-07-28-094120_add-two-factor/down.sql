@@ -0,0 +1,8 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+alter table institution drop column if exists require_two_factor;
+drop table if exists totp_recovery_code;
+drop table if exists totp;
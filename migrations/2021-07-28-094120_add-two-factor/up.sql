@@ -0,0 +1,28 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    TOTP (RFC 6238) second factors. Each user has at most one; it only counts once `confirmed` is
+    set (i.e. once the user has shown that their authenticator app produces the right codes).
+*/
+create table if not exists totp (
+    id serial primary key,
+    user_id integer not null unique references users (id) on delete cascade,
+    /* base32 encoded, as this is what authenticator apps expect */
+    secret text not null,
+    confirmed boolean not null default false,
+    /* the last time step for which a code was accepted, so that a code cannot be used twice */
+    last_used_step bigint,
+    created timestamp not null default now()
+);
+
+/* Single-use codes which can be used instead of a TOTP code, stored as SHA-256 hashes. */
+create table if not exists totp_recovery_code (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    code_hash text not null,
+    used boolean not null default false
+);
+
+alter table institution add column if not exists require_two_factor boolean not null default false;
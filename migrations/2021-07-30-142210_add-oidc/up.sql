@@ -0,0 +1,30 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    OpenID Connect ("sign in with your school account") configuration. Each institution may
+    register at most one issuer, which its members can then use to log in.
+*/
+create table if not exists institution_oidc (
+    id serial primary key,
+    institution_id integer not null unique references institution (id) on delete cascade,
+    /* the issuer identifier, e.g. `https://accounts.example.com` */
+    issuer text not null,
+    client_id text not null,
+    client_secret text not null
+);
+
+/*
+    Logins which have been started (i.e. the user has been redirected to the issuer) but not yet
+    completed. `state` is the value round-tripped through the issuer, and is used to find the PKCE
+    code verifier and the nonce which the ID token must contain.
+*/
+create table if not exists oidc_login (
+    id serial primary key,
+    state text not null unique,
+    institution_id integer not null references institution (id) on delete cascade,
+    code_verifier text not null,
+    nonce text not null,
+    created timestamp not null default now()
+);
@@ -0,0 +1,28 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Attempts to use authentication endpoints which count towards their rate limits (e.g. failed
+    logins, or registrations). Either (or both) of `user_id` and `ip` are used to work out whether
+    somebody is making too many attempts; old rows are deleted as they stop being relevant.
+*/
+create table if not exists auth_attempt (
+    id serial primary key,
+    /* which endpoint the attempt was made against, e.g. `login` */
+    action text not null,
+    user_id integer references users (id) on delete cascade,
+    ip text,
+    created timestamp not null default now()
+);
+
+create index if not exists auth_attempt_user_id on auth_attempt (action, user_id);
+create index if not exists auth_attempt_ip on auth_attempt (action, ip);
+
+/* Accounts which have been locked after too many failed logins. */
+create table if not exists account_lockout (
+    id serial primary key,
+    user_id integer not null unique references users (id) on delete cascade,
+    locked_until timestamp not null,
+    created timestamp not null default now()
+);
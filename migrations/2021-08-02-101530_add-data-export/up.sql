@@ -0,0 +1,20 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Exports of everything we store about a user (e.g. in response to a subject access request).
+    Exports are built in the background; `completed` is set (and `json` and `html` are filled in)
+    once the export is ready to download. Old exports are deleted after a week.
+*/
+create table if not exists data_export (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    /* the user who asked for the export – either the user themselves or an administrator */
+    requested_by integer references users (id) on delete set null,
+    created timestamp not null default now(),
+    completed timestamp,
+    failed boolean not null default false,
+    json text,
+    html text
+);
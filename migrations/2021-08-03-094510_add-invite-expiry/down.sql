@@ -0,0 +1,10 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+alter table class_teacher_invite drop column if exists created, drop column if exists expires;
+alter table institution_teacher_invite drop column if exists created, drop column if exists expires;
+alter table institution_student_invite drop column if exists created, drop column if exists expires;
+alter table administrator_invite drop column if exists created, drop column if exists expires;
+alter table student_group_teacher_invite drop column if exists created, drop column if exists expires;
@@ -0,0 +1,27 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Invitations now expire if they haven't been accepted within a fortnight. Invitations which
+    were sent before this was added are given a fortnight from now.
+*/
+alter table class_teacher_invite
+    add column if not exists created timestamp not null default now(),
+    add column if not exists expires timestamp not null default now() + interval '14 days';
+
+alter table institution_teacher_invite
+    add column if not exists created timestamp not null default now(),
+    add column if not exists expires timestamp not null default now() + interval '14 days';
+
+alter table institution_student_invite
+    add column if not exists created timestamp not null default now(),
+    add column if not exists expires timestamp not null default now() + interval '14 days';
+
+alter table administrator_invite
+    add column if not exists created timestamp not null default now(),
+    add column if not exists expires timestamp not null default now() + interval '14 days';
+
+alter table student_group_teacher_invite
+    add column if not exists created timestamp not null default now(),
+    add column if not exists expires timestamp not null default now() + interval '14 days';
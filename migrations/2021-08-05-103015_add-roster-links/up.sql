@@ -0,0 +1,20 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Roster imports identify users, student groups and classes by an identifier which comes from
+    the school's management information system (e.g. a OneRoster `sourcedId`). This table records
+    which row each of these identifiers was imported as, so that importing the same roster again
+    updates the rows which already exist rather than creating new ones.
+
+    `target_id` refers to a row in `users`, `student_group` or `class` (depending on `kind`).
+*/
+create table if not exists roster_link (
+    id serial primary key,
+    institution_id integer not null references institution (id) on delete cascade,
+    kind text not null,
+    source_id text not null,
+    target_id integer not null,
+    unique (institution_id, kind, source_id)
+);
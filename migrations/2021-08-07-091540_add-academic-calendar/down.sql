@@ -0,0 +1,9 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+alter table class drop column if exists academic_term_id;
+drop table if exists holiday;
+drop table if exists academic_term;
+drop table if exists academic_year;
@@ -0,0 +1,40 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Institutions divide their academic years into terms (and terms can be divided into half-terms,
+    which are stored as terms with a `parent_term_id`). Holidays are the days on which nobody
+    should be expected to work – they usually fall between terms, but don't have to.
+
+    All the dates are inclusive, and are in the local time of whoever is looking at them.
+*/
+create table if not exists academic_year (
+    id serial primary key,
+    institution_id integer not null references institution (id) on delete cascade,
+    name text not null,
+    starts date not null,
+    ends date not null,
+    check (starts <= ends)
+);
+
+create table if not exists academic_term (
+    id serial primary key,
+    academic_year_id integer not null references academic_year (id) on delete cascade,
+    parent_term_id integer references academic_term (id) on delete cascade,
+    name text not null,
+    starts date not null,
+    ends date not null,
+    check (starts <= ends)
+);
+
+create table if not exists holiday (
+    id serial primary key,
+    academic_year_id integer not null references academic_year (id) on delete cascade,
+    name text not null,
+    starts date not null,
+    ends date not null,
+    check (starts <= ends)
+);
+
+alter table class add column academic_term_id integer references academic_term (id) on delete set null;
@@ -0,0 +1,27 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Synchronous tasks can repeat (e.g. a lesson which takes place every Monday and Wednesday).
+    `recurrence` holds an RFC 5545 RRULE (we only support a subset of them – see
+    `class::tasks::synchronous::recurrence`), and the task's `start_time` and `end_time` are those
+    of the first occurrence.
+
+    Occurrences which have been changed or cancelled are stored in
+    `class_synchronous_task_occurrence`, where they are identified by the time they would have
+    started at had they not been changed. Fields which are null are the same as the task's.
+*/
+alter table class_synchronous_task add column recurrence text;
+
+create table if not exists class_synchronous_task_occurrence (
+    id serial primary key,
+    class_synchronous_task_id integer not null references class_synchronous_task (id) on delete cascade,
+    original_start_time timestamp not null,
+    cancelled boolean not null default false,
+    title text,
+    description text,
+    start_time timestamp,
+    end_time timestamp,
+    unique (class_synchronous_task_id, original_start_time)
+);
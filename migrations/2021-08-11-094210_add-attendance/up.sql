@@ -0,0 +1,29 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    The attendance register for synchronous tasks. Each mark is for one student at one occurrence
+    of a task (occurrences are identified by the time at which they would originally have started;
+    see `class_synchronous_task_occurrence`).
+
+    `mark` is one of the values of `class::attendance::AttendanceMark` – don't insert integers
+    directly.
+
+    Institutions can set an `attendance_threshold` (a percentage); when a student's attendance in
+    one of its classes drops below it, their teachers and the institution's administrators are
+    notified.
+*/
+create table if not exists attendance (
+    id serial primary key,
+    class_synchronous_task_id integer not null references class_synchronous_task (id) on delete cascade,
+    occurrence timestamp not null,
+    class_student_id integer not null references class_student (id) on delete cascade,
+    mark smallint not null,
+    note text,
+    recorded_by integer references users (id) on delete set null,
+    recorded timestamp not null,
+    unique (class_synchronous_task_id, occurrence, class_student_id)
+);
+
+alter table institution add column attendance_threshold integer check (attendance_threshold between 1 and 100);
@@ -0,0 +1,34 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Work which students hand in for asynchronous tasks. Every submission is kept (resubmitting
+    adds a new one, rather than replacing the old one), and the most recent one is the one which
+    counts.
+
+    A submission is `late` if it was made after the task's due date. Once a student has submitted
+    something, they can only resubmit if the task has a `resubmit_until` date which hasn't passed
+    yet.
+
+    The files themselves are kept in the storage backend (see `storage.rs`); `storage_key`
+    identifies them there.
+*/
+alter table class_asynchronous_task add column resubmit_until timestamp;
+
+create table if not exists async_task_submission (
+    id serial primary key,
+    student_class_asynchronous_task_id integer not null references student_class_asynchronous_task (id) on delete cascade,
+    text text,
+    submitted timestamp not null,
+    late boolean not null
+);
+
+create table if not exists async_task_submission_file (
+    id serial primary key,
+    async_task_submission_id integer not null references async_task_submission (id) on delete cascade,
+    filename text not null,
+    content_type text not null,
+    size bigint not null,
+    storage_key text not null unique
+);
@@ -0,0 +1,8 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+drop table if exists async_task_mark;
+drop table if exists async_task_grading;
+drop table if exists grade_category;
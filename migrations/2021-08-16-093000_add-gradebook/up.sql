@@ -0,0 +1,44 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    The gradebook.
+
+    Teachers choose how an asynchronous task is graded (`async_task_grading`); tasks without a row
+    in that table aren't graded. `scheme` is one of the values of
+    `class::gradebook::GradingScheme` – don't insert integers directly. `max_points` is only used
+    by the "points out of N" scheme.
+
+    Each student's mark for a task is stored as a percentage (`score`), whichever scheme is used –
+    it is converted to (and from) points, letters, etc. when it's shown to (or entered by) a
+    teacher. Marks are only shown to students once their teacher has `released` them.
+
+    Tasks can be put into a `grade_category`; a student's overall average in a class is the
+    average of their averages in each category, weighted by the categories' `weight`s.
+*/
+create table if not exists grade_category (
+    id serial primary key,
+    class_id integer not null references class (id) on delete cascade,
+    name text not null,
+    weight integer not null check (weight > 0),
+    unique (class_id, name)
+);
+
+create table if not exists async_task_grading (
+    id serial primary key,
+    class_asynchronous_task_id integer not null unique references class_asynchronous_task (id) on delete cascade,
+    scheme smallint not null,
+    max_points integer check (max_points > 0),
+    grade_category_id integer references grade_category (id) on delete set null,
+    released boolean not null default false
+);
+
+create table if not exists async_task_mark (
+    id serial primary key,
+    student_class_asynchronous_task_id integer not null unique references student_class_asynchronous_task (id) on delete cascade,
+    score double precision not null check (score between 0 and 100),
+    feedback text,
+    marked_by integer references users (id) on delete set null,
+    marked timestamp not null
+);
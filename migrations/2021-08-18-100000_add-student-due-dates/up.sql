@@ -0,0 +1,9 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Lets teachers give individual students a different due date for an asynchronous task (e.g. an
+    extension). If `due_date` is null, the student's work is due when the task is.
+*/
+alter table student_class_asynchronous_task add column due_date timestamp;
@@ -0,0 +1,13 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    How long (in minutes) doing an asynchronous task should take. The teacher's estimate is stored
+    on the task, and students can replace it with their own (if a student's `estimated_minutes` is
+    null, the teacher's estimate is used).
+*/
+alter table class_asynchronous_task
+    add column estimated_minutes integer not null default 25 check (estimated_minutes > 0);
+alter table student_class_asynchronous_task
+    add column estimated_minutes integer check (estimated_minutes > 0);
@@ -0,0 +1,9 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Students are always warned when the scheduler can't fit their work in before it is due. If
+    `notify_teachers_of_overload` is set, the class' teachers are warned as well.
+*/
+alter table class add column notify_teachers_of_overload boolean not null default false;
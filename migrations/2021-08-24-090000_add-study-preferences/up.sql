@@ -0,0 +1,34 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    When (and how much) users want to study, which the scheduler only places work inside of. All
+    times are in the user's timezone (`users.timezone`).
+
+    `study_preferences` holds the general settings (users who don't have a row get the defaults).
+    `break_every_minutes` is null if the user doesn't want breaks to be scheduled.
+*/
+create table if not exists study_preferences (
+    id serial primary key,
+    user_id integer not null unique references users(id) on delete cascade,
+    max_minutes_per_day integer not null check (max_minutes_per_day > 0),
+    buffer_minutes integer not null check (buffer_minutes >= 0),
+    break_every_minutes integer check (break_every_minutes > 0),
+    break_minutes integer not null check (break_minutes >= 0)
+);
+
+/*
+    The times of day during which the user is happy to study, for each day of the week (`weekday`
+    is the number of days from Monday, so 0 is Monday and 6 is Sunday). A day can have more than
+    one window, or none (in which case no work is scheduled on that day). Users who haven't set
+    any study hours get the defaults.
+*/
+create table if not exists study_hours (
+    id serial primary key,
+    user_id integer not null references users(id) on delete cascade,
+    weekday smallint not null check (weekday between 0 and 6),
+    starts time not null,
+    ends time not null,
+    check (starts < ends)
+);
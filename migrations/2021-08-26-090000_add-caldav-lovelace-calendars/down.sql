@@ -0,0 +1,7 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+
+alter table caldav drop column if exists lovelace_calendar_url;
+alter table caldav_unauthenticated drop column if exists lovelace_calendar_url;
@@ -0,0 +1,11 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    The URL of the calendar collection which the scheduler writes work into. This is found (or
+    created, next to the calendar the user connected) the first time they are scheduled, so it is
+    null until then.
+*/
+alter table caldav add column lovelace_calendar_url text;
+alter table caldav_unauthenticated add column lovelace_calendar_url text;
@@ -0,0 +1,22 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    When the Google Calendar access token stops working (it is refreshed a little before then). This
+    is null if we don't know, in which case the token is refreshed the next time it is used.
+*/
+alter table google_calendar add column access_token_expires timestamp;
+
+/*
+    Google Calendar connections which have been started (i.e. the user has been sent to Google) but
+    not yet completed. `state` is the value round-tripped through Google, and is only valid for a
+    few minutes after `created`.
+*/
+create table if not exists gcal_oauth_state (
+    id serial primary key,
+    state text not null unique,
+    user_id integer not null references users (id) on delete cascade,
+    lovelace_calendar_id text not null,
+    created timestamp not null default now()
+);
@@ -0,0 +1,17 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Which account an institution's identity provider logs somebody into. `subject` is the `sub`
+    claim of the ID token, which (unlike the email address) the issuer guarantees never to reassign.
+*/
+create table if not exists oidc_link (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    institution_id integer not null references institution (id) on delete cascade,
+    subject text not null,
+    created timestamp not null default now(),
+    unique (institution_id, subject),
+    unique (institution_id, user_id)
+);
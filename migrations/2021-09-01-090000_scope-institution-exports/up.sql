@@ -0,0 +1,11 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    The institution whose administrator asked for an export. Exports which an administrator asks
+    for only contain data belonging to their institution; `institution_id` is null for exports
+    which users ask for themselves.
+*/
+alter table data_export
+    add column institution_id integer references institution (id) on delete cascade;
@@ -0,0 +1,18 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Estimates can be at most two weeks (20160 minutes) long, which is as far ahead as work is
+    scheduled.
+*/
+alter table class_asynchronous_task
+    drop constraint if exists class_asynchronous_task_estimated_minutes_check;
+alter table class_asynchronous_task
+    add constraint class_asynchronous_task_estimated_minutes_check
+    check (estimated_minutes > 0 and estimated_minutes <= 20160);
+alter table student_class_asynchronous_task
+    drop constraint if exists student_class_asynchronous_task_estimated_minutes_check;
+alter table student_class_asynchronous_task
+    add constraint student_class_asynchronous_task_estimated_minutes_check
+    check (estimated_minutes > 0 and estimated_minutes <= 20160);
@@ -0,0 +1,16 @@
+/*
+This source code file is distributed subject to the terms of the GNU Affero General Public License.
+A copy of this license can be found in the `licenses` directory at the root of this project.
+*/
+/*
+    Which task (and whose work on it) each warning about work which can't be finished in time is
+    about. Schedules are recomputed often, so nobody is warned about the same student's work on
+    the same task again while they still have an unread warning about it.
+*/
+create table if not exists overload_warning (
+    id serial primary key,
+    notification_id integer not null unique references notifications (id) on delete cascade,
+    class_asynchronous_task_id integer not null references class_asynchronous_task (id) on delete cascade,
+    /* the student whose work the warning is about */
+    student_id integer not null references users (id) on delete cascade
+);
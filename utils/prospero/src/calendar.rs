@@ -9,7 +9,8 @@ use crate::{
 };
 use chrono::{DateTime, Utc};
 use ical::parser::ical::component::IcalCalendar;
-use reqwest::Method;
+use icalendar::Component;
+use reqwest::{Method, StatusCode};
 use roxmltree::{Descendants, Document};
 use uuid::Uuid;
 
@@ -23,20 +24,32 @@ pub struct Calendar {
 pub struct Etag(String);
 
 impl Calendar {
+    /// The URL of this calendar.
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
     /// Saves a new event in the calendar.
     pub async fn save_event(&self, event: icalendar::Event) -> CalDavResult<EventPointer> {
+        // the event is stored at `<uid>.ics` (which is where `EventPointer::delete` looks for it)
+        let uid = event
+            .properties()
+            .get("UID")
+            .map(|uid| uid.value().to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
         let mut calendar = icalendar::Calendar::new();
         calendar.push(event);
-        let uid = Uuid::new_v4().to_string();
         let req = self
             .client
             .request(Method::PUT, format!("{}/{}.ics", self.url, &uid))
             .await?
-            .header("If-None-Match", "Match")
+            .header("If-None-Match", "*")
             .header("Content-Type", "text/calendar")
-            .header("Content-Length", "xxxx")
             .body(calendar.to_string());
         let res = req.send().await?;
+        if res.status() == StatusCode::UNAUTHORIZED {
+            return Err(CalDavError::Unauthorized);
+        }
         if res.status().as_u16() != 201 && res.status() != 200 && res.status().as_u16() != 207 {
             return Err(CalDavError::OtherError);
         }
@@ -83,6 +96,9 @@ impl Calendar {
             .header("Depth", "1")
             .send()
             .await?;
+        if res.status() == StatusCode::UNAUTHORIZED {
+            return Err(CalDavError::Unauthorized);
+        }
         let text = res.text().await.unwrap();
         let tree = Document::parse(&text).unwrap();
         let res = get_calendar_data(tree.descendants());
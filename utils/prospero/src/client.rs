@@ -1,13 +1,13 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use atomic_refcell::AtomicRefCell;
-
-use digest_auth::AuthContext;
+use digest_auth::{AuthContext, HttpMethod, WwwAuthenticateHeader};
 use reqwest::{Client, Method, RequestBuilder};
+use roxmltree::{Document, Node};
 use uuid::Uuid;
 
 pub(crate) const MKCALENDAR: &[u8] = b"MKCALENDAR";
 pub(crate) const REPORT: &[u8] = b"REPORT";
+pub(crate) const PROPFIND: &[u8] = b"PROPFIND";
 
 use crate::{
     calendar::Calendar,
@@ -21,8 +21,18 @@ pub struct DavClient {
     auth_scheme: AuthScheme,
     url: String,
     client: Client,
-    /// This is not neat but it makes the API nicer.
-    auth_header: AtomicRefCell<Option<String>>,
+    /// How the server asked to be authenticated with (this is only asked once, and is shared with
+    /// the calendars created from this client).
+    challenge: Arc<Mutex<Option<Challenge>>>,
+}
+
+/// The ways in which a server can ask to be authenticated with.
+#[derive(Debug, Clone)]
+enum Challenge {
+    None,
+    Basic,
+    /// The prompt keeps track of how many times its nonce has been used.
+    Digest(WwwAuthenticateHeader),
 }
 
 impl DavClient {
@@ -30,40 +40,63 @@ impl DavClient {
     where
         S: AsRef<str>,
     {
-        self.authenticate_cache_http(self.client.request(method, url.as_ref()))
-            .await
+        self.authenticate_cache_http(
+            self.client.request(method.clone(), url.as_ref()),
+            &method,
+            url.as_ref(),
+        )
+        .await
     }
-    async fn authenticate_fresh_http(
-        &self,
-        request: RequestBuilder,
-        username: &str,
-        password: &str,
-    ) -> CalDavResult<RequestBuilder> {
+    /// Asks the server how it wants to be authenticated with (servers which don't ask for any
+    /// authentication are sent requests without any credentials).
+    async fn fetch_challenge(&self) -> CalDavResult<Challenge> {
         let res = self.client.get(&self.url).send().await?;
-        let headers = res.headers();
-        let wwwauth = headers["www-authenticate"].to_str()?;
-        let url = self.url.parse::<http::Uri>()?;
-        let context = AuthContext::new(username, password, url.path());
-        let mut prompt = digest_auth::parse(wwwauth)?;
-        let answer = prompt.respond(&context)?.to_header_string();
-        Ok(request.header("Authorization", answer))
+        let wwwauth = match res.headers().get("www-authenticate") {
+            Some(wwwauth) => wwwauth.to_str()?,
+            None => return Ok(Challenge::None),
+        };
+        if wwwauth.to_lowercase().starts_with("basic") {
+            return Ok(Challenge::Basic);
+        }
+        Ok(Challenge::Digest(digest_auth::parse(wwwauth)?))
     }
 
     async fn authenticate_cache_http(
         &self,
         request: RequestBuilder,
+        method: &Method,
+        url: &str,
     ) -> CalDavResult<RequestBuilder> {
-        match self.auth_scheme {
-            AuthScheme::UsernamePassword(ref username, ref password) => {
-                if let Some(ref auth) = *self.auth_header.borrow() {
-                    Ok(request.header("Authorization", auth))
-                } else {
-                    self.authenticate_fresh_http(request, username, password)
-                        .await
-                }
+        let (username, password) = match self.auth_scheme {
+            AuthScheme::UsernamePassword(ref username, ref password) => (username, password),
+            AuthScheme::None => return Ok(request),
+            AuthScheme::OAuth(ref access_token) => return Ok(request.bearer_auth(access_token)),
+        };
+        let cached = self.challenge.lock().unwrap().is_some();
+        if !cached {
+            let challenge = self.fetch_challenge().await?;
+            *self.challenge.lock().unwrap() = Some(challenge);
+        }
+        match self.challenge.lock().unwrap().as_mut() {
+            Some(Challenge::Digest(prompt)) => {
+                // the digest is computed for the request which is actually being sent
+                let url = url.parse::<http::Uri>()?;
+                let uri = url
+                    .path_and_query()
+                    .map(|path| path.as_str())
+                    .unwrap_or_else(|| url.path());
+                let context = AuthContext::new_with_method(
+                    username.as_str(),
+                    password.as_str(),
+                    uri,
+                    None::<&[u8]>,
+                    HttpMethod::from(method.as_str()),
+                );
+                let answer = prompt.respond(&context)?.to_header_string();
+                Ok(request.header("Authorization", answer))
             }
-            AuthScheme::None => Ok(request),
-            AuthScheme::OAuth(ref access_token) => Ok(request.bearer_auth(access_token)),
+            Some(Challenge::Basic) => Ok(request.basic_auth(username, Some(password))),
+            Some(Challenge::None) | None => Ok(request),
         }
     }
     pub fn new_unauthenticated<S>(s: S) -> Self
@@ -74,7 +107,7 @@ impl DavClient {
             auth_scheme: AuthScheme::None,
             url: s.into(),
             client: Client::new(),
-            auth_header: AtomicRefCell::new(None),
+            challenge: Arc::new(Mutex::new(None)),
         }
     }
     /// Construct a new CalDAV client which uses username/password authentication.
@@ -88,7 +121,7 @@ impl DavClient {
             auth_scheme: AuthScheme::new_username_password(username.into(), password.into()),
             url: url.into(),
             client: Client::new(),
-            auth_header: AtomicRefCell::new(None),
+            challenge: Arc::new(Mutex::new(None)),
         }
     }
     /// Construct a new CalDAV client which uses OAuth authentication.
@@ -104,7 +137,7 @@ impl DavClient {
             auth_scheme: AuthScheme::OAuth(access_token),
             url,
             client: Client::new(),
-            auth_header: AtomicRefCell::new(None),
+            challenge: Arc::new(Mutex::new(None)),
         }
     }
     /// Returns a list of calendars.
@@ -130,17 +163,88 @@ impl DavClient {
         todo!()
     }
     pub fn calendar(&'_ self) -> Calendar {
+        self.calendar_at(self.url.to_string())
+    }
+    /// Returns the calendar at `url` (which should be on the same server as this client's URL),
+    /// using the same credentials as this client.
+    pub fn calendar_at<S>(&'_ self, url: S) -> Calendar
+    where
+        S: Into<String>,
+    {
         Calendar {
             client: Arc::new(self.clone()),
-            url: Arc::new(self.url.to_string()),
+            url: Arc::new(url.into()),
+        }
+    }
+    /// Finds the calendar called `name` in the collection at this client's URL (usually the user's
+    /// calendar home), if there is one.
+    pub async fn find_calendar(&'_ self, name: &str) -> CalDavResult<Option<Calendar>> {
+        let body_string = xml! {
+            <?xml version="1.0" encoding="utf-8" ?>
+            <D:propfind xmlns:D="DAV:">
+                <D:prop>
+                    <D:displayname/>
+                    <D:resourcetype/>
+                </D:prop>
+            </D:propfind>
+        }
+        .to_string();
+        let res = self
+            .request(Method::from_bytes(PROPFIND).unwrap(), &self.url)
+            .await?
+            .header("Content-Type", "application/xml; charset=\"utf-8\"")
+            .header("Depth", "1")
+            .body(body_string)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(CalDavError::OtherError);
+        }
+        let text = res.text().await?;
+        let tree = Document::parse(&text).map_err(|_| CalDavError::OtherError)?;
+        let href = tree
+            .descendants()
+            .filter(|node| node.tag_name().name() == "response")
+            .filter(|response| {
+                find(*response, "resourcetype")
+                    .and_then(|resourcetype| find(resourcetype, "calendar"))
+                    .is_some()
+                    && find(*response, "displayname").and_then(|node| node.text()) == Some(name)
+            })
+            .find_map(|response| find(response, "href").and_then(|node| node.text()));
+        match href {
+            Some(href) => Ok(Some(self.calendar_at(self.resolve(href)?))),
+            None => Ok(None),
         }
     }
-    /// Creates a new calendar from the provided `MakeCalendar` struct. If any of the fields on the
-    /// `MkCalendar` struct are `None` a uuid will be used in their place.
+    /// Turns a `href` returned by the server (which is often just a path) into a full URL.
+    ///
+    /// Full URLs are only accepted if they point to the same server as this client's URL (so that
+    /// a server can't get the client to send its credentials somewhere else).
+    fn resolve(&self, href: &str) -> CalDavResult<String> {
+        let href = href.trim().trim_end_matches('/');
+        let url = self.url.parse::<http::Uri>()?;
+        let (scheme, authority) = match (url.scheme(), url.authority()) {
+            (Some(scheme), Some(authority)) => (scheme, authority),
+            _ => return Err(CalDavError::OtherError),
+        };
+        if href.starts_with('/') && !href.starts_with("//") {
+            return Ok(format!("{}://{}{}", scheme, authority, href));
+        }
+        let resolved = href.parse::<http::Uri>()?;
+        if resolved.scheme() == Some(scheme) && resolved.authority() == Some(authority) {
+            Ok(href.to_string())
+        } else {
+            Err(CalDavError::OtherError)
+        }
+    }
+    /// Creates a new calendar from the provided `MakeCalendar` struct (inside the collection at
+    /// this client's URL). If any of the fields on the `MkCalendar` struct are `None` a uuid will
+    /// be used in their place.
     pub async fn make_calendar(&'_ self, cal: MakeCalendar) -> CalDavResult<Calendar> {
         let url = format!(
             "{}/{}",
-            self.url,
+            self.url.trim_end_matches('/'),
             cal.id.unwrap_or_else(|| Uuid::new_v4().to_string())
         );
         let name = if let Some(name) = cal.name {
@@ -150,8 +254,8 @@ impl DavClient {
         };
         let body_string = xml! {
             <?xml version="1.0" encoding="utf-8" ?>
-            <C:mkcalendar xmlns:D="DAV"
-                          xmlns:C="url:ietf:params:xml:ns:caldav">
+            <C:mkcalendar xmlns:D="DAV:"
+                          xmlns:C="urn:ietf:params:xml:ns:caldav">
                 <D:set>
                     <D:prop>
                         <D:displayname>{name}</D:displayname>
@@ -160,19 +264,26 @@ impl DavClient {
             </C:mkcalendar>
         }
         .to_string();
-        self.client
+        let res = self
             .request(Method::from_bytes(MKCALENDAR).unwrap(), &url)
+            .await?
+            .header("Content-Type", "application/xml; charset=\"utf-8\"")
             .body(body_string)
             .send()
-            .await
-            .map(|_| Calendar {
-                client: Arc::new(self.clone()),
-                url: Arc::new(self.url.to_string()),
-            })
-            .map_err(CalDavError::RequestError)
+            .await?;
+        if !res.status().is_success() {
+            return Err(CalDavError::OtherError);
+        }
+        Ok(self.calendar_at(url))
     }
 }
 
+/// Finds the first element called `tag` inside `node` (ignoring namespaces).
+fn find<'a, 'input>(node: Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    node.descendants()
+        .find(|child| child.tag_name().name() == tag)
+}
+
 #[derive(Derivative, Debug)]
 #[derivative(Default(new = "true"))]
 pub struct MakeCalendar {
@@ -211,3 +322,38 @@ impl AuthScheme {
         Self::UsernamePassword(username, password)
     }
 }
+
+#[cfg(test)]
+mod test_client {
+    use super::DavClient;
+
+    #[test]
+    fn test_resolve() {
+        let client = DavClient::new_username_password(
+            "user",
+            "password",
+            "https://caldav.example.com/calendars/user/",
+        );
+        assert_eq!(
+            client.resolve("/calendars/user/lovelace/").unwrap(),
+            "https://caldav.example.com/calendars/user/lovelace"
+        );
+        assert_eq!(
+            client
+                .resolve("https://caldav.example.com/calendars/user/lovelace/")
+                .unwrap(),
+            "https://caldav.example.com/calendars/user/lovelace"
+        );
+        // the credentials mustn't be sent anywhere else
+        assert!(client
+            .resolve("https://attacker.example.com/calendars/user/lovelace/")
+            .is_err());
+        assert!(client
+            .resolve("http://caldav.example.com/calendars/user/lovelace/")
+            .is_err());
+        assert!(client
+            .resolve("https://caldav.example.com:8443/calendars/user/lovelace/")
+            .is_err());
+        assert!(client.resolve("//attacker.example.com/lovelace/").is_err());
+    }
+}
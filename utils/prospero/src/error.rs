@@ -5,6 +5,9 @@ use reqwest::{header::ToStrError, Error};
 pub enum CalDavError {
     #[error("request error")]
     RequestError(Error),
+    /// The server didn't accept the credentials (e.g. because an access token has expired).
+    #[error("unauthorized")]
+    Unauthorized,
     #[error("other error")]
     OtherError,
 }
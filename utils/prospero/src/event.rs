@@ -162,7 +162,8 @@ impl EventPointer {
             EventPointerData::CreatedEventResponse { uid } => uid.clone(),
         };
         std::mem::drop(borrow);
-        self.client
+        let res = self
+            .client
             .request(
                 Method::from_bytes(DELETE).unwrap(),
                 format!("{}/{}.ics", self.url, uid),
@@ -170,6 +171,9 @@ impl EventPointer {
             .await?
             .send()
             .await?;
+        if res.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(CalDavError::Unauthorized);
+        }
         Ok(())
     }
 }